@@ -0,0 +1,11 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use emosaic::mosaic::tiles::read_exif_metadata;
+use libfuzzer_sys::fuzz_target;
+
+// Reading EXIF metadata from a malformed JPEG must never panic.
+fuzz_target!(|data: &[u8]| {
+    let _ = read_exif_metadata(&mut Cursor::new(data));
+});
@@ -0,0 +1,22 @@
+#![no_main]
+
+use emosaic::mosaic::tiles::{AnalysisCache, TileSet};
+use image::Rgb;
+use libfuzzer_sys::fuzz_target;
+
+// Decoding an analysis cache must never panic or over-allocate,
+// whatever mode and schema the bytes were produced for.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(cache) = AnalysisCache::from_bytes(data) {
+        let _ = cache.get::<1>(false);
+        let _ = cache.get::<4>(true);
+    }
+    if let Ok(mut cache) = AnalysisCache::from_any_bytes(data) {
+        let _ = cache.insert_mode_file(false, 4, data);
+        let _ = cache.get::<1>(false);
+    }
+    let _ = TileSet::<[Rgb<u8>; 1]>::from_cache_bytes(data);
+    let _ = TileSet::<[Rgb<u8>; 4]>::from_cache_bytes(data);
+    let _ = TileSet::<[Rgb<u8>; 9]>::from_cache_bytes(data);
+    let _ = TileSet::<[Rgb<u8>; 1024]>::from_cache_bytes(data);
+});
@@ -377,6 +377,21 @@ function toggleDistanceOverlay() {
     }
 }
 
+function toggleSeamOverlay() {
+    const overlay = document.getElementById('seam-overlay');
+    if (overlay) {
+        overlay.classList.toggle('visible');
+    }
+    // Notify parent window of state change
+    if (window.parent !== window) {
+        const isVisible = overlay && overlay.classList.contains('visible');
+        window.parent.postMessage({
+            type: 'seamOverlayToggled',
+            visible: isVisible
+        }, '*');
+    }
+}
+
 function openTileImage(imagePath, isWebCompatible) {
     if (isWebCompatible) {
         // For web hosting, open the relative URL directly
@@ -404,6 +419,8 @@ function openTileImage(imagePath, isWebCompatible) {
 window.addEventListener('message', function(event) {
     if (event.data.type === 'toggleDistanceOverlay') {
         toggleDistanceOverlay();
+    } else if (event.data.type === 'toggleSeamOverlay') {
+        toggleSeamOverlay();
     }
 });
 
@@ -412,7 +429,7 @@ function adjustMosaicLayout() {
     const image = document.querySelector('.mosaic-image');
     const container = document.querySelector('.mosaic-container');
     const zoomContainer = document.querySelector('.zoom-container');
-    const overlay = document.querySelector('.distance-overlay');
+    const overlays = document.querySelectorAll('.distance-overlay');
     const tileRegions = document.querySelectorAll('.tile-region');
     const overlayTiles = document.querySelectorAll('.distance-overlay-tile');
 
@@ -431,12 +448,12 @@ function adjustMosaicLayout() {
     const offsetY = imageRect.top - zoomContainerRect.top;
 
     // Update overlay dimensions and position to match the image exactly
-    if (overlay) {
+    overlays.forEach(overlay => {
         overlay.style.left = offsetX + 'px';
         overlay.style.top = offsetY + 'px';
         overlay.style.width = imageRect.width + 'px';
         overlay.style.height = imageRect.height + 'px';
-    }
+    });
 
 
     // Distance overlay tiles should keep their percentage positioning relative to the overlay container
@@ -491,6 +508,8 @@ window.addEventListener('load', function() {
     attemptHideIOSToolbar();
     adjustMosaicLayout();
     setupYearFilter();
+    setupTimeline();
+    setupTileSearch();
     setupTouchHandlers();
     setupSmartTooltips();
 
@@ -703,6 +722,11 @@ async function loadTooltipImage(tileRegion) {
 }
 
 function handleTileClick(imagePath, isWebCompatible, tileElement, tileImageUrl, distanceInfo, dateInfo) {
+    const locate = document.getElementById('tile-locate');
+    if (locate && locate.checked) {
+        locateTile(tileElement);
+        return;
+    }
     if (isMobile()) {
         showMobileModal(tileImageUrl, distanceInfo, dateInfo, tileElement);
     } else {
@@ -728,8 +752,13 @@ async function showMobileModal(imageUrl, distanceInfo, dateInfo, tileElement) {
     window.currentMobileTileHash = tileHash;
     window.currentMobileTilePath = tilePath; // Store original tile path for fallback
 
-    // Base content
-    let content = distanceInfo + dateInfo;
+    // Tapping the picture opens its linked page, when the mosaic gives one
+    const tileLink = tileElement ? tileElement.dataset.tileLink : '';
+    modalImage.onclick = tileLink ? () => window.open(tileLink, '_blank') : null;
+
+    // Base content, with the caption and camera of the picture
+    const detailsInfo = tileElement ? tileElement.dataset.detailsInfo || '' : '';
+    let content = distanceInfo + dateInfo + detailsInfo;
 
     // Add flag UI for mobile with lazy loading
     if (tileHash && window.flagSystem) {
@@ -1439,8 +1468,238 @@ function toggleFlag(tileHash, tilePath) {
     }
 }
 
+// Search of the tiles by file name or folder
+
+function setupTileSearch() {
+    const input = document.getElementById('tile-search-input');
+    const dim = document.getElementById('tile-search-dim');
+    if (!input || !dim) {
+        return;
+    }
+    input.addEventListener('input', updateTileSearch);
+    dim.addEventListener('change', updateTileSearch);
+    const locate = document.getElementById('tile-locate');
+    if (locate) {
+        // Leaving the locate mode clears the placements highlighted
+        locate.addEventListener('change', () => {
+            if (!locate.checked) {
+                document.querySelectorAll('.tile-region.locate-match').forEach(tile => {
+                    tile.classList.remove('locate-match');
+                });
+                updateTileSearch();
+            }
+        });
+    }
+}
+
+// Paths are compared case insensitively, with the separators of Windows as slashes
+function normalizeTilePath(path) {
+    return path.replace(/\\/g, '/').toLowerCase();
+}
+
+// Highlight the tiles whose path contains the text searched, dimming the others if asked
+function updateTileSearch() {
+    const query = normalizeTilePath(document.getElementById('tile-search-input').value.trim());
+    const dim = document.getElementById('tile-search-dim').checked;
+    const pictures = new Set();
+    let matches = 0;
+    document.querySelectorAll('.tile-region').forEach(tile => {
+        const path = tile.dataset.tilePath || '';
+        const match = query !== '' && normalizeTilePath(path).includes(query);
+        tile.classList.toggle('search-match', match);
+        tile.classList.toggle('search-dimmed', query !== '' && dim && !match);
+        if (match) {
+            matches++;
+            pictures.add(path);
+        }
+    });
+    const count = document.getElementById('tile-search-count');
+    if (query === '') {
+        count.textContent = '';
+    } else {
+        count.textContent = `${matches} ${matches === 1 ? 'tile' : 'tiles'} of ` +
+            `${pictures.size} ${pictures.size === 1 ? 'picture' : 'pictures'}`;
+    }
+}
+
+// Click to locate: highlight every placement of the picture of a tile, or none when clicking
+// one of them again
+
+function locateTile(tileElement) {
+    const located = tileElement.classList.contains('locate-match');
+    document.querySelectorAll('.tile-region.locate-match').forEach(tile => {
+        tile.classList.remove('locate-match');
+    });
+    const count = document.getElementById('tile-search-count');
+    if (located) {
+        count.textContent = '';
+        return;
+    }
+    const placements = tilesOf(tileElement.dataset.tileHash);
+    placements.forEach(tile => tile.classList.add('locate-match'));
+    const name = (tileElement.dataset.tilePath || '').split(/[\\/]/).pop();
+    count.textContent =
+        `${name}: ${placements.length} ${placements.length === 1 ? 'placement' : 'placements'}`;
+}
+
+// Timeline scrubber
+// Note: timelineFirstMonth and timelineLastMonth are defined in the HTML when the pictures were
+// taken over several months
+
+const TIMELINE_MONTH_NAMES = [
+    'Jan', 'Feb', 'Mar', 'Apr', 'May', 'Jun', 'Jul', 'Aug', 'Sep', 'Oct', 'Nov', 'Dec'
+];
+// Time taken to play the whole timeline, in milliseconds
+const TIMELINE_DURATION = 10000;
+
+let timelineTimer = null;
+let timelineTiles = [];
+
+function setupTimeline() {
+    const slider = document.getElementById('timeline-slider');
+    if (!slider) {
+        return;
+    }
+    timelineTiles = Array.from(document.querySelectorAll('.tile-region'));
+    slider.addEventListener('input', function() {
+        stopTimeline();
+        updateTimeline(parseInt(this.value));
+    });
+}
+
+function formatTimelineMonth(month) {
+    const year = 1970 + Math.floor(month / 12);
+    return TIMELINE_MONTH_NAMES[((month % 12) + 12) % 12] + ' ' + year;
+}
+
+// Reveal the tiles taken up to the month of the given step of the timeline, or all of them,
+// the undated ones included, at its last step
+function updateTimeline(step) {
+    const display = document.getElementById('timeline-display');
+    const steps = timelineLastMonth - timelineFirstMonth + 1;
+    const lastMonth = timelineFirstMonth + step;
+    display.textContent = step >= steps ? 'All' : formatTimelineMonth(lastMonth);
+    timelineTiles.forEach(tile => {
+        const month = tile.dataset.month;
+        const revealed = step >= steps || (month !== 'unknown' && parseInt(month) <= lastMonth);
+        tile.classList.toggle('unrevealed', !revealed);
+    });
+}
+
+function toggleTimelinePlay() {
+    if (timelineTimer) {
+        stopTimeline();
+        return;
+    }
+    const slider = document.getElementById('timeline-slider');
+    const steps = parseInt(slider.max);
+    // Start over once the whole timeline was played
+    if (parseInt(slider.value) >= steps) {
+        slider.value = '0';
+        updateTimeline(0);
+    }
+    document.getElementById('timeline-play').textContent = '⏸';
+    timelineTimer = setInterval(() => {
+        const step = parseInt(slider.value) + 1;
+        slider.value = String(step);
+        updateTimeline(step);
+        if (step >= steps) {
+            stopTimeline();
+        }
+    }, Math.max(50, TIMELINE_DURATION / steps));
+}
+
+function stopTimeline() {
+    if (timelineTimer) {
+        clearInterval(timelineTimer);
+        timelineTimer = null;
+    }
+    const button = document.getElementById('timeline-play');
+    if (button) {
+        button.textContent = '▶';
+    }
+}
+
+// Map of where the pictures were taken
+// Note: leafletUrl and photoLocations are defined in the HTML when the pictures were located
+
+let photoMap = null;
+
+function loadLeaflet() {
+    if (window.L) {
+        return Promise.resolve();
+    }
+    const stylesheet = document.createElement('link');
+    stylesheet.rel = 'stylesheet';
+    stylesheet.href = leafletUrl + '/leaflet.css';
+    document.head.appendChild(stylesheet);
+    return new Promise((resolve, reject) => {
+        const script = document.createElement('script');
+        script.src = leafletUrl + '/leaflet.js';
+        script.onload = resolve;
+        script.onerror = reject;
+        document.head.appendChild(script);
+    });
+}
+
+async function toggleMapPane() {
+    const pane = document.getElementById('map-pane');
+    if (!pane) {
+        return;
+    }
+    pane.classList.toggle('visible');
+    if (!pane.classList.contains('visible')) {
+        highlightTiles(null);
+        return;
+    }
+    if (photoMap) {
+        photoMap.invalidateSize();
+        return;
+    }
+    try {
+        await loadLeaflet();
+    } catch (e) {
+        document.getElementById('photo-map').textContent =
+            'The map could not be loaded, it needs an internet connection';
+        return;
+    }
+    photoMap = L.map('photo-map');
+    L.tileLayer('https://{s}.tile.openstreetmap.org/{z}/{x}/{y}.png', {
+        maxZoom: 19,
+        attribution: '&copy; <a href="https://www.openstreetmap.org/copyright">OpenStreetMap</a> contributors'
+    }).addTo(photoMap);
+    const markers = photoLocations.map(location => {
+        const count = tilesOf(location.hash).length;
+        // Set as text, file names are not markup
+        const popup = document.createElement('div');
+        popup.textContent = `${location.name} (${count} ${count === 1 ? 'tile' : 'tiles'})`;
+        return L.marker([location.latitude, location.longitude])
+            .bindPopup(popup)
+            .on('click', () => highlightTiles(location.hash))
+            .addTo(photoMap);
+    });
+    photoMap.fitBounds(L.featureGroup(markers).getBounds().pad(0.1), { maxZoom: 12 });
+    photoMap.on('popupclose', () => highlightTiles(null));
+}
+
+function tilesOf(tileHash) {
+    return document.querySelectorAll(`.tile-region[data-tile-hash="${tileHash}"]`);
+}
+
+// Highlight the tiles of the picture with the given hash, or none
+function highlightTiles(tileHash) {
+    document.querySelectorAll('.tile-region.map-highlight').forEach(tile => {
+        tile.classList.remove('map-highlight');
+    });
+    if (tileHash) {
+        tilesOf(tileHash).forEach(tile => tile.classList.add('map-highlight'));
+    }
+}
+
 // Make functions globally accessible
 window.toggleDistanceOverlay = toggleDistanceOverlay;
+window.toggleMapPane = toggleMapPane;
+window.highlightTiles = highlightTiles;
 window.openTileImage = openTileImage;
 window.adjustMosaicLayout = adjustMosaicLayout;
 window.setupSmartTooltips = setupSmartTooltips;
@@ -1452,6 +1711,12 @@ window.showMobileModal = showMobileModal;
 window.closeMobileModal = closeMobileModal;
 window.setupYearFilter = setupYearFilter;
 window.updateYearFilter = updateYearFilter;
+window.setupTimeline = setupTimeline;
+window.setupTileSearch = setupTileSearch;
+window.updateTileSearch = updateTileSearch;
+window.locateTile = locateTile;
+window.updateTimeline = updateTimeline;
+window.toggleTimelinePlay = toggleTimelinePlay;
 window.setupTouchHandlers = setupTouchHandlers;
 window.setupYearFilterTouchHandlers = setupYearFilterTouchHandlers;
 window.positionYearFilter = positionYearFilter;
@@ -0,0 +1,4 @@
+#![feature(generic_const_exprs)]
+#![feature(type_changing_struct_update)]
+#![allow(incomplete_features)]
+pub mod mosaic;
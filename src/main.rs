@@ -1,29 +1,75 @@
 #![feature(generic_const_exprs)]
 #![feature(type_changing_struct_update)]
-mod mosaic;
-
+#![allow(incomplete_features)]
+use emosaic::mosaic;
 use image::imageops::FilterType;
-use mosaic::error::ImageError;
+use mosaic::analysis::block_colors;
+use mosaic::bench::{self, BenchRow};
+use mosaic::cancel;
+use mosaic::contact_sheet::{self, Caption};
+use mosaic::coverage;
+use mosaic::delta_e::DeltaMap;
+use mosaic::diff::ManifestDiff;
+use mosaic::edges::EdgeFeatures;
+use mosaic::error::{EmosaicError, ImageError};
+use mosaic::failures::{FailureReport, Quarantine};
 use std::collections::{HashMap, HashSet};
 use std::ffi::{OsStr, OsString};
 use std::fs::create_dir_all;
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::sync::{
-    atomic::{AtomicBool, AtomicU64, Ordering},
-    Arc, RwLock,
-};
+use std::process::ExitCode;
+use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
-use std::{fs, io, thread};
+use std::{fs, io};
 
 use clap::{self, Args, Parser, Subcommand, ValueEnum};
 use image::{imageops, DynamicImage, ImageFormat, Rgb, Rgba, RgbaImage};
 
-use indicatif::{ProgressBar, ProgressStyle};
-use mosaic::image::find_images;
-use mosaic::stats::MosaicConfig;
-use mosaic::tiles::{prepare_tile, prepare_tile_with_date, Tile, TileSet};
-use mosaic::{analyse, render_nto1, render_nto1_no_repeat, render_random};
+use mosaic::ignore::Ignore;
+use mosaic::image::{
+    apply_mask, check_stdout, clear_regions, find_images, hidden_cells, is_stdio, open_limited,
+    output_dir, read_stdin_image, rotate, rotated_dimensions, save_output, ClearFill, ClearRegion,
+    OutputFormat, OutputOptions, Panel, DEFAULT_MAX_SOURCE_PIXELS,
+};
+use mosaic::layout::Layout;
+use mosaic::logging::{self, LogFormat};
+use mosaic::manifest::Manifest;
+use mosaic::memory::{self, MemoryMonitor};
+use mosaic::optimize::{self, Optimize};
+use mosaic::pins::Pins;
+use mosaic::progress::{self, Progress};
+use mosaic::quality::QualityScore;
+use mosaic::rendering::{
+    render_nto1_irregular, LoadFailures, RenderConfig, RenderResult, RowCallback,
+};
+use mosaic::runtime::RuntimeStats;
+use mosaic::seams::SeamMap;
+use mosaic::stats::{DistanceScale, MosaicConfig, RenderStats, StatsSummary};
+use mosaic::story::StoryOptions;
+use mosaic::terminal::{TerminalPreview, TerminalProtocol};
+use mosaic::tiles::{
+    augment, cache_dir, is_kept_crop, list_cache_dir, prepare_tile, prepare_tile_isolated,
+    read_picture_metadata, read_sharpness, refresh_rotated_tiles, refresh_tiles, split_crop,
+    tile_weights, write_atomic, AnalysisCache, AnalysisCheckpoint, CacheLock, IndexKind,
+    LibraryOptions, Resolution, Tile, TileAnalysis, TileFilter, TileList, TileSet, TileSource,
+    DEFAULT_IMAGE_CACHE_BYTES, MAX_TILES, SIZE,
+};
+use mosaic::time_gradient::TimeGradient;
+use mosaic::timestamp::{normalize_dates, DateFormat, TimeZone};
+use mosaic::usage::UsageFormat;
+use mosaic::wall::{self, WallOrder};
+use mosaic::web::links::TileLinks;
+use mosaic::web::palette::Palette;
+use mosaic::web::serve::{RenderParams, Server};
+use mosaic::wizard;
+use mosaic::{
+    analyse, render_manifest, render_nto1, render_nto1_no_repeat, render_nto1_panels,
+    render_nto1_story, render_random, MatchOptions,
+};
+use rand::seq::SliceRandom;
 use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use tracing::{debug_span, error, info, warn};
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -32,23 +78,114 @@ struct Cli {
     #[clap(default_value_t = 16_u32, short = 's', long, value_parser)]
     tile_size: u32,
 
-    /// Output image path
+    /// Output image path, or - to write it to stdout in the --format given
     #[clap(default_value = "./output.jpg", short, long, value_parser)]
     output_path: PathBuf,
 
-    /// Path to input image
+    /// Output image format, inferred from the output path extension by default
+    #[clap(long, arg_enum)]
+    format: Option<OutputFormat>,
+
+    /// Quality (1-100) for lossy output formats
+    #[clap(long, default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: u8,
+
+    /// Encoder speed (1-10) for AVIF output, lower is slower but smaller
+    #[clap(long, default_value_t = 6, value_parser = clap::value_parser!(u8).range(1..=10))]
+    speed: u8,
+
+    /// Use lossless compression for WebP output
+    #[clap(long)]
+    lossless: bool,
+
+    /// Path to input image, or - to read it from stdin, not needed by the wall and diff
+    /// subcommands
     #[clap(value_parser)]
-    img: PathBuf,
+    img: Option<PathBuf>,
 
     #[clap(long)]
     /// Crop tiles instead of resizing
     crop: bool,
 
+    #[clap(long, value_name = "PIXELS", default_value_t = DEFAULT_MAX_SOURCE_PIXELS)]
+    /// Refuse source and mask images with more pixels than this, before decoding them
+    max_source_pixels: u64,
+
+    #[clap(long, value_name = "COUNT", value_parser = clap::value_parser!(u16).range(1..))]
+    /// Number of threads to analyse and render with, one per CPU by default, which also caps the
+    /// tile images decoded at once, apart from those given up on after --tile-timeout. Outside
+    /// of the random mode and previews, the same inputs give the same mosaic with any number of
+    /// threads
+    threads: Option<u16>,
+
+    #[clap(long, value_name = "SIZE", value_parser = memory::parse_size)]
+    /// Keep the run within this much memory, e.g. 4G: caps the decoded tile images kept, copies
+    /// every row into the mosaic once rendered rather than holding them all besides it, and scores
+    /// fewer matches at once when nearing it. The whole mosaic is still held to encode it
+    max_memory: Option<u64>,
+
+    #[clap(long, value_name = "DIR", require_equals = true)]
+    /// Keep the analysis caches of the tiles directories under DIR, or the user cache directory
+    /// without one, instead of in the tiles directories, e.g. for read-only network shares
+    cache_dir: Option<Option<PathBuf>>,
+
+    #[clap(long, value_name = "GLOB")]
+    /// Leave the pictures matching GLOB out of the tiles directories, e.g. '*.edited.jpg' or
+    /// 'Screenshots/', with the syntax of the .emosaicignore files. Can be repeated
+    exclude: Vec<String>,
+
+    #[clap(long)]
+    /// Follow the symbolic links to directories in the tiles directories, each directory being
+    /// looked into once, and each image used once whatever the links to it
+    follow_symlinks: bool,
+
+    #[clap(long, value_name = "WxH")]
+    /// Leave out of the tiles analysed the pictures smaller than WxH, in either orientation,
+    /// e.g. thumbnails
+    min_tile_resolution: Option<Resolution>,
+
+    #[clap(long, value_name = "RATIO", value_parser = is_at_least_one)]
+    /// Leave out of the tiles analysed the pictures whose longer side is more than RATIO times
+    /// their shorter side, e.g. panoramas
+    max_aspect_ratio: Option<f64>,
+
+    #[clap(long, value_name = "SCORE", value_parser = is_non_negative)]
+    /// Leave out of the tiles analysed the pictures less sharp than SCORE, the variance of
+    /// their Laplacian, e.g. 100 for shaken or out of focus photos. Recorded in the analysis
+    /// cache, so that it can be changed without decoding the pictures again
+    min_sharpness: Option<f64>,
+
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u8).range(1..=8))]
+    /// Take up to N crops of every picture analysed as extra tiles, zooming on its centre and
+    /// corners, e.g. to make a --no-repeat mosaic out of few photos. Only the crops at least
+    /// 256 pixels wide are taken, and they point at the original file with their region, e.g.
+    /// photo.jpg#xywh=percent:0,0,70,70
+    augment_crops: Option<u8>,
+
+    #[clap(long, value_enum, default_value_t = ProgressFormat::Bar, value_name = "FORMAT")]
+    /// How to report the progress of the long phases: bars on stderr, or newline delimited JSON
+    /// events on stdout with the phase, steps completed and in total, seconds left and picture read
+    progress: ProgressFormat,
+
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    /// Show more messages: the time spent in every phase with -v, everything with -vv
+    verbose: u8,
+
+    #[clap(short, long, action = clap::ArgAction::Count, conflicts_with = "verbose")]
+    /// Show fewer messages: only warnings and errors with -q, only errors with -qq
+    quiet: u8,
+
+    #[clap(long, value_enum, default_value_t = LogFormat::Text, value_name = "FORMAT")]
+    /// Format of the messages on stderr, one JSON object per message with json
+    log_format: LogFormat,
+
     #[clap(subcommand)]
     subcmd: Option<SubCommand>,
 }
 
 #[derive(Subcommand)]
+// Parsed once per run, so the size of the mosaic options does not matter
+#[allow(clippy::large_enum_variant)]
 enum SubCommand {
     /// This command converts an image into a tile, applying trimming
     /// and resizing to the selected tile size as needed. This is done
@@ -56,22 +193,334 @@ enum SubCommand {
     /// the outcome on a specific image
     Prepare,
     Mosaic(Mosaic),
+    /// Render the placements of a manifest written with `mosaic --manifest` again at the
+    /// tile size given with -s, without matching any tiles. The source image is only used to
+    /// reapply the tint of the original mosaic
+    RenderFromManifest(RenderFromManifest),
+    /// Compare two manifests written with `mosaic --manifest` for the same source, e.g. with
+    /// different settings or versions of the tile set, and render which cells changed: blue
+    /// where the new tile is closer, orange where it is farther, one square of -s pixels per
+    /// cell. The source image is not used
+    Diff(Diff),
+    /// Arrange every tile of a directory on a grid, ordered by color, brightness or date or
+    /// shuffled, without any source image, e.g. for a photo wall
+    Wall(Wall),
+    /// Lay out every tile of a directory along a Hilbert curve through the colors, so that they
+    /// fade smoothly into each other, with optional captions, e.g. to review a tile library.
+    /// The source image is not used
+    ContactSheet(ContactSheet),
+    /// Compare the colors of the source image with the average colors of the tiles, and report
+    /// which colors and regions will match poorly, with a heatmap of the distance from every
+    /// cell to its nearest tile, one square of -s pixels per cell, to tell whether to add
+    /// pictures before rendering
+    Coverage(Coverage),
+    /// Manage the analysis caches of tiles directories
+    Cache(Cache),
+    /// Match the tiles against the source image for every combination of modes and mosaic
+    /// options given, without compositing any tile image, and print a table of the average
+    /// distance, distinct tiles placed and time taken by each, to compare settings
+    Bench(Bench),
+    /// Set up a mosaic by answering a few questions: the source image, suggested by the path
+    /// given before init, the tiles directory, the size of the mosaic and whether pictures may
+    /// repeat. Saves the chosen settings to a config file, with the command rendering the
+    /// mosaic, and opens a quick preview
+    Init(Init),
+    /// Serve a page on this computer for choosing the settings of the mosaic of the source
+    /// image interactively: every change renders a quick preview shown next to the source, and
+    /// a button renders the full mosaic to the output path
+    Serve(Serve),
+}
+
+impl Cli {
+    /// The options choosing the pictures used as tiles and the resources a run may take, given
+    /// again to the runs started by the serve subcommand.
+    fn library_args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = Vec::new();
+        let mut push = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                args.push(format!("--{}={}", name, value).into());
+            }
+        };
+        push("threads", self.threads.map(|threads| threads.to_string()));
+        push("max-memory", self.max_memory.map(|bytes| bytes.to_string()));
+        for glob in &self.exclude {
+            push("exclude", Some(glob.clone()));
+        }
+        let resolution = self
+            .min_tile_resolution
+            .map(|resolution| resolution.to_string());
+        push("min-tile-resolution", resolution);
+        push(
+            "max-aspect-ratio",
+            self.max_aspect_ratio.map(|ratio| ratio.to_string()),
+        );
+        push(
+            "min-sharpness",
+            self.min_sharpness.map(|score| score.to_string()),
+        );
+        push(
+            "augment-crops",
+            self.augment_crops.map(|count| count.to_string()),
+        );
+        match &self.cache_dir {
+            Some(Some(dir)) => {
+                let mut arg = OsString::from("--cache-dir=");
+                arg.push(dir);
+                args.push(arg);
+            }
+            Some(None) => args.push("--cache-dir".into()),
+            None => (),
+        }
+        if self.follow_symlinks {
+            args.push("--follow-symlinks".into());
+        }
+        args
+    }
 }
 
 #[derive(Args)]
-struct Mosaic {
+struct Init {
+    #[clap(long, value_name = "PATH", default_value = "emosaic.toml")]
+    /// Where to save the chosen settings
+    config: PathBuf,
+
+    #[clap(long)]
+    /// Save the settings without rendering a preview
+    no_preview: bool,
+}
+
+#[derive(Args)]
+struct Serve {
+    #[clap(flatten)]
+    library: TilesDir,
+
+    #[clap(long, default_value_t = 8080)]
+    /// Port to serve the page on, only reachable from this computer
+    port: u16,
+
+    #[clap(long)]
+    /// Open the page in the default browser
+    open: bool,
+}
+
+#[derive(Args)]
+struct Bench {
+    #[clap(flatten)]
+    library: TilesDir,
+
+    #[clap(long, arg_enum, use_value_delimiter = true, default_values = &["1", "2", "4"])]
+    /// Modes to compare, separated by commas
+    modes: Vec<Mode>,
+
+    #[clap(long = "with", value_name = "OPTIONS", allow_hyphen_values = true)]
+    /// Options of the mosaic subcommand to compare with the defaults, separated by spaces, e.g.
+    /// "--no-repeat --greedy" or "--index hnsw --repeat-penalty 10". Can be repeated
+    configurations: Vec<String>,
+}
+
+/// The mosaic options of a configuration of the bench subcommand.
+#[derive(Parser)]
+struct BenchConfiguration {
+    #[clap(flatten)]
+    mosaic: Mosaic,
+}
+
+#[derive(Args)]
+struct Coverage {
+    #[clap(flatten)]
+    library: TilesDir,
+
+    #[clap(long, default_value_t = 1)]
+    /// Downsampling factor applied to the original image, as for the mosaic
+    downsample: u16,
+
+    #[clap(long, value_name = "DISTANCE", default_value_t = 75)]
+    /// Largest distance between a cell and its nearest tile (per pixel, summed over the RGB
+    /// channels, 0-765) still matching well
+    max_distance: u32,
+
+    #[clap(long, arg_enum, default_value_t = Palette::Classic)]
+    /// Color scheme of the heatmap
+    palette: Palette,
+
+    #[clap(long)]
+    /// Open the heatmap in the default viewer when done
+    open: bool,
+}
+
+#[derive(Args)]
+struct Cache {
+    #[clap(subcommand)]
+    command: CacheCommand,
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Migrate the analysis caches written by older versions of emosaic in place, re-reading
+    /// from the EXIF data of the tiles only what they lack instead of analysing them again
+    Upgrade {
+        /// Paths to the tiles directories whose caches are upgraded
+        #[clap(value_parser, required = true)]
+        tiles_dirs: Vec<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+struct RenderFromManifest {
+    /// Path to the JSON placement manifest
+    #[clap(value_parser)]
+    manifest: PathBuf,
+
+    #[clap(long, default_value_t = DEFAULT_IMAGE_CACHE_BYTES / MEGABYTE)]
+    /// Memory budget in MB for decoded tile images kept in memory while rendering
+    tile_cache_mb: usize,
+
+    #[clap(long)]
+    /// Open the output image in the default viewer when done
+    open: bool,
+}
+
+#[derive(Args)]
+struct Diff {
+    /// Path to the JSON placement manifest of the first mosaic
+    #[clap(value_parser)]
+    before: PathBuf,
+
+    /// Path to the JSON placement manifest of the second mosaic
+    #[clap(value_parser)]
+    after: PathBuf,
+
+    #[clap(long)]
+    /// Open the output image in the default viewer when done
+    open: bool,
+}
+
+/// The tiles directory of the subcommands using the pictures of a single one.
+#[derive(Args)]
+struct TilesDir {
     /// Path to directory containing tile images
     #[clap(value_parser)]
     tiles_dir: PathBuf,
 
+    #[clap(long, default_values_t = [String::from("jpg"), String::from("jpeg")])]
+    /// Extensions of image files in the tiles dir
+    extensions: Vec<String>,
+
+    #[clap(long, value_name = "SECS", default_value_t = 60)]
+    /// Give up on tiles taking longer than this to analyse and quarantine them, 0 to wait
+    /// forever
+    tile_timeout: u64,
+}
+
+impl TilesDir {
+    /// The tile set of the directory, analysed beforehand unless cached, found as `library` says.
+    fn load<const N: usize>(
+        &self,
+        tile_size: u32,
+        crop: bool,
+        library: &LibraryOptions,
+    ) -> Result<TileSet<[Rgb<u8>; N]>, EmosaicError>
+    where
+        [(); N * 3]:,
+    {
+        let extensions: HashSet<String> = self.extensions.iter().cloned().collect();
+        load_tile_set::<N>(
+            &self.tiles_dir,
+            &extensions,
+            tile_size,
+            crop,
+            false,
+            false,
+            false,
+            None,
+            self.tile_timeout,
+            library,
+        )
+    }
+
+    /// The options of the mosaic subcommand choosing the same tiles in the directory.
+    fn mosaic_args(&self) -> Vec<String> {
+        let extensions = self
+            .extensions
+            .iter()
+            .map(|ext| format!("--extensions={}", ext));
+        extensions
+            .chain([format!("--tile-timeout={}", self.tile_timeout)])
+            .collect()
+    }
+}
+
+/// The tiles laid out by the wall and contact sheet subcommands.
+#[derive(Args)]
+struct WallTiles {
+    #[clap(flatten)]
+    library: TilesDir,
+
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    /// Number of tiles per row, enough for a square grid by default
+    columns: Option<u32>,
+
+    #[clap(long, default_value_t = DEFAULT_IMAGE_CACHE_BYTES / MEGABYTE)]
+    /// Memory budget in MB for decoded tile images kept in memory while rendering
+    tile_cache_mb: usize,
+
+    #[clap(long)]
+    /// Open the output image in the default viewer when done
+    open: bool,
+}
+
+#[derive(Args)]
+struct Wall {
+    #[clap(flatten)]
+    tiles: WallTiles,
+
+    #[clap(long, arg_enum, default_value_t = WallOrder::Hue)]
+    /// Order of the tiles, row by row
+    order: WallOrder,
+}
+
+#[derive(Args)]
+struct ContactSheet {
+    #[clap(flatten)]
+    tiles: WallTiles,
+
+    #[clap(long, arg_enum)]
+    /// Write the file name or the date of every tile under it
+    captions: Option<Caption>,
+}
+
+#[derive(Args)]
+struct Mosaic {
+    /// Path to directory containing tile images
+    #[clap(value_parser, required_unless_present_any = ["tiles-dirs", "tiles-list"])]
+    tiles_dir: Option<PathBuf>,
+
+    #[clap(long = "tiles-dir", value_name = "PATH[:WEIGHT]")]
+    /// Additional directory of tile images, with an optional weight favoring its tiles: the
+    /// distances of its tiles are divided by the weight. Can be repeated. Implies --greedy
+    /// with --no-repeat when weights differ
+    tiles_dirs: Vec<TileSource>,
+
+    #[clap(long, value_name = "PATH")]
+    /// Text file listing tile images one per line, relative to the file, each optionally
+    /// followed by a tab and a weight and by another tab and a label, to use a curated subset
+    /// of a library without copying it. Their analysis is cached per image
+    tiles_list: Option<PathBuf>,
+
     /// Mosaic mode to use
     #[clap(default_value_t = Mode::_1, arg_enum, short, long, value_parser)]
     mode: Mode,
 
-    /// Deletes analysis cache from tiles directory forcing re-analysis of tiles
+    /// Deletes analysis cache from tiles directory forcing re-analysis of tiles, including
+    /// quarantined ones
     #[clap(short, long, value_parser)]
     force: bool,
 
+    #[clap(long, value_name = "SECS", default_value_t = 60)]
+    /// Give up on tiles taking longer than this to analyse and quarantine them, 0 to wait
+    /// forever
+    tile_timeout: u64,
+
     /// Value between 0 and 1 indicating the opacity of the source image overlayed on the output image
     #[clap(default_value_t = 0.0, short, long, value_parser = is_between_zero_and_one)]
     tint_opacity: f64,
@@ -80,6 +529,27 @@ struct Mosaic {
     #[clap(long)]
     no_repeat: bool,
 
+    #[clap(long, value_name = "PENALTY", value_parser = is_non_negative)]
+    /// Favor tiles that were placed less often: every use of a tile adds this penalty, in
+    /// color distance per pixel, to its distance for the cells matched after it. Gives more
+    /// diverse mosaics without the tile count requirement of --no-repeat
+    repeat_penalty: Option<f64>,
+
+    #[clap(long, value_name = "PASSES")]
+    /// After rendering, swap the tiles of pairs of cells whenever that lowers their total
+    /// color distance, for up to PASSES passes over the mosaic
+    optimize_swaps: Option<usize>,
+
+    #[clap(long, value_name = "anneal[:SECONDS]")]
+    /// After rendering, refine the whole mosaic by simulated annealing, swapping and replacing
+    /// tiles for SECONDS (10 by default) to lower the average color distance
+    optimize: Option<Optimize>,
+
+    #[clap(long, value_name = "irregular[:MAX_CELLS]")]
+    /// Experimental: place the tiles on rectangles of 1 to MAX_CELLS (4 by default) grid cells a
+    /// side, cut at random, instead of the regular grid. Tiles are cropped to their rectangle
+    layout: Option<Layout>,
+
     #[clap(long, default_value_t = 1)]
     /// Downsampling factor applied to the original image
     downsample: u16,
@@ -88,6 +558,26 @@ struct Mosaic {
     /// Select one of the best tiles randomly (within x% distance from the best one)
     randomize: Option<f64>,
 
+    #[clap(long, value_name = "VARIANCE", requires = "randomize", value_parser = is_non_negative)]
+    /// Only randomize the cells where the variance of the source colors around them, averaged
+    /// over the RGB channels, is at most VARIANCE, e.g. 100 for skies and walls, keeping the
+    /// best matches in detailed regions such as faces and edges
+    randomize_max_variance: Option<f64>,
+
+    #[clap(long, value_name = "WEIGHT", value_parser = is_non_negative)]
+    /// Match the contours of the source image as well as its colors, weighing the difference
+    /// in edges per pixel against the difference in colors summed over the RGB channels.
+    /// Implies --greedy with --no-repeat
+    edge_weight: Option<f64>,
+
+    #[clap(long, value_name = "WEIGHT", value_parser = is_non_negative)]
+    /// Avoid tiles whose sides clash with the tiles already placed next to them, weighing the
+    /// difference between the colors along the touching sides against the difference in colors
+    /// with the source, both summed over the RGB channels, e.g. 0.5. Smooths the mosaic without
+    /// blending. Needs -m 2 or finer, matches the cells one at a time and implies --greedy with
+    /// --no-repeat
+    continuity_weight: Option<f64>,
+
     #[clap(long, default_values_t = [String::from("jpg"), String::from("jpeg")])]
     /// Extensions of image files in the tiles dir
     extensions: Vec<String>,
@@ -96,6 +586,110 @@ struct Mosaic {
     /// When combined with no-repeat, uses a less accurate but faster algorithm
     greedy: bool,
 
+    #[clap(long)]
+    /// Fill small regions of similar colors with pictures taken at the same event, so that
+    /// every area of the mosaic tells a story
+    story: bool,
+
+    #[clap(long, arg_enum, value_name = "DIRECTION")]
+    /// Favor older pictures at one end of the mosaic and newer ones at the other, trading
+    /// some color accuracy for a chronological reading. Implies --greedy with --no-repeat
+    time_gradient: Option<TimeGradient>,
+
+    #[clap(long, arg_enum, default_value_t = OrderBy::Color)]
+    /// Order the pictures by date, left to right and top to bottom unless --time-gradient
+    /// chooses another direction
+    order_by: OrderBy,
+
+    #[clap(long, value_name = "WEIGHT", value_parser = is_non_negative)]
+    /// Penalty, in color distance per pixel, for placing a picture at the opposite end of
+    /// the mosaic from its date, balancing dates against colors [default: 48]
+    date_weight: Option<f64>,
+
+    #[clap(long, value_name = "OFFSET")]
+    /// Time zone, as UTC or an offset such as +02:00, to show and group the dates the pictures
+    /// were taken in. Pictures that do not record their offset are taken to be in it
+    timezone: Option<TimeZone>,
+
+    #[clap(long, arg_enum, default_value_t = DateFormat::Date)]
+    /// How dates are shown in the tooltips of the HTML pages
+    date_format: DateFormat,
+
+    #[clap(long, arg_enum, default_value_t = Palette::Classic)]
+    /// Color scheme of the distance overlay and of the distances in the HTML pages
+    palette: Palette,
+
+    #[clap(long)]
+    /// Color the distance overlay, the distances in the HTML pages and the statistics image on
+    /// an absolute scale of the color distance per pixel, instead of from the best to the worst
+    /// match of this mosaic, so that mosaics can be compared
+    absolute_distances: bool,
+
+    #[clap(long, value_name = "PATH")]
+    /// Write the statistics image, the heatmap of the distances of the placements, to PATH
+    /// instead of <OUTPUT>.stats.png
+    stats_image: Option<PathBuf>,
+
+    #[clap(long, value_name = "PIXELS", default_value_t = 8, value_parser = clap::value_parser!(u32).range(1..=64))]
+    /// Side in pixels of the cells of the statistics image, whose columns and rows are
+    /// numbered along its sides and whose colors are explained under it
+    stats_scale: u32,
+
+    #[clap(long)]
+    /// Also write <OUTPUT>.diff.png, one pixel per tile colored with --palette by the delta-E
+    /// between the finished mosaic, tint included, and the source region under the tile, to
+    /// find the areas that look off
+    diff_image: bool,
+
+    #[clap(long)]
+    /// Measure the delta-E across every junction between neighbouring tiles of the finished
+    /// mosaic, report the harshest and write <OUTPUT>.seams.png, the junctions of every tile
+    /// colored with --palette. With --html or --web, the pages get a seam overlay too
+    seams: bool,
+
+    #[clap(long)]
+    /// Downsample the finished mosaic back to the resolution of the source and report its
+    /// PSNR and SSIM against the source with the runtime statistics, to compare modes and
+    /// options objectively. Not computed for dry runs
+    score: bool,
+
+    #[clap(long, value_name = "PATH")]
+    /// TOML file of tiles to pin to grid cells and tiles to exclude, with paths relative to the
+    /// first tiles directory. Implies --greedy with --no-repeat
+    pins: Option<PathBuf>,
+
+    #[clap(long, value_name = "SOURCE=OUTPUT")]
+    /// Also render the mosaic of the source image SOURCE to OUTPUT in this run, with the same
+    /// tiles and options, analysed and loaded once, e.g. for the other panels of a triptych.
+    /// Can be repeated
+    panel: Vec<Panel>,
+
+    #[clap(long, requires = "panel")]
+    /// Place every tile at most once across all the mosaics of the run, the first one and
+    /// those of --panel, e.g. so that every picture appears once in a triptych. The first
+    /// mosaics get the closest tiles. Implies --no-repeat
+    global_no_repeat: bool,
+
+    #[clap(long, value_name = "PATH")]
+    /// Image stretched over the mosaic: tiles are kept where it is white and the source image,
+    /// scaled up, is shown where it is black
+    mask: Option<PathBuf>,
+
+    #[clap(long, value_parser, value_name = "X,Y,W,H")]
+    /// Rectangle of the output image, in pixels from its top left corner, to keep clear of
+    /// tiles, e.g. for a title. Can be repeated
+    clear_region: Vec<ClearRegion>,
+
+    #[clap(long, value_enum, default_value_t = ClearFill::Source)]
+    /// What to show in the --clear-region rectangles
+    clear_fill: ClearFill,
+
+    #[clap(long, value_name = "DEGREES", allow_hyphen_values = true)]
+    /// Rotate the grid of tiles clockwise by this angle, e.g. 45 for a grid of diamonds. Cannot
+    /// be combined with --html, --web, --manifest or --pins, whose cells are those of an
+    /// upright grid
+    rotate_grid: Option<f64>,
+
     #[clap(long)]
     /// Generate HTML output with interactive tile tooltips showing distance and path
     html: bool,
@@ -104,9 +698,197 @@ struct Mosaic {
     /// Generate web-compatible HTML with relative URLs for static hosting (S3, etc.)
     web: bool,
 
+    #[clap(long, value_name = "PX", visible_alias = "export-max-px")]
+    /// Export thumbnails of the placed tiles, at most PX pixels on their longest side, to the
+    /// tiles/ directory next to the HTML and link them instead of the originals. Implies --web
+    web_thumbnails: Option<u32>,
+
+    #[clap(long, requires = "web-thumbnails", value_parser = clap::value_parser!(u8).range(1..=100))]
+    /// JPEG quality (1-100) of the exported thumbnails, 85 by default
+    export_quality: Option<u8>,
+
+    #[clap(long, requires = "web-thumbnails")]
+    /// Leave the EXIF data of the originals, such as their GPS position, out of the exported
+    /// thumbnails
+    export_strip_exif: bool,
+
+    #[clap(long)]
+    /// Generate HTML showing the mosaic as a zoomable Deep Zoom tile pyramid, for mosaics too
+    /// large for a single image in the browser. Implies --html
+    html_pyramid: bool,
+
+    #[clap(long, conflicts_with = "html-pyramid")]
+    /// Add a map to the pages of --html or --web showing where the pictures placed were taken,
+    /// from their EXIF GPS positions, with Leaflet and OpenStreetMap loaded when it is first
+    /// opened. Mind that the positions are published with the pages
+    map: bool,
+
+    #[clap(long, value_name = "PATH")]
+    /// Open pages, e.g. those of the pictures in a photo library, when clicking the tiles of
+    /// the HTML output instead of the tile images, from a TOML file of URLs by tile path,
+    /// relative to the tiles directory
+    tile_links: Option<PathBuf>,
+
+    #[clap(long, value_name = "PATH")]
+    /// Read the tile analyses from a JSON file, in the format written by --export-analysis,
+    /// instead of analysing the tiles directory
+    tiles_analysis: Option<PathBuf>,
+
+    #[clap(long, value_name = "PATH")]
+    /// Write the analyses of the tiles to a JSON file for external tools
+    export_analysis: Option<PathBuf>,
+
+    #[clap(long, value_name = "PATH")]
+    /// Write every tile placement and the mosaic configuration to a JSON manifest at PATH
+    manifest: Option<PathBuf>,
+
+    #[clap(long, value_name = "PATH")]
+    /// Write the usage count of every image, the distance of every placement and the worst
+    /// matches to PATH, as CSV or JSON from its extension
+    stats_out: Option<PathBuf>,
+
+    #[clap(long, value_name = "PATH")]
+    /// Write the total time, the time spent in every phase, the peak memory, the number of
+    /// tiles, the hit rate of the tile image cache and the average distance to a JSON file
+    stats_json: Option<PathBuf>,
+
     #[clap(long, default_value = "Mosaic Widget")]
-    /// Title for the generated HTML page
+    /// Title for the generated HTML page and output image metadata
     title: String,
+
+    #[clap(long)]
+    /// Description for the generated HTML page and output image metadata
+    description: Option<String>,
+
+    #[clap(long)]
+    /// Author for the generated HTML page and output image metadata
+    author: Option<String>,
+
+    #[clap(long, arg_enum, default_value_t = IndexKind::Auto)]
+    /// Nearest neighbor index: picked from the tile set size by default, exact kd-tree (compact: 16-bit coordinates, half the memory) or brute force, or approximate but much faster HNSW for huge tile sets
+    index: IndexKind,
+
+    #[clap(long)]
+    /// Render a quick low-resolution draft using a sample of the tiles, without writing any caches
+    preview: bool,
+
+    #[clap(long)]
+    /// Match the tiles against their cached analysis only, and write the statistics image, a
+    /// preview of the mosaic in the analysed colors and the HTML page without loading any tile
+    /// image
+    dry_run: bool,
+
+    #[clap(long)]
+    /// Discard the cached prepared tiles of rotated images and re-analyse, e.g. after fixing
+    /// their orientation
+    refresh_rotated: bool,
+
+    #[clap(long)]
+    /// Discard the cached prepared tiles of every tile image and re-analyse, e.g. after the
+    /// cache was filled by another version of emosaic
+    refresh_tiles: bool,
+
+    #[clap(long)]
+    /// Open the generated HTML page, or the output image, in the default viewer when done
+    open: bool,
+
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "PROTOCOL",
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "auto"
+    )]
+    /// Draw a low-resolution preview of the mosaic in the terminal as rows of tiles are
+    /// rendered, with ANSI colors, sixel or kitty graphics
+    terminal_preview: Option<TerminalProtocol>,
+
+    #[clap(long, value_name = "DISTANCE")]
+    /// Before matching, discard the tiles whose average color is farther than DISTANCE (summed
+    /// over the RGB channels, 0-765) from the average color of every block of the source image
+    prefilter: Option<u32>,
+
+    #[clap(long, value_name = "DISTANCE")]
+    /// Place synthetic tiles of the average color of the cell where the best tile is farther
+    /// than DISTANCE (per pixel, summed over the RGB channels, 0-765). Implies --greedy with
+    /// --no-repeat
+    filler: Option<u32>,
+
+    #[clap(long, value_name = "DISTANCE")]
+    /// Largest acceptable distance between a cell and its tile (per pixel, summed over the RGB
+    /// channels, 0-765). What happens to worse matches is chosen with --on-bad-match
+    max_distance: Option<u32>,
+
+    #[clap(long, value_enum, default_value_t = OnBadMatch::Warn)]
+    /// What to do with the matches farther than --max-distance. Filler and correct imply
+    /// --greedy with --no-repeat
+    on_bad_match: OnBadMatch,
+
+    #[clap(long, default_value_t = DEFAULT_IMAGE_CACHE_BYTES / MEGABYTE)]
+    /// Memory budget in MB for decoded tile images kept in memory while rendering
+    tile_cache_mb: usize,
+}
+
+impl Mosaic {
+    /// The tiles directories, the positional one first.
+    fn sources(&self) -> Vec<TileSource> {
+        let positional = self.tiles_dir.clone().map(TileSource::unweighted);
+        positional
+            .into_iter()
+            .chain(self.tiles_dirs.iter().cloned())
+            .collect()
+    }
+
+    /// The directory relative paths to tiles are resolved from, e.g. in pins and tile links:
+    /// the first tiles directory, or else the directory of the tiles list.
+    fn base_dir(&self) -> PathBuf {
+        match (self.sources().first(), &self.tiles_list) {
+            (Some(source), _) => source.path.clone(),
+            (None, Some(list)) => list_dir(list).to_owned(),
+            (None, None) => PathBuf::from("."),
+        }
+    }
+}
+
+const MEGABYTE: usize = 1024 * 1024;
+
+/// Tile size used for preview renders (rounded up to a multiple of the mode dimension)
+const PREVIEW_TILE_SIZE: u32 = 4;
+
+/// Maximum number of tiles sampled from the tile set for preview renders
+const PREVIEW_MAX_TILES: usize = 2000;
+
+/// Side in pixels of the tiles in the image of the junctions written with --seams
+const SEAM_CELL_SIZE: u32 = 16;
+
+/// How the progress of the long phases is reported
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum ProgressFormat {
+    /// Bars on stderr
+    Bar,
+    /// Newline delimited JSON events on stdout, for scripts and user interfaces
+    Json,
+}
+
+/// How the tiles are ordered across the mosaic
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum OrderBy {
+    /// By color only
+    Color,
+    /// Chronologically, trading some color accuracy for a timeline
+    Date,
+}
+
+/// Fallback for the cells whose best match is farther than --max-distance
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum OnBadMatch {
+    /// List them at the end of the statistics and in the HTML report
+    Warn,
+    /// Place synthetic tiles of the average color of the cell instead, as --filler
+    Filler,
+    /// Shift the colors of the tile to those of the cell
+    Correct,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
@@ -137,6 +919,26 @@ enum Mode {
     Random,
 }
 
+impl Mode {
+    /// Number of cells per tile side, or `None` for random mode.
+    fn dim(self) -> Option<u32> {
+        match self {
+            Mode::_1 => Some(1),
+            Mode::_2 => Some(2),
+            Mode::_3 => Some(3),
+            Mode::_4 => Some(4),
+            Mode::_5 => Some(5),
+            Mode::_6 => Some(6),
+            Mode::_8 => Some(8),
+            Mode::_16 => Some(16),
+            Mode::_32 => Some(32),
+            Mode::_64 => Some(64),
+            Mode::_128 => Some(128),
+            Mode::Random => None,
+        }
+    }
+}
+
 /// Parses str as f64 and returns the resulting value if between 0 and 1 (inclusive)
 fn is_between_zero_and_one(s: &str) -> Result<f64, String> {
     let value: f64 = s.parse().map_err(|e| format!("{}", e))?;
@@ -146,125 +948,67 @@ fn is_between_zero_and_one(s: &str) -> Result<f64, String> {
     Err(String::from("Value must be between 0 and 1"))
 }
 
-fn is_percentage(s: &str) -> Result<f64, String> {
+fn is_non_negative(s: &str) -> Result<f64, String> {
     let value: f64 = s.parse().map_err(|e| format!("{}", e))?;
-    if (0.0..=100.0).contains(&value) {
+    if value >= 0.0 {
         return Ok(value);
     }
-    Err(String::from("Value must be between 0 and 100"))
+    Err(String::from("Value must not be negative"))
 }
 
-/// Memory monitor that tracks peak RSS usage in a background thread
-struct MemoryMonitor {
-    peak_rss_kb: Arc<AtomicU64>,
-    shutdown: Arc<AtomicBool>,
-    _handle: thread::JoinHandle<()>,
-}
-
-impl MemoryMonitor {
-    /// Start monitoring memory usage in a background thread
-    fn start() -> Self {
-        let peak_rss_kb = Arc::new(AtomicU64::new(0));
-        let shutdown = Arc::new(AtomicBool::new(false));
-
-        let peak_rss_kb_clone = Arc::clone(&peak_rss_kb);
-        let shutdown_clone = Arc::clone(&shutdown);
-
-        let handle = thread::spawn(move || {
-            while !shutdown_clone.load(Ordering::Relaxed) {
-                if let Some(current_rss_kb) = get_current_rss_kb() {
-                    // Update peak if current is higher
-                    let mut current_peak = peak_rss_kb_clone.load(Ordering::Relaxed);
-                    while current_rss_kb > current_peak {
-                        match peak_rss_kb_clone.compare_exchange_weak(
-                            current_peak,
-                            current_rss_kb,
-                            Ordering::Relaxed,
-                            Ordering::Relaxed,
-                        ) {
-                            Ok(_) => break,
-                            Err(updated_peak) => current_peak = updated_peak,
-                        }
-                    }
-                }
-                thread::sleep(Duration::from_millis(100)); // Check every 100ms
-            }
-        });
-
-        Self {
-            peak_rss_kb,
-            shutdown,
-            _handle: handle,
-        }
-    }
-
-    /// Get the peak memory usage in MB
-    fn get_peak_mb(&self) -> String {
-        let peak_kb = self.peak_rss_kb.load(Ordering::Relaxed);
-        if peak_kb > 0 {
-            format!("{:.1}", peak_kb as f64 / 1024.0)
-        } else {
-            "N/A".to_string()
-        }
+fn is_at_least_one(s: &str) -> Result<f64, String> {
+    let value: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    if value >= 1.0 {
+        return Ok(value);
     }
+    Err(String::from("Value must be at least 1"))
 }
 
-impl Drop for MemoryMonitor {
-    fn drop(&mut self) {
-        self.shutdown.store(true, Ordering::Relaxed);
+fn is_percentage(s: &str) -> Result<f64, String> {
+    let value: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    if (0.0..=100.0).contains(&value) {
+        return Ok(value);
     }
+    Err(String::from("Value must be between 0 and 100"))
 }
 
-/// Get current RSS (Resident Set Size) in KB for the current process
-fn get_current_rss_kb() -> Option<u64> {
-    #[cfg(target_os = "macos")]
-    {
-        use std::process::Command;
-        let output = Command::new("ps")
-            .args(["-o", "rss=", "-p"])
-            .arg(std::process::id().to_string())
-            .output()
-            .ok()?;
-
-        let rss_str = String::from_utf8(output.stdout).ok()?;
-        rss_str.trim().parse::<u64>().ok()
-    }
-
-    #[cfg(target_os = "linux")]
-    {
-        let status = std::fs::read_to_string("/proc/self/status").ok()?;
-        for line in status.lines() {
-            if line.starts_with("VmRSS:") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    return parts[1].parse::<u64>().ok();
-                }
-            }
-        }
-        None
+/// Open a generated file in the default browser or image viewer.
+///
+/// Failing to open it is not an error, the file has already been written.
+fn open_result(path: &Path) {
+    if is_stdio(path) {
+        warn!("⚠️  The output image was written to stdout, there is no file to open");
+        return;
     }
-
-    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
-    {
-        None
+    info!("🔗 Opening {}", path.display());
+    if let Err(e) = opener::open(path) {
+        warn!("⚠️  Failed to open {}: {}", path.display(), e);
     }
 }
 
-fn print_runtime_stats(start_time: Instant, memory_monitor: &MemoryMonitor) {
+fn print_runtime_stats(
+    start_time: Instant,
+    memory_monitor: &MemoryMonitor,
+    score: Option<QualityScore>,
+) {
     let duration = start_time.elapsed();
     let total_secs = duration.as_secs_f64();
 
-    eprintln!("📊 Runtime Statistics:");
-    eprintln!("   Total execution time: {:.2}s", total_secs);
+    info!("📊 Runtime Statistics:");
+    info!("   Total execution time: {:.2}s", total_secs);
 
     if total_secs >= 60.0 {
         let mins = total_secs as u64 / 60;
         let secs = total_secs % 60.0;
-        eprintln!("   ({} min {:.1}s)", mins, secs);
+        info!("   ({} min {:.1}s)", mins, secs);
     }
 
     if total_secs >= 1.0 {
-        eprintln!("   Peak memory usage: {} MB", memory_monitor.get_peak_mb());
+        info!("   Peak memory usage: {} MB", memory_monitor.get_peak_mb());
+    }
+
+    if let Some(score) = score {
+        info!("   Quality against the source: {}", score);
     }
 }
 
@@ -305,7 +1049,7 @@ fn validate_input_image(path: &Path) -> Result<(), String> {
             ));
         }
     } else {
-        return Err(format!("❌ Input file has no extension\n💡 Please use an image file with a proper extension like .jpg or .png"));
+        return Err("❌ Input file has no extension\n💡 Please use an image file with a proper extension like .jpg or .png".to_string());
     }
 
     Ok(())
@@ -343,483 +1087,2959 @@ fn validate_output_path(path: &Path) -> Result<(), String> {
     }
     Ok(())
 }
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            error!("{}", error);
+            ExitCode::from(error.exit_code())
+        }
+    }
+}
+
+fn run() -> Result<(), EmosaicError> {
     // unsafe { backtrace_on_stack_overflow::enable() };
 
     let start_time = Instant::now();
-    let memory_monitor = MemoryMonitor::start();
 
     let cli = Cli::parse();
+    let library_args = cli.library_args();
 
     let Cli {
         img,
         output_path,
+        format,
+        quality,
+        speed,
+        lossless,
         tile_size,
         subcmd,
         crop,
+        max_source_pixels,
+        threads,
+        max_memory,
+        cache_dir,
+        exclude,
+        follow_symlinks,
+        min_tile_resolution,
+        max_aspect_ratio,
+        min_sharpness,
+        augment_crops,
+        progress: progress_format,
+        verbose,
+        quiet,
+        log_format,
     } = cli;
 
+    logging::init(logging::level(verbose, quiet), log_format);
+    let memory_monitor = MemoryMonitor::start(max_memory);
+
+    progress::set_json(progress_format == ProgressFormat::Json);
+    let excludes = Ignore::excludes(&exclude)?;
+
+    if let Some(threads) = threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(usize::from(threads))
+            .build_global()
+            .map_err(|e| EmosaicError::Validation(e.to_string()))?;
+    }
+
+    // The image piped to stdin is kept in a temporary file for the whole run
+    let stdin_img = match &img {
+        Some(img) if is_stdio(img) => Some(
+            read_stdin_image()
+                .map_err(|e| EmosaicError::io("❌ Failed to read the input image from stdin", e))?,
+        ),
+        _ => None,
+    };
+    let img = match &stdin_img {
+        Some(file) => Some(file.path().to_path_buf()),
+        None => img,
+    };
+
     // Validate CLI arguments
-    validate_tile_size(tile_size)?;
-    validate_input_image(&img)?;
-    validate_output_path(&output_path)?;
+    validate_tile_size(tile_size).map_err(EmosaicError::Validation)?;
+    if let Some(img) = &img {
+        validate_input_image(img).map_err(EmosaicError::Validation)?;
+    }
+    let require_img = |img: Option<PathBuf>| {
+        img.ok_or_else(|| {
+            EmosaicError::Validation(String::from("❌ Missing the path to the input image"))
+        })
+    };
+    if is_stdio(&output_path) {
+        check_stdout(format, progress_format == ProgressFormat::Json)?;
+    } else {
+        validate_output_path(&output_path).map_err(EmosaicError::Validation)?;
+    }
+    let output_options = OutputOptions {
+        format: format
+            .or_else(|| OutputFormat::from_path(&output_path))
+            .unwrap_or(OutputFormat::Png),
+        quality,
+        speed,
+        lossless,
+    };
 
     let cache_path: PathBuf = dirs::cache_dir()
-        .ok_or_else(|| "Failed to get cache directory")?
+        .ok_or_else(|| EmosaicError::Validation(String::from("Failed to get cache directory")))?
         .join("mosaic");
     create_dir_all(&cache_path).map_err(|e| {
-        format!(
-            "Failed to create cache directory {}: {}",
-            cache_path.display(),
-            e
+        EmosaicError::io(
+            format!("Failed to create cache directory {}", cache_path.display()),
+            e,
         )
     })?;
+    let library = LibraryOptions {
+        cache_root: cache_dir.map(|dir| dir.unwrap_or_else(|| cache_path.join("analysis"))),
+        excludes,
+        follow_symlinks,
+        filter: TileFilter {
+            min_resolution: min_tile_resolution,
+            max_aspect_ratio,
+            min_sharpness: min_sharpness.map(|score| score as f32),
+        },
+        augment_crops: augment_crops.map_or(0, usize::from),
+        memory_budget: max_memory,
+    };
 
     match subcmd {
         None => (),
         Some(SubCommand::Prepare) => {
-            let tile = prepare_tile(&img, tile_size, crop)
-                .map_err(|e| format!("Failed to prepare tile from {}: {}", img.display(), e))?;
-            tile.save(&output_path)
-                .map_err(|e| format!("Failed to save tile to {}: {}", output_path.display(), e))?;
-            print_runtime_stats(start_time, &memory_monitor);
+            let img = require_img(img)?;
+            let tile = prepare_tile(&img, tile_size, crop, true).map_err(|e| {
+                EmosaicError::decode(format!("Failed to prepare tile from {}", img.display()), e)
+            })?;
+            save_output(&tile.into(), &output_path, &output_options, &[]).map_err(|e| {
+                EmosaicError::decode(
+                    format!("Failed to save tile to {}", output_path.display()),
+                    e,
+                )
+            })?;
+            print_runtime_stats(start_time, &memory_monitor, None);
         }
-        Some(SubCommand::Mosaic(args)) => {
-            // Validate tiles directory
-            validate_tiles_directory(&args.tiles_dir)?;
+        Some(SubCommand::RenderFromManifest(args)) => {
+            let manifest = Manifest::load(&args.manifest).map_err(|e| {
+                EmosaicError::io(
+                    format!("Failed to read manifest {}", args.manifest.display()),
+                    e,
+                )
+            })?;
+            let config = &manifest.config;
+            let tint_opacity = f64::from(config.tint_opacity);
+            let (cols, rows) = manifest.grid_size();
+            let dimensions = (cols as u32 * tile_size, rows as u32 * tile_size);
+            let channels = if tint_opacity > 0.0 { 4 } else { 3 };
+            check_output_space(&output_path, &output_options, dimensions, channels)
+                .map_err(EmosaicError::Validation)?;
+            info!(
+                "Re-rendering {} placements of {} at {}x{}",
+                manifest.placements.len(),
+                args.manifest.display(),
+                dimensions.0,
+                dimensions.1
+            );
 
-            let mode = args.mode;
-            let tint_opacity = args.tint_opacity;
-            let img_path = &img;
-            // Open the source image
-            eprintln!("Opening source image: {}", img_path.display());
-            let img = image::open(img_path)
-                .map_err(|e| format!("Failed to open source image {}: {}", img_path.display(), e))?
-                .to_rgb8();
-
-            let img_and_stats = match mode {
-                Mode::_1 => n_to_1::<1>(args, &img, tile_size, crop, mode, tint_opacity as f32),
-                Mode::_2 => n_to_1::<4>(args, &img, tile_size, crop, mode, tint_opacity as f32),
-                Mode::_3 => n_to_1::<9>(args, &img, tile_size, crop, mode, tint_opacity as f32),
-                Mode::_4 => n_to_1::<16>(args, &img, tile_size, crop, mode, tint_opacity as f32),
-                Mode::_5 => n_to_1::<25>(args, &img, tile_size, crop, mode, tint_opacity as f32),
-                Mode::_6 => n_to_1::<36>(args, &img, tile_size, crop, mode, tint_opacity as f32),
-                Mode::_8 => n_to_1::<64>(args, &img, tile_size, crop, mode, tint_opacity as f32),
-                Mode::_16 => n_to_1::<256>(args, &img, tile_size, crop, mode, tint_opacity as f32),
-                Mode::_32 => n_to_1::<1024>(args, &img, tile_size, crop, mode, tint_opacity as f32),
-                Mode::_64 => n_to_1::<4096>(args, &img, tile_size, crop, mode, tint_opacity as f32),
-                Mode::_128 => {
-                    n_to_1::<16384>(args, &img, tile_size, crop, mode, tint_opacity as f32)
+            let (image_cache_budget, memory_budget) =
+                (args.tile_cache_mb * MEGABYTE, library.memory_budget);
+            let output = render_manifest(
+                &manifest,
+                tile_size,
+                image_cache_budget,
+                memory_budget,
+                None,
+            )
+            .map_err(|e| EmosaicError::decode("Failed to render manifest", e))?;
+            let output = if tint_opacity > 0.0 {
+                let img = require_img(img)?;
+                let source = open_limited(&img, max_source_pixels)?.to_rgb8();
+                tint(output, &source, tint_opacity)
+            } else {
+                DynamicImage::ImageRgb8(output)
+            };
+
+            let mut metadata = vec![("Title", config.title.as_str())];
+            if let Some(description) = &config.description {
+                metadata.push(("Description", description));
+            }
+            if let Some(author) = &config.author {
+                metadata.push(("Author", author));
+            }
+            save_output(&output, &output_path, &output_options, &metadata).map_err(|e| {
+                let context = format!("Failed to save output image to {}", output_path.display());
+                EmosaicError::decode(context, e)
+            })?;
+            info!(
+                "🎉 All done! Your mosaic is ready at {}",
+                output_path.display()
+            );
+            if args.open {
+                open_result(&output_path);
+            }
+        }
+        Some(SubCommand::Diff(args)) => {
+            let load = |path: &Path| {
+                Manifest::load(path).map_err(|e| {
+                    EmosaicError::io(format!("Failed to read manifest {}", path.display()), e)
+                })
+            };
+            let diff = ManifestDiff::new(&load(&args.before)?, &load(&args.after)?)?;
+            diff.summarise();
+            let output = diff.render(tile_size);
+            save_output(&output.into(), &output_path, &output_options, &[]).map_err(|e| {
+                let context = format!("Failed to save output image to {}", output_path.display());
+                EmosaicError::decode(context, e)
+            })?;
+            info!("The comparison is ready at {}", output_path.display());
+            if args.open {
+                open_result(&output_path);
+            }
+        }
+        Some(SubCommand::Wall(args)) => {
+            let tile_set = load_wall_tiles(&args.tiles, tile_size, crop, &library)?;
+            let arranged = wall::arrange(&tile_set, args.order);
+            let (columns, rows) = wall_grid(&args.tiles, arranged.len());
+            let dimensions = (columns * tile_size, rows * tile_size);
+            check_output_space(&output_path, &output_options, dimensions, 3)
+                .map_err(EmosaicError::Validation)?;
+            info!(
+                "Arranging {} tiles on a {}x{} wall",
+                arranged.len(),
+                columns,
+                rows
+            );
+            let rendered = wall::render_wall(&tile_set, &arranged, columns, tile_size, None);
+            save_wall(rendered, &output_path, &output_options, args.tiles.open)?;
+        }
+        Some(SubCommand::ContactSheet(args)) => {
+            let tile_set = load_wall_tiles(&args.tiles, tile_size, crop, &library)?;
+            let arranged = contact_sheet::arrange(&tile_set);
+            let (columns, rows) = wall_grid(&args.tiles, arranged.len());
+            let band = match args.captions {
+                Some(_) => contact_sheet::caption_height(tile_size),
+                None => 0,
+            };
+            let dimensions = (columns * tile_size, rows * (tile_size + band));
+            check_output_space(&output_path, &output_options, dimensions, 3)
+                .map_err(EmosaicError::Validation)?;
+            info!(
+                "Laying out {} tiles on a {}x{} contact sheet",
+                arranged.len(),
+                columns,
+                rows
+            );
+            let rendered = contact_sheet::render_contact_sheet(
+                &tile_set,
+                &arranged,
+                columns,
+                tile_size,
+                args.captions,
+            );
+            save_wall(rendered, &output_path, &output_options, args.tiles.open)?;
+        }
+        Some(SubCommand::Coverage(args)) => {
+            let img = require_img(img)?;
+            validate_tiles_directory(&args.library.tiles_dir).map_err(EmosaicError::Validation)?;
+            if args.downsample == 0 {
+                return Err(EmosaicError::Validation(String::from(
+                    "❌ --downsample must be at least 1",
+                )));
+            }
+            let source = open_limited(&img, max_source_pixels)?.to_rgb8();
+            let (width, height) = (
+                (source.width() / u32::from(args.downsample)).max(1),
+                (source.height() / u32::from(args.downsample)).max(1),
+            );
+            check_output_space(
+                &output_path,
+                &output_options,
+                (width * tile_size, height * tile_size),
+                3,
+            )
+            .map_err(EmosaicError::Validation)?;
+            let tile_set = args.library.load::<1>(tile_size, crop, &library)?;
+            if tile_set.is_empty() {
+                return Err(EmosaicError::Validation(format!(
+                    "❌ No tiles found in {}",
+                    args.library.tiles_dir.display()
+                )));
+            }
+            let cells = imageops::resize(&source, width, height, FilterType::Lanczos3);
+            info!(
+                "Comparing the {}x{} cells of {} with {} tiles",
+                width,
+                height,
+                img.display(),
+                tile_set.len()
+            );
+            let coverage = coverage::Coverage::new(&cells, &tile_set);
+            coverage.summarise(args.max_distance);
+            let heatmap = coverage.render(tile_size, args.palette);
+            save_output(&heatmap.into(), &output_path, &output_options, &[]).map_err(|e| {
+                let context = format!("Failed to save output image to {}", output_path.display());
+                EmosaicError::decode(context, e)
+            })?;
+            info!("The coverage heatmap is ready at {}", output_path.display());
+            if args.open {
+                open_result(&output_path);
+            }
+        }
+        Some(SubCommand::Cache(args)) => match args.command {
+            CacheCommand::Upgrade { tiles_dirs } => {
+                for tiles_dir in &tiles_dirs {
+                    validate_tiles_directory(tiles_dir).map_err(EmosaicError::Validation)?;
+                    upgrade_cache(tiles_dir, &library)?;
                 }
-                Mode::Random => {
-                    let images = find_images(&args.tiles_dir, |ext| {
-                        args.extensions.contains(&ext.to_string_lossy().to_string())
-                    });
-                    let mut tile_set = TileSet::<()>::new();
-                    let extensions: HashSet<String> =
-                        args.extensions.iter().map(|x| x.to_owned()).collect();
-                    for path_buf in images.map_err(|e| {
-                        format!(
-                            "Failed to find images in {}: {}",
-                            args.tiles_dir.display(),
-                            e
-                        )
-                    })? {
-                        if let Some(ext) = path_buf.extension() {
-                            if let Some(ext_str) = ext.to_str() {
-                                if extensions.contains(ext_str) && path_buf.exists() {
-                                    tile_set.push_tile(path_buf, ());
-                                }
-                            }
-                        }
+            }
+        },
+        Some(SubCommand::Bench(args)) => {
+            let img = require_img(img)?;
+            validate_tiles_directory(&args.library.tiles_dir).map_err(EmosaicError::Validation)?;
+            let source = open_limited(&img, max_source_pixels)?.to_rgb8();
+            let mut rows = Vec::new();
+            for &mode in &args.modes {
+                let bench = (&args, &library, &source, tile_size, crop, mode);
+                rows.extend(match mode {
+                    Mode::_1 => bench_mode::<1>(bench)?,
+                    Mode::_2 => bench_mode::<4>(bench)?,
+                    Mode::_3 => bench_mode::<9>(bench)?,
+                    Mode::_4 => bench_mode::<16>(bench)?,
+                    Mode::_5 => bench_mode::<25>(bench)?,
+                    Mode::_6 => bench_mode::<36>(bench)?,
+                    Mode::_8 => bench_mode::<64>(bench)?,
+                    Mode::_16 => bench_mode::<256>(bench)?,
+                    Mode::_32 => bench_mode::<1024>(bench)?,
+                    Mode::_64 => bench_mode::<4096>(bench)?,
+                    Mode::_128 => bench_mode::<16384>(bench)?,
+                    Mode::Random => {
+                        return Err(EmosaicError::Validation(String::from(
+                            "❌ The random mode does not match tiles, it cannot be benchmarked",
+                        )))
                     }
-                    eprintln!("Tile set with {} tiles", tile_set.len());
-                    Ok(ImgAndStats {
-                        img: render_random(&img, tile_set, tile_size),
-                        stats_img: None,
-                        html_generator: None,
-                    })
-                }
+                });
+            }
+            print!("{}", bench::table(&rows));
+        }
+        Some(SubCommand::Init(args)) => run_init(&args, img.as_deref(), &library)?,
+        Some(SubCommand::Serve(args)) => {
+            let img = require_img(img)?;
+            run_serve(
+                &args,
+                &library,
+                library_args,
+                &img,
+                &output_path,
+                tile_size,
+                crop,
+            )?
+        }
+        Some(SubCommand::Mosaic(mut args)) => {
+            cancel::install_handler();
+            // Validate tiles directories
+            let sources = args.sources();
+            for source in &sources {
+                validate_tiles_directory(&source.path).map_err(EmosaicError::Validation)?;
             }
-            .map_err(|e| format!("Mosaic generation failed: {}", e))?;
-
-            let output = img_and_stats.img;
-            if tint_opacity > 0.0 {
-                // Create overlay more efficiently using from_fn
-                let alpha_value = (255.0 * tint_opacity) as u8;
-                let overlay = RgbaImage::from_fn(img.width(), img.height(), |x, y| {
-                    let p = img.get_pixel(x, y);
-                    Rgba([p[0], p[1], p[2], alpha_value])
-                });
 
-                // Scale up to match the output size
-                let overlay = imageops::resize(
-                    &overlay,
-                    output.width(),
-                    output.height(),
-                    FilterType::Nearest,
-                );
+            if args.refresh_rotated || args.refresh_tiles {
+                let extensions: HashSet<OsString> =
+                    args.extensions.iter().map(OsString::from).collect();
+                let mut paths = Vec::new();
+                for source in &sources {
+                    paths.extend(
+                        find_images(&source.path, |ext| extensions.contains(ext), &library)
+                            .map_err(|e| {
+                                let context =
+                                    format!("Failed to find images in {}", source.path.display());
+                                EmosaicError::io(context, e)
+                            })?,
+                    );
+                }
+                if let Some(path) = &args.tiles_list {
+                    paths.extend(
+                        read_tile_list(path)?
+                            .tiles
+                            .into_iter()
+                            .map(|tile| tile.path),
+                    );
+                }
+                let (deleted, which) = if args.refresh_tiles {
+                    (refresh_tiles(&paths), "")
+                } else {
+                    (refresh_rotated_tiles(&paths), " of rotated images")
+                };
+                let deleted = deleted.map_err(|e| {
+                    EmosaicError::io(format!("Failed to refresh the cached tiles{}", which), e)
+                })?;
+                info!("🔄 Discarded {} cached tiles{}", deleted, which);
+                if deleted > 0 {
+                    args.force = true;
+                }
+            }
 
-                // Apply overlay
-                let mut output2 = DynamicImage::ImageRgb8(output).to_rgba8();
-                imageops::overlay(&mut output2, &overlay, 0, 0);
+            let mode = args.mode;
+            let tint_opacity = args.tint_opacity;
+            let open = args.open;
+            let mut metadata = vec![("Title", args.title.clone())];
+            if let Some(description) = &args.description {
+                metadata.push(("Description", description.clone()));
+            }
+            if let Some(author) = &args.author {
+                metadata.push(("Author", author.clone()));
+            }
+            let metadata: Vec<(&str, &str)> = metadata
+                .iter()
+                .map(|(key, text)| (*key, text.as_str()))
+                .collect();
+            let img = require_img(img)?;
+            let img_path = &img;
+            let panels = std::mem::take(&mut args.panel);
+            if !panels.is_empty() {
+                check_panels(&args)?;
+            }
+            for panel in &panels {
+                validate_input_image(&panel.source).map_err(EmosaicError::Validation)?;
+                validate_output_path(&panel.output).map_err(EmosaicError::Validation)?;
+            }
+            // Open the source image
+            info!("Opening source image: {}", img_path.display());
+            let img = open_limited(img_path, max_source_pixels)?.to_rgb8();
+            let mask = match &args.mask {
+                Some(mask_path) => Some(open_limited(mask_path, max_source_pixels)?.to_luma8()),
+                None => None,
+            };
+            let panel_sources = (panels.iter())
+                .map(|panel| Ok(open_limited(&panel.source, max_source_pixels)?.to_rgb8()))
+                .collect::<Result<Vec<_>, EmosaicError>>()?;
+            let (clear_region, clear_fill) =
+                (std::mem::take(&mut args.clear_region), args.clear_fill);
+            let rotate_grid = args.rotate_grid.filter(|degrees| degrees % 360.0 != 0.0);
+            if let Some(degrees) = rotate_grid {
+                if !degrees.is_finite() {
+                    return Err(EmosaicError::Validation(format!(
+                        "❌ Invalid --rotate-grid angle {}",
+                        degrees
+                    )));
+                }
+                if args.html
+                    || args.web
+                    || args.manifest.is_some()
+                    || args.pins.is_some()
+                    || args.seams
+                {
+                    return Err(EmosaicError::Validation(String::from(
+                        "--rotate-grid cannot be combined with --html, --web, --manifest, --pins \
+                         or --seams",
+                    )));
+                }
+            }
+            if let Some(path) = &args.stats_out {
+                stats_out_format(path)?;
+            }
+            if args.map && !(args.html || args.web) {
+                return Err(EmosaicError::Validation(String::from(
+                    "❌ --map adds a map to the HTML pages, it needs --html or --web",
+                )));
+            }
+            if is_stdio(&output_path)
+                && (args.html
+                    || args.web
+                    || args.html_pyramid
+                    || args.diff_image
+                    || args.seams
+                    || args.dry_run)
+            {
+                return Err(EmosaicError::Validation(String::from(
+                    "-o - cannot be combined with --html, --web, --html-pyramid, --diff-image, \
+                     --seams or --dry-run, which write their files next to the output image",
+                )));
+            }
+            let dry_run = args.dry_run;
+            if dry_run && mode == Mode::Random {
+                return Err(EmosaicError::Validation(String::from(
+                    "--dry-run needs the analysis of the tiles, it cannot be used in random mode",
+                )));
+            }
+            if dry_run {
+                check_dry_run(&args)?;
+            }
+            // Tiles are matched on the source turned the other way, and the mosaic is turned
+            // back and cropped to the size of an upright one
+            let rotated_source = rotate_grid.map(|degrees| {
+                rotate(
+                    &img,
+                    -degrees,
+                    rotated_dimensions(img.dimensions(), degrees),
+                )
+            });
+            let source = rotated_source.as_ref().unwrap_or(&img);
+
+            let channels = if tint_opacity > 0.0 { 4 } else { 3 };
+            let dimensions = output_dimensions(&img, tile_size, &args);
+            if !dry_run {
+                check_output_space(&output_path, &output_options, dimensions, channels)
+                    .map_err(EmosaicError::Validation)?;
+            }
 
-                output2
-                    .save_with_format(&output_path, ImageFormat::Png)
-                    .map_err(|e| {
-                        format!(
-                            "Failed to save output image to {}: {}",
-                            output_path.display(),
-                            e
-                        )
-                    })?;
-                print_runtime_stats(start_time, &memory_monitor);
+            let terminal_preview = args
+                .terminal_preview
+                .map(|protocol| TerminalPreview::new(protocol, dimensions));
+            let preview = terminal_preview.as_ref();
+            // The rows of a rotated grid are not rows of the output image
+            let row_preview = preview.filter(|_| rotate_grid.is_none());
+            let score = args.score;
+            let stats_json = args.stats_json.clone();
+            let stats_image = args.stats_image.clone();
+            let grid_tile_size = output_tile_size(tile_size, &args);
+            let grid = (dimensions.0 / grid_tile_size, dimensions.1 / grid_tile_size);
+            let diff_image = args
+                .diff_image
+                .then(|| (grid, args.mode.dim().unwrap_or(1), args.palette));
+            let seams = args.seams.then_some((grid, args.palette));
+            // The tiles that the mask or the clear regions hide are left out of the reports, but
+            // the cells of a rotated grid are not those of the output image
+            let hidden = hidden_cells(grid, dimensions, &clear_region, mask.as_ref());
+            let hidden_placements = match rotate_grid {
+                Some(_) => HashSet::new(),
+                None => hidden.clone(),
+            };
+            let mut img_and_stats = match mode {
+                Mode::_1 => n_to_1::<1>(
+                    args,
+                    &library,
+                    source,
+                    &panel_sources,
+                    tile_size,
+                    crop,
+                    mode,
+                    tint_opacity as f32,
+                    row_preview,
+                    &hidden_placements,
+                ),
+                Mode::_2 => n_to_1::<4>(
+                    args,
+                    &library,
+                    source,
+                    &panel_sources,
+                    tile_size,
+                    crop,
+                    mode,
+                    tint_opacity as f32,
+                    row_preview,
+                    &hidden_placements,
+                ),
+                Mode::_3 => n_to_1::<9>(
+                    args,
+                    &library,
+                    source,
+                    &panel_sources,
+                    tile_size,
+                    crop,
+                    mode,
+                    tint_opacity as f32,
+                    row_preview,
+                    &hidden_placements,
+                ),
+                Mode::_4 => n_to_1::<16>(
+                    args,
+                    &library,
+                    source,
+                    &panel_sources,
+                    tile_size,
+                    crop,
+                    mode,
+                    tint_opacity as f32,
+                    row_preview,
+                    &hidden_placements,
+                ),
+                Mode::_5 => n_to_1::<25>(
+                    args,
+                    &library,
+                    source,
+                    &panel_sources,
+                    tile_size,
+                    crop,
+                    mode,
+                    tint_opacity as f32,
+                    row_preview,
+                    &hidden_placements,
+                ),
+                Mode::_6 => n_to_1::<36>(
+                    args,
+                    &library,
+                    source,
+                    &panel_sources,
+                    tile_size,
+                    crop,
+                    mode,
+                    tint_opacity as f32,
+                    row_preview,
+                    &hidden_placements,
+                ),
+                Mode::_8 => n_to_1::<64>(
+                    args,
+                    &library,
+                    source,
+                    &panel_sources,
+                    tile_size,
+                    crop,
+                    mode,
+                    tint_opacity as f32,
+                    row_preview,
+                    &hidden_placements,
+                ),
+                Mode::_16 => n_to_1::<256>(
+                    args,
+                    &library,
+                    source,
+                    &panel_sources,
+                    tile_size,
+                    crop,
+                    mode,
+                    tint_opacity as f32,
+                    row_preview,
+                    &hidden_placements,
+                ),
+                Mode::_32 => n_to_1::<1024>(
+                    args,
+                    &library,
+                    source,
+                    &panel_sources,
+                    tile_size,
+                    crop,
+                    mode,
+                    tint_opacity as f32,
+                    row_preview,
+                    &hidden_placements,
+                ),
+                Mode::_64 => n_to_1::<4096>(
+                    args,
+                    &library,
+                    source,
+                    &panel_sources,
+                    tile_size,
+                    crop,
+                    mode,
+                    tint_opacity as f32,
+                    row_preview,
+                    &hidden_placements,
+                ),
+                Mode::_128 => n_to_1::<16384>(
+                    args,
+                    &library,
+                    source,
+                    &panel_sources,
+                    tile_size,
+                    crop,
+                    mode,
+                    tint_opacity as f32,
+                    row_preview,
+                    &hidden_placements,
+                ),
+                Mode::Random => random(
+                    args,
+                    &library,
+                    source,
+                    tile_size,
+                    crop,
+                    tint_opacity as f32,
+                    row_preview,
+                    &hidden_placements,
+                ),
+            }?;
+            let summary = img_and_stats.summary;
+            let (tiles, cache_hit_rate) = (img_and_stats.tiles, img_and_stats.cache_hit_rate);
+            // Once the outputs are written, so that they count in the total time
+            let runtime_stats = |score| -> Result<(), EmosaicError> {
+                print_runtime_stats(start_time, &memory_monitor, score);
+                let Some(path) = &stats_json else {
+                    return Ok(());
+                };
+                let path = match cancel::interrupted() {
+                    true => cancel::partial_path(path),
+                    false => path.clone(),
+                };
+                let stats = RuntimeStats {
+                    total_seconds: start_time.elapsed().as_secs_f64(),
+                    phases: RuntimeStats::phases(&logging::phase_durations()),
+                    peak_rss_mb: memory_monitor.peak_mb(),
+                    tiles,
+                    summary,
+                    cache_hit_rate,
+                };
+                stats.save(&path).map_err(|e| {
+                    let context = format!(
+                        "❌ Failed to write runtime statistics to {}",
+                        path.display()
+                    );
+                    EmosaicError::io(context, e)
+                })?;
+                info!("⏱️  Runtime statistics saved to {}", path.display());
+                Ok(())
+            };
+            // What was done before Ctrl-C is saved next to the outputs, without replacing them
+            let interrupted = cancel::interrupted();
+            // Only the statistics image asked for is written along with a mosaic sent to stdout
+            let write_stats_image = stats_image.is_some() || !is_stdio(&output_path);
+            let output_path = if interrupted && !is_stdio(&output_path) {
+                cancel::partial_path(&output_path)
+            } else {
+                output_path
+            };
+            let stats_path = match stats_image {
+                Some(path) if interrupted => cancel::partial_path(&path),
+                Some(path) => path,
+                None => output_path.with_extension("stats.png"),
+            };
+            if dry_run {
+                let result_path = write_dry_run(img_and_stats, &output_path, &stats_path)?;
+                if open {
+                    open_result(&result_path);
+                }
+                runtime_stats(None)?;
                 return Ok(());
             }
+            if let Some(degrees) = rotate_grid {
+                img_and_stats.img = rotate(&img_and_stats.img, degrees, dimensions);
+            }
+            if let Some(mask) = &mask {
+                img_and_stats.img = apply_mask(std::mem::take(&mut img_and_stats.img), &img, mask);
+            }
+            let clear = |mosaic| clear_regions(mosaic, &img, &clear_region, clear_fill);
+            if !clear_region.is_empty() {
+                img_and_stats.img = clear(std::mem::take(&mut img_and_stats.img));
+            }
+            if let Some(preview) = preview {
+                preview.finish(&img_and_stats.img);
+            }
+            let panel_images = std::mem::take(&mut img_and_stats.panels);
+            for ((panel, source), image) in panels.iter().zip(&panel_sources).zip(panel_images) {
+                let path = match interrupted {
+                    true => cancel::partial_path(&panel.output),
+                    false => panel.output.clone(),
+                };
+                let output = match tint_opacity > 0.0 {
+                    true => tint(image, source, tint_opacity),
+                    false => DynamicImage::ImageRgb8(image),
+                };
+                save_output(&output, &path, &output_options, &metadata).map_err(|e| {
+                    let context = format!("Failed to save output image to {}", path.display());
+                    EmosaicError::decode(context, e)
+                })?;
+                info!(
+                    "🖼️  Mosaic of {} saved to {}",
+                    panel.source.display(),
+                    path.display()
+                );
+            }
 
-            eprintln!("✓ Mosaic generation completed successfully");
-            eprintln!("📝 Writing output file to {}", output_path.display());
-            output
-                .save_with_format(&output_path, ImageFormat::Png)
-                .map_err(|e| {
-                    format!(
-                        "❌ Failed to save output image to {}: {}\n💡 Ensure the directory is writable and has sufficient disk space",
-                        output_path.display(),
-                        e
-                    )
+            let output = img_and_stats.img;
+            if tint_opacity > 0.0 {
+                let mut output2 = tint(output, &img, tint_opacity);
+                if !clear_region.is_empty() {
+                    output2 = DynamicImage::ImageRgb8(clear(output2.into_rgb8()));
+                }
+                save_output(&output2, &output_path, &output_options, &metadata).map_err(|e| {
+                    let context =
+                        format!("Failed to save output image to {}", output_path.display());
+                    EmosaicError::decode(context, e)
                 })?;
+                if let Some(diff_image) = diff_image {
+                    write_diff_image(&output2.to_rgb8(), &img, diff_image, &hidden, &output_path)?;
+                }
+                if let Some(seams) = seams {
+                    write_seam_image(&output2.to_rgb8(), seams, &hidden, &output_path)?;
+                }
+                let score =
+                    score.then(|| QualityScore::hiding(&output2.to_rgb8(), &img, grid, &hidden));
+                if interrupted {
+                    runtime_stats(score)?;
+                    return Err(interrupted_error(&output_path));
+                }
+                if open {
+                    open_result(&output_path);
+                }
+                runtime_stats(score)?;
+                return Ok(());
+            }
+
+            info!("✓ Mosaic generation completed successfully");
+            info!("📝 Writing output file to {}", output_path.display());
+            let output = DynamicImage::ImageRgb8(output);
+            save_output(&output, &output_path, &output_options, &metadata).map_err(|e| {
+                let context = format!(
+                    "❌ Failed to save output image to {}",
+                    output_path.display()
+                );
+                let hint = "💡 Ensure the directory is writable and has sufficient disk space";
+                EmosaicError::decode(context, format!("{}\n{}", e, hint))
+            })?;
+            let mosaic_image = output.as_rgb8().expect("mosaic is an RGB image");
+            if let Some(diff_image) = diff_image {
+                write_diff_image(mosaic_image, &img, diff_image, &hidden, &output_path)?;
+            }
+            if let Some(seams) = seams {
+                write_seam_image(mosaic_image, seams, &hidden, &output_path)?;
+            }
+            let score = score.then(|| QualityScore::hiding(mosaic_image, &img, grid, &hidden));
 
-            if let Some(stats_img) = img_and_stats.stats_img {
-                let stats_path = output_path.with_extension("stats.png");
-                eprintln!(
+            if let Some(stats_img) = img_and_stats.stats_img.filter(|_| write_stats_image) {
+                info!(
                     "📊 Writing statistics visualization to {}",
                     stats_path.display()
                 );
                 stats_img
                     .save_with_format(&stats_path, ImageFormat::Png)
                     .map_err(|e| {
-                        format!(
-                            "⚠️  Failed to save statistics image to {}: {}\n💡 This is non-critical - the main mosaic was saved successfully",
-                            stats_path.display(),
-                            e
-                        )
+                        let context = format!(
+                            "⚠️  Failed to save statistics image to {}",
+                            stats_path.display()
+                        );
+                        let hint =
+                            "💡 This is non-critical - the main mosaic was saved successfully";
+                        EmosaicError::decode(context, format!("{}\n{}", e, hint))
                     })?;
-                eprintln!("📊 Statistics file saved (shows tile matching quality)");
+                info!("📊 Statistics file saved (shows tile matching quality)");
             }
 
             // Generate HTML file if requested
+            let mut result_path = output_path.clone();
             if let Some(html_generator) = img_and_stats.html_generator {
                 let html_path = output_path.with_extension("html");
-                eprintln!("📄 Generating interactive HTML at {}", html_path.display());
+                info!("📄 Generating interactive HTML at {}", html_path.display());
 
-                html_generator(&output_path, &html_path)
-                    .map_err(|e| format!("⚠️  Failed to generate HTML file: {}", e))?;
+                html_generator(&output_path, &html_path, mosaic_image)
+                    .map_err(|e| EmosaicError::io("⚠️  Failed to generate HTML file", e))?;
 
-                eprintln!("📄 Interactive HTML file saved (hover over tiles for details)");
+                info!("📄 Interactive HTML file saved (hover over tiles for details)");
+                result_path = html_path;
             }
 
-            eprintln!(
+            if interrupted {
+                runtime_stats(score)?;
+                return Err(interrupted_error(&output_path));
+            }
+            info!(
                 "🎉 All done! Your mosaic is ready at {}",
                 output_path.display()
             );
-            print_runtime_stats(start_time, &memory_monitor);
+            if open {
+                open_result(&result_path);
+            }
+            runtime_stats(score)?;
+            return Ok(());
+        }
+    }
+
+    print_runtime_stats(start_time, &memory_monitor, None);
+    Ok(())
+}
+
+/// Fail when `args` render other mosaics with `--panel` along with options that apply to a
+/// single mosaic.
+fn check_panels(args: &Mosaic) -> Result<(), EmosaicError> {
+    if args.mode == Mode::Random {
+        return Err(EmosaicError::Validation(String::from(
+            "--panel needs the analysis of the tiles, it cannot be used in random mode",
+        )));
+    }
+    if args.story
+        || args.layout.is_some()
+        || args.pins.is_some()
+        || args.prefilter.is_some()
+        || args.edge_weight.is_some()
+        || args.optimize_swaps.is_some()
+        || args.optimize.is_some()
+        || args.mask.is_some()
+        || !args.clear_region.is_empty()
+        || args.rotate_grid.is_some()
+        || args.html
+        || args.web
+        || args.html_pyramid
+        || args.manifest.is_some()
+        || args.stats_out.is_some()
+        || args.stats_image.is_some()
+        || args.diff_image
+        || args.seams
+        || args.score
+        || args.dry_run
+        || args.preview
+        || args.terminal_preview.is_some()
+    {
+        return Err(EmosaicError::Validation(String::from(
+            "--panel cannot be combined with --story, --layout, --pins, --prefilter, \
+             --edge-weight, --optimize-swaps, --optimize, --mask, --clear-region, --rotate-grid, \
+             --html, --web, --html-pyramid, --manifest, --stats-out, --stats-image, \
+             --diff-image, --seams, --score, --dry-run, --preview or --terminal-preview, which \
+             apply to a single mosaic",
+        )));
+    }
+    Ok(())
+}
+
+/// Fail when `args` ask for a dry run along with options that need the tile images.
+fn check_dry_run(args: &Mosaic) -> Result<(), EmosaicError> {
+    if args.force
+        || args.preview
+        || args.edge_weight.is_some()
+        || args.web
+        || args.web_thumbnails.is_some()
+        || args.html_pyramid
+        || args.terminal_preview.is_some()
+    {
+        return Err(EmosaicError::Validation(String::from(
+            "--dry-run cannot be combined with --force, --preview, --edge-weight, --web, \
+             --web-thumbnails, --html-pyramid or --terminal-preview, which need the tile images",
+        )));
+    }
+    Ok(())
+}
+
+/// Match the tiles against `source` in `mode`, of `N` blocks per tile, once with the default
+/// options and once per configuration of `args`, as dry runs timed from the loading of the
+/// analysis cache. The tiles are analysed beforehand when the cache does not cover the mode.
+fn bench_mode<const N: usize>(
+    (args, library, source, tile_size, crop, mode): (
+        &Bench,
+        &LibraryOptions,
+        &image::RgbImage,
+        u32,
+        bool,
+        Mode,
+    ),
+) -> Result<Vec<BenchRow>, EmosaicError>
+where
+    [(); N * 3]:,
+{
+    args.library.load::<N>(tile_size, crop, library)?;
+    let tiles_dir = args.library.tiles_dir.to_str().ok_or_else(|| {
+        let display = args.library.tiles_dir.display();
+        EmosaicError::Validation(format!("❌ Invalid tiles directory {}", display))
+    })?;
+    let mode_name = mode.to_possible_value().expect("modes are not skipped");
+    let tiles_args = args.library.mosaic_args();
+    let configurations = std::iter::once("").chain(args.configurations.iter().map(String::as_str));
+    let mut rows = Vec::new();
+    for configuration in configurations {
+        let mut argv = vec!["mosaic", tiles_dir, "--dry-run", "-m", mode_name.get_name()];
+        argv.extend(tiles_args.iter().map(String::as_str));
+        argv.extend(configuration.split_whitespace());
+        let mosaic = BenchConfiguration::try_parse_from(argv)
+            .map_err(|e| {
+                let message = format!("❌ Invalid options \"{}\": {}", configuration, e);
+                EmosaicError::Validation(message)
+            })?
+            .mosaic;
+        if mosaic.mode != mode {
+            return Err(EmosaicError::Validation(format!(
+                "❌ Invalid options \"{}\": give the modes with --modes",
+                configuration
+            )));
         }
+        check_dry_run(&mosaic)?;
+        let start = Instant::now();
+        let hidden = HashSet::new();
+        let outcome = n_to_1::<N>(
+            mosaic,
+            library,
+            source,
+            &[],
+            tile_size,
+            crop,
+            mode,
+            0.0,
+            None,
+            &hidden,
+        );
+        let outcome = outcome.map(|result| (result.summary, start.elapsed()));
+        if let Err(error) = &outcome {
+            warn!("{}", error);
+        }
+        rows.push(BenchRow {
+            dim: mode.dim().expect("the random mode is not benchmarked"),
+            configuration: configuration.to_string(),
+            outcome,
+        });
+    }
+    Ok(rows)
+}
+
+/// Ask for the settings of a mosaic, save them to the config file and render a preview with
+/// them, by running emosaic again.
+fn run_init(args: &Init, img: Option<&Path>, library: &LibraryOptions) -> Result<(), EmosaicError> {
+    let config = wizard::interview(&mut io::stdin().lock(), &mut io::stderr(), img, library)
+        .map_err(|e| EmosaicError::io("Failed to read the answers", e))?;
+    let toml = config
+        .to_toml()
+        .map_err(|e| EmosaicError::Validation(format!("Failed to write the settings: {}", e)))?;
+    fs::write(&args.config, toml).map_err(|e| {
+        EmosaicError::io(
+            format!("Failed to write the settings to {}", args.config.display()),
+            e,
+        )
+    })?;
+    info!("📝 Saved the settings to {}", args.config.display());
+
+    if !args.no_preview {
+        info!(
+            "👀 Rendering a preview to {}",
+            config.preview_path().display()
+        );
+        let emosaic = std::env::current_exe()
+            .map_err(|e| EmosaicError::io("Failed to find the emosaic executable", e))?;
+        let status = std::process::Command::new(emosaic)
+            .args(config.preview_args())
+            .status()
+            .map_err(|e| EmosaicError::io("Failed to render the preview", e))?;
+        if !status.success() {
+            return Err(EmosaicError::Render(format!(
+                "❌ The preview failed, the settings are saved in {}",
+                args.config.display()
+            )));
+        }
+    }
+    info!("✨ Make the full mosaic with:\n   {}", config.command());
+    Ok(())
+}
+
+/// Serve the page choosing the settings of the mosaic of `img`, starting from `tile_size` and
+/// `crop`, until interrupted. The pictures are counted as `library` says. Previews and the full
+/// mosaic are rendered by running emosaic again, with `library_args`, see [`Cli::library_args`].
+fn run_serve(
+    args: &Serve,
+    library: &LibraryOptions,
+    library_args: Vec<OsString>,
+    img: &Path,
+    output_path: &Path,
+    tile_size: u32,
+    crop: bool,
+) -> Result<(), EmosaicError> {
+    let tiles = &args.library;
+    if !tiles.tiles_dir.is_dir() {
+        return Err(EmosaicError::Validation(format!(
+            "❌ Tiles directory does not exist: {}",
+            tiles.tiles_dir.display()
+        )));
     }
+    let extensions: HashSet<&OsStr> = tiles.extensions.iter().map(OsStr::new).collect();
+    let known = |extension: &OsStr| extensions.contains(extension);
+    let pictures = find_images(&tiles.tiles_dir, known, library)
+        .map_err(|e| EmosaicError::io(format!("Failed to read {}", tiles.tiles_dir.display()), e))?
+        .len();
+    let dimensions = image::image_dimensions(img).map_err(|e| {
+        EmosaicError::decode(format!("Failed to read the size of {}", img.display()), e)
+    })?;
+    // As many tiles across as the setup wizard would suggest
+    let (downsample, _) = wizard::plan(
+        dimensions,
+        tile_size * wizard::MAX_TILES_ACROSS,
+        pictures,
+        true,
+    );
+    let defaults = RenderParams {
+        mode: 1,
+        tile_size,
+        downsample,
+        no_repeat: false,
+        tint_opacity: 0.0,
+        crop,
+    };
+
+    let emosaic = std::env::current_exe()
+        .map_err(|e| EmosaicError::io("Failed to find the emosaic executable", e))?;
+    let render = move |render_args: &[OsString]| {
+        let output = std::process::Command::new(&emosaic)
+            .args(render_args)
+            .output()
+            .map_err(|e| format!("Failed to run emosaic: {}", e))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            // The error is the last line emosaic printed
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let error = stderr.lines().rev().find(|line| !line.trim().is_empty());
+            Err(error.unwrap_or("The render failed").to_string())
+        }
+    };
+    let preview = std::env::temp_dir().join(format!("emosaic-serve-{}.png", std::process::id()));
+    let server = Server::new(
+        args.port,
+        library_args,
+        img.to_path_buf(),
+        tiles.tiles_dir.clone(),
+        tiles.mosaic_args(),
+        pictures,
+        output_path.to_path_buf(),
+        preview,
+        defaults,
+        render,
+    );
 
-    print_runtime_stats(start_time, &memory_monitor);
+    let listener = std::net::TcpListener::bind(("127.0.0.1", args.port))
+        .map_err(|e| EmosaicError::io(format!("Failed to listen on port {}", args.port), e))?;
+    let url = format!("http://127.0.0.1:{}/", args.port);
+    info!(
+        "🌐 Serving the settings of the mosaic of {} from {} pictures on {}, press Ctrl+C to stop",
+        img.display(),
+        pictures,
+        url
+    );
+    if args.open {
+        if let Err(e) = opener::open(&url) {
+            warn!("⚠️  Failed to open {}: {}", url, e);
+        }
+    }
+    Arc::new(server).run(listener);
     Ok(())
 }
 
+/// The error ending a run interrupted with Ctrl-C, once its partial mosaic is saved to
+/// `output_path`.
+fn interrupted_error(output_path: &Path) -> EmosaicError {
+    EmosaicError::Interrupted(format!(
+        "⏹️  Interrupted, the mosaic rendered so far was saved to {}",
+        output_path.display()
+    ))
+}
+
+/// Write the outputs of a dry run next to `output_path`: the mosaic drawn in the analysed
+/// colors of its tiles, the statistics image to `stats_path` and the HTML page over the former
+/// if requested. Returns the path of the file to open.
+fn write_dry_run(
+    img_and_stats: ImgAndStats,
+    output_path: &Path,
+    stats_path: &Path,
+) -> Result<PathBuf, EmosaicError> {
+    let ImgAndStats {
+        img,
+        stats_img,
+        html_generator,
+        ..
+    } = img_and_stats;
+    let preview_path = output_path.with_extension("dry-run.png");
+    img.save_with_format(&preview_path, ImageFormat::Png)
+        .map_err(|e| {
+            let context = format!(
+                "❌ Failed to save the dry run to {}",
+                preview_path.display()
+            );
+            EmosaicError::decode(context, e)
+        })?;
+    info!(
+        "🧪 Mosaic in the analysed tile colors saved to {}",
+        preview_path.display()
+    );
+    if let Some(stats_img) = stats_img {
+        stats_img
+            .save_with_format(stats_path, ImageFormat::Png)
+            .map_err(|e| {
+                let context = format!(
+                    "❌ Failed to save statistics image to {}",
+                    stats_path.display()
+                );
+                EmosaicError::decode(context, e)
+            })?;
+        info!(
+            "📊 Statistics visualization saved to {}",
+            stats_path.display()
+        );
+    }
+    let mut result_path = preview_path.clone();
+    if let Some(html_generator) = html_generator {
+        let html_path = output_path.with_extension("html");
+        html_generator(&preview_path, &html_path, &img)
+            .map_err(|e| EmosaicError::io("⚠️  Failed to generate HTML file", e))?;
+        info!("📄 Interactive HTML file saved to {}", html_path.display());
+        result_path = html_path;
+    }
+    Ok(result_path)
+}
+
+/// Compare the `grid` of tiles of the finished `mosaic` but the `hidden` ones with `source` over
+/// `dim`x`dim` sub-blocks per tile, print the result and save it next to `output_path` as
+/// `.diff.png`.
+fn write_diff_image(
+    mosaic: &image::RgbImage,
+    source: &image::RgbImage,
+    (grid, dim, palette): ((u32, u32), u32, Palette),
+    hidden: &HashSet<(u32, u32)>,
+    output_path: &Path,
+) -> Result<(), EmosaicError> {
+    let mut delta_map = DeltaMap::new(mosaic, source, grid, dim);
+    delta_map.hide_cells(hidden);
+    delta_map.summarise();
+    let diff_path = output_path.with_extension("diff.png");
+    delta_map
+        .render(palette)
+        .save_with_format(&diff_path, ImageFormat::Png)
+        .map_err(|e| {
+            let context = format!(
+                "❌ Failed to save the difference image to {}",
+                diff_path.display()
+            );
+            EmosaicError::decode(context, e)
+        })?;
+    info!("🔍 Difference image saved to {}", diff_path.display());
+    Ok(())
+}
+
+/// Measure the junctions between the `grid` of tiles of the finished `mosaic` but the `hidden`
+/// ones, print the result and save it next to `output_path` as `.seams.png`.
+fn write_seam_image(
+    mosaic: &image::RgbImage,
+    (grid, palette): ((u32, u32), Palette),
+    hidden: &HashSet<(u32, u32)>,
+    output_path: &Path,
+) -> Result<(), EmosaicError> {
+    let mut seam_map = SeamMap::new(mosaic, grid);
+    seam_map.hide_cells(hidden);
+    seam_map.summarise();
+    let seams_path = output_path.with_extension("seams.png");
+    seam_map
+        .render(SEAM_CELL_SIZE, palette)
+        .save_with_format(&seams_path, ImageFormat::Png)
+        .map_err(|e| {
+            let context = format!(
+                "❌ Failed to save the seam image to {}",
+                seams_path.display()
+            );
+            EmosaicError::decode(context, e)
+        })?;
+    info!("🧱 Seam image saved to {}", seams_path.display());
+    Ok(())
+}
+
+/// Overlay the source image, scaled up to the size of the mosaic, with the given opacity.
+fn tint(output: image::RgbImage, source: &image::RgbImage, opacity: f64) -> DynamicImage {
+    // Create overlay more efficiently using from_fn
+    let alpha_value = (255.0 * opacity) as u8;
+    let overlay = RgbaImage::from_fn(source.width(), source.height(), |x, y| {
+        let p = source.get_pixel(x, y);
+        Rgba([p[0], p[1], p[2], alpha_value])
+    });
+
+    // Scale up to match the output size
+    let overlay = imageops::resize(
+        &overlay,
+        output.width(),
+        output.height(),
+        FilterType::Nearest,
+    );
+
+    // Apply overlay
+    let mut output = DynamicImage::ImageRgb8(output).to_rgba8();
+    imageops::overlay(&mut output, &overlay, 0, 0);
+    DynamicImage::ImageRgba8(output)
+}
+
+/// Deferred HTML generation, called with the mosaic and HTML output paths and the mosaic itself
+/// once the image is saved
+type HtmlGenerator = Box<
+    dyn FnOnce(&std::path::Path, &std::path::Path, &image::RgbImage) -> Result<(), std::io::Error>
+        + Send,
+>;
+
 struct ImgAndStats {
     img: image::ImageBuffer<image::Rgb<u8>, Vec<u8>>,
+    summary: StatsSummary,
+    /// Number of tiles the mosaic was made from
+    tiles: usize,
+    /// Share of the tile images found in the cache of decoded images while rendering
+    cache_hit_rate: Option<f64>,
     stats_img: Option<image::ImageBuffer<image::Rgb<u8>, Vec<u8>>>,
     // Store HTML generation data as a closure that can be called later
-    html_generator: Option<
-        Box<dyn FnOnce(&std::path::Path, &std::path::Path) -> Result<(), std::io::Error> + Send>,
-    >,
+    html_generator: Option<HtmlGenerator>,
+    /// Mosaics of the sources of --panel, in order
+    panels: Vec<image::RgbImage>,
+}
+
+/// Row callback feeding the terminal preview, if any.
+fn preview_callback(
+    preview: Option<&TerminalPreview>,
+) -> Option<impl Fn(&image::RgbImage, u32) + Sync + '_> {
+    preview.map(|preview| move |row: &image::RgbImage, y| preview.row_rendered(row, y))
+}
+
+/// The `source` image resized by the `downsample` factor, to multiples of `dim` pixels.
+fn resize_source(source: &image::RgbImage, downsample: u16, dim: u32) -> image::RgbImage {
+    let nwidth = round_to_multiple(source.width() / downsample as u32, dim);
+    let nheight = round_to_multiple(source.height() / downsample as u32, dim);
+
+    info!(
+        "Resizing source image from {}x{} to {}x{}",
+        source.width(),
+        source.height(),
+        nwidth,
+        nheight
+    );
+
+    imageops::resize(source, nwidth, nheight, FilterType::Lanczos3)
+}
+
+/// Round `value` to the nearest multiple of `dim`, rounding halves down.
+fn round_to_multiple(value: u32, dim: u32) -> u32 {
+    let remainder = value % dim;
+    if remainder > dim.div_euclid(2) {
+        value + dim - remainder
+    } else {
+        value - remainder
+    }
+}
+
+/// Dimensions of the mosaic that will be rendered from `img`, mirroring the resizing
+/// done by `n_to_1` and the tile sizes used by preview renders.
+fn output_dimensions(img: &image::RgbImage, tile_size: u32, args: &Mosaic) -> (u32, u32) {
+    let tile_size = output_tile_size(tile_size, args);
+    match args.mode.dim() {
+        Some(dim) => {
+            let width = round_to_multiple(img.width() / args.downsample as u32, dim);
+            let height = round_to_multiple(img.height() / args.downsample as u32, dim);
+            (width / dim * tile_size, height / dim * tile_size)
+        }
+        None => (img.width() * tile_size, img.height() * tile_size),
+    }
+}
+
+/// Size in pixels of the tiles of the output image, smaller for a preview.
+fn output_tile_size(tile_size: u32, args: &Mosaic) -> u32 {
+    match (args.preview, args.mode.dim()) {
+        (true, Some(dim)) => PREVIEW_TILE_SIZE.div_ceil(dim) * dim,
+        (true, None) => PREVIEW_TILE_SIZE,
+        (false, _) => tile_size,
+    }
 }
 
+/// Fail before rendering when the output image could not be encoded as described by
+/// `options` or would not fit in the space available at `path`.
+fn check_output_space(
+    path: &Path,
+    options: &OutputOptions,
+    (width, height): (u32, u32),
+    channels: u8,
+) -> Result<(), String> {
+    let format = options.format;
+    if width.max(height) > format.max_dimension() {
+        return Err(format!(
+            "❌ The output image would be {}x{}, larger than the {}px maximum of {:?}\n💡 Use a smaller tile size, --downsample, or another --format",
+            width,
+            height,
+            format.max_dimension(),
+            format
+        ));
+    }
+    let estimate = options.estimated_size(width, height, channels);
+    let dir = output_dir(path);
+    let available = fs2::available_space(&dir).map_err(|e| {
+        format!(
+            "Failed to check the space available in {}: {}",
+            dir.display(),
+            e
+        )
+    })?;
+    let megabytes = |bytes: u64| bytes.div_ceil(MEGABYTE as u64);
+    if estimate > available {
+        return Err(format!(
+            "❌ The {}x{} output image may need up to {} MB but only {} MB are available in {}\n💡 Free some space, use a smaller tile size, --downsample, or a lossy --format",
+            width,
+            height,
+            megabytes(estimate),
+            megabytes(available),
+            dir.display()
+        ));
+    }
+    info!(
+        "📐 Output image will be {}x{} (up to ~{} MB, {} MB available)",
+        width,
+        height,
+        megabytes(estimate),
+        megabytes(available)
+    );
+    Ok(())
+}
+
+/// Fail when `tile_set` has no tile left to place, e.g. because every image was skipped,
+/// excluded or smaller than --min-tile-resolution.
+fn check_tiles_left<T>(tile_set: &TileSet<T>) -> Result<(), EmosaicError> {
+    if tile_set.is_empty() {
+        return Err(EmosaicError::Validation(String::from(
+            "❌ No tiles left to place: every image was skipped, excluded or smaller than \
+             --min-tile-resolution",
+        )));
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
 fn n_to_1<const N: usize>(
     mosaic_args: Mosaic,
+    library: &LibraryOptions,
     original_img: &image::ImageBuffer<image::Rgb<u8>, Vec<u8>>,
+    panels: &[image::RgbImage],
     tile_size: u32,
     crop: bool,
     mode: Mode,
     tint_opacity: f32,
-) -> Result<ImgAndStats, ImageError>
+    terminal_preview: Option<&TerminalPreview>,
+    hidden: &HashSet<(u32, u32)>,
+) -> Result<ImgAndStats, EmosaicError>
 where
     [(); N * 3]:,
 {
+    let sources = mosaic_args.sources();
+    let base_dir = mosaic_args.base_dir();
+    let tile_list = mosaic_args
+        .tiles_list
+        .as_deref()
+        .map(read_tile_list)
+        .transpose()?;
     let Mosaic {
         extensions,
         force,
+        tile_timeout,
         no_repeat,
+        repeat_penalty,
+        optimize_swaps,
+        optimize: optimization,
+        layout,
         downsample,
         randomize,
-        tiles_dir,
+        randomize_max_variance,
+        edge_weight,
+        continuity_weight,
         greedy,
+        story,
+        time_gradient,
+        order_by,
+        date_weight,
+        timezone,
+        date_format,
+        palette,
+        absolute_distances,
+        pins,
+        global_no_repeat,
         html,
         web,
+        web_thumbnails,
+        export_quality,
+        export_strip_exif,
+        html_pyramid,
+        seams,
+        map,
+        tile_links,
+        tiles_analysis,
+        export_analysis,
+        manifest,
+        stats_out,
+        stats_scale,
         title,
+        description,
+        author,
+        preview,
+        dry_run,
+        index,
+        prefilter,
+        filler,
+        max_distance,
+        on_bad_match,
+        tile_cache_mb,
         ..
     } = mosaic_args;
 
+    let web = web || web_thumbnails.is_some();
+    let no_repeat = no_repeat || global_no_repeat;
     let dim = (N as f64).sqrt() as u32;
 
-    // resize the original img by the downsampling factor
-    let mut nwidth = original_img.width() / downsample as u32;
-    let mut nheight = original_img.height() / downsample as u32;
-
-    // adjust the sizes to be multiples of the dim
-    let nwidth_mod = nwidth % dim;
-    if nwidth_mod > dim.div_euclid(2) {
-        nwidth += dim - nwidth_mod
-    } else {
-        nwidth -= nwidth_mod
-    }
-    let nheight_mod = nheight % dim;
-    if nheight_mod > dim.div_euclid(2) {
-        nheight += dim - nheight_mod
+    let tile_size = if preview {
+        let preview_tile_size = PREVIEW_TILE_SIZE.div_ceil(dim) * dim;
+        info!(
+            "🔍 Preview mode: rendering a draft with {}px tiles and no cache writes",
+            preview_tile_size
+        );
+        preview_tile_size
+    } else if dry_run {
+        info!("🧪 Dry run: matching the tiles against their cached analysis only");
+        dim
     } else {
-        nheight -= nheight_mod
-    }
-
-    eprintln!(
-        "Resizing source image from {}x{} to {}x{}",
-        original_img.width(),
-        original_img.height(),
-        nwidth,
-        nheight
-    );
+        tile_size
+    };
 
-    let img = imageops::resize(original_img, nwidth, nheight, FilterType::Lanczos3);
+    let img = resize_source(original_img, downsample, dim);
+    let panel_imgs: Vec<_> = (panels.iter())
+        .map(|panel| resize_source(panel, downsample, dim))
+        .collect();
 
-    let analysis_cache_path = tiles_dir.join(format!(
-        ".emosaic_{}to1{}",
-        N,
-        if crop { "_cropped" } else { "" }
-    ));
     // Validate the source image dimensions
-    if img.width() % dim != 0 || img.height() % dim != 0 {
-        eprintln!(
+    if !img.width().is_multiple_of(dim) || !img.height().is_multiple_of(dim) {
+        return Err(EmosaicError::Validation(format!(
             "Invalid source dimensions ({}x{}): Dimensions must be divisible by {}",
             img.width(),
             img.height(),
             dim
-        );
-        std::process::exit(1);
+        )));
+    }
+    if !tile_size.is_multiple_of(dim) {
+        return Err(EmosaicError::Validation(format!(
+            "Invalid tile size: Tile size must be divisible by {}",
+            dim
+        )));
+    }
+    if story && (no_repeat || randomize.is_some()) {
+        return Err(EmosaicError::Validation(String::from(
+            "--story cannot be combined with --no-repeat or --randomize",
+        )));
+    }
+    let time_gradient = match (time_gradient, order_by) {
+        (None, OrderBy::Date) => Some(TimeGradient::ReadingOrder),
+        (time_gradient, _) => time_gradient,
+    };
+    if time_gradient.is_some() && (story || randomize.is_some()) {
+        return Err(EmosaicError::Validation(String::from(
+            "--time-gradient and --order-by date cannot be combined with --story or --randomize",
+        )));
+    }
+    let date_weight = date_weight.unwrap_or(RenderConfig::default().time_gradient_penalty);
+    let pins = match pins {
+        Some(path) => Pins::load(&path, &base_dir)?,
+        None => Pins::default(),
+    };
+    let tile_links = load_tile_links(tile_links, html || web || html_pyramid, &base_dir)?;
+    if story && !pins.pins.is_empty() {
+        return Err(EmosaicError::Validation(String::from(
+            "--story cannot be combined with pinned tiles",
+        )));
+    }
+    let list_weights = tile_list.iter().flat_map(|list| &list.tiles);
+    let mut source_weights =
+        (sources.iter().map(|source| source.weight)).chain(list_weights.map(|tile| tile.weight));
+    let first_weight = source_weights.next();
+    let weighted = source_weights.any(|weight| Some(weight) != first_weight);
+    if story && weighted {
+        return Err(EmosaicError::Validation(String::from(
+            "--story cannot be combined with weighted tiles directories",
+        )));
+    }
+    if story && edge_weight.is_some() {
+        return Err(EmosaicError::Validation(String::from(
+            "--story cannot be combined with --edge-weight",
+        )));
+    }
+    if story && repeat_penalty.is_some() {
+        return Err(EmosaicError::Validation(String::from(
+            "--story cannot be combined with --repeat-penalty",
+        )));
+    }
+    if story && continuity_weight.is_some() {
+        return Err(EmosaicError::Validation(String::from(
+            "--story cannot be combined with --continuity-weight",
+        )));
     }
-    if tile_size % dim != 0 {
-        eprintln!("Invalid tile size: Tile size must be divisible by {}", dim);
-        std::process::exit(1);
+    if dim == 1 && continuity_weight.is_some() {
+        return Err(EmosaicError::Validation(String::from(
+            "--continuity-weight compares the colors along the sides of the tiles, which -m 1 \
+             does not analyse: use -m 2 or finer",
+        )));
     }
+    if filler.is_some() && max_distance.is_some() {
+        return Err(EmosaicError::Validation(String::from(
+            "--filler cannot be combined with --max-distance, use --on-bad-match filler",
+        )));
+    }
+    let filler = filler.or(max_distance.filter(|_| on_bad_match == OnBadMatch::Filler));
+    let correct = max_distance.filter(|_| on_bad_match == OnBadMatch::Correct);
+    if story && (filler.is_some() || correct.is_some()) {
+        return Err(EmosaicError::Validation(String::from(
+            "--story cannot be combined with --filler or --on-bad-match filler|correct",
+        )));
+    }
+    if optimize_swaps.is_some() && (story || correct.is_some()) {
+        return Err(EmosaicError::Validation(String::from(
+            "--optimize-swaps cannot be combined with --story or --on-bad-match correct",
+        )));
+    }
+    if optimization.is_some() && (story || correct.is_some()) {
+        return Err(EmosaicError::Validation(String::from(
+            "--optimize cannot be combined with --story or --on-bad-match correct",
+        )));
+    }
+    if layout.is_some()
+        && (story
+            || no_repeat
+            || randomize.is_some()
+            || time_gradient.is_some()
+            || !pins.pins.is_empty()
+            || weighted
+            || edge_weight.is_some()
+            || repeat_penalty.is_some()
+            || continuity_weight.is_some()
+            || filler.is_some()
+            || correct.is_some()
+            || optimize_swaps.is_some()
+            || optimization.is_some()
+            || manifest.is_some()
+            || html
+            || web
+            || html_pyramid)
+    {
+        return Err(EmosaicError::Validation(String::from(
+            "--layout only matches every rectangle to its nearest tile, and cannot be combined \
+             with --story, --no-repeat, --randomize, --time-gradient, --order-by date, --pins, \
+             weighted tiles directories, --edge-weight, --repeat-penalty, --continuity-weight, \
+             --filler, --on-bad-match filler|correct, --optimize-swaps, --optimize, --manifest or \
+             the HTML outputs",
+        )));
+    }
+    if global_no_repeat
+        && (greedy
+            || time_gradient.is_some()
+            || weighted
+            || repeat_penalty.is_some()
+            || continuity_weight.is_some()
+            || filler.is_some()
+            || correct.is_some())
+    {
+        return Err(EmosaicError::Validation(String::from(
+            "--global-no-repeat places the closest tiles of all the mosaics first, and cannot be \
+             combined with --greedy, --time-gradient, --order-by date, weighted tiles, \
+             --repeat-penalty, --continuity-weight, --filler or --on-bad-match filler|correct",
+        )));
+    }
+    // In preview mode only a sample of the tile set is used, but no-repeat still needs enough tiles
+    let cells = ((img.width() / dim) * (img.height() / dim)) as usize;
+    let max_tiles = preview.then(|| {
+        if no_repeat {
+            PREVIEW_MAX_TILES.max(cells.div_ceil(2))
+        } else {
+            PREVIEW_MAX_TILES
+        }
+    });
     let extensions: HashSet<_> = extensions.iter().map(|x| x.to_owned()).collect();
+    let mut tile_set: TileSet<[Rgb<u8>; N]> = match &tiles_analysis {
+        Some(path) => read_tile_analysis(path, max_tiles)?,
+        None => {
+            let mut tile_set = TileSet::new();
+            for source in &sources {
+                tile_set.append(load_tile_set::<N>(
+                    &source.path,
+                    &extensions,
+                    tile_size,
+                    crop,
+                    force,
+                    preview,
+                    dry_run,
+                    max_tiles,
+                    tile_timeout,
+                    library,
+                )?);
+            }
+            if let Some(list) = &tile_list {
+                tile_set.append(load_tile_list::<N>(
+                    list,
+                    tile_size,
+                    crop,
+                    force,
+                    preview,
+                    dry_run,
+                    max_tiles,
+                    tile_timeout,
+                    library,
+                )?);
+            }
+            match max_tiles {
+                Some(max_tiles) => tile_set.sample(max_tiles),
+                None => tile_set,
+            }
+        }
+    };
+    if tile_set.len() > MAX_TILES {
+        return Err(EmosaicError::Validation(format!(
+            "❌ {} tiles exceed the limit of {} tiles in a mosaic",
+            tile_set.len(),
+            MAX_TILES
+        )));
+    }
+    check_tiles_left(&tile_set)?;
+    if let Some(zone) = timezone {
+        normalize_dates(&mut tile_set, zone);
+    }
+    if let Some(path) = &export_analysis {
+        TileAnalysis::from_tile_set(&tile_set)
+            .save(path)
+            .map_err(|e| {
+                EmosaicError::cache(
+                    format!("❌ Failed to export tile analysis to {}", path.display()),
+                    e,
+                )
+            })?;
+        info!("🧾 Tile analysis exported to {}", path.display());
+    }
+    if !pins.exclude.is_empty() {
+        let excluded;
+        (tile_set, excluded) = tile_set.retain_paths(|path| !pins.is_excluded(path));
+        info!("Excluded {} tiles", excluded);
+        check_tiles_left(&tile_set)?;
+    }
+    if let Some(max_distance) = prefilter {
+        // Without repetition each tile is placed at most twice, once per orientation
+        let min_tiles = if no_repeat { cells.div_ceil(2) } else { 1 };
+        let blocks = block_colors(&img, dim);
+        let pruned;
+        (tile_set, pruned) = tile_set.prune_far_from(&blocks, max_distance, min_tiles, |path| {
+            pins.is_pinned(path)
+        });
+        info!(
+            "Pruned {} tiles farther than {} from every source block",
+            pruned, max_distance
+        );
+    }
+    tile_set.set_write_cache(!preview);
+    tile_set.set_memory_budget(library.memory_budget);
+    tile_set.set_image_cache_budget(tile_cache_mb * MEGABYTE);
+    if dry_run {
+        tile_set.use_analysis_images();
+    }
+    info!("Tile set with {} tiles", tile_set.len());
+    let pinned = pins.resolve(&tile_set, (img.width() / dim, img.height() / dim))?;
+    let weights = weighted.then(|| {
+        let mut weights = tile_weights(&tile_set, &sources);
+        if let Some(list) = &tile_list {
+            let list_weights = list.weights();
+            for (weight, path) in weights.iter_mut().zip(tile_set.paths()) {
+                if let Some(list_weight) = list_weights.get(path.as_path()) {
+                    *weight = *list_weight;
+                }
+            }
+        }
+        weights
+    });
+    let edges = edge_weight.map(|weight| {
+        info!("Measuring the edges of the source image and the tiles");
+        let grid = (img.width() / dim, img.height() / dim);
+        EdgeFeatures::new(original_img, grid, &tile_set, tile_size, weight)
+    });
+    let on_row = preview_callback(terminal_preview);
+    let on_row = on_row.as_ref().map(|f| f as RowCallback);
+    let render = |img: &image::RgbImage, tile_set| {
+        if no_repeat
+            && !greedy
+            && time_gradient.is_none()
+            && pinned.is_empty()
+            && weights.is_none()
+            && edges.is_none()
+            && repeat_penalty.is_none()
+            && continuity_weight.is_none()
+            && filler.is_none()
+            && correct.is_none()
+        {
+            render_nto1_no_repeat(img, tile_set, tile_size, index)
+        } else {
+            let options = MatchOptions {
+                no_repeat,
+                randomize: randomize.map(|factor| (factor, randomize_max_variance)),
+                index,
+                time_gradient: time_gradient.map(|gradient| (gradient, date_weight)),
+                pins: pinned.clone(),
+                weights: weights.as_deref(),
+                edges: edges.as_ref(),
+                repeat_penalty,
+                continuity: continuity_weight,
+                filler,
+                correct,
+                on_row,
+            };
+            render_nto1(img, tile_set, tile_size, options)
+        }
+    };
+    let mut panel_images = Vec::new();
+    let mut result = if let Some(Layout::Irregular { max_cells }) = layout {
+        render_nto1_irregular(&img, tile_set, tile_size, index, max_cells)
+    } else if story {
+        let options = StoryOptions::default();
+        render_nto1_story(&img, tile_set, tile_size, index, &options, on_row)
+    } else if global_no_repeat {
+        let sources: Vec<&image::RgbImage> = std::iter::once(&img).chain(&panel_imgs).collect();
+        let rendered = render_nto1_panels(&sources, tile_set, tile_size, index)?;
+        let mut panels = rendered.panels.into_iter();
+        let (image, stats) = panels.next().expect("every source is rendered");
+        panel_images = panels.map(|(image, _)| image).collect();
+        RenderResult {
+            image,
+            stats,
+            tile_set: rendered.tile_set,
+        }
+    } else {
+        // Every rendering gives the tile set back for the next one
+        let mut tile_set = tile_set;
+        for panel in &panel_imgs {
+            let rendered = render(panel, tile_set)?;
+            panel_images.push(rendered.image);
+            tile_set = rendered.tile_set;
+        }
+        render(&img, tile_set)?
+    };
+
+    if let Some(passes) = optimize_swaps {
+        let fixed = pinned.keys().copied().collect();
+        optimize::optimize_swaps(&mut result, &img, tile_size, index, passes, &fixed)?;
+    }
+    if let Some(Optimize::Anneal { seconds }) = optimization {
+        let fixed = pinned.keys().copied().collect();
+        let budget = Duration::from_secs_f64(seconds);
+        optimize::anneal(
+            &mut result,
+            &img,
+            tile_size,
+            index,
+            budget,
+            no_repeat,
+            &fixed,
+        )?;
+    }
+
+    if let (Some(max_distance), OnBadMatch::Warn) = (max_distance, on_bad_match) {
+        result
+            .stats
+            .set_max_distance(SIZE::from_num(max_distance as usize * N));
+    }
+    if absolute_distances {
+        result
+            .stats
+            .set_distance_scale(DistanceScale::Absolute { pixels: N as u32 });
+    }
+    // Configuration recorded in the HTML output and the manifest
+    let mode_str = match mode {
+        Mode::_1 => "1x1 (N=1)",
+        Mode::_2 => "2x2 (N=4)",
+        Mode::_3 => "3x3 (N=9)",
+        Mode::_4 => "4x4 (N=16)",
+        Mode::_5 => "5x5 (N=25)",
+        Mode::_6 => "6x6 (N=36)",
+        Mode::_8 => "8x8 (N=64)",
+        Mode::_16 => "16x16 (N=256)",
+        Mode::_32 => "32x32 (N=1024)",
+        Mode::_64 => "64x64 (N=4096)",
+        Mode::_128 => "128x128 (N=16384)",
+        Mode::Random => "Random",
+    };
+
+    let config = MosaicConfig {
+        tile_size,
+        mode: mode_str.to_string(),
+        no_repeat,
+        greedy,
+        story,
+        time_gradient,
+        filler,
+        crop,
+        tint_opacity,
+        downsample: downsample.into(),
+        randomize,
+        randomize_max_variance,
+        tiles_dir: base_dir.display().to_string(),
+        title: title.clone(),
+        description: description.clone(),
+        author: author.clone(),
+        thumbnail_size: web_thumbnails,
+        export_quality,
+        export_strip_exif,
+        date_format,
+        palette,
+        tile_links,
+    };
+    // The tiles of an irregular layout span several cells, none of them is left out
+    let no_cells = HashSet::new();
+    let hidden = match layout {
+        Some(Layout::Irregular { .. }) => &no_cells,
+        None => hidden,
+    };
+
+    let mut outputs = mosaic_outputs(
+        result.image,
+        result.stats,
+        result.tile_set,
+        config,
+        &sources,
+        manifest.as_deref(),
+        stats_out.as_deref(),
+        stats_scale,
+        html,
+        web,
+        html_pyramid,
+        seams,
+        map,
+        hidden,
+    )?;
+    outputs.panels = panel_images;
+    Ok(outputs)
+}
+
+/// Render a mosaic of tiles drawn at random, a tile per pixel of `source`.
+#[allow(clippy::too_many_arguments)]
+fn random(
+    args: Mosaic,
+    library: &LibraryOptions,
+    source: &image::RgbImage,
+    tile_size: u32,
+    crop: bool,
+    tint_opacity: f32,
+    terminal_preview: Option<&TerminalPreview>,
+    hidden: &HashSet<(u32, u32)>,
+) -> Result<ImgAndStats, EmosaicError> {
+    let sources = args.sources();
+    let mut images = Vec::new();
+    for source in &sources {
+        let found = find_images(
+            &source.path,
+            |ext| args.extensions.contains(&ext.to_string_lossy().to_string()),
+            library,
+        );
+        images.extend(found.map_err(|e| {
+            let context = format!("Failed to find images in {}", source.path.display());
+            EmosaicError::io(context, e)
+        })?);
+    }
+    let base_dir = args.base_dir();
+    let web = args.web || args.web_thumbnails.is_some();
+    let tile_links = load_tile_links(
+        args.tile_links,
+        args.html || web || args.html_pyramid,
+        &base_dir,
+    )?;
+    let mut tile_set = TileSet::<()>::new();
+    let extensions: HashSet<String> = args.extensions.iter().map(|x| x.to_owned()).collect();
+    images.retain(|path| {
+        let extension = path.extension().and_then(OsStr::to_str);
+        extension.is_some_and(|extension| extensions.contains(extension)) && path.exists()
+    });
+    if let Some(path) = &args.tiles_list {
+        images.extend(
+            read_tile_list(path)?
+                .tiles
+                .into_iter()
+                .map(|tile| tile.path),
+        );
+    }
+    for path_buf in augment(images, tile_size, library.augment_crops) {
+        tile_set.push_tile(path_buf, ());
+    }
+    check_tiles_left(&tile_set)?;
+    let tile_size = if args.preview {
+        info!(
+            "🔍 Preview mode: rendering a draft with {}px tiles",
+            PREVIEW_TILE_SIZE
+        );
+        // Without repetition every pixel of the source needs a tile of its own
+        let cells = (source.width() * source.height()) as usize;
+        let max_tiles = if args.no_repeat {
+            PREVIEW_MAX_TILES.max(cells)
+        } else {
+            PREVIEW_MAX_TILES
+        };
+        tile_set = tile_set.sample(max_tiles);
+        tile_set.set_write_cache(false);
+        PREVIEW_TILE_SIZE
+    } else {
+        tile_size
+    };
+    tile_set.set_memory_budget(library.memory_budget);
+    tile_set.set_image_cache_budget(args.tile_cache_mb * MEGABYTE);
+    info!("Tile set with {} tiles", tile_set.len());
+
+    let on_row = preview_callback(terminal_preview);
+    let (image, stats) = render_random(
+        source,
+        &tile_set,
+        tile_size,
+        args.no_repeat,
+        on_row.as_ref().map(|f| f as RowCallback),
+    )?;
+
+    let config = MosaicConfig {
+        tile_size,
+        mode: "Random".to_string(),
+        no_repeat: args.no_repeat,
+        greedy: false,
+        story: false,
+        time_gradient: None,
+        filler: None,
+        crop,
+        tint_opacity,
+        downsample: 1,
+        randomize: None,
+        randomize_max_variance: None,
+        tiles_dir: base_dir.display().to_string(),
+        title: args.title,
+        description: args.description,
+        author: args.author,
+        thumbnail_size: args.web_thumbnails,
+        export_quality: args.export_quality,
+        export_strip_exif: args.export_strip_exif,
+        date_format: args.date_format,
+        palette: args.palette,
+        tile_links,
+    };
+    mosaic_outputs(
+        image,
+        stats,
+        tile_set,
+        config,
+        &sources,
+        args.manifest.as_deref(),
+        args.stats_out.as_deref(),
+        args.stats_scale,
+        args.html,
+        web,
+        args.html_pyramid,
+        args.seams,
+        args.map,
+        hidden,
+    )
+}
+
+/// Load the tiles of a wall or contact sheet, which needs at least one.
+fn load_wall_tiles(
+    args: &WallTiles,
+    tile_size: u32,
+    crop: bool,
+    library: &LibraryOptions,
+) -> Result<TileSet<[Rgb<u8>; 1]>, EmosaicError> {
+    validate_tiles_directory(&args.library.tiles_dir).map_err(EmosaicError::Validation)?;
+    let mut tile_set = args.library.load::<1>(tile_size, crop, library)?;
+    if tile_set.is_empty() {
+        return Err(EmosaicError::Validation(format!(
+            "❌ No tiles found in {}",
+            args.library.tiles_dir.display()
+        )));
+    }
+    tile_set.set_memory_budget(library.memory_budget);
+    tile_set.set_image_cache_budget(args.tile_cache_mb * MEGABYTE);
+    Ok(tile_set)
+}
+
+/// Columns and rows of a wall or contact sheet of `count` tiles.
+fn wall_grid(args: &WallTiles, count: usize) -> (u32, u32) {
+    let columns = args.columns.unwrap_or_else(|| wall::square_columns(count));
+    (columns, (count as u32).div_ceil(columns))
+}
+
+/// Save a rendered wall or contact sheet, reporting the tiles that could not be loaded.
+fn save_wall(
+    (output, failures): (image::RgbImage, LoadFailures),
+    output_path: &Path,
+    output_options: &OutputOptions,
+    open: bool,
+) -> Result<(), EmosaicError> {
+    if !failures.is_empty() {
+        warn!(
+            "⚠️  {} tiles could not be loaded and were replaced by a solid block",
+            failures.len()
+        );
+    }
+    save_output(&output.into(), output_path, output_options, &[]).map_err(|e| {
+        let context = format!("Failed to save output image to {}", output_path.display());
+        EmosaicError::decode(context, e)
+    })?;
+    info!(
+        "🎉 All done! Your image is ready at {}",
+        output_path.display()
+    );
+    if open {
+        open_result(output_path);
+    }
+    Ok(())
+}
+
+/// Migrate the analysis caches of `tiles_dir` written by older versions of emosaic to the
+/// current schema in place, merging the per-mode files of schema 0 into a single cache and
+/// re-reading from EXIF the time of the pictures dated only to the day.
+fn upgrade_cache(tiles_dir: &Path, library: &LibraryOptions) -> Result<(), EmosaicError> {
+    let cache_dir = tiles_cache_dir(tiles_dir, library)?;
+    let cache_path = cache_dir.join(AnalysisCache::FILE_NAME);
+    // Runs analysing the tiles meanwhile wait for the upgrade
+    let _lock = CacheLock::acquire(&cache_dir).map_err(|e| {
+        EmosaicError::io(
+            format!("Failed to lock analysis cache {}", cache_path.display()),
+            e,
+        )
+    })?;
+    let bytes = match fs::read(&cache_path) {
+        Ok(bytes) => Some(bytes),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => {
+            let context = format!("Failed to read analysis cache {}", cache_path.display());
+            return Err(EmosaicError::io(context, e));
+        }
+    };
+    let mode_files = AnalysisCache::mode_files(&cache_dir)
+        .map_err(|e| EmosaicError::io(format!("Failed to list {}", cache_dir.display()), e))?;
+    let version = bytes.as_deref().and_then(AnalysisCache::schema_version);
+    match (&bytes, version) {
+        (None, _) if mode_files.is_empty() => {
+            return Err(EmosaicError::Validation(format!(
+                "❌ No analysis cache in {}, nothing to upgrade",
+                tiles_dir.display()
+            )));
+        }
+        (Some(_), Some(AnalysisCache::SCHEMA_VERSION)) if mode_files.is_empty() => {
+            info!("{} is up to date", cache_path.display());
+            return Ok(());
+        }
+        _ => (),
+    }
+
+    let mut cache = match &bytes {
+        Some(bytes) => AnalysisCache::from_any_bytes(bytes).map_err(|e| {
+            e.with_context(format!(
+                "Failed to decode analysis cache {}",
+                cache_path.display()
+            ))
+        })?,
+        None => AnalysisCache::default(),
+    };
+    for (path, n, crop) in &mode_files {
+        let mode_bytes = fs::read(path).map_err(|e| {
+            EmosaicError::io(
+                format!("Failed to read analysis cache {}", path.display()),
+                e,
+            )
+        })?;
+        cache
+            .insert_mode_file(*crop, *n, &mode_bytes)
+            .map_err(|e| {
+                e.with_context(format!(
+                    "Failed to decode analysis cache {}",
+                    path.display()
+                ))
+            })?;
+    }
+    let refreshed = cache.refresh_metadata(|path| read_picture_metadata(path).ok());
+
+    // Replace the cache only once it is complete, so that an interruption loses nothing
+    let encoded = cache.to_bytes()?;
+    write_atomic(&cache_path, |writer| writer.write_all(&encoded)).map_err(|e| {
+        EmosaicError::io(
+            format!("Failed to write analysis cache {}", cache_path.display()),
+            e,
+        )
+    })?;
+    for (path, _, _) in &mode_files {
+        fs::remove_file(path).map_err(|e| {
+            EmosaicError::io(
+                format!("Failed to remove analysis cache {}", path.display()),
+                e,
+            )
+        })?;
+    }
+    // The per-mode files are the oldest schema
+    let from = match version {
+        Some(version) if mode_files.is_empty() => version,
+        _ => 0,
+    };
+    info!(
+        "✅ Upgraded the analysis cache of {} from schema {} to {}, re-reading the dates, \
+         captions and cameras of {} pictures",
+        tiles_dir.display(),
+        from,
+        AnalysisCache::SCHEMA_VERSION,
+        refreshed
+    );
+    Ok(())
+}
+
+/// Read the links opened from the tiles of the HTML outputs, which need one of them.
+fn load_tile_links(
+    path: Option<PathBuf>,
+    html_output: bool,
+    tiles_dir: &Path,
+) -> Result<TileLinks, EmosaicError> {
+    if path.is_some() && !html_output {
+        return Err(EmosaicError::Validation(String::from(
+            "--tile-links needs --html, --web or --html-pyramid",
+        )));
+    }
+    match path {
+        Some(path) => TileLinks::load(&path, tiles_dir).map_err(|e| {
+            EmosaicError::Validation(format!(
+                "❌ Failed to read tile links from {}: {}",
+                path.display(),
+                e
+            ))
+        }),
+        None => Ok(TileLinks::default()),
+    }
+}
+
+/// Summarise the placements of a rendered mosaic but those of the `hidden` cells, render its
+/// statistics image, write its manifest if requested and prepare the requested HTML output for
+/// once the mosaic is saved.
+#[allow(clippy::too_many_arguments)]
+fn mosaic_outputs<T: Send + Sync + 'static>(
+    image: image::RgbImage,
+    mut stats: RenderStats<SIZE>,
+    tile_set: TileSet<T>,
+    config: MosaicConfig,
+    sources: &[TileSource],
+    manifest: Option<&Path>,
+    stats_out: Option<&Path>,
+    stats_scale: u32,
+    html: bool,
+    web: bool,
+    html_pyramid: bool,
+    seams: bool,
+    map: bool,
+    hidden: &HashSet<(u32, u32)>,
+) -> Result<ImgAndStats, EmosaicError> {
+    stats.hide_cells(hidden);
+    stats.summarise(&tile_set);
+    let summary = stats.summary(&tile_set);
+    let (tiles, cache_hit_rate) = (tile_set.len(), tile_set.image_cache_hit_rate());
+    if sources.len() > 1 {
+        stats.summarise_sources(&tile_set, sources);
+    }
+
+    // A mosaic interrupted before its first tile has no statistics to draw
+    let stats_img = stats
+        .clone()
+        .render_heatmap(config.palette, stats_scale)
+        .ok();
+
+    let manifest = manifest.map(|path| match cancel::interrupted() {
+        true => cancel::partial_path(path),
+        false => path.to_path_buf(),
+    });
+    if let Some(manifest_path) = &manifest {
+        stats
+            .manifest(&tile_set, &config)
+            .save(manifest_path)
+            .map_err(|e| {
+                EmosaicError::io(
+                    format!("❌ Failed to write manifest to {}", manifest_path.display()),
+                    e,
+                )
+            })?;
+        info!("🧾 Placement manifest saved to {}", manifest_path.display());
+    }
+    if let Some(path) = stats_out {
+        let format = stats_out_format(path)?;
+        let stats_path = match cancel::interrupted() {
+            true => cancel::partial_path(path),
+            false => path.to_path_buf(),
+        };
+        stats
+            .usage_report(&tile_set)
+            .save(&stats_path, format)
+            .map_err(|e| {
+                let context = format!("❌ Failed to write statistics to {}", stats_path.display());
+                EmosaicError::io(context, e)
+            })?;
+        info!("📈 Tile usage statistics saved to {}", stats_path.display());
+    }
+
+    let html_generator = if html || web || html_pyramid {
+        if web {
+            info!("🌐 Web-compatible HTML output requested - will generate after image save");
+        } else if html_pyramid {
+            info!("🔎 Zoomable HTML output requested - will generate after image save");
+        } else {
+            info!("📄 HTML output requested - will generate after image save");
+        }
+
+        Some(Box::new(
+            move |mosaic_path: &std::path::Path,
+                  html_path: &std::path::Path,
+                  mosaic_image: &image::RgbImage|
+                  -> Result<(), std::io::Error> {
+                let mut stats = stats;
+                if seams && !html_pyramid {
+                    stats.measure_seams(mosaic_image);
+                }
+                if map {
+                    let (located, pictures) = stats.locate_photos(&tile_set);
+                    info!(
+                        "🗺️  Located {} of the {} pictures placed",
+                        located, pictures
+                    );
+                }
+                let pyramid = html_pyramid.then_some(mosaic_image);
+                stats.generate_html_with_options(
+                    mosaic_path,
+                    html_path,
+                    &tile_set,
+                    &config,
+                    web,
+                    pyramid,
+                )
+            },
+        ) as HtmlGenerator)
+    } else {
+        None
+    };
+
+    Ok(ImgAndStats {
+        img: image,
+        summary,
+        tiles,
+        cache_hit_rate,
+        stats_img,
+        html_generator,
+        panels: Vec::new(),
+    })
+}
+
+/// The format of the statistics written with --stats-out to `path`, from its extension.
+fn stats_out_format(path: &Path) -> Result<UsageFormat, EmosaicError> {
+    UsageFormat::from_path(path).ok_or_else(|| {
+        EmosaicError::Validation(format!(
+            "❌ --stats-out needs a .csv or .json file, got {}",
+            path.display()
+        ))
+    })
+}
+
+/// The directory holding the analysis cache of `tiles_dir`, see [`cache_dir`].
+fn tiles_cache_dir(tiles_dir: &Path, library: &LibraryOptions) -> Result<PathBuf, EmosaicError> {
+    cache_dir(tiles_dir, library).map_err(|e| {
+        let context = format!(
+            "Failed to create the cache directory of {}",
+            tiles_dir.display()
+        );
+        EmosaicError::io(context, e)
+    })
+}
+
+/// Load the analysis of the tiles in `tiles_dir` from its analysis cache, migrated first when
+/// written by an older version, or analyse them and update the cache. When `max_tiles` is
+/// given, only a random sample of that many tiles is kept. A `dry_run` never analyses the
+/// tiles, and fails when they are not in the cache. The tiles are found, filtered and cached as
+/// `library` says.
+#[allow(clippy::too_many_arguments)]
+fn load_tile_set<const N: usize>(
+    tiles_dir: &Path,
+    extensions: &HashSet<String>,
+    tile_size: u32,
+    crop: bool,
+    force: bool,
+    preview: bool,
+    dry_run: bool,
+    max_tiles: Option<usize>,
+    tile_timeout: u64,
+    library: &LibraryOptions,
+) -> Result<TileSet<[Rgb<u8>; N]>, EmosaicError>
+where
+    [(); N * 3]:,
+{
+    let cache_dir = tiles_cache_dir(tiles_dir, library)?;
+    // Caches kept apart from their tiles refer to them by absolute paths, which hold from any
+    // working directory
+    let absolute;
+    let tiles_dir = if cache_dir == tiles_dir {
+        tiles_dir
+    } else {
+        absolute = tiles_dir.canonicalize().map_err(|e| {
+            EmosaicError::io(format!("Failed to resolve {}", tiles_dir.display()), e)
+        })?;
+        &absolute
+    };
+    let analysis_cache_path = cache_dir.join(AnalysisCache::FILE_NAME);
+    let mut bytes = fs::read(&analysis_cache_path).ok();
+    let outdated = match &bytes {
+        Some(bytes) => AnalysisCache::schema_version(bytes)
+            .is_some_and(|version| version < AnalysisCache::SCHEMA_VERSION),
+        None => AnalysisCache::mode_files(&cache_dir).is_ok_and(|files| !files.is_empty()),
+    };
+    // Migrating keeps the analysis, and only reads again what older caches lack
+    if outdated && !force {
+        info!(
+            "Migrating the analysis cache of {} written by an older version of emosaic",
+            tiles_dir.display()
+        );
+        bytes = match upgrade_cache(tiles_dir, library) {
+            Ok(()) => fs::read(&analysis_cache_path).ok(),
+            Err(e) => {
+                warn!("⚠️  {}, analysing the tiles again", e);
+                None
+            }
+        };
+    }
+    let analysis_cache = bytes
+        .and_then(|bytes| match AnalysisCache::from_partial_bytes(&bytes) {
+            Ok((analysis_cache, 0)) => Some(analysis_cache),
+            Ok((analysis_cache, lost)) => {
+                info!(
+                    "Re-analysing the {} damaged analyses of the cache {}",
+                    lost,
+                    analysis_cache_path.display()
+                );
+                Some(analysis_cache)
+            }
+            Err(err) => {
+                info!(
+                    "Ignoring corrupt analysis cache {} ({}), re-analysing",
+                    analysis_cache_path.display(),
+                    err
+                );
+                None
+            }
+        })
+        .unwrap_or_default();
     let tile_set = if force {
         None
     } else {
-        fs::read(&analysis_cache_path).ok()
+        let tile_set = analysis_cache.get::<N>(crop);
+        if let (None, Some(cached_n), false) = (&tile_set, analysis_cache.cached_n(crop), dry_run) {
+            info!(
+                "Analysis cache only covers modes up to N={}, re-analysing for N={}",
+                cached_n, N
+            );
+        }
+        tile_set
     };
-    let tile_set: TileSet<[Rgb<u8>; N]> = tile_set
-        .and_then(|bytes| bincode::deserialize::<TileSet<[Rgb<u8>; N]>>(&bytes).ok())
+    if tile_set.is_none() && dry_run {
+        return Err(EmosaicError::Validation(format!(
+            "❌ --dry-run needs an analysis cache of {} for this mode, run once without it",
+            tiles_dir.display()
+        )));
+    }
+    let tile_set = tile_set
         .map(|analysis| {
-            eprintln!("Reusing analysis cache");
-            // Filter out tiles for files that no longer exist or don't match extensions
+            info!("Reusing analysis cache");
+            let ignore = Ignore::for_dir(tiles_dir, &library.excludes).map_err(|e| {
+                let context = format!("Failed to read the ignore file of {}", tiles_dir.display());
+                EmosaicError::io(context, e)
+            })?;
+            // Filter out tiles for files that no longer exist, don't match extensions or are
+            // ignored, and crops no longer asked for
+            let crops = library.augment_crops;
             let valid_data: Vec<_> = analysis
                 .tiles
                 .par_iter()
                 .filter_map(|tile| {
                     let path = analysis.get_path(tile);
-                    let extension = path.extension()?.to_str()?;
-                    if path.exists() && extensions.contains(extension) {
+                    let file = split_crop(path).0;
+                    let extension = file.extension()?.to_str()?;
+                    let ignored = file
+                        .strip_prefix(tiles_dir)
+                        .is_ok_and(|relative| ignore.is_file_ignored(relative));
+                    let kept = is_kept_crop(path, crops);
+                    if file.exists() && extensions.contains(extension) && !ignored && kept {
                         Some((path.to_owned(), tile.clone()))
                     } else {
                         None
                     }
                 })
                 .collect();
-            
+            let valid_data = library.filter.retain(valid_data, |(path, _)| path);
+            warn_uncropped(valid_data.iter().map(|(path, _)| path.as_path()), crops);
+
             // Create new TileSet from valid tiles, renumbering indices sequentially
-            let (paths, tiles): (Vec<PathBuf>, Vec<Tile<[Rgb<u8>; N]>>) = valid_data.into_iter().unzip();
+            let (paths, tiles): (Vec<PathBuf>, Vec<Tile<_>>) = valid_data.into_iter().unzip();
             let renumbered_tiles: Vec<Tile<[Rgb<u8>; N]>> = tiles
                 .into_iter()
                 .enumerate()
                 .map(|(i, tile)| Tile {
-                    idx: (i + 1) as u16,
+                    idx: (i + 1) as u32,
                     colors: tile.colors,
                     flipped: tile.flipped,
                     date_taken: tile.date_taken,
+                    caption: tile.caption,
+                    camera: tile.camera,
+                    sharpness: tile.sharpness,
                 })
                 .collect();
-            TileSet::from_tiles(renumbered_tiles, paths)
+            let tile_set = TileSet::from_tiles(renumbered_tiles, paths);
+            Ok(match max_tiles {
+                Some(max_tiles) => tile_set.sample(max_tiles),
+                None => tile_set,
+            })
         })
         .unwrap_or_else(|| {
             let extensions = extensions.iter().map(OsString::from).collect();
-            let tile_set = generate_tile_set::<N>(&tiles_dir, tile_size, extensions, crop).unwrap();
-            let encoded_tile_set = bincode::serialize(&tile_set).unwrap();
-            fs::write(&analysis_cache_path, encoded_tile_set).unwrap();
-            tile_set
-        });
-    eprintln!("Tile set with {} tiles", tile_set.len());
-    let result = if no_repeat && !greedy {
-        render_nto1_no_repeat(&img, tile_set, tile_size)?
-    } else {
-        render_nto1(&img, tile_set, tile_size, no_repeat, randomize)
-    };
-
-    result.stats.summarise(&result.tile_set);
+            let timeout = (tile_timeout > 0).then(|| Duration::from_secs(tile_timeout));
+            let tile_set = generate_tile_set::<N>(
+                tiles_dir, tile_size, extensions, crop, max_tiles, timeout, force, library,
+            )?;
+            if cancel::interrupted() {
+                let resume = if max_tiles.is_none() {
+                    ", the tiles analysed so far are kept for the next run"
+                } else {
+                    ""
+                };
+                return Err(EmosaicError::Interrupted(format!(
+                    "⏹️  Interrupted while analysing the tiles{}",
+                    resume
+                )));
+            }
+            if !preview {
+                // Other runs may have updated the cache of the same tiles in the meantime, with
+                // a finer analysis to keep
+                let updated = AnalysisCache::update(&cache_dir, |analysis_cache| {
+                    let cached_n = analysis_cache.cached_n(crop);
+                    if force || cached_n.is_none_or(|cached_n| cached_n <= N) {
+                        analysis_cache.insert(crop, &tile_set);
+                    }
+                });
+                if let Err(e) = updated {
+                    error!("❌ {}", e);
+                    return Ok(tile_set);
+                }
+                if let Err(e) = AnalysisCheckpoint::remove(&cache_dir) {
+                    error!("❌ Failed to remove the analysis checkpoint: {}", e);
+                }
+            }
+            Ok(tile_set)
+        })?;
+    Ok(drop_blurry_tiles(
+        tile_set,
+        &library.filter,
+        (!preview).then_some(&cache_dir),
+        !dry_run,
+    ))
+}
 
-    // Extract data and create HTML generator if requested
-    let image = result.image;
-    let stats = result.stats;
-    let tile_set = result.tile_set;
+/// Leave out of `tile_set` the tiles of the pictures blurrier than the minimum sharpness of
+/// `filter`.
+///
+/// Unless `measure` is false, the sharpness of the pictures not measured when analysed is
+/// measured first, and added to the analysis cache in `cache_dir` if given, so that the
+/// threshold can be changed without decoding them again.
+fn drop_blurry_tiles<const N: usize>(
+    mut tile_set: TileSet<[Rgb<u8>; N]>,
+    filter: &TileFilter,
+    cache_dir: Option<&Path>,
+    measure: bool,
+) -> TileSet<[Rgb<u8>; N]> {
+    let Some(min_sharpness) = filter.min_sharpness else {
+        return tile_set;
+    };
+    let unmeasured: Vec<(usize, PathBuf)> = (tile_set.tiles.iter().enumerate())
+        .filter(|(_, tile)| measure && tile.sharpness.is_none())
+        .map(|(i, tile)| (i, tile_set.get_path(tile).to_owned()))
+        .collect();
+    if !unmeasured.is_empty() {
+        info!(
+            "Measuring the sharpness of {} tiles not measured when analysed",
+            unmeasured.len()
+        );
+        let pb = Progress::with_template(
+            unmeasured.len() as u64,
+            "Measuring sharpness",
+            &RenderConfig::default().progress_template,
+        );
+        let measured: Vec<(usize, PathBuf, f32)> = unmeasured
+            .into_par_iter()
+            .filter_map(|(i, path)| {
+                let sharpness = read_sharpness(&path).ok();
+                pb.inc(1);
+                Some((i, path, sharpness?))
+            })
+            .collect();
+        pb.finish_and_clear();
+        let mut scores = HashMap::new();
+        for (i, path, sharpness) in measured {
+            tile_set.tiles[i].sharpness = Some(sharpness);
+            scores.insert(path, sharpness);
+        }
+        if let Some(cache_dir) = cache_dir {
+            let updated = AnalysisCache::update(cache_dir, |analysis_cache| {
+                analysis_cache.set_sharpness(&scores)
+            });
+            if let Err(e) = updated {
+                error!("❌ Failed to record the sharpness of the tiles: {}", e);
+            }
+        }
+    }
+    let blurry: HashSet<PathBuf> = (tile_set.tiles.iter())
+        .filter(|tile| filter.is_blurry(tile.sharpness))
+        .map(|tile| tile_set.get_path(tile).to_owned())
+        .collect();
+    let (tile_set, dropped) = tile_set.retain_paths(|path| !blurry.contains(path));
+    if dropped > 0 {
+        info!(
+            "Skipping {} images blurrier than a sharpness of {}",
+            dropped, min_sharpness
+        );
+    }
+    tile_set
+}
 
-    // Clone for different uses
-    let stats_for_render = stats.clone();
-    let stats_img = Some(stats_for_render.render(tile_size));
+/// The directory the relative paths of the tiles list at `path` are relative to.
+fn list_dir(path: &Path) -> &Path {
+    path.parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."))
+}
 
-    let html_generator = if html || web {
-        if web {
-            eprintln!("🌐 Web-compatible HTML output requested - will generate after image save");
-        } else {
-            eprintln!("📄 HTML output requested - will generate after image save");
-        }
-
-        // Create MosaicConfig for HTML generation
-        let mode_str = match mode {
-            Mode::_1 => "1x1 (N=1)",
-            Mode::_2 => "2x2 (N=4)",
-            Mode::_3 => "3x3 (N=9)",
-            Mode::_4 => "4x4 (N=16)",
-            Mode::_5 => "5x5 (N=25)",
-            Mode::_6 => "6x6 (N=36)",
-            Mode::_8 => "8x8 (N=64)",
-            Mode::_16 => "16x16 (N=256)",
-            Mode::_32 => "32x32 (N=1024)",
-            Mode::_64 => "64x64 (N=4096)",
-            Mode::_128 => "128x128 (N=16384)",
-            Mode::Random => "Random",
-        };
+/// Read the tiles list at `path`, with the paths of its images resolved and the missing ones
+/// left out.
+fn read_tile_list(path: &Path) -> Result<TileList, EmosaicError> {
+    let mut list = TileList::load(path)?;
+    let missing = list.resolve();
+    if missing > 0 {
+        warn!(
+            "⚠️  Skipping {} missing images of the tiles list {}",
+            missing,
+            path.display()
+        );
+    }
+    if list.tiles.is_empty() {
+        return Err(EmosaicError::Validation(format!(
+            "❌ The tiles list {} has no images",
+            path.display()
+        )));
+    }
+    Ok(list)
+}
 
-        let config = MosaicConfig {
+/// Load the tile set of the images of `list`, reusing their analysis from the cache shared by
+/// every tiles list and analysing the images it lacks, which are added to it. The labels of
+/// the list replace the captions of the pictures. The tiles are filtered and cached as
+/// `library` says.
+#[allow(clippy::too_many_arguments)]
+fn load_tile_list<const N: usize>(
+    list: &TileList,
+    tile_size: u32,
+    crop: bool,
+    force: bool,
+    preview: bool,
+    dry_run: bool,
+    max_tiles: Option<usize>,
+    tile_timeout: u64,
+    library: &LibraryOptions,
+) -> Result<TileSet<[Rgb<u8>; N]>, EmosaicError>
+where
+    [(); N * 3]:,
+{
+    let cache_dir = list_cache_dir(library)
+        .map_err(|e| EmosaicError::io("Failed to create the cache of the tiles lists", e))?;
+    let analysis_cache_path = cache_dir.join(AnalysisCache::FILE_NAME);
+    let cached = fs::read(&analysis_cache_path)
+        .ok()
+        .filter(|_| !force)
+        .and_then(|bytes| AnalysisCache::from_partial_bytes(&bytes).ok())
+        .and_then(|(analysis_cache, _)| analysis_cache.get::<N>(crop))
+        .unwrap_or_else(TileSet::new);
+    let listed = list.tiles.iter().map(|tile| tile.path.as_path()).collect();
+    let listed: Vec<&Path> = library.filter.retain(listed, |path| path);
+    let listed_set: HashSet<&Path> = listed.iter().copied().collect();
+    let crops = library.augment_crops;
+    let (mut tile_set, _) = cached
+        .retain_paths(|path| listed_set.contains(split_crop(path).0) && is_kept_crop(path, crops));
+    if !tile_set.is_empty() {
+        info!("Reusing the analysis of {} listed tiles", tile_set.len());
+        warn_uncropped(tile_set.paths().iter().map(PathBuf::as_path), crops);
+    }
+    let reused: HashSet<&Path> = tile_set.paths().iter().map(PathBuf::as_path).collect();
+    let images_paths: Vec<PathBuf> = listed
+        .into_iter()
+        .filter(|path| !reused.contains(path))
+        .map(Path::to_owned)
+        .collect();
+    if !images_paths.is_empty() && dry_run {
+        return Err(EmosaicError::Validation(format!(
+            "❌ --dry-run needs the analysis of every listed tile for this mode, {} are missing, \
+             run once without it",
+            images_paths.len()
+        )));
+    }
+    if !images_paths.is_empty() {
+        let timeout = (tile_timeout > 0).then(|| Duration::from_secs(tile_timeout));
+        let max_tiles = max_tiles.map(|max_tiles| max_tiles.saturating_sub(tile_set.len()));
+        let analysed = analyse_images::<N>(
+            &cache_dir,
+            &cache_dir,
+            images_paths,
             tile_size,
-            mode: mode_str.to_string(),
-            no_repeat,
-            greedy,
             crop,
-            tint_opacity,
-            downsample: downsample.into(),
-            randomize,
-            tiles_dir: tiles_dir.display().to_string(),
-            title: title.clone(),
-        };
-
-        // Clone the necessary data for the closure
-        let stats_clone = stats.clone();
-        let tile_set_clone = tile_set.clone();
-        Some(Box::new(
-            move |mosaic_path: &std::path::Path,
-                  html_path: &std::path::Path|
-                  -> Result<(), std::io::Error> {
-                stats_clone.generate_html_with_options(mosaic_path, html_path, &tile_set_clone, &config, web)
-            },
-        )
-            as Box<
-                dyn FnOnce(&std::path::Path, &std::path::Path) -> Result<(), std::io::Error> + Send,
-            >)
-    } else {
-        None
-    };
+            max_tiles,
+            timeout,
+            force,
+            library,
+        )?;
+        if cancel::interrupted() {
+            return Err(EmosaicError::Interrupted(String::from(
+                "⏹️  Interrupted while analysing the tiles, the tiles analysed so far are kept for \
+                 the next run",
+            )));
+        }
+        if !preview {
+            // The cache holds the tiles of every list: the new analyses are added to those of
+            // the other lists, as long as they are of the same mode
+            let analysed_paths: HashSet<&Path> =
+                analysed.paths().iter().map(PathBuf::as_path).collect();
+            let updated = AnalysisCache::update(&cache_dir, |analysis_cache| {
+                let cached_n = analysis_cache.cached_n(crop);
+                if force || cached_n.is_none_or(|cached_n| cached_n <= N) {
+                    let cached = analysis_cache.get::<N>(crop).unwrap_or_else(TileSet::new);
+                    let (mut merged, _) =
+                        cached.retain_paths(|path| !analysed_paths.contains(path));
+                    merged.append(TileSet::from_tiles(
+                        analysed.tiles.clone(),
+                        analysed.paths().to_vec(),
+                    ));
+                    analysis_cache.insert(crop, &merged);
+                }
+            });
+            match updated {
+                Ok(()) => {
+                    if let Err(e) = AnalysisCheckpoint::remove(&cache_dir) {
+                        error!("❌ Failed to remove the analysis checkpoint: {}", e);
+                    }
+                }
+                Err(e) => error!("❌ {}", e),
+            }
+        }
+        tile_set.append(analysed);
+    }
+    let cache = (!preview).then_some(cache_dir.as_path());
+    let mut tile_set = drop_blurry_tiles(tile_set, &library.filter, cache, !dry_run);
+    let labels: HashMap<&Path, &str> = list
+        .tiles
+        .iter()
+        .filter_map(|tile| Some((tile.path.as_path(), tile.label.as_deref()?)))
+        .collect();
+    let captions: Vec<_> = tile_set
+        .paths()
+        .iter()
+        .map(|path| labels.get(path.as_path()).map(|label| label.to_string()))
+        .collect();
+    for (tile, caption) in tile_set.tiles.iter_mut().zip(captions) {
+        if caption.is_some() {
+            tile.caption = caption;
+        }
+    }
+    Ok(tile_set)
+}
 
-    Ok(ImgAndStats {
-        img: image,
-        stats_img,
-        html_generator,
+/// Read the tile set from the JSON tile analysis at `path`. When `max_tiles` is given, only a
+/// random sample of that many tiles is kept.
+fn read_tile_analysis<const N: usize>(
+    path: &Path,
+    max_tiles: Option<usize>,
+) -> Result<TileSet<[Rgb<u8>; N]>, EmosaicError> {
+    info!("Reading tile analysis from {}", path.display());
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let tile_set = TileAnalysis::load(path)
+        .map_err(|e| e.to_string())
+        .and_then(|analysis| analysis.to_tile_set::<N>(base_dir))
+        .map_err(|e| {
+            EmosaicError::cache(format!("❌ Invalid tile analysis {}", path.display()), e)
+        })?;
+    Ok(match max_tiles {
+        Some(max_tiles) => tile_set.sample(max_tiles),
+        None => tile_set,
     })
 }
 
+/// Analyse the images in `tiles_path`. When `max_tiles` is given, only a random sample
+/// of that many images is analysed and the prepared tiles are not written to the cache.
+///
+/// Images taking longer than `timeout` to prepare, too large to decode or crashing the
+/// decoder are quarantined and skipped by later analyses, unless `retry` is set. The images
+/// are found, filtered and cached as `library` says.
+#[allow(clippy::too_many_arguments)]
 fn generate_tile_set<const N: usize>(
     tiles_path: &Path,
     tile_size: u32,
     extensions: HashSet<OsString>,
     crop: bool,
-) -> io::Result<TileSet<[Rgb<u8>; N]>>
+    max_tiles: Option<usize>,
+    timeout: Option<Duration>,
+    retry: bool,
+    library: &LibraryOptions,
+) -> Result<TileSet<[Rgb<u8>; N]>, EmosaicError>
 where
     // TileSet<T>: Serialize,
     // T: std::hash::Hash + Eq + Copy,
 {
-    let images_paths = find_images(tiles_path, |path: &OsStr| extensions.contains(path))?;
-    let pb = ProgressBar::new(images_paths.len() as u64)
-        .with_message("Analysing tiles")
-        .with_style(
-            ProgressStyle::default_bar()
-                .template("{msg} {wide_bar} {pos}/{len} ({per_sec})")
-                .unwrap(),
+    let cache_dir = tiles_cache_dir(tiles_path, library)?;
+    let known = |path: &OsStr| extensions.contains(path);
+    let images_paths = find_images(tiles_path, known, library).map_err(|e| {
+        EmosaicError::io(
+            format!("Failed to find images in {}", tiles_path.display()),
+            e,
+        )
+    })?;
+    analyse_images::<N>(
+        tiles_path,
+        &cache_dir,
+        images_paths,
+        tile_size,
+        crop,
+        max_tiles,
+        timeout,
+        retry,
+        library,
+    )
+}
+
+/// Analyse the images at `images_paths`, keeping the checkpoint, failure report and
+/// quarantine of the analysis in `cache_dir`, with the paths of the failed images relative to
+/// `tiles_path` when they are in it. The images are filtered and cropped as `library` says.
+#[allow(clippy::too_many_arguments)]
+fn analyse_images<const N: usize>(
+    tiles_path: &Path,
+    cache_dir: &Path,
+    images_paths: Vec<PathBuf>,
+    tile_size: u32,
+    crop: bool,
+    max_tiles: Option<usize>,
+    timeout: Option<Duration>,
+    retry: bool,
+    library: &LibraryOptions,
+) -> Result<TileSet<[Rgb<u8>; N]>, EmosaicError> {
+    let _span = debug_span!("analysis").entered();
+    let images_paths = library.filter.retain(images_paths, |path| path);
+    let mut images_paths = augment(images_paths, tile_size, library.augment_crops);
+    let mut quarantine = if retry {
+        Quarantine::default()
+    } else {
+        Quarantine::load(cache_dir)
+    };
+    let found = images_paths.len();
+    images_paths.retain(|path| !quarantine.contains(path.strip_prefix(tiles_path).unwrap_or(path)));
+    if images_paths.len() < found {
+        info!(
+            "Skipping {} quarantined images listed in {}, use --force to retry them",
+            found - images_paths.len(),
+            cache_dir.join(Quarantine::FILE_NAME).display()
         );
+    }
+    if let Some(max_tiles) = max_tiles {
+        images_paths.shuffle(&mut rand::thread_rng());
+        images_paths.truncate(max_tiles);
+    }
+    let write_cache = max_tiles.is_none();
+
+    // Resume an interrupted analysis of the same images, unless forced to start over
+    let mut checkpoint = (write_cache && !retry)
+        .then(|| AnalysisCheckpoint::load(cache_dir, crop, N, tile_size))
+        .flatten()
+        .unwrap_or_else(|| AnalysisCheckpoint::new(crop, N, tile_size));
+    if !checkpoint.is_empty() {
+        let found: HashSet<&Path> = images_paths.iter().map(PathBuf::as_path).collect();
+        checkpoint.retain(|path| found.contains(path));
+        let analysed = checkpoint.paths();
+        images_paths.retain(|path| !analysed.contains(path.as_path()));
+        info!(
+            "Resuming the interrupted analysis of {} images, {} left to analyse",
+            checkpoint.len(),
+            images_paths.len()
+        );
+    }
+
+    let pb = Progress::with_template(
+        (checkpoint.len() + images_paths.len()) as u64,
+        "Analysing tiles",
+        &RenderConfig::default().progress_template,
+    );
+    pb.set_position(checkpoint.len() as u64);
 
     let errors: RwLock<Vec<ImageError>> = RwLock::new(vec![]);
-    let tile_data: Vec<_> = images_paths
-        .into_par_iter()
-        .map(|path| {
-            let img_and_date = prepare_tile_with_date(&path, tile_size, crop);
-            (path, img_and_date)
-        })
-        .inspect(move |_| pb.inc(1))
-        .filter_map(|x| match x {
-            (path, Ok((img, date_taken))) => Some((path, img, date_taken)),
-            (path, Err(error)) => {
-                let path = path.strip_prefix(tiles_path).unwrap();
-                errors.write().unwrap().push(ImageError {
-                    path: path.to_owned(),
-                    ..error
-                });
-                None
+    for batch in images_paths.chunks(AnalysisCheckpoint::INTERVAL) {
+        let analysed: Vec<_> = batch
+            .par_iter()
+            .map(|path| {
+                progress::set_current_file(path);
+                let img_and_metadata =
+                    prepare_tile_isolated(path, tile_size, crop, write_cache, timeout);
+                (path, img_and_metadata)
+            })
+            .inspect(|_| pb.inc(1))
+            .filter_map(|x| match x {
+                (path, Ok((img, metadata, sharpness))) => {
+                    Some((path, analyse::<N>(img), metadata, sharpness))
+                }
+                (path, Err(error)) => {
+                    let path = path.strip_prefix(tiles_path).unwrap_or(path);
+                    errors.write().unwrap().push(ImageError {
+                        path: path.to_owned(),
+                        ..error
+                    });
+                    None
+                }
+            })
+            .collect();
+        for (path, colors, metadata, sharpness) in analysed {
+            checkpoint.push(path.clone(), &colors, &metadata, sharpness);
+        }
+        if write_cache {
+            if let Err(e) = checkpoint.save(cache_dir) {
+                error!(
+                    "❌ Failed to write the analysis checkpoint to {}: {}",
+                    cache_dir.join(AnalysisCheckpoint::FILE_NAME).display(),
+                    e
+                );
             }
-        })
-        .collect();
+        }
+        if cancel::interrupted() {
+            break;
+        }
+    }
 
-    let dates = tile_data
+    // Create tiles with date information
+    let tiles: Vec<_> = checkpoint.into_images::<N>().collect();
+    let dates = tiles
         .iter()
-        .filter(|(_, _, date)| date.is_some())
+        .filter(|(_, tile)| tile.date_taken.is_some())
         .count();
 
-    // Create tiles with date information
-    let tiles: Vec<_> = tile_data
-        .into_iter()
-        .enumerate()
-        .map(|(idx, (path, img, date_taken))| {
-            let colors = analyse::<N>(img);
-            let tile = Tile::new_with_date((idx + 1) as u16, colors, date_taken);
-            (path, tile)
-        })
-        .collect();
-
     let tile_set = TileSet::from_tiles(
         tiles.iter().map(|(_, tile)| tile.clone()).collect(),
         tiles.into_iter().map(|(path, _)| path).collect(),
     );
-    let all_errors = errors.into_inner().unwrap();
-    if !all_errors.is_empty() {
-        eprintln!("Failed to read the following images({}):", all_errors.len());
-        for error in all_errors {
-            eprintln!("- {}", error);
+    let report = FailureReport::new(&errors.into_inner().unwrap());
+    let report_path = cache_dir.join(FailureReport::FILE_NAME);
+    if report.total > 0 {
+        report.summarise();
+        if write_cache {
+            match report.save(&report_path) {
+                Ok(()) => info!("Failures written to {}", report_path.display()),
+                Err(e) => error!(
+                    "❌ Failed to write failures to {}: {}",
+                    report_path.display(),
+                    e
+                ),
+            }
+        }
+    } else if write_cache {
+        // A report left by an earlier analysis no longer applies
+        let _ = fs::remove_file(&report_path);
+    }
+    let quarantined = quarantine.extend(&report);
+    if write_cache {
+        match quarantine.save(cache_dir) {
+            Ok(()) if quarantined > 0 => info!("Quarantined {} images", quarantined),
+            Ok(()) => (),
+            Err(e) => error!(
+                "❌ Failed to write quarantine to {}: {}",
+                cache_dir.join(Quarantine::FILE_NAME).display(),
+                e
+            ),
         }
     }
 
     summarise_tileset(&tile_set);
-    eprintln!("Extracted {} dates successfully", dates);
+    info!("Extracted {} dates successfully", dates);
     Ok(tile_set)
 }
 
+/// Warn when `crops` crops of the pictures are asked for but none of the cached tiles at
+/// `paths` is a crop, since the pictures analysed before are not looked at again.
+fn warn_uncropped<'a>(mut paths: impl Iterator<Item = &'a Path>, crops: usize) {
+    if crops > 0 && paths.all(|path| split_crop(path).1.is_none()) {
+        warn!("⚠️  No crops of the cached tiles were taken, use --force to take them");
+    }
+}
+
 fn summarise_tileset<T>(tile_set: &TileSet<T>)
 where
     T: std::hash::Hash + Eq + Copy,
 {
-    let mut tiles_by_color: HashMap<T, u16> = HashMap::new();
+    let mut tiles_by_color: HashMap<T, u32> = HashMap::new();
     for tile in tile_set.tiles.iter() {
         *tiles_by_color.entry(tile.colors).or_default() += 1;
     }
 
-    eprintln!(
+    info!(
         "The analysis produced {} unique tiles",
         tiles_by_color.len()
     );
@@ -1,6 +1,11 @@
 //! Utility functions for mosaic algorithms
 
+use kiddo::fixed::distance::Manhattan;
+use kiddo::fixed::kdtree::KdTree;
 use kiddo::NearestNeighbour;
+use typenum::U0;
+
+use super::tiles::{TileIndex, SIZE};
 
 /// Compare two sets of nearest neighbor matches for sorting
 /// Returns ordering based on the distance of the worst (last) match in each set
@@ -11,17 +16,272 @@ pub fn compare_matches<B: Ord, C>(
     b.last().unwrap().distance.cmp(&a.last().unwrap().distance)
 }
 
+/// Manhattan distance between two color vectors.
+///
+/// Summed over fixed-size chunks of u8 lanes so the compiler turns it into packed
+/// SIMD operations (e.g. `psadbw` on x86); 16 lanes of at most 255 fit in a u16.
+fn manhattan(a: &[u8], b: &[u8]) -> u32 {
+    const LANES: usize = 16;
+    let chunks_a = a.chunks_exact(LANES);
+    let chunks_b = b.chunks_exact(LANES);
+    let tail: u32 = chunks_a
+        .remainder()
+        .iter()
+        .zip(chunks_b.remainder())
+        .map(|(x, y)| u32::from(x.abs_diff(*y)))
+        .sum();
+    chunks_a.zip(chunks_b).fold(tail, |sum, (ca, cb)| {
+        let chunk: u16 = ca
+            .iter()
+            .zip(cb)
+            .map(|(x, y)| u16::from(x.abs_diff(*y)))
+            .sum();
+        sum + u32::from(chunk)
+    })
+}
+
+/// Exact nearest neighbour search by scanning every item.
+///
+/// For small tile sets, or when the dimension is too high for a kd-tree to prune
+/// anything, a linear scan over packed colors beats walking the tree.
+pub struct BruteForceIndex<const K: usize> {
+    coords: Vec<u8>,
+    items: Vec<i32>,
+}
+
+impl<const K: usize> BruteForceIndex<K> {
+    /// Build the index from tile coordinates and their signed tile indices.
+    pub fn build(entries: impl Iterator<Item = ([SIZE; K], i32)>) -> BruteForceIndex<K> {
+        let mut index = BruteForceIndex {
+            coords: vec![],
+            items: vec![],
+        };
+        for (coords, item) in entries {
+            index.coords.extend(coords.iter().map(|c| c.to_num::<u8>()));
+            index.items.push(item);
+        }
+        index
+    }
+
+    fn distances<'a>(&'a self, query: &'a [u8]) -> impl Iterator<Item = (u32, usize)> + 'a {
+        self.coords
+            .chunks_exact(K)
+            .map(move |coords| manhattan(coords, query))
+            .zip(0..)
+    }
+
+    fn neighbour(&self, (distance, i): (u32, usize)) -> NearestNeighbour<SIZE, i32> {
+        NearestNeighbour {
+            distance: SIZE::from_num(distance),
+            item: self.items[i],
+        }
+    }
+}
+
+fn packed<const K: usize>(query: &[SIZE; K]) -> Vec<u8> {
+    query.iter().map(|c| c.to_num::<u8>()).collect()
+}
+
+impl<const K: usize> TileIndex<K> for BruteForceIndex<K> {
+    fn nearest_one(&self, query: &[SIZE; K]) -> NearestNeighbour<SIZE, i32> {
+        let query = packed(query);
+        self.distances(&query)
+            .min()
+            .map(|nearest| self.neighbour(nearest))
+            .unwrap_or(NearestNeighbour {
+                distance: SIZE::MAX,
+                item: 0,
+            })
+    }
+
+    fn nearest_n(&self, query: &[SIZE; K], n: usize) -> Vec<NearestNeighbour<SIZE, i32>> {
+        let query = packed(query);
+        let mut distances: Vec<_> = self.distances(&query).collect();
+        if n == 0 {
+            return vec![];
+        }
+        if n < distances.len() {
+            distances.select_nth_unstable(n - 1);
+            distances.truncate(n);
+        }
+        distances.sort_unstable();
+        distances.into_iter().map(|d| self.neighbour(d)).collect()
+    }
+
+    fn remove(&mut self, coords: &[SIZE; K], item: i32) -> usize {
+        let coords = packed(coords);
+        let mut removed = 0;
+        let mut i = 0;
+        while i < self.items.len() {
+            if self.items[i] == item && self.coords[i * K..(i + 1) * K] == coords[..] {
+                let last = self.items.len() - 1;
+                self.items.swap_remove(i);
+                if i != last {
+                    self.coords.copy_within(last * K..(last + 1) * K, i * K);
+                }
+                self.coords.truncate(last * K);
+                removed += 1;
+            } else {
+                i += 1;
+            }
+        }
+        removed
+    }
+
+    fn size(&self) -> usize {
+        self.items.len()
+    }
+}
+
+/// Coordinates of the compact kd-tree: color components only need 8 bits, but kiddo
+/// computes distances in the coordinate type, so 16 bits are needed to sum them.
+type CompactCoord = fixed::FixedU16<U0>;
+
+/// Exact kd-tree storing coordinates in 16 instead of 32 bits, halving the index memory.
+///
+/// Distances saturate at `u16::MAX`, which colors only reach past N=85 (255 * 3 * 86 >
+/// 65535): in the two finest modes, poorly matching candidates compare as equal.
+pub struct CompactKdTree<const K: usize>(KdTree<CompactCoord, i32, K, 640, u32>);
+
+impl<const K: usize> CompactKdTree<K> {
+    /// Build the index from tile coordinates and their signed tile indices.
+    pub fn build(entries: impl Iterator<Item = ([SIZE; K], i32)>) -> CompactKdTree<K> {
+        let mut kd = KdTree::new();
+        for (coords, item) in entries {
+            kd.add(&compact(&coords), item);
+        }
+        CompactKdTree(kd)
+    }
+}
+
+fn compact<const K: usize>(coords: &[SIZE; K]) -> [CompactCoord; K] {
+    coords.map(|c| CompactCoord::from_num(c.to_num::<u8>()))
+}
+
+fn widen(neighbour: NearestNeighbour<CompactCoord, i32>) -> NearestNeighbour<SIZE, i32> {
+    NearestNeighbour {
+        distance: SIZE::from_num(neighbour.distance.to_num::<u16>()),
+        item: neighbour.item,
+    }
+}
+
+impl<const K: usize> TileIndex<K> for CompactKdTree<K> {
+    fn nearest_one(&self, query: &[SIZE; K]) -> NearestNeighbour<SIZE, i32> {
+        widen(self.0.nearest_one::<Manhattan>(&compact(query)))
+    }
+
+    fn nearest_n(&self, query: &[SIZE; K], n: usize) -> Vec<NearestNeighbour<SIZE, i32>> {
+        self.0
+            .nearest_n::<Manhattan>(&compact(query), n)
+            .into_iter()
+            .map(widen)
+            .collect()
+    }
+
+    fn remove(&mut self, coords: &[SIZE; K], item: i32) -> usize {
+        self.0.remove(&compact(coords), item)
+    }
+
+    fn size(&self) -> usize {
+        self.0.size() as usize
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use kiddo::NearestNeighbour;
+    use rand::Rng;
+
+    fn random_coords<const K: usize>(rng: &mut impl Rng) -> [SIZE; K] {
+        let mut coords = [SIZE::from_num(0); K];
+        for c in coords.iter_mut() {
+            *c = SIZE::from_num(rng.gen_range(0u8, 255));
+        }
+        coords
+    }
+
+    #[test]
+    fn test_manhattan() {
+        let a: Vec<u8> = (0..40).collect();
+        let b: Vec<u8> = (0..40).map(|x| 255 - x).collect();
+        let expected: u32 = a
+            .iter()
+            .zip(&b)
+            .map(|(x, y)| u32::from(x.abs_diff(*y)))
+            .sum();
+        assert_eq!(manhattan(&a, &b), expected);
+        assert_eq!(manhattan(&a, &a), 0);
+    }
+
+    #[test]
+    fn test_brute_force_matches_kdtree() {
+        let mut rng = rand::thread_rng();
+        let entries: Vec<([SIZE; 12], i32)> =
+            (1..=300).map(|i| (random_coords(&mut rng), i)).collect();
+        let brute = BruteForceIndex::build(entries.iter().copied());
+        let mut kdtree: KdTree<SIZE, i32, 12, 640, u32> = KdTree::new();
+        for (coords, item) in &entries {
+            kdtree.add(coords, *item);
+        }
+        for _ in 0..50 {
+            let query = random_coords(&mut rng);
+            let expected = kdtree.nearest_n::<Manhattan>(&query, 5);
+            let actual = brute.nearest_n(&query, 5);
+            let distances = |xs: &[NearestNeighbour<SIZE, i32>]| {
+                xs.iter().map(|x| x.distance).collect::<Vec<_>>()
+            };
+            assert_eq!(distances(&actual), distances(&expected));
+            assert_eq!(brute.nearest_one(&query).distance, expected[0].distance);
+        }
+    }
+
+    #[test]
+    fn test_compact_kdtree_matches_kdtree() {
+        let mut rng = rand::thread_rng();
+        let entries: Vec<([SIZE; 12], i32)> =
+            (1..=300).map(|i| (random_coords(&mut rng), i)).collect();
+        let mut compact = CompactKdTree::build(entries.iter().copied());
+        let mut kdtree: KdTree<SIZE, i32, 12, 640, u32> = KdTree::new();
+        for (coords, item) in &entries {
+            kdtree.add(coords, *item);
+        }
+        for _ in 0..50 {
+            let query = random_coords(&mut rng);
+            let expected = kdtree.nearest_n::<Manhattan>(&query, 5);
+            let actual = compact.nearest_n(&query, 5);
+            let distances = |xs: &[NearestNeighbour<SIZE, i32>]| {
+                xs.iter().map(|x| x.distance).collect::<Vec<_>>()
+            };
+            assert_eq!(distances(&actual), distances(&expected));
+            assert_eq!(compact.nearest_one(&query).distance, expected[0].distance);
+        }
+
+        let (coords, item) = entries[0];
+        assert_eq!(compact.remove(&coords, item), 1);
+        assert_eq!(compact.size(), 299);
+    }
+
+    #[test]
+    fn test_brute_force_remove() {
+        let a = [SIZE::from_num(10); 3];
+        let b = [SIZE::from_num(20); 3];
+        let mut brute = BruteForceIndex::build(vec![(a, 1), (b, 2), (b, -2)].into_iter());
+        assert_eq!(brute.remove(&b, 2), 1);
+        assert_eq!(brute.size(), 2);
+        assert_eq!(brute.nearest_one(&b).item, -2);
+        assert_eq!(brute.remove(&b, 2), 0);
+        assert_eq!(brute.remove(&b, -2), 1);
+        assert_eq!(brute.remove(&a, 1), 1);
+        assert_eq!(brute.nearest_one(&a).item, 0);
+        assert!(brute.nearest_n(&a, 3).is_empty());
+    }
 
     #[test]
     fn test_compare_matches() {
         let match_a = vec![NearestNeighbour { distance: 10, item: 1 }];
         let match_b = vec![NearestNeighbour { distance: 20, item: 2 }];
-        
+
         let ordering = compare_matches(&match_a, &match_b);
         assert_eq!(ordering, std::cmp::Ordering::Greater);
     }
-}
\ No newline at end of file
+}
@@ -1,24 +1,99 @@
-use ::image::{Rgb, RgbImage};
+use ::image::imageops::{self, FilterType};
+use ::image::{GrayImage, Luma, Rgb, RgbImage};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
 use super::color::average_color;
 
 /// Abstract an image into an sqrt(N)*sqrt(N) grid of average colors
+///
+/// Cell boundaries need not fall on pixel boundaries: pixels straddling two cells count
+/// towards each in proportion to their overlap, so no pixel is dropped or aliased when the
+/// image dimensions are not multiples of sqrt(N).
 pub fn analyse<const N: usize>(img: RgbImage) -> [Rgb<u8>; N] {
-    let dim = (N as f64).sqrt();
-    let dim_width = (f64::from(img.width()) / dim).floor() as u32;
-    let dim_height = (f64::from(img.height()) / dim).floor() as u32;
+    let dim = (N as f64).sqrt() as u64;
+    let (width, height) = (u64::from(img.width()), u64::from(img.height()));
+
+    // Measure the image in units of 1/dim pixels so the overlaps are integral: a pixel
+    // spans `dim` units and a cell spans `width` units across and `height` units down.
+    let spans = |cell: u64, size: u64| {
+        let (start, end) = (cell * size, (cell + 1) * size);
+        (start / dim..end.div_ceil(dim)).map(move |pixel| {
+            let overlap = ((pixel + 1) * dim).min(end) - (pixel * dim).max(start);
+            (pixel as u32, overlap)
+        })
+    };
+    let area = width * height;
 
     let mut colors = [Rgb([0u8, 0, 0]); N];
-    for i in 0..N {
-        let top = (i / dim as usize) as u32;
-        let left = (i % dim as usize) as u32;
-        let rect = (left * dim_width, top * dim_height, dim_width, dim_height);
-        let color = average_color(&img, rect);
-        colors[i] = color;
+    for (i, color) in colors.iter_mut().enumerate() {
+        let (top, left) = (i as u64 / dim, i as u64 % dim);
+        let mut sums = [0u64; 3];
+        for (y, y_weight) in spans(top, height) {
+            for (x, x_weight) in spans(left, width) {
+                let pixel = img.get_pixel(x, y);
+                for (sum, channel) in sums.iter_mut().zip(pixel.0) {
+                    *sum += x_weight * y_weight * u64::from(channel);
+                }
+            }
+        }
+        *color = Rgb(sums.map(|sum| (sum / area) as u8));
     }
 
     colors
 }
 
+/// Average color of every `step`x`step` block of an image, row by row.
+pub fn block_colors(img: &RgbImage, step: u32) -> Vec<Rgb<u8>> {
+    let mut colors = Vec::new();
+    for top in (0..img.height() / step * step).step_by(step as usize) {
+        for left in (0..img.width() / step * step).step_by(step as usize) {
+            colors.push(average_color(img, (left, top, step, step)));
+        }
+    }
+    colors
+}
+
+/// Derive a coarser sqrt(N)*sqrt(N) analysis from a finer square grid of colors.
+///
+/// Each coarse cell averages the fine cells it covers, weighted by their overlap, so any
+/// finer grid can be used and not only multiples of sqrt(N).
+pub fn downsample<const N: usize>(colors: &[Rgb<u8>]) -> [Rgb<u8>; N] {
+    let from_dim = (colors.len() as f64).sqrt() as usize;
+    let to_dim = (N as f64).sqrt() as usize;
+    assert_eq!(
+        from_dim * from_dim,
+        colors.len(),
+        "analysis grid must be square"
+    );
+    assert!(from_dim >= to_dim, "cannot upsample an analysis");
+
+    // Measure both grids in units of 1/(from_dim*to_dim) so the overlaps are integral:
+    // a fine cell spans `to_dim` units and a coarse cell spans `from_dim` units.
+    let overlap = |fine: usize, coarse: usize| {
+        let start = (fine * to_dim).max(coarse * from_dim);
+        let end = ((fine + 1) * to_dim).min((coarse + 1) * from_dim);
+        end.saturating_sub(start) as u64
+    };
+    let area = (from_dim * from_dim) as u64;
+
+    let mut result = [Rgb([0u8, 0, 0]); N];
+    for (i, color) in result.iter_mut().enumerate() {
+        let (top, left) = (i / to_dim, i % to_dim);
+        let mut sums = [0u64; 3];
+        for (j, fine) in colors.iter().enumerate() {
+            let weight = overlap(j % from_dim, left) * overlap(j / from_dim, top);
+            if weight == 0 {
+                continue;
+            }
+            for (sum, channel) in sums.iter_mut().zip(fine.0) {
+                *sum += weight * u64::from(channel);
+            }
+        }
+        *color = Rgb(sums.map(|sum| (sum / area) as u8));
+    }
+    result
+}
+
 /// Extract colors from a specific region of an image for tile matching
 pub fn get_img_colors<const N: usize>(
     x: u32,
@@ -27,14 +102,136 @@ pub fn get_img_colors<const N: usize>(
     source_img: &::image::ImageBuffer<Rgb<u8>, Vec<u8>>,
 ) -> [Rgb<u8>; N] {
     let mut colors = [Rgb([0, 0, 0]); N];
-    for i in 0..N {
+    for (i, color) in colors.iter_mut().enumerate() {
         let x = x + (i as u32 % step);
         let y = y + (i as u32 / step);
-        colors[i] = *source_img.get_pixel(x, y)
+        *color = *source_img.get_pixel(x, y)
     }
     colors
 }
 
+/// Colors of every sqrt(N)*sqrt(N) block of the source image, row by row.
+///
+/// The blocks are analysed in a single parallel pass up front, so the renderers can look
+/// them up by cell instead of sampling the source image again for every match.
+pub fn source_blocks<const N: usize>(source_img: &RgbImage) -> Vec<[Rgb<u8>; N]> {
+    let step = (N as f64).sqrt() as u32;
+    let htiles = source_img.width() / step;
+    let vtiles = source_img.height() / step;
+    (0..htiles * vtiles)
+        .into_par_iter()
+        .map(|cell| get_img_colors(cell % htiles * step, cell / htiles * step, step, source_img))
+        .collect()
+}
+
+/// Variance of the colors of every block of `blocks`, a grid `htiles` blocks wide, and of its
+/// neighbours, averaged over the RGB channels: low in flat regions such as skies and walls,
+/// and high in detailed ones such as faces and edges. Including the neighbours measures the
+/// detail around the cells of mode 1, whose blocks are a single pixel.
+pub fn block_variances<const N: usize>(blocks: &[[Rgb<u8>; N]], htiles: usize) -> Vec<f64> {
+    let vtiles = blocks.len() / htiles.max(1);
+    (0..blocks.len())
+        .into_par_iter()
+        .map(|cell| {
+            let (col, row) = (cell % htiles, cell / htiles);
+            let (mut sums, mut squares, mut count) = ([0.0; 3], [0.0; 3], 0.0);
+            for y in row.saturating_sub(1)..(row + 2).min(vtiles) {
+                for x in col.saturating_sub(1)..(col + 2).min(htiles) {
+                    for color in &blocks[y * htiles + x] {
+                        for c in 0..3 {
+                            let value = f64::from(color[c]);
+                            sums[c] += value;
+                            squares[c] += value * value;
+                        }
+                        count += 1.0;
+                    }
+                }
+            }
+            (0..3)
+                .map(|c| squares[c] / count - (sums[c] / count).powi(2))
+                .sum::<f64>()
+                / 3.0
+        })
+        .collect()
+}
+
+/// Pixels across every sub-cell at which edges are measured, so tiles and source blocks are
+/// compared at the same scale whatever their resolution.
+pub const EDGE_SCALE: u32 = 4;
+
+/// Sobel gradient magnitude of the luma of an image, scaled to fit a byte.
+pub fn edge_magnitudes(img: &RgbImage) -> GrayImage {
+    let luma = imageops::grayscale(img);
+    let (width, height) = (i64::from(luma.width()), i64::from(luma.height()));
+    // Pixels outside the image repeat the nearest border pixel
+    let at = |x: i64, y: i64| {
+        let (x, y) = (x.clamp(0, width - 1), y.clamp(0, height - 1));
+        i32::from(luma.get_pixel(x as u32, y as u32).0[0])
+    };
+    GrayImage::from_fn(luma.width(), luma.height(), |x, y| {
+        let (x, y) = (i64::from(x), i64::from(y));
+        let gx = at(x + 1, y - 1) + 2 * at(x + 1, y) + at(x + 1, y + 1)
+            - at(x - 1, y - 1)
+            - 2 * at(x - 1, y)
+            - at(x - 1, y + 1);
+        let gy = at(x - 1, y + 1) + 2 * at(x, y + 1) + at(x + 1, y + 1)
+            - at(x - 1, y - 1)
+            - 2 * at(x, y - 1)
+            - at(x + 1, y - 1);
+        // Each gradient is at most 4*255, so their sum divided by 8 fits a byte
+        Luma([((gx.abs() + gy.abs()) / 8) as u8])
+    })
+}
+
+/// Abstract the edges of an image into an sqrt(N)*sqrt(N) grid of average Sobel magnitudes,
+/// measured on the image scaled to `EDGE_SCALE` pixels across every cell.
+pub fn analyse_edges<const N: usize>(img: &RgbImage) -> [u8; N] {
+    let size = (N as f64).sqrt() as u32 * EDGE_SCALE;
+    let img = imageops::resize(img, size, size, FilterType::Triangle);
+    get_img_edges(0, 0, &edge_magnitudes(&img))
+}
+
+/// Extract the edges of the block at (`x`, `y`) of an edge map with `EDGE_SCALE` pixels
+/// across every sub-cell, for tile matching
+pub fn get_img_edges<const N: usize>(x: u32, y: u32, edges: &GrayImage) -> [u8; N] {
+    let dim = (N as f64).sqrt() as u32;
+    let mut result = [0; N];
+    for (i, edge) in result.iter_mut().enumerate() {
+        let left = x + i as u32 % dim * EDGE_SCALE;
+        let top = y + i as u32 / dim * EDGE_SCALE;
+        let mut sum = 0u32;
+        for y in top..top + EDGE_SCALE {
+            for x in left..left + EDGE_SCALE {
+                sum += u32::from(edges.get_pixel(x, y).0[0]);
+            }
+        }
+        *edge = (sum / (EDGE_SCALE * EDGE_SCALE)) as u8;
+    }
+    result
+}
+
+/// Edges of every sqrt(N)*sqrt(N) block of a `htiles`x`vtiles` grid over the source image,
+/// row by row, measured on the original image rather than its block-sized resize so the
+/// contours within each sub-cell are kept.
+pub fn source_edge_blocks<const N: usize>(
+    original_img: &RgbImage,
+    htiles: u32,
+    vtiles: u32,
+) -> Vec<[u8; N]> {
+    let scale = (N as f64).sqrt() as u32 * EDGE_SCALE;
+    let img = imageops::resize(
+        original_img,
+        htiles * scale,
+        vtiles * scale,
+        FilterType::Triangle,
+    );
+    let edges = edge_magnitudes(&img);
+    (0..htiles * vtiles)
+        .into_par_iter()
+        .map(|cell| get_img_edges(cell % htiles * scale, cell / htiles * scale, &edges))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -54,6 +251,57 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_analyse_fractional_cells() {
+        // The middle column of a 3x2 image is split evenly between the two columns of cells
+        let img = RgbImage::from_fn(3, 2, |x, _| Rgb([[0, 200, 100][x as usize], 0, 0]));
+        let (left, right) = (Rgb([66, 0, 0]), Rgb([133, 0, 0]));
+        assert_eq!(analyse::<4>(img), [left, right, left, right]);
+
+        // Images smaller than the grid are sampled without losing any pixel
+        let img = RgbImage::from_pixel(1, 1, Rgb([10, 20, 30]));
+        assert_eq!(analyse::<9>(img), [Rgb([10, 20, 30]); 9]);
+    }
+
+    #[test]
+    fn test_block_colors() {
+        let img = RgbImage::from_fn(4, 5, |x, y| Rgb([(x * 10) as u8, (y * 10) as u8, 0]));
+        assert_eq!(
+            block_colors(&img, 2),
+            [
+                Rgb([5, 5, 0]),
+                Rgb([25, 5, 0]),
+                Rgb([5, 25, 0]),
+                Rgb([25, 25, 0])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_downsample_identity() {
+        let colors: Vec<_> = (0..9).map(|i| Rgb([i * 10, i * 20, 255 - i])).collect();
+        assert_eq!(downsample::<9>(&colors).to_vec(), colors);
+    }
+
+    #[test]
+    fn test_downsample_matches_analyse() {
+        let mut img = RgbImage::new(4, 4);
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            *pixel = Rgb([(x * 60) as u8, (y * 60) as u8, ((x + y) * 30) as u8]);
+        }
+        let fine = analyse::<16>(img.clone());
+        assert_eq!(downsample::<4>(&fine), analyse::<4>(img.clone()));
+        assert_eq!(downsample::<1>(&fine), analyse::<1>(img));
+    }
+
+    #[test]
+    fn test_downsample_uneven_grid() {
+        // The centre cell of a 3x3 grid is split evenly between the four cells of a 2x2 grid
+        let mut colors = vec![Rgb([0, 0, 0]); 9];
+        colors[4] = Rgb([252, 252, 252]);
+        assert_eq!(downsample::<4>(&colors), [Rgb([28, 28, 28]); 4]);
+    }
+
     #[test]
     fn test_get_img_colors() {
         let mut img = RgbImage::new(4, 4);
@@ -70,4 +318,59 @@ mod tests {
         assert_eq!(colors[2], Rgb([0, 64, 128]));   // (0,1)
         assert_eq!(colors[3], Rgb([64, 64, 128]));  // (1,1)
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_source_blocks() {
+        let img = RgbImage::from_fn(5, 4, |x, y| Rgb([x as u8 * 50, y as u8 * 60, 7]));
+        let blocks = source_blocks::<4>(&img);
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(blocks[1], get_img_colors::<4>(2, 0, 2, &img));
+        assert_eq!(blocks[2], get_img_colors::<4>(0, 2, 2, &img));
+    }
+
+    #[test]
+    fn test_block_variances() {
+        // A flat left half and a striped right half
+        let img = RgbImage::from_fn(8, 2, |x, y| match (x < 4, (x + y) % 2) {
+            (true, _) => Rgb([90, 90, 90]),
+            (false, 0) => Rgb([0, 0, 0]),
+            (false, _) => Rgb([200, 200, 200]),
+        });
+        let variances = block_variances(&source_blocks::<1>(&img), 8);
+        assert_eq!(variances.len(), 16);
+        assert_eq!(variances[0], 0.0);
+        assert_eq!(variances[10], 0.0);
+        assert!(variances[3] > 0.0, "the neighbours count");
+        assert_eq!(variances[6], 10000.0);
+        // Blocks of several pixels measure the detail within them
+        let variances = block_variances(&source_blocks::<4>(&img), 4);
+        assert_eq!(variances[0], 0.0);
+        assert_eq!(variances[3], 10000.0);
+    }
+
+    #[test]
+    fn test_analyse_edges() {
+        // Flat images have no edges
+        let img = RgbImage::from_pixel(10, 10, Rgb([90, 120, 30]));
+        assert_eq!(analyse_edges::<4>(&img), [0; 4]);
+
+        // A vertical contour down the middle shows in the inner sub-cells only
+        let img = RgbImage::from_fn(16, 16, |x, _| Rgb([[0, 255][(x >= 8) as usize]; 3]));
+        let edges = analyse_edges::<16>(&img);
+        for row in edges.chunks_exact(4) {
+            assert_eq!(row[0], 0);
+            assert!(row[1] > 0);
+            assert_eq!(row[1], row[2]);
+            assert_eq!(row[3], 0);
+        }
+    }
+
+    #[test]
+    fn test_source_edge_blocks() {
+        let img = RgbImage::from_fn(40, 20, |x, y| Rgb([(x * 6) as u8, (y * 12) as u8, 0]));
+        let blocks = source_edge_blocks::<4>(&img, 2, 1);
+        assert_eq!(blocks.len(), 2);
+        let edges = edge_magnitudes(&imageops::resize(&img, 16, 8, FilterType::Triangle));
+        assert_eq!(blocks[1], get_img_edges::<4>(8, 0, &edges));
+    }
+}
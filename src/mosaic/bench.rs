@@ -0,0 +1,132 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use super::error::EmosaicError;
+use super::stats::StatsSummary;
+
+/// The outcome of matching the tiles in one configuration of the bench subcommand.
+#[derive(Debug)]
+pub struct BenchRow {
+    /// Number of blocks per side of the tiles of the mode
+    pub dim: u32,
+    /// Options of the mosaic subcommand, empty for the defaults
+    pub configuration: String,
+    /// The statistics of the matches and the time taken, or why they failed
+    pub outcome: Result<(StatsSummary, Duration), EmosaicError>,
+}
+
+impl BenchRow {
+    /// Average distance from the cells to their tiles per block, so that modes can be compared.
+    pub fn distance_per_block(&self) -> Option<f64> {
+        let (summary, _) = self.outcome.as_ref().ok()?;
+        Some(summary.average_distance / f64::from(self.dim * self.dim))
+    }
+
+    fn configuration(&self) -> &str {
+        match self.configuration.as_str() {
+            "" => "(defaults)",
+            configuration => configuration,
+        }
+    }
+}
+
+/// The outcome of every configuration of the bench subcommand as a table, one line per row
+/// after the header, with the distances per pixel so that modes can be compared. Failed
+/// configurations give the first line of their error instead.
+pub fn table(rows: &[BenchRow]) -> String {
+    let width = rows
+        .iter()
+        .map(|row| row.configuration().len())
+        .chain([7])
+        .max()
+        .unwrap_or(0);
+    let mut table = format!(
+        "{:>4}  {:<width$}  {:>13}  {:>12}  {:>9}\n",
+        "MODE",
+        "OPTIONS",
+        "DISTANCE/PX",
+        "UNIQUE TILES",
+        "TIME",
+        width = width
+    );
+    for row in rows {
+        let (summary, elapsed) = match &row.outcome {
+            Ok(outcome) => outcome,
+            Err(error) => {
+                let error = error.to_string();
+                let reason = error.lines().next().unwrap_or_default();
+                let configuration = row.configuration();
+                let _ = writeln!(
+                    table,
+                    "{:>4}  {:<width$}  {}",
+                    row.dim, configuration, reason
+                );
+                continue;
+            }
+        };
+        let unique = format!("{}/{}", summary.unique_tiles, summary.cells);
+        let _ = writeln!(
+            table,
+            "{:>4}  {:<width$}  {:>13.1}  {:>12}  {:>8.2}s",
+            row.dim,
+            row.configuration(),
+            row.distance_per_block().unwrap_or_default(),
+            unique,
+            elapsed.as_secs_f64(),
+            width = width
+        );
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(dim: u32, configuration: &str, average_distance: f64) -> BenchRow {
+        let summary = StatsSummary {
+            cells: 100,
+            unique_tiles: 40,
+            fillers: 0,
+            average_distance,
+        };
+        BenchRow {
+            dim,
+            configuration: configuration.to_string(),
+            outcome: Ok((summary, Duration::from_millis(1500))),
+        }
+    }
+
+    #[test]
+    fn test_table() {
+        let rows = [
+            row(1, "", 30.0),
+            row(2, "", 120.0),
+            row(2, "--no-repeat --greedy", 200.0),
+            BenchRow {
+                dim: 4,
+                configuration: String::from("--no-repeat"),
+                outcome: Err(EmosaicError::Render(String::from(
+                    "❌ Too few tiles\n💡 Add some",
+                ))),
+            },
+        ];
+        // Distances are compared per block across modes
+        assert_eq!(rows[1].distance_per_block(), Some(30.0));
+        assert_eq!(rows[2].distance_per_block(), Some(50.0));
+        assert_eq!(rows[3].distance_per_block(), None);
+
+        let table = table(&rows);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "MODE  OPTIONS                 DISTANCE/PX  UNIQUE TILES       TIME",
+                "   1  (defaults)                     30.0        40/100      1.50s",
+                "   2  (defaults)                     30.0        40/100      1.50s",
+                "   2  --no-repeat --greedy           50.0        40/100      1.50s",
+                "   4  --no-repeat           ❌ Too few tiles",
+            ]
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether Ctrl-C was pressed since [`install_handler`].
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    // A second Ctrl-C ends the process right away
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+    }
+}
+
+/// Catch Ctrl-C, so that the analysis, optimization and render loops stop at their next step
+/// and what they did so far is saved instead of thrown away. A second Ctrl-C ends the process
+/// right away. Only catches it on Unix.
+pub fn install_handler() {
+    #[cfg(unix)]
+    unsafe {
+        let handler: extern "C" fn(libc::c_int) = on_interrupt;
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+/// Whether Ctrl-C was pressed, in which case long running loops should stop.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Where the partial result of an interrupted run is saved instead of `path`: with `.partial`
+/// before its extension, so that it is still opened by the same programs.
+pub fn partial_path(path: &Path) -> PathBuf {
+    match path.extension() {
+        Some(extension) => {
+            let mut partial = OsString::from("partial.");
+            partial.push(extension);
+            path.with_extension(partial)
+        }
+        None => path.with_extension("partial"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_path() {
+        assert_eq!(
+            partial_path(Path::new("out/mosaic.png")),
+            Path::new("out/mosaic.partial.png")
+        );
+        assert_eq!(
+            partial_path(Path::new("placements.json")),
+            Path::new("placements.partial.json")
+        );
+        assert_eq!(
+            partial_path(Path::new("mosaic")),
+            Path::new("mosaic.partial")
+        );
+    }
+}
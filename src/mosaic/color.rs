@@ -41,6 +41,60 @@ pub fn average_color(img: &RgbImage, rect: (u32, u32, u32, u32)) -> Rgb<u8> {
     Rgb([r, g, b])
 }
 
+/// Shift the colors of each cell of a square grid over `img` from `from` to `to`, the
+/// average colors of the cells row by row, e.g. to bring a poorly matching tile closer to
+/// the colors of the block it replaces. With `flipped`, `from` are the colors of the image
+/// before it was mirrored horizontally.
+pub fn correct_colors(img: &mut RgbImage, from: &[Rgb<u8>], to: &[Rgb<u8>], flipped: bool) {
+    let dim = (from.len() as f64).sqrt() as u32;
+    let cell_width = (img.width() / dim).max(1);
+    let cell_height = (img.height() / dim).max(1);
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        let col = (x / cell_width).min(dim - 1);
+        let row = (y / cell_height).min(dim - 1);
+        let from_col = if flipped { dim - 1 - col } else { col };
+        let from = from[(row * dim + from_col) as usize];
+        let to = to[(row * dim + col) as usize];
+        for c in 0..3 {
+            let shifted = i16::from(pixel[c]) + i16::from(to[c]) - i16::from(from[c]);
+            pixel[c] = shifted.clamp(0, 255) as u8;
+        }
+    }
+}
+
+/// CIELAB coordinates of an sRGB color under the D65 illuminant: lightness from 0 to 100,
+/// then the green-red and blue-yellow axes.
+pub fn to_lab(color: Rgb<u8>) -> [f64; 3] {
+    // Linear RGB, then CIEXYZ relative to the white point
+    let [r, g, b] = color.0.map(|c| {
+        let c = f64::from(c) / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    });
+    let x = (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / 0.95047;
+    let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+    let z = (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / 1.08883;
+    let f = |t: f64| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// Perceived difference between two colors, as the CIE76 delta-E: the distance between
+/// their CIELAB coordinates, where about 2.3 is just noticeable.
+pub fn delta_e(a: Rgb<u8>, b: Rgb<u8>) -> f64 {
+    let (a, b) = (to_lab(a), to_lab(b));
+    (0..3).map(|i| (a[i] - b[i]).powi(2)).sum::<f64>().sqrt()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -98,4 +152,45 @@ mod tests {
         let img = RgbImage::new(5, 5);
         average_color(&img, (0, 3, 2, 5)); // top=3 + height=5 = 8 > img.height=5
     }
+
+    #[test]
+    fn test_correct_colors() {
+        // 2x2 cells of 2x2 pixels, the top left cell dark red and the others gray
+        let mut img = RgbImage::from_fn(4, 4, |x, y| {
+            if x < 2 && y < 2 {
+                Rgb([100, 0, 0])
+            } else {
+                Rgb([128, 128, 128])
+            }
+        });
+        let gray = Rgb([128, 128, 128]);
+        let from = [Rgb([100, 0, 0]), gray, gray, gray];
+        let to = [Rgb([150, 10, 0]), Rgb([28, 228, 255]), gray, gray];
+        correct_colors(&mut img, &from, &to, false);
+        assert_eq!(*img.get_pixel(1, 1), Rgb([150, 10, 0]));
+        assert_eq!(*img.get_pixel(3, 0), Rgb([28, 228, 255]));
+        assert_eq!(*img.get_pixel(2, 3), gray);
+
+        // The colors of a flipped image are mirrored
+        let gray = Rgb([50, 50, 50]);
+        let mut img = RgbImage::from_pixel(2, 2, gray);
+        let from = [Rgb([0, 0, 0]), gray, gray, gray];
+        correct_colors(&mut img, &from, &[Rgb([60, 60, 60]); 4], true);
+        assert_eq!(*img.get_pixel(0, 0), Rgb([60, 60, 60]));
+        assert_eq!(*img.get_pixel(1, 0), Rgb([110, 110, 110]));
+    }
+
+    #[test]
+    fn test_delta_e() {
+        let lab = to_lab(Rgb([255, 255, 255]));
+        assert!((lab[0] - 100.0).abs() < 0.01 && lab[1].abs() < 0.01 && lab[2].abs() < 0.01);
+        assert_eq!(to_lab(Rgb([0, 0, 0])), [0.0, 0.0, 0.0]);
+        let red = to_lab(Rgb([255, 0, 0]));
+        assert!((red[0] - 53.24).abs() < 0.05 && (red[1] - 80.09).abs() < 0.05);
+
+        assert_eq!(delta_e(Rgb([10, 20, 30]), Rgb([10, 20, 30])), 0.0);
+        assert!((delta_e(Rgb([0, 0, 0]), Rgb([255, 255, 255])) - 100.0).abs() < 0.01);
+        let (a, b) = (Rgb([200, 30, 90]), Rgb([40, 120, 200]));
+        assert_eq!(delta_e(a, b), delta_e(b, a));
+    }
 }
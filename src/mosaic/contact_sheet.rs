@@ -0,0 +1,218 @@
+use ::image::{imageops, Rgb, RgbImage};
+
+use super::font;
+use super::rendering::LoadFailures;
+use super::tiles::{Tile, TileSet};
+use super::timestamp::DateFormat;
+use super::wall::render_wall;
+
+/// Bits per channel of the Hilbert curve through the RGB cube.
+const HILBERT_BITS: u32 = 8;
+
+/// Color of the bands the captions are written on.
+const CAPTION_BACKGROUND: Rgb<u8> = Rgb([24, 24, 24]);
+/// Color of the captions.
+const CAPTION_COLOR: Rgb<u8> = Rgb([220, 220, 220]);
+
+/// What is written under each tile of a contact sheet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Caption {
+    /// The file name, without its extension
+    Name,
+    /// The day the picture was taken, when known
+    Date,
+}
+
+/// Position of the point `x`, with `bits` bits per coordinate, along a Hilbert curve through
+/// the cube, so that consecutive positions are neighbouring points. This is Skilling's
+/// transform of the coordinates into the transposed index, whose bits are then interleaved.
+fn hilbert_index(mut x: [u32; 3], bits: u32) -> u32 {
+    let top = 1 << (bits - 1);
+    let mut q = top;
+    while q > 1 {
+        let p = q - 1;
+        for i in 0..3 {
+            if x[i] & q != 0 {
+                x[0] ^= p;
+            } else {
+                let t = (x[0] ^ x[i]) & p;
+                x[0] ^= t;
+                x[i] ^= t;
+            }
+        }
+        q >>= 1;
+    }
+    x[1] ^= x[0];
+    x[2] ^= x[1];
+    let mut t = 0;
+    let mut q = top;
+    while q > 1 {
+        if x[2] & q != 0 {
+            t ^= q - 1;
+        }
+        q >>= 1;
+    }
+    let mut index = 0;
+    for bit in (0..bits).rev() {
+        for coordinate in x {
+            index = index << 1 | ((coordinate ^ t) >> bit & 1);
+        }
+    }
+    index
+}
+
+/// The indices of the tiles of `tile_set` along a Hilbert curve through the RGB cube, so that
+/// the average colors change smoothly from one tile to the next.
+pub fn arrange(tile_set: &TileSet<[Rgb<u8>; 1]>) -> Vec<u32> {
+    let mut tiles: Vec<&Tile<[Rgb<u8>; 1]>> = tile_set.tiles.iter().collect();
+    tiles.sort_by_cached_key(|tile| hilbert_index(tile.colors[0].0.map(u32::from), HILBERT_BITS));
+    tiles.into_iter().map(|tile| tile.idx).collect()
+}
+
+/// Size of the pixels of the captions under tiles of `tile_size` pixels.
+fn caption_scale(tile_size: u32) -> u32 {
+    (tile_size / 128).max(1)
+}
+
+/// Height of the band under every row of tiles of `tile_size` pixels holding their captions.
+pub fn caption_height(tile_size: u32) -> u32 {
+    (font::GLYPH_HEIGHT + 4) * caption_scale(tile_size)
+}
+
+/// The `caption` of `tile`, keeping only as many of its last characters as fit in `width`
+/// pixels at `scale`, since the end of numbered file names tells them apart.
+fn caption_text(
+    tile_set: &TileSet<[Rgb<u8>; 1]>,
+    tile: &Tile<[Rgb<u8>; 1]>,
+    caption: Caption,
+    width: u32,
+    scale: u32,
+) -> String {
+    let text = match caption {
+        Caption::Name => tile_set
+            .get_path(tile)
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+        Caption::Date => tile
+            .taken()
+            .map(|taken| taken.format(DateFormat::Date))
+            .unwrap_or_default(),
+    };
+    let fits = ((width / scale + 1) / font::ADVANCE) as usize;
+    let skip = text.chars().count().saturating_sub(fits);
+    text.chars().skip(skip).collect()
+}
+
+/// Renders the tiles `arranged` row by row on a grid of `columns` columns, as on a wall, with
+/// the `caption` of every tile, if any, centered on a band under its row.
+///
+/// # Returns
+/// The rendered contact sheet, with a solid block of the average color of the tile wherever
+/// its image failed to load, and the cells and errors of the failures in no particular order
+pub fn render_contact_sheet(
+    tile_set: &TileSet<[Rgb<u8>; 1]>,
+    arranged: &[u32],
+    columns: u32,
+    tile_size: u32,
+    caption: Option<Caption>,
+) -> (RgbImage, LoadFailures) {
+    let (wall, failures) = render_wall(tile_set, arranged, columns, tile_size, None);
+    let Some(caption) = caption else {
+        return (wall, failures);
+    };
+    let (scale, band) = (caption_scale(tile_size), caption_height(tile_size));
+    let rows = wall.height() / tile_size;
+    let mut sheet =
+        RgbImage::from_pixel(wall.width(), rows * (tile_size + band), CAPTION_BACKGROUND);
+    for row in 0..rows {
+        let strip = imageops::crop_imm(&wall, 0, row * tile_size, wall.width(), tile_size);
+        imageops::replace(
+            &mut sheet,
+            &strip.to_image(),
+            0,
+            i64::from(row * (tile_size + band)),
+        );
+    }
+    for (i, &idx) in arranged.iter().enumerate() {
+        let Some(tile) = tile_set.get_tile(idx as i32) else {
+            continue;
+        };
+        let (col, row) = (i as u32 % columns, i as u32 / columns);
+        let text = caption_text(tile_set, &tile, caption, tile_size, scale);
+        let x = col * tile_size + (tile_size - font::text_width(&text, scale)) / 2;
+        let y = row * (tile_size + band) + tile_size + 2 * scale;
+        font::draw_text(&mut sheet, &text, x, y, scale, CAPTION_COLOR);
+    }
+    (sheet, failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_hilbert_index() {
+        let bits = 3;
+        let side = 1 << bits;
+        let mut points = vec![None; side * side * side];
+        for r in 0..side as u32 {
+            for g in 0..side as u32 {
+                for b in 0..side as u32 {
+                    let index = hilbert_index([r, g, b], bits) as usize;
+                    assert_eq!(points[index], None, "every point has its own index");
+                    points[index] = Some([r, g, b]);
+                }
+            }
+        }
+        // Consecutive points of the curve are neighbours
+        let points: Vec<[u32; 3]> = points.into_iter().map(Option::unwrap).collect();
+        for pair in points.windows(2) {
+            let steps: u32 = (0..3).map(|i| pair[0][i].abs_diff(pair[1][i])).sum();
+            assert_eq!(steps, 1, "{:?}", pair);
+        }
+    }
+
+    #[test]
+    fn test_render_contact_sheet() {
+        let dark = Rgb([10, 10, 10]);
+        let mut tile_set = TileSet::new();
+        for name in ["IMG_0001.png", "IMG_0002.png", "IMG_0003.png"] {
+            tile_set.push_tile_with_image(
+                PathBuf::from(name),
+                [dark],
+                RgbImage::from_pixel(64, 64, dark),
+            );
+        }
+        let arranged = arrange(&tile_set);
+        assert_eq!(arranged.len(), 3);
+
+        let (plain, failures) = render_contact_sheet(&tile_set, &arranged, 2, 64, None);
+        assert!(failures.is_empty());
+        assert_eq!(plain.dimensions(), (128, 128));
+
+        let (sheet, _) = render_contact_sheet(&tile_set, &arranged, 2, 64, Some(Caption::Name));
+        let band = caption_height(64);
+        assert_eq!(sheet.dimensions(), (128, 2 * (64 + band)));
+        assert_eq!(*sheet.get_pixel(0, 0), dark);
+        assert_eq!(*sheet.get_pixel(0, 64), CAPTION_BACKGROUND);
+        let written = |x0: u32, y0: u32| {
+            (x0..x0 + 64).any(|x| (y0..y0 + band).any(|y| *sheet.get_pixel(x, y) == CAPTION_COLOR))
+        };
+        assert!(written(0, 64) && written(64, 64) && written(0, 128 + band));
+        assert!(!written(64, 128 + band), "no caption without a tile");
+
+        let tile = tile_set.get_tile(1).unwrap();
+        assert_eq!(
+            caption_text(&tile_set, &tile, Caption::Name, 64, 1),
+            "IMG_0001"
+        );
+        assert_eq!(
+            caption_text(&tile_set, &tile, Caption::Name, 40, 1),
+            "G_0001"
+        );
+        assert_eq!(caption_text(&tile_set, &tile, Caption::Date, 64, 1), "");
+    }
+}
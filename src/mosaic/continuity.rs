@@ -0,0 +1,100 @@
+use ::image::Rgb;
+
+use super::tiles::Tile;
+
+/// Side of a cell on which a neighbouring tile was placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+impl Side {
+    pub const ALL: [Side; 4] = [Side::Left, Side::Right, Side::Top, Side::Bottom];
+
+    /// Column and row of the neighbour of the cell at `col` and `row` on this side, if it
+    /// lies within a grid of `htiles`x`vtiles` cells.
+    pub fn neighbour(
+        self,
+        (col, row): (u32, u32),
+        (htiles, vtiles): (u32, u32),
+    ) -> Option<(u32, u32)> {
+        match self {
+            Side::Left => col.checked_sub(1).map(|col| (col, row)),
+            Side::Right => (col + 1 < htiles).then_some((col + 1, row)),
+            Side::Top => row.checked_sub(1).map(|row| (col, row)),
+            Side::Bottom => (row + 1 < vtiles).then_some((col, row + 1)),
+        }
+    }
+}
+
+/// Color of the analysis grid of `tile` at column `x` and row `y`, as the tile is placed.
+fn grid_color<const N: usize>(
+    tile: &Tile<[Rgb<u8>; N]>,
+    dim: usize,
+    x: usize,
+    y: usize,
+) -> Rgb<u8> {
+    let x = if tile.flipped { dim - 1 - x } else { x };
+    tile.colors[y * dim + x]
+}
+
+/// How strongly `tile` clashes with `neighbour` placed on its `side`: the difference between
+/// the colors along the sides of their analysis grids that touch, summed over the RGB channels
+/// and averaged along the side.
+pub fn clash<const N: usize>(
+    tile: &Tile<[Rgb<u8>; N]>,
+    neighbour: &Tile<[Rgb<u8>; N]>,
+    side: Side,
+) -> f64 {
+    let dim = (N as f64).sqrt() as usize;
+    let last = dim - 1;
+    let distance: u32 = (0..dim)
+        .map(|i| {
+            let (own, other) = match side {
+                Side::Left => ((0, i), (last, i)),
+                Side::Right => ((last, i), (0, i)),
+                Side::Top => ((i, 0), (i, last)),
+                Side::Bottom => ((i, last), (i, 0)),
+            };
+            let a = grid_color(tile, dim, own.0, own.1);
+            let b = grid_color(neighbour, dim, other.0, other.1);
+            a.0.iter()
+                .zip(b.0)
+                .map(|(&a, b)| u32::from(a.abs_diff(b)))
+                .sum::<u32>()
+        })
+        .sum();
+    f64::from(distance) / dim as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clash() {
+        let (black, white) = (Rgb([0, 0, 0]), Rgb([100, 100, 100]));
+        // Black on the left, white on the right
+        let tile = Tile::new(1, [black, white, black, white]);
+        let same = Tile::new(2, [black, white, black, white]);
+        assert_eq!(clash(&tile, &same, Side::Right), 300.0);
+        assert_eq!(clash(&tile, &same, Side::Left), 300.0);
+        assert_eq!(clash(&tile, &same, Side::Top), 0.0);
+        // Flipped, the neighbour on the right shows its black side
+        let flipped = Tile {
+            flipped: true,
+            ..same
+        };
+        assert_eq!(clash(&tile, &flipped, Side::Right), 0.0);
+        assert_eq!(clash(&tile, &flipped, Side::Left), 0.0);
+        assert_eq!(clash(&tile, &flipped, Side::Bottom), 300.0);
+
+        assert_eq!(Side::Left.neighbour((0, 1), (2, 2)), None);
+        assert_eq!(Side::Right.neighbour((0, 1), (2, 2)), Some((1, 1)));
+        assert_eq!(Side::Top.neighbour((0, 1), (2, 2)), Some((0, 0)));
+        assert_eq!(Side::Bottom.neighbour((0, 1), (2, 2)), None);
+    }
+}
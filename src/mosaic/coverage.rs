@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+
+use ::image::{Rgb, RgbImage};
+use rayon::prelude::*;
+use tracing::info;
+
+use super::stats::DistanceScale;
+use super::tiles::{IndexKind, Tile, TileSet};
+use super::web::palette::Palette;
+
+/// Bits kept per channel when grouping the poorly covered cells by color.
+const COLOR_GROUP_BITS: u32 = 3;
+
+/// Number of poorly covered colors listed in the report.
+const POOR_COLORS_LISTED: usize = 5;
+
+/// Similar colors of the source image that no tile comes close to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoorColor {
+    /// Average color of the cells
+    pub color: Rgb<u8>,
+    /// Number of cells of these colors
+    pub cells: usize,
+    /// Average distance from the cells to their nearest tile
+    pub distance: f64,
+}
+
+/// How well the tiles of a tile set cover the colors of a source image, cell by cell of the
+/// mosaic grid, comparing the color of every cell with the average colors of the tiles.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coverage {
+    /// Number of columns and rows of the mosaic grid
+    pub grid: (u32, u32),
+    /// Color of every cell, in row major order
+    pub colors: Vec<Rgb<u8>>,
+    /// Distance from every cell to the nearest tile, summed over the RGB channels (0-765), in
+    /// row major order
+    pub distances: Vec<u32>,
+}
+
+impl Coverage {
+    /// Match every pixel of `cells`, one per cell of the mosaic grid, with the tile of
+    /// `tile_set` whose average color is nearest.
+    pub fn new(cells: &RgbImage, tile_set: &TileSet<[Rgb<u8>; 1]>) -> Coverage {
+        let index = tile_set.build_index(IndexKind::Auto);
+        let colors: Vec<Rgb<u8>> = cells.pixels().copied().collect();
+        let distances = colors
+            .par_iter()
+            .map(|&color| {
+                let coords = Tile::from_colors([color]).coords();
+                index.nearest_one(&coords).distance.to_num::<u32>()
+            })
+            .collect();
+        Coverage {
+            grid: cells.dimensions(),
+            colors,
+            distances,
+        }
+    }
+
+    /// Share of the cells at most `max_distance` from their nearest tile.
+    pub fn share_within(&self, max_distance: u32) -> f64 {
+        let within = self
+            .distances
+            .iter()
+            .filter(|&&distance| distance <= max_distance)
+            .count();
+        within as f64 / self.distances.len().max(1) as f64
+    }
+
+    /// The colors of the cells farther than `max_distance` from their nearest tile, grouped by
+    /// similar colors, from the most common to the least.
+    pub fn poor_colors(&self, max_distance: u32) -> Vec<PoorColor> {
+        let shift = 8 - COLOR_GROUP_BITS;
+        // The sums of the channels and distances of the cells of every group
+        let mut groups: HashMap<[u8; 3], ([u64; 3], u64, usize)> = HashMap::new();
+        for (color, &distance) in self.colors.iter().zip(&self.distances) {
+            if distance <= max_distance {
+                continue;
+            }
+            let (channels, distances, cells) =
+                groups.entry(color.0.map(|c| c >> shift)).or_default();
+            for (sum, &c) in channels.iter_mut().zip(&color.0) {
+                *sum += u64::from(c);
+            }
+            *distances += u64::from(distance);
+            *cells += 1;
+        }
+        let mut poor: Vec<PoorColor> = groups
+            .into_values()
+            .map(|(channels, distances, cells)| PoorColor {
+                color: Rgb(channels.map(|sum| (sum / cells as u64) as u8)),
+                cells,
+                distance: distances as f64 / cells as f64,
+            })
+            .collect();
+        poor.sort_by(|a, b| b.cells.cmp(&a.cells).then(a.color.0.cmp(&b.color.0)));
+        poor
+    }
+
+    /// Print how much of the source is covered by the tiles, and which colors match poorly.
+    pub fn summarise(&self, max_distance: u32) {
+        let cells = self.distances.len();
+        let good = self.share_within(max_distance / 2);
+        let within = self.share_within(max_distance);
+        info!(
+            "Coverage of {}x{} cells (distances per pixel, summed over the RGB channels):",
+            self.grid.0, self.grid.1
+        );
+        info!(
+            "  ✅ {:.1}% within {} of a tile",
+            100.0 * good,
+            max_distance / 2
+        );
+        info!(
+            "  ⚠️  {:.1}% between {} and {}",
+            100.0 * (within - good),
+            max_distance / 2,
+            max_distance
+        );
+        info!(
+            "  ❌ {:.1}% farther than {}",
+            100.0 * (1.0 - within),
+            max_distance
+        );
+        let poor = self.poor_colors(max_distance);
+        if poor.is_empty() {
+            info!("Every cell has a tile within {}", max_distance);
+            return;
+        }
+        info!("Colors matching poorly:");
+        for color in poor.iter().take(POOR_COLORS_LISTED) {
+            let Rgb([r, g, b]) = color.color;
+            info!(
+                "  #{:02x}{:02x}{:02x}: {:.1}% of the cells, {:.0} from the nearest tile on average",
+                r,
+                g,
+                b,
+                100.0 * color.cells as f64 / cells as f64,
+                color.distance
+            );
+        }
+        if poor.len() > POOR_COLORS_LISTED {
+            info!("  ... and {} more", poor.len() - POOR_COLORS_LISTED);
+        }
+        info!("💡 Add pictures in these colors to the tiles, or their regions will stand out");
+    }
+
+    /// Render every cell as a square of `cell_size` pixels, colored with `palette` by its
+    /// distance to the nearest tile on the absolute scale of the distance heatmap of mosaics.
+    pub fn render(&self, cell_size: u32, palette: Palette) -> RgbImage {
+        let (cols, _) = self.grid;
+        RgbImage::from_fn(self.grid.0 * cell_size, self.grid.1 * cell_size, |x, y| {
+            let cell = ((y / cell_size) * cols + x / cell_size) as usize;
+            let t = f64::from(self.distances[cell]) / DistanceScale::WORST_PER_PIXEL;
+            Rgb(palette.color(t))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_coverage() {
+        let (black, white, red) = (Rgb([0, 0, 0]), Rgb([250, 250, 250]), Rgb([250, 0, 0]));
+        let mut tile_set = TileSet::new();
+        tile_set.push_tile(PathBuf::from("black.jpg"), [black]);
+        tile_set.push_tile(PathBuf::from("white.jpg"), [white]);
+        let cells = RgbImage::from_fn(4, 2, |x, _| match x {
+            0 => black,
+            1 => Rgb([10, 10, 10]),
+            2 => white,
+            _ => red,
+        });
+
+        let coverage = Coverage::new(&cells, &tile_set);
+        assert_eq!(coverage.grid, (4, 2));
+        assert_eq!(coverage.distances, [0, 30, 0, 250, 0, 30, 0, 250]);
+        assert_eq!(coverage.share_within(0), 0.5);
+        assert_eq!(coverage.share_within(75), 0.75);
+        assert_eq!(
+            coverage.poor_colors(75),
+            [PoorColor {
+                color: red,
+                cells: 2,
+                distance: 250.0
+            }]
+        );
+        assert!(coverage.poor_colors(250).is_empty());
+
+        let heatmap = coverage.render(3, Palette::Classic);
+        assert_eq!(heatmap.dimensions(), (12, 6));
+        assert_eq!(heatmap.get_pixel(0, 0).0, Palette::Classic.color(0.0));
+        assert_eq!(heatmap.get_pixel(11, 5).0, Palette::Classic.color(1.0));
+    }
+}
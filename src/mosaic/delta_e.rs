@@ -0,0 +1,201 @@
+use std::collections::HashSet;
+
+use ::image::imageops::{self, FilterType};
+use ::image::{Rgb, RgbImage};
+use rayon::prelude::*;
+use tracing::info;
+
+use super::color::{average_color, delta_e};
+use super::web::palette::Palette;
+
+/// Delta-E from which a tile is reported as a weak area: a difference that is obvious at a
+/// glance.
+pub const WEAK_DELTA_E: f64 = 10.0;
+
+/// Delta-E rendered in the last color of the palette: colors this far apart look unrelated.
+const WORST_DELTA_E: f64 = 50.0;
+
+/// Number of the weakest tiles listed in the report.
+const WEAKEST_LISTED: usize = 5;
+
+/// Color of the hidden cells in the rendered map, outside of the palettes.
+const HIDDEN_COLOR: Rgb<u8> = Rgb([32, 32, 32]);
+
+/// How far every tile of a rendered mosaic is from the region of the source image it stands
+/// for, as the eye sees it rather than as the matcher scored it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeltaMap {
+    /// Number of columns and rows of tiles
+    pub grid: (u32, u32),
+    /// Mean CIE76 delta-E between the `dim`x`dim` sub-blocks of every tile and of its source
+    /// region, in row major order
+    pub deltas: Vec<f64>,
+    /// Columns and rows of the cells that the mask or the clear regions hide, left out
+    pub hidden: HashSet<(u32, u32)>,
+}
+
+impl DeltaMap {
+    /// Compare the `grid` of tiles of `mosaic` with `source`, scaled to the same grid, over
+    /// `dim`x`dim` sub-blocks of every tile.
+    pub fn new(mosaic: &RgbImage, source: &RgbImage, grid: (u32, u32), dim: u32) -> DeltaMap {
+        let (cols, rows) = grid;
+        let tile_size = (mosaic.width() / cols, mosaic.height() / rows);
+        let dim = dim.min(tile_size.0).min(tile_size.1).max(1);
+        let source = imageops::resize(source, cols * dim, rows * dim, FilterType::Lanczos3);
+        // Bounds of the `i`th of the `dim` sub-blocks of a tile of `size` pixels
+        let span = |i: u32, size: u32| (i * size / dim, (i + 1) * size / dim - i * size / dim);
+        let deltas = (0..cols * rows)
+            .into_par_iter()
+            .map(|cell| {
+                let (col, row) = (cell % cols, cell / cols);
+                let mut sum = 0.0;
+                for y in 0..dim {
+                    for x in 0..dim {
+                        let (left, width) = span(x, tile_size.0);
+                        let (top, height) = span(y, tile_size.1);
+                        let rect = (
+                            col * tile_size.0 + left,
+                            row * tile_size.1 + top,
+                            width,
+                            height,
+                        );
+                        let rendered = average_color(mosaic, rect);
+                        sum += delta_e(rendered, *source.get_pixel(col * dim + x, row * dim + y));
+                    }
+                }
+                sum / f64::from(dim * dim)
+            })
+            .collect();
+        DeltaMap {
+            grid,
+            deltas,
+            hidden: HashSet::new(),
+        }
+    }
+
+    /// Leave out `cells`, which the mask or the clear regions hide.
+    pub fn hide_cells(&mut self, cells: &HashSet<(u32, u32)>) {
+        self.hidden.extend(cells);
+    }
+
+    /// The delta-E of the tiles that are not hidden.
+    fn visible(&self) -> impl Iterator<Item = (usize, f64)> + '_ {
+        let cols = self.grid.0;
+        (self.deltas.iter().enumerate())
+            .filter(move |&(cell, _)| {
+                let cell = cell as u32;
+                !self.hidden.contains(&(cell % cols, cell / cols))
+            })
+            .map(|(cell, &delta)| (cell, delta))
+    }
+
+    /// Average delta-E over the tiles.
+    pub fn mean(&self) -> f64 {
+        let (sum, n) = self
+            .visible()
+            .fold((0.0, 0), |(sum, n), (_, d)| (sum + d, n + 1));
+        sum / f64::from(n.max(1))
+    }
+
+    /// Share of the tiles farther than `delta` from their source region.
+    pub fn share_above(&self, delta: f64) -> f64 {
+        let above = self.visible().filter(|&(_, d)| d > delta).count();
+        above as f64 / self.visible().count().max(1) as f64
+    }
+
+    /// The column, row and delta-E of the `n` tiles farthest from their source region, the
+    /// farthest first.
+    pub fn weakest(&self, n: usize) -> Vec<(u32, u32, f64)> {
+        let mut cells: Vec<usize> = self.visible().map(|(cell, _)| cell).collect();
+        cells.sort_by(|&a, &b| self.deltas[b].total_cmp(&self.deltas[a]).then(a.cmp(&b)));
+        cells
+            .into_iter()
+            .take(n)
+            .map(|cell| {
+                let cell = cell as u32;
+                (
+                    cell % self.grid.0,
+                    cell / self.grid.0,
+                    self.deltas[cell as usize],
+                )
+            })
+            .collect()
+    }
+
+    /// Print how close the mosaic is to the source overall, and where it is farthest.
+    pub fn summarise(&self) {
+        info!(
+            "🔍 Delta-E between the {}x{} tiles and the source: {:.1} on average, {:.1}% above {}",
+            self.grid.0,
+            self.grid.1,
+            self.mean(),
+            100.0 * self.share_above(WEAK_DELTA_E),
+            WEAK_DELTA_E
+        );
+        let weakest = self.weakest(WEAKEST_LISTED);
+        if weakest.iter().any(|&(_, _, delta)| delta > WEAK_DELTA_E) {
+            let cells: Vec<String> = weakest
+                .iter()
+                .filter(|&&(_, _, delta)| delta > WEAK_DELTA_E)
+                .map(|(col, row, delta)| format!("({}, {}) {:.1}", col, row, delta))
+                .collect();
+            info!("   Weakest tiles (column, row): {}", cells.join(", "));
+        }
+    }
+
+    /// Render every tile as one pixel, colored with `palette` by its delta-E on a fixed scale
+    /// where the last color stands for colors that look unrelated, and the hidden ones in gray.
+    pub fn render(&self, palette: Palette) -> RgbImage {
+        let (cols, rows) = self.grid;
+        RgbImage::from_fn(cols, rows, |x, y| {
+            if self.hidden.contains(&(x, y)) {
+                return HIDDEN_COLOR;
+            }
+            let t = self.deltas[(y * cols + x) as usize] / WORST_DELTA_E;
+            Rgb(palette.color(t.min(1.0)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_map() {
+        let (black, white) = (Rgb([0, 0, 0]), Rgb([255, 255, 255]));
+        // Two tiles of 4x4 pixels: the left one half black, half white, the right one white
+        let mosaic = RgbImage::from_fn(8, 4, |x, _| if x < 2 { black } else { white });
+        // The source is white on the left and black on the right, downscaled to the grid
+        let source = RgbImage::from_fn(2, 1, |x, _| if x == 0 { white } else { black });
+
+        let map = DeltaMap::new(&mosaic, &source, (2, 1), 1);
+        assert_eq!(map.grid, (2, 1));
+        assert!((map.deltas[1] - 100.0).abs() < 0.01);
+        assert!(map.deltas[0] > 0.0 && map.deltas[0] < map.deltas[1]);
+        assert_eq!(map.weakest(1), [(1, 0, map.deltas[1])]);
+        assert_eq!(map.share_above(WEAK_DELTA_E), 1.0);
+
+        // Finer sub-blocks see that the left half of the first tile is black
+        let fine = DeltaMap::new(&mosaic, &RgbImage::from_pixel(4, 2, white), (2, 1), 2);
+        assert!((fine.deltas[0] - 50.0).abs() < 0.01);
+        assert!(fine.deltas[1].abs() < 0.01);
+        assert_eq!(fine.share_above(WEAK_DELTA_E), 0.5);
+
+        // Hidden tiles are left out of the report
+        let mut hidden = map.clone();
+        hidden.hide_cells(&HashSet::from([(1, 0)]));
+        assert_eq!(hidden.mean(), map.deltas[0]);
+        assert_eq!(hidden.share_above(WEAK_DELTA_E), 1.0);
+        assert_eq!(hidden.weakest(2), [(0, 0, map.deltas[0])]);
+        assert_eq!(
+            *hidden.render(Palette::Classic).get_pixel(1, 0),
+            HIDDEN_COLOR
+        );
+
+        let heatmap = fine.render(Palette::Classic);
+        assert_eq!(heatmap.dimensions(), (2, 1));
+        assert_eq!(heatmap.get_pixel(0, 0).0, Palette::Classic.color(1.0));
+        assert_eq!(heatmap.get_pixel(1, 0).0, Palette::Classic.color(0.0));
+    }
+}
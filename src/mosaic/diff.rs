@@ -0,0 +1,257 @@
+use ::image::{Rgb, RgbImage};
+use tracing::info;
+
+use super::error::EmosaicError;
+use super::manifest::{Manifest, Placement};
+
+/// Color of the cells whose tile is the same in both mosaics.
+const UNCHANGED: Rgb<u8> = Rgb([64, 64, 64]);
+/// Color of the cells given a different tile at the same distance.
+const SWAPPED: Rgb<u8> = Rgb([160, 160, 160]);
+/// Color of the cells given a closer tile, blue as in the Okabe-Ito colors.
+const IMPROVED: Rgb<u8> = Rgb([0, 114, 178]);
+/// Color of the cells given a farther tile, vermillion as in the Okabe-Ito colors.
+const WORSENED: Rgb<u8> = Rgb([213, 94, 0]);
+
+/// How a cell of the mosaic grid changed from one manifest to the other.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CellChange {
+    /// The same tile, in the same orientation
+    Unchanged,
+    /// Another tile, closer to the cell by the given distance
+    Improved(f64),
+    /// Another tile, farther from the cell by the given distance
+    Worsened(f64),
+    /// Another tile at the same distance
+    Swapped,
+}
+
+/// The changes between two mosaics of the same source, cell by cell, e.g. rendered with
+/// different settings or versions of a tile set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestDiff {
+    /// Number of columns and rows of the mosaic grid
+    pub grid: (usize, usize),
+    /// Change of every cell, in row major order
+    pub cells: Vec<CellChange>,
+    /// Average distance of the first mosaic
+    pub before: f64,
+    /// Average distance of the second mosaic
+    pub after: f64,
+}
+
+impl ManifestDiff {
+    /// Compare the placements of `before` and `after`, which must have the same grid.
+    pub fn new(before: &Manifest, after: &Manifest) -> Result<ManifestDiff, EmosaicError> {
+        let grid = before.grid_size();
+        if after.grid_size() != grid {
+            return Err(EmosaicError::Validation(format!(
+                "❌ The mosaics have different grids, {}x{} and {}x{}: were they made from the \
+                 same source with the same mode?",
+                grid.0,
+                grid.1,
+                after.grid_size().0,
+                after.grid_size().1
+            )));
+        }
+        let cells: Vec<CellChange> = cells_of(before, grid)
+            .into_iter()
+            .zip(cells_of(after, grid))
+            .map(|cell| match cell {
+                (Some(a), Some(b)) => change(a, b),
+                _ => CellChange::Unchanged,
+            })
+            .collect();
+        let average = |manifest: &Manifest| {
+            let total: f64 = manifest.placements.iter().map(|p| p.distance).sum();
+            total / manifest.placements.len().max(1) as f64
+        };
+        Ok(ManifestDiff {
+            grid,
+            cells,
+            before: average(before),
+            after: average(after),
+        })
+    }
+
+    /// Number of cells that were improved, worsened and swapped.
+    pub fn counts(&self) -> (usize, usize, usize) {
+        self.cells.iter().fold(
+            (0, 0, 0),
+            |(improved, worsened, swapped), cell| match cell {
+                CellChange::Improved(_) => (improved + 1, worsened, swapped),
+                CellChange::Worsened(_) => (improved, worsened + 1, swapped),
+                CellChange::Swapped => (improved, worsened, swapped + 1),
+                CellChange::Unchanged => (improved, worsened, swapped),
+            },
+        )
+    }
+
+    /// Print how many cells changed and how the average distance moved.
+    pub fn summarise(&self) {
+        let (improved, worsened, swapped) = self.counts();
+        info!(
+            "{} of {} cells changed: {} improved, {} worsened, {} swapped for a tile as close",
+            improved + worsened + swapped,
+            self.cells.len(),
+            improved,
+            worsened,
+            swapped
+        );
+        info!(
+            "Average distance went from {:.1} to {:.1} ({:+.1})",
+            self.before,
+            self.after,
+            self.after - self.before
+        );
+    }
+
+    /// Render every cell as a square of `cell_size` pixels: dark gray where the tile is the
+    /// same, blue where the new tile is closer and orange where it is farther, brighter the
+    /// more the distance changed, and light gray where another tile is as close.
+    pub fn render(&self, cell_size: u32) -> RgbImage {
+        let largest = self
+            .cells
+            .iter()
+            .map(|cell| match cell {
+                CellChange::Improved(delta) | CellChange::Worsened(delta) => *delta,
+                _ => 0.0,
+            })
+            .fold(0.0, f64::max);
+        let shade = |color: Rgb<u8>, delta: f64| {
+            // Even the smallest changes stand out from the unchanged cells
+            let t = 0.4 + 0.6 * delta / largest;
+            Rgb(color
+                .0
+                .map(|c| (f64::from(c) * t + f64::from(UNCHANGED[0]) * (1.0 - t)) as u8))
+        };
+        let (cols, _) = self.grid;
+        RgbImage::from_fn(
+            self.grid.0 as u32 * cell_size,
+            self.grid.1 as u32 * cell_size,
+            |x, y| {
+                let cell = (y / cell_size) as usize * cols + (x / cell_size) as usize;
+                match self.cells[cell] {
+                    CellChange::Unchanged => UNCHANGED,
+                    CellChange::Swapped => SWAPPED,
+                    CellChange::Improved(delta) => shade(IMPROVED, delta),
+                    CellChange::Worsened(delta) => shade(WORSENED, delta),
+                }
+            },
+        )
+    }
+}
+
+/// The placement of every cell of the grid, in row major order.
+fn cells_of(manifest: &Manifest, (cols, rows): (usize, usize)) -> Vec<Option<&Placement>> {
+    let mut cells = vec![None; cols * rows];
+    for placement in &manifest.placements {
+        cells[placement.y * cols + placement.x] = Some(placement);
+    }
+    cells
+}
+
+/// How the placement `a` of a cell changed to `b`.
+fn change(a: &Placement, b: &Placement) -> CellChange {
+    if a.path == b.path && a.flipped == b.flipped && a.filler == b.filler {
+        CellChange::Unchanged
+    } else if b.distance < a.distance {
+        CellChange::Improved(a.distance - b.distance)
+    } else if b.distance > a.distance {
+        CellChange::Worsened(b.distance - a.distance)
+    } else {
+        CellChange::Swapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mosaic::stats::MosaicConfig;
+    use std::path::PathBuf;
+
+    fn manifest(placements: &[(&str, f64)]) -> Manifest {
+        Manifest {
+            config: MosaicConfig {
+                tile_size: 16,
+                mode: "test".to_string(),
+                no_repeat: false,
+                greedy: false,
+                story: false,
+                time_gradient: None,
+                filler: None,
+                crop: false,
+                tint_opacity: 0.0,
+                downsample: 1,
+                randomize: None,
+                randomize_max_variance: None,
+                tiles_dir: "tiles".to_string(),
+                title: "Test Mosaic".to_string(),
+                description: None,
+                author: None,
+                thumbnail_size: None,
+                export_quality: None,
+                export_strip_exif: false,
+                date_format: Default::default(),
+                palette: Default::default(),
+                tile_links: Default::default(),
+            },
+            placements: placements
+                .iter()
+                .enumerate()
+                .map(|(x, &(path, distance))| Placement {
+                    x,
+                    y: 0,
+                    path: PathBuf::from(path),
+                    flipped: false,
+                    distance,
+                    date: None,
+                    filler: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_manifest_diff() {
+        let before = manifest(&[
+            ("a.jpg", 10.0),
+            ("b.jpg", 10.0),
+            ("c.jpg", 10.0),
+            ("d.jpg", 5.0),
+        ]);
+        let after = manifest(&[
+            ("a.jpg", 10.0),
+            ("e.jpg", 4.0),
+            ("f.jpg", 13.0),
+            ("g.jpg", 5.0),
+        ]);
+        let diff = ManifestDiff::new(&before, &after).unwrap();
+        assert_eq!(
+            diff.cells,
+            [
+                CellChange::Unchanged,
+                CellChange::Improved(6.0),
+                CellChange::Worsened(3.0),
+                CellChange::Swapped
+            ]
+        );
+        assert_eq!(diff.counts(), (1, 1, 1));
+        assert_eq!((diff.before, diff.after), (8.75, 8.0));
+
+        let image = diff.render(2);
+        assert_eq!(image.dimensions(), (8, 2));
+        assert_eq!(*image.get_pixel(1, 1), UNCHANGED);
+        assert_eq!(*image.get_pixel(2, 0), IMPROVED);
+        assert_eq!(*image.get_pixel(6, 0), SWAPPED);
+
+        let smaller = manifest(&[("a.jpg", 10.0)]);
+        let error = ManifestDiff::new(&before, &smaller).unwrap_err();
+        assert!(
+            error.to_string().contains("different grids, 4x1 and 1x1"),
+            "{}",
+            error
+        );
+        assert_eq!(error.exit_code(), 2);
+    }
+}
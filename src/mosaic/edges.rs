@@ -0,0 +1,100 @@
+use ::image::Rgb;
+use ::image::RgbImage;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use super::analysis::{analyse_edges, source_edge_blocks};
+use super::tiles::TileSet;
+
+/// Edges of the source blocks and of the tiles, weighed against their colors when matching
+/// so the tiles follow the contours of the source image.
+pub struct EdgeFeatures<const N: usize> {
+    /// Edges of every block of the source image, row by row
+    pub blocks: Vec<[u8; N]>,
+    /// Edges of every tile, in tile order
+    pub tiles: Vec<[u8; N]>,
+    /// Weight of a difference in edges against a difference in colors summed over the RGB
+    /// channels
+    pub weight: f64,
+}
+
+impl<const N: usize> EdgeFeatures<N> {
+    /// Measure the edges of the `htiles`x`vtiles` blocks of the original source image and of
+    /// every tile at `tile_size`.
+    pub fn new(
+        original_img: &RgbImage,
+        (htiles, vtiles): (u32, u32),
+        tile_set: &TileSet<[Rgb<u8>; N]>,
+        tile_size: u32,
+        weight: f64,
+    ) -> EdgeFeatures<N> {
+        EdgeFeatures {
+            blocks: source_edge_blocks(original_img, htiles, vtiles),
+            tiles: tile_edges(tile_set, tile_size),
+            weight,
+        }
+    }
+
+    /// Weighted distance between the edges of the block `cell` and those of the tile `item`,
+    /// negative for flipped tiles. Tiles added after the edges were measured, such as
+    /// fillers, are flat.
+    pub fn distance(&self, cell: usize, item: i32) -> f64 {
+        let mut edges = self
+            .tiles
+            .get(item.unsigned_abs() as usize - 1)
+            .copied()
+            .unwrap_or([0; N]);
+        if item < 0 {
+            flip_edges(&mut edges);
+        }
+        let distance: u32 = self.blocks[cell]
+            .iter()
+            .zip(edges)
+            .map(|(&a, b)| u32::from(a.abs_diff(b)))
+            .sum();
+        self.weight * f64::from(distance)
+    }
+}
+
+/// Edges of every tile, in tile order, measured on their images at `tile_size`. Tiles whose
+/// image cannot be loaded are taken to be flat.
+pub fn tile_edges<const N: usize>(
+    tile_set: &TileSet<[Rgb<u8>; N]>,
+    tile_size: u32,
+) -> Vec<[u8; N]> {
+    (0..tile_set.len())
+        .into_par_iter()
+        .map(|position| {
+            let tile = tile_set.get_tile(position as i32 + 1).unwrap();
+            tile_set
+                .get_image(&tile, tile_size)
+                .map_or([0; N], |image| analyse_edges(&image))
+        })
+        .collect()
+}
+
+/// Flip a grid of edges horizontally, like the image of a flipped tile.
+fn flip_edges<const N: usize>(edges: &mut [u8; N]) {
+    let dim = (N as f64).sqrt() as usize;
+    for row in edges.chunks_exact_mut(dim) {
+        row.reverse();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edge_distance() {
+        let features = EdgeFeatures {
+            blocks: vec![[10, 0, 10, 0]],
+            tiles: vec![[0, 10, 0, 10]],
+            weight: 0.5,
+        };
+        assert_eq!(features.distance(0, 1), 20.0);
+        // Flipping the tile lines its edges up with those of the block
+        assert_eq!(features.distance(0, -1), 0.0);
+        // Fillers are flat
+        assert_eq!(features.distance(0, 2), 10.0);
+    }
+}
@@ -1,11 +1,292 @@
-
+use std::fmt;
+use std::io;
 use std::path::PathBuf;
 
+use ::image::error::{ImageError as ImgError, LimitErrorKind, ParameterError, ParameterErrorKind};
 use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+/// Message of the error given for images with no pixel that is not white.
+const ALL_WHITE: &str = "the image is all white";
+/// Message of the error given for images whose preparation panicked.
+const CRASHED: &str = "crashed while preparing the tile";
 
 #[derive(Debug, Display)]
 #[display(fmt = "{:?}: {}", path, error)]
-pub(crate) struct ImageError {
-    pub(crate) path: PathBuf,
-    pub(crate) error: ::image::ImageError,
-}
\ No newline at end of file
+pub struct ImageError {
+    pub path: PathBuf,
+    pub error: ::image::ImageError,
+}
+
+impl std::error::Error for ImageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Underlying cause of an [`EmosaicError`].
+pub type Cause = Box<dyn std::error::Error + Send + Sync>;
+
+/// A failure of the program, by what went wrong, each ending the process with its own exit
+/// code.
+#[derive(Debug, thiserror::Error)]
+pub enum EmosaicError {
+    /// A file or directory could not be read or written
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    /// An image could not be decoded, prepared or encoded
+    #[error("{context}: {source}")]
+    Decode {
+        context: String,
+        #[source]
+        source: Cause,
+    },
+    /// A tile analysis could not be read or written
+    #[error("{context}: {source}")]
+    Cache {
+        context: String,
+        #[source]
+        source: Cause,
+    },
+    /// The arguments or the inputs are invalid
+    #[error("{0}")]
+    Validation(String),
+    /// The mosaic cannot be rendered with these tiles and settings
+    #[error("{0}")]
+    Render(String),
+    /// The run was interrupted with Ctrl-C, after saving what it had done
+    #[error("{0}")]
+    Interrupted(String),
+}
+
+impl From<ImageError> for EmosaicError {
+    fn from(error: ImageError) -> EmosaicError {
+        EmosaicError::decode(
+            format!("Failed to prepare tile {}", error.path.display()),
+            error.error,
+        )
+    }
+}
+
+impl EmosaicError {
+    /// The error for a file that could not be read or written while `context`.
+    pub fn io(context: impl Into<String>, source: io::Error) -> EmosaicError {
+        EmosaicError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    /// The error for an image that could not be decoded, prepared or encoded while `context`.
+    pub fn decode(context: impl Into<String>, source: impl Into<Cause>) -> EmosaicError {
+        EmosaicError::Decode {
+            context: context.into(),
+            source: source.into(),
+        }
+    }
+
+    /// The error for a tile analysis that could not be used while `context`.
+    pub fn cache(context: impl Into<String>, source: impl Into<Cause>) -> EmosaicError {
+        EmosaicError::Cache {
+            context: context.into(),
+            source: source.into(),
+        }
+    }
+
+    /// The error with `context` in place of its own, e.g. to name the file that failed, unless
+    /// its message stands alone.
+    pub fn with_context(self, context: impl Into<String>) -> EmosaicError {
+        match self {
+            EmosaicError::Io { source, .. } => EmosaicError::io(context, source),
+            EmosaicError::Decode { source, .. } => EmosaicError::decode(context, source),
+            EmosaicError::Cache { source, .. } => EmosaicError::cache(context, source),
+            error => error,
+        }
+    }
+
+    /// Exit code of the process failing with this error. 1 is left to other failures, and
+    /// invalid inputs share 2 with the arguments rejected by the command line parser, and
+    /// interrupted runs exit with 130 like the processes killed by Ctrl-C.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            EmosaicError::Validation(_) => 2,
+            EmosaicError::Io { .. } => 3,
+            EmosaicError::Decode { .. } => 4,
+            EmosaicError::Cache { .. } => 5,
+            EmosaicError::Render(_) => 6,
+            EmosaicError::Interrupted(_) => 130,
+        }
+    }
+}
+
+/// Why an image could not be used as a tile, to triage the failures of large libraries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailureKind {
+    /// The file could not be read
+    Io,
+    /// The file is not an image in a supported format, or is corrupt
+    Decode,
+    /// The image is smaller than the tiles
+    TooSmall,
+    /// The image has nothing but white pixels, which are cropped from the edges of tiles
+    AllWhite,
+    /// The image is too large to decode safely
+    TooLarge,
+    /// Preparing the tile took too long
+    Timeout,
+    /// Preparing the tile panicked
+    Crash,
+}
+
+impl FailureKind {
+    /// What to do about the images failing this way.
+    pub fn hint(self) -> &'static str {
+        match self {
+            FailureKind::Io => "check that the files are readable and the disk is healthy",
+            FailureKind::Decode => {
+                "the files are corrupt, truncated or in an unsupported format: re-export them, \
+                 or leave their extension out of --extensions"
+            }
+            FailureKind::TooSmall => {
+                "images smaller than the tile size are skipped: lower --tile-size or remove them"
+            }
+            FailureKind::AllWhite => "blank pictures have nothing to match: remove them",
+            FailureKind::TooLarge => {
+                "the images are quarantined: downscale them, then retry them with --force"
+            }
+            FailureKind::Timeout => {
+                "the images are quarantined: raise --tile-timeout, then retry them with --force"
+            }
+            FailureKind::Crash => {
+                "the images are quarantined: re-export them, then retry them with --force"
+            }
+        }
+    }
+
+    /// Whether the images failing this way are skipped by later analyses until --force,
+    /// because trying them again could stall or take down the analysis.
+    pub fn quarantined(self) -> bool {
+        matches!(
+            self,
+            FailureKind::TooLarge | FailureKind::Timeout | FailureKind::Crash
+        )
+    }
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FailureKind::Io => "I/O error",
+            FailureKind::Decode => "decode error",
+            FailureKind::TooSmall => "too small",
+            FailureKind::AllWhite => "all white",
+            FailureKind::TooLarge => "too large",
+            FailureKind::Timeout => "timed out",
+            FailureKind::Crash => "crashed",
+        })
+    }
+}
+
+impl ImageError {
+    /// The error for an image at `path` with no pixel that is not white.
+    pub fn all_white(path: PathBuf) -> ImageError {
+        ImageError {
+            path,
+            error: ImgError::Parameter(ParameterError::from_kind(ParameterErrorKind::Generic(
+                ALL_WHITE.to_string(),
+            ))),
+        }
+    }
+
+    /// The error for an image at `path` that took too long to prepare.
+    pub fn timed_out(path: PathBuf) -> ImageError {
+        ImageError {
+            path,
+            error: io::Error::new(io::ErrorKind::TimedOut, "timed out").into(),
+        }
+    }
+
+    /// The error for an image at `path` whose preparation panicked.
+    pub fn crashed(path: PathBuf) -> ImageError {
+        ImageError {
+            path,
+            error: io::Error::other(CRASHED).into(),
+        }
+    }
+
+    /// Why the image could not be used.
+    pub fn kind(&self) -> FailureKind {
+        match &self.error {
+            ImgError::IoError(error) if error.kind() == io::ErrorKind::TimedOut => {
+                FailureKind::Timeout
+            }
+            ImgError::IoError(error) if error.to_string() == CRASHED => FailureKind::Crash,
+            ImgError::IoError(_) => FailureKind::Io,
+            ImgError::Limits(limit) if limit.kind() == LimitErrorKind::DimensionError => {
+                FailureKind::TooSmall
+            }
+            ImgError::Limits(_) => FailureKind::TooLarge,
+            ImgError::Parameter(error)
+                if error.kind() == ParameterErrorKind::Generic(ALL_WHITE.to_string()) =>
+            {
+                FailureKind::AllWhite
+            }
+            _ => FailureKind::Decode,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::error::LimitError;
+
+    #[test]
+    fn test_failure_kind() {
+        let path = PathBuf::from("a.jpg");
+        let error = |error| ImageError {
+            path: path.clone(),
+            error,
+        };
+        let io = io::Error::new(io::ErrorKind::NotFound, "gone");
+        assert_eq!(error(io.into()).kind(), FailureKind::Io);
+        let small = LimitError::from_kind(LimitErrorKind::DimensionError);
+        assert_eq!(error(ImgError::Limits(small)).kind(), FailureKind::TooSmall);
+        assert_eq!(
+            ImageError::all_white(path.clone()).kind(),
+            FailureKind::AllWhite
+        );
+        assert_eq!(
+            ImageError::timed_out(path.clone()).kind(),
+            FailureKind::Timeout
+        );
+        assert_eq!(ImageError::crashed(path.clone()).kind(), FailureKind::Crash);
+        let large = LimitError::from_kind(LimitErrorKind::InsufficientMemory);
+        assert_eq!(error(ImgError::Limits(large)).kind(), FailureKind::TooLarge);
+        let garbage = ::image::load_from_memory(b"not an image").unwrap_err();
+        assert_eq!(error(garbage).kind(), FailureKind::Decode);
+    }
+
+    #[test]
+    fn test_emosaic_error() {
+        let missing = io::Error::new(io::ErrorKind::NotFound, "gone");
+        let error = EmosaicError::io("Failed to read a.json", missing);
+        assert_eq!(error.to_string(), "Failed to read a.json: gone");
+        assert!(std::error::Error::source(&error).is_some());
+
+        let errors = [
+            error,
+            EmosaicError::decode("Failed to open a.png", "truncated"),
+            EmosaicError::cache("Invalid tile analysis", "bad version"),
+            EmosaicError::Validation(String::from("bad size")),
+            EmosaicError::Render(String::from("too few tiles")),
+        ];
+        let codes: Vec<u8> = errors.iter().map(EmosaicError::exit_code).collect();
+        assert_eq!(codes, [3, 4, 5, 2, 6]);
+    }
+}
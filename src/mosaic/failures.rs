@@ -0,0 +1,222 @@
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use super::error::{FailureKind, ImageError};
+
+/// Failures listed per category on the terminal, the rest are only in the report file.
+const FAILURES_LISTED: usize = 5;
+
+/// The images of a tiles directory that could not be analysed, grouped by why they failed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FailureReport {
+    pub total: usize,
+    /// Categories of failures, most common first
+    pub categories: Vec<FailureCategory>,
+}
+
+/// The images that failed for the same reason.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FailureCategory {
+    pub kind: FailureKind,
+    pub count: usize,
+    /// What to do about these images
+    pub hint: String,
+    /// The failed images, by path
+    pub failures: Vec<Failure>,
+}
+
+/// An image that could not be analysed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Failure {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+impl FailureReport {
+    /// Name of the report file written in the tiles directory.
+    pub const FILE_NAME: &'static str = ".emosaic-failures.json";
+
+    /// Group `errors` by their kind.
+    pub fn new(errors: &[ImageError]) -> FailureReport {
+        let mut categories: Vec<FailureCategory> = Vec::new();
+        for error in errors {
+            let kind = error.kind();
+            let failure = Failure {
+                path: error.path.clone(),
+                error: error.error.to_string(),
+            };
+            match categories.iter_mut().find(|category| category.kind == kind) {
+                Some(category) => category.failures.push(failure),
+                None => categories.push(FailureCategory {
+                    kind,
+                    count: 0,
+                    hint: kind.hint().to_string(),
+                    failures: vec![failure],
+                }),
+            }
+        }
+        for category in &mut categories {
+            category.count = category.failures.len();
+            category.failures.sort_by(|a, b| a.path.cmp(&b.path));
+        }
+        categories.sort_by(|a, b| b.count.cmp(&a.count).then(a.kind.cmp(&b.kind)));
+        FailureReport {
+            total: errors.len(),
+            categories,
+        }
+    }
+
+    /// Print the counts and hints of every category with a few of its images.
+    pub fn summarise(&self) {
+        info!("Failed to read {} images:", self.total);
+        for category in &self.categories {
+            info!(
+                "- {} ({}): {}",
+                category.kind, category.count, category.hint
+            );
+            for failure in category.failures.iter().take(FAILURES_LISTED) {
+                info!("    {}: {}", failure.path.display(), failure.error);
+            }
+            if category.count > FAILURES_LISTED {
+                info!("    ... and {} more", category.count - FAILURES_LISTED);
+            }
+        }
+    }
+
+    /// Write the report as JSON.
+    pub fn write<W: Write>(&self, writer: W) -> Result<(), io::Error> {
+        serde_json::to_writer_pretty(writer, self).map_err(io::Error::from)
+    }
+
+    /// Write the report to the file at `path`.
+    pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+}
+
+/// Images of a tiles directory that timed out, were too large or crashed the analysis, and
+/// are skipped by later analyses of the directory until they are retried with --force.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Quarantine {
+    /// Paths relative to the tiles directory
+    pub paths: BTreeSet<PathBuf>,
+}
+
+impl Quarantine {
+    /// Name of the quarantine file in the tiles directory, listing a path per line.
+    pub const FILE_NAME: &'static str = ".emosaic-quarantine";
+
+    /// Read the quarantine of the tiles directory `dir`, empty when there is none.
+    pub fn load(dir: &Path) -> Quarantine {
+        let paths = fs::read_to_string(dir.join(Quarantine::FILE_NAME))
+            .map(|contents| {
+                contents
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(PathBuf::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Quarantine { paths }
+    }
+
+    /// Write the quarantine of the tiles directory `dir`, removing the file when it is empty.
+    pub fn save(&self, dir: &Path) -> Result<(), io::Error> {
+        let path = dir.join(Quarantine::FILE_NAME);
+        if self.paths.is_empty() {
+            return match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        for path in &self.paths {
+            writeln!(writer, "{}", path.display())?;
+        }
+        writer.flush()
+    }
+
+    /// Quarantine the images of the report whose failures could stall or take down a
+    /// later analysis, returning how many were added.
+    pub fn extend(&mut self, report: &FailureReport) -> usize {
+        let before = self.paths.len();
+        let failures = report
+            .categories
+            .iter()
+            .filter(|category| category.kind.quarantined())
+            .flat_map(|category| &category.failures);
+        self.paths
+            .extend(failures.map(|failure| failure.path.clone()));
+        self.paths.len() - before
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.paths.contains(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failure_report() {
+        let errors = vec![
+            ImageError::all_white(PathBuf::from("b.jpg")),
+            ImageError {
+                path: PathBuf::from("c.jpg"),
+                error: ::image::load_from_memory(b"").unwrap_err(),
+            },
+            ImageError::all_white(PathBuf::from("a.jpg")),
+        ];
+        let report = FailureReport::new(&errors);
+        assert_eq!(report.total, 3);
+        let kinds: Vec<_> = report
+            .categories
+            .iter()
+            .map(|category| (category.kind, category.count))
+            .collect();
+        assert_eq!(
+            kinds,
+            [(FailureKind::AllWhite, 2), (FailureKind::Decode, 1)]
+        );
+        let paths: Vec<_> = report.categories[0]
+            .failures
+            .iter()
+            .map(|failure| failure.path.to_str().unwrap())
+            .collect();
+        assert_eq!(paths, ["a.jpg", "b.jpg"]);
+
+        let mut json = Vec::new();
+        report.write(&mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["categories"][0]["kind"], "all-white");
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let errors = vec![
+            ImageError::timed_out(PathBuf::from("panorama.jpg")),
+            ImageError::all_white(PathBuf::from("blank.jpg")),
+            ImageError::crashed(PathBuf::from("bomb.png")),
+        ];
+        let mut quarantine = Quarantine::default();
+        assert_eq!(quarantine.extend(&FailureReport::new(&errors)), 2);
+        assert!(quarantine.contains(Path::new("bomb.png")));
+        assert!(!quarantine.contains(Path::new("blank.jpg")));
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        quarantine.save(dir).unwrap();
+        assert_eq!(Quarantine::load(dir), quarantine);
+        Quarantine::default().save(dir).unwrap();
+        assert!(!dir.join(Quarantine::FILE_NAME).exists());
+    }
+}
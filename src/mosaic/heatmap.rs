@@ -0,0 +1,165 @@
+use ::image::{imageops, Rgb, RgbImage};
+
+use super::font;
+
+/// Color of the background around the heatmap.
+const BACKGROUND: Rgb<u8> = Rgb([255, 255, 255]);
+/// Color of the ticks and labels.
+const INK: Rgb<u8> = Rgb([64, 64, 64]);
+/// Length of the ticks of the axes, before scaling with the text.
+const TICK_LENGTH: u32 = 3;
+/// Space between the parts of the image, before scaling with the text.
+const GAP: u32 = 4;
+/// Height of the color bar of the legend, before scaling with the text.
+const LEGEND_HEIGHT: u32 = 10;
+/// Intervals between the ticks of the axes, in cells, the smallest fitting its labels first.
+const TICK_STEPS: [u32; 10] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000];
+
+/// The color scale of a heatmap, for its legend.
+pub struct Legend<'a> {
+    /// What the colors stand for
+    pub title: &'a str,
+    /// Label of the low end of the scale
+    pub low: String,
+    /// Label of the high end of the scale
+    pub high: String,
+    /// Color of a value at `t`, from 0 for the low end of the scale to 1 for the high end
+    pub color: &'a dyn Fn(f64) -> Rgb<u8>,
+}
+
+/// Draw `cells`, an image of one pixel per cell of the mosaic grid, with every cell a square
+/// of `scale` pixels, its columns and rows numbered along the top and left sides, and `legend`
+/// below it.
+pub fn decorate(cells: &RgbImage, scale: u32, legend: &Legend) -> RgbImage {
+    let scale = scale.max(1);
+    let (cols, rows) = cells.dimensions();
+    let text = (scale / 8).clamp(1, 2);
+    let glyph_height = font::GLYPH_HEIGHT * text;
+    let label_width = |n: u32| font::text_width(&n.to_string(), text);
+    let step = |count: u32| {
+        let widest = label_width(count.saturating_sub(1)) + GAP * text;
+        TICK_STEPS
+            .iter()
+            .copied()
+            .find(|&step| step * scale >= widest)
+            .unwrap_or(TICK_STEPS[TICK_STEPS.len() - 1])
+    };
+    // The labels of the rows are drawn across, so their ticks only need to clear their height
+    let row_step = TICK_STEPS
+        .iter()
+        .copied()
+        .find(|&step| step * scale >= glyph_height + text)
+        .unwrap_or(TICK_STEPS[TICK_STEPS.len() - 1]);
+
+    let left = label_width(rows.saturating_sub(1)) + (TICK_LENGTH + GAP) * text;
+    let top = glyph_height + (TICK_LENGTH + GAP) * text;
+    let right = label_width(cols.saturating_sub(1)) / 2 + GAP * text;
+    let legend_width = (cols * scale).max(legend_text_width(legend, text));
+    let width = left + legend_width + right;
+    let height = top + rows * scale + (GAP * 2 + LEGEND_HEIGHT) * text + glyph_height + GAP * text;
+    let mut image = RgbImage::from_pixel(width, height, BACKGROUND);
+
+    let heatmap = imageops::resize(cells, cols * scale, rows * scale, imageops::Nearest);
+    imageops::replace(&mut image, &heatmap, i64::from(left), i64::from(top));
+
+    // Column ticks above the heatmap, labelled with the number of the column they start
+    for col in (0..cols).step_by(step(cols) as usize) {
+        let x = left + col * scale;
+        fill(
+            &mut image,
+            (x, top - TICK_LENGTH * text),
+            (text, TICK_LENGTH * text),
+        );
+        let label = col.to_string();
+        let centered = (x + text / 2).saturating_sub(font::text_width(&label, text) / 2);
+        font::draw_text(&mut image, &label, centered, 0, text, INK);
+    }
+    // Row ticks left of the heatmap, labelled with the number of the row they start
+    for row in (0..rows).step_by(row_step as usize) {
+        let y = top + row * scale;
+        fill(
+            &mut image,
+            (left - TICK_LENGTH * text, y),
+            (TICK_LENGTH * text, text),
+        );
+        let label = row.to_string();
+        let x = left - (TICK_LENGTH + GAP) * text - font::text_width(&label, text);
+        font::draw_text(
+            &mut image,
+            &label,
+            x,
+            y.saturating_sub(glyph_height / 2),
+            text,
+            INK,
+        );
+    }
+
+    // Color bar of the legend under the heatmap, with its ends and title below it
+    let bar_top = top + rows * scale + GAP * 2 * text;
+    for x in 0..legend_width {
+        let t = f64::from(x) / f64::from((legend_width - 1).max(1));
+        let color = (legend.color)(t);
+        for y in bar_top..bar_top + LEGEND_HEIGHT * text {
+            image.put_pixel(left + x, y, color);
+        }
+    }
+    let labels_top = bar_top + (LEGEND_HEIGHT + GAP) * text;
+    font::draw_text(&mut image, &legend.low, left, labels_top, text, INK);
+    let title_x = left + (legend_width - font::text_width(legend.title, text)) / 2;
+    font::draw_text(&mut image, legend.title, title_x, labels_top, text, INK);
+    let high_x = left + legend_width - font::text_width(&legend.high, text);
+    font::draw_text(&mut image, &legend.high, high_x, labels_top, text, INK);
+    image
+}
+
+/// Width of the labels under the color bar of `legend` drawn at `scale`, with its title
+/// centered between its ends.
+fn legend_text_width(legend: &Legend, scale: u32) -> u32 {
+    let ends = font::text_width(&legend.low, scale).max(font::text_width(&legend.high, scale));
+    font::text_width(legend.title, scale) + 2 * (ends + 2 * font::ADVANCE * scale)
+}
+
+/// Fill the rectangle of `size` at `corner` with the color of the ticks.
+fn fill(image: &mut RgbImage, corner: (u32, u32), size: (u32, u32)) {
+    for y in corner.1..corner.1 + size.1 {
+        for x in corner.0..corner.0 + size.0 {
+            if x < image.width() && y < image.height() {
+                image.put_pixel(x, y, INK);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decorate() {
+        let cells = RgbImage::from_fn(30, 4, |x, _| Rgb([x as u8 * 8, 0, 0]));
+        let color = |t: f64| Rgb([(t * 255.0) as u8, 0, 255]);
+        let legend = Legend {
+            title: "DISTANCE",
+            low: String::from("0"),
+            high: String::from("120"),
+            color: &color,
+        };
+        let image = decorate(&cells, 8, &legend);
+        let left = font::text_width("3", 1) + (TICK_LENGTH + GAP);
+        let top = font::GLYPH_HEIGHT + TICK_LENGTH + GAP;
+        assert!(image.width() >= left + 30 * 8);
+        // The cells are scaled up inside the margins of the axes
+        assert_eq!(*image.get_pixel(left, top), Rgb([0, 0, 0]));
+        assert_eq!(
+            *image.get_pixel(left + 8 * 29 + 7, top + 8 * 3 + 7),
+            Rgb([232, 0, 0])
+        );
+        // The first column has a tick above it, the second none at this scale
+        assert_eq!(*image.get_pixel(left, top - 1), INK);
+        assert_eq!(*image.get_pixel(left + 8, top - 1), BACKGROUND);
+        // The legend runs from the low to the high color under the heatmap
+        let bar = top + 4 * 8 + GAP * 2;
+        assert_eq!(*image.get_pixel(left, bar), color(0.0));
+        assert_eq!(*image.get_pixel(left + 30 * 8 - 1, bar), color(1.0));
+    }
+}
@@ -0,0 +1,213 @@
+use std::io;
+use std::path::{Component, Path};
+
+use glob::{MatchOptions, Pattern};
+
+use super::error::EmosaicError;
+
+/// Name of the file of patterns of the pictures to leave out, at the root of a tiles directory.
+pub const IGNORE_FILE: &str = ".emosaicignore";
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// A line of an ignore file: a glob matched against the name of the files and directories, or
+/// against their path from the root of the tiles directory when it has a slash other than at
+/// its end.
+#[derive(Clone, Debug)]
+struct Rule {
+    pattern: Pattern,
+    /// Whether the line starts with `!`, taking the matching paths back in
+    negated: bool,
+    /// Whether the line ends with `/`, matching only directories
+    directory: bool,
+    /// Whether the pattern is matched against the whole path
+    anchored: bool,
+}
+
+impl Rule {
+    /// The rule of `line`, if it is not blank or a comment, or the line and why its pattern is
+    /// invalid.
+    fn parse(line: &str) -> Result<Option<Rule>, (&str, glob::PatternError)> {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            return Ok(None);
+        }
+        let (negated, pattern) = match trimmed.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, trimmed),
+        };
+        let (directory, pattern) = match pattern.strip_suffix('/') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+        let anchored = pattern.contains('/');
+        let pattern = Pattern::new(pattern.trim_start_matches('/')).map_err(|e| (line, e))?;
+        Ok(Some(Rule {
+            pattern,
+            negated,
+            directory,
+            anchored,
+        }))
+    }
+
+    fn matches(&self, path: &str, name: &str, is_dir: bool) -> bool {
+        if self.directory && !is_dir {
+            return false;
+        }
+        let matched = if self.anchored { path } else { name };
+        self.pattern.matches_with(matched, MATCH_OPTIONS)
+    }
+}
+
+/// The pictures to leave out of a tiles directory, from its [`IGNORE_FILE`] and the patterns
+/// given with `--exclude`, with the syntax of gitignore files: the last rule matching a
+/// path decides, and the contents of the directories left out are never looked at.
+#[derive(Clone, Debug, Default)]
+pub struct Ignore {
+    rules: Vec<Rule>,
+}
+
+impl Ignore {
+    /// The rules of the lines of `contents`.
+    fn parse(contents: &str) -> Result<Ignore, String> {
+        let rules = contents
+            .lines()
+            .filter_map(|line| Rule::parse(line).transpose())
+            .collect::<Result<_, _>>()
+            .map_err(|(line, e)| format!("invalid pattern {:?}: {}", line, e))?;
+        Ok(Ignore { rules })
+    }
+
+    /// The rules leaving out of every tiles directory the pictures matching `patterns`, in the
+    /// syntax of the lines of an [`IGNORE_FILE`].
+    pub fn excludes(patterns: &[String]) -> Result<Ignore, EmosaicError> {
+        let rules = patterns
+            .iter()
+            .filter_map(|pattern| Rule::parse(pattern).transpose())
+            .collect::<Result<_, _>>()
+            .map_err(|(pattern, e)| {
+                EmosaicError::Validation(format!(
+                    "❌ Invalid --exclude pattern {:?}: {}",
+                    pattern, e
+                ))
+            })?;
+        Ok(Ignore { rules })
+    }
+
+    /// The rules of the tiles directory `dir`, followed by the `excludes` of every tiles
+    /// directory.
+    pub fn for_dir(dir: &Path, excludes: &Ignore) -> io::Result<Ignore> {
+        let path = dir.join(IGNORE_FILE);
+        let mut ignore = match std::fs::read_to_string(&path) {
+            Ok(contents) => Ignore::parse(&contents).map_err(|e| {
+                let message = format!("{} in {}", e, path.display());
+                io::Error::new(io::ErrorKind::InvalidData, message)
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ignore::default(),
+            Err(e) => return Err(e),
+        };
+        ignore.rules.extend(excludes.rules.iter().cloned());
+        Ok(ignore)
+    }
+
+    /// Whether there is nothing to leave out.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether the file or directory at `relative`, from the root of the tiles directory, is
+    /// left out on its own, regardless of the directories it is in.
+    pub fn is_ignored(&self, relative: &Path, is_dir: bool) -> bool {
+        let path = slash_path(relative);
+        let name = path.rsplit('/').next().unwrap_or_default();
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(&path, name, is_dir))
+            .is_some_and(|rule| !rule.negated)
+    }
+
+    /// Whether the file at `relative`, from the root of the tiles directory, is left out, on
+    /// its own or with one of the directories it is in.
+    pub fn is_file_ignored(&self, relative: &Path) -> bool {
+        relative
+            .ancestors()
+            .skip(1)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .any(|dir| self.is_ignored(dir, true))
+            || self.is_ignored(relative, false)
+    }
+}
+
+/// `path` with its components joined by slashes, whatever the platform.
+fn slash_path(path: &Path) -> String {
+    let components: Vec<_> = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+    components.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignore() {
+        let ignore = Ignore::parse(
+            "# Phone clutter\n\
+             Screenshots/\n\
+             *.edited.jpg\n\
+             !keep.edited.jpg\n\
+             /2019/blurry*.jpg\n\
+             \n\
+             archive/**/*.png\n",
+        )
+        .unwrap();
+        let ignored = |path: &str| ignore.is_file_ignored(Path::new(path));
+        assert!(ignored("Screenshots/a.jpg"));
+        assert!(ignored("2024/Screenshots/b.jpg"));
+        assert!(ignored("2024/party.edited.jpg"));
+        assert!(!ignored("2024/keep.edited.jpg"));
+        assert!(ignored("2019/blurry1.jpg"));
+        // Anchored patterns only match from the root, and stars do not cross directories
+        assert!(!ignored("old/2019/blurry1.jpg"));
+        assert!(!ignored("2019/x/blurry1.jpg"));
+        assert!(ignored("archive/a/b/c.png"));
+        assert!(!ignored("archive/a/b/c.jpg"));
+        assert!(!ignored("2024/party.jpg"));
+        // Only directories match patterns ending with a slash
+        assert!(!ignored("2024/Screenshots"));
+
+        assert!(Ignore::parse("[unclosed").is_err());
+    }
+
+    #[test]
+    fn test_for_dir() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let none = Ignore::default();
+        assert!(Ignore::for_dir(dir, &none).unwrap().is_empty());
+        std::fs::write(dir.join(IGNORE_FILE), "*.png\n").unwrap();
+        let ignore = Ignore::for_dir(dir, &none).unwrap();
+        assert!(ignore.is_file_ignored(Path::new("a/b.png")));
+        assert!(!ignore.is_file_ignored(Path::new("a/b.jpg")));
+        // The excludes come after the rules of the file
+        let excludes = Ignore::excludes(&[String::from("*.jpg"), String::from("!a/b.png")]);
+        let ignore = Ignore::for_dir(dir, &excludes.unwrap()).unwrap();
+        assert!(ignore.is_file_ignored(Path::new("a/b.jpg")));
+        assert!(!ignore.is_file_ignored(Path::new("a/b.png")));
+        let error = Ignore::excludes(&[String::from("[unclosed")]).unwrap_err();
+        assert_eq!(error.exit_code(), 2, "{}", error);
+        std::fs::write(dir.join(IGNORE_FILE), "[unclosed\n").unwrap();
+        let error = Ignore::for_dir(dir, &none).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,10 +1,42 @@
+use std::collections::HashSet;
 use std::ffi::OsStr;
-use std::fs::read_dir;
-use std::io;
+use std::fs::{read_dir, File};
+use std::io::{self, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
+use ::image::codecs::avif::AvifEncoder;
+use ::image::codecs::jpeg::JpegEncoder;
+use ::image::error::{EncodingError, ImageFormatHint, LimitErrorKind};
+use ::image::imageops::sample_bilinear;
+use ::image::{
+    DynamicImage, GrayImage, ImageEncoder, ImageFormat, ImageReader, ImageResult, Limits, Rgb,
+    RgbImage,
+};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use tempfile::NamedTempFile;
+use tracing::info;
 
-pub fn find_images(path: &Path, extension: impl Fn(&OsStr) -> bool) -> io::Result<Vec<PathBuf>> {
+use super::error::EmosaicError;
+use super::ignore::Ignore;
+use super::tiles::LibraryOptions;
+
+/// The images under `path` whose extension is accepted by `extension`, leaving out those of its
+/// [`Ignore`] rules and the excludes of `library`, sorted by path. The symbolic links to
+/// directories are only followed when `library` says so.
+///
+/// Every directory is looked into once, whatever the links to it, so cycles of symbolic links
+/// end, and an image reached by several paths is only returned by the first of them in that
+/// order.
+pub fn find_images(
+    path: &Path,
+    extension: impl Fn(&OsStr) -> bool,
+    library: &LibraryOptions,
+) -> io::Result<Vec<PathBuf>> {
+    let follow_symlinks = library.follow_symlinks;
+    let ignore = Ignore::for_dir(path, &library.excludes)?;
+    let root = path;
+    let mut visited: HashSet<PathBuf> = HashSet::from([root.canonicalize()?]);
     let mut stack : Vec<PathBuf> = vec![path.to_owned()];
     let mut images_paths = vec![];
     while let Some(p) = stack.pop() {
@@ -12,12 +44,788 @@ pub fn find_images(path: &Path, extension: impl Fn(&OsStr) -> bool) -> io::Resul
         for entry in entries {
             let entry = entry?;
             let path = entry.path();
-            if path.is_dir() {
-                stack.push(path);
-            } else if path.extension().map_or(false, |ext| extension(ext)) {
+            let file_type = entry.file_type()?;
+            let is_dir = if file_type.is_symlink() {
+                // Broken links have no target to read
+                match std::fs::metadata(&path) {
+                    Ok(metadata) if metadata.is_dir() && !follow_symlinks => continue,
+                    Ok(metadata) => metadata.is_dir(),
+                    Err(_) => continue,
+                }
+            } else {
+                file_type.is_dir()
+            };
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            if ignore.is_ignored(relative, is_dir) {
+                continue;
+            }
+            if is_dir {
+                if visited.insert(path.canonicalize()?) {
+                    stack.push(path);
+                }
+            } else if path.extension().is_some_and(&extension) {
                 images_paths.push(path);
             }
         }
     }
+    images_paths.sort();
+    let mut canonical_paths = HashSet::new();
+    let found = images_paths.len();
+    images_paths.retain(|path| match path.canonicalize() {
+        Ok(canonical) => canonical_paths.insert(canonical),
+        Err(_) => true,
+    });
+    if images_paths.len() < found {
+        info!(
+            "Skipping {} images found more than once through symbolic links in {}",
+            found - images_paths.len(),
+            root.display()
+        );
+    }
     Ok(images_paths)
 }
+
+/// Save an image as PNG, storing `metadata` as textual chunks (e.g. `Title`, `Author`).
+///
+/// Values which are not ASCII are written as international text (iTXt) chunks.
+pub fn save_png_with_metadata(
+    image: &DynamicImage,
+    path: &Path,
+    metadata: &[(&str, &str)],
+) -> Result<(), png::EncodingError> {
+    let writer = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(writer, image.width(), image.height());
+    encoder.set_depth(png::BitDepth::Eight);
+    let data = if image.color().has_alpha() {
+        encoder.set_color(png::ColorType::Rgba);
+        image.to_rgba8().into_raw()
+    } else {
+        encoder.set_color(png::ColorType::Rgb);
+        image.to_rgb8().into_raw()
+    };
+    for (keyword, text) in metadata {
+        if text.is_ascii() {
+            encoder.add_text_chunk(keyword.to_string(), text.to_string())?;
+        } else {
+            encoder.add_itxt_chunk(keyword.to_string(), text.to_string())?;
+        }
+    }
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()
+}
+
+/// File format of the output image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    Webp,
+    Avif,
+    Tiff,
+}
+
+impl OutputFormat {
+    /// Infer the format from the extension of `path`, if it is a supported one.
+    pub fn from_path(path: &Path) -> Option<OutputFormat> {
+        match ImageFormat::from_path(path).ok()? {
+            ImageFormat::Png => Some(OutputFormat::Png),
+            ImageFormat::Jpeg => Some(OutputFormat::Jpeg),
+            ImageFormat::WebP => Some(OutputFormat::Webp),
+            ImageFormat::Avif => Some(OutputFormat::Avif),
+            ImageFormat::Tiff => Some(OutputFormat::Tiff),
+            _ => None,
+        }
+    }
+
+    /// Largest width or height the encoder supports.
+    pub fn max_dimension(self) -> u32 {
+        match self {
+            OutputFormat::Png | OutputFormat::Tiff => u32::MAX,
+            OutputFormat::Jpeg | OutputFormat::Avif => u32::from(u16::MAX),
+            OutputFormat::Webp => 16_383,
+        }
+    }
+}
+
+/// How to encode the output image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutputOptions {
+    pub format: OutputFormat,
+    /// Quality (1-100) for lossy encodings
+    pub quality: u8,
+    /// Encoder speed (1-10, from slowest and smallest to fastest), used by AVIF
+    pub speed: u8,
+    /// Use lossless compression for WebP, which supports both
+    pub lossless: bool,
+}
+
+impl OutputOptions {
+    /// Whether the encoding discards information, using `quality`.
+    pub fn is_lossy(&self) -> bool {
+        match self.format {
+            OutputFormat::Png | OutputFormat::Tiff => false,
+            OutputFormat::Webp => !self.lossless,
+            OutputFormat::Jpeg | OutputFormat::Avif => true,
+        }
+    }
+
+    /// Rough upper bound of the encoded size in bytes of a `width`x`height` image.
+    ///
+    /// Lossless encodings are bounded by the raw pixels, and lossy ones by a bits-per-pixel
+    /// budget growing with `quality` (about 8 bits at 90, the high end for JPEG photos).
+    pub fn estimated_size(&self, width: u32, height: u32, channels: u8) -> u64 {
+        let pixels = u64::from(width) * u64::from(height);
+        if self.is_lossy() {
+            let quality = u64::from(self.quality);
+            pixels * (quality * quality) / 10_000 + pixels / 8
+        } else {
+            pixels * u64::from(channels)
+        }
+    }
+}
+
+/// The path standing for stdin as the input image, and for stdout as the output image.
+pub const STDIO: &str = "-";
+
+/// Whether `path` stands for stdin or stdout.
+pub fn is_stdio(path: &Path) -> bool {
+    path == Path::new(STDIO)
+}
+
+/// Read the image piped to stdin into a temporary file, with the extension of the format
+/// sniffed from its contents, so that it is opened like any input image. The file is removed
+/// when dropped.
+pub fn read_stdin_image() -> io::Result<NamedTempFile> {
+    read_image_to_file(io::stdin().lock())
+}
+
+/// Read the image of `reader` into a temporary file, as [`read_stdin_image`] does.
+fn read_image_to_file<R: Read>(mut reader: R) -> io::Result<NamedTempFile> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let format = ::image::guess_format(&bytes).map_err(io::Error::other)?;
+    let extension = format.extensions_str().first().copied().unwrap_or("img");
+    let mut file = tempfile::Builder::new()
+        .prefix("emosaic-stdin-")
+        .suffix(&format!(".{}", extension))
+        .tempfile()?;
+    file.write_all(&bytes)?;
+    Ok(file)
+}
+
+/// Save the output image as described by `options`, to stdout when `path` is [`STDIO`].
+///
+/// `metadata` is only stored in PNG files.
+pub fn save_output(
+    image: &DynamicImage,
+    path: &Path,
+    options: &OutputOptions,
+    metadata: &[(&str, &str)],
+) -> ImageResult<()> {
+    if is_stdio(path) {
+        return write_output(image, options, metadata, io::stdout().lock());
+    }
+    let encoding_error = |format, error: String| {
+        ::image::ImageError::Encoding(EncodingError::new(ImageFormatHint::Exact(format), error))
+    };
+    match options.format {
+        OutputFormat::Png => save_png_with_metadata(image, path, metadata)
+            .map_err(|e| encoding_error(ImageFormat::Png, e.to_string())),
+        OutputFormat::Jpeg => {
+            // JPEG has no alpha channel
+            let writer = BufWriter::new(File::create(path)?);
+            JpegEncoder::new_with_quality(writer, options.quality).encode_image(&image.to_rgb8())
+        }
+        OutputFormat::Webp => {
+            let encoder = webp::Encoder::from_image(image)
+                .map_err(|e| encoding_error(ImageFormat::WebP, e.to_string()))?;
+            let data = if options.lossless {
+                encoder.encode_lossless()
+            } else {
+                encoder.encode(f32::from(options.quality))
+            };
+            std::fs::write(path, &*data)?;
+            Ok(())
+        }
+        OutputFormat::Avif => {
+            let writer = BufWriter::new(File::create(path)?);
+            let encoder =
+                AvifEncoder::new_with_speed_quality(writer, options.speed, options.quality);
+            let (width, height) = (image.width(), image.height());
+            encoder.write_image(image.as_bytes(), width, height, image.color().into())
+        }
+        OutputFormat::Tiff => image.save_with_format(path, ImageFormat::Tiff),
+    }
+}
+
+/// Write the output image as described by `options` to `writer`, as [`save_output`] does to
+/// stdout.
+fn write_output<W: Write>(
+    image: &DynamicImage,
+    options: &OutputOptions,
+    metadata: &[(&str, &str)],
+    mut writer: W,
+) -> ImageResult<()> {
+    // Some encoders seek back in what they write, so the image goes through a file
+    let file = NamedTempFile::new()?;
+    save_output(image, file.path(), options, metadata)?;
+    io::copy(&mut File::open(file.path())?, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Fail when the output image is written to stdout without an explicit `format`, or along with
+/// the progress events written there with `json_progress`.
+pub fn check_stdout(format: Option<OutputFormat>, json_progress: bool) -> Result<(), EmosaicError> {
+    if format.is_none() {
+        return Err(EmosaicError::Validation(String::from(
+            "❌ Writing the output image to stdout needs its --format\n💡 e.g. -o - --format png",
+        )));
+    }
+    if json_progress {
+        return Err(EmosaicError::Validation(String::from(
+            "❌ --progress json writes its events to stdout, where the output image is written \
+             with -o -",
+        )));
+    }
+    Ok(())
+}
+
+/// The directory the output image at `path` is written to, the temporary directory it goes
+/// through for stdout.
+pub fn output_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        _ if is_stdio(path) => std::env::temp_dir(),
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Another mosaic rendered in the same run as the first one, with the same tiles: the path of
+/// its source image and that of its output image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Panel {
+    pub source: PathBuf,
+    pub output: PathBuf,
+}
+
+impl FromStr for Panel {
+    type Err = String;
+
+    /// Parse a panel written as `source=output`, split at the first `=`. Neither image can go
+    /// through stdin or stdout, which are those of the first mosaic.
+    fn from_str(s: &str) -> Result<Panel, String> {
+        let (source, output) = s
+            .split_once('=')
+            .filter(|(source, output)| !source.is_empty() && !output.is_empty())
+            .ok_or_else(|| String::from("Expected SOURCE=OUTPUT"))?;
+        let (source, output) = (PathBuf::from(source), PathBuf::from(output));
+        if is_stdio(&source) || is_stdio(&output) {
+            return Err(String::from(
+                "Only the first mosaic can use stdin and stdout",
+            ));
+        }
+        Ok(Panel { source, output })
+    }
+}
+
+/// Largest source image, in pixels, opened unless overridden.
+pub const DEFAULT_MAX_SOURCE_PIXELS: u64 = 250_000_000;
+
+/// Open the image at `path`, refusing it before decoding any pixel when it has more than
+/// `max_pixels` pixels, so that a huge or malicious input cannot exhaust the memory.
+///
+/// Decoders are also kept from allocating much more than an image of `max_pixels` 16 bit
+/// RGBA pixels needs, in case the header lies.
+pub fn open_limited(path: &Path, max_pixels: u64) -> Result<DynamicImage, EmosaicError> {
+    let failed = |e: ::image::ImageError| {
+        let context = format!("Failed to open image {}", path.display());
+        match e {
+            ::image::ImageError::IoError(e) => EmosaicError::io(context, e),
+            ::image::ImageError::Limits(limit)
+                if limit.kind() == LimitErrorKind::InsufficientMemory =>
+            {
+                EmosaicError::Validation(format!(
+                    "❌ Decoding {} needs more memory than allowed, raise --max-source-pixels",
+                    path.display()
+                ))
+            }
+            e => EmosaicError::decode(context, e),
+        }
+    };
+    let reader =
+        || -> ImageResult<ImageReader<_>> { Ok(ImageReader::open(path)?.with_guessed_format()?) };
+    let (width, height) = reader()
+        .and_then(|reader| reader.into_dimensions())
+        .map_err(failed)?;
+    let pixels = u64::from(width) * u64::from(height);
+    if pixels > max_pixels {
+        return Err(EmosaicError::Validation(format!(
+            "❌ {} is {}x{}, {:.1} megapixels, over the limit of {:.1}: downscale it or raise \
+             --max-source-pixels",
+            path.display(),
+            width,
+            height,
+            pixels as f64 / 1e6,
+            max_pixels as f64 / 1e6
+        )));
+    }
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(max_pixels.saturating_mul(8));
+    let mut reader = reader().map_err(failed)?;
+    reader.limits(limits);
+    reader.decode().map_err(failed)
+}
+
+/// Dimensions of the smallest canvas holding a `width`x`height` image rotated by `degrees`.
+pub fn rotated_dimensions((width, height): (u32, u32), degrees: f64) -> (u32, u32) {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (width, height) = (f64::from(width), f64::from(height));
+    // Rounding errors of right angles must not add a pixel
+    let fit = |length: f64| (length - 1e-6).ceil().max(1.0) as u32;
+    (
+        fit(width * cos.abs() + height * sin.abs()),
+        fit(width * sin.abs() + height * cos.abs()),
+    )
+}
+
+/// Rotate `image` clockwise by `degrees` onto a canvas of `dimensions` with the same center,
+/// sampled bilinearly. The edges of the image are stretched over the parts of the canvas it
+/// does not cover, so that tiles matched there blend in with the picture.
+pub fn rotate(image: &RgbImage, degrees: f64, (width, height): (u32, u32)) -> RgbImage {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (image_width, image_height) = (f64::from(image.width()), f64::from(image.height()));
+    let mut rotated = RgbImage::new(width, height);
+    rotated
+        .par_enumerate_pixels_mut()
+        .for_each(|(x, y, pixel)| {
+            // Turn the offset from the center of the canvas back to the image
+            let dx = f64::from(x) + 0.5 - f64::from(width) / 2.0;
+            let dy = f64::from(y) + 0.5 - f64::from(height) / 2.0;
+            let u = ((dx * cos + dy * sin) / image_width + 0.5).clamp(0.0, 1.0);
+            let v = ((dy * cos - dx * sin) / image_height + 0.5).clamp(0.0, 1.0);
+            if let Some(color) = sample_bilinear(image, u as f32, v as f32) {
+                *pixel = color;
+            }
+        });
+    rotated
+}
+
+/// Restrict the mosaic to the white areas of `mask`, showing `source` scaled up in the black
+/// areas and blending the two in the grays in between. Both images are stretched over the
+/// mosaic and sampled bilinearly, so that the background stays smooth across tiles.
+pub fn apply_mask(mut mosaic: RgbImage, source: &RgbImage, mask: &GrayImage) -> RgbImage {
+    let (width, height) = mosaic.dimensions();
+    mosaic.par_enumerate_pixels_mut().for_each(|(x, y, pixel)| {
+        let u = (x as f32 + 0.5) / width as f32;
+        let v = (y as f32 + 0.5) / height as f32;
+        let weight = match sample_bilinear(mask, u, v) {
+            Some(luma) => f32::from(luma[0]) / 255.0,
+            None => return,
+        };
+        if weight >= 1.0 {
+            return;
+        }
+        if let Some(background) = sample_bilinear(source, u, v) {
+            for (channel, &photo) in pixel.0.iter_mut().zip(background.0.iter()) {
+                *channel = (f32::from(*channel) * weight + f32::from(photo) * (1.0 - weight))
+                    .round() as u8;
+            }
+        }
+    });
+    mosaic
+}
+
+/// A rectangle of the output image, in pixels from its top left corner, kept clear of tiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClearRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for ClearRegion {
+    type Err = String;
+
+    /// Parse a region written as `x,y,w,h`.
+    fn from_str(s: &str) -> Result<ClearRegion, String> {
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<u32>().map_err(|e| e.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        match values[..] {
+            [x, y, width, height] => Ok(ClearRegion {
+                x,
+                y,
+                width,
+                height,
+            }),
+            _ => Err(String::from("Expected x,y,w,h")),
+        }
+    }
+}
+
+/// What to show in the regions kept clear of tiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ClearFill {
+    /// The source image scaled up
+    Source,
+    /// Plain white
+    Blank,
+}
+
+/// Replace the tiles in `regions` of the mosaic with `fill`, clipping the regions to the
+/// mosaic. The source image is sampled as in `apply_mask`.
+pub fn clear_regions(
+    mut mosaic: RgbImage,
+    source: &RgbImage,
+    regions: &[ClearRegion],
+    fill: ClearFill,
+) -> RgbImage {
+    let (width, height) = mosaic.dimensions();
+    for region in regions {
+        let x_end = region.x.saturating_add(region.width).min(width);
+        let y_end = region.y.saturating_add(region.height).min(height);
+        for y in region.y..y_end {
+            for x in region.x..x_end {
+                let pixel = match fill {
+                    ClearFill::Blank => Rgb([255, 255, 255]),
+                    ClearFill::Source => {
+                        let u = (x as f32 + 0.5) / width as f32;
+                        let v = (y as f32 + 0.5) / height as f32;
+                        match sample_bilinear(source, u, v) {
+                            Some(pixel) => pixel,
+                            None => continue,
+                        }
+                    }
+                };
+                mosaic.put_pixel(x, y, pixel);
+            }
+        }
+    }
+    mosaic
+}
+
+/// The columns and rows of the cells of a `grid` over a mosaic of `size` pixels that show no
+/// tile once `mask` and the clear `regions` are applied: those wholly inside a region, or
+/// wholly where the mask stretched as in `apply_mask` is black.
+pub fn hidden_cells(
+    grid: (u32, u32),
+    size: (u32, u32),
+    regions: &[ClearRegion],
+    mask: Option<&GrayImage>,
+) -> HashSet<(u32, u32)> {
+    let (cols, rows) = grid;
+    let (width, height) = size;
+    let tile_size = (width / cols.max(1), height / rows.max(1));
+    let cleared = |x: u32, y: u32| {
+        regions.iter().any(|region| {
+            region.x <= x
+                && region.y <= y
+                && x + tile_size.0 <= region.x.saturating_add(region.width)
+                && y + tile_size.1 <= region.y.saturating_add(region.height)
+        })
+    };
+    let masked = |x: u32, y: u32| {
+        let Some(mask) = mask else {
+            return false;
+        };
+        (y..y + tile_size.1).all(|y| {
+            (x..x + tile_size.0).all(|x| {
+                let u = (x as f32 + 0.5) / width as f32;
+                let v = (y as f32 + 0.5) / height as f32;
+                matches!(sample_bilinear(mask, u, v), Some(luma) if luma[0] == 0)
+            })
+        })
+    };
+    (0..cols * rows)
+        .into_par_iter()
+        .map(|cell| (cell % cols, cell / cols))
+        .filter(|&(col, row)| {
+            let (x, y) = (col * tile_size.0, row * tile_size.1);
+            cleared(x, y) || masked(x, y)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::RgbImage;
+
+    #[test]
+    fn test_save_png_with_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("metadata.png");
+        let image = DynamicImage::ImageRgb8(RgbImage::new(3, 2));
+        save_png_with_metadata(&image, &path, &[("Title", "Holiday"), ("Author", "Zoë")]).unwrap();
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let reader = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!((info.width, info.height), (3, 2));
+        assert_eq!(info.uncompressed_latin1_text[0].keyword, "Title");
+        assert_eq!(info.uncompressed_latin1_text[0].text, "Holiday");
+        assert_eq!(info.utf8_text[0].keyword, "Author");
+        assert_eq!(info.utf8_text[0].get_text().unwrap(), "Zoë");
+    }
+
+    #[test]
+    fn test_output_format_from_path() {
+        let format = |path| OutputFormat::from_path(Path::new(path));
+        assert_eq!(format("a.png"), Some(OutputFormat::Png));
+        assert_eq!(format("a.JPG"), Some(OutputFormat::Jpeg));
+        assert_eq!(format("a.jpeg"), Some(OutputFormat::Jpeg));
+        assert_eq!(format("a.webp"), Some(OutputFormat::Webp));
+        assert_eq!(format("a.avif"), Some(OutputFormat::Avif));
+        assert_eq!(format("a.tif"), Some(OutputFormat::Tiff));
+        assert_eq!(format("a.gif"), None);
+        assert_eq!(format("output"), None);
+    }
+
+    fn options(format: OutputFormat) -> OutputOptions {
+        OutputOptions {
+            format,
+            quality: 90,
+            speed: 10,
+            lossless: false,
+        }
+    }
+
+    #[test]
+    fn test_output_options_estimated_size() {
+        assert_eq!(options(OutputFormat::Png).estimated_size(100, 10, 3), 3000);
+        assert_eq!(options(OutputFormat::Tiff).estimated_size(100, 10, 4), 4000);
+        let lossless_webp = OutputOptions {
+            lossless: true,
+            ..options(OutputFormat::Webp)
+        };
+        assert_eq!(lossless_webp.estimated_size(100, 10, 3), 3000);
+        let jpeg = |quality| OutputOptions {
+            quality,
+            ..options(OutputFormat::Jpeg)
+        };
+        let png = options(OutputFormat::Png).estimated_size(1000, 1000, 3);
+        assert!(jpeg(90).estimated_size(1000, 1000, 3) < png);
+        assert!(jpeg(50).estimated_size(1000, 1000, 3) < jpeg(90).estimated_size(1000, 1000, 3));
+        assert!(jpeg(1).estimated_size(1000, 1000, 3) > 0);
+        assert!(options(OutputFormat::Webp).estimated_size(1000, 1000, 3) < png);
+    }
+
+    #[test]
+    fn test_save_output_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        for image in [
+            DynamicImage::ImageRgb8(::image::RgbImage::new(4, 4)),
+            DynamicImage::ImageRgba8(::image::RgbaImage::new(4, 4)),
+        ] {
+            for (options, expected) in [
+                (options(OutputFormat::Png), ImageFormat::Png),
+                (options(OutputFormat::Jpeg), ImageFormat::Jpeg),
+                (options(OutputFormat::Webp), ImageFormat::WebP),
+                (
+                    OutputOptions {
+                        lossless: true,
+                        ..options(OutputFormat::Webp)
+                    },
+                    ImageFormat::WebP,
+                ),
+                (options(OutputFormat::Avif), ImageFormat::Avif),
+                (options(OutputFormat::Tiff), ImageFormat::Tiff),
+            ] {
+                let name = format!("output-{:?}-{}", options.format, options.lossless);
+                let path = dir.path().join(name);
+                save_output(&image, &path, &options, &[]).unwrap();
+                let bytes = std::fs::read(&path).unwrap();
+                if expected == ImageFormat::Avif {
+                    // Not recognised by `guess_format` without the AVIF decoder
+                    assert_eq!(&bytes[4..12], b"ftypavif");
+                } else {
+                    assert_eq!(::image::guess_format(&bytes).unwrap(), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_stdio() {
+        assert!(is_stdio(Path::new("-")));
+        assert!(!is_stdio(Path::new("./-")) && !is_stdio(Path::new("out.png")));
+        assert_eq!(output_dir(Path::new("-")), std::env::temp_dir());
+        assert_eq!(output_dir(Path::new("out.png")), Path::new("."));
+        assert_eq!(
+            output_dir(Path::new("mosaics/out.png")),
+            Path::new("mosaics")
+        );
+
+        assert!(check_stdout(Some(OutputFormat::Png), false).is_ok());
+        let error = check_stdout(None, false).unwrap_err().to_string();
+        assert!(error.contains("--format"), "{}", error);
+        let error = check_stdout(Some(OutputFormat::Png), true)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("json"), "{}", error);
+
+        let panel: Panel = "left.jpg=mosaics/left.png".parse().unwrap();
+        assert_eq!(panel.source, Path::new("left.jpg"));
+        assert_eq!(panel.output, Path::new("mosaics/left.png"));
+        assert!("left.jpg".parse::<Panel>().is_err());
+        assert!("left.jpg=".parse::<Panel>().is_err());
+        assert!("left.jpg=-".parse::<Panel>().is_err());
+
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 3, Rgb([10, 20, 30])));
+        let mut bytes = Vec::new();
+        write_output(&image, &options(OutputFormat::Png), &[], &mut bytes).unwrap();
+        assert_eq!(::image::guess_format(&bytes).unwrap(), ImageFormat::Png);
+
+        // The piped image is opened from a file named after its format
+        let file = read_image_to_file(bytes.as_slice()).unwrap();
+        assert_eq!(file.path().extension().unwrap(), "png");
+        assert_eq!(
+            ::image::open(file.path()).unwrap().to_rgb8(),
+            image.to_rgb8()
+        );
+        assert!(read_image_to_file(&b"not an image"[..]).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_find_images_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let (tiles, elsewhere) = (dir.join("tiles"), dir.join("elsewhere"));
+        std::fs::create_dir_all(tiles.join("album")).unwrap();
+        std::fs::create_dir_all(&elsewhere).unwrap();
+        std::fs::write(tiles.join("album/1.jpg"), b"").unwrap();
+        std::fs::write(elsewhere.join("2.jpg"), b"").unwrap();
+        // A farm of links to the album, a cycle, a link to a file and a link to another album
+        symlink(tiles.join("album"), tiles.join("farm")).unwrap();
+        symlink(&tiles, tiles.join("album/cycle")).unwrap();
+        symlink(tiles.join("album/1.jpg"), tiles.join("link.jpg")).unwrap();
+        symlink(&elsewhere, tiles.join("linked")).unwrap();
+        symlink(dir.join("missing"), tiles.join("broken.jpg")).unwrap();
+
+        let jpg = |extension: &OsStr| extension == "jpg";
+        let library = LibraryOptions::default();
+        assert_eq!(
+            find_images(&tiles, jpg, &library).unwrap(),
+            [tiles.join("album/1.jpg")]
+        );
+        let library = LibraryOptions {
+            follow_symlinks: true,
+            ..LibraryOptions::default()
+        };
+        assert_eq!(
+            find_images(&tiles, jpg, &library).unwrap(),
+            [tiles.join("album/1.jpg"), tiles.join("linked/2.jpg")]
+        );
+    }
+
+    #[test]
+    fn test_open_limited() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("open-limited.png");
+        RgbImage::from_pixel(20, 10, Rgb([1, 2, 3]))
+            .save(&path)
+            .unwrap();
+        assert_eq!(
+            open_limited(&path, 200).unwrap().to_rgb8().dimensions(),
+            (20, 10)
+        );
+        let error = open_limited(&path, 199).unwrap_err();
+        assert!(
+            error.to_string().contains("--max-source-pixels"),
+            "{}",
+            error
+        );
+        assert_eq!(error.exit_code(), 2);
+        let error = open_limited(Path::new("missing.png"), 200).unwrap_err();
+        assert!(matches!(error, EmosaicError::Io { .. }), "{}", error);
+    }
+
+    #[test]
+    fn test_apply_mask() {
+        let mosaic = RgbImage::from_pixel(4, 2, ::image::Rgb([0, 0, 0]));
+        let source = RgbImage::from_pixel(2, 1, ::image::Rgb([200, 100, 50]));
+        // Mosaic on the left, photo on the right
+        let mask = GrayImage::from_fn(4, 2, |x, _| ::image::Luma([if x < 2 { 255 } else { 0 }]));
+
+        let masked = apply_mask(mosaic, &source, &mask);
+        assert_eq!(masked.get_pixel(0, 0).0, [0, 0, 0]);
+        assert_eq!(masked.get_pixel(3, 1).0, [200, 100, 50]);
+
+        let gray = GrayImage::from_pixel(1, 1, ::image::Luma([51]));
+        let masked = apply_mask(masked, &source, &gray);
+        assert_eq!(masked.get_pixel(0, 0).0, [160, 80, 40]);
+    }
+
+    #[test]
+    fn test_rotate() {
+        assert_eq!(rotated_dimensions((4, 2), 90.0), (2, 4));
+        assert_eq!(rotated_dimensions((4, 2), -180.0), (4, 2));
+        assert_eq!(rotated_dimensions((10, 10), 45.0), (15, 15));
+
+        let image = RgbImage::from_fn(4, 2, |x, _| ::image::Rgb([x as u8 * 50, 0, 0]));
+        let rotated = rotate(&image, 90.0, (2, 4));
+        // The left column turns into the top row
+        assert_eq!(rotated.get_pixel(0, 0).0, [0, 0, 0]);
+        assert_eq!(rotated.get_pixel(1, 3).0, [150, 0, 0]);
+        let back = rotate(&rotated, -90.0, (4, 2));
+        assert_eq!(back, image);
+
+        // The edges of the image are stretched over the corners of a larger canvas
+        let rotated = rotate(&image, 45.0, rotated_dimensions((4, 2), 45.0));
+        assert_eq!(rotated.dimensions(), (5, 5));
+        assert_eq!(rotated.get_pixel(0, 0).0, [0, 0, 0]);
+        assert_eq!(rotated.get_pixel(4, 4).0, [150, 0, 0]);
+    }
+
+    #[test]
+    fn test_clear_regions() {
+        let region: ClearRegion = "1, 0,2,5".parse().unwrap();
+        assert_eq!(
+            region,
+            ClearRegion {
+                x: 1,
+                y: 0,
+                width: 2,
+                height: 5
+            }
+        );
+        assert!("1,2,3".parse::<ClearRegion>().is_err());
+        assert!("1,2,3,-4".parse::<ClearRegion>().is_err());
+
+        let mosaic = RgbImage::from_pixel(4, 2, ::image::Rgb([0, 0, 0]));
+        let source = RgbImage::from_pixel(2, 1, ::image::Rgb([200, 100, 50]));
+        let cleared = clear_regions(mosaic, &source, &[region], ClearFill::Source);
+        assert_eq!(cleared.get_pixel(0, 0).0, [0, 0, 0]);
+        assert_eq!(cleared.get_pixel(2, 1).0, [200, 100, 50]);
+        assert_eq!(cleared.get_pixel(3, 1).0, [0, 0, 0]);
+
+        let cleared = clear_regions(cleared, &source, &[region], ClearFill::Blank);
+        assert_eq!(cleared.get_pixel(1, 0).0, [255, 255, 255]);
+    }
+
+    #[test]
+    fn test_hidden_cells() {
+        // 4x2 tiles of 2x2 pixels, the mask black over the two columns on the right
+        let mask = GrayImage::from_fn(8, 4, |x, _| ::image::Luma([if x < 4 { 255 } else { 0 }]));
+        let region = ClearRegion {
+            x: 0,
+            y: 0,
+            width: 3,
+            height: 2,
+        };
+        let mut hidden: Vec<_> = hidden_cells((4, 2), (8, 4), &[region], Some(&mask))
+            .into_iter()
+            .collect();
+        hidden.sort();
+        // The region only covers the first tile whole
+        assert_eq!(hidden, [(0, 0), (2, 0), (2, 1), (3, 0), (3, 1)]);
+        assert!(hidden_cells((4, 2), (8, 4), &[], None).is_empty());
+    }
+}
@@ -0,0 +1,140 @@
+use std::str::FromStr;
+
+use rand::Rng;
+
+/// An experimental arrangement of the tiles other than the regular grid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// Rectangles of one to `max_cells` grid cells a side, cut at random
+    Irregular { max_cells: u32 },
+}
+
+impl Layout {
+    /// Largest side of an irregular rectangle, in grid cells, when none is given.
+    pub const DEFAULT_MAX_CELLS: u32 = 4;
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    /// Parse a layout written as `irregular` or `irregular:max_cells`.
+    fn from_str(s: &str) -> Result<Layout, String> {
+        let (name, max_cells) = match s.split_once(':') {
+            Some((name, max_cells)) => match max_cells.parse::<u32>() {
+                Ok(max_cells) if max_cells > 0 => (name, max_cells),
+                _ => {
+                    return Err(format!(
+                        "The largest side must be a positive number of cells, got {}",
+                        max_cells
+                    ))
+                }
+            },
+            None => (s, Layout::DEFAULT_MAX_CELLS),
+        };
+        match name {
+            "irregular" => Ok(Layout::Irregular { max_cells }),
+            _ => Err(format!(
+                "Unknown layout {:?}, expected irregular[:max_cells]",
+                name
+            )),
+        }
+    }
+}
+
+/// A rectangle of grid cells, by the column and row of its top left cell and its size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Cut a `width`x`height` grid into rectangles of one to `max_cells` cells a side, at most
+/// twice as long as they are wide, so that tiles cropped to them keep most of the picture.
+///
+/// Rectangles are cut across their longer side at a random cell while they are too large
+/// or too long, and half of the time otherwise, so that every size shows up.
+pub fn cut_rectangles(width: u32, height: u32, max_cells: u32, rng: &mut impl Rng) -> Vec<Rect> {
+    let mut rects = Vec::new();
+    let mut pending = vec![Rect {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    }];
+    while let Some(rect) = pending.pop() {
+        if rect.width == 0 || rect.height == 0 {
+            continue;
+        }
+        let (long, short) = (rect.width.max(rect.height), rect.width.min(rect.height));
+        let must_cut = long > max_cells || long > 2 * short;
+        if long == 1 || !(must_cut || rng.gen_bool(0.5)) {
+            rects.push(rect);
+            continue;
+        }
+        let at = rng.gen_range(1, long);
+        if rect.width >= rect.height {
+            pending.push(Rect { width: at, ..rect });
+            pending.push(Rect {
+                x: rect.x + at,
+                width: rect.width - at,
+                ..rect
+            });
+        } else {
+            pending.push(Rect { height: at, ..rect });
+            pending.push(Rect {
+                y: rect.y + at,
+                height: rect.height - at,
+                ..rect
+            });
+        }
+    }
+    rects
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_parse_layout() {
+        assert_eq!(
+            "irregular".parse(),
+            Ok(Layout::Irregular {
+                max_cells: Layout::DEFAULT_MAX_CELLS
+            })
+        );
+        assert_eq!(
+            "irregular:6".parse(),
+            Ok(Layout::Irregular { max_cells: 6 })
+        );
+        assert!("irregular:0".parse::<Layout>().is_err());
+        assert!("penrose".parse::<Layout>().is_err());
+    }
+
+    #[test]
+    fn test_cut_rectangles() {
+        let rects = cut_rectangles(13, 7, 3, &mut StdRng::seed_from_u64(1));
+        let mut covered = vec![0; 13 * 7];
+        for rect in &rects {
+            assert!(rect.width.max(rect.height) <= 3, "{:?}", rect);
+            assert!(rect.width.max(rect.height) <= 2 * rect.width.min(rect.height));
+            for y in rect.y..rect.y + rect.height {
+                for x in rect.x..rect.x + rect.width {
+                    covered[(y * 13 + x) as usize] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|&count| count == 1), "{:?}", covered);
+        assert!(rects.iter().any(|rect| rect.width > 1));
+
+        assert_eq!(
+            cut_rectangles(1, 1, 1, &mut StdRng::seed_from_u64(1)).len(),
+            1
+        );
+        assert!(cut_rectangles(0, 5, 3, &mut StdRng::seed_from_u64(1)).is_empty());
+    }
+}
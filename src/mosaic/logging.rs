@@ -0,0 +1,254 @@
+use std::fmt;
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::fmt::format::{FmtSpan, Writer};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, MakeWriter};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+/// Time spent in every phase that ended so far, by name, in the order they first ended.
+static PHASES: Mutex<Vec<(&'static str, Duration)>> = Mutex::new(Vec::new());
+
+/// Format of the messages written to stderr.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Plain messages, prefixed with their level and phase when verbose
+    #[default]
+    Text,
+    /// One JSON object per message, with its time, level, phases and fields
+    Json,
+}
+
+/// The most detailed messages shown, from the number of `-v` and `-q` given: warnings and
+/// errors only with `-q`, errors only with `-qq`, the timings of the phases with `-v` and
+/// everything with `-vv`.
+pub fn level(verbose: u8, quiet: u8) -> LevelFilter {
+    match i16::from(verbose) - i16::from(quiet) {
+        i16::MIN..=-2 => LevelFilter::ERROR,
+        -1 => LevelFilter::WARN,
+        0 => LevelFilter::INFO,
+        1 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+/// Write the messages up to `level` to stderr in `format`, with the time spent in every phase
+/// when it ends, and time the phases for [`phase_durations`] whatever the level.
+pub fn init(level: LevelFilter, format: LogFormat) {
+    let phases = filter_fn(|metadata| metadata.is_span()).with_max_level_hint(LevelFilter::DEBUG);
+    tracing_subscriber::registry()
+        .with(messages(level, format, io::stderr))
+        .with(PhaseTimer.with_filter(phases))
+        .init();
+}
+
+/// The layer writing the messages up to `level` to `writer` in `format`, with the time spent in
+/// every phase when it ends. JSON messages have their time, level, target, the phases they were
+/// written in from the outermost, message and other fields.
+fn messages<S, W>(
+    level: LevelFilter,
+    format: LogFormat,
+    writer: W,
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let messages = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(false)
+        .with_span_events(FmtSpan::CLOSE);
+    match format {
+        LogFormat::Text => {
+            let verbose = level > LevelFilter::INFO;
+            messages
+                .event_format(TextFormat { verbose })
+                .with_filter(level)
+                .boxed()
+        }
+        LogFormat::Json => messages
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(true)
+            .with_filter(level)
+            .boxed(),
+    }
+}
+
+/// The time spent in every phase that ended so far, summed over the phases of the same name,
+/// e.g. `analysis`, `index`, `scoring` and `compositing`, in the order they first ended.
+pub fn phase_durations() -> Vec<(&'static str, Duration)> {
+    PHASES.lock().unwrap().clone()
+}
+
+/// When a phase started.
+struct Started(Instant);
+
+/// Records the time spent in every span, as a phase.
+pub struct PhaseTimer;
+
+impl<S> Layer<S> for PhaseTimer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, _: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Started(Instant::now()));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(elapsed) = span.extensions().get::<Started>().map(|s| s.0.elapsed()) else {
+            return;
+        };
+        let mut phases = PHASES.lock().unwrap();
+        match phases.iter_mut().find(|(name, _)| *name == span.name()) {
+            Some((_, duration)) => *duration += elapsed,
+            None => phases.push((span.name(), elapsed)),
+        }
+    }
+}
+
+/// Messages as they were always printed, and with their level and phase too when verbose.
+pub struct TextFormat {
+    pub verbose: bool,
+}
+
+impl<S, N> FormatEvent<S, N> for TextFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        if self.verbose {
+            write!(writer, "{:>5} ", event.metadata().level())?;
+            if let Some(scope) = ctx.event_scope() {
+                for span in scope.from_root() {
+                    write!(writer, "{}:", span.name())?;
+                }
+                write!(writer, " ")?;
+            }
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::sync::{Arc, Mutex};
+
+    /// Messages written by a subscriber, shared with the test.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// The messages written while `f` runs, up to `level`, in `format`.
+    fn capture(level: LevelFilter, format: LogFormat, f: impl FnOnce()) -> String {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber =
+            tracing_subscriber::registry().with(messages(level, format, move || writer.clone()));
+        tracing::subscriber::with_default(subscriber, f);
+        let bytes = captured.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    fn log() {
+        let _span = tracing::debug_span!("analysis").entered();
+        tracing::info!("Analysed {} tiles", 3);
+        tracing::debug!(skipped = 1, "Skipped the broken ones");
+    }
+
+    #[test]
+    fn test_level() {
+        assert_eq!(level(0, 0), LevelFilter::INFO);
+        assert_eq!(level(1, 0), LevelFilter::DEBUG);
+        assert_eq!(level(3, 0), LevelFilter::TRACE);
+        assert_eq!(level(0, 1), LevelFilter::WARN);
+        assert_eq!(level(0, 2), LevelFilter::ERROR);
+        assert_eq!(level(1, 1), LevelFilter::INFO);
+    }
+
+    #[test]
+    fn test_text_format() {
+        let text = capture(LevelFilter::INFO, LogFormat::Text, log);
+        assert_eq!(text, "Analysed 3 tiles\n");
+
+        let text = capture(LevelFilter::DEBUG, LogFormat::Text, log);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], " INFO analysis: Analysed 3 tiles");
+        assert_eq!(
+            lines[1],
+            "DEBUG analysis: Skipped the broken ones skipped=1"
+        );
+        // The time spent in the phase when it ends
+        assert!(lines[2].starts_with("DEBUG analysis: close time.busy="));
+    }
+
+    #[test]
+    fn test_phase_timer() {
+        let subscriber = tracing_subscriber::registry().with(PhaseTimer);
+        tracing::subscriber::with_default(subscriber, || {
+            for _ in 0..2 {
+                let _span = tracing::debug_span!("test_phase").entered();
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        });
+        let phases = phase_durations();
+        let (_, duration) = phases
+            .iter()
+            .find(|(name, _)| *name == "test_phase")
+            .unwrap();
+        // Both phases of the name are summed
+        assert!(*duration >= Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_json_format() {
+        let json = capture(LevelFilter::DEBUG, LogFormat::Json, log);
+        let events: Vec<Value> = json
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["level"], "INFO");
+        assert_eq!(events[0]["message"], "Analysed 3 tiles");
+        assert_eq!(
+            events[0]["spans"],
+            serde_json::json!([{"name": "analysis"}])
+        );
+        assert!(events[0]["timestamp"].is_string());
+        assert_eq!(events[1]["skipped"], 1);
+        assert_eq!(events[2]["message"], "close");
+        assert!(events[2]["time.busy"].is_string());
+    }
+}
@@ -0,0 +1,168 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::stats::{MosaicConfig, RenderStats};
+use super::tiles::TileSet;
+
+/// A tile placed in the mosaic, as written to the manifest.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Placement {
+    /// Column of the tile in the mosaic grid
+    pub x: usize,
+    /// Row of the tile in the mosaic grid
+    pub y: usize,
+    pub path: PathBuf,
+    pub flipped: bool,
+    pub distance: f64,
+    pub date: Option<String>,
+    /// Color of the synthetic tile placed instead of an image, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filler: Option<[u8; 3]>,
+}
+
+/// Every tile placement of a mosaic, in row major order, with the configuration used to
+/// generate it, for external tools to post-process, audit or re-render the mosaic.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub config: MosaicConfig,
+    pub placements: Vec<Placement>,
+}
+
+impl Manifest {
+    /// Read a manifest written as JSON by [`Manifest::write`].
+    pub fn read<R: Read>(reader: R) -> Result<Manifest, std::io::Error> {
+        serde_json::from_reader(reader).map_err(io::Error::from)
+    }
+
+    /// Read the manifest in the file at `path`.
+    pub fn load(path: &Path) -> Result<Manifest, std::io::Error> {
+        Manifest::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Write the manifest as JSON.
+    pub fn write<W: Write>(&self, writer: W) -> Result<(), std::io::Error> {
+        serde_json::to_writer_pretty(writer, self).map_err(io::Error::from)
+    }
+
+    /// Write the manifest to the file at `path`.
+    pub fn save(&self, path: &Path) -> Result<(), std::io::Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Number of columns and rows of the mosaic grid.
+    pub fn grid_size(&self) -> (usize, usize) {
+        self.placements
+            .iter()
+            .fold((0, 0), |(cols, rows), placement| {
+                (cols.max(placement.x + 1), rows.max(placement.y + 1))
+            })
+    }
+}
+
+impl<D> RenderStats<D>
+where
+    f64: From<D>,
+    D: std::cmp::Ord,
+    D: std::convert::From<u8>,
+    D: std::ops::AddAssign,
+    D: Copy,
+    D: std::fmt::Display,
+{
+    /// Build the manifest of every tile placement together with the configuration used to
+    /// generate the mosaic.
+    ///
+    /// # Arguments
+    /// * `tile_set` - The tile set used for generating the mosaic
+    /// * `config` - Configuration settings used to generate the mosaic
+    pub fn manifest<T>(&self, tile_set: &TileSet<T>, config: &MosaicConfig) -> Manifest {
+        let placements = self
+            .grid_cells()
+            .into_iter()
+            .map(|(col, row)| {
+                let tile = &self.tiles()[&(col, row)];
+                Placement {
+                    x: col as usize,
+                    y: row as usize,
+                    path: tile_set.get_path(tile).to_path_buf(),
+                    flipped: tile.flipped,
+                    distance: tile.colors.into(),
+                    date: tile.date_taken.clone(),
+                    filler: tile_set.filler_color(tile).map(|color| color.0),
+                }
+            })
+            .collect();
+        Manifest {
+            config: config.clone(),
+            placements,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        tile_set.push_tile(PathBuf::from("a.jpg"), [Rgb([255, 0, 0])]);
+        tile_set.push_tile(PathBuf::from("b.jpg"), [Rgb([0, 0, 255])]);
+        let mut flipped = tile_set.tiles[1].clone();
+        flipped.flipped = true;
+        flipped.date_taken = Some("2024:06:01 12:00:00".to_string());
+
+        let mut stats: RenderStats<u32> = RenderStats::new();
+        stats.push_tile(1, 0, &flipped, 7);
+        stats.push_tile(0, 0, &tile_set.tiles[0], 3);
+        let config = MosaicConfig {
+            tile_size: 16,
+            mode: "test".to_string(),
+            no_repeat: false,
+            greedy: false,
+            story: false,
+            time_gradient: None,
+            filler: None,
+            crop: false,
+            tint_opacity: 0.0,
+            downsample: 1,
+            randomize: None,
+            randomize_max_variance: None,
+            tiles_dir: "tiles".to_string(),
+            title: "Test Mosaic".to_string(),
+            description: None,
+            author: None,
+            thumbnail_size: None,
+            export_quality: None,
+            export_strip_exif: false,
+            date_format: Default::default(),
+            palette: Default::default(),
+            tile_links: Default::default(),
+        };
+
+        let mut json = Vec::new();
+        let manifest = stats.manifest(&tile_set, &config);
+        manifest.write(&mut json).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value["config"]["tile_size"], 16);
+        assert_eq!(value["config"]["title"], "Test Mosaic");
+        assert_eq!(
+            value["placements"],
+            serde_json::json!([
+                {"x": 0, "y": 0, "path": "a.jpg", "flipped": false, "distance": 3.0, "date": null},
+                {"x": 1, "y": 0, "path": "b.jpg", "flipped": true, "distance": 7.0, "date": "2024:06:01 12:00:00"},
+            ])
+        );
+
+        let read = Manifest::read(json.as_slice()).unwrap();
+        assert_eq!(read.placements, manifest.placements);
+        assert_eq!(read.config.title, "Test Mosaic");
+        assert_eq!(read.grid_size(), (2, 1));
+    }
+}
@@ -0,0 +1,265 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use super::error::EmosaicError;
+
+/// How often the memory used is measured.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Share of the memory budget beyond which the run is under pressure.
+const PRESSURE_SHARE: f64 = 0.8;
+
+/// Whether the memory used last approached the budget.
+static PRESSURE: AtomicBool = AtomicBool::new(false);
+
+/// Whether a [`MemoryMonitor`] measured the memory used approaching the budget last time.
+pub fn under_pressure() -> bool {
+    PRESSURE.load(Ordering::Relaxed)
+}
+
+/// Parse a number of bytes with an optional binary unit, e.g. `4G`, `512MB` or `1.5GiB`.
+pub fn parse_size(s: &str) -> Result<u64, EmosaicError> {
+    let s = s.trim();
+    let digits = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(digits);
+    let number: f64 = number.parse().map_err(|_| {
+        EmosaicError::Validation(format!("Invalid size {:?}, expected e.g. 4G or 512M", s))
+    })?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let unit = unit.trim_end_matches('B').trim_end_matches('I');
+    let exponent = match unit {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        _ => {
+            return Err(EmosaicError::Validation(format!(
+                "Invalid size unit in {:?}, expected K, M, G or T",
+                s
+            )))
+        }
+    };
+    let bytes = number * 1024f64.powi(exponent);
+    if bytes < 1.0 {
+        return Err(EmosaicError::Validation(String::from(
+            "Size must be at least one byte",
+        )));
+    }
+    Ok(bytes as u64)
+}
+
+/// Resident memory of the current process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Resident memory now, in KB
+    pub current_kb: u64,
+    /// Most resident memory since the process started, in KB, as far as the system tracks it
+    pub peak_kb: u64,
+}
+
+/// Measure the resident memory of the current process, where the system tells it: from
+/// `/proc` on Linux, `task_info` on macOS and `GetProcessMemoryInfo` on Windows.
+pub fn memory_usage() -> Option<MemoryUsage> {
+    platform::memory_usage()
+}
+
+/// Memory monitor that tracks peak RSS usage in a background thread, and whether it approaches
+/// a memory budget
+pub struct MemoryMonitor {
+    peak_rss_kb: Arc<AtomicU64>,
+    shutdown: Arc<AtomicBool>,
+    _handle: thread::JoinHandle<()>,
+}
+
+impl MemoryMonitor {
+    /// Start monitoring memory usage in a background thread, against `budget` bytes if given
+    pub fn start(budget: Option<u64>) -> Self {
+        let budget_kb = budget.map_or(0, |bytes| (bytes / 1024).max(1));
+        let peak_rss_kb = Arc::new(AtomicU64::new(0));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let peak_rss_kb_clone = Arc::clone(&peak_rss_kb);
+        let shutdown_clone = Arc::clone(&shutdown);
+
+        let handle = thread::spawn(move || {
+            while !shutdown_clone.load(Ordering::Relaxed) {
+                if let Some(usage) = memory_usage() {
+                    peak_rss_kb_clone
+                        .fetch_max(usage.current_kb.max(usage.peak_kb), Ordering::Relaxed);
+                    let pressure = budget_kb > 0
+                        && usage.current_kb as f64 >= budget_kb as f64 * PRESSURE_SHARE;
+                    PRESSURE.store(pressure, Ordering::Relaxed);
+                }
+                thread::sleep(SAMPLE_INTERVAL);
+            }
+        });
+
+        Self {
+            peak_rss_kb,
+            shutdown,
+            _handle: handle,
+        }
+    }
+
+    /// Get the peak memory usage in MB, if it could be measured
+    pub fn peak_mb(&self) -> Option<f64> {
+        // The system peak also covers the time since the last sample
+        let system_peak_kb = memory_usage().map_or(0, |usage| usage.peak_kb);
+        let peak_kb = self.peak_rss_kb.load(Ordering::Relaxed).max(system_peak_kb);
+        (peak_kb > 0).then(|| peak_kb as f64 / 1024.0)
+    }
+
+    /// Get the peak memory usage in MB
+    pub fn get_peak_mb(&self) -> String {
+        match self.peak_mb() {
+            Some(peak_mb) => format!("{:.1}", peak_mb),
+            None => "N/A".to_string(),
+        }
+    }
+}
+
+impl Drop for MemoryMonitor {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::MemoryUsage;
+
+    pub fn memory_usage() -> Option<MemoryUsage> {
+        let status = std::fs::read_to_string("/proc/self/status").ok()?;
+        let field = |name: &str| {
+            let line = status.lines().find(|line| line.starts_with(name))?;
+            line.split_whitespace().nth(1)?.parse::<u64>().ok()
+        };
+        let current_kb = field("VmRSS:")?;
+        Some(MemoryUsage {
+            current_kb,
+            peak_kb: field("VmHWM:").unwrap_or(current_kb),
+        })
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::MemoryUsage;
+    use std::mem::MaybeUninit;
+
+    pub fn memory_usage() -> Option<MemoryUsage> {
+        let mut info = MaybeUninit::<libc::mach_task_basic_info>::uninit();
+        let mut count = libc::MACH_TASK_BASIC_INFO_COUNT;
+        let result = unsafe {
+            libc::task_info(
+                libc::mach_task_self(),
+                libc::MACH_TASK_BASIC_INFO,
+                info.as_mut_ptr() as libc::task_info_t,
+                &mut count,
+            )
+        };
+        if result != libc::KERN_SUCCESS {
+            return None;
+        }
+        let info = unsafe { info.assume_init() };
+        Some(MemoryUsage {
+            current_kb: info.resident_size / 1024,
+            peak_kb: info.resident_size_max / 1024,
+        })
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::MemoryUsage;
+    use std::ffi::c_void;
+    use std::mem;
+
+    /// `PROCESS_MEMORY_COUNTERS` of the Windows API, of which only the working sets are read.
+    #[repr(C)]
+    #[derive(Default)]
+    #[allow(dead_code)]
+    struct ProcessMemoryCounters {
+        cb: u32,
+        page_fault_count: u32,
+        peak_working_set_size: usize,
+        working_set_size: usize,
+        quota_peak_paged_pool_usage: usize,
+        quota_paged_pool_usage: usize,
+        quota_peak_non_paged_pool_usage: usize,
+        quota_non_paged_pool_usage: usize,
+        pagefile_usage: usize,
+        peak_pagefile_usage: usize,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentProcess() -> *mut c_void;
+    }
+
+    #[link(name = "psapi")]
+    extern "system" {
+        fn GetProcessMemoryInfo(
+            process: *mut c_void,
+            counters: *mut ProcessMemoryCounters,
+            cb: u32,
+        ) -> i32;
+    }
+
+    pub fn memory_usage() -> Option<MemoryUsage> {
+        let cb = mem::size_of::<ProcessMemoryCounters>() as u32;
+        let mut counters = ProcessMemoryCounters {
+            cb,
+            ..Default::default()
+        };
+        if unsafe { GetProcessMemoryInfo(GetCurrentProcess(), &mut counters, cb) } == 0 {
+            return None;
+        }
+        Some(MemoryUsage {
+            current_kb: counters.working_set_size as u64 / 1024,
+            peak_kb: counters.peak_working_set_size as u64 / 1024,
+        })
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod platform {
+    use super::MemoryUsage;
+
+    pub fn memory_usage() -> Option<MemoryUsage> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+    fn test_memory_usage() {
+        let usage = memory_usage().unwrap();
+        assert!(usage.current_kb > 0);
+        assert!(usage.peak_kb >= usage.current_kb);
+
+        let monitor = MemoryMonitor::start(None);
+        assert!(monitor.peak_mb().is_some_and(|peak| peak > 0.0));
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4G").ok(), Some(4 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("512MB").ok(), Some(512 * 1024 * 1024));
+        assert_eq!(parse_size("1.5gib").ok(), Some(3 * 512 * 1024 * 1024));
+        assert_eq!(parse_size("2048").ok(), Some(2048));
+        assert_eq!(parse_size("64 k").ok(), Some(64 * 1024));
+        assert!(parse_size("4X").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("0").is_err());
+    }
+}
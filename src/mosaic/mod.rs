@@ -1,16 +1,48 @@
 pub mod algorithms;
 pub mod analysis;
+pub mod bench;
+pub mod cancel;
 pub mod color;
+pub mod contact_sheet;
+pub mod continuity;
+pub mod coverage;
+pub mod delta_e;
+pub mod diff;
+pub mod edges;
 pub mod error;
+pub mod failures;
+pub mod font;
+pub mod heatmap;
+pub mod ignore;
 pub mod image;
+pub mod layout;
+pub mod logging;
+pub mod manifest;
+pub mod memory;
+pub mod optimize;
+pub mod pins;
+pub mod progress;
+pub mod quality;
 pub mod rendering;
+pub mod runtime;
+pub mod seams;
 pub mod stats;
+pub mod story;
+pub mod terminal;
 pub mod tiles;
+pub mod time_gradient;
+pub mod timestamp;
+pub mod usage;
+pub mod wall;
 pub mod web;
+pub mod wizard;
 
 // Re-export key types and functions for backwards compatibility
 pub use analysis::analyse;
-pub use rendering::{render_nto1, render_nto1_no_repeat, render_random};
+pub use rendering::{
+    render_manifest, render_nto1, render_nto1_no_repeat, render_nto1_panels, render_nto1_story,
+    render_random, MatchOptions,
+};
 
 #[cfg(test)]
 mod tests {
@@ -19,9 +51,10 @@ mod tests {
     use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 
     use super::*;
-    use std::path::PathBuf;
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
     use ::image::{Rgb, RgbImage};
-    use tiles::TileSet;
+    use tiles::{cache_dir, AnalysisCache, ExifMetadata, IndexKind, LibraryOptions, TileSet};
 
     #[test]
     fn test_tile_set_new() {
@@ -45,15 +78,433 @@ mod tests {
         assert_eq!(new_tile_set.tiles[0].colors, "42");
     }
 
+    #[test]
+    fn test_tile_set_sample() {
+        let mut tile_set: TileSet<u32> = TileSet::new();
+        for i in 0..10 {
+            tile_set.push_tile(PathBuf::from(format!("{}.jpg", i)), i);
+        }
+        let sampled = tile_set.sample(4);
+        assert_eq!(sampled.len(), 4);
+        for (i, tile) in sampled.tiles.iter().enumerate() {
+            assert_eq!(tile.idx as usize, i + 1);
+            let path = sampled.get_path(tile);
+            assert_eq!(path, PathBuf::from(format!("{}.jpg", tile.colors)));
+        }
+    }
+
+    #[test]
+    fn test_tile_set_beyond_16_bit_indices() {
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        // Distinct colors, spread over every channel to keep the kd-tree buckets small
+        for i in 0..70_000u32 {
+            let (q, r) = (i / 256, i % 256);
+            let color = [r, q + r, 2 * q + 3 * r + 128 * (q / 256)].map(|c| (c % 256) as u8);
+            tile_set.push_tile(PathBuf::from(format!("{}.jpg", i)), [Rgb(color)]);
+        }
+        let last = tile_set.get_tile(70_000).unwrap();
+        assert_eq!(tile_set.get_path(&last), Path::new("69999.jpg"));
+        assert!(tile_set.get_tile(-70_000).unwrap().flipped);
+        for kind in [IndexKind::Kdtree, IndexKind::Brute] {
+            let index = tile_set.build_index(kind);
+            assert_eq!(index.size(), 140_000);
+            assert_eq!(
+                index.nearest_one(&last.coords()).item.unsigned_abs(),
+                70_000
+            );
+        }
+    }
+
+    #[test]
+    fn test_tile_set_prune_far_from() {
+        let mut tile_set: TileSet<[Rgb<u8>; 4]> = TileSet::new();
+        tile_set.push_tile(PathBuf::from("red.jpg"), [Rgb([250, 0, 0]); 4]);
+        tile_set.push_tile(PathBuf::from("blue.jpg"), [Rgb([0, 0, 250]); 4]);
+        tile_set.push_tile(PathBuf::from("dark red.jpg"), [Rgb([200, 0, 0]); 4]);
+        let blocks = [Rgb([255, 0, 0]), Rgb([240, 10, 0])];
+
+        let (pruned, count) = tile_set.clone().prune_far_from(&blocks, 20, 1, |_| false);
+        assert_eq!(count, 2);
+        let paths: Vec<_> = pruned.tiles.iter().map(|t| pruned.get_path(t)).collect();
+        assert_eq!(paths, [PathBuf::from("red.jpg")]);
+        assert_eq!(pruned.tiles[0].idx, 1);
+
+        let (pruned, count) = tile_set.clone().prune_far_from(&blocks, 0, 2, |_| false);
+        assert_eq!(count, 1, "the closest tiles are kept");
+        let paths: Vec<_> = pruned.tiles.iter().map(|t| pruned.get_path(t)).collect();
+        assert_eq!(
+            paths,
+            [PathBuf::from("red.jpg"), PathBuf::from("dark red.jpg")]
+        );
+
+        let (pruned, count) =
+            tile_set.prune_far_from(&blocks, 20, 1, |path| path == Path::new("blue.jpg"));
+        assert_eq!(count, 1);
+        let paths: Vec<_> = pruned.tiles.iter().map(|t| pruned.get_path(t)).collect();
+        assert_eq!(paths, [PathBuf::from("red.jpg"), PathBuf::from("blue.jpg")]);
+    }
+
+    #[test]
+    fn test_tile_set_retain_paths() {
+        let mut tile_set: TileSet<u32> = TileSet::new();
+        for i in 0..4 {
+            tile_set.push_tile(PathBuf::from(format!("{}.jpg", i)), i);
+        }
+        let (kept, count) = tile_set.retain_paths(|path| path != Path::new("1.jpg"));
+        assert_eq!(count, 1);
+        let colors: Vec<_> = kept.tiles.iter().map(|t| (t.idx, t.colors)).collect();
+        assert_eq!(colors, [(1, 0), (2, 2), (3, 3)]);
+        assert_eq!(kept.get_path(&kept.tiles[1]), Path::new("2.jpg"));
+    }
+
+    #[test]
+    fn test_tile_set_append() {
+        let mut tile_set: TileSet<u32> = TileSet::new();
+        tile_set.push_tile(PathBuf::from("a/0.jpg"), 0);
+        let mut other: TileSet<u32> = TileSet::new();
+        other.push_tile(PathBuf::from("b/1.jpg"), 1);
+        other.push_filler(2, Rgb([9, 9, 9]));
+        tile_set.append(other);
+        let colors: Vec<_> = tile_set.tiles.iter().map(|t| (t.idx, t.colors)).collect();
+        assert_eq!(colors, [(1, 0), (2, 1), (3, 2)]);
+        assert_eq!(tile_set.get_path(&tile_set.tiles[1]), Path::new("b/1.jpg"));
+        assert_eq!(
+            tile_set.filler_color(&tile_set.tiles[2]),
+            Some(Rgb([9, 9, 9]))
+        );
+    }
+
+    #[test]
+    fn test_tile_set_cache_round_trip() {
+        let mut tile_set: TileSet<[Rgb<u8>; 4]> = TileSet::new();
+        tile_set.push_tile(PathBuf::from("a.jpg"), [Rgb([1, 2, 3]); 4]);
+        tile_set.push_tile(PathBuf::from("b.jpg"), [Rgb([4, 5, 6]); 4]);
+        let bytes = bincode::serialize(&tile_set).unwrap();
+        let decoded = TileSet::<[Rgb<u8>; 4]>::from_cache_bytes(&bytes).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded.tiles[1].colors, [Rgb([4, 5, 6]); 4]);
+        assert_eq!(decoded.get_path(&decoded.tiles[1]), PathBuf::from("b.jpg"));
+    }
+
+    #[test]
+    fn test_tile_set_cache_mismatched_n() {
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        tile_set.push_tile(PathBuf::from("a.jpg"), [Rgb([1, 2, 3])]);
+        let bytes = bincode::serialize(&tile_set).unwrap();
+        assert!(TileSet::<[Rgb<u8>; 4]>::from_cache_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_tile_set_cache_corrupt() {
+        let mut tile_set: TileSet<[Rgb<u8>; 4]> = TileSet::new();
+        tile_set.push_tile(PathBuf::from("a.jpg"), [Rgb([1, 2, 3]); 4]);
+        let bytes = bincode::serialize(&tile_set).unwrap();
+        for len in 0..bytes.len() {
+            assert!(TileSet::<[Rgb<u8>; 4]>::from_cache_bytes(&bytes[..len]).is_err());
+        }
+        let huge_length = [0xff; 16];
+        assert!(TileSet::<[Rgb<u8>; 4]>::from_cache_bytes(&huge_length).is_err());
+    }
+
+    #[test]
+    fn test_analysis_cache_derives_coarser_modes() {
+        let mut tile_set: TileSet<[Rgb<u8>; 4]> = TileSet::new();
+        tile_set.push_tile(
+            PathBuf::from("a.jpg"),
+            [
+                Rgb([0, 0, 0]),
+                Rgb([40, 40, 40]),
+                Rgb([80, 80, 80]),
+                Rgb([120, 120, 120]),
+            ],
+        );
+        let mut cache = AnalysisCache::default();
+        cache.insert(true, &tile_set);
+        let decoded = AnalysisCache::from_bytes(&cache.to_bytes().unwrap()).unwrap();
+
+        assert_eq!(decoded.cached_n(true), Some(4));
+        assert_eq!(decoded.cached_n(false), None);
+        assert_eq!(
+            decoded.get::<4>(true).unwrap().tiles[0].colors,
+            tile_set.tiles[0].colors
+        );
+        let derived = decoded.get::<1>(true).unwrap();
+        assert_eq!(derived.tiles[0].colors, [Rgb([60, 60, 60])]);
+        assert_eq!(derived.get_path(&derived.tiles[0]), PathBuf::from("a.jpg"));
+        assert!(decoded.get::<9>(true).is_none());
+        assert!(decoded.get::<1>(false).is_none());
+    }
+
+    #[test]
+    fn test_tile_set_analysis_images() {
+        let (a, b, c, d) = (
+            Rgb([0, 0, 0]),
+            Rgb([40, 40, 40]),
+            Rgb([80, 80, 80]),
+            Rgb([120, 120, 120]),
+        );
+        let mut tile_set: TileSet<[Rgb<u8>; 4]> = TileSet::new();
+        tile_set.push_tile(PathBuf::from("/nonexistent/a.jpg"), [a, b, c, d]);
+        tile_set.use_analysis_images();
+
+        let image = tile_set.get_image(&tile_set.tiles[0], 32).unwrap();
+        assert_eq!(image.dimensions(), (2, 2));
+        assert_eq!(image.pixels().copied().collect::<Vec<_>>(), [a, b, c, d]);
+        let flipped = tile_set.get_tile(-1).unwrap();
+        let image = tile_set.get_image(&flipped, 32).unwrap();
+        assert_eq!(image.pixels().copied().collect::<Vec<_>>(), [b, a, d, c]);
+    }
+
+    #[test]
+    fn test_analysis_cache_corrupt() {
+        let mut tile_set: TileSet<[Rgb<u8>; 4]> = TileSet::new();
+        tile_set.push_tile(PathBuf::from("a.jpg"), [Rgb([1, 2, 3]); 4]);
+        let mut cache = AnalysisCache::default();
+        cache.insert(false, &tile_set);
+        let bytes = cache.to_bytes().unwrap();
+        for len in 0..bytes.len() {
+            assert!(AnalysisCache::from_bytes(&bytes[..len]).is_err());
+        }
+        // An old per-mode cache file is not mistaken for a unified one
+        let legacy = bincode::serialize(&tile_set).unwrap();
+        let error = AnalysisCache::from_bytes(&legacy).unwrap_err();
+        assert!(
+            matches!(error, error::EmosaicError::Cache { .. }),
+            "{}",
+            error
+        );
+        assert_eq!(error.exit_code(), 5);
+    }
+
+    #[test]
+    fn test_cache_dir() {
+        let tiles = Path::new("src/mosaic");
+        assert_eq!(cache_dir(tiles, &LibraryOptions::default()).unwrap(), tiles);
+
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let library = LibraryOptions {
+            cache_root: Some(root.to_path_buf()),
+            ..LibraryOptions::default()
+        };
+        let dir = cache_dir(tiles, &library).unwrap();
+        // The same directory from another path to it
+        let other = cache_dir(Path::new("src/mosaic/../mosaic"), &library).unwrap();
+        let unrelated = cache_dir(Path::new("src"), &library).unwrap();
+        assert_eq!(dir, other);
+        assert_ne!(dir, unrelated);
+        assert!(dir.starts_with(root) && dir.is_dir());
+        let name = dir.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("mosaic-"));
+    }
+
+    #[test]
+    fn test_analysis_cache_partial() {
+        let mut tile_set: TileSet<[Rgb<u8>; 4]> = TileSet::new();
+        tile_set.push_tile(PathBuf::from("a.jpg"), [Rgb([1, 2, 3]); 4]);
+        let mut cache = AnalysisCache::default();
+        cache.insert(false, &tile_set);
+        let first = cache.to_bytes().unwrap().len();
+        cache.insert(true, &tile_set);
+        let bytes = cache.to_bytes().unwrap();
+
+        let recover = |bytes: &[u8]| {
+            let (recovered, lost) = AnalysisCache::from_partial_bytes(bytes).unwrap();
+            (recovered.cached_n(false), recovered.cached_n(true), lost)
+        };
+        assert_eq!(recover(&bytes), (Some(4), Some(4), 0));
+        // The entry cut short is lost, the one before it is kept
+        for len in first..bytes.len() {
+            assert_eq!(recover(&bytes[..len]), (Some(4), None, 1));
+        }
+        for len in 0..first {
+            assert!(AnalysisCache::from_partial_bytes(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_analysis_cache_upgrade() {
+        let paths = vec![PathBuf::from("a.jpg"), PathBuf::from("b.jpg")];
+        let tiles = |date: &str| {
+            vec![
+                (vec![10_u8, 20, 30], 1_u16, Some(date.to_string())),
+                (vec![40, 50, 60], 2, None),
+            ]
+        };
+        // A shared cache with 16-bit indices, then with 32-bit ones
+        let schema_1 =
+            bincode::serialize(&vec![(false, 1_usize, tiles("2024:05:01"), &paths)]).unwrap();
+        let tiles_32: Vec<_> = tiles("2024:05:01 10:00:00")
+            .into_iter()
+            .map(|(colors, idx, date)| (colors, u32::from(idx), date))
+            .collect();
+        let schema_2 = bincode::serialize(&vec![(true, 1_usize, tiles_32, &paths)]).unwrap();
+        assert_eq!(AnalysisCache::schema_version(&schema_1), Some(1));
+        assert_eq!(AnalysisCache::schema_version(&schema_2), Some(2));
+        assert_eq!(AnalysisCache::schema_version(b"not a cache"), None);
+        assert!(AnalysisCache::from_bytes(&schema_1).is_err());
+        assert!(AnalysisCache::from_bytes(&schema_2).is_err());
+
+        let mut cache = AnalysisCache::from_any_bytes(&schema_1).unwrap();
+        assert_eq!(cache.cached_n(false), Some(1));
+        let upgraded = AnalysisCache::from_any_bytes(&schema_2).unwrap();
+        let tile_set = upgraded.get::<1>(true).unwrap();
+        assert_eq!(
+            tile_set.tiles[0].date_taken.as_deref(),
+            Some("2024:05:01 10:00:00")
+        );
+
+        // A finer per-mode file replaces the coarser analysis, a coarser one is ignored
+        let per_mode = |n: usize| {
+            let tiles: Vec<_> = tiles("2024:05:01")
+                .into_iter()
+                .map(|(colors, idx, date)| (colors.repeat(n), idx, date))
+                .collect();
+            bincode::serialize(&(tiles, &paths)).unwrap()
+        };
+        cache.insert_mode_file(false, 4, &per_mode(4)).unwrap();
+        cache.insert_mode_file(false, 1, &per_mode(1)).unwrap();
+        assert_eq!(cache.cached_n(false), Some(4));
+        assert!(cache.insert_mode_file(true, 4, &per_mode(1)).is_err());
+
+        // Only the dates lacking a time are read again, with the captions and cameras missing
+        let read = std::sync::Mutex::new(Vec::new());
+        let refreshed = cache.refresh_metadata(|path| {
+            read.lock().unwrap().push(path.to_owned());
+            Some(ExifMetadata {
+                date_taken: Some(String::from("2024:05:01 12:34:56")),
+                camera_model: (path == Path::new("a.jpg")).then(|| String::from("iPhone 12")),
+                ..ExifMetadata::default()
+            })
+        });
+        assert_eq!(refreshed, 1);
+        let mut read = read.into_inner().unwrap();
+        read.sort();
+        assert_eq!(read, [PathBuf::from("a.jpg"), PathBuf::from("b.jpg")]);
+
+        let bytes = cache.to_bytes().unwrap();
+        assert_eq!(
+            AnalysisCache::schema_version(&bytes),
+            Some(AnalysisCache::SCHEMA_VERSION)
+        );
+        let decoded = AnalysisCache::from_bytes(&bytes).unwrap();
+        let tile_set = decoded.get::<4>(false).unwrap();
+        assert_eq!(
+            tile_set.tiles[0].date_taken.as_deref(),
+            Some("2024:05:01 12:34:56")
+        );
+        assert_eq!(tile_set.tiles[1].date_taken, None);
+        assert_eq!(tile_set.tiles[0].camera.as_deref(), Some("iPhone 12"));
+        assert_eq!(tile_set.tiles[1].camera, None);
+
+        // A cache of schema 3, with a header but no captions nor cameras
+        let mut schema_3 = b"EMSC".to_vec();
+        schema_3.extend(3_u32.to_le_bytes());
+        schema_3.extend(schema_2);
+        assert_eq!(AnalysisCache::schema_version(&schema_3), Some(3));
+        assert!(AnalysisCache::from_bytes(&schema_3).is_err());
+        let upgraded = AnalysisCache::from_any_bytes(&schema_3).unwrap();
+        assert_eq!(upgraded.cached_n(true), Some(1));
+
+        // A cache of schema 4, with captions and cameras but no sharpness
+        let caption = Some(String::from("Picnic"));
+        let tiles = vec![(
+            vec![1_u8, 2, 3],
+            1_u32,
+            None::<String>,
+            caption,
+            None::<String>,
+        )];
+        let entries = vec![(true, 1_usize, tiles, vec![PathBuf::from("a.jpg")])];
+        let mut schema_4 = b"EMSC".to_vec();
+        schema_4.extend(4_u32.to_le_bytes());
+        schema_4.extend(bincode::serialize(&entries).unwrap());
+        assert!(AnalysisCache::from_bytes(&schema_4).is_err());
+        let mut upgraded = AnalysisCache::from_any_bytes(&schema_4).unwrap();
+        let tile_set = upgraded.get::<1>(true).unwrap();
+        assert_eq!(tile_set.tiles[0].caption.as_deref(), Some("Picnic"));
+        assert_eq!(tile_set.tiles[0].sharpness, None);
+        upgraded.set_sharpness(&HashMap::from([(PathBuf::from("a.jpg"), 120.0)]));
+        let bytes = upgraded.to_bytes().unwrap();
+        let tile_set = AnalysisCache::from_bytes(&bytes)
+            .unwrap()
+            .get::<1>(true)
+            .unwrap();
+        assert_eq!(tile_set.tiles[0].sharpness, Some(120.0));
+    }
+
+    #[test]
+    fn test_index_kind_resolve() {
+        assert_eq!(IndexKind::Auto.resolve(100, 3), IndexKind::Brute);
+        assert_eq!(IndexKind::Auto.resolve(1_000_000, 3), IndexKind::Kdtree);
+        assert_eq!(IndexKind::Auto.resolve(1_000_000, 27), IndexKind::Brute);
+        assert_eq!(
+            IndexKind::Auto.resolve(1_000_000, 3 * 1024),
+            IndexKind::Brute
+        );
+        assert_eq!(IndexKind::Hnsw.resolve(100, 3), IndexKind::Hnsw);
+        assert_eq!(IndexKind::Kdtree.resolve(100, 3), IndexKind::Kdtree);
+        assert_eq!(
+            IndexKind::KdtreeCompact.resolve(100, 3),
+            IndexKind::KdtreeCompact
+        );
+    }
+
     #[test]
     fn test_render_random() {
         let source_img = RgbImage::new(10, 10);
         let mut tile_set: TileSet<()> = TileSet::new();
         let tile_size = 32;
         tile_set.push_tile_with_image(PathBuf::new(), (), RgbImage::new(tile_size, tile_size));
-        let output = render_random(&source_img, tile_set, tile_size);
+        let (output, stats) =
+            render_random(&source_img, &tile_set, tile_size, false, None).unwrap();
         assert_eq!(output.width(), source_img.width() * tile_size);
         assert_eq!(output.height(), source_img.height() * tile_size);
+        assert_eq!(stats.tile_count(), 100);
+        assert_eq!(stats.tiles()[&(1, 2)].colors, tiles::SIZE::from_num(0));
+    }
+
+    #[test]
+    fn test_render_random_no_repeat() {
+        let mut tile_set: TileSet<()> = TileSet::new();
+        for i in 0..4 {
+            tile_set.push_tile_with_image(PathBuf::new(), (), RgbImage::new(1, 1));
+            assert_eq!(tile_set.tiles[i].idx as usize, i + 1);
+        }
+        let (_, stats) = render_random(&RgbImage::new(2, 2), &tile_set, 1, true, None).unwrap();
+        let placed: Vec<_> = stats
+            .tiles()
+            .values()
+            .map(|tile| tile.idx)
+            .sorted()
+            .collect();
+        assert_eq!(placed, [1, 2, 3, 4]);
+        assert!(render_random(&RgbImage::new(3, 2), &tile_set, 1, true, None).is_err());
+    }
+
+    #[test]
+    fn test_render_random_missing_tile() {
+        let red = Rgb([255, 0, 0]);
+        let mut tile_set: TileSet<()> = TileSet::new();
+        tile_set.push_tile(PathBuf::from("/nonexistent/a.png"), ());
+        tile_set.set_write_cache(false);
+        let source_img = RgbImage::from_pixel(2, 1, red);
+
+        // A solid block of the pixel's color when no tile can be loaded
+        let (output, stats) = render_random(&source_img, &tile_set, 2, false, None).unwrap();
+        assert_eq!(*output.get_pixel(3, 1), red);
+        assert_eq!(stats.tile_count(), 2);
+        assert!(stats.failures().iter().all(|failure| !failure.replaced));
+
+        // And a spare tile that can be loaded without repetition
+        tile_set.push_tile_with_image(PathBuf::new(), (), RgbImage::new(2, 2));
+        let source_img = RgbImage::from_pixel(1, 1, red);
+        for _ in 0..8 {
+            let (output, stats) = render_random(&source_img, &tile_set, 2, true, None).unwrap();
+            assert_eq!(*output.get_pixel(1, 1), Rgb([0, 0, 0]));
+            assert_eq!(stats.tiles()[&(0, 0)].idx, 2);
+            assert!(stats.failures().iter().all(|failure| failure.replaced));
+        }
     }
 
     #[test]
@@ -62,11 +513,560 @@ mod tests {
         let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
         tile_set.push_tile_with_image(PathBuf::new(), [Rgb([0, 0, 0]); 1], RgbImage::new(8, 8));
         let tile_size = 8;
-        let output = render_nto1(&source_img, tile_set, tile_size, false, None);
+        let output = render_nto1(
+            &source_img,
+            tile_set,
+            tile_size,
+            MatchOptions {
+                index: IndexKind::Kdtree,
+                ..MatchOptions::default()
+            },
+        )
+        .unwrap();
         assert_eq!(output.image.width(), source_img.width() * tile_size);
         assert_eq!(output.image.height(), source_img.height() * tile_size);
     }
 
+    #[test]
+    fn test_render_nto1_missing_tile() {
+        let red = Rgb([255, 0, 0]);
+        let dark_red = Rgb([200, 0, 0]);
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        tile_set.push_tile(PathBuf::from("/nonexistent/red.png"), [red]);
+        tile_set.push_tile_with_image(
+            PathBuf::new(),
+            [dark_red],
+            RgbImage::from_pixel(2, 2, dark_red),
+        );
+        tile_set.set_write_cache(false);
+        let source_img = RgbImage::from_pixel(2, 1, red);
+
+        // The nearest tile that can be loaded takes the place of the missing one
+        let result = render_nto1(
+            &source_img,
+            tile_set,
+            2,
+            MatchOptions {
+                index: IndexKind::Brute,
+                ..MatchOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(*result.image.get_pixel(3, 1), dark_red);
+        let failures = result.stats.failures();
+        assert_eq!(failures.len(), 2);
+        assert!(failures.iter().all(|failure| failure.replaced));
+        assert!(failures[0].error.contains("red.png"));
+
+        // And a solid block of the cell's color when none can be loaded
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        tile_set.push_tile(PathBuf::from("/nonexistent/red.png"), [red]);
+        tile_set.set_write_cache(false);
+        let result = render_nto1(
+            &source_img,
+            tile_set,
+            2,
+            MatchOptions {
+                index: IndexKind::Brute,
+                ..MatchOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(*result.image.get_pixel(3, 1), red);
+        assert!(result
+            .stats
+            .failures()
+            .iter()
+            .all(|failure| !failure.replaced));
+    }
+
+    #[test]
+    fn test_render_nto1_time_gradient() {
+        // Tiles of the same average color, told apart by their images
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        let red = Rgb([255, 0, 0]);
+        for (i, (date, pixel)) in [("2024:06:01", 255), ("2010:01:01", 0)].iter().enumerate() {
+            let image = RgbImage::from_pixel(1, 1, Rgb([*pixel; 3]));
+            tile_set.push_tile_with_image(PathBuf::new(), [red], image);
+            tile_set.tiles[i].date_taken = Some(date.to_string());
+        }
+        let source_img = RgbImage::from_pixel(2, 1, red);
+        let gradient = Some((time_gradient::TimeGradient::LeftRight, 48.0));
+
+        let output = render_nto1(
+            &source_img,
+            tile_set,
+            1,
+            MatchOptions {
+                index: IndexKind::Brute,
+                time_gradient: gradient,
+                ..MatchOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(output.image.as_raw(), &[0, 0, 0, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_render_nto1_pins() {
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        for color in [Rgb([255, 0, 0]), Rgb([200, 0, 0]), Rgb([0, 0, 255])].iter() {
+            tile_set.push_tile_with_image(
+                PathBuf::new(),
+                [*color],
+                RgbImage::from_pixel(1, 1, *color),
+            );
+        }
+        let source_img = RgbImage::from_pixel(3, 1, Rgb([255, 0, 0]));
+        // The pinned red tile is only placed where pinned
+        let pins = HashMap::from([((1, 0), 1), ((2, 0), 3)]);
+
+        let output = render_nto1(
+            &source_img,
+            tile_set,
+            1,
+            MatchOptions {
+                index: IndexKind::Brute,
+                pins,
+                ..MatchOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(output.image.as_raw(), &[200, 0, 0, 255, 0, 0, 0, 0, 255]);
+        let distances: Vec<_> = output
+            .stats
+            .tiles()
+            .values()
+            .map(|tile| tile.colors.to_num::<u32>())
+            .collect();
+        assert_eq!(distances.iter().max().copied(), Some(510));
+    }
+
+    #[test]
+    fn test_render_nto1_filler() {
+        let red = Rgb([255, 0, 0]);
+        let blue = Rgb([0, 0, 200]);
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        tile_set.push_tile_with_image(PathBuf::new(), [red], RgbImage::from_pixel(1, 1, red));
+        let source_img = RgbImage::from_fn(3, 1, |x, _| if x == 1 { blue } else { red });
+
+        let output = render_nto1(
+            &source_img,
+            tile_set,
+            2,
+            MatchOptions {
+                index: IndexKind::Brute,
+                filler: Some(100),
+                ..MatchOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(output.image.get_pixel(0, 0), &red);
+        assert_eq!(output.image.get_pixel(3, 1), &blue);
+        assert_eq!(output.tile_set.len(), 2);
+        let filler = &output.stats.tiles()[&(1, 0)];
+        assert_eq!(output.tile_set.filler_color(filler), Some(blue));
+        assert_eq!(
+            output.tile_set.get_path(filler),
+            Path::new("filler #0000c8")
+        );
+
+        let config = stats::MosaicConfig {
+            tile_size: 2,
+            mode: "test".to_string(),
+            no_repeat: false,
+            greedy: false,
+            story: false,
+            time_gradient: None,
+            filler: Some(100),
+            crop: false,
+            tint_opacity: 0.0,
+            downsample: 1,
+            randomize: None,
+            randomize_max_variance: None,
+            tiles_dir: String::new(),
+            title: "Test Mosaic".to_string(),
+            description: None,
+            author: None,
+            thumbnail_size: None,
+            export_quality: None,
+            export_strip_exif: false,
+            date_format: Default::default(),
+            palette: Default::default(),
+            tile_links: Default::default(),
+        };
+        let mut manifest = output.stats.manifest(&output.tile_set, &config);
+        assert_eq!(manifest.placements[1].filler, Some([0, 0, 200]));
+        // Fillers are rendered without any image file, the missing cell is left black
+        manifest
+            .placements
+            .retain(|placement| placement.filler.is_some());
+        let image = render_manifest(&manifest, 1, 1 << 20, None, None).unwrap();
+        assert_eq!(image.as_raw(), &[0, 0, 0, 0, 0, 200]);
+    }
+
+    #[test]
+    fn test_render_nto1_correct() {
+        let red = Rgb([200, 0, 0]);
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        tile_set.push_tile_with_image(PathBuf::new(), [red], RgbImage::from_pixel(1, 1, red));
+        let source_img = RgbImage::from_fn(2, 1, |x, _| {
+            if x == 0 {
+                Rgb([190, 0, 0])
+            } else {
+                Rgb([0, 0, 200])
+            }
+        });
+
+        // Only the tile of the blue cell is farther than 100
+        let output = render_nto1(
+            &source_img,
+            tile_set,
+            1,
+            MatchOptions {
+                index: IndexKind::Brute,
+                correct: Some(100),
+                ..MatchOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(output.image.as_raw(), &[200, 0, 0, 0, 0, 200]);
+        assert_eq!(output.stats.tiles()[&(1, 0)].colors.to_num::<u32>(), 400);
+    }
+
+    #[test]
+    fn test_render_nto1_weights() {
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        for color in [Rgb([200, 0, 0]), Rgb([150, 0, 0])].iter() {
+            tile_set.push_tile_with_image(
+                PathBuf::new(),
+                [*color],
+                RgbImage::from_pixel(1, 1, *color),
+            );
+        }
+        let source_img = RgbImage::from_pixel(1, 1, Rgb([210, 0, 0]));
+
+        // The farther tile wins once its distance is divided by its weight
+        let output = render_nto1(
+            &source_img,
+            tile_set.clone(),
+            1,
+            MatchOptions {
+                index: IndexKind::Brute,
+                weights: Some(&[1.0, 1.0]),
+                ..MatchOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(output.image.as_raw(), &[200, 0, 0]);
+        let output = render_nto1(
+            &source_img,
+            tile_set,
+            1,
+            MatchOptions {
+                index: IndexKind::Brute,
+                weights: Some(&[1.0, 8.0]),
+                ..MatchOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(output.image.as_raw(), &[150, 0, 0]);
+        assert_eq!(output.stats.tiles()[&(0, 0)].colors.to_num::<u32>(), 60);
+    }
+
+    #[test]
+    fn test_render_nto1_repeat_penalty() {
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        for color in [Rgb([200, 0, 0]), Rgb([150, 0, 0])].iter() {
+            tile_set.push_tile_with_image(
+                PathBuf::new(),
+                [*color],
+                RgbImage::from_pixel(1, 1, *color),
+            );
+        }
+        let source_img = RgbImage::from_pixel(4, 1, Rgb([210, 0, 0]));
+        let reds = |image: &RgbImage| {
+            image
+                .pixels()
+                .map(|pixel| pixel[0])
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+
+        let output = render_nto1(
+            &source_img,
+            tile_set.clone(),
+            1,
+            MatchOptions {
+                index: IndexKind::Brute,
+                ..MatchOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(reds(&output.image), [200, 200, 200, 200]);
+        // The closest tile costs 30 more per use, so the other one is worth it on the third use
+        let output = render_nto1(
+            &source_img,
+            tile_set,
+            1,
+            MatchOptions {
+                index: IndexKind::Brute,
+                repeat_penalty: Some(30.0),
+                ..MatchOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(reds(&output.image), [150, 200, 200, 200]);
+    }
+
+    #[test]
+    fn test_render_nto1_continuity() {
+        let (black, white) = (Rgb([0, 0, 0]), Rgb([255, 255, 255]));
+        // A single tile, black on the left and white on the right, or the other way flipped
+        let mut tile_set: TileSet<[Rgb<u8>; 4]> = TileSet::new();
+        let image = RgbImage::from_fn(2, 2, |x, _| if x == 0 { black } else { white });
+        tile_set.push_tile_with_image(PathBuf::new(), [black, white, black, white], image);
+        // A row of gray cells that both orientations match equally
+        let source_img = RgbImage::from_pixel(8, 2, Rgb([128, 128, 128]));
+
+        let output = render_nto1(
+            &source_img,
+            tile_set,
+            2,
+            MatchOptions {
+                index: IndexKind::Brute,
+                continuity: Some(1.0),
+                ..MatchOptions::default()
+            },
+        )
+        .unwrap();
+        // Neighbouring tiles face each other with the same color
+        for seam in [2, 4, 6] {
+            assert_eq!(
+                output.image.get_pixel(seam - 1, 0),
+                output.image.get_pixel(seam, 0)
+            );
+        }
+        // Placements are recorded by column and row, whatever the size of the segments
+        assert_eq!(output.stats.grid_cells(), [(0, 0), (1, 0), (2, 0), (3, 0)]);
+    }
+
+    #[test]
+    fn test_render_nto1_deterministic() {
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        for red in (0..=255).step_by(5) {
+            let color = Rgb([red, 0, 255 - red]);
+            tile_set.push_tile_with_image(
+                PathBuf::new(),
+                [color],
+                RgbImage::from_pixel(1, 1, color),
+            );
+        }
+        let source_img = RgbImage::from_fn(8, 8, |x, y| Rgb([(x * 32) as u8, (y * 32) as u8, 128]));
+        // Without repetition every choice depends on the ones before it
+        let render = |threads| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            pool.install(|| {
+                let no_repeat = render_nto1(
+                    &source_img,
+                    tile_set.clone(),
+                    1,
+                    MatchOptions {
+                        no_repeat: true,
+                        index: IndexKind::Brute,
+                        ..MatchOptions::default()
+                    },
+                )
+                .unwrap();
+                let randomized = render_nto1(
+                    &source_img,
+                    tile_set.clone(),
+                    1,
+                    MatchOptions {
+                        randomize: Some((50.0, None)),
+                        index: IndexKind::Brute,
+                        repeat_penalty: Some(10.0),
+                        ..MatchOptions::default()
+                    },
+                )
+                .unwrap();
+                (no_repeat.image, randomized.image)
+            })
+        };
+        assert_eq!(render(1), render(4));
+    }
+
+    #[test]
+    fn test_render_nto1_randomize_max_variance() {
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        for gray in (0..=255).step_by(5) {
+            let color = Rgb([gray, gray, gray]);
+            tile_set.push_tile_with_image(
+                PathBuf::new(),
+                [color],
+                RgbImage::from_pixel(1, 1, color),
+            );
+        }
+        // A flat left half and a striped right half, between the grays of the tiles
+        let source_img = RgbImage::from_fn(16, 4, |x, y| match (x < 8, (x + y) % 2) {
+            (true, _) => Rgb([102, 102, 102]),
+            (false, 0) => Rgb([2, 2, 2]),
+            (false, _) => Rgb([198, 198, 198]),
+        });
+        let render = |randomize| {
+            render_nto1(
+                &source_img,
+                tile_set.clone(),
+                1,
+                MatchOptions {
+                    randomize,
+                    index: IndexKind::Brute,
+                    ..MatchOptions::default()
+                },
+            )
+            .unwrap()
+            .image
+        };
+        let exact = render(None);
+        let everywhere = render(Some((100.0, None)));
+        let flat_only = render(Some((100.0, Some(50.0))));
+        let right = |image: &RgbImage| ::image::imageops::crop_imm(image, 10, 0, 6, 4).to_image();
+        let left = |image: &RgbImage| ::image::imageops::crop_imm(image, 0, 0, 7, 4).to_image();
+        assert_ne!(right(&everywhere), right(&exact));
+        assert_eq!(right(&flat_only), right(&exact));
+        assert_eq!(left(&flat_only), left(&everywhere));
+    }
+
+    #[test]
+    fn test_render_nto1_irregular() {
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        for color in [Rgb([255, 0, 0]), Rgb([0, 0, 255])] {
+            tile_set.push_tile_with_image(
+                PathBuf::new(),
+                [color],
+                RgbImage::from_pixel(1, 1, color),
+            );
+        }
+        // Red on the left half, blue on the right one
+        let source_img = RgbImage::from_fn(8, 6, |x, _| {
+            if x < 4 {
+                Rgb([250, 0, 0])
+            } else {
+                Rgb([0, 0, 250])
+            }
+        });
+        let result =
+            rendering::render_nto1_irregular(&source_img, tile_set, 2, IndexKind::Brute, 2);
+        assert_eq!(result.image.dimensions(), (16, 12));
+        assert_eq!(*result.image.get_pixel(0, 11), Rgb([255, 0, 0]));
+        assert_eq!(*result.image.get_pixel(15, 0), Rgb([0, 0, 255]));
+        // Some rectangles span several cells
+        assert!(result.stats.tile_count() < 8 * 6);
+    }
+
+    #[test]
+    fn test_render_nto1_story() {
+        // Two events with a red tile each, and cells closer to the second event overall
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        let tiles = [
+            ("2020:01:01", Rgb([255, 0, 0])),
+            ("2020:01:01", Rgb([0, 255, 0])),
+            ("2024:06:01", Rgb([240, 0, 0])),
+            ("2024:06:01", Rgb([200, 0, 30])),
+        ];
+        for (i, &(date, color)) in tiles.iter().enumerate() {
+            tile_set.push_tile_with_image(
+                PathBuf::new(),
+                [color],
+                RgbImage::from_pixel(1, 1, color),
+            );
+            tile_set.tiles[i].date_taken = Some(date.to_string());
+        }
+        let source_img = RgbImage::from_fn(2, 1, |x, _| {
+            if x == 0 {
+                Rgb([255, 0, 0])
+            } else {
+                Rgb([200, 0, 30])
+            }
+        });
+        let options = story::StoryOptions {
+            min_event_tiles: 1,
+            ..story::StoryOptions::default()
+        };
+
+        let output = render_nto1_story(&source_img, tile_set, 1, IndexKind::Brute, &options, None);
+        assert_eq!(output.image.as_raw(), &[240, 0, 0, 200, 0, 30]);
+    }
+
+    #[test]
+    fn test_render_manifest() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        for (i, color) in [Rgb([255, 0, 0]), Rgb([0, 0, 255])].iter().enumerate() {
+            let path = dir.join(format!("{}.png", i));
+            RgbImage::from_pixel(4, 4, *color).save(&path).unwrap();
+            tile_set.push_tile(path, [*color]);
+        }
+        tile_set.set_write_cache(false);
+        let source_img = RgbImage::from_fn(3, 2, |x, y| {
+            if (x + y) % 2 == 0 {
+                Rgb([250, 0, 0])
+            } else {
+                Rgb([0, 0, 250])
+            }
+        });
+        let rendered = render_nto1(
+            &source_img,
+            tile_set,
+            4,
+            MatchOptions {
+                index: IndexKind::Kdtree,
+                ..MatchOptions::default()
+            },
+        )
+        .unwrap();
+        let config = stats::MosaicConfig {
+            tile_size: 4,
+            mode: "test".to_string(),
+            no_repeat: false,
+            greedy: false,
+            story: false,
+            time_gradient: None,
+            filler: None,
+            crop: false,
+            tint_opacity: 0.0,
+            downsample: 1,
+            randomize: None,
+            randomize_max_variance: None,
+            tiles_dir: dir.display().to_string(),
+            title: "Test Mosaic".to_string(),
+            description: None,
+            author: None,
+            thumbnail_size: None,
+            export_quality: None,
+            export_strip_exif: false,
+            date_format: Default::default(),
+            palette: Default::default(),
+            tile_links: Default::default(),
+        };
+        let manifest = rendered.stats.manifest(&rendered.tile_set, &config);
+
+        let image = render_manifest(&manifest, 4, 1 << 20, None, None).unwrap();
+        assert_eq!(image.as_raw(), rendered.image.as_raw());
+        // Composited row by row within a memory budget
+        let image = render_manifest(&manifest, 4, 1 << 20, Some(1 << 30), None).unwrap();
+        assert_eq!(image.as_raw(), rendered.image.as_raw());
+        let image = render_manifest(&manifest, 2, 1 << 20, None, None).unwrap();
+        assert_eq!(image.dimensions(), (6, 4));
+
+        temp.close().unwrap();
+        assert!(render_manifest(&manifest, 2, 1 << 20, None, None).is_err());
+    }
+
     #[test]
     fn test_analyse_tiles() {
         let images = vec![
@@ -80,7 +1080,7 @@ mod tests {
         assert_eq!(tile_set.len(), 2);
     }
 
-    fn gen_test_analyse_tiles_consistency<const N: usize>()
+    fn gen_test_analyse_tiles_consistency<const N: usize>(indices: &[IndexKind])
     where
         [(); N * 3]:,
     {
@@ -114,16 +1114,22 @@ mod tests {
 
         for (i, img) in universe.iter().enumerate() {
             eprintln!("Rendering image {} of {}", i + 1, universe.len());
-            let rendered_img = render_nto1(&img, tile_set.clone(), dim, false, None);
-            assert_eq!(
-                rendered_img.image.into_iter().collect::<Vec<_>>(),
-                img.into_iter().collect::<Vec<_>>()
-            );
-            let rendered_img = render_nto1_no_repeat(&img, tile_set.clone(), dim).unwrap();
-            assert_eq!(
-                rendered_img.image.into_iter().collect::<Vec<_>>(),
-                img.into_iter().collect::<Vec<_>>()
-            );
+            for &index in indices {
+                let rendered_img = render_nto1(
+                    img,
+                    tile_set.clone(),
+                    dim,
+                    MatchOptions {
+                        index,
+                        ..MatchOptions::default()
+                    },
+                )
+                .unwrap();
+                assert_eq!(rendered_img.image.as_raw(), img.as_raw());
+                let rendered_img =
+                    render_nto1_no_repeat(img, tile_set.clone(), dim, index).unwrap();
+                assert_eq!(rendered_img.image.as_raw(), img.as_raw());
+            }
         }
 
         // for any image built from tiles from this universe without duplicates, the mosaic image should be an exact match
@@ -132,31 +1138,52 @@ mod tests {
             for (i, tile) in tiles.enumerate() {
                 ::image::imageops::overlay(&mut img, tile, 0, i as i64 * dim as i64);
             }
-            let rendered_img = render_nto1(&img, tile_set.clone(), dim, false, None);
-            assert_eq!(
-                rendered_img.image.into_iter().collect::<Vec<_>>(),
-                img.into_iter().collect::<Vec<_>>()
-            );
-            let rendered_img = render_nto1_no_repeat(&img, tile_set.clone(), dim).unwrap();
-            assert_eq!(
-                rendered_img.image.into_iter().collect::<Vec<_>>(),
-                img.into_iter().collect::<Vec<_>>()
-            );
+            for &index in indices {
+                let rendered_img = render_nto1(
+                    &img,
+                    tile_set.clone(),
+                    dim,
+                    MatchOptions {
+                        index,
+                        ..MatchOptions::default()
+                    },
+                )
+                .unwrap();
+                assert_eq!(rendered_img.image.as_raw(), img.as_raw());
+                let rendered_img =
+                    render_nto1_no_repeat(&img, tile_set.clone(), dim, index).unwrap();
+                assert_eq!(rendered_img.image.as_raw(), img.as_raw());
+            }
         }
     }
 
     #[test]
     fn test_analyse_tiles_consistency_1() {
-        gen_test_analyse_tiles_consistency::<1>();
+        gen_test_analyse_tiles_consistency::<1>(&[
+            IndexKind::Kdtree,
+            IndexKind::KdtreeCompact,
+            IndexKind::Brute,
+            IndexKind::Hnsw,
+        ]);
     }
     
     #[test]
     fn test_analyse_tiles_consistency_4() {
-        gen_test_analyse_tiles_consistency::<4>();
+        gen_test_analyse_tiles_consistency::<4>(&[
+            IndexKind::Kdtree,
+            IndexKind::KdtreeCompact,
+            IndexKind::Brute,
+            IndexKind::Hnsw,
+        ]);
     }
     
     #[test]
     fn test_analyse_tiles_consistency_9() {
-        gen_test_analyse_tiles_consistency::<9>();
+        // HNSW rebuilds its graph per render, too slow to sweep the whole universe
+        gen_test_analyse_tiles_consistency::<9>(&[
+            IndexKind::Kdtree,
+            IndexKind::KdtreeCompact,
+            IndexKind::Brute,
+        ]);
     }
 }
\ No newline at end of file
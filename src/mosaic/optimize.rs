@@ -0,0 +1,546 @@
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use ::image::{imageops, Rgb, RgbImage};
+use kiddo::distance_metric::DistanceMetric;
+use kiddo::fixed::distance::Manhattan;
+use rand::Rng;
+use tracing::info;
+
+use super::analysis::source_blocks;
+use super::cancel;
+use super::error::ImageError;
+use super::rendering::{RenderConfig, RenderResult};
+use super::tiles::{IndexKind, Tile, SIZE};
+
+/// A cell of the mosaic whose tile may be swapped.
+struct Cell<const N: usize> {
+    /// Column and row in the mosaic grid
+    cell: (u32, u32),
+    tile: Tile<[Rgb<u8>; N]>,
+    distance: SIZE,
+}
+
+/// A global refinement of the rendered mosaic.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Optimize {
+    /// Simulated annealing of the tile of every cell, for a time budget in seconds
+    Anneal { seconds: f64 },
+}
+
+impl Optimize {
+    /// Time budget of annealing when none is given.
+    pub const DEFAULT_ANNEAL_SECONDS: f64 = 10.0;
+}
+
+impl FromStr for Optimize {
+    type Err = String;
+
+    /// Parse a refinement written as `anneal` or `anneal:seconds`.
+    fn from_str(s: &str) -> Result<Optimize, String> {
+        let (name, seconds) = match s.split_once(':') {
+            Some((name, seconds)) => match seconds.parse::<f64>() {
+                Ok(seconds) if seconds.is_finite() && seconds > 0.0 => (name, seconds),
+                _ => {
+                    return Err(format!(
+                        "Seconds must be a positive number, got {}",
+                        seconds
+                    ))
+                }
+            },
+            None => (s, Optimize::DEFAULT_ANNEAL_SECONDS),
+        };
+        match name {
+            "anneal" => Ok(Optimize::Anneal { seconds }),
+            _ => Err(format!(
+                "Unknown optimization {:?}, expected anneal[:seconds]",
+                name
+            )),
+        }
+    }
+}
+
+/// Improve a rendered mosaic by swapping the tiles of pairs of cells whenever that lowers
+/// their total color distance, 2-opt style, for up to `passes` passes over the cells or until
+/// no swap helps.
+///
+/// Every cell is only tried against the cells holding the tiles nearest to it in the index.
+/// Cells in `fixed`, by column and row, and cells given synthetic filler tiles keep their
+/// tiles. Only the swapped cells are composited again, and the statistics are updated with
+/// their new distances.
+///
+/// Returns the number of swaps made.
+pub fn optimize_swaps<const N: usize>(
+    result: &mut RenderResult<N>,
+    source_img: &RgbImage,
+    tile_size: u32,
+    index: IndexKind,
+    passes: usize,
+    fixed: &HashSet<(u32, u32)>,
+) -> Result<usize, ImageError>
+where
+    [(); N * 3]:,
+{
+    let step = (N as f64).sqrt() as u32;
+    let htiles = (source_img.width() / step) as usize;
+    let blocks: Vec<_> = source_blocks::<N>(source_img)
+        .into_iter()
+        .map(|colors| Tile::from_colors(colors).coords())
+        .collect();
+    let block = |(col, row): (u32, u32)| &blocks[row as usize * htiles + col as usize];
+
+    let tile_set = &result.tile_set;
+    let mut cells: Vec<Cell<N>> = result
+        .stats
+        .grid_cells()
+        .into_iter()
+        .filter(|cell| !fixed.contains(cell))
+        .filter_map(|cell| {
+            let placed = &result.stats.tiles()[&cell];
+            let item = if placed.flipped {
+                -(placed.idx as i32)
+            } else {
+                placed.idx as i32
+            };
+            let tile = tile_set.get_tile(item)?;
+            if tile_set.filler_color(&tile).is_some() {
+                return None;
+            }
+            let distance = Manhattan::dist(block(cell), &tile.coords());
+            Some(Cell {
+                cell,
+                tile,
+                distance,
+            })
+        })
+        .collect();
+
+    // Cells holding every signed tile index
+    let item = |tile: &Tile<_>| {
+        if tile.flipped {
+            -(tile.idx as i32)
+        } else {
+            tile.idx as i32
+        }
+    };
+    let mut cells_of: HashMap<i32, Vec<usize>> = HashMap::new();
+    for (i, cell) in cells.iter().enumerate() {
+        cells_of.entry(item(&cell.tile)).or_default().push(i);
+    }
+    let mut positions: Vec<usize> = cells_of
+        .keys()
+        .map(|item| item.unsigned_abs() as usize - 1)
+        .collect();
+    positions.sort_unstable();
+    positions.dedup();
+    let placed = tile_set.build_index_of(index, &positions);
+
+    let before: f64 = cells.iter().map(|cell| cell.distance.to_num::<f64>()).sum();
+    let count = RenderConfig::default().swap_neighbor_count;
+    let mut swapped = HashSet::new();
+    let mut swaps = 0;
+    for _ in 0..passes {
+        if cancel::interrupted() {
+            break;
+        }
+        let mut pass_swaps = 0;
+        for a in 0..cells.len() {
+            let item_a = item(&cells[a].tile);
+            let coords_a = block(cells[a].cell);
+            let tile_coords_a = cells[a].tile.coords();
+            let mut best: Option<(SIZE, usize, SIZE, SIZE)> = None;
+            for candidate in placed.nearest_n(coords_a, count) {
+                if candidate.item == item_a {
+                    continue;
+                }
+                let Some(holders) = cells_of.get(&candidate.item) else {
+                    continue;
+                };
+                for &b in holders.iter().take(count) {
+                    let before = cells[a].distance + cells[b].distance;
+                    let distance_b = Manhattan::dist(block(cells[b].cell), &tile_coords_a);
+                    let after = candidate.distance + distance_b;
+                    if after < before && best.is_none_or(|(gain, ..)| before - after > gain) {
+                        best = Some((before - after, b, candidate.distance, distance_b));
+                    }
+                }
+            }
+            if let Some((_, b, distance_a, distance_b)) = best {
+                let item_b = item(&cells[b].tile);
+                for (item, from, to) in [(item_a, a, b), (item_b, b, a)] {
+                    let holders = cells_of.get_mut(&item).unwrap();
+                    holders.retain(|&cell| cell != from);
+                    holders.push(to);
+                }
+                let tile_a = cells[a].tile.clone();
+                cells[a].tile = std::mem::replace(&mut cells[b].tile, tile_a);
+                cells[a].distance = distance_a;
+                cells[b].distance = distance_b;
+                swapped.extend([a, b]);
+                pass_swaps += 1;
+            }
+        }
+        swaps += pass_swaps;
+        if pass_swaps == 0 {
+            break;
+        }
+    }
+
+    for &i in &swapped {
+        let cell = &cells[i];
+        let image = tile_set.get_image(&cell.tile, tile_size)?;
+        let (col, row) = cell.cell;
+        let (x, y) = (col as i64 * tile_size as i64, row as i64 * tile_size as i64);
+        imageops::replace(&mut result.image, &image, x, y);
+        result.stats.push_tile(col, row, &cell.tile, cell.distance);
+    }
+    let after: f64 = cells.iter().map(|cell| cell.distance.to_num::<f64>()).sum();
+    if !cells.is_empty() {
+        info!(
+            "Swapped {} pairs of tiles, lowering the average distance from {:.1} to {:.1}",
+            swaps,
+            before / cells.len() as f64,
+            after / cells.len() as f64
+        );
+    }
+    Ok(swaps)
+}
+
+/// Improve a rendered mosaic by simulated annealing for the time `budget`: at every step the
+/// tiles of two random cells are swapped, or the tile of a random cell is replaced with one of
+/// the tiles nearest to it in the index, and the move is kept if it lowers the total color
+/// distance, or with a probability that shrinks as the mosaic cools down otherwise. The best
+/// assignment found is kept.
+///
+/// With `no_repeat`, tiles already placed are never placed again. Cells in `fixed`, by column
+/// and row, and cells given synthetic filler tiles keep their tiles, and their tiles are not
+/// placed elsewhere. Only the cells whose tile changed are composited again, and the
+/// statistics are updated with their new distances.
+///
+/// Returns the number of cells whose tile changed.
+pub fn anneal<const N: usize>(
+    result: &mut RenderResult<N>,
+    source_img: &RgbImage,
+    tile_size: u32,
+    index: IndexKind,
+    budget: Duration,
+    no_repeat: bool,
+    fixed: &HashSet<(u32, u32)>,
+) -> Result<usize, ImageError>
+where
+    [(); N * 3]:,
+{
+    let step = (N as f64).sqrt() as u32;
+    let htiles = (source_img.width() / step) as usize;
+    let blocks: Vec<_> = source_blocks::<N>(source_img)
+        .into_iter()
+        .map(|colors| Tile::from_colors(colors).coords())
+        .collect();
+    let block = |(col, row): (u32, u32)| &blocks[row as usize * htiles + col as usize];
+
+    let tile_set = &result.tile_set;
+    // Tiles that stay where they are and are never placed elsewhere
+    let mut kept = HashSet::new();
+    let mut cells: Vec<Cell<N>> = Vec::new();
+    for cell in result.stats.grid_cells() {
+        let placed = &result.stats.tiles()[&cell];
+        let item = if placed.flipped {
+            -(placed.idx as i32)
+        } else {
+            placed.idx as i32
+        };
+        let Some(tile) = tile_set.get_tile(item) else {
+            continue;
+        };
+        if fixed.contains(&cell) || tile_set.filler_color(&tile).is_some() {
+            kept.insert(tile.idx);
+            continue;
+        }
+        let distance = Manhattan::dist(block(cell), &tile.coords());
+        cells.push(Cell {
+            cell,
+            tile,
+            distance,
+        });
+    }
+    if cells.is_empty() {
+        return Ok(0);
+    }
+
+    // Tiles a cell may be given instead of its own
+    let count = RenderConfig::default().anneal_neighbor_count;
+    let index = tile_set.build_index(index.resolve(tile_set.len() * 2, N * 3));
+    let candidates: Vec<Vec<Tile<[Rgb<u8>; N]>>> = cells
+        .iter()
+        .map(|cell| {
+            index
+                .nearest_n(block(cell.cell), count)
+                .into_iter()
+                .filter_map(|candidate| tile_set.get_tile(candidate.item))
+                .filter(|tile| !kept.contains(&tile.idx) && tile_set.filler_color(tile).is_none())
+                .collect()
+        })
+        .collect();
+    let mut uses: HashMap<u32, usize> = HashMap::new();
+    for cell in &cells {
+        *uses.entry(cell.tile.idx).or_default() += 1;
+    }
+
+    let total =
+        |cells: &[Cell<N>]| -> f64 { cells.iter().map(|cell| cell.distance.to_num::<f64>()).sum() };
+    let before = total(&cells);
+    let mut energy = before;
+    let mut best = (
+        energy,
+        cells
+            .iter()
+            .map(|cell| cell.tile.clone())
+            .collect::<Vec<_>>(),
+    );
+    // Start hot enough to accept most moves costing a tenth of an average distance, and cool
+    // down a thousandfold by the end of the budget
+    let start_temperature = (before / cells.len() as f64 / 10.0).max(1.0);
+    let mut temperature = start_temperature;
+    let mut rng = rand::thread_rng();
+    let start = Instant::now();
+    let mut moves = 0;
+    loop {
+        if moves % 1024 == 0 {
+            let elapsed = start.elapsed().as_secs_f64() / budget.as_secs_f64();
+            if elapsed >= 1.0 || cancel::interrupted() {
+                break;
+            }
+            temperature = start_temperature * 1e-3f64.powf(elapsed);
+            if energy < best.0 {
+                best = (energy, cells.iter().map(|cell| cell.tile.clone()).collect());
+            }
+        }
+        moves += 1;
+        let accept = |delta: f64, rng: &mut rand::rngs::ThreadRng| {
+            delta < 0.0 || rng.gen::<f64>() < (-delta / temperature).exp()
+        };
+        let a = rng.gen_range(0, cells.len());
+        if rng.gen::<bool>() {
+            let b = rng.gen_range(0, cells.len());
+            if a == b || cells[a].tile == cells[b].tile {
+                continue;
+            }
+            let distance_a = Manhattan::dist(block(cells[a].cell), &cells[b].tile.coords());
+            let distance_b = Manhattan::dist(block(cells[b].cell), &cells[a].tile.coords());
+            let delta = (distance_a + distance_b).to_num::<f64>()
+                - (cells[a].distance + cells[b].distance).to_num::<f64>();
+            if accept(delta, &mut rng) {
+                let tile_a = cells[a].tile.clone();
+                cells[a].tile = std::mem::replace(&mut cells[b].tile, tile_a);
+                cells[a].distance = distance_a;
+                cells[b].distance = distance_b;
+                energy += delta;
+            }
+        } else {
+            if candidates[a].is_empty() {
+                continue;
+            }
+            let tile = &candidates[a][rng.gen_range(0, candidates[a].len())];
+            if *tile == cells[a].tile
+                || (no_repeat
+                    && tile.idx != cells[a].tile.idx
+                    && uses.get(&tile.idx).is_some_and(|&n| n > 0))
+            {
+                continue;
+            }
+            let distance = Manhattan::dist(block(cells[a].cell), &tile.coords());
+            let delta = distance.to_num::<f64>() - cells[a].distance.to_num::<f64>();
+            if accept(delta, &mut rng) {
+                *uses.get_mut(&cells[a].tile.idx).unwrap() -= 1;
+                *uses.entry(tile.idx).or_default() += 1;
+                cells[a].tile = tile.clone();
+                cells[a].distance = distance;
+                energy += delta;
+            }
+        }
+    }
+    if energy < best.0 {
+        best = (energy, cells.iter().map(|cell| cell.tile.clone()).collect());
+    }
+
+    let mut changed = 0;
+    for (cell, tile) in cells.iter_mut().zip(best.1) {
+        if cell.tile != tile {
+            cell.distance = Manhattan::dist(block(cell.cell), &tile.coords());
+            cell.tile = tile;
+        }
+    }
+    for cell in &cells {
+        let placed = &result.stats.tiles()[&cell.cell];
+        if placed.idx == cell.tile.idx && placed.flipped == cell.tile.flipped {
+            continue;
+        }
+        let image = result.tile_set.get_image(&cell.tile, tile_size)?;
+        let (col, row) = cell.cell;
+        let (x, y) = (col as i64 * tile_size as i64, row as i64 * tile_size as i64);
+        imageops::replace(&mut result.image, &image, x, y);
+        result.stats.push_tile(col, row, &cell.tile, cell.distance);
+        changed += 1;
+    }
+    info!(
+        "Annealed {} moves in {:.1}s, changing {} cells and lowering the average distance \
+         from {:.1} to {:.1}",
+        moves,
+        start.elapsed().as_secs_f64(),
+        changed,
+        before / cells.len() as f64,
+        best.0 / cells.len() as f64
+    );
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mosaic::stats::RenderStats;
+    use crate::mosaic::tiles::TileSet;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_optimize_swaps() {
+        let (red, blue) = (Rgb([200, 0, 0]), Rgb([0, 0, 200]));
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        for color in [red, blue] {
+            tile_set.push_tile_with_image(
+                PathBuf::new(),
+                [color],
+                RgbImage::from_pixel(1, 1, color),
+            );
+        }
+        let source_img = RgbImage::from_fn(3, 1, |x, _| [red, blue, red][x as usize]);
+        // Red and blue were placed the wrong way round in the first two cells
+        let mut stats = RenderStats::new();
+        let placements = [(2, blue), (1, red), (1, red)];
+        for (x, &(item, _)) in placements.iter().enumerate() {
+            let tile = tile_set.get_tile(item).unwrap();
+            stats.push_tile(x as u32, 0, &tile, SIZE::from_num(0));
+        }
+        let image = RgbImage::from_fn(3, 1, |x, _| placements[x as usize].1);
+        let mut result = RenderResult {
+            image,
+            stats,
+            tile_set,
+        };
+
+        let swaps = optimize_swaps(
+            &mut result,
+            &source_img,
+            1,
+            IndexKind::Brute,
+            5,
+            &HashSet::new(),
+        )
+        .unwrap();
+        assert_eq!(swaps, 1);
+        assert_eq!(result.image, source_img);
+        assert_eq!(result.stats.tiles()[&(0, 0)].idx, 1);
+        assert_eq!(result.stats.tiles()[&(1, 0)].colors, SIZE::from_num(0));
+
+        // Cells that are fixed keep their tiles
+        let mut stats = RenderStats::new();
+        for (x, &(item, _)) in placements.iter().enumerate() {
+            let tile = result.tile_set.get_tile(item).unwrap();
+            stats.push_tile(x as u32, 0, &tile, SIZE::from_num(0));
+        }
+        let image = RgbImage::from_fn(3, 1, |x, _| placements[x as usize].1);
+        let mut result = RenderResult {
+            image,
+            stats,
+            tile_set: result.tile_set,
+        };
+        let fixed = HashSet::from([(0, 0)]);
+        let swaps =
+            optimize_swaps(&mut result, &source_img, 1, IndexKind::Brute, 5, &fixed).unwrap();
+        assert_eq!(swaps, 0);
+    }
+    #[test]
+    fn test_parse_optimize() {
+        assert_eq!(
+            "anneal".parse(),
+            Ok(Optimize::Anneal {
+                seconds: Optimize::DEFAULT_ANNEAL_SECONDS
+            })
+        );
+        assert_eq!("anneal:2.5".parse(), Ok(Optimize::Anneal { seconds: 2.5 }));
+        assert!("anneal:0".parse::<Optimize>().is_err());
+        assert!("anneal:soon".parse::<Optimize>().is_err());
+        assert!("greedy".parse::<Optimize>().is_err());
+    }
+
+    #[test]
+    fn test_anneal() {
+        let (red, dark_red, blue) = (Rgb([200, 0, 0]), Rgb([150, 0, 0]), Rgb([0, 0, 200]));
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        for color in [red, dark_red, blue] {
+            tile_set.push_tile_with_image(
+                PathBuf::new(),
+                [color],
+                RgbImage::from_pixel(1, 1, color),
+            );
+        }
+        let render = |tile_set, placements: [(i32, Rgb<u8>); 2]| {
+            let tile_set: TileSet<[Rgb<u8>; 1]> = tile_set;
+            let mut stats = RenderStats::new();
+            for (x, &(item, _)) in placements.iter().enumerate() {
+                let tile = tile_set.get_tile(item).unwrap();
+                stats.push_tile(x as u32, 0, &tile, SIZE::from_num(0));
+            }
+            RenderResult {
+                image: RgbImage::from_fn(2, 1, |x, _| placements[x as usize].1),
+                stats,
+                tile_set,
+            }
+        };
+        let budget = Duration::from_millis(100);
+
+        // Blue was placed in the red cell, and dark red in the blue one
+        let source_img = RgbImage::from_fn(2, 1, |x, _| [red, blue][x as usize]);
+        let mut result = render(tile_set, [(3, blue), (2, dark_red)]);
+        let fixed = HashSet::new();
+        let changed = anneal(
+            &mut result,
+            &source_img,
+            1,
+            IndexKind::Brute,
+            budget,
+            true,
+            &fixed,
+        );
+        assert_eq!(changed.unwrap(), 2);
+        assert_eq!(result.image, source_img);
+        assert_eq!(result.stats.tiles()[&(1, 0)].colors, SIZE::from_num(0));
+
+        // Without repetition the red tile cannot fill both red cells
+        let source_img = RgbImage::from_pixel(2, 1, red);
+        let mut result = render(result.tile_set, [(1, red), (2, dark_red)]);
+        let changed = anneal(
+            &mut result,
+            &source_img,
+            1,
+            IndexKind::Brute,
+            budget,
+            true,
+            &fixed,
+        );
+        assert_eq!(changed.unwrap(), 0);
+        let changed = anneal(
+            &mut result,
+            &source_img,
+            1,
+            IndexKind::Brute,
+            budget,
+            false,
+            &fixed,
+        );
+        // The red tile may also be flipped in the first cell, at no cost
+        assert!(changed.unwrap() >= 1);
+        assert_eq!(result.stats.tiles()[&(1, 0)].idx, 1);
+        assert_eq!(result.image, source_img);
+    }
+}
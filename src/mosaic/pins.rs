@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use super::error::EmosaicError;
+use super::tiles::{split_crop, with_crop, TileSet};
+
+/// A tile pinned to a cell of the mosaic grid.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Pin {
+    pub path: PathBuf,
+    /// Column of the cell in the mosaic grid
+    pub x: u32,
+    /// Row of the cell in the mosaic grid
+    pub y: u32,
+    /// Place the tile mirrored horizontally
+    #[serde(default)]
+    pub flipped: bool,
+}
+
+/// Manual edits to a mosaic: tiles pinned to grid cells, placed before any other tile, and
+/// tiles excluded from the mosaic.
+///
+/// Written in TOML as an `exclude` list of paths and `[[pin]]` tables.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pins {
+    #[serde(default, rename = "pin")]
+    pub pins: Vec<Pin>,
+    #[serde(default)]
+    pub exclude: Vec<PathBuf>,
+}
+
+impl Pins {
+    /// Parse edits written in TOML.
+    fn parse(text: &str) -> Result<Pins, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    /// Read the edits in the TOML file at `path`, resolving relative tile paths from
+    /// `tiles_dir`.
+    pub fn load(path: &Path, tiles_dir: &Path) -> Result<Pins, EmosaicError> {
+        let text = fs::read_to_string(path).map_err(|e| {
+            EmosaicError::io(format!("Failed to read pins from {}", path.display()), e)
+        })?;
+        let mut pins = Pins::parse(&text).map_err(|e| {
+            EmosaicError::Validation(format!("❌ Invalid pins in {}: {}", path.display(), e))
+        })?;
+        for pin in pins.pins.iter_mut() {
+            pin.path = identity(&tiles_dir.join(&pin.path));
+        }
+        for path in pins.exclude.iter_mut() {
+            *path = identity(&tiles_dir.join(&path));
+        }
+        Ok(pins)
+    }
+
+    /// Whether the tile at `path`, or the picture it is a crop of, is excluded from the mosaic.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        let picture = identity(split_crop(path).0);
+        self.exclude.contains(&identity(path)) || self.exclude.contains(&picture)
+    }
+
+    /// Whether the tile at `path` is pinned to a cell.
+    pub fn is_pinned(&self, path: &Path) -> bool {
+        let path = identity(path);
+        self.pins.iter().any(|pin| pin.path == path)
+    }
+
+    /// The signed index of the tile pinned to every cell of a grid of `cols` by `rows` cells,
+    /// negative for flipped tiles as in the tile indices.
+    ///
+    /// Fails when a pinned tile is not in `tile_set` or a cell is outside the grid or pinned
+    /// twice.
+    pub fn resolve<T>(
+        &self,
+        tile_set: &TileSet<T>,
+        (cols, rows): (u32, u32),
+    ) -> Result<HashMap<(u32, u32), i32>, EmosaicError> {
+        if self.pins.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let invalid =
+            |reason: String| EmosaicError::Validation(format!("❌ Invalid pins: {}", reason));
+        let indices: HashMap<PathBuf, i32> = tile_set
+            .tiles
+            .iter()
+            .map(|tile| (identity(tile_set.get_path(tile)), tile.idx as i32))
+            .collect();
+        let mut pinned = HashMap::new();
+        for pin in &self.pins {
+            if pin.x >= cols || pin.y >= rows {
+                return Err(invalid(format!(
+                    "{} is pinned at ({}, {}), outside the {}x{} grid",
+                    pin.path.display(),
+                    pin.x,
+                    pin.y,
+                    cols,
+                    rows
+                )));
+            }
+            let idx = *indices.get(&pin.path).ok_or_else(|| {
+                invalid(format!(
+                    "{} is pinned but not in the tile set",
+                    pin.path.display()
+                ))
+            })?;
+            let item = if pin.flipped { -idx } else { idx };
+            if pinned.insert((pin.x, pin.y), item).is_some() {
+                return Err(invalid(format!(
+                    "({}, {}) is pinned more than once",
+                    pin.x, pin.y
+                )));
+            }
+        }
+        Ok(pinned)
+    }
+}
+
+/// The canonical form of `path` when it exists, so that differently spelled paths to the
+/// same tile match, crops keeping their region. Loaded paths are canonical already.
+pub(crate) fn identity(path: &Path) -> PathBuf {
+    let (file, region) = split_crop(path);
+    let canonical = fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+    match region {
+        Some(region) => with_crop(&canonical, region).unwrap_or_else(|| path.to_path_buf()),
+        None => canonical,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pins() {
+        let pins = Pins::parse(
+            r#"
+            exclude = ["blurry.jpg"]
+
+            [[pin]]
+            path = "us.jpg"
+            x = 3
+            y = 1
+
+            [[pin]]
+            path = "dog.jpg"
+            x = 0
+            y = 0
+            flipped = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(pins.exclude, [PathBuf::from("blurry.jpg")]);
+        assert_eq!(
+            pins.pins[1],
+            Pin {
+                path: PathBuf::from("dog.jpg"),
+                x: 0,
+                y: 0,
+                flipped: true
+            }
+        );
+        assert!(pins.is_excluded(Path::new("blurry.jpg")));
+        assert!(!pins.is_excluded(Path::new("us.jpg")));
+        assert!(pins.is_pinned(Path::new("us.jpg")));
+        assert!(Pins::parse("pins = []").is_err());
+    }
+
+    #[test]
+    fn test_resolve_pins() {
+        let mut tile_set: TileSet<()> = TileSet::new();
+        tile_set.push_tile(PathBuf::from("us.jpg"), ());
+        tile_set.push_tile(PathBuf::from("dog.jpg"), ());
+        let pin = |path: &str, x, y, flipped| Pin {
+            path: PathBuf::from(path),
+            x,
+            y,
+            flipped,
+        };
+        let mut pins = Pins {
+            pins: vec![pin("dog.jpg", 0, 0, true), pin("us.jpg", 3, 1, false)],
+            exclude: vec![],
+        };
+        assert_eq!(
+            pins.resolve(&tile_set, (4, 2)).unwrap(),
+            HashMap::from([((0, 0), -2), ((3, 1), 1)])
+        );
+        let error = |pins: &Pins, grid| pins.resolve(&tile_set, grid).unwrap_err().to_string();
+        assert_eq!(
+            error(&pins, (3, 2)),
+            "❌ Invalid pins: us.jpg is pinned at (3, 1), outside the 3x2 grid"
+        );
+
+        pins.pins.push(pin("cat.jpg", 1, 1, false));
+        assert_eq!(
+            error(&pins, (4, 2)),
+            "❌ Invalid pins: cat.jpg is pinned but not in the tile set"
+        );
+        pins.pins[2] = pin("us.jpg", 0, 0, false);
+        assert_eq!(
+            error(&pins, (4, 2)),
+            "❌ Invalid pins: (0, 0) is pinned more than once"
+        );
+        assert!(matches!(
+            pins.resolve(&tile_set, (4, 2)),
+            Err(EmosaicError::Validation(_))
+        ));
+    }
+}
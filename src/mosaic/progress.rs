@@ -0,0 +1,190 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde::Serialize;
+use tracing::level_filters::LevelFilter;
+
+/// Least time between two events of the same phase, other than its last one.
+pub const EVENT_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Whether progress is reported as JSON events on stdout instead of bars on stderr.
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// The picture read last, reported with the events.
+static CURRENT_FILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Report the progress of every phase from now on as newline delimited JSON events on stdout,
+/// for scripts and user interfaces, instead of drawing bars on stderr.
+pub fn set_json(enabled: bool) {
+    JSON.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether progress is reported as JSON events.
+pub fn json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Tell the events that the picture at `path` is being read.
+pub fn set_current_file(path: &Path) {
+    if json() {
+        *CURRENT_FILE.lock().unwrap() = Some(path.to_path_buf());
+    }
+}
+
+/// The progress of a phase of the run at one point.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProgressEvent {
+    /// Name of the phase, as in the message of its bar, e.g. `Rendering`
+    pub phase: &'static str,
+    /// Number of steps of the phase done
+    pub completed: u64,
+    /// Number of steps of the phase
+    pub total: u64,
+    /// Estimated seconds left in the phase, once a step is done
+    pub eta: Option<f64>,
+    /// The picture read last, in this phase or an earlier one
+    pub file: Option<PathBuf>,
+}
+
+/// The progress of a phase of the run: a bar on stderr, or events on stdout with
+/// [`set_json`].
+pub struct Progress {
+    bar: ProgressBar,
+    phase: &'static str,
+    started: Instant,
+    /// When the last event was written and the steps done then, if any
+    reported: Mutex<Option<(Instant, u64)>>,
+}
+
+impl Progress {
+    /// Start a phase of `total` steps named `phase`.
+    pub fn new(total: u64, phase: &'static str) -> Progress {
+        let bar = ProgressBar::new(total).with_message(phase);
+        // Quiet runs draw no bars either
+        if json() || LevelFilter::current() < LevelFilter::INFO {
+            bar.set_draw_target(ProgressDrawTarget::hidden());
+        }
+        Progress {
+            bar,
+            phase,
+            started: Instant::now(),
+            reported: Mutex::new(None),
+        }
+    }
+
+    /// Start a phase of `total` steps named `phase`, with its bar drawn after `template`.
+    pub fn with_template(total: u64, phase: &'static str, template: &str) -> Progress {
+        let progress = Progress::new(total, phase);
+        progress
+            .bar
+            .set_style(ProgressStyle::default_bar().template(template).unwrap());
+        progress
+    }
+
+    /// Stop drawing the bar, e.g. while a preview of the mosaic is drawn instead. The events
+    /// are still written.
+    pub fn hide(&self) {
+        self.bar.set_draw_target(ProgressDrawTarget::hidden());
+    }
+
+    /// Count `steps` more steps done.
+    pub fn inc(&self, steps: u64) {
+        self.bar.inc(steps);
+        self.report(false);
+    }
+
+    /// Count `position` steps done in all.
+    pub fn set_position(&self, position: u64) {
+        self.bar.set_position(position);
+        self.report(false);
+    }
+
+    /// End the phase, clearing its bar.
+    pub fn finish_and_clear(&self) {
+        self.bar.finish_and_clear();
+        self.report(true);
+    }
+
+    /// The progress of the phase now.
+    pub fn event(&self) -> ProgressEvent {
+        let completed = self.bar.position();
+        let total = self.bar.length().unwrap_or(completed);
+        let eta = (completed > 0).then(|| {
+            let left = total.saturating_sub(completed) as f64;
+            let eta = self.started.elapsed().as_secs_f64() * left / completed as f64;
+            (eta * 10.0).round() / 10.0
+        });
+        ProgressEvent {
+            phase: self.phase,
+            completed,
+            total,
+            eta,
+            file: CURRENT_FILE.lock().unwrap().clone(),
+        }
+    }
+
+    /// Write the progress of the phase as a JSON event, unless one was written less than
+    /// [`EVENT_INTERVAL`] ago and the phase is not `finished`, or it was written for the same
+    /// steps done.
+    fn report(&self, finished: bool) {
+        if !json() {
+            return;
+        }
+        let completed = self.bar.position();
+        let finished = finished || self.bar.length() == Some(completed);
+        let mut reported = if finished {
+            self.reported.lock().unwrap()
+        } else {
+            // Another thread is writing an event already
+            match self.reported.try_lock() {
+                Ok(reported) => reported,
+                Err(_) => return,
+            }
+        };
+        let skip = reported.is_some_and(|(at, reported)| {
+            reported == completed || !finished && at.elapsed() < EVENT_INTERVAL
+        });
+        if skip {
+            return;
+        }
+        *reported = Some((Instant::now(), completed));
+        if let Ok(line) = serde_json::to_string(&self.event()) {
+            // A closed pipe must not stop the run
+            let _ = writeln!(io::stdout().lock(), "{}", line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event() {
+        let progress = Progress::new(4, "Rendering");
+        let event = progress.event();
+        assert_eq!(
+            (event.phase, event.completed, event.total),
+            ("Rendering", 0, 4)
+        );
+        assert_eq!(event.eta, None);
+
+        progress.inc(1);
+        progress.inc(1);
+        let event = progress.event();
+        assert_eq!((event.completed, event.total), (2, 4));
+        // The two steps left should take as long as the two done, to a tenth of a second
+        let elapsed = progress.started.elapsed().as_secs_f64();
+        assert!(event.eta.is_some_and(|eta| eta <= elapsed + 0.05));
+
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["phase"], "Rendering");
+        assert_eq!(json["completed"], 2);
+        assert_eq!(json["total"], 4);
+        assert!(json.get("file").is_some());
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use ::image::imageops::{self, FilterType};
+use ::image::{GrayImage, RgbImage};
+
+/// Side of the windows over which the structural similarity is measured.
+const SSIM_WINDOW: u32 = 8;
+/// Distance between the corners of neighbouring SSIM windows, so that they overlap.
+const SSIM_STRIDE: u32 = 4;
+/// Stabilising constants of the SSIM formula, for 8 bit channels.
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// Objective similarity of a finished mosaic to its source image, to compare modes and flags.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualityScore {
+    /// Peak signal to noise ratio over the RGB channels, in decibels: higher is closer, and
+    /// infinite for identical images
+    pub psnr: f64,
+    /// Mean structural similarity of the luma, from -1 to 1 for identical images
+    pub ssim: f64,
+}
+
+impl QualityScore {
+    /// Score `mosaic`, downsampled back to the resolution of `source`, against `source`.
+    pub fn new(mosaic: &RgbImage, source: &RgbImage) -> QualityScore {
+        QualityScore::hiding(mosaic, source, (1, 1), &HashSet::new())
+    }
+
+    /// Score `mosaic` like [`QualityScore::new`], leaving out the `hidden` cells of its `grid`
+    /// of tiles, which the mask or the clear regions hide.
+    pub fn hiding(
+        mosaic: &RgbImage,
+        source: &RgbImage,
+        (cols, rows): (u32, u32),
+        hidden: &HashSet<(u32, u32)>,
+    ) -> QualityScore {
+        let (width, height) = source.dimensions();
+        let mosaic = imageops::resize(mosaic, width, height, FilterType::Lanczos3);
+        let visible = |x: u32, y: u32| !hidden.contains(&(x * cols / width, y * rows / height));
+        QualityScore {
+            psnr: psnr(&mosaic, source, &visible),
+            ssim: ssim(
+                &imageops::grayscale(&mosaic),
+                &imageops::grayscale(source),
+                &visible,
+            ),
+        }
+    }
+}
+
+impl fmt::Display for QualityScore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.psnr.is_infinite() {
+            write!(f, "PSNR ∞ dB, SSIM {:.4}", self.ssim)
+        } else {
+            write!(f, "PSNR {:.2} dB, SSIM {:.4}", self.psnr, self.ssim)
+        }
+    }
+}
+
+/// Peak signal to noise ratio between two images of the same size, over the RGB channels of
+/// the `visible` pixels.
+fn psnr(a: &RgbImage, b: &RgbImage, visible: &dyn Fn(u32, u32) -> bool) -> f64 {
+    let (squares, n) = (a.enumerate_pixels())
+        .zip(b.pixels())
+        .filter(|((x, y, _), _)| visible(*x, *y))
+        .flat_map(|((_, _, a), b)| a.0.iter().zip(b.0.iter()))
+        .fold((0, 0), |(squares, n), (a, b)| {
+            (squares + u64::from(a.abs_diff(*b)).pow(2), n + 1)
+        });
+    let mse = squares as f64 / n.max(1) as f64;
+    10.0 * (255.0 * 255.0 / mse).log10()
+}
+
+/// Mean structural similarity between two grayscale images of the same size, over
+/// overlapping square windows of `visible` pixels, or the whole images when they are smaller
+/// than a window.
+fn ssim(a: &GrayImage, b: &GrayImage, visible: &dyn Fn(u32, u32) -> bool) -> f64 {
+    let (width, height) = a.dimensions();
+    let size = (SSIM_WINDOW.min(width), SSIM_WINDOW.min(height));
+    let lefts = (0..=width - size.0).step_by(SSIM_STRIDE as usize);
+    let tops: Vec<u32> = (0..=height - size.1)
+        .step_by(SSIM_STRIDE as usize)
+        .collect();
+    let windows: Vec<f64> = lefts
+        .flat_map(|left| tops.iter().map(move |&top| (left, top)))
+        .filter(|&(left, top)| {
+            (top..top + size.1).all(|y| (left..left + size.0).all(|x| visible(x, y)))
+        })
+        .map(|(left, top)| {
+            let pixels = || {
+                (top..top + size.1).flat_map(move |y| {
+                    (left..left + size.0).map(move |x| {
+                        (
+                            f64::from(a.get_pixel(x, y)[0]),
+                            f64::from(b.get_pixel(x, y)[0]),
+                        )
+                    })
+                })
+            };
+            let n = f64::from(size.0 * size.1);
+            let (mean_a, mean_b) = pixels().fold((0.0, 0.0), |(sa, sb), (a, b)| (sa + a, sb + b));
+            let (mean_a, mean_b) = (mean_a / n, mean_b / n);
+            let (var_a, var_b, covariance) =
+                pixels().fold((0.0, 0.0, 0.0), |(va, vb, c), (a, b)| {
+                    let (da, db) = (a - mean_a, b - mean_b);
+                    (va + da * da, vb + db * db, c + da * db)
+                });
+            let (var_a, var_b, covariance) = (var_a / n, var_b / n, covariance / n);
+            ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+                / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1) * (var_a + var_b + SSIM_C2))
+        })
+        .collect();
+    windows.iter().sum::<f64>() / windows.len().max(1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::Rgb;
+
+    #[test]
+    fn test_quality_score() {
+        let source = RgbImage::from_fn(16, 12, |x, y| Rgb([(x * 16) as u8, (y * 20) as u8, 90]));
+        // The same image at twice the resolution, as a mosaic of 2x2 tiles of the source pixels
+        let mosaic = imageops::resize(&source, 32, 24, FilterType::Nearest);
+        let score = QualityScore::new(&mosaic, &source);
+        assert!(score.psnr > 30.0, "{}", score);
+        assert!(score.ssim > 0.95, "{}", score);
+
+        let identical = QualityScore::new(&source, &source);
+        assert!(identical.psnr.is_infinite());
+        assert!((identical.ssim - 1.0).abs() < 1e-9);
+        assert_eq!(identical.to_string(), "PSNR ∞ dB, SSIM 1.0000");
+
+        // Flat gray loses the structure of the gradient
+        let flat = RgbImage::from_pixel(32, 24, Rgb([128, 128, 128]));
+        let poor = QualityScore::new(&flat, &source);
+        assert!(poor.psnr < score.psnr && poor.ssim < score.ssim);
+
+        // Images smaller than a window are compared as a whole
+        let tiny = RgbImage::from_pixel(3, 2, Rgb([10, 20, 30]));
+        assert!((QualityScore::new(&tiny, &tiny).ssim - 1.0).abs() < 1e-9);
+
+        // Hiding the flat right half of a mosaic scores the left half alone
+        let half = RgbImage::from_fn(32, 24, |x, y| match x < 16 {
+            true => *mosaic.get_pixel(x, y),
+            false => Rgb([128, 128, 128]),
+        });
+        let hidden = HashSet::from([(1, 0)]);
+        let visible = QualityScore::hiding(&half, &source, (2, 1), &hidden);
+        let whole = QualityScore::new(&half, &source);
+        assert!(
+            visible.psnr > whole.psnr && visible.ssim > whole.ssim,
+            "{}",
+            visible
+        );
+    }
+}
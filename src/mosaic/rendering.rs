@@ -1,20 +1,41 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Mutex, RwLock};
 
 use ::image::RgbImage;
 use ::image::{imageops, Rgb};
-use indicatif::{ProgressBar, ProgressStyle};
+use kiddo::distance_metric::DistanceMetric;
 use kiddo::fixed::distance::Manhattan;
+use kiddo::fixed::kdtree::KdTree;
 use kiddo::NearestNeighbour;
 use rand::prelude::IteratorRandom;
 use rand::prelude::SliceRandom;
-use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+};
+use tracing::{debug, debug_span, info};
 
 use super::algorithms::compare_matches;
-use super::analysis::get_img_colors;
-use super::error::ImageError;
-use super::stats::RenderStats;
-use super::tiles::{flipped_coords, Tile, TileSet};
+use super::analysis::{analyse, block_variances, downsample, source_blocks};
+use super::cancel;
+use super::color::{average_color, correct_colors};
+use super::continuity::{clash, Side};
+use super::edges::EdgeFeatures;
+use super::error::{EmosaicError, ImageError};
+use super::layout::cut_rectangles;
+use super::manifest::Manifest;
+use super::memory;
+use super::progress::Progress;
+use super::stats::{RenderStats, TileFailure};
+use super::story::{events, grow_regions, StoryOptions};
+use super::tiles::{
+    flipped_coords, split_crop, IndexKind, Tile, TileIndex, TileSet, MAX_TILES, SIZE,
+};
+use super::time_gradient::{date_positions, deviation, TimeGradient};
 use fixed::traits::FromFixed;
 
 /// Configuration for rendering operations
@@ -22,6 +43,21 @@ use fixed::traits::FromFixed;
 pub struct RenderConfig {
     /// Number of nearest neighbors to consider for randomized selection
     pub random_neighbor_count: usize,
+    /// Number of nearest neighbors weighed against their dates on a time gradient
+    pub gradient_neighbor_count: usize,
+    /// Number of nearest neighbors weighed against the weights of their sources and their edges
+    pub weighted_neighbor_count: usize,
+    /// Number of nearest tiles, and of the cells holding each, tried for swaps with a cell
+    pub swap_neighbor_count: usize,
+    /// Number of nearest tiles a cell may be given instead of its own when annealing
+    pub anneal_neighbor_count: usize,
+    /// Number of nearest tiles tried in turn when the image of a matched tile cannot be loaded
+    pub retry_neighbor_count: usize,
+    /// Number of nearest tiles scored for every cell at once by the greedy no repeat mode
+    pub greedy_neighbor_count: usize,
+    /// Default penalty, in color distance per pixel of a block, for placing a tile at the
+    /// opposite end of a time gradient from its date
+    pub time_gradient_penalty: f64,
     /// Progress bar template
     pub progress_template: String,
 }
@@ -30,11 +66,31 @@ impl Default for RenderConfig {
     fn default() -> Self {
         Self {
             random_neighbor_count: 20,
+            gradient_neighbor_count: 50,
+            weighted_neighbor_count: 50,
+            swap_neighbor_count: 10,
+            anneal_neighbor_count: 20,
+            retry_neighbor_count: 5,
+            greedy_neighbor_count: 100000,
+            time_gradient_penalty: 48.0,
             progress_template: "{msg} {wide_bar} {pos}/{len} ({per_sec})".to_string(),
         }
     }
 }
 
+/// Number of cells scored at once by the greedy no repeat mode within a memory budget.
+const SCORING_BATCH: u32 = 4096;
+
+/// Fewest nearest tiles scored for a cell at once by the greedy no repeat mode.
+const MIN_SCORED_NEIGHBORS: usize = 10;
+
+/// Callback notified of each row of tiles as soon as it is rendered, in no particular order,
+/// with its vertical offset in the output image.
+pub type RowCallback<'a> = &'a (dyn Fn(&RgbImage, u32) + Sync);
+
+/// Columns and rows of the segments whose tile image could not be loaded, with the errors.
+pub type LoadFailures = Vec<((u32, u32), ImageError)>;
+
 /// Core rendering function that creates a mosaic by applying tiles to segments of the source image.
 ///
 /// This function processes the image in parallel, dividing it into segments and applying
@@ -44,39 +100,62 @@ impl Default for RenderConfig {
 /// * `source_img` - The source image to create a mosaic from
 /// * `tile_size` - Size of each tile in pixels
 /// * `step` - Step size for tile placement (affects tile density)
-/// * `get_tile` - Function that generates a tile image for given coordinates
+/// * `in_place` - Whether every row is copied into the mosaic as soon as it is rendered rather
+///   than kept until all are, so the rows are not held besides the mosaic, e.g. within a memory
+///   budget
+/// * `get_tile` - Function that generates a tile image for given coordinates, or fails when
+///   the image cannot be loaded
+/// * `on_row` - Optional callback notified of each rendered row, replacing the progress bars
 ///
 /// # Returns
-/// A new `RgbImage` containing the rendered mosaic
+/// A new `RgbImage` containing the rendered mosaic, with a solid block of the average color
+/// of the segment wherever `get_tile` failed, and the columns and rows and errors of the
+/// failures in no particular order
 pub fn render(
     source_img: &RgbImage,
     tile_size: u32,
     step: u32,
-    get_tile: impl Fn(u32, u32) -> ::image::ImageBuffer<Rgb<u8>, Vec<u8>> + Sync,
-) -> RgbImage {
+    in_place: bool,
+    get_tile: impl Fn(u32, u32) -> Result<RgbImage, ImageError> + Sync,
+    on_row: Option<RowCallback>,
+) -> (RgbImage, LoadFailures) {
+    let _span = debug_span!("compositing").entered();
     let tile_size_stepped = tile_size / step;
 
     let config = RenderConfig::default();
-    let pb = ProgressBar::new((source_img.height() * source_img.width() / step / step) as u64)
-        .with_message("Rendering")
-        .with_style(
-            ProgressStyle::default_bar()
-                .template(&config.progress_template)
-                .unwrap(),
-        );
+    let pb = Progress::with_template(
+        (source_img.height() * source_img.width() / step / step) as u64,
+        "Rendering",
+        &config.progress_template,
+    );
+    if on_row.is_some() {
+        pb.hide();
+    }
 
+    let output = Mutex::new(RgbImage::new(
+        source_img.width() * tile_size_stepped,
+        source_img.height() * tile_size_stepped,
+    ));
+    // Even in place, the whole mosaic is held, as it is tinted and encoded in one piece
+    let failures = Mutex::new(Vec::new());
     let segments: Vec<_> = (0..source_img.height())
         .into_par_iter()
         .step_by(step as usize)
         .map(|y| {
             let mut image = RgbImage::new(source_img.width() * tile_size_stepped, tile_size);
-            let mut indices: Vec<_> = (0..source_img.width()).step_by(step as usize).collect();
-            indices.shuffle(&mut rand::thread_rng());
-
-            for x in indices.into_iter() {
+            for x in (0..source_img.width()).step_by(step as usize) {
                 pb.inc(1);
+                // The cells left after an interruption stay black
+                if cancel::interrupted() {
+                    continue;
+                }
 
-                let tile_img = get_tile(x, y);
+                let tile_img = get_tile(x, y).unwrap_or_else(|e| {
+                    failures.lock().unwrap().push(((x / step, y / step), e));
+                    let segment = imageops::crop_imm(source_img, x, y, step, step);
+                    let color = *imageops::thumbnail(&*segment, 1, 1).get_pixel(0, 0);
+                    RgbImage::from_pixel(tile_size, tile_size, color)
+                });
 
                 // Calculate tile coordinates in output image
                 let tile_x = x * tile_size_stepped;
@@ -84,23 +163,140 @@ pub fn render(
 
                 imageops::replace(&mut image, &tile_img, tile_x.into(), tile_y.into());
             }
-            image
+            if let Some(on_row) = on_row {
+                on_row(&image, y / step * tile_size);
+            }
+            if in_place {
+                let top = i64::from(y / step * tile_size);
+                imageops::replace(&mut *output.lock().unwrap(), &image, 0, top);
+                return None;
+            }
+            Some(image)
         })
         .collect();
 
-    let mut output = RgbImage::new(
-        source_img.width() * tile_size_stepped,
-        source_img.height() * tile_size_stepped,
-    );
-    let pb = ProgressBar::new((source_img.height() / step) as u64).with_message("Merging");
-    for (i, segment) in segments.into_iter().enumerate() {
-        pb.inc(1);
-        imageops::replace(&mut output, &segment, 0, i as i64 * tile_size as i64);
+    let mut output = output.into_inner().unwrap();
+    if !in_place {
+        let pb = Progress::new((source_img.height() / step) as u64, "Merging");
+        if on_row.is_some() {
+            pb.hide();
+        }
+        for (i, segment) in segments.into_iter().flatten().enumerate() {
+            pb.inc(1);
+            imageops::replace(&mut output, &segment, 0, i as i64 * tile_size as i64);
+        }
+    }
+    (output, failures.into_inner().unwrap())
+}
+
+/// A tile placed in a cell instead of the one matched to it, at the given distance, because
+/// the image of the matched tile failed to load with the given error.
+type Replacement<T> = (Tile<T>, SIZE, ImageError);
+
+/// Load the image of `tile`, matched to a cell, or when it cannot be loaded, e.g. because
+/// the picture was deleted after the analysis, the image of the nearest of `alternatives`
+/// that can, with the tile it belongs to.
+///
+/// # Returns
+/// * `Ok((image, None))` - The image of `tile`
+/// * `Ok((image, Some(replacement)))` - The image of another tile
+/// * `Err(ImageError)` - If neither `tile` nor any of `alternatives` could be loaded
+fn load_or_replace<T: Copy>(
+    tile_set: &TileSet<T>,
+    tile: &Tile<T>,
+    load: impl Fn(&Tile<T>) -> Result<RgbImage, ImageError>,
+    alternatives: impl FnOnce() -> Vec<NearestNeighbour<SIZE, i32>>,
+) -> Result<(RgbImage, Option<Replacement<T>>), ImageError> {
+    let error = match load(tile) {
+        Ok(image) => return Ok((image, None)),
+        Err(error) => error,
+    };
+    for alternative in alternatives() {
+        if alternative.item.unsigned_abs() == tile.idx {
+            continue;
+        }
+        let Some(replacement) = tile_set.get_tile(alternative.item) else {
+            continue;
+        };
+        if let Ok(image) = load(&replacement) {
+            return Ok((image, Some((replacement, alternative.distance, error))));
+        }
     }
-    output
+    Err(error)
 }
 
-/// Renders a mosaic using N-to-1 tile matching with KD-tree optimization.
+/// Record in `stats` the cells given `replacements` for their matched tiles, and the cells
+/// left with a solid block because no tile could be loaded, in reading order.
+fn record_failures<T>(
+    stats: &mut RenderStats<SIZE>,
+    mut replacements: Vec<((u32, u32), Replacement<T>)>,
+    mut failures: LoadFailures,
+) {
+    replacements.sort_by_key(|&((col, row), _)| (row, col));
+    failures.sort_by_key(|&((col, row), _)| (row, col));
+    for ((col, row), (tile, distance, error)) in replacements {
+        stats.push_tile(col, row, &tile, distance);
+        stats.push_failure(TileFailure {
+            position: (col, row),
+            error: error.to_string(),
+            replaced: true,
+        });
+    }
+    for (position, error) in failures {
+        stats.push_failure(TileFailure {
+            position,
+            error: error.to_string(),
+            replaced: false,
+        });
+    }
+}
+
+/// How [`render_nto1`] matches the tiles to the cells of the mosaic, besides their colors.
+/// The default is the nearest tile of every cell, found with the default index.
+#[derive(Clone, Default)]
+pub struct MatchOptions<'a, const N: usize> {
+    /// Place every tile at most once
+    pub no_repeat: bool,
+    /// Randomization factor (0-100%) for tile selection, with the largest variance of the
+    /// colors around a cell for it to be randomized, if any: detailed regions such as faces and
+    /// edges keep their best matches while flat ones get variety
+    pub randomize: Option<(f64, Option<f64>)>,
+    /// Nearest neighbor index used to find matching tiles
+    pub index: IndexKind,
+    /// Direction in which the chosen tiles should get newer, trading some color accuracy for
+    /// dates that read chronologically across the mosaic, with the penalty, in color distance
+    /// per pixel of a block, for placing a tile at the opposite end of the gradient from its
+    /// date
+    pub time_gradient: Option<(TimeGradient, f64)>,
+    /// Signed indices of the tiles pinned to grid cells, by column and row. They are placed as
+    /// is and taken out of the index, so they only appear where pinned
+    pub pins: HashMap<(u32, u32), i32>,
+    /// Weight of every tile, in tile order, favoring the tiles of some sources: the distances
+    /// of the nearest matches are divided by their weights
+    pub weights: Option<&'a [f64]>,
+    /// Edges of the source blocks and of the tiles: the weighted difference in edges is added
+    /// to the distances of the nearest matches, so tiles follow contours
+    pub edges: Option<&'a EdgeFeatures<N>>,
+    /// Penalty, in color distance per pixel of a block, added to the distance of a tile for
+    /// every time it was already placed, so the mosaic uses more distinct tiles without the
+    /// hard tile count requirement of `no_repeat`
+    pub repeat_penalty: Option<f64>,
+    /// Weight of the clash between the colors along the sides of a tile and those of the tiles
+    /// already placed next to its cell, in color distance per pixel of a block, so
+    /// neighbouring tiles blend without post processing. Needs an analysis grid of at least
+    /// 2x2, and cells matched one at a time
+    pub continuity: Option<f64>,
+    /// Distance, per pixel of a block and summed over the RGB channels, beyond which cells are
+    /// given a synthetic tile of their average color instead of their best match
+    pub filler: Option<u32>,
+    /// Distance, per pixel of a block and summed over the RGB channels, beyond which the
+    /// colors of the best match are shifted to those of the block
+    pub correct: Option<u32>,
+    /// Callback notified of each rendered row, replacing the progress bars
+    pub on_row: Option<RowCallback<'a>>,
+}
+
+/// Renders a mosaic using N-to-1 tile matching with nearest neighbor search.
 ///
 /// This function analyzes the source image in N-pixel blocks and finds the best matching
 /// tiles from the tile set using nearest neighbor search in color space.
@@ -109,37 +305,53 @@ pub fn render(
 /// * `source_img` - The source image to create a mosaic from
 /// * `tile_set` - Set of available tiles with pre-computed color analysis
 /// * `tile_size` - Size of each output tile in pixels
-/// * `no_repeat` - If true, prevents tiles from being used multiple times
-/// * `randomize` - Optional randomization factor (0-100%) for tile selection
+/// * `options` - How the tiles are matched to the cells
 ///
 /// # Returns
 /// * `Ok(RenderResult)` - Contains the rendered image, statistics, and tile set
-/// * `Err(RenderError)` - If rendering fails due to insufficient tiles or other errors
+/// * `Err(EmosaicError::Render)` - If there are too few tiles for `no_repeat`
 ///
 /// # Examples
 /// ```
-/// use emosaic::mosaic::rendering::render_nto1;
-/// // let result = render_nto1(&image, tile_set, 32, false, None)?;
+/// use emosaic::mosaic::rendering::{render_nto1, MatchOptions};
+/// // let options = MatchOptions { no_repeat: true, ..MatchOptions::default() };
+/// // let result = render_nto1(&image, tile_set, 32, options)?;
 /// ```
 pub fn render_nto1<const N: usize>(
     source_img: &RgbImage,
-    tile_set: TileSet<[Rgb<u8>; N]>,
+    mut tile_set: TileSet<[Rgb<u8>; N]>,
     tile_size: u32,
-    no_repeat: bool,
-    randomize: Option<f64>,
-) -> RenderResult<N>
+    options: MatchOptions<'_, N>,
+) -> Result<RenderResult<N>, EmosaicError>
 where
     [(); N * 3]:,
 {
-    let stats = Mutex::new(RenderStats::new());
-
-    let kdtree = RwLock::new(tile_set.build_kiddo());
+    let MatchOptions {
+        no_repeat,
+        randomize,
+        index,
+        time_gradient,
+        mut pins,
+        weights,
+        edges,
+        repeat_penalty,
+        continuity,
+        filler,
+        correct,
+        on_row,
+    } = options;
+    let mut kdtree = tile_set.build_index(index);
+    for &item in pins.values() {
+        for item in [item, -item] {
+            kdtree.remove(&tile_set.get_tile(item).unwrap().coords(), item);
+        }
+    }
 
     let step = (N as f64).sqrt() as u32;
 
     let htiles = source_img.width() / step;
     let vtiles = source_img.height() / step;
-    eprintln!(
+    info!(
         "Doing {}x{} tiles resulting in a {}x{} image (step: {step})",
         htiles,
         vtiles,
@@ -148,79 +360,399 @@ where
     );
 
     if no_repeat && (htiles * vtiles) as usize > tile_set.len() * 2 {
-        panic!(
-            "❌ Insufficient tiles for no-repeat mode: need {} tiles but only have {} available",
-            (htiles * vtiles) as usize,
-            tile_set.len() * 2
-        );
+        return Err(insufficient_tiles(htiles * vtiles, tile_set.len() * 2));
     }
 
-    let image = render(source_img, tile_size, step, |x, y| {
-        let colors = get_img_colors(x, y, step, source_img);
-        let mut tile = Tile::from_colors(colors);
-        let closest: NearestNeighbour<_, _>;
-        {
-            let writer = if no_repeat {
-                Some(kdtree.write().unwrap())
-            } else {
-                None
+    let blocks = source_blocks::<N>(source_img);
+    let block = |x: u32, y: u32| blocks[(y / step * htiles + x / step) as usize];
+
+    // Cells that no tile matches closely enough get synthetic tiles, placed like pins
+    if let Some(threshold) = filler {
+        let max_distance = SIZE::from_num(threshold as usize * N);
+        let cells: Vec<(u32, u32)> = (0..vtiles)
+            .flat_map(|row| (0..htiles).map(move |col| (col, row)))
+            .filter(|cell| !pins.contains_key(cell))
+            .collect();
+        let poor: Vec<_> = cells
+            .into_par_iter()
+            .filter_map(|(col, row)| {
+                let colors = blocks[(row * htiles + col) as usize];
+                let coords = Tile::from_colors(colors).coords();
+                let nearest = kdtree.nearest_one(&coords);
+                (nearest.distance > max_distance).then_some(((col, row), colors))
+            })
+            .collect();
+        let mut fillers: HashMap<[u8; 3], i32> = HashMap::new();
+        for (cell, colors) in &poor {
+            let color = downsample::<1>(colors)[0];
+            let item = match fillers.get(&color.0) {
+                Some(&item) => item,
+                None if tile_set.len() >= MAX_TILES => continue,
+                None => tile_set.push_filler([color; N], color) as i32,
             };
-            match randomize {
-                Some(factor) => {
-                    let config = RenderConfig::default();
-                    let mut closest_ones = kdtree
-                        .read()
-                        .unwrap()
-                        .nearest_n::<Manhattan>(&tile.coords(), config.random_neighbor_count);
-                    closest_ones.sort_by_key(|x| x.distance);
-                    let min_distance = f64::from_fixed(closest_ones[0].distance);
-                    closest = closest_ones
-                        .into_iter()
-                        .take_while(|x| {
-                            f64::from_fixed(x.distance) - min_distance
-                                < factor * min_distance / 100.0
-                        })
-                        .choose(&mut rand::thread_rng())
-                        .unwrap();
-                }
-                _ => {
-                    closest = writer.as_ref().map_or_else(
-                        || {
-                            kdtree
-                                .read()
-                                .unwrap()
-                                .nearest_one::<Manhattan>(&tile.coords())
-                        },
-                        |kdtree| kdtree.nearest_one::<Manhattan>(&tile.coords()),
-                    );
+            fillers.insert(color.0, item);
+            pins.insert(*cell, item);
+        }
+        info!(
+            "Filled {} cells farther than {} from every tile with {} synthetic tiles",
+            poor.len(),
+            threshold,
+            fillers.len()
+        );
+    }
+    let pins = &pins;
+    // Cells randomized when only the flat regions are
+    let randomized: Option<Vec<bool>> = randomize
+        .and_then(|(_, max_variance)| max_variance)
+        .map(|max_variance| {
+            let variances = block_variances(&blocks, htiles as usize);
+            let randomized: Vec<bool> = variances.iter().map(|&v| v <= max_variance).collect();
+            info!(
+                "Randomizing {} of {} cells, keeping the best matches where the variance exceeds {}",
+                randomized.iter().filter(|&&randomized| randomized).count(),
+                randomized.len(),
+                max_variance
+            );
+            randomized
+        });
+    let correct = correct.map(|threshold| (threshold, SIZE::from_num(threshold as usize * N)));
+    let corrected = AtomicUsize::new(0);
+
+    let date_positions = time_gradient.map(|_| date_positions(&tile_set));
+    let config = RenderConfig::default();
+    // Color distance divided by the weight of the source of the tile, plus the difference in
+    // edges with the block, the penalty for the times the tile was already placed and the
+    // clash with the tiles already placed around the cell
+    let weighted = |candidate: &NearestNeighbour<SIZE, i32>, cell: usize, placed: &Placed| {
+        let position = candidate.item.unsigned_abs() as usize - 1;
+        let weight = weights.map_or(1.0, |weights| {
+            // Fillers are added after the weights were computed
+            weights.get(position).copied().unwrap_or(1.0)
+        });
+        let edges = edges.map_or(0.0, |edges| edges.distance(cell, candidate.item));
+        let repeats = repeat_penalty.map_or(0.0, |penalty| {
+            penalty * N as f64 * f64::from(placed.uses.get(position).copied().unwrap_or_default())
+        });
+        let clashes = continuity.map_or(0.0, |weight| {
+            let tile = tile_set.get_tile(candidate.item).unwrap();
+            let (col, row) = (cell as u32 % htiles, cell as u32 / htiles);
+            let clashes: Vec<f64> = Side::ALL
+                .iter()
+                .filter_map(|&side| {
+                    let (col, row) = side.neighbour((col, row), (htiles, vtiles))?;
+                    let item = placed.items.get((row * htiles + col) as usize).copied()??;
+                    Some(clash(&tile, &tile_set.get_tile(item).unwrap(), side))
+                })
+                .collect();
+            let mean = clashes.iter().sum::<f64>() / clashes.len().max(1) as f64;
+            weight * N as f64 * mean
+        });
+        f64::from_fixed(candidate.distance) / weight + edges + repeats + clashes
+    };
+
+    // Match a cell given the tiles placed before it. Random choices are seeded by the cell, so
+    // they do not depend on scheduling
+    let kdtree = RwLock::new(kdtree);
+    let match_cell = |cell: usize, placed: &Placed| {
+        let kdtree = kdtree.read().unwrap();
+        let (col, row) = (cell as u32 % htiles, cell as u32 / htiles);
+        let colors = blocks[cell];
+        let coords = Tile::from_colors(colors).coords();
+        if let Some(&item) = pins.get(&(col, row)) {
+            let pinned = tile_set.get_tile(item).unwrap();
+            let distance = Manhattan::dist(&coords, &pinned.coords());
+            return NearestNeighbour { distance, item };
+        }
+        let randomize = randomize.map(|(factor, _)| factor).filter(|_| {
+            randomized
+                .as_ref()
+                .is_none_or(|randomized| randomized[cell])
+        });
+        let closest = match (randomize, time_gradient.zip(date_positions.as_ref())) {
+            (_, Some(((gradient, penalty), date_positions))) => {
+                let position =
+                    gradient.position((col * step, row * step), step, source_img.dimensions());
+                let candidates = kdtree.nearest_n(&coords, config.gradient_neighbor_count);
+                let score = |candidate: &NearestNeighbour<SIZE, i32>| {
+                    let date = date_positions[candidate.item.unsigned_abs() as usize - 1];
+                    weighted(candidate, cell, placed)
+                        + penalty * N as f64 * deviation(date, position)
+                };
+                candidates
+                    .into_iter()
+                    .min_by(|a, b| score(a).total_cmp(&score(b)))
+                    .unwrap()
+            }
+            (Some(factor), None) => {
+                let mut closest_ones = kdtree.nearest_n(&coords, config.random_neighbor_count);
+                closest_ones.sort_by(|a, b| {
+                    weighted(a, cell, placed).total_cmp(&weighted(b, cell, placed))
+                });
+                let min_distance = weighted(&closest_ones[0], cell, placed);
+                closest_ones
+                    .into_iter()
+                    .take_while(|x| {
+                        weighted(x, cell, placed) - min_distance <= factor * min_distance / 100.0
+                    })
+                    .choose(&mut StdRng::seed_from_u64(cell as u64))
+                    .unwrap()
+            }
+            (None, None)
+                if weights.is_some()
+                    || edges.is_some()
+                    || repeat_penalty.is_some()
+                    || continuity.is_some() =>
+            {
+                kdtree
+                    .nearest_n(&coords, config.weighted_neighbor_count)
+                    .into_iter()
+                    .min_by(|a, b| weighted(a, cell, placed).total_cmp(&weighted(b, cell, placed)))
+                    .unwrap()
+            }
+            (None, None) => kdtree.nearest_one(&coords),
+        };
+        assert!(
+            closest.item != 0,
+            "Closest item should not be zero. Did you use FixedU8? closest: {:?}, len(kdtree): {}",
+            closest,
+            kdtree.size()
+        );
+        closest
+    };
+
+    // Assign every cell its tile first, and composite the tiles second. Without repetition,
+    // with a repeat penalty or with continuity every choice depends on the choices made before
+    // it, so the cells are matched one at a time in a fixed shuffled order rather than in
+    // parallel
+    let cells = (htiles * vtiles) as usize;
+    let scoring = debug_span!("scoring").entered();
+    let pb = Progress::with_template(cells as u64, "Matching", &config.progress_template);
+    if on_row.is_some() {
+        pb.hide();
+    }
+    let sequential = no_repeat || repeat_penalty.is_some() || continuity.is_some();
+    let assigned: Vec<NearestNeighbour<SIZE, i32>> = if sequential {
+        let mut assigned = vec![None; cells];
+        let mut placed = Placed {
+            uses: vec![0; tile_set.len()],
+            items: vec![None; cells],
+        };
+        let mut order: Vec<usize> = (0..cells).collect();
+        order.shuffle(&mut StdRng::seed_from_u64(0));
+        for cell in order {
+            if cancel::interrupted() {
+                return Err(interrupted_matching());
+            }
+            pb.inc(1);
+            let closest = match_cell(cell, &placed);
+            let (col, row) = (cell as u32 % htiles, cell as u32 / htiles);
+            if !pins.contains_key(&(col, row)) {
+                if no_repeat {
+                    let tile = tile_set.get_tile(closest.item).unwrap();
+                    kdtree.write().unwrap().remove(&tile.coords(), closest.item);
                 }
+                placed.uses[closest.item.unsigned_abs() as usize - 1] += 1;
             }
-            assert!(
-                closest.item != 0,
-                "Closest item should not be zero. Did you use FixedU8? closest: {:?}, len(kdtree): {}",
-                closest,
-                kdtree.read().unwrap().size()
-            );
-            tile = tile_set
-                .get_tile(closest.item)
-                .unwrap_or_else(|| panic!("Tile not found: {:?}", closest.item));
-            if no_repeat {
-                writer.unwrap().remove(&tile.coords(), closest.item);
+            placed.items[cell] = Some(closest.item);
+            assigned[cell] = Some(closest);
+        }
+        assigned.into_iter().map(Option::unwrap).collect()
+    } else {
+        (0..cells)
+            .into_par_iter()
+            .inspect(|_| pb.inc(1))
+            .map(|cell| match_cell(cell, &Placed::default()))
+            .collect()
+    };
+    pb.finish_and_clear();
+    drop(scoring);
+
+    let mut stats = RenderStats::new();
+    for (cell, closest) in assigned.iter().enumerate() {
+        let (col, row) = (cell as u32 % htiles, cell as u32 / htiles);
+        let tile = tile_set
+            .get_tile(closest.item)
+            .unwrap_or_else(|| panic!("Tile not found: {:?}", closest.item));
+        stats.push_tile(col, row, &tile, closest.distance);
+    }
+
+    let replacements = Mutex::new(Vec::new());
+    let in_place = tile_set.memory_budget().is_some();
+    let get_tile = |x, y| {
+        let colors = block(x, y);
+        let (col, row) = (x / step, y / step);
+        let closest = &assigned[(row * htiles + col) as usize];
+        let tile = tile_set.get_tile(closest.item).unwrap();
+        let coords = Tile::from_colors(colors).coords();
+        let load = |tile: &Tile<_>| tile_set.get_image(tile, tile_size);
+        let alternatives = || {
+            kdtree
+                .read()
+                .unwrap()
+                .nearest_n(&coords, config.retry_neighbor_count)
+        };
+        let (mut image, replacement) = load_or_replace(&tile_set, &tile, load, alternatives)?;
+        let (tile, distance) = match replacement {
+            Some((tile, distance, error)) => {
+                let replacement = (tile.clone(), distance, error);
+                replacements.lock().unwrap().push(((col, row), replacement));
+                (tile, distance)
             }
+            None => (tile, closest.distance),
+        };
+        let unpinned = !pins.contains_key(&(col, row));
+        if correct.is_some_and(|(_, max_distance)| unpinned && distance > max_distance) {
+            correct_colors(&mut image, &tile.colors, &colors, tile.flipped);
+            corrected.fetch_add(1, Ordering::Relaxed);
         }
+        Ok(image)
+    };
+    let (image, failures) = render(source_img, tile_size, step, in_place, get_tile, on_row);
+    record_failures(&mut stats, replacements.into_inner().unwrap(), failures);
+
+    if let Some((threshold, _)) = correct {
+        info!(
+            "Color corrected {} tiles farther than {} from their cell",
+            corrected.into_inner(),
+            threshold
+        );
+    }
+    Ok(RenderResult {
+        image,
+        stats,
+        tile_set,
+    })
+}
+
+/// The tiles placed so far while the cells of a mosaic are matched one at a time.
+#[derive(Default)]
+struct Placed {
+    /// Number of cells every tile was placed in, in tile order
+    uses: Vec<u32>,
+    /// Signed index of the tile placed in every cell so far, row by row
+    items: Vec<Option<i32>>,
+}
+
+/// Renders a mosaic in the "story" layout, where zooming into any area shows pictures from a
+/// single event.
+///
+/// Adjacent cells of similar colors are first grown into small regions and the tiles are
+/// grouped into events by the date they were taken. Each region is then assigned the event
+/// whose average colors best match its cells, and every cell is matched against the tiles
+/// of its region's event only.
+///
+/// # Arguments
+/// * `source_img` - The source image to create a mosaic from
+/// * `tile_set` - Set of available tiles with pre-computed color analysis
+/// * `tile_size` - Size of each output tile in pixels
+/// * `index` - Nearest neighbor index used to find matching tiles in each event
+/// * `options` - Event and region settings
+/// * `on_row` - Optional callback notified of each rendered row, replacing the progress bars
+///
+/// # Returns
+/// * `RenderResult` - Contains the rendered image, statistics, and tile set
+pub fn render_nto1_story<const N: usize>(
+    source_img: &RgbImage,
+    tile_set: TileSet<[Rgb<u8>; N]>,
+    tile_size: u32,
+    index: IndexKind,
+    options: &StoryOptions,
+    on_row: Option<RowCallback>,
+) -> RenderResult<N>
+where
+    [(); N * 3]:,
+{
+    let step = (N as f64).sqrt() as u32;
+
+    let htiles = source_img.width() / step;
+    let vtiles = source_img.height() / step;
+    info!(
+        "Doing {}x{} tiles resulting in a {}x{} image (step: {step})",
+        htiles,
+        vtiles,
+        htiles * tile_size,
+        vtiles * tile_size,
+    );
+
+    let cells = source_blocks::<N>(source_img);
+    let average = |colors: &[Rgb<u8>]| downsample::<1>(colors)[0];
+    let cell_colors: Vec<_> = cells.iter().map(|colors| average(colors)).collect();
+    let (regions, region_count) = grow_regions(&cell_colors, htiles as usize, options);
+    let events = events(&tile_set, options);
+    info!(
+        "Telling a story of {} events across {} regions",
+        events.len(),
+        region_count
+    );
+
+    // Assign each region the event whose average tile colors are closest to its cells
+    let event_colors: Vec<KdTree<SIZE, u32, 3, 640, u32>> = events
+        .iter()
+        .map(|event| {
+            let mut kd = KdTree::new();
+            for (i, &position) in event.iter().enumerate() {
+                let color = average(&tile_set.tiles[position].colors);
+                kd.add(&color.0.map(SIZE::from_num), i as u32);
+            }
+            kd
+        })
+        .collect();
+    let mut region_cells = vec![Vec::new(); region_count];
+    for (cell, &region) in regions.iter().enumerate() {
+        region_cells[region].push(cell);
+    }
+    let region_events: Vec<usize> = region_cells
+        .par_iter()
+        .map(|cells| {
+            let cost = |kd: &KdTree<SIZE, u32, 3, 640, u32>| -> SIZE {
+                cells
+                    .iter()
+                    .map(|&cell| {
+                        let color = cell_colors[cell].0.map(SIZE::from_num);
+                        kd.nearest_one::<Manhattan>(&color).distance
+                    })
+                    .sum()
+            };
+            (0..events.len())
+                .min_by_key(|&event| cost(&event_colors[event]))
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let indices: Vec<_> = events
+        .iter()
+        .map(|event| tile_set.build_index_of(index, event))
+        .collect();
+    let stats = Mutex::new(RenderStats::new());
+
+    let config = RenderConfig::default();
+    let replacements = Mutex::new(Vec::new());
+    let in_place = tile_set.memory_budget().is_some();
+    let get_tile = |x, y| {
+        let (col, row) = (x / step, y / step);
+        let cell = (row * htiles + col) as usize;
+        let coords = Tile::from_colors(cells[cell]).coords();
+        let index = &indices[region_events[regions[cell]]];
+        let closest = index.nearest_one(&coords);
+        let tile = tile_set
+            .get_tile(closest.item)
+            .unwrap_or_else(|| panic!("Tile not found: {:?}", closest.item));
         stats
             .lock()
             .unwrap()
-            .push_tile(x, y, &tile, closest.distance);
-        tile_set.get_image(&tile, tile_size).unwrap_or_else(|_| {
-            panic!(
-                "Image not found: {}",
-                tile_set.get_path(&tile).to_str().unwrap()
-            )
-        })
-    });
+            .push_tile(col, row, &tile, closest.distance);
+        let load = |tile: &Tile<_>| tile_set.get_image(tile, tile_size);
+        let alternatives = || index.nearest_n(&coords, config.retry_neighbor_count);
+        let (image, replacement) = load_or_replace(&tile_set, &tile, load, alternatives)?;
+        if let Some(replacement) = replacement {
+            replacements.lock().unwrap().push(((col, row), replacement));
+        }
+        Ok(image)
+    };
+    let (image, failures) = render(source_img, tile_size, step, in_place, get_tile, on_row);
 
-    let stats = stats.into_inner().unwrap();
+    let mut stats = stats.into_inner().unwrap();
+    record_failures(&mut stats, replacements.into_inner().unwrap(), failures);
 
     RenderResult {
         image,
@@ -229,6 +761,130 @@ where
     }
 }
 
+/// Renders a mosaic on irregular rectangles of grid cells instead of the regular grid.
+///
+/// The grid is cut at random into rectangles of one to `max_cells` cells a side, the same
+/// for every run. Each rectangle is analysed as a whole into N blocks and given its nearest
+/// tile, scaled to cover the rectangle and cropped to it around its center.
+///
+/// # Arguments
+/// * `source_img` - The source image to create a mosaic from
+/// * `tile_set` - Set of available tiles with pre-computed color analysis
+/// * `tile_size` - Size of each output tile in pixels, for a rectangle of one cell
+/// * `index` - Nearest neighbor index used to find matching tiles
+/// * `max_cells` - Largest side of a rectangle, in grid cells
+///
+/// # Returns
+/// * `RenderResult` - Contains the rendered image, statistics, and tile set. The statistics
+///   record every rectangle at its top left cell
+pub fn render_nto1_irregular<const N: usize>(
+    source_img: &RgbImage,
+    tile_set: TileSet<[Rgb<u8>; N]>,
+    tile_size: u32,
+    index: IndexKind,
+    max_cells: u32,
+) -> RenderResult<N>
+where
+    [(); N * 3]:,
+{
+    let step = (N as f64).sqrt() as u32;
+
+    let htiles = source_img.width() / step;
+    let vtiles = source_img.height() / step;
+    let rects = cut_rectangles(htiles, vtiles, max_cells, &mut StdRng::seed_from_u64(0));
+    info!(
+        "Doing {} rectangles of up to {}x{} tiles resulting in a {}x{} image (step: {step})",
+        rects.len(),
+        max_cells,
+        max_cells,
+        htiles * tile_size,
+        vtiles * tile_size,
+    );
+
+    let kdtree = tile_set.build_index(index);
+    let config = RenderConfig::default();
+    let _span = debug_span!("compositing").entered();
+    let pb = Progress::with_template(rects.len() as u64, "Rendering", &config.progress_template);
+    let replacements = Mutex::new(Vec::new());
+    let failures = Mutex::new(Vec::new());
+    let placed: Vec<_> = rects
+        .par_iter()
+        .map(|rect| {
+            pb.inc(1);
+            let region = imageops::crop_imm(
+                source_img,
+                rect.x * step,
+                rect.y * step,
+                rect.width * step,
+                rect.height * step,
+            );
+            let colors = analyse::<N>(region.to_image());
+            let coords = Tile::from_colors(colors).coords();
+            let closest = kdtree.nearest_one(&coords);
+            let tile = tile_set.get_tile(closest.item).unwrap();
+
+            let (width, height) = (rect.width * tile_size, rect.height * tile_size);
+            let side = width.max(height);
+            // Pictures too small for a large rectangle are scaled up from the usual size
+            let load = |tile: &Tile<_>| {
+                let image = tile_set.get_image(tile, side);
+                image.or_else(|_| tile_set.get_image(tile, tile_size))
+            };
+            let alternatives = || kdtree.nearest_n(&coords, config.retry_neighbor_count);
+            // The rectangles left after an interruption stay black
+            if cancel::interrupted() {
+                return (rect, tile, closest.distance, RgbImage::new(width, height));
+            }
+            let cell = (rect.x, rect.y);
+            let image = match load_or_replace(&tile_set, &tile, load, alternatives) {
+                Ok((mut image, replacement)) => {
+                    if let Some(replacement) = replacement {
+                        replacements.lock().unwrap().push((cell, replacement));
+                    }
+                    if image.dimensions() != (side, side) {
+                        image =
+                            imageops::resize(&image, side, side, imageops::FilterType::Lanczos3);
+                    }
+                    let (left, top) = ((side - width) / 2, (side - height) / 2);
+                    imageops::crop_imm(&image, left, top, width, height).to_image()
+                }
+                Err(error) => {
+                    failures.lock().unwrap().push((cell, error));
+                    let color = *imageops::thumbnail(&*region, 1, 1).get_pixel(0, 0);
+                    RgbImage::from_pixel(width, height, color)
+                }
+            };
+            (rect, tile, closest.distance, image)
+        })
+        .collect();
+    pb.finish_and_clear();
+
+    let mut output = RgbImage::new(htiles * tile_size, vtiles * tile_size);
+    let mut stats = RenderStats::new();
+    for (rect, tile, distance, image) in placed {
+        stats.push_tile(rect.x, rect.y, &tile, distance);
+        let (x, y) = (rect.x * tile_size, rect.y * tile_size);
+        imageops::replace(&mut output, &image, x.into(), y.into());
+    }
+    let failures = failures.into_inner().unwrap();
+    record_failures(&mut stats, replacements.into_inner().unwrap(), failures);
+
+    RenderResult {
+        image: output,
+        stats,
+        tile_set,
+    }
+}
+
+/// The error for a grid of `cells` too large for the `available` tiles to be placed at most
+/// once each.
+fn insufficient_tiles(cells: u32, available: usize) -> EmosaicError {
+    EmosaicError::Render(format!(
+        "❌ Insufficient tiles for no-repeat mode: need {} tiles but only have {} available",
+        cells, available
+    ))
+}
+
 /// Result of a rendering operation containing the output image and metadata.
 ///
 /// This struct encapsulates the complete result of a mosaic rendering operation,
@@ -239,7 +895,47 @@ pub struct RenderResult<const N: usize> {
     /// The tile set used for rendering (may have been modified if no_repeat was used)
     pub tile_set: TileSet<[Rgb<u8>; N]>,
     /// Statistics about the rendering process (tile usage, distances, etc.)
-    pub stats: RenderStats<super::tiles::SIZE>,
+    pub stats: RenderStats<SIZE>,
+}
+
+/// The error ending a render interrupted with Ctrl-C before any tile was placed.
+fn interrupted_matching() -> EmosaicError {
+    EmosaicError::Interrupted(String::from(
+        "⏹️  Interrupted while matching the tiles, before any tile was placed",
+    ))
+}
+
+/// Score the `cells` numbered from 0 with `score`, in batches of `batch` cells, keeping
+/// `neighbors` matches for each until `under_pressure`: the cells of every batch scored while
+/// it is keep half as many as the previous batch, down to [`MIN_SCORED_NEIGHBORS`]. Stops with
+/// [`EmosaicError::Interrupted`] once `interrupted`, skipping the cells left.
+fn score_cells<T: Send>(
+    cells: u32,
+    batch: u32,
+    mut neighbors: usize,
+    under_pressure: impl Fn() -> bool,
+    interrupted: impl Fn() -> bool + Sync,
+    score: impl Fn(u32, usize) -> T + Sync,
+) -> Result<Vec<(u32, T)>, EmosaicError> {
+    let mut scores = Vec::with_capacity(cells as usize);
+    for start in (0..cells).step_by(batch.max(1) as usize) {
+        if under_pressure() && neighbors > MIN_SCORED_NEIGHBORS {
+            neighbors = (neighbors / 2).max(MIN_SCORED_NEIGHBORS);
+            debug!(
+                "Scoring {} matches per cell to stay within the memory budget",
+                neighbors
+            );
+        }
+        let scored: Option<Vec<_>> = (start..(start + batch).min(cells))
+            .into_par_iter()
+            .map(|n| match interrupted() {
+                true => None,
+                false => Some((n, score(n, neighbors))),
+            })
+            .collect();
+        scores.extend(scored.ok_or_else(interrupted_matching)?);
+    }
+    Ok(scores)
 }
 
 /// Renders a mosaic with no tile repetition using an optimized greedy algorithm.
@@ -251,10 +947,11 @@ pub struct RenderResult<const N: usize> {
 /// * `source_img` - The source image to create a mosaic from
 /// * `tile_set` - Set of available tiles with pre-computed color analysis
 /// * `tile_size` - Size of each output tile in pixels
+/// * `index` - Nearest neighbor index used to find matching tiles
 ///
 /// # Returns
 /// * `Ok(RenderResult)` - Contains the rendered image, statistics, and tile set
-/// * `Err(ImageError)` - If rendering fails due to image processing errors
+/// * `Err(EmosaicError)` - If there are too few tiles, or a tile image cannot be prepared
 ///
 /// # Performance
 /// This algorithm is more computationally expensive than `render_nto1` but produces
@@ -263,21 +960,106 @@ pub fn render_nto1_no_repeat<const N: usize>(
     source_img: &RgbImage,
     tile_set: TileSet<[Rgb<u8>; N]>,
     tile_size: u32,
-) -> Result<RenderResult<N>, ImageError>
+    index: IndexKind,
+) -> Result<RenderResult<N>, EmosaicError>
 where
     [(); N * 3]:,
 {
-    let stats = Mutex::new(RenderStats::new());
+    let (htiles, vtiles) = grid_of::<N>(source_img);
+    if (htiles * vtiles) as usize > tile_set.len() * 2 {
+        return Err(insufficient_tiles(htiles * vtiles, tile_set.len() * 2));
+    }
+
+    let index = index.resolve(tile_set.len() * 2, N * 3);
+    debug!("Building {:?} index", index);
+    let kdtree = RwLock::new(tile_set.build_index(index));
+    debug!("Built {:?} index", index);
+
+    let (image, stats) = place_no_repeat(source_img, &tile_set, tile_size, &kdtree)?;
+    Ok(RenderResult {
+        image,
+        stats,
+        tile_set,
+    })
+}
+
+/// Result of rendering the mosaics of several source images with the same tiles.
+pub struct PanelsResult<const N: usize> {
+    /// The mosaic of every source image with the statistics of its placements, in order
+    pub panels: Vec<(RgbImage, RenderStats<SIZE>)>,
+    /// The tile set used for rendering
+    pub tile_set: TileSet<[Rgb<u8>; N]>,
+}
+
+/// Renders the mosaics of several source images with the same tiles, e.g. the panels of a
+/// triptych, placing every tile at most once across all of them.
+///
+/// Every panel is rendered as with [`render_nto1_no_repeat`], but the index is built once for
+/// all of them and the tiles placed in a panel are taken out of it before the next panel is
+/// matched, so the first panels get the closest tiles.
+///
+/// # Returns
+/// * `Ok(PanelsResult)` - Contains the rendered images and statistics, and the tile set
+/// * `Err(EmosaicError)` - If there are too few tiles for the cells of all the panels, or a
+///   tile image cannot be prepared
+pub fn render_nto1_panels<const N: usize>(
+    sources: &[&RgbImage],
+    tile_set: TileSet<[Rgb<u8>; N]>,
+    tile_size: u32,
+    index: IndexKind,
+) -> Result<PanelsResult<N>, EmosaicError>
+where
+    [(); N * 3]:,
+{
+    let cells: u32 = sources
+        .iter()
+        .map(|source| {
+            let (htiles, vtiles) = grid_of::<N>(source);
+            htiles * vtiles
+        })
+        .sum();
+    // A tile and its flipped version are taken out of the index together
+    if cells as usize > tile_set.len() {
+        return Err(insufficient_tiles(cells, tile_set.len()));
+    }
+
+    let index = index.resolve(tile_set.len() * 2, N * 3);
+    debug!("Building {:?} index", index);
+    let kdtree = RwLock::new(tile_set.build_index(index));
+    debug!("Built {:?} index", index);
+
+    let mut panels = Vec::with_capacity(sources.len());
+    for (i, source) in sources.iter().enumerate() {
+        info!("Rendering panel {} of {}", i + 1, sources.len());
+        panels.push(place_no_repeat(source, &tile_set, tile_size, &kdtree)?);
+    }
+    Ok(PanelsResult { panels, tile_set })
+}
+
+/// Columns and rows of the grid of cells of the mosaic of `source_img`, of `N` blocks each.
+fn grid_of<const N: usize>(source_img: &RgbImage) -> (u32, u32) {
+    let step = (N as f64).sqrt() as u32;
+    (source_img.width() / step, source_img.height() / step)
+}
 
-    eprintln!("Building kdtree");
-    let kdtree = RwLock::new(tile_set.build_kiddo());
-    eprintln!("Built kdtree");
+/// Place the tiles of `kdtree`, an index of `tile_set`, on the cells of `source_img`, each at
+/// most once, removing them from the index as they are placed.
+fn place_no_repeat<const N: usize>(
+    source_img: &RgbImage,
+    tile_set: &TileSet<[Rgb<u8>; N]>,
+    tile_size: u32,
+    kdtree: &RwLock<Box<dyn TileIndex<{ N * 3 }>>>,
+) -> Result<(RgbImage, RenderStats<SIZE>), EmosaicError>
+where
+    [(); N * 3]:,
+{
+    let stats = Mutex::new(RenderStats::new());
 
     let step = (N as f64).sqrt() as u32;
 
     let htiles = source_img.width() / step;
     let vtiles = source_img.height() / step;
-    eprintln!(
+    info!(
         "Doing {}x{} tiles resulting in a {}x{} image (step: {step})",
         htiles,
         vtiles,
@@ -285,40 +1067,44 @@ where
         vtiles * tile_size,
     );
 
-    if (htiles * vtiles) as usize > tile_set.len() * 2 {
-        panic!(
-            "❌ Insufficient tiles for no-repeat mode: need {} tiles but only have {} available",
-            (htiles * vtiles) as usize,
-            tile_set.len() * 2
-        );
-    }
-
     let tile_size_stepped = tile_size / step;
 
     let config = RenderConfig::default();
-    let pb = ProgressBar::new((vtiles * htiles) as u64)
-        .with_message("Scoring")
-        .with_style(
-            ProgressStyle::default_bar()
-                .template(&config.progress_template)
-                .unwrap(),
-        );
+    let scoring = debug_span!("scoring").entered();
+    let pb = Progress::with_template(
+        (vtiles * htiles) as u64,
+        "Scoring",
+        &config.progress_template,
+    );
 
+    let blocks = source_blocks::<N>(source_img);
     let compute_nearest = |n: u32, k| {
-        let x = n / vtiles * step;
-        let y = n % vtiles * step;
-        let tile = Tile::from_colors(get_img_colors(x, y, step, source_img));
+        let (col, row) = (n / vtiles, n % vtiles);
+        let tile = Tile::from_colors(blocks[(row * htiles + col) as usize]);
         let coords = tile.coords();
-        let mut nearest = kdtree.read().unwrap().nearest_n::<Manhattan>(&coords, k);
+        let mut nearest = kdtree.read().unwrap().nearest_n(&coords, k);
         nearest.reverse();
         nearest
     };
 
-    let mut matches: Vec<_> = (0..htiles * vtiles)
-        .into_par_iter()
-        .inspect(|_| pb.inc(1))
-        .map(|n| (n, compute_nearest(n, 100000)))
-        .collect();
+    // Within a memory budget the cells are scored in batches. Cells that run out of matches
+    // take more from the index later
+    let cells = htiles * vtiles;
+    let batch = match tile_set.memory_budget() {
+        Some(_) => SCORING_BATCH,
+        None => cells.max(1),
+    };
+    let mut matches = score_cells(
+        cells,
+        batch,
+        config.greedy_neighbor_count,
+        memory::under_pressure,
+        cancel::interrupted,
+        |n, neighbors| {
+            pb.inc(1);
+            compute_nearest(n, neighbors)
+        },
+    )?;
 
     // sort matches by nearest score, reversed as we pop from the end
     matches.sort_unstable_by(|(_, a), (_, b)| {
@@ -333,17 +1119,20 @@ where
     let mut used = HashSet::new();
 
     pb.finish_and_clear();
+    drop(scoring);
 
-    let pb = ProgressBar::new((vtiles * htiles) as u64)
-        .with_message("Rendering")
-        .with_style(
-            ProgressStyle::default_bar()
-                .template(&config.progress_template)
-                .unwrap(),
-        );
+    let _span = debug_span!("compositing").entered();
+    let pb = Progress::with_template(
+        (vtiles * htiles) as u64,
+        "Rendering",
+        &config.progress_template,
+    );
 
     // select tiles by nearest order, removing as we go
     while let Some((n, mut nearest)) = matches.pop() {
+        if cancel::interrupted() {
+            break;
+        }
         let nearest_item = if let Some(item) = nearest.pop() {
             item
         } else {
@@ -354,14 +1143,15 @@ where
             used.insert(-item);
             let tile = tile_set.get_tile(item).unwrap();
             let tile_img = tile_set.get_image(&tile, tile_size)?;
-            let tile_x = (n / vtiles) * tile_size;
-            let tile_y = (n % vtiles) * tile_size;
+            let (col, row) = (n / vtiles, n % vtiles);
+            let tile_x = col * tile_size;
+            let tile_y = row * tile_size;
             // eprintln!("n={n}, tile_x={tile_x}, tile_y={tile_y}");
             imageops::overlay(&mut image, &tile_img, tile_x.into(), tile_y.into());
             stats
                 .lock()
                 .unwrap()
-                .push_tile(tile_x, tile_y, &tile, nearest_item.distance);
+                .push_tile(col, row, &tile, nearest_item.distance);
             let mut tree = kdtree.write().unwrap();
             let mut coords = tile.coords();
             // eprintln!("Removing tile {}", item);
@@ -381,7 +1171,7 @@ where
             pb.inc(1);
         } else {
             if nearest.is_empty() {
-                nearest = compute_nearest(n, 10);
+                nearest = compute_nearest(n, MIN_SCORED_NEIGHBORS);
             }
             // ordered reinsert of nearest in matches
             match matches.binary_search_by(|(_, x)| compare_matches(&nearest, x)) {
@@ -391,13 +1181,73 @@ where
         }
     }
 
-    let stats = stats.into_inner().unwrap();
+    Ok((image, stats.into_inner().unwrap()))
+}
 
-    Ok(RenderResult {
-        image,
-        stats,
-        tile_set,
-    })
+/// Renders the placements of a manifest again at any tile size, without matching any tiles.
+///
+/// # Arguments
+/// * `manifest` - Placements of a previously generated mosaic
+/// * `tile_size` - Size of each output tile in pixels
+/// * `image_cache_budget` - Memory budget in bytes for the decoded tile images
+/// * `memory_budget` - Resident memory the rendering is kept within, in bytes, if any
+/// * `on_row` - Optional callback notified of each rendered row, replacing the progress bars
+///
+/// # Returns
+/// * `Ok(RgbImage)` - The rendered mosaic, black where the grid has no placement
+/// * `Err(ImageError)` - If the image of a placed tile no longer exists or cannot be decoded
+pub fn render_manifest(
+    manifest: &Manifest,
+    tile_size: u32,
+    image_cache_budget: usize,
+    memory_budget: Option<u64>,
+    on_row: Option<RowCallback>,
+) -> Result<RgbImage, ImageError> {
+    let (cols, rows) = manifest.grid_size();
+    let mut tile_set: TileSet<()> = TileSet::new();
+    let mut indices: HashMap<&Path, u32> = HashMap::new();
+    let mut grid = vec![None; cols * rows];
+    for placement in &manifest.placements {
+        let idx = *indices
+            .entry(&placement.path)
+            .or_insert_with(|| match placement.filler {
+                Some(color) => tile_set.push_filler((), Rgb(color)),
+                None => {
+                    tile_set.push_tile(placement.path.clone(), ());
+                    tile_set.len() as u32
+                }
+            });
+        grid[placement.y * cols + placement.x] = Some(Tile {
+            flipped: placement.flipped,
+            ..Tile::new(idx, ())
+        });
+    }
+    let missing = indices.iter().find(|(path, &idx)| {
+        tile_set.filler_color(&Tile::new(idx, ())).is_none() && !split_crop(path).0.exists()
+    });
+    if let Some((path, _)) = missing {
+        return Err(ImageError {
+            path: path.to_path_buf(),
+            error: ::image::ImageError::IoError(io::Error::new(
+                io::ErrorKind::NotFound,
+                "tile image not found",
+            )),
+        });
+    }
+    tile_set.set_memory_budget(memory_budget);
+    tile_set.set_image_cache_budget(image_cache_budget);
+
+    let get_tile = |x: u32, y: u32| match &grid[y as usize * cols + x as usize] {
+        Some(tile) => tile_set.get_image(tile, tile_size),
+        None => Ok(RgbImage::new(tile_size, tile_size)),
+    };
+    let grid_img = RgbImage::new(cols as u32, rows as u32);
+    let in_place = memory_budget.is_some();
+    let (image, failures) = render(&grid_img, tile_size, 1, in_place, get_tile, on_row);
+    match failures.into_iter().next() {
+        Some((_, error)) => Err(error),
+        None => Ok(image),
+    }
 }
 
 /// Renders a mosaic with completely random tile selection.
@@ -406,35 +1256,231 @@ where
 /// without considering color matching or tile optimization.
 ///
 /// # Arguments
-/// * `source_img` - The source image (used only for dimensions)
+/// * `source_img` - The source image, a pixel per tile
 /// * `tile_set` - Set of available tiles (no color analysis needed)
 /// * `tile_size` - Size of each output tile in pixels
+/// * `no_repeat` - If true, places every tile at most once, in a random order
+/// * `on_row` - Optional callback notified of each rendered row, replacing the progress bar
 ///
 /// # Returns
-/// A new `RgbImage` containing the random tile mosaic
+/// * `Ok` - A new `RgbImage` containing the random tile mosaic, and the placement of every
+///   tile with the distance between its average color and the pixel it replaces. A tile whose
+///   image cannot be loaded is replaced by another random tile, or a solid block of the pixel
+///   when none can be, and recorded among the failures of the statistics
+/// * `Err(EmosaicError::Render)` - If there are fewer tiles than pixels for `no_repeat`
 ///
 /// # Performance
 /// This is the fastest rendering method but produces the lowest visual quality.
-pub fn render_random(source_img: &RgbImage, tile_set: TileSet<()>, tile_size: u32) -> RgbImage {
+pub fn render_random(
+    source_img: &RgbImage,
+    tile_set: &TileSet<()>,
+    tile_size: u32,
+    no_repeat: bool,
+    on_row: Option<RowCallback>,
+) -> Result<(RgbImage, RenderStats<SIZE>), EmosaicError> {
+    let (width, height) = source_img.dimensions();
+    let cells = (width * height) as usize;
+    let config = RenderConfig::default();
+    // Tiles left over without repetition replace those that cannot be loaded
+    let mut spare = Vec::new();
+    let tiles: Vec<&Tile<()>> = if no_repeat {
+        if cells > tile_set.len() {
+            return Err(EmosaicError::Render(format!(
+                "❌ Insufficient tiles for no-repeat random mode: need {} tiles but only have {} \
+                 available, downscale the source image or add tiles",
+                cells,
+                tile_set.len()
+            )));
+        }
+        let mut tiles: Vec<_> = tile_set.tiles.iter().collect();
+        tiles.shuffle(&mut rand::thread_rng());
+        spare = tiles.split_off(cells);
+        tiles
+    } else {
+        (0..cells).map(|_| tile_set.random_tile()).collect()
+    };
+
     let mut output = RgbImage::new(
         source_img.width() * tile_size,
         source_img.height() * tile_size,
     );
+    let mut stats = RenderStats::new();
+    let mut spare = spare.into_iter();
+    let (mut replacements, mut failures) = (Vec::new(), Vec::new());
 
-    let pb = ProgressBar::new(source_img.height() as u64 * source_img.width() as u64)
-        .with_message("Rendering");
+    let _span = debug_span!("compositing").entered();
+    let pb = Progress::new(
+        source_img.height() as u64 * source_img.width() as u64,
+        "Rendering",
+    );
+    if on_row.is_some() {
+        pb.hide();
+    }
     for tile_y in 0..source_img.height() {
+        if cancel::interrupted() {
+            break;
+        }
         for tile_x in 0..source_img.width() {
             pb.inc(1);
-            imageops::overlay(
-                &mut output,
-                &tile_set
-                    .get_image(tile_set.random_tile(), tile_size)
-                    .expect("Image not found"),
-                (tile_x * tile_size).into(),
-                (tile_y * tile_size).into(),
+            let tile = tiles[(tile_y * width + tile_x) as usize];
+            let pixel = source_img.get_pixel(tile_x, tile_y);
+            let load = |tile: &Tile<()>| tile_set.get_image(tile, tile_size);
+            let alternatives = || {
+                let others: Vec<&Tile<()>> = match no_repeat {
+                    true => spare.by_ref().take(config.retry_neighbor_count).collect(),
+                    false => (0..config.retry_neighbor_count)
+                        .map(|_| tile_set.random_tile())
+                        .collect(),
+                };
+                let neighbour = |tile: &Tile<()>| NearestNeighbour {
+                    distance: SIZE::from_num(0),
+                    item: tile.idx as i32,
+                };
+                others.into_iter().map(neighbour).collect()
+            };
+            let tile_img = match load_or_replace(tile_set, tile, load, alternatives) {
+                Ok((tile_img, replacement)) => {
+                    let average =
+                        average_color(&tile_img, (0, 0, tile_img.width(), tile_img.height()));
+                    let distance: u32 = (0..3)
+                        .map(|c| u32::from(average[c].abs_diff(pixel[c])))
+                        .sum();
+                    let distance = SIZE::from_num(distance);
+                    match replacement {
+                        Some((tile, _, error)) => {
+                            replacements.push(((tile_x, tile_y), (tile, distance, error)))
+                        }
+                        None => stats.push_tile(tile_x, tile_y, tile, distance),
+                    }
+                    tile_img
+                }
+                Err(error) => {
+                    // The solid block has the color of the pixel it replaces
+                    stats.push_tile(tile_x, tile_y, tile, SIZE::from_num(0));
+                    failures.push(((tile_x, tile_y), error));
+                    RgbImage::from_pixel(tile_size, tile_size, *pixel)
+                }
+            };
+            let (x, y) = (tile_x * tile_size, tile_y * tile_size);
+            imageops::overlay(&mut output, &tile_img, x.into(), y.into());
+        }
+        if let Some(on_row) = on_row {
+            let y = tile_y * tile_size;
+            on_row(
+                &imageops::crop_imm(&output, 0, y, output.width(), tile_size).to_image(),
+                y,
             );
         }
     }
-    output
+    record_failures(&mut stats, replacements, failures);
+    Ok((output, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_score_cells() {
+        // Without pressure every cell keeps every match, whatever the batches
+        let scores = score_cells(
+            10,
+            3,
+            100,
+            || false,
+            || false,
+            |n, neighbors| (n * 2, neighbors),
+        )
+        .unwrap();
+        let cells: Vec<u32> = scores.iter().map(|(n, _)| *n).collect();
+        assert_eq!(cells, (0..10).collect::<Vec<_>>());
+        assert!(scores.iter().all(|(n, score)| *score == (n * 2, 100)));
+
+        // Every batch scored under pressure keeps half as many matches, down to the minimum
+        let batches = AtomicUsize::new(0);
+        let under_pressure = || batches.fetch_add(1, Ordering::SeqCst) >= 1;
+        let scores = score_cells(9, 2, 60, under_pressure, || false, |_, neighbors| neighbors);
+        let neighbors: Vec<usize> = scores
+            .unwrap()
+            .iter()
+            .map(|(_, neighbors)| *neighbors)
+            .collect();
+        assert_eq!(neighbors, [60, 60, 30, 30, 15, 15, 10, 10, 10]);
+        assert_eq!(batches.into_inner(), 5);
+
+        assert!(score_cells(0, 4, 10, || true, || false, |n, _| n)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_score_cells_interrupted() {
+        // Interrupted before any placement, no cell is scored
+        let scored = AtomicUsize::new(0);
+        let result = score_cells(
+            10,
+            3,
+            100,
+            || false,
+            || true,
+            |n, _| {
+                scored.fetch_add(1, Ordering::SeqCst);
+                n
+            },
+        );
+        assert!(matches!(result, Err(EmosaicError::Interrupted(_))));
+        assert_eq!(scored.into_inner(), 0);
+
+        // Interrupted between two batches, the cells of later batches are skipped
+        let batches = AtomicUsize::new(0);
+        let under_pressure = || batches.fetch_add(1, Ordering::SeqCst) > 0;
+        let interrupted = || batches.load(Ordering::SeqCst) > 1;
+        let scored = AtomicUsize::new(0);
+        let result = score_cells(10, 3, 100, under_pressure, interrupted, |n, _| {
+            scored.fetch_add(1, Ordering::SeqCst);
+            n
+        });
+        assert!(matches!(result, Err(EmosaicError::Interrupted(_))));
+        assert_eq!(scored.into_inner(), 3);
+    }
+
+    #[test]
+    fn test_render_nto1_panels() {
+        // Six shades of gray, for two panels of three black cells each
+        let tile_set: TileSet<[Rgb<u8>; 1]> = (0..6u8)
+            .map(|shade| {
+                let image = RgbImage::from_pixel(2, 2, Rgb([shade * 40; 3]));
+                (
+                    PathBuf::from(format!("{}.png", shade)),
+                    image.clone(),
+                    analyse::<1>(image),
+                )
+            })
+            .collect();
+        let source = RgbImage::from_pixel(3, 1, Rgb([0, 0, 0]));
+        let result =
+            render_nto1_panels(&[&source, &source], tile_set.clone(), 2, IndexKind::Brute).unwrap();
+        assert_eq!(result.panels.len(), 2);
+
+        // No tile is placed twice, in the same panel or across them, and the first panel gets
+        // the closest tiles
+        let placed = |stats: &RenderStats<SIZE>| -> HashSet<u32> {
+            stats.tiles().values().map(|tile| tile.idx).collect()
+        };
+        let (first, second) = (placed(&result.panels[0].1), placed(&result.panels[1].1));
+        assert_eq!((first.len(), second.len()), (3, 3));
+        assert!(first.is_disjoint(&second));
+        assert_eq!(first, HashSet::from([1, 2, 3]));
+        assert_eq!(result.panels[1].0.dimensions(), (6, 2));
+
+        // Rendered one by one, the panels get the same tiles
+        let alone = render_nto1_no_repeat(&source, tile_set.clone(), 2, IndexKind::Brute).unwrap();
+        assert_eq!(placed(&alone.stats), first);
+
+        // Every cell of every panel needs a tile of its own, a tile and its flip counting once
+        let error = render_nto1_panels(&[&source; 3], tile_set, 2, IndexKind::Brute).err();
+        assert!(matches!(error, Some(EmosaicError::Render(_))));
+    }
 }
@@ -0,0 +1,90 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use super::stats::StatsSummary;
+
+/// The time spent in a phase of the run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PhaseDuration {
+    /// Name of the phase, e.g. `analysis`, `index`, `scoring` or `compositing`
+    pub name: &'static str,
+    pub seconds: f64,
+}
+
+/// What a run of the mosaic took and gave, for benchmarking pipelines.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuntimeStats {
+    /// Seconds from the start of the run to its end
+    pub total_seconds: f64,
+    /// The phases of the run, in the order they ended
+    pub phases: Vec<PhaseDuration>,
+    /// Peak resident memory in megabytes, where it can be measured
+    pub peak_rss_mb: Option<f64>,
+    /// Number of tiles the mosaic was made from
+    pub tiles: usize,
+    #[serde(flatten)]
+    pub summary: StatsSummary,
+    /// Share of the tile images loaded that were found in the cache of decoded images, if any
+    /// was loaded
+    pub cache_hit_rate: Option<f64>,
+}
+
+impl RuntimeStats {
+    /// The durations of `phases`, as given by [`super::logging::phase_durations`].
+    pub fn phases(phases: &[(&'static str, Duration)]) -> Vec<PhaseDuration> {
+        phases
+            .iter()
+            .map(|&(name, duration)| PhaseDuration {
+                name,
+                seconds: duration.as_secs_f64(),
+            })
+            .collect()
+    }
+
+    /// Write the statistics as JSON to the file at `path`.
+    pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self).map_err(io::Error::from)?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runtime_stats() {
+        let phases = [
+            ("analysis", Duration::from_millis(1500)),
+            ("compositing", Duration::from_millis(250)),
+        ];
+        let stats = RuntimeStats {
+            total_seconds: 2.0,
+            phases: RuntimeStats::phases(&phases),
+            peak_rss_mb: Some(128.5),
+            tiles: 40,
+            summary: StatsSummary {
+                cells: 100,
+                unique_tiles: 30,
+                fillers: 2,
+                average_distance: 12.5,
+            },
+            cache_hit_rate: None,
+        };
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["phases"][0]["name"], "analysis");
+        assert_eq!(json["phases"][0]["seconds"], 1.5);
+        assert_eq!(json["phases"][1]["name"], "compositing");
+        assert_eq!(json["peak_rss_mb"], 128.5);
+        // The summary is inlined
+        assert_eq!(json["cells"], 100);
+        assert_eq!(json["unique_tiles"], 30);
+        assert_eq!(json["average_distance"], 12.5);
+        assert!(json["cache_hit_rate"].is_null());
+    }
+}
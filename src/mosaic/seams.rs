@@ -0,0 +1,275 @@
+use std::collections::HashSet;
+
+use ::image::{Rgb, RgbImage};
+use rayon::prelude::*;
+use tracing::info;
+
+use super::color::delta_e;
+use super::web::palette::Palette;
+
+/// Mean delta-E across a junction from which it is reported as harsh: an edge that the eye
+/// picks out as a grid line.
+pub const HARSH_SEAM: f64 = 20.0;
+
+/// Mean delta-E across a junction rendered in the last color of the palette.
+pub const WORST_SEAM: f64 = 50.0;
+
+/// Share of harsh junctions from which smoothing them is suggested.
+const HARSH_SHARE_HINTED: f64 = 0.25;
+
+/// Number of the harshest junctions listed in the report.
+const HARSHEST_LISTED: usize = 5;
+
+/// Color of the tiles between the junctions in the rendered seam map.
+const TILE_COLOR: Rgb<u8> = Rgb([32, 32, 32]);
+
+/// A junction between two neighbouring tiles of the grid.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Seam {
+    /// Column and row of the tile on the left of or above the junction
+    pub cell: (u32, u32),
+    /// Whether the junction runs down between a tile and the one on its right, rather than
+    /// across between a tile and the one below it
+    pub vertical: bool,
+    /// Mean CIE76 delta-E between the pixels on either side of the junction
+    pub contrast: f64,
+}
+
+/// How abrupt every junction between the tiles of a rendered mosaic is, to judge whether the
+/// grid shows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeamMap {
+    /// Number of columns and rows of tiles
+    pub grid: (u32, u32),
+    /// Contrast across the junction on the right of every tile but those of the last column,
+    /// in row major order
+    pub vertical: Vec<f64>,
+    /// Contrast across the junction below every tile but those of the last row, in row major
+    /// order
+    pub horizontal: Vec<f64>,
+    /// Columns and rows of the tiles that the mask or the clear regions hide, whose junctions
+    /// are left out
+    pub hidden: HashSet<(u32, u32)>,
+}
+
+impl SeamMap {
+    /// Measure the junctions between the `grid` of tiles of `mosaic`.
+    pub fn new(mosaic: &RgbImage, grid: (u32, u32)) -> SeamMap {
+        let (cols, rows) = grid;
+        let tile_size = (mosaic.width() / cols.max(1), mosaic.height() / rows.max(1));
+        // Mean delta-E between the pixels at `a` and `b` of the `n` pixel pairs
+        let contrast = |pairs: &mut dyn Iterator<Item = ((u32, u32), (u32, u32))>| {
+            let (sum, n) = pairs.fold((0.0, 0), |(sum, n), (a, b)| {
+                let delta = delta_e(*mosaic.get_pixel(a.0, a.1), *mosaic.get_pixel(b.0, b.1));
+                (sum + delta, n + 1)
+            });
+            sum / f64::from(n.max(1))
+        };
+        let vertical = (0..cols.saturating_sub(1) * rows)
+            .into_par_iter()
+            .map(|seam| {
+                let (col, row) = (seam % (cols - 1), seam / (cols - 1));
+                let x = (col + 1) * tile_size.0;
+                let ys = row * tile_size.1..(row + 1) * tile_size.1;
+                contrast(&mut ys.map(|y| ((x - 1, y), (x, y))))
+            })
+            .collect();
+        let horizontal = (0..cols * rows.saturating_sub(1))
+            .into_par_iter()
+            .map(|seam| {
+                let (col, row) = (seam % cols, seam / cols);
+                let y = (row + 1) * tile_size.1;
+                let xs = col * tile_size.0..(col + 1) * tile_size.0;
+                contrast(&mut xs.map(|x| ((x, y - 1), (x, y))))
+            })
+            .collect();
+        SeamMap {
+            grid,
+            vertical,
+            horizontal,
+            hidden: HashSet::new(),
+        }
+    }
+
+    /// Leave out the junctions of `cells`, which the mask or the clear regions hide.
+    pub fn hide_cells(&mut self, cells: &HashSet<(u32, u32)>) {
+        self.hidden.extend(cells);
+    }
+
+    /// Whether the junction on the right of or below `cell` has a hidden tile on either side.
+    fn hides(&self, (col, row): (u32, u32), vertical: bool) -> bool {
+        let other = if vertical {
+            (col + 1, row)
+        } else {
+            (col, row + 1)
+        };
+        self.hidden.contains(&(col, row)) || self.hidden.contains(&other)
+    }
+
+    /// Every junction between tiles that are not hidden, those running down first.
+    pub fn seams(&self) -> impl Iterator<Item = Seam> + '_ {
+        let (cols, _) = self.grid;
+        let vertical = self.vertical.iter().enumerate().map(move |(i, &contrast)| {
+            let i = i as u32;
+            Seam {
+                cell: (i % (cols - 1), i / (cols - 1)),
+                vertical: true,
+                contrast,
+            }
+        });
+        let horizontal = self
+            .horizontal
+            .iter()
+            .enumerate()
+            .map(move |(i, &contrast)| {
+                let i = i as u32;
+                Seam {
+                    cell: (i % cols, i / cols),
+                    vertical: false,
+                    contrast,
+                }
+            });
+        (vertical.chain(horizontal)).filter(move |seam| !self.hides(seam.cell, seam.vertical))
+    }
+
+    /// Average contrast over the junctions.
+    pub fn mean(&self) -> f64 {
+        let count = self.seams().count();
+        self.seams().map(|seam| seam.contrast).sum::<f64>() / count.max(1) as f64
+    }
+
+    /// Share of the junctions with a contrast above `contrast`.
+    pub fn share_above(&self, contrast: f64) -> f64 {
+        let count = self.seams().count();
+        let above = self.seams().filter(|seam| seam.contrast > contrast).count();
+        above as f64 / count.max(1) as f64
+    }
+
+    /// The `n` junctions of the highest contrast, the highest first.
+    pub fn harshest(&self, n: usize) -> Vec<Seam> {
+        let mut seams: Vec<Seam> = self.seams().collect();
+        seams.sort_by(|a, b| b.contrast.total_cmp(&a.contrast));
+        seams.truncate(n);
+        seams
+    }
+
+    /// Print how abrupt the junctions are overall, which are the harshest, and how to smooth
+    /// them when many are harsh.
+    pub fn summarise(&self) {
+        let share = self.share_above(HARSH_SEAM);
+        info!(
+            "🧱 Delta-E across the junctions of the {}x{} tiles: {:.1} on average, {:.1}% above {}",
+            self.grid.0,
+            self.grid.1,
+            self.mean(),
+            100.0 * share,
+            HARSH_SEAM
+        );
+        let harshest: Vec<String> = self
+            .harshest(HARSHEST_LISTED)
+            .iter()
+            .filter(|seam| seam.contrast > HARSH_SEAM)
+            .map(|seam| {
+                let (col, row) = seam.cell;
+                let other = if seam.vertical {
+                    (col + 1, row)
+                } else {
+                    (col, row + 1)
+                };
+                format!(
+                    "({}, {})|({}, {}) {:.1}",
+                    col, row, other.0, other.1, seam.contrast
+                )
+            })
+            .collect();
+        if !harshest.is_empty() {
+            info!(
+                "   Harshest junctions (column, row): {}",
+                harshest.join(", ")
+            );
+        }
+        if share > HARSH_SHARE_HINTED {
+            info!(
+                "💡 The grid shows: try --continuity-weight to match tiles with their neighbours, \
+                 or --tint-opacity to blend the source over the junctions"
+            );
+        }
+    }
+
+    /// Render every tile as a square of `cell_size` pixels, with its junctions drawn along its
+    /// sides, colored with `palette` by their contrast on a fixed scale, but those of the hidden
+    /// tiles.
+    pub fn render(&self, cell_size: u32, palette: Palette) -> RgbImage {
+        let (cols, rows) = self.grid;
+        let width = (cell_size / 8).max(1);
+        let color = |contrast: f64| Rgb(palette.color(contrast / WORST_SEAM));
+        RgbImage::from_fn(cols * cell_size, rows * cell_size, |x, y| {
+            let (col, row) = (x / cell_size, y / cell_size);
+            let (dx, dy) = (x % cell_size, y % cell_size);
+            let vertical = |col: u32| {
+                let hidden = self.hides((col, row), true);
+                (!hidden).then(|| self.vertical[(row * (cols - 1) + col) as usize])
+            };
+            let horizontal = |row: u32| {
+                let hidden = self.hides((col, row), false);
+                (!hidden).then(|| self.horizontal[(row * cols + col) as usize])
+            };
+            let contrast = [
+                (dx < width && col > 0).then(|| vertical(col - 1)),
+                (dx >= cell_size - width && col + 1 < cols).then(|| vertical(col)),
+                (dy < width && row > 0).then(|| horizontal(row - 1)),
+                (dy >= cell_size - width && row + 1 < rows).then(|| horizontal(row)),
+            ]
+            .iter()
+            .flatten()
+            .flatten()
+            .copied()
+            .reduce(f64::max);
+            contrast.map_or(TILE_COLOR, color)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seam_map() {
+        let (black, white) = (Rgb([0, 0, 0]), Rgb([255, 255, 255]));
+        // 3x2 tiles of 2x2 pixels: the middle column black, the others white
+        let mosaic = RgbImage::from_fn(6, 4, |x, _| if x / 2 == 1 { black } else { white });
+
+        let map = SeamMap::new(&mosaic, (3, 2));
+        assert_eq!(map.vertical.len(), 4);
+        assert_eq!(map.horizontal.len(), 3);
+        assert!(map.vertical.iter().all(|&c| (c - 100.0).abs() < 0.01));
+        assert!(map.horizontal.iter().all(|&c| c.abs() < 0.01));
+        assert_eq!(map.share_above(HARSH_SEAM), 4.0 / 7.0);
+        assert!((map.mean() - 400.0 / 7.0).abs() < 0.01);
+
+        let harshest = map.harshest(1);
+        assert_eq!(harshest.len(), 1);
+        assert!(harshest[0].vertical);
+        let below = map.seams().find(|seam| !seam.vertical).unwrap();
+        assert_eq!(below.cell, (0, 0));
+
+        let rendered = map.render(8, Palette::Classic);
+        assert_eq!(rendered.dimensions(), (24, 16));
+        assert_eq!(*rendered.get_pixel(4, 4), TILE_COLOR);
+        assert_eq!(rendered.get_pixel(7, 4).0, Palette::Classic.color(1.0));
+        assert_eq!(rendered.get_pixel(4, 7).0, Palette::Classic.color(0.0));
+        // The outer sides of the grid have no junction
+        assert_eq!(*rendered.get_pixel(0, 0), TILE_COLOR);
+
+        // Hiding the middle tile of the first row leaves out its three junctions
+        let mut hidden = map.clone();
+        hidden.hide_cells(&HashSet::from([(1, 0)]));
+        assert_eq!(hidden.seams().count(), 4);
+        assert_eq!(hidden.share_above(HARSH_SEAM), 0.5);
+        assert_eq!(
+            *hidden.render(8, Palette::Classic).get_pixel(7, 4),
+            TILE_COLOR
+        );
+    }
+}
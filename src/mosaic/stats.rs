@@ -1,23 +1,119 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 
 use image::{ImageBuffer, Rgb, RgbImage};
-
-use super::tiles::{Tile, TileSet};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use super::error::EmosaicError;
+use super::heatmap::{self, Legend};
+use super::seams::SeamMap;
+use super::tiles::{tile_sources, Tile, TileSet, TileSource};
+use super::time_gradient::TimeGradient;
+use super::timestamp::DateFormat;
+use super::usage::WORST_MATCHES_LISTED;
+use super::web::links::TileLinks;
+use super::web::map::{self, PhotoLocation};
+use super::web::palette::Palette;
 
 /// Configuration settings used to generate the mosaic
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MosaicConfig {
     pub tile_size: u32,
     pub mode: String,
     pub no_repeat: bool,
     pub greedy: bool,
+    /// Whether regions were filled with pictures from the same event
+    pub story: bool,
+    /// Direction in which the pictures get newer, if any
+    pub time_gradient: Option<TimeGradient>,
+    /// Distance beyond which cells were given synthetic filler tiles, if any
+    pub filler: Option<u32>,
     pub crop: bool,
     pub tint_opacity: f32,
     pub downsample: u32,
     pub randomize: Option<f64>,
+    /// Largest variance of the colors around a cell for it to be randomized, if any
+    #[serde(default)]
+    pub randomize_max_variance: Option<f64>,
     pub tiles_dir: String,
     pub title: String,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    /// Longest side of the tile thumbnails exported for web hosting, if any
+    pub thumbnail_size: Option<u32>,
+    /// JPEG quality of the exported thumbnails, the default one when not given
+    #[serde(default)]
+    pub export_quality: Option<u8>,
+    /// Whether the EXIF data of the originals was left out of the exported thumbnails
+    #[serde(default)]
+    pub export_strip_exif: bool,
+    /// How dates are shown in the tooltips of the HTML pages
+    #[serde(default)]
+    pub date_format: DateFormat,
+    /// Color scheme of the distance overlay and of the distances in the HTML pages
+    #[serde(default)]
+    pub palette: Palette,
+    /// Pages opened by clicking the tiles of the HTML pages instead of their images
+    #[serde(default, skip_serializing_if = "TileLinks::is_empty")]
+    pub tile_links: TileLinks,
+}
+
+/// How distances are placed on the color scales of the distance overlay and heatmap.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DistanceScale {
+    /// Relative to the distances of this mosaic, so its worst match is always bad
+    #[default]
+    Relative,
+    /// Absolute, from a perfect match to [`DistanceScale::WORST_PER_PIXEL`] per pixel of cells
+    /// of `pixels` source pixels, so mosaics of any mode can be compared
+    Absolute { pixels: u32 },
+}
+
+impl DistanceScale {
+    /// Color distance per pixel, summed over the RGB channels, at the bad end of the absolute
+    /// scale
+    pub const WORST_PER_PIXEL: f64 = 150.0;
+
+    /// Distance of a cell at the bad end of the scale, if absolute.
+    pub fn ceiling(self) -> Option<f64> {
+        match self {
+            DistanceScale::Relative => None,
+            DistanceScale::Absolute { pixels } => {
+                Some(DistanceScale::WORST_PER_PIXEL * f64::from(pixels))
+            }
+        }
+    }
+}
+
+/// Number of bad matches listed in the reports
+pub(crate) const BAD_MATCHES_LISTED: usize = 20;
+
+/// A cell whose matched tile could not be loaded while rendering, e.g. because the picture
+/// was deleted after the analysis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileFailure {
+    /// Column and row of the cell in the mosaic grid
+    pub position: (u32, u32),
+    /// Why the image of the matched tile could not be loaded
+    pub error: String,
+    /// Whether the next nearest tile was placed instead, rather than a solid block of the
+    /// cell's color
+    pub replaced: bool,
+}
+
+/// Headline numbers of a rendered mosaic, to compare settings.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct StatsSummary {
+    /// Number of cells given a tile
+    pub cells: usize,
+    /// Number of distinct images placed, fillers aside
+    pub unique_tiles: usize,
+    /// Number of cells given a synthetic filler
+    pub fillers: usize,
+    /// Average distance from the cells to their tiles, summed over the RGB channels and the
+    /// blocks of the mode
+    pub average_distance: f64,
 }
 
 /// Statistics collector for mosaic rendering operations.
@@ -26,8 +122,21 @@ pub struct MosaicConfig {
 /// to provide analytics about the mosaic generation process.
 #[derive(Clone)]
 pub struct RenderStats<D> {
-    /// Maps tile positions (x, y) to tiles with distance information
+    /// Maps the columns and rows of the cells in the mosaic grid to their tiles with distance
+    /// information
     tiles: HashMap<(u32, u32), Tile<D>>,
+    /// Distance beyond which placements are reported as bad matches, if any
+    max_distance: Option<D>,
+    /// How distances are colored in the distance overlay and heatmap
+    distance_scale: DistanceScale,
+    /// Cells whose matched tile could not be loaded
+    failures: Vec<TileFailure>,
+    /// Contrast across the junctions between the tiles, for the seam overlay, if measured
+    seams: Option<SeamMap>,
+    /// Where the pictures placed were taken, for the map of the widget, if located
+    locations: Option<Vec<PhotoLocation>>,
+    /// Cells that the mask or the clear regions hide, left out of the placements
+    hidden: HashSet<(u32, u32)>,
 }
 
 impl<D> RenderStats<D>
@@ -43,24 +152,98 @@ where
     pub fn new() -> Self {
         Self {
             tiles: HashMap::new(),
+            max_distance: None,
+            distance_scale: DistanceScale::Relative,
+            failures: Vec::new(),
+            seams: None,
+            locations: None,
+            hidden: HashSet::new(),
         }
     }
 
-    /// Record a tile placement with its position and color distance.
+    /// Report the placements farther than `max_distance` from their cell as bad matches.
+    pub fn set_max_distance(&mut self, max_distance: D) {
+        self.max_distance = Some(max_distance);
+    }
+
+    /// Distance beyond which placements are reported as bad matches, if any
+    pub(crate) fn max_distance(&self) -> Option<D> {
+        self.max_distance
+    }
+
+    /// Color the distance overlay and heatmap on `scale` instead of relative to this mosaic.
+    pub fn set_distance_scale(&mut self, scale: DistanceScale) {
+        self.distance_scale = scale;
+    }
+
+    /// How distances are colored in the distance overlay and heatmap
+    pub(crate) fn distance_scale(&self) -> DistanceScale {
+        self.distance_scale
+    }
+
+    /// The column and row in the mosaic grid of every placement farther than the maximum
+    /// distance from its cell, with its tile, worst first. Empty without a maximum distance.
+    pub fn bad_matches(&self) -> Vec<((u32, u32), &Tile<D>)> {
+        let max_distance = match self.max_distance {
+            Some(max_distance) => max_distance,
+            None => return vec![],
+        };
+        let mut bad_matches: Vec<_> = self
+            .grid_cells()
+            .into_iter()
+            .map(|cell| (cell, &self.tiles[&cell]))
+            .filter(|(_, tile)| tile.colors > max_distance)
+            .collect();
+        bad_matches.sort_by_key(|&(cell, tile)| (std::cmp::Reverse(tile.colors), cell));
+        bad_matches
+    }
+
+    /// Record a tile placement with its cell and color distance.
     ///
     /// # Arguments
-    /// * `x` - X coordinate where tile was placed
-    /// * `y` - Y coordinate where tile was placed
+    /// * `col` - Column of the cell in the mosaic grid where the tile was placed
+    /// * `row` - Row of the cell in the mosaic grid where the tile was placed
     /// * `tile` - The tile that was placed
     /// * `distance` - Color distance/quality metric for this tile placement
-    pub fn push_tile<T>(&mut self, x: u32, y: u32, tile: &Tile<T>, distance: D) {
+    pub fn push_tile<T>(&mut self, col: u32, row: u32, tile: &Tile<T>, distance: D) {
         let stats_tile = Tile {
             colors: distance, // Note: repurposing colors field to store distance
             idx: tile.idx,
             flipped: tile.flipped,
             date_taken: tile.date_taken.clone(),
+            caption: tile.caption.clone(),
+            camera: tile.camera.clone(),
+            sharpness: tile.sharpness,
         };
-        self.tiles.insert((x, y), stats_tile);
+        self.tiles.insert((col, row), stats_tile);
+    }
+
+    /// Record a cell whose matched tile could not be loaded.
+    pub fn push_failure(&mut self, failure: TileFailure) {
+        self.failures.push(failure);
+    }
+
+    /// Cells whose matched tile could not be loaded, in the order they were recorded.
+    pub fn failures(&self) -> &[TileFailure] {
+        &self.failures
+    }
+
+    /// Leave out the placements and load failures of `cells`, which the mask or the clear
+    /// regions hide in the finished mosaic.
+    pub fn hide_cells(&mut self, cells: &HashSet<(u32, u32)>) {
+        self.tiles.retain(|cell, _| !cells.contains(cell));
+        self.failures
+            .retain(|failure| !cells.contains(&failure.position));
+        self.hidden.extend(cells);
+    }
+
+    /// Number of columns and rows of the mosaic grid, hidden cells included.
+    pub(crate) fn grid(&self) -> (u32, u32) {
+        let cells = self.tiles.keys().chain(&self.hidden);
+        let (max_col, max_row) = cells.fold((0, 0), |(max_col, max_row), &(col, row)| {
+            (max_col.max(col), max_row.max(row))
+        });
+        (max_col + 1, max_row + 1)
     }
 
     /// Get the number of tiles recorded in these statistics.
@@ -74,6 +257,69 @@ where
         &self.tiles
     }
 
+    /// Measure the junctions between the tiles of `mosaic`, the image rendered from these
+    /// placements, for the seam overlay of the HTML pages.
+    pub fn measure_seams(&mut self, mosaic: &RgbImage) {
+        let mut seams = SeamMap::new(mosaic, self.grid());
+        seams.hide_cells(&self.hidden);
+        self.seams = Some(seams);
+    }
+
+    /// Contrast across the junctions between the tiles, if measured
+    pub(crate) fn seams(&self) -> Option<&SeamMap> {
+        self.seams.as_ref()
+    }
+
+    /// Read where the pictures placed were taken from their EXIF GPS positions, for the map of
+    /// the widget, and return how many of them were located out of the distinct pictures placed.
+    pub fn locate_photos<T>(&mut self, tile_set: &TileSet<T>) -> (usize, usize) {
+        let mut paths: Vec<&Path> = self
+            .tiles
+            .values()
+            .filter(|tile| tile_set.filler_color(tile).is_none())
+            .map(|tile| tile_set.get_path(tile))
+            .collect();
+        paths.sort();
+        paths.dedup();
+        let locations = map::locate_photos(&paths);
+        let located = (locations.len(), paths.len());
+        self.locations = Some(locations);
+        located
+    }
+
+    /// Where the pictures placed were taken, if located
+    pub(crate) fn locations(&self) -> Option<&[PhotoLocation]> {
+        self.locations.as_deref()
+    }
+
+    /// Columns and rows of the cells of the recorded tiles, in row major order.
+    pub(crate) fn grid_cells(&self) -> Vec<(u32, u32)> {
+        let mut cells: Vec<_> = self.tiles.keys().copied().collect();
+        cells.sort_unstable_by_key(|&(col, row)| (row, col));
+        cells
+    }
+
+    /// The number of cells, distinct images and fillers placed, and the average distance.
+    pub fn summary<T>(&self, tile_set: &TileSet<T>) -> StatsSummary {
+        let mut total_distance: D = 0_u8.into();
+        let mut unique_tiles = HashSet::new();
+        let mut fillers = 0;
+        for tile in self.tiles.values() {
+            total_distance += tile.colors;
+            if tile_set.filler_color(tile).is_some() {
+                fillers += 1;
+            } else {
+                unique_tiles.insert(tile_set.get_path(tile));
+            }
+        }
+        StatsSummary {
+            cells: self.tiles.len(),
+            unique_tiles: unique_tiles.len(),
+            fillers,
+            average_distance: f64::from(total_distance) / self.tiles.len().max(1) as f64,
+        }
+    }
+
     /// Print a summary of mosaic generation statistics.
     ///
     /// Displays:
@@ -86,112 +332,213 @@ where
     /// * `tile_set` - The tile set used for generating the mosaic
     pub fn summarise<T>(&self, tile_set: &TileSet<T>) {
         if self.tiles.is_empty() {
-            eprintln!("No tiles recorded in statistics");
+            info!("No tiles recorded in statistics");
             return;
         }
 
-        // Calculate total distance and count tile usage
-        let mut total_distance: D = 0_u8.into();
-        let mut tile_usage_count: HashMap<&Path, u16> = HashMap::with_capacity(self.tiles.len());
+        let summary = self.summary(tile_set);
+        // Print basic statistics
+        info!("Mosaic Statistics:");
+        info!("  Total tiles placed: {}", summary.cells);
+        info!("  Unique images used: {}", summary.unique_tiles);
+        if summary.fillers > 0 {
+            info!("  Synthetic fillers placed: {}", summary.fillers);
+        }
+        info!("  Average color distance: {:.3}", summary.average_distance);
 
+        // Count tile usage
+        let mut tile_usage_count: HashMap<&Path, u16> = HashMap::with_capacity(self.tiles.len());
         for tile in self.tiles.values() {
-            total_distance += tile.colors;
-            let path = tile_set.get_path(tile);
-            *tile_usage_count.entry(path).or_insert(0) += 1;
+            if tile_set.filler_color(tile).is_none() {
+                *tile_usage_count.entry(tile_set.get_path(tile)).or_insert(0) += 1;
+            }
         }
 
-        let unique_tiles = tile_usage_count.len();
-        let total_distance_f64: f64 = total_distance.into();
-        let tile_count = self.tiles.len() as f64;
-
-        // Print basic statistics
-        eprintln!("Mosaic Statistics:");
-        eprintln!("  Total tiles placed: {}", self.tiles.len());
-        eprintln!("  Unique images used: {}", unique_tiles);
-        eprintln!(
-            "  Average color distance: {:.3}",
-            total_distance_f64 / tile_count
-        );
-
         // Show most frequently used tiles
         let mut usage_by_count: Vec<_> = tile_usage_count.into_iter().collect();
         usage_by_count.sort_by(|(_, a), (_, b)| b.cmp(a));
 
-        eprintln!("\nTop 10 most used tiles:");
+        info!("Top 10 most used tiles:");
         for (i, (path, count)) in usage_by_count.iter().take(10).enumerate() {
-            eprintln!("  {}. {} ({} times)", i + 1, path.display(), count);
+            info!("  {}. {} ({} times)", i + 1, path.display(), count);
         }
 
         // Show worst color matches
         let mut worst_matches: Vec<_> = self.tiles.values().collect();
-        worst_matches.sort_by(|a, b| b.colors.cmp(&a.colors));
+        worst_matches.sort_by_key(|t| std::cmp::Reverse(t.colors));
 
-        eprintln!("\nWorst 10 color matches:");
-        for (i, tile) in worst_matches.iter().take(10).enumerate() {
+        info!("Worst {} color matches:", WORST_MATCHES_LISTED);
+        for (i, tile) in worst_matches.iter().take(WORST_MATCHES_LISTED).enumerate() {
             let path = tile_set.get_path(tile);
-            eprintln!(
+            info!(
                 "  {}. {} (distance: {})",
                 i + 1,
                 path.display(),
                 tile.colors
             );
         }
+
+        if let Some(max_distance) = self.max_distance {
+            let bad_matches = self.bad_matches();
+            if bad_matches.is_empty() {
+                info!("No match farther than {}", max_distance);
+            } else {
+                info!(
+                    "⚠️  {} matches farther than {}:",
+                    bad_matches.len(),
+                    max_distance
+                );
+                for ((col, row), tile) in bad_matches.iter().take(BAD_MATCHES_LISTED) {
+                    info!(
+                        "  ({}, {}) {} (distance: {})",
+                        col,
+                        row,
+                        tile_set.get_path(tile).display(),
+                        tile.colors
+                    );
+                }
+                if bad_matches.len() > BAD_MATCHES_LISTED {
+                    info!("  ... and {} more", bad_matches.len() - BAD_MATCHES_LISTED);
+                }
+            }
+        }
+
+        if !self.failures.is_empty() {
+            info!("⚠️  {} tiles could not be loaded:", self.failures.len());
+            for failure in self.failures.iter().take(BAD_MATCHES_LISTED) {
+                let (col, row) = failure.position;
+                let outcome = if failure.replaced {
+                    "replaced by the next nearest tile"
+                } else {
+                    "replaced by a solid block"
+                };
+                info!("  ({}, {}) {}: {}", col, row, outcome, failure.error);
+            }
+            if self.failures.len() > BAD_MATCHES_LISTED {
+                info!(
+                    "  ... and {} more",
+                    self.failures.len() - BAD_MATCHES_LISTED
+                );
+            }
+        }
+    }
+
+    /// Number of tiles placed from each of `sources`, in the order of `sources`.
+    pub fn source_usage<T>(&self, tile_set: &TileSet<T>, sources: &[TileSource]) -> Vec<usize> {
+        let tile_sources = tile_sources(tile_set, sources);
+        let mut usage = vec![0; sources.len()];
+        for tile in self.tiles.values() {
+            if let Some(source) = tile_sources[tile.idx as usize - 1] {
+                usage[source] += 1;
+            }
+        }
+        usage
     }
-    /// Render a grayscale visualization of tile color distances.
+
+    /// Print how many tiles were placed from each of `sources`.
+    pub fn summarise_sources<T>(&self, tile_set: &TileSet<T>, sources: &[TileSource]) {
+        let usage = self.source_usage(tile_set, sources);
+        info!("Tiles placed per source:");
+        for (source, count) in sources.iter().zip(usage) {
+            info!(
+                "  {} (weight {}): {} ({:.1}%)",
+                source.path.display(),
+                source.weight,
+                count,
+                100.0 * count as f64 / self.tiles.len().max(1) as f64
+            );
+        }
+    }
+
+    /// Render a visualization of tile color distances.
     ///
-    /// Creates an image where each pixel's brightness represents how well
-    /// the tile at that position matched the target color. Darker pixels
-    /// indicate better matches (lower distance).
+    /// Creates an image where each pixel's color represents how well
+    /// the tile at that position matched the target color. With the classic
+    /// palette the image is grayscale, and darker pixels indicate better
+    /// matches (lower distance); other palettes color it like the distance overlay.
     ///
     /// # Arguments
-    /// * `tile_size` - Size of each tile in pixels for coordinate conversion
+    /// * `palette` - Color scheme of the distances
     ///
     /// # Returns
-    /// A grayscale image showing the quality of tile matches
-    ///
-    /// # Panics
-    /// Panics if no tiles have been recorded in the statistics
-    pub fn render(self, tile_size: u32) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    /// * `Ok` - An image showing the quality of tile matches
+    /// * `Err(EmosaicError::Render)` - If no tiles have been recorded in the statistics
+    pub fn render(self, palette: Palette) -> Result<ImageBuffer<Rgb<u8>, Vec<u8>>, EmosaicError> {
         if self.tiles.is_empty() {
-            panic!("Cannot render visualization: no tiles recorded");
-        }
-
-        if tile_size == 0 {
-            panic!("Tile size must be greater than 0");
+            return Err(EmosaicError::Render(String::from(
+                "❌ Cannot render the statistics image: no tiles recorded",
+            )));
         }
 
         // Find the bounds of the mosaic
-        let max_x = self.tiles.keys().map(|(x, _)| *x).max().unwrap_or(0);
-        let max_y = self.tiles.keys().map(|(_, y)| *y).max().unwrap_or(0);
-
-        // Find the maximum distance for normalization
-        let distances: Vec<f64> = self.tiles.values().map(|t| t.colors.into()).collect();
-        let max_distance = distances
-            .iter()
-            .max_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
-            .copied()
-            .unwrap_or(1.0); // Avoid division by zero
-
-        // Create the visualization image
-        let image_width = (max_x / tile_size) + 1;
-        let image_height = (max_y / tile_size) + 1;
-        let mut image = RgbImage::new(image_width, image_height);
+        let (cols, rows) = self.grid();
+
+        let max_distance = self.heatmap_ceiling();
+
+        // Create the visualization image, a pixel per cell
+        let mut image = RgbImage::new(cols, rows);
 
         // Fill the image with distance visualizations
-        for ((x, y), tile) in &self.tiles {
+        for ((col, row), tile) in &self.tiles {
             let distance: f64 = tile.colors.into();
             let normalized_distance = if max_distance > 0.0 {
-                distance / max_distance
+                (distance / max_distance).min(1.0)
             } else {
                 0.0
             };
-
-            let brightness = (normalized_distance * 255.0) as u8;
-            let color = Rgb([brightness, brightness, brightness]);
-            image.put_pixel(*x / tile_size, *y / tile_size, color);
+            let color = heatmap_color(palette, normalized_distance);
+            image.put_pixel(*col, *row, color);
         }
 
-        image
+        Ok(image)
+    }
+
+    /// Render the visualization of tile color distances of [`RenderStats::render`] with every
+    /// cell a square of `scale` pixels, the columns and rows of the grid numbered, and a legend
+    /// of the colors.
+    ///
+    /// # Returns
+    /// * `Ok` - The decorated image
+    /// * `Err(EmosaicError::Render)` - If no tiles have been recorded in the statistics
+    pub fn render_heatmap(self, palette: Palette, scale: u32) -> Result<RgbImage, EmosaicError> {
+        let high = match self.distance_scale {
+            DistanceScale::Relative => format!("{:.0}", self.heatmap_ceiling()),
+            DistanceScale::Absolute { .. } => {
+                format!("{:.0}+ PER PIXEL", DistanceScale::WORST_PER_PIXEL)
+            }
+        };
+        let color = |t: f64| heatmap_color(palette, t);
+        let legend = Legend {
+            title: "DISTANCE",
+            low: String::from("0"),
+            high,
+            color: &color,
+        };
+        Ok(heatmap::decorate(&self.render(palette)?, scale, &legend))
+    }
+
+    /// Distance at the bad end of the heatmap: the ceiling of an absolute scale, or the worst
+    /// match of this mosaic.
+    fn heatmap_ceiling(&self) -> f64 {
+        self.distance_scale.ceiling().unwrap_or_else(|| {
+            self.tiles
+                .values()
+                .map(|t| f64::from(t.colors))
+                .max_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+                .unwrap_or(1.0) // Avoid division by zero
+        })
+    }
+}
+
+/// Color of a distance at `t` in the heatmap, from 0 for a perfect match to 1 for the bad end
+/// of the scale: gray levels with the classic palette, or the colors of the distance overlay.
+fn heatmap_color(palette: Palette, t: f64) -> Rgb<u8> {
+    match palette {
+        Palette::Classic => {
+            let brightness = (t * 255.0) as u8;
+            Rgb([brightness, brightness, brightness])
+        }
+        palette => Rgb(palette.color(t)),
     }
 }
 
@@ -240,6 +587,69 @@ mod tests {
         assert_eq!(stats.tile_count(), 2);
     }
 
+    #[test]
+    fn test_source_usage() {
+        let mut tile_set: TileSet<()> = TileSet::new();
+        tile_set.push_tile(PathBuf::from("family/a.jpg"), ());
+        tile_set.push_tile(PathBuf::from("landscapes/b.jpg"), ());
+        let mut stats: RenderStats<u32> = RenderStats::new();
+        for (x, tile) in [(0, 0), (1, 0), (2, 1)] {
+            stats.push_tile(x, 0, &tile_set.tiles[tile], 0);
+        }
+        let sources = ["family:2", "landscapes", "other"].map(|s| s.parse().unwrap());
+        assert_eq!(stats.source_usage(&tile_set, &sources), [2, 1, 0]);
+    }
+
+    #[test]
+    fn test_grid_cells() {
+        let mut stats: RenderStats<u32> = RenderStats::new();
+        let tile = Tile::from_colors([Rgb([255, 0, 0])]);
+        for (col, row) in [(2, 0), (0, 1), (0, 0), (2, 1)] {
+            stats.push_tile(col, row, &tile, 0);
+        }
+
+        // Cells left empty, e.g. by an interruption, keep the others in place
+        assert_eq!(stats.grid_cells(), [(0, 0), (2, 0), (0, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn test_hide_cells() {
+        let mut stats: RenderStats<u32> = RenderStats::new();
+        let tile = Tile::from_colors([Rgb([255, 0, 0])]);
+        for col in 0..3 {
+            stats.push_tile(col, 0, &tile, 0);
+        }
+        stats.push_failure(TileFailure {
+            position: (2, 0),
+            error: String::from("deleted"),
+            replaced: false,
+        });
+
+        stats.hide_cells(&HashSet::from([(2, 0)]));
+        assert_eq!(stats.grid_cells(), [(0, 0), (1, 0)]);
+        assert!(stats.failures().is_empty());
+        // The hidden cells still count in the size of the grid
+        assert_eq!(stats.grid(), (3, 1));
+    }
+
+    #[test]
+    fn test_bad_matches() {
+        let mut stats: RenderStats<u32> = RenderStats::new();
+        let tile = Tile::from_colors([Rgb([255, 0, 0])]);
+        for (x, distance) in [(0, 10), (1, 300), (2, 50), (3, 400)] {
+            stats.push_tile(x, 0, &tile, distance);
+        }
+        assert!(stats.bad_matches().is_empty());
+
+        stats.set_max_distance(50);
+        let bad_matches: Vec<_> = stats
+            .bad_matches()
+            .into_iter()
+            .map(|(cell, tile)| (cell, tile.colors))
+            .collect();
+        assert_eq!(bad_matches, [((3, 0), 400), ((1, 0), 300)]);
+    }
+
     #[test]
     fn test_summarise_empty() {
         let stats: RenderStats<u32> = RenderStats::new();
@@ -267,26 +677,26 @@ mod tests {
         stats.push_tile(20, 20, &tile1, 15); // Use tile1 again
 
         assert_eq!(stats.tile_count(), 3);
+        assert_eq!(
+            stats.summary(&tile_set),
+            StatsSummary {
+                cells: 3,
+                unique_tiles: 2,
+                fillers: 0,
+                average_distance: 15.0
+            }
+        );
 
         // Should not panic and should display statistics
         stats.summarise(&tile_set);
     }
 
     #[test]
-    #[should_panic(expected = "Cannot render visualization: no tiles recorded")]
-    fn test_render_empty_panic() {
+    fn test_render_empty() {
         let stats: RenderStats<u32> = RenderStats::new();
-        stats.render(16);
-    }
-
-    #[test]
-    #[should_panic(expected = "Tile size must be greater than 0")]
-    fn test_render_zero_tile_size_panic() {
-        let mut stats: RenderStats<u32> = RenderStats::new();
-        let tile = Tile::from_colors([Rgb([255, 0, 0])]);
-        stats.push_tile(0, 0, &tile, 100);
-
-        stats.render(0);
+        let error = stats.clone().render(Palette::Classic).unwrap_err();
+        assert!(matches!(error, EmosaicError::Render(_)));
+        assert!(stats.render_heatmap(Palette::Classic, 8).is_err());
     }
 
     #[test]
@@ -295,9 +705,9 @@ mod tests {
         let tile = Tile::from_colors([Rgb([255, 0, 0])]);
 
         stats.push_tile(0, 0, &tile, 50);
-        stats.push_tile(16, 16, &tile, 150);
+        stats.push_tile(1, 1, &tile, 150);
 
-        let rendered = stats.render(16);
+        let rendered = stats.clone().render(Palette::Classic).unwrap();
         assert_eq!(rendered.width(), 2);
         assert_eq!(rendered.height(), 2);
 
@@ -308,6 +718,27 @@ mod tests {
         // Pixel 1 should be darker (lower distance = 50)
         // Pixel 2 should be lighter (higher distance = 150)
         assert!(pixel1[0] < pixel2[0]);
+
+        // Other palettes color the distances like the overlay
+        let rendered = stats.render(Palette::Viridis).unwrap();
+        assert_eq!(rendered.get_pixel(1, 1).0, Palette::Viridis.color(1.0));
+    }
+
+    #[test]
+    fn test_render_absolute_scale() {
+        let mut stats: RenderStats<u32> = RenderStats::new();
+        let tile = Tile::from_colors([Rgb([255, 0, 0])]);
+        stats.push_tile(0, 0, &tile, 150);
+        stats.push_tile(1, 0, &tile, 1200);
+        let scale = DistanceScale::Absolute { pixels: 4 };
+        assert_eq!(scale.ceiling(), Some(600.0));
+        assert_eq!(DistanceScale::Relative.ceiling(), None);
+        stats.set_distance_scale(scale);
+
+        // Distances are no longer stretched to the worst match of the mosaic
+        let rendered = stats.render(Palette::Classic).unwrap();
+        assert_eq!(rendered.get_pixel(0, 0).0, [63, 63, 63]);
+        assert_eq!(rendered.get_pixel(1, 0).0, [255, 255, 255]);
     }
 
     #[test]
@@ -318,7 +749,10 @@ mod tests {
         let colors = [Rgb([255, 0, 0])];
         tile_set.push_tile(PathBuf::from("test.jpg"), colors);
 
-        let tile = tile_set.tiles[0].clone();
+        let tile = tile_set.tiles[0].clone().with_details(
+            Some("Mum's <birthday>".to_string()),
+            Some("iPhone 12".to_string()),
+        );
         stats.push_tile(0, 0, &tile, 100);
 
         let config = MosaicConfig {
@@ -326,15 +760,30 @@ mod tests {
             mode: "test".to_string(),
             no_repeat: false,
             greedy: false,
+            story: false,
+            time_gradient: None,
+            filler: None,
             crop: false,
             tint_opacity: 0.0,
             downsample: 1,
             randomize: None,
+            randomize_max_variance: None,
             tiles_dir: "test_tiles".to_string(),
+            title: "Test Mosaic".to_string(),
+            description: None,
+            author: None,
+            thumbnail_size: None,
+            export_quality: None,
+            export_strip_exif: false,
+            date_format: Default::default(),
+            palette: Default::default(),
+            tile_links: Default::default(),
         };
 
         let mosaic_path = PathBuf::from("test_mosaic.jpg");
-        let output_path = PathBuf::from("/tmp/test_widget.html");
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let output_path = dir.join("test_widget.html");
 
         // Should not panic and should create valid HTML
         let result = {
@@ -352,5 +801,66 @@ mod tests {
             )
         };
         assert!(result.is_ok(), "Widget generation should succeed");
+        let html = std::fs::read_to_string(&output_path).unwrap();
+        assert!(!html.contains("seam-overlay"));
+        assert!(!html.contains("photo-map"));
+        assert!(html.contains(r#"id="tile-search-input""#));
+        assert!(html.contains(r#"id="tile-locate""#));
+        assert!(html.contains(r#"data-tile-path="test.jpg""#));
+        // The caption and camera are escaped in the tooltip and for the mobile modal
+        assert!(html.contains(r#"<span class="tile-caption">Mum&#39;s &lt;birthday&gt;</span>"#));
+        assert!(html.contains(r#"<span class="tile-camera">📷 iPhone 12</span>"#));
+        assert!(html.contains(r#"data-details-info="&lt;br/&gt;&lt;span class=&quot;tile-caption"#));
+        // The assets are written next to the page from the binary
+        let js = std::fs::read_to_string(dir.join("mosaic-widget.js")).unwrap();
+        assert!(js.contains("toggleSeamOverlay"));
+        assert!(dir.join("mosaic-widget.css").is_file());
+
+        // A black tile next to a white one draws a harsh junction between them
+        stats.push_tile(1, 0, &tile, 100);
+        let mosaic = RgbImage::from_fn(32, 16, |x, _| {
+            if x < 16 {
+                Rgb([0, 0, 0])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        });
+        stats.measure_seams(&mosaic);
+        assert_eq!(stats.seams().unwrap().grid, (2, 1));
+        stats
+            .generate_mosaic_widget_with_options(
+                &mosaic_path,
+                &output_path,
+                &tile_set,
+                &config,
+                false,
+            )
+            .unwrap();
+        let html = std::fs::read_to_string(&output_path).unwrap();
+        assert!(html.contains(r#"<div id="seam-overlay" class="distance-overlay">"#));
+        assert!(html.contains("seam-overlay-vertical overlay-distance-bad"));
+
+        // Pictures taken over several months get a timeline, month by month
+        assert!(!html.contains("timeline-slider"));
+        let mut later = tile.clone();
+        later.date_taken = Some(String::from("2024:03:05 10:00:00"));
+        stats.push_tile(1, 0, &later, 100);
+        let mut earlier = tile.clone();
+        earlier.date_taken = Some(String::from("2024:01:05"));
+        stats.push_tile(0, 0, &earlier, 100);
+        stats
+            .generate_mosaic_widget_with_options(
+                &mosaic_path,
+                &output_path,
+                &tile_set,
+                &config,
+                false,
+            )
+            .unwrap();
+        let html = std::fs::read_to_string(&output_path).unwrap();
+        assert!(html.contains(r#"data-month="648""#));
+        assert!(html.contains(r#"data-month="650""#));
+        assert!(html.contains(r#"min="0" max="3" value="3""#));
+        assert!(html.contains("var timelineFirstMonth = 648;"));
     }
 }
@@ -0,0 +1,186 @@
+use std::collections::VecDeque;
+
+use ::image::Rgb;
+
+use super::tiles::TileSet;
+
+/// Settings of the "story" layout, which fills small regions of similar cells with pictures
+/// from the same event.
+#[derive(Debug, Clone)]
+pub struct StoryOptions {
+    /// Pictures taken more than this many days apart belong to different events
+    pub event_gap_days: i64,
+    /// Fewest tiles in an event, shorter runs of dates are merged with the next ones
+    pub min_event_tiles: usize,
+    /// Most cells grown into a single region
+    pub region_cells: usize,
+    /// Largest color distance (summed over the RGB channels) from a region's first cell to
+    /// the other cells grown into it
+    pub region_threshold: u32,
+}
+
+impl Default for StoryOptions {
+    fn default() -> Self {
+        Self {
+            event_gap_days: 1,
+            min_event_tiles: 16,
+            region_cells: 16,
+            region_threshold: 120,
+        }
+    }
+}
+
+/// Group the positions of the tiles into events of pictures taken on nearby days, in
+/// chronological order. Undated tiles are gathered into a last event of their own.
+pub fn events<T>(tile_set: &TileSet<T>, options: &StoryOptions) -> Vec<Vec<usize>> {
+    let mut dated: Vec<(i64, usize)> = tile_set
+        .tiles
+        .iter()
+        .enumerate()
+        .filter_map(|(i, tile)| Some((tile.day_taken()?, i)))
+        .collect();
+    dated.sort_unstable();
+
+    let mut events: Vec<Vec<usize>> = Vec::new();
+    let mut last_day = None;
+    for (day, i) in dated {
+        let gap = last_day.is_none_or(|last| day - last > options.event_gap_days);
+        match events.last_mut() {
+            Some(event) if !gap || event.len() < options.min_event_tiles => event.push(i),
+            _ => events.push(vec![i]),
+        }
+        last_day = Some(day);
+    }
+    if events.len() > 1 && events[events.len() - 1].len() < options.min_event_tiles {
+        let last = events.pop().unwrap();
+        events.last_mut().unwrap().extend(last);
+    }
+
+    let undated: Vec<usize> = tile_set
+        .tiles
+        .iter()
+        .enumerate()
+        .filter(|(_, tile)| tile.day_taken().is_none())
+        .map(|(i, _)| i)
+        .collect();
+    if !undated.is_empty() {
+        events.push(undated);
+    }
+    events
+}
+
+/// Grow regions of adjacent cells with similar colors over a grid `width` cells wide, given
+/// in row major order. Regions are seeded at the first unassigned cell in scan order and
+/// grown breadth first.
+///
+/// Returns the region of every cell and the number of regions.
+pub fn grow_regions(
+    colors: &[Rgb<u8>],
+    width: usize,
+    options: &StoryOptions,
+) -> (Vec<usize>, usize) {
+    let distance = |a: Rgb<u8>, b: Rgb<u8>| {
+        a.0.iter()
+            .zip(b.0.iter())
+            .map(|(&a, &b)| (a as i32 - b as i32).unsigned_abs())
+            .sum::<u32>()
+    };
+
+    let mut regions = vec![usize::MAX; colors.len()];
+    let mut count = 0;
+    for seed in 0..colors.len() {
+        if regions[seed] != usize::MAX {
+            continue;
+        }
+        regions[seed] = count;
+        let mut size = 1;
+        let mut queue = VecDeque::from([seed]);
+        while let Some(cell) = queue.pop_front() {
+            let (x, y) = (cell % width, cell / width);
+            let neighbours = [
+                (x > 0).then(|| cell - 1),
+                (x + 1 < width).then(|| cell + 1),
+                (y > 0).then(|| cell - width),
+                (cell + width < colors.len()).then(|| cell + width),
+            ];
+            for neighbour in neighbours.iter().flatten().copied() {
+                if size < options.region_cells
+                    && regions[neighbour] == usize::MAX
+                    && distance(colors[seed], colors[neighbour]) <= options.region_threshold
+                {
+                    regions[neighbour] = count;
+                    size += 1;
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        count += 1;
+    }
+    (regions, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_events() {
+        let mut tile_set: TileSet<()> = TileSet::new();
+        let dates = [
+            Some("2024:06:02"),
+            None,
+            Some("2020:01:01"),
+            Some("2024:06:01"),
+            Some("2020:01:02"),
+            Some("2021:03:10"),
+        ];
+        for (i, date) in dates.iter().enumerate() {
+            tile_set.push_tile(PathBuf::from(format!("{}.jpg", i)), ());
+            tile_set.tiles[i].date_taken = date.map(str::to_string);
+        }
+
+        let options = StoryOptions {
+            min_event_tiles: 1,
+            ..StoryOptions::default()
+        };
+        assert_eq!(
+            events(&tile_set, &options),
+            [vec![2, 4], vec![5], vec![3, 0], vec![1]]
+        );
+
+        let options = StoryOptions {
+            min_event_tiles: 2,
+            ..StoryOptions::default()
+        };
+        assert_eq!(
+            events(&tile_set, &options),
+            [vec![2, 4], vec![5, 3, 0], vec![1]]
+        );
+    }
+
+    #[test]
+    fn test_grow_regions() {
+        let red = Rgb([255, 0, 0]);
+        let blue = Rgb([0, 0, 255]);
+        #[rustfmt::skip]
+        let colors = [
+            red, red, blue,
+            red, blue, blue,
+        ];
+        let options = StoryOptions::default();
+        assert_eq!(
+            grow_regions(&colors, 3, &options),
+            (vec![0, 0, 1, 0, 1, 1], 2)
+        );
+
+        let options = StoryOptions {
+            region_cells: 2,
+            ..StoryOptions::default()
+        };
+        assert_eq!(
+            grow_regions(&colors, 3, &options),
+            (vec![0, 0, 1, 2, 3, 1], 4)
+        );
+    }
+}
@@ -0,0 +1,493 @@
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use ::image::RgbImage;
+use base64::Engine;
+
+/// Graphics protocol used to draw the preview in the terminal.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TerminalProtocol {
+    /// Kitty graphics when the terminal supports them, ANSI otherwise
+    #[default]
+    Auto,
+    /// Colored half block characters, supported by most terminals
+    Ansi,
+    /// Sixel graphics, e.g. in xterm, foot or mlterm
+    Sixel,
+    /// Kitty graphics protocol, e.g. in kitty, WezTerm or Ghostty
+    Kitty,
+}
+
+impl TerminalProtocol {
+    /// Pick a concrete protocol for the terminal we are running in.
+    pub fn resolve(self) -> TerminalProtocol {
+        match self {
+            TerminalProtocol::Auto => {
+                let term = std::env::var("TERM").unwrap_or_default();
+                let program = std::env::var("TERM_PROGRAM").unwrap_or_default();
+                if std::env::var_os("KITTY_WINDOW_ID").is_some()
+                    || term.contains("kitty")
+                    || term.contains("ghostty")
+                    || program == "WezTerm"
+                {
+                    TerminalProtocol::Kitty
+                } else {
+                    TerminalProtocol::Ansi
+                }
+            }
+            protocol => protocol,
+        }
+    }
+
+    /// Preview pixels drawn per terminal cell, assuming cells twice as tall as wide.
+    fn cell_pixels(self) -> (u32, u32) {
+        match self {
+            TerminalProtocol::Sixel => (10, 20),
+            TerminalProtocol::Kitty => (4, 8),
+            _ => (1, 2),
+        }
+    }
+
+    /// Preview rows drawn at once: a line of half blocks, a sixel or a line of kitty images.
+    fn band_height(self) -> u32 {
+        match self {
+            TerminalProtocol::Sixel => 6,
+            TerminalProtocol::Kitty => 8,
+            _ => 2,
+        }
+    }
+}
+
+/// Largest size with the aspect ratio of `width` x `height` that fits in `max_width` x
+/// `max_height`, never upscaling.
+fn fit((width, height): (u32, u32), (max_width, max_height): (u32, u32)) -> (u32, u32) {
+    let scale = (f64::from(max_width) / f64::from(width))
+        .min(f64::from(max_height) / f64::from(height))
+        .min(1.0);
+    let scaled = |size: u32| ((f64::from(size) * scale).round() as u32).max(1);
+    (scaled(width), scaled(height))
+}
+
+/// Index of the cell of a `cells` grid covering position `pos` of `size`, so that every
+/// cell covers at least one position when `cells <= size`.
+fn cell_of(pos: u32, size: u32, cells: u32) -> u32 {
+    ((u64::from(pos + 1) * u64::from(cells) - 1) / u64::from(size)) as u32
+}
+
+struct State {
+    /// Sum of the output pixels covered by each preview pixel
+    sums: Vec<[u64; 3]>,
+    /// Number of output rows accumulated into each preview row
+    rows_filled: Vec<u32>,
+    bands_drawn: u32,
+    finished: bool,
+    output: Box<dyn Write + Send>,
+}
+
+/// A low resolution preview of the mosaic drawn in the terminal as its rows are rendered.
+///
+/// Rows may be rendered in any order, bands of the preview are drawn top to bottom as soon
+/// as all the rows they cover are complete.
+pub struct TerminalPreview {
+    protocol: TerminalProtocol,
+    /// Height of the output image
+    height: u32,
+    /// Size of the preview in pixels
+    preview_width: u32,
+    preview_height: u32,
+    /// Preview column covering each output column, and the number of columns it covers
+    column_of: Vec<u32>,
+    column_span: Vec<u32>,
+    row_span: Vec<u32>,
+    state: Mutex<State>,
+}
+
+impl TerminalPreview {
+    /// Create a preview of a `width` x `height` output image fitting the terminal, drawn to stderr.
+    pub fn new(protocol: TerminalProtocol, dimensions: (u32, u32)) -> Self {
+        let (rows, columns) = console::Term::stderr().size();
+        // Leave room for the messages printed after rendering
+        let cells = (u32::from(columns), u32::from(rows).saturating_sub(4).max(1));
+        Self::with_output(protocol, dimensions, cells, Box::new(io::stderr()))
+    }
+
+    fn with_output(
+        protocol: TerminalProtocol,
+        (width, height): (u32, u32),
+        (columns, rows): (u32, u32),
+        output: Box<dyn Write + Send>,
+    ) -> Self {
+        let protocol = protocol.resolve();
+        let (cell_width, cell_height) = protocol.cell_pixels();
+        let (width, height) = (width.max(1), height.max(1));
+        let (preview_width, preview_height) =
+            fit((width, height), (columns * cell_width, rows * cell_height));
+
+        let column_of: Vec<u32> = (0..width)
+            .map(|x| cell_of(x, width, preview_width))
+            .collect();
+        let mut column_span = vec![0; preview_width as usize];
+        for &column in &column_of {
+            column_span[column as usize] += 1;
+        }
+        let mut row_span = vec![0; preview_height as usize];
+        for y in 0..height {
+            row_span[cell_of(y, height, preview_height) as usize] += 1;
+        }
+
+        TerminalPreview {
+            protocol,
+            height,
+            preview_width,
+            preview_height,
+            column_of,
+            column_span,
+            row_span,
+            state: Mutex::new(State {
+                sums: vec![[0; 3]; (preview_width * preview_height) as usize],
+                rows_filled: vec![0; preview_height as usize],
+                bands_drawn: 0,
+                finished: false,
+                output,
+            }),
+        }
+    }
+
+    /// Add a rendered row of tiles at offset `y` of the output image to the preview.
+    pub fn row_rendered(&self, row: &RgbImage, y: u32) {
+        let end = (y + row.height()).min(self.height);
+        if y >= end {
+            return;
+        }
+        let first = cell_of(y, self.height, self.preview_height);
+        let last = cell_of(end - 1, self.height, self.preview_height);
+        let preview_width = self.preview_width as usize;
+
+        // Downscale outside of the lock, rows are rendered in parallel
+        let mut sums = vec![[0u64; 3]; (last - first + 1) as usize * preview_width];
+        let mut filled = vec![0u32; (last - first + 1) as usize];
+        for (output_y, pixels) in (y..end).zip(row.rows()) {
+            let preview_y = (cell_of(output_y, self.height, self.preview_height) - first) as usize;
+            filled[preview_y] += 1;
+            let sums = &mut sums[preview_y * preview_width..][..preview_width];
+            for (&column, pixel) in self.column_of.iter().zip(pixels) {
+                let sum = &mut sums[column as usize];
+                for (sum, channel) in sum.iter_mut().zip(pixel.0) {
+                    *sum += u64::from(channel);
+                }
+            }
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let offset = first as usize * preview_width;
+        for (total, sum) in state.sums[offset..].iter_mut().zip(&sums) {
+            for (total, sum) in total.iter_mut().zip(sum) {
+                *total += sum;
+            }
+        }
+        for (total, count) in state.rows_filled[first as usize..].iter_mut().zip(&filled) {
+            *total += count;
+        }
+        self.draw_bands(&mut state, false);
+    }
+
+    /// Draw the rest of the preview once `image` is complete. Renderers that don't place
+    /// tiles row by row only show the preview at this point.
+    pub fn finish(&self, image: &RgbImage) {
+        let state = self.state.lock().unwrap();
+        let started = state.rows_filled.iter().any(|&rows| rows > 0);
+        drop(state);
+        if !started {
+            self.row_rendered(image, 0);
+        }
+        let mut state = self.state.lock().unwrap();
+        self.draw_bands(&mut state, true);
+        self.close(&mut state);
+    }
+
+    /// Draw the bands whose rows are all complete, or every remaining band when `flush`.
+    fn draw_bands(&self, state: &mut State, flush: bool) {
+        let band_height = self.protocol.band_height();
+        let bands = self.preview_height.div_ceil(band_height);
+        while state.bands_drawn < bands {
+            let rows = state.bands_drawn * band_height
+                ..((state.bands_drawn + 1) * band_height).min(self.preview_height);
+            let complete = rows
+                .clone()
+                .all(|r| state.rows_filled[r as usize] >= self.row_span[r as usize]);
+            if !complete && !flush {
+                break;
+            }
+
+            let band: Vec<Vec<[u8; 3]>> = rows.map(|r| self.preview_row(state, r)).collect();
+            let mut text = String::new();
+            if state.bands_drawn == 0 && self.protocol == TerminalProtocol::Sixel {
+                text.push_str(&sixel_header(self.preview_width, self.preview_height));
+            }
+            text.push_str(&match self.protocol {
+                TerminalProtocol::Sixel => encode_sixel(&band),
+                TerminalProtocol::Kitty => {
+                    let (cell_width, _) = self.protocol.cell_pixels();
+                    encode_kitty(&band, self.preview_width.div_ceil(cell_width))
+                }
+                _ => encode_ansi(&band),
+            });
+            // The preview is only a visual aid, a closed terminal must not fail the render
+            let _ = state.output.write_all(text.as_bytes());
+            let _ = state.output.flush();
+            state.bands_drawn += 1;
+        }
+    }
+
+    fn preview_row(&self, state: &State, r: u32) -> Vec<[u8; 3]> {
+        let rows = u64::from(state.rows_filled[r as usize].max(1));
+        let sums = &state.sums[(r * self.preview_width) as usize..][..self.preview_width as usize];
+        sums.iter()
+            .zip(&self.column_span)
+            .map(|(sum, &columns)| sum.map(|sum| (sum / (rows * u64::from(columns))) as u8))
+            .collect()
+    }
+
+    /// Terminate a sixel image that was started, so the terminal leaves graphics mode.
+    fn close(&self, state: &mut State) {
+        if !state.finished && state.bands_drawn > 0 && self.protocol == TerminalProtocol::Sixel {
+            let _ = state.output.write_all(b"\x1b\\\n");
+            let _ = state.output.flush();
+        }
+        state.finished = true;
+    }
+}
+
+impl Drop for TerminalPreview {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.state.lock() {
+            self.close(&mut state);
+        }
+    }
+}
+
+/// One line of half blocks: the foreground colors the top row and the background the bottom one.
+fn encode_ansi(band: &[Vec<[u8; 3]>]) -> String {
+    let mut text = String::new();
+    for (x, [r, g, b]) in band[0].iter().enumerate() {
+        match band.get(1) {
+            Some(bottom) => {
+                let [br, bg, bb] = bottom[x];
+                text.push_str(&format!("\x1b[48;2;{};{};{}m", br, bg, bb));
+            }
+            None => text.push_str("\x1b[49m"),
+        }
+        text.push_str(&format!("\x1b[38;2;{};{};{}m▀", r, g, b));
+    }
+    text.push_str("\x1b[0m\n");
+    text
+}
+
+/// Levels of each channel of the sixel palette, a 6x6x6 color cube.
+const SIXEL_LEVELS: u16 = 6;
+
+fn sixel_color([r, g, b]: [u8; 3]) -> u16 {
+    let level = |c: u8| (u16::from(c) * (SIXEL_LEVELS - 1) + 127) / 255;
+    (level(r) * SIXEL_LEVELS + level(g)) * SIXEL_LEVELS + level(b)
+}
+
+/// Start of a sixel image: raster attributes and the palette.
+fn sixel_header(width: u32, height: u32) -> String {
+    let mut text = format!("\x1bPq\"1;1;{};{}", width, height);
+    let percent = |level: u16| level * 100 / (SIXEL_LEVELS - 1);
+    for color in 0..SIXEL_LEVELS.pow(3) {
+        let (r, g, b) = (
+            color / SIXEL_LEVELS / SIXEL_LEVELS,
+            color / SIXEL_LEVELS % SIXEL_LEVELS,
+            color % SIXEL_LEVELS,
+        );
+        text.push_str(&format!(
+            "#{};2;{};{};{}",
+            color,
+            percent(r),
+            percent(g),
+            percent(b)
+        ));
+    }
+    text
+}
+
+/// One sixel, up to 6 rows of pixels, drawn one color at a time with run-length encoding.
+fn encode_sixel(band: &[Vec<[u8; 3]>]) -> String {
+    let colors: Vec<Vec<u16>> = band
+        .iter()
+        .map(|row| row.iter().copied().map(sixel_color).collect())
+        .collect();
+    let mut palette: Vec<u16> = colors.iter().flatten().copied().collect();
+    palette.sort_unstable();
+    palette.dedup();
+
+    let mut text = String::new();
+    for color in palette {
+        text.push_str(&format!("#{}", color));
+        let sixels: Vec<char> = (0..band[0].len())
+            .map(|x| {
+                let bits = (0..colors.len())
+                    .filter(|&dy| colors[dy][x] == color)
+                    .fold(0u8, |bits, dy| bits | 1 << dy);
+                char::from(63 + bits)
+            })
+            .collect();
+        for run in sixels.chunk_by(|a, b| a == b) {
+            if run.len() > 3 {
+                text.push_str(&format!("!{}{}", run.len(), run[0]));
+            } else {
+                text.extend(run);
+            }
+        }
+        text.push('$');
+    }
+    text.push('-');
+    text
+}
+
+/// Kitty escape codes carry at most this many bytes of base64 payload each.
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+/// One line of the terminal: the band as a kitty image scaled to `columns` cells on one row.
+fn encode_kitty(band: &[Vec<[u8; 3]>], columns: u32) -> String {
+    let pixels: Vec<u8> = band.iter().flatten().flatten().copied().collect();
+    let payload = base64::engine::general_purpose::STANDARD.encode(pixels);
+    let chunks: Vec<&[u8]> = payload.as_bytes().chunks(KITTY_CHUNK_SIZE).collect();
+
+    let mut text = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(i + 1 < chunks.len());
+        if i == 0 {
+            text.push_str(&format!(
+                "\x1b_Ga=T,f=24,s={},v={},c={},r=1,q=2,m={};",
+                band[0].len(),
+                band.len(),
+                columns,
+                more
+            ));
+        } else {
+            text.push_str(&format!("\x1b_Gm={};", more));
+        }
+        text.push_str(std::str::from_utf8(chunk).unwrap());
+        text.push_str("\x1b\\");
+    }
+    text.push('\n');
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::Rgb;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        fn take(&self) -> String {
+            String::from_utf8(std::mem::take(&mut *self.0.lock().unwrap())).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_fit() {
+        assert_eq!(fit((1600, 800), (80, 40)), (80, 40));
+        assert_eq!(fit((1600, 1600), (80, 44)), (44, 44));
+        assert_eq!(fit((10, 1000), (80, 44)), (1, 44));
+        assert_eq!(fit((20, 10), (80, 44)), (20, 10));
+    }
+
+    #[test]
+    fn test_cell_of() {
+        let cells: Vec<_> = (0..10).map(|pos| cell_of(pos, 10, 3)).collect();
+        assert_eq!(cells, [0, 0, 0, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(cell_of(5, 8, 8), 5);
+    }
+
+    #[test]
+    fn test_encode_ansi() {
+        let band = vec![vec![[255, 0, 0]], vec![[0, 0, 255]]];
+        assert_eq!(
+            encode_ansi(&band),
+            "\x1b[48;2;0;0;255m\x1b[38;2;255;0;0m▀\x1b[0m\n"
+        );
+        assert_eq!(
+            encode_ansi(&band[..1]),
+            "\x1b[49m\x1b[38;2;255;0;0m▀\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_sixel() {
+        // Two rows of 5 pixels: red on top, blue at the bottom
+        let band = vec![vec![[255, 0, 0]; 5], vec![[0, 0, 255]; 5]];
+        assert_eq!(encode_sixel(&band), "#5!5A$#180!5@$-");
+    }
+
+    #[test]
+    fn test_encode_kitty() {
+        let band = vec![vec![[255, 0, 0], [0, 255, 0]]];
+        assert_eq!(
+            encode_kitty(&band, 1),
+            "\x1b_Ga=T,f=24,s=2,v=1,c=1,r=1,q=2,m=0;/wAAAP8A\x1b\\\n"
+        );
+    }
+
+    #[test]
+    fn test_preview_draws_bands_in_order() {
+        let output = Output::default();
+        // A 2x8 output previewed at full size, 4 bands of 2 rows
+        let preview = TerminalPreview::with_output(
+            TerminalProtocol::Ansi,
+            (2, 8),
+            (80, 40),
+            Box::new(output.clone()),
+        );
+        let row = |color: u8| RgbImage::from_pixel(2, 4, Rgb([color, color, color]));
+
+        preview.row_rendered(&row(100), 4);
+        assert_eq!(output.take(), "", "the top rows are not rendered yet");
+        preview.row_rendered(&row(200), 0);
+        let text = output.take();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].contains("\x1b[38;2;200;200;200m"));
+        assert!(lines[3].contains("\x1b[38;2;100;100;100m"));
+
+        preview.finish(&row(0));
+        assert_eq!(output.take(), "", "every band was already drawn");
+    }
+
+    #[test]
+    fn test_preview_downscales() {
+        let output = Output::default();
+        let preview = TerminalPreview::with_output(
+            TerminalProtocol::Ansi,
+            (4, 4),
+            (2, 1),
+            Box::new(output.clone()),
+        );
+        let mut image = RgbImage::new(4, 4);
+        for (x, _, pixel) in image.enumerate_pixels_mut() {
+            *pixel = Rgb([if x < 2 { 10 } else { 30 }, 0, 0]);
+        }
+
+        preview.finish(&image);
+        assert_eq!(
+            output.take(),
+            "\x1b[48;2;10;0;0m\x1b[38;2;10;0;0m▀\x1b[48;2;30;0;0m\x1b[38;2;30;0;0m▀\x1b[0m\n"
+        );
+    }
+}
@@ -1,14 +1,48 @@
 use typenum::U0;
 
 // Re-export the main types and functions from the focused modules
+pub use atomic::{write_atomic, CacheLock};
+pub use cache::{cache_dir, list_cache_dir, AnalysisCache};
+pub use checkpoint::AnalysisCheckpoint;
+pub use crops::{
+    augment, is_kept_crop, split_crop, with_crop, CropRegion, CROP_REGIONS, MAX_AUGMENT_CROPS,
+    MIN_CROP_SIZE,
+};
+pub use filter::{Rejection, Resolution, TileFilter};
+pub use image_cache::DEFAULT_IMAGE_CACHE_BYTES;
+pub use json::{AnalysedTile, TileAnalysis};
+pub use list::{ListedTile, TileList};
+pub use options::LibraryOptions;
+pub use sharpness::{sharpness, SHARPNESS_SIZE};
+pub use source::{tile_sources, tile_weights, TileSource};
 pub use tile::Tile;
-pub use tileset::TileSet;
-pub use utils::{flipped_coords, prepare_tile, prepare_tile_with_date};
+pub use tileset::{IndexKind, TileIndex, TileSet, MAX_TILES};
+#[cfg(test)]
+pub(crate) use utils::jpeg_with_exif;
+pub use utils::{
+    flipped_coords, open_oriented, prepare_tile, prepare_tile_isolated, prepare_tile_with_metadata,
+    read_exif_metadata, read_picture_metadata, read_sharpness, refresh_rotated_tiles,
+    refresh_tiles, ExifMetadata,
+};
 
 /// Representation type for computing distances between N-vectors
+#[allow(clippy::upper_case_acronyms)]
 pub type SIZE = fixed::FixedU32<U0>;
 
 // Module declarations
+mod atomic;
+mod cache;
+mod checkpoint;
+mod crops;
+mod filter;
+mod formats;
+mod hnsw;
+mod image_cache;
+mod json;
+mod list;
+mod options;
+mod sharpness;
+mod source;
 mod tile;
 mod tileset;
 mod utils;
@@ -0,0 +1,100 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use fs2::FileExt;
+use tempfile::NamedTempFile;
+
+/// Write the file at `path` with `write`, through a temporary file of the same directory
+/// renamed over it once complete, so that readers, other runs included, find either its
+/// previous contents or the new ones but never a partial file.
+pub fn write_atomic(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<&File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let temp = NamedTempFile::new_in(dir)?;
+    let mut writer = BufWriter::new(temp.as_file());
+    write(&mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    // The temporary file is removed if it cannot replace the file
+    temp.persist(path)?;
+    Ok(())
+}
+
+/// An exclusive lock on the analysis cache of a tiles directory, so that runs analysing the
+/// same tiles at once update it one after the other. The lock is released when dropped, or by
+/// the system when the run dies.
+pub struct CacheLock(File);
+
+impl CacheLock {
+    /// Name of the lock file in the tiles directory.
+    pub const FILE_NAME: &'static str = ".emosaic.lock";
+
+    /// Lock the analysis cache of the tiles directory `dir`, waiting for any other run holding
+    /// the lock to release it.
+    pub fn acquire(dir: &Path) -> io::Result<CacheLock> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(dir.join(CacheLock::FILE_NAME))?;
+        file.lock_exclusive()?;
+        Ok(CacheLock(file))
+    }
+}
+
+impl Drop for CacheLock {
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_write_atomic() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("cache");
+        write_atomic(&path, |w| w.write_all(b"first")).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+
+        // A failed write leaves the previous contents and no temporary file behind
+        let failed = write_atomic(&path, |w| {
+            w.write_all(b"partial")?;
+            Err(io::Error::other("interrupted"))
+        });
+        assert!(failed.is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_cache_lock() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let lock = CacheLock::acquire(dir).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let path = dir.to_path_buf();
+        let waiting = thread::spawn(move || {
+            let _lock = CacheLock::acquire(&path).unwrap();
+            sender.send(()).unwrap();
+        });
+        // The other run waits until the lock is released
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(lock);
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+        waiting.join().unwrap();
+    }
+}
@@ -0,0 +1,547 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use ::image::Rgb;
+use bincode::Options;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::atomic::{write_atomic, CacheLock};
+use super::options::LibraryOptions;
+use super::tile::Tile;
+use super::tileset::TileSet;
+use super::utils::ExifMetadata;
+use crate::mosaic::analysis::downsample;
+use crate::mosaic::error::{Cause, EmosaicError};
+use crate::mosaic::timestamp::Timestamp;
+
+/// Bytes starting the cache files, followed by the version of their schema.
+const MAGIC: [u8; 4] = *b"EMSC";
+
+/// The directory holding the analysis cache of the tiles directory `tiles_dir`, with the
+/// checkpoint, lock, quarantine and failures of its analysis: the tiles directory itself, or
+/// under the cache root of `library`, a directory named after it and a hash of its absolute
+/// path, created when missing.
+pub fn cache_dir(tiles_dir: &Path, library: &LibraryOptions) -> io::Result<PathBuf> {
+    let Some(root) = &library.cache_root else {
+        return Ok(tiles_dir.to_owned());
+    };
+    let tiles_dir = tiles_dir.canonicalize()?;
+    let name = tiles_dir
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    let hash = md5::compute(tiles_dir.as_os_str().as_encoded_bytes());
+    let dir = root.join(format!("{}-{:x}", name, hash));
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// The directory holding the analysis cache of the tiles of every tiles list, recorded by
+/// their absolute paths so that the lists share the analyses of the images they have in
+/// common: under the cache root of `library`, or the user cache directory, created when
+/// missing.
+pub fn list_cache_dir(library: &LibraryOptions) -> io::Result<PathBuf> {
+    let root = match library.cache_root.clone() {
+        Some(root) => root,
+        None => dirs::cache_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No user cache directory"))?
+            .join("mosaic")
+            .join("analysis"),
+    };
+    let dir = root.join("lists");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Bincode options for decoding caches: fails on corrupt input instead of panicking
+/// and never allocates more than the size of the input.
+pub(crate) fn cache_options(limit: usize) -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(limit as u64)
+}
+
+/// Schema version in the header of the cache file `bytes`, if they start with one.
+fn header_version(bytes: &[u8]) -> Option<u32> {
+    let header = bytes.get(..8).filter(|header| header[..4] == MAGIC)?;
+    Some(u32::from_le_bytes(header[4..].try_into().ok()?))
+}
+
+/// The error for cache bytes that cannot be decoded, for `source`.
+fn invalid(source: impl Into<Cause>) -> EmosaicError {
+    EmosaicError::cache("Invalid analysis cache", source)
+}
+
+/// Decode `bytes` as a whole, for telling apart the schemas of old cache files without a
+/// header: trailing bytes mean they were written with another schema.
+fn decode_exact<T: DeserializeOwned>(bytes: &[u8]) -> bincode::Result<T> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_limit(bytes.len() as u64)
+        .deserialize(bytes)
+}
+
+/// The analysis of a tiles directory at a single grid size.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    crop: bool,
+    n: usize,
+    tiles: Vec<Tile<Vec<u8>>>,
+    paths: Vec<PathBuf>,
+}
+
+impl CacheEntry {
+    fn validate(&self) -> Result<(), String> {
+        let dim = (self.n as f64).sqrt() as usize;
+        if self.n == 0 || dim * dim != self.n {
+            return Err(format!("analysis for N={} is not a square grid", self.n));
+        }
+        if self.tiles.len() != self.paths.len() {
+            return Err(format!(
+                "tile set has {} tiles but {} paths",
+                self.tiles.len(),
+                self.paths.len()
+            ));
+        }
+        for tile in &self.tiles {
+            if tile.colors.len() != self.n * 3 {
+                return Err(format!(
+                    "invalid length {}, expected {} color components (analysis for N={})",
+                    tile.colors.len(),
+                    self.n * 3,
+                    self.n
+                ));
+            }
+            if tile.idx == 0 || tile.idx as usize > self.paths.len() {
+                return Err(format!(
+                    "tile index {} out of range 1..={}",
+                    tile.idx,
+                    self.paths.len()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A tile as written by the schemas before the captions and cameras, with `I` tile indices.
+#[derive(Deserialize)]
+struct LegacyTile<I>(Vec<u8>, I, Option<String>);
+
+/// An analysis as written by the schemas before the captions and cameras, with `I` tile
+/// indices.
+#[derive(Deserialize)]
+struct LegacyEntry<I> {
+    crop: bool,
+    n: usize,
+    tiles: Vec<LegacyTile<I>>,
+    paths: Vec<PathBuf>,
+}
+
+impl<I: Into<u32>> LegacyEntry<I> {
+    fn upgrade(self) -> Result<CacheEntry, String> {
+        let entry = CacheEntry {
+            crop: self.crop,
+            n: self.n,
+            tiles: self
+                .tiles
+                .into_iter()
+                .map(|LegacyTile(colors, idx, date_taken)| {
+                    Tile::new_with_date(idx.into(), colors, date_taken)
+                })
+                .collect(),
+            paths: self.paths,
+        };
+        entry.validate()?;
+        Ok(entry)
+    }
+}
+
+/// A cache as written by the schemas before the captions and cameras, with `I` tile indices,
+/// without the header of schema 3.
+#[derive(Deserialize)]
+struct LegacyCache<I> {
+    entries: Vec<LegacyEntry<I>>,
+}
+
+impl<I: Into<u32> + DeserializeOwned> LegacyCache<I> {
+    fn decode(bytes: &[u8]) -> bincode::Result<AnalysisCache> {
+        let legacy: LegacyCache<I> = decode_exact(bytes)?;
+        let entries = legacy
+            .entries
+            .into_iter()
+            .map(LegacyEntry::upgrade)
+            .collect::<Result<_, _>>()
+            .map_err(|err| Box::new(bincode::ErrorKind::Custom(err)))?;
+        Ok(AnalysisCache { entries })
+    }
+}
+
+/// A tile as written by schema 4, before the sharpness of the pictures.
+#[derive(Deserialize)]
+struct CaptionedTile(Vec<u8>, u32, Option<String>, Option<String>, Option<String>);
+
+/// An analysis as written by schema 4, before the sharpness of the pictures.
+#[derive(Deserialize)]
+struct CaptionedEntry {
+    crop: bool,
+    n: usize,
+    tiles: Vec<CaptionedTile>,
+    paths: Vec<PathBuf>,
+}
+
+/// A cache as written by schema 4, before the sharpness of the pictures, without its header.
+#[derive(Deserialize)]
+struct CaptionedCache {
+    entries: Vec<CaptionedEntry>,
+}
+
+impl CaptionedCache {
+    fn decode(bytes: &[u8]) -> bincode::Result<AnalysisCache> {
+        let captioned: CaptionedCache = cache_options(bytes.len()).deserialize(bytes)?;
+        let entries = captioned
+            .entries
+            .into_iter()
+            .map(|entry| {
+                let tiles = entry.tiles.into_iter().map(
+                    |CaptionedTile(colors, idx, date_taken, caption, camera)| {
+                        Tile::new_with_date(idx, colors, date_taken).with_details(caption, camera)
+                    },
+                );
+                let entry = CacheEntry {
+                    crop: entry.crop,
+                    n: entry.n,
+                    tiles: tiles.collect(),
+                    paths: entry.paths,
+                };
+                entry.validate()?;
+                Ok(entry)
+            })
+            .collect::<Result<_, String>>()
+            .map_err(|err| Box::new(bincode::ErrorKind::Custom(err)))?;
+        Ok(AnalysisCache { entries })
+    }
+}
+
+/// Analyses of a tiles directory shared by every mode, one per crop setting.
+///
+/// Each analysis is kept at the finest grid computed so far, and coarser modes are derived
+/// from it by downsampling instead of analysing the tiles again.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AnalysisCache {
+    entries: Vec<CacheEntry>,
+}
+
+impl AnalysisCache {
+    /// Name of the cache file in the tiles directory.
+    pub const FILE_NAME: &'static str = ".emosaic";
+
+    /// Version of the schema of the cache files, bumped whenever their layout changes so that
+    /// the caches of older versions are migrated by `emosaic cache upgrade` instead of being
+    /// analysed again:
+    ///
+    /// 0. one `.emosaic_{N}to1[_cropped]` file per mode, with 16-bit tile indices
+    /// 1. a single `.emosaic` file shared by every mode
+    /// 2. 32-bit tile indices, for libraries of more than 32k tiles
+    /// 3. a header holding the schema version
+    /// 4. the caption and camera of the pictures
+    /// 5. the sharpness of the pictures
+    ///
+    /// Caches of schema 2 and before may only hold the day the pictures were taken, those of
+    /// schema 3 and before no captions nor cameras, and those of schema 4 and before no
+    /// sharpness.
+    pub const SCHEMA_VERSION: u32 = 5;
+
+    /// Schema version of the bytes of a `.emosaic` file, from their header or, for the older
+    /// files without one, from the schema they decode with. `None` if they are not a cache.
+    pub fn schema_version(bytes: &[u8]) -> Option<u32> {
+        if let Some(version) = header_version(bytes) {
+            return Some(version);
+        }
+        if LegacyCache::<u32>::decode(bytes).is_ok() {
+            Some(2)
+        } else if LegacyCache::<u16>::decode(bytes).is_ok() {
+            Some(1)
+        } else {
+            None
+        }
+    }
+
+    /// The bytes of a cache file after its header, if it has the current schema.
+    fn body(bytes: &[u8]) -> bincode::Result<&[u8]> {
+        let version = header_version(bytes)
+            .ok_or_else(|| Box::new(bincode::ErrorKind::Custom(String::from("no header"))))?;
+        if version != AnalysisCache::SCHEMA_VERSION {
+            return Err(Box::new(bincode::ErrorKind::Custom(format!(
+                "schema version {}, expected {}",
+                version,
+                AnalysisCache::SCHEMA_VERSION
+            ))));
+        }
+        Ok(&bytes[8..])
+    }
+
+    /// Decode a cache from the bytes of a cache file.
+    pub fn from_bytes(bytes: &[u8]) -> Result<AnalysisCache, EmosaicError> {
+        let bytes = AnalysisCache::body(bytes).map_err(invalid)?;
+        let cache: AnalysisCache = cache_options(bytes.len())
+            .deserialize(bytes)
+            .map_err(invalid)?;
+        for entry in &cache.entries {
+            entry.validate().map_err(invalid)?;
+        }
+        Ok(cache)
+    }
+
+    /// Decode what is left of a cache whose file was cut short or damaged, e.g. by a run of an
+    /// older version of emosaic killed while writing it: the valid entries before the damage
+    /// are kept, and the others are left to be analysed again.
+    ///
+    /// # Returns
+    /// The cache and the number of entries lost, or an error when none is left
+    pub fn from_partial_bytes(bytes: &[u8]) -> Result<(AnalysisCache, usize), EmosaicError> {
+        let mut body = AnalysisCache::body(bytes).map_err(invalid)?;
+        let count: u64 = cache_options(body.len())
+            .deserialize_from(&mut body)
+            .map_err(invalid)?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            // The entries after a damaged one cannot be found
+            let Ok(entry) = cache_options(body.len()).deserialize_from::<_, CacheEntry>(&mut body)
+            else {
+                break;
+            };
+            if entry.validate().is_ok() {
+                entries.push(entry);
+            }
+        }
+        let lost = (count - entries.len() as u64) as usize;
+        if entries.is_empty() && lost > 0 {
+            return Err(invalid(format!(
+                "none of its {} entries is complete",
+                count
+            )));
+        }
+        Ok((AnalysisCache { entries }, lost))
+    }
+
+    /// Update the cache file of the tiles directory `dir` with `update`, holding its lock so
+    /// that runs analysing the same tiles at once keep the analyses of each other: the cache is
+    /// read again under the lock and replaced atomically.
+    pub fn update(dir: &Path, update: impl FnOnce(&mut AnalysisCache)) -> Result<(), EmosaicError> {
+        let path = dir.join(AnalysisCache::FILE_NAME);
+        let _lock = CacheLock::acquire(dir).map_err(|e| {
+            EmosaicError::io(
+                format!("Failed to lock analysis cache {}", path.display()),
+                e,
+            )
+        })?;
+        let mut cache = fs::read(&path)
+            .ok()
+            .and_then(|bytes| AnalysisCache::from_partial_bytes(&bytes).ok())
+            .map(|(cache, _)| cache)
+            .unwrap_or_default();
+        update(&mut cache);
+        let bytes = cache.to_bytes()?;
+        write_atomic(&path, |writer| writer.write_all(&bytes)).map_err(|e| {
+            EmosaicError::io(
+                format!("Failed to write analysis cache {}", path.display()),
+                e,
+            )
+        })
+    }
+
+    /// Decode a cache from the bytes of a `.emosaic` file written with any schema up to the
+    /// current one.
+    pub fn from_any_bytes(bytes: &[u8]) -> Result<AnalysisCache, EmosaicError> {
+        match AnalysisCache::schema_version(bytes) {
+            Some(4) => CaptionedCache::decode(&bytes[8..]).map_err(invalid),
+            Some(3) => LegacyCache::<u32>::decode(&bytes[8..]).map_err(invalid),
+            Some(2) => LegacyCache::<u32>::decode(bytes).map_err(invalid),
+            Some(1) => LegacyCache::<u16>::decode(bytes).map_err(invalid),
+            Some(version) if version > AnalysisCache::SCHEMA_VERSION => Err(invalid(format!(
+                "written by a newer version of emosaic, with schema version {}",
+                version
+            ))),
+            _ => AnalysisCache::from_bytes(bytes),
+        }
+    }
+
+    /// Encode the cache for writing to a cache file.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, EmosaicError> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(AnalysisCache::SCHEMA_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut bytes, self)
+            .map_err(|e| EmosaicError::cache("Failed to encode the analysis cache", e))?;
+        Ok(bytes)
+    }
+
+    /// The per-mode cache files of schema 0 in the tiles directory `dir`, with the grid size
+    /// and crop setting of their analysis.
+    pub fn mode_files(dir: &Path) -> io::Result<Vec<(PathBuf, usize, bool)>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(mode) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(".emosaic_"))
+            else {
+                continue;
+            };
+            let (mode, crop) = match mode.strip_suffix("_cropped") {
+                Some(mode) => (mode, true),
+                None => (mode, false),
+            };
+            if let Some(n) = mode.strip_suffix("to1").and_then(|n| n.parse().ok()) {
+                files.push((path, n, crop));
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Add the analysis at grid size `n` of a per-mode cache file of schema 0, unless the
+    /// cache already holds one at least as fine for `crop`.
+    pub fn insert_mode_file(
+        &mut self,
+        crop: bool,
+        n: usize,
+        bytes: &[u8],
+    ) -> Result<(), EmosaicError> {
+        let (tiles, paths): (Vec<LegacyTile<u16>>, Vec<PathBuf>) =
+            decode_exact(bytes).map_err(invalid)?;
+        let entry = LegacyEntry {
+            crop,
+            n,
+            tiles,
+            paths,
+        }
+        .upgrade()
+        .map_err(invalid)?;
+        if self.cached_n(crop).is_none_or(|cached_n| cached_n < n) {
+            self.entries.retain(|entry| entry.crop != crop);
+            self.entries.push(entry);
+        }
+        Ok(())
+    }
+
+    /// Re-read with `read_metadata` what older schemas left out of the tiles: when the
+    /// pictures were taken for the dates that are only a day, and their caption and camera,
+    /// reading every picture once.
+    ///
+    /// # Returns
+    /// The number of pictures whose metadata changed
+    pub fn refresh_metadata(
+        &mut self,
+        read_metadata: impl Fn(&Path) -> Option<ExifMetadata> + Sync,
+    ) -> usize {
+        let day_only = |date: &Option<String>| {
+            date.as_deref()
+                .is_some_and(|date| !Timestamp::parse(date).is_some_and(|taken| taken.has_time))
+        };
+        let stale = |tile: &Tile<Vec<u8>>| {
+            day_only(&tile.date_taken) || (tile.caption.is_none() && tile.camera.is_none())
+        };
+        let mut stale: Vec<&Path> = self
+            .entries
+            .iter()
+            .flat_map(|entry| {
+                entry
+                    .tiles
+                    .iter()
+                    .filter(|tile| stale(tile))
+                    .map(move |tile| entry.paths[tile.idx as usize - 1].as_path())
+            })
+            .collect();
+        stale.sort();
+        stale.dedup();
+        let metadata: HashMap<PathBuf, ExifMetadata> = stale
+            .into_par_iter()
+            .filter_map(|path| Some((path.to_owned(), read_metadata(path)?)))
+            .collect();
+        let mut changed = HashSet::new();
+        for entry in &mut self.entries {
+            for tile in &mut entry.tiles {
+                let path = &entry.paths[tile.idx as usize - 1];
+                let Some(metadata) = metadata.get(path) else {
+                    continue;
+                };
+                if day_only(&tile.date_taken)
+                    && metadata.date_taken.is_some()
+                    && tile.date_taken != metadata.date_taken
+                {
+                    tile.date_taken = metadata.date_taken.clone();
+                    changed.insert(path);
+                }
+                if tile.caption.is_none() && metadata.caption.is_some() {
+                    tile.caption = metadata.caption.clone();
+                    changed.insert(path);
+                }
+                if tile.camera.is_none() {
+                    tile.camera = metadata.camera();
+                    if tile.camera.is_some() {
+                        changed.insert(path);
+                    }
+                }
+            }
+        }
+        changed.len()
+    }
+
+    /// Record the sharpness of the pictures at the paths of `scores`, in every analysis.
+    pub fn set_sharpness(&mut self, scores: &HashMap<PathBuf, f32>) {
+        for entry in &mut self.entries {
+            for tile in &mut entry.tiles {
+                let path = &entry.paths[tile.idx as usize - 1];
+                if let Some(&sharpness) = scores.get(path) {
+                    tile.sharpness = Some(sharpness);
+                }
+            }
+        }
+    }
+
+    /// Grid size of the cached analysis for `crop`, if any.
+    pub fn cached_n(&self, crop: bool) -> Option<usize> {
+        self.entry(crop).map(|entry| entry.n)
+    }
+
+    /// The tile set for mode N, derived from the cached analysis when it is at least as fine.
+    pub fn get<const N: usize>(&self, crop: bool) -> Option<TileSet<[Rgb<u8>; N]>> {
+        let entry = self.entry(crop).filter(|entry| entry.n >= N)?;
+        let tiles = entry
+            .tiles
+            .iter()
+            .map(|tile| {
+                tile.clone().map(|colors| {
+                    let colors: Vec<_> = colors
+                        .chunks_exact(3)
+                        .map(|c| Rgb([c[0], c[1], c[2]]))
+                        .collect();
+                    downsample::<N>(&colors)
+                })
+            })
+            .collect();
+        Some(TileSet::from_tiles(tiles, entry.paths.clone()))
+    }
+
+    /// Store the analysis of `tile_set` for `crop`, replacing any previous one.
+    pub fn insert<const N: usize>(&mut self, crop: bool, tile_set: &TileSet<[Rgb<u8>; N]>) {
+        self.entries.retain(|entry| entry.crop != crop);
+        self.entries.push(CacheEntry {
+            crop,
+            n: N,
+            tiles: tile_set.raw_colors(),
+            paths: tile_set.paths().to_vec(),
+        });
+    }
+
+    fn entry(&self, crop: bool) -> Option<&CacheEntry> {
+        self.entries.iter().find(|entry| entry.crop == crop)
+    }
+}
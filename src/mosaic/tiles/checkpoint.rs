@@ -0,0 +1,204 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use ::image::Rgb;
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+use super::atomic::write_atomic;
+use super::cache::cache_options;
+use super::tile::Tile;
+use super::utils::ExifMetadata;
+
+/// A tile analysed before the analysis was interrupted.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct AnalysedImage {
+    path: PathBuf,
+    colors: Vec<u8>,
+    date_taken: Option<String>,
+    caption: Option<String>,
+    camera: Option<String>,
+    sharpness: Option<f32>,
+}
+
+/// The tiles analysed so far by an analysis of a tiles directory, written every few hundred
+/// images so that an interrupted analysis of a large library resumes where it left off.
+///
+/// A checkpoint only applies to an analysis with the same crop, grid and tile size, and is
+/// removed once the analysis cache is written.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AnalysisCheckpoint {
+    crop: bool,
+    n: usize,
+    tile_size: u32,
+    images: Vec<AnalysedImage>,
+}
+
+impl AnalysisCheckpoint {
+    /// Name of the checkpoint file in the tiles directory.
+    pub const FILE_NAME: &'static str = ".emosaic-partial";
+
+    /// Number of images analysed between writes of the checkpoint.
+    pub const INTERVAL: usize = 500;
+
+    /// An empty checkpoint for an analysis at grid size `n`.
+    pub fn new(crop: bool, n: usize, tile_size: u32) -> AnalysisCheckpoint {
+        AnalysisCheckpoint {
+            crop,
+            n,
+            tile_size,
+            images: Vec::new(),
+        }
+    }
+
+    /// Read the checkpoint of the tiles directory `dir`, if there is one for an analysis
+    /// with the same settings.
+    pub fn load(dir: &Path, crop: bool, n: usize, tile_size: u32) -> Option<AnalysisCheckpoint> {
+        let bytes = fs::read(dir.join(AnalysisCheckpoint::FILE_NAME)).ok()?;
+        let checkpoint: AnalysisCheckpoint = cache_options(bytes.len()).deserialize(&bytes).ok()?;
+        let matches = (checkpoint.crop, checkpoint.n, checkpoint.tile_size) == (crop, n, tile_size)
+            && checkpoint
+                .images
+                .iter()
+                .all(|image| image.colors.len() == n * 3);
+        matches.then_some(checkpoint)
+    }
+
+    /// Write the checkpoint of the tiles directory `dir`, replacing the previous one only once
+    /// it is complete so that an interruption, or another run, never leaves a truncated
+    /// checkpoint behind.
+    pub fn save(&self, dir: &Path) -> Result<(), io::Error> {
+        let bytes = bincode::serialize(self).map_err(io::Error::other)?;
+        write_atomic(&dir.join(AnalysisCheckpoint::FILE_NAME), |writer| {
+            writer.write_all(&bytes)
+        })
+    }
+
+    /// Remove the checkpoint of the tiles directory `dir`, if any.
+    pub fn remove(dir: &Path) -> Result<(), io::Error> {
+        match fs::remove_file(dir.join(AnalysisCheckpoint::FILE_NAME)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Number of images analysed so far.
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+
+    /// Paths of the images analysed so far.
+    pub fn paths(&self) -> HashSet<&Path> {
+        self.images
+            .iter()
+            .map(|image| image.path.as_path())
+            .collect()
+    }
+
+    /// Forget the images whose path fails `keep`, e.g. those no longer in the directory.
+    pub fn retain(&mut self, mut keep: impl FnMut(&Path) -> bool) {
+        self.images.retain(|image| keep(&image.path));
+    }
+
+    /// Record the analysis of the image at `path`, with the date, caption and camera of its
+    /// `metadata` and its `sharpness` if measured.
+    pub fn push<const N: usize>(
+        &mut self,
+        path: PathBuf,
+        colors: &[Rgb<u8>; N],
+        metadata: &ExifMetadata,
+        sharpness: Option<f32>,
+    ) {
+        debug_assert_eq!(N, self.n);
+        self.images.push(AnalysedImage {
+            path,
+            colors: colors.iter().flat_map(|color| color.0).collect(),
+            date_taken: metadata.date_taken.clone(),
+            caption: metadata.caption.clone(),
+            camera: metadata.camera(),
+            sharpness,
+        });
+    }
+
+    /// The path and tile of every image analysed so far, numbered from 1 in the order they
+    /// were recorded.
+    pub fn into_images<const N: usize>(
+        self,
+    ) -> impl Iterator<Item = (PathBuf, Tile<[Rgb<u8>; N]>)> {
+        self.images.into_iter().enumerate().map(|(i, image)| {
+            let mut colors = [Rgb([0, 0, 0]); N];
+            for (color, c) in colors.iter_mut().zip(image.colors.chunks_exact(3)) {
+                *color = Rgb([c[0], c[1], c[2]]);
+            }
+            let tile = Tile::new_with_date((i + 1) as u32, colors, image.date_taken)
+                .with_details(image.caption, image.camera);
+            let tile = Tile {
+                sharpness: image.sharpness,
+                ..tile
+            };
+            (image.path, tile)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analysis_checkpoint() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        AnalysisCheckpoint::remove(dir).unwrap();
+        assert_eq!(AnalysisCheckpoint::load(dir, false, 4, 32), None);
+
+        let mut checkpoint = AnalysisCheckpoint::new(false, 4, 32);
+        checkpoint.push(
+            PathBuf::from("a.jpg"),
+            &[Rgb([1, 2, 3]); 4],
+            &ExifMetadata::default(),
+            None,
+        );
+        let metadata = ExifMetadata {
+            date_taken: Some("2024-05-01".to_string()),
+            camera_model: Some("iPhone 12".to_string()),
+            caption: Some("Picnic".to_string()),
+            ..ExifMetadata::default()
+        };
+        checkpoint.push(
+            PathBuf::from("b.jpg"),
+            &[Rgb([4, 5, 6]); 4],
+            &metadata,
+            Some(250.0),
+        );
+        checkpoint.save(dir).unwrap();
+
+        // Only an analysis with the same settings resumes from the checkpoint
+        assert_eq!(AnalysisCheckpoint::load(dir, true, 4, 32), None);
+        assert_eq!(AnalysisCheckpoint::load(dir, false, 1, 32), None);
+        assert_eq!(AnalysisCheckpoint::load(dir, false, 4, 64), None);
+        let mut resumed = AnalysisCheckpoint::load(dir, false, 4, 32).unwrap();
+        assert_eq!(resumed, checkpoint);
+
+        resumed.retain(|path| path != Path::new("a.jpg"));
+        assert_eq!(resumed.paths(), HashSet::from([Path::new("b.jpg")]));
+        let images: Vec<_> = resumed.into_images::<4>().collect();
+        assert_eq!(images.len(), 1);
+        let (path, tile) = &images[0];
+        assert_eq!(path, Path::new("b.jpg"));
+        assert_eq!((tile.idx, tile.colors), (1, [Rgb([4, 5, 6]); 4]));
+        assert_eq!(tile.date_taken.as_deref(), Some("2024-05-01"));
+        assert_eq!(tile.caption.as_deref(), Some("Picnic"));
+        assert_eq!(tile.camera.as_deref(), Some("iPhone 12"));
+        assert_eq!(tile.sharpness, Some(250.0));
+
+        AnalysisCheckpoint::remove(dir).unwrap();
+        assert!(!dir.join(AnalysisCheckpoint::FILE_NAME).exists());
+    }
+}
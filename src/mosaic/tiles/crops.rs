@@ -0,0 +1,210 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use image::DynamicImage;
+use rayon::prelude::*;
+use tracing::info;
+
+use super::filter::picture_dimensions;
+
+/// Most crops taken from every picture, the number of [`CROP_REGIONS`].
+pub const MAX_AUGMENT_CROPS: usize = 8;
+
+/// Shortest side, in pixels, of the crops taken, so that they are not blurry once prepared.
+pub const MIN_CROP_SIZE: u32 = 256;
+
+/// Start of the fragment naming a crop of a picture after its path, in the syntax of W3C media
+/// fragments, e.g. `photo.jpg#xywh=percent:0,0,70,70`.
+const CROP_FRAGMENT: &str = "#xywh=percent:";
+
+/// A region of a picture, in percents of its width and height once rotated upright.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CropRegion {
+    pub x: u8,
+    pub y: u8,
+    pub width: u8,
+    pub height: u8,
+}
+
+const fn region(x: u8, y: u8, width: u8, height: u8) -> CropRegion {
+    CropRegion {
+        x,
+        y,
+        width,
+        height,
+    }
+}
+
+/// The crops taken from the pictures, in order: a zoom on the centre, the four corners, a
+/// closer zoom on the centre and both sides. Taking fewer crops takes the first ones, so that
+/// the tiles of a smaller count are among those of a larger one.
+pub const CROP_REGIONS: [CropRegion; MAX_AUGMENT_CROPS] = [
+    region(20, 20, 60, 60),
+    region(0, 0, 70, 70),
+    region(30, 30, 70, 70),
+    region(30, 0, 70, 70),
+    region(0, 30, 70, 70),
+    region(30, 30, 40, 40),
+    region(0, 25, 50, 50),
+    region(50, 25, 50, 50),
+];
+
+impl CropRegion {
+    /// The rectangle of the region in a picture of `width` by `height` pixels: its left, top,
+    /// width and height, at least a pixel wide and high.
+    pub fn rect(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let scale = |percent: u8, size: u32| (u64::from(percent) * u64::from(size) / 100) as u32;
+        let (x, y) = (scale(self.x, width), scale(self.y, height));
+        let w = scale(self.width, width).clamp(1, width.saturating_sub(x).max(1));
+        let h = scale(self.height, height).clamp(1, height.saturating_sub(y).max(1));
+        (x, y, w, h)
+    }
+
+    /// The region of the upright `image`.
+    pub fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        let (x, y, width, height) = self.rect(image.width(), image.height());
+        image.crop_imm(x, y, width, height)
+    }
+
+    /// Whether the region of a picture of `width` by `height` pixels is at least `min_size`
+    /// pixels on its shorter side.
+    pub fn fits(&self, width: u32, height: u32, min_size: u32) -> bool {
+        let (_, _, width, height) = self.rect(width, height);
+        width.min(height) >= min_size
+    }
+}
+
+impl CropRegion {
+    /// The fragment naming the region after the path or URL of its picture.
+    pub fn fragment(&self) -> String {
+        format!("{}{}", CROP_FRAGMENT, self)
+    }
+}
+
+impl fmt::Display for CropRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+/// The path naming the `region` of the picture at `path`, or `None` when `path` is not valid
+/// UTF-8 and cannot carry it.
+pub fn with_crop(path: &Path, region: CropRegion) -> Option<PathBuf> {
+    let path = path.to_str()?;
+    Some(PathBuf::from(format!("{}{}", path, region.fragment())))
+}
+
+/// The path of the picture a tile is taken from and the region of it, if the tile is a crop.
+pub fn split_crop(path: &Path) -> (&Path, Option<CropRegion>) {
+    let split = path.to_str().and_then(|text| {
+        let (file, fragment) = text.rsplit_once(CROP_FRAGMENT)?;
+        let mut values = fragment.split(',').map(|value| value.parse::<u8>().ok());
+        let region = region(
+            values.next()??,
+            values.next()??,
+            values.next()??,
+            values.next()??,
+        );
+        let valid = values.next().is_none()
+            && region.width > 0
+            && region.height > 0
+            && region.x as u32 + region.width as u32 <= 100
+            && region.y as u32 + region.height as u32 <= 100;
+        valid.then_some((Path::new(file), region))
+    });
+    match split {
+        Some((file, region)) => (file, Some(region)),
+        None => (path, None),
+    }
+}
+
+/// Whether the tile at `path` is the whole picture or one of the first `count` crops.
+pub fn is_kept_crop(path: &Path, count: usize) -> bool {
+    match split_crop(path).1 {
+        Some(region) => CROP_REGIONS[..count.min(MAX_AUGMENT_CROPS)].contains(&region),
+        None => true,
+    }
+}
+
+/// The paths of the first `count` crops of a picture of `width` by `height` pixels at `path`
+/// whose shorter side is at least `min_size` pixels.
+pub fn crop_paths(
+    path: &Path,
+    (width, height): (u32, u32),
+    count: usize,
+    min_size: u32,
+) -> Vec<PathBuf> {
+    CROP_REGIONS[..count.min(MAX_AUGMENT_CROPS)]
+        .iter()
+        .filter(|region| region.fits(width, height, min_size))
+        .filter_map(|region| with_crop(path, *region))
+        .collect()
+}
+
+/// The `paths` of the pictures to analyse followed by the paths of the crops of those large
+/// enough to take up to `count` crops at least `min_size` pixels wide from.
+pub fn augment(paths: Vec<PathBuf>, min_size: u32, count: usize) -> Vec<PathBuf> {
+    if count == 0 {
+        return paths;
+    }
+    let min_size = min_size.max(MIN_CROP_SIZE);
+    let crops: Vec<PathBuf> = paths
+        .par_iter()
+        .filter_map(|path| Some((path, picture_dimensions(path)?)))
+        .flat_map_iter(|(path, dimensions)| crop_paths(path, dimensions, count, min_size))
+        .collect();
+    if !crops.is_empty() {
+        info!(
+            "Taking {} crops of {} images as extra tiles",
+            crops.len(),
+            paths.len()
+        );
+    }
+    let mut paths = paths;
+    paths.extend(crops);
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_crop_paths() {
+        let path = Path::new("photos/beach.jpg");
+        let crops = crop_paths(path, (4000, 3000), 3, MIN_CROP_SIZE);
+        assert_eq!(
+            crops,
+            [
+                PathBuf::from("photos/beach.jpg#xywh=percent:20,20,60,60"),
+                PathBuf::from("photos/beach.jpg#xywh=percent:0,0,70,70"),
+                PathBuf::from("photos/beach.jpg#xywh=percent:30,30,70,70"),
+            ]
+        );
+        for crop in &crops {
+            assert_eq!(split_crop(crop).0, path);
+            assert!(is_kept_crop(crop, 3));
+        }
+        assert_eq!(split_crop(&crops[1]).1, Some(CROP_REGIONS[1]));
+        assert!(!is_kept_crop(&crops[2], 2));
+        assert!(is_kept_crop(path, 0));
+        assert_eq!(split_crop(path), (path, None));
+        // Crops smaller than the minimum size are not taken
+        assert_eq!(crop_paths(path, (400, 300), 8, MIN_CROP_SIZE).len(), 0);
+        assert_eq!(crop_paths(path, (600, 450), 8, MIN_CROP_SIZE).len(), 5);
+
+        let invalid = Path::new("a.jpg#xywh=percent:50,50,60,60");
+        assert_eq!(split_crop(invalid), (invalid, None));
+    }
+
+    #[test]
+    fn test_apply_crop() {
+        let image = RgbImage::from_fn(100, 50, |x, y| Rgb([x as u8, y as u8, 0]));
+        let cropped = CROP_REGIONS[2]
+            .apply(&DynamicImage::ImageRgb8(image))
+            .to_rgb8();
+        assert_eq!(cropped.dimensions(), (70, 35));
+        assert_eq!(cropped.get_pixel(0, 0), &Rgb([30, 15, 0]));
+    }
+}
@@ -0,0 +1,208 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use image::ImageReader;
+use rayon::prelude::*;
+use tracing::info;
+
+use super::crops::split_crop;
+use super::formats;
+
+/// A width and a height in pixels, written `WxH`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Resolution, String> {
+        let invalid = || format!("Invalid resolution {:?}, expected e.g. 200x200", s);
+        let (width, height) = s.split_once(['x', 'X']).ok_or_else(invalid)?;
+        let width = width.trim().parse().map_err(|_| invalid())?;
+        let height = height.trim().parse().map_err(|_| invalid())?;
+        Ok(Resolution { width, height })
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+/// Why a picture is left out of the tiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    /// Smaller than the minimum resolution
+    TooSmall,
+    /// More elongated than the maximum aspect ratio
+    TooElongated,
+}
+
+/// The pictures too small, too elongated or too blurry to make good tiles, e.g. thumbnails,
+/// panoramas and shaken photos.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TileFilter {
+    /// Smallest picture kept, in either orientation
+    pub min_resolution: Option<Resolution>,
+    /// Largest ratio of the longer side of a picture kept to its shorter side
+    pub max_aspect_ratio: Option<f64>,
+    /// Least sharpness of a picture kept, the variance of its Laplacian
+    pub min_sharpness: Option<f32>,
+}
+
+impl TileFilter {
+    /// Whether every picture is kept whatever its dimensions.
+    fn ignores_dimensions(&self) -> bool {
+        self.min_resolution.is_none() && self.max_aspect_ratio.is_none()
+    }
+
+    /// Whether a picture of `sharpness` is left out. Pictures whose sharpness was not measured
+    /// are kept.
+    pub fn is_blurry(&self, sharpness: Option<f32>) -> bool {
+        matches!((self.min_sharpness, sharpness), (Some(min), Some(sharpness)) if sharpness < min)
+    }
+
+    /// Why a picture of `width` by `height` pixels is left out, if it is.
+    pub fn check(&self, width: u32, height: u32) -> Option<Rejection> {
+        let (short, long) = (width.min(height), width.max(height));
+        if let Some(min) = self.min_resolution {
+            if short < min.width.min(min.height) || long < min.width.max(min.height) {
+                return Some(Rejection::TooSmall);
+            }
+        }
+        match self.max_aspect_ratio {
+            Some(max) if short == 0 || long as f64 / short as f64 > max => {
+                Some(Rejection::TooElongated)
+            }
+            _ => None,
+        }
+    }
+
+    /// Keep the `items` whose picture, at `path(item)`, is not left out for its dimensions,
+    /// and report how many were and why. Pictures whose size cannot be read are kept, to fail when analysed.
+    pub fn retain<T: Send>(&self, items: Vec<T>, path: impl Fn(&T) -> &Path + Sync) -> Vec<T> {
+        if self.ignores_dimensions() {
+            return items;
+        }
+        let checked: Vec<_> = items
+            .into_par_iter()
+            .map(|item| {
+                let rejection = picture_dimensions(path(&item))
+                    .and_then(|(width, height)| self.check(width, height));
+                (item, rejection)
+            })
+            .collect();
+        let count = |rejection| {
+            checked
+                .iter()
+                .filter(|(_, r)| *r == Some(rejection))
+                .count()
+        };
+        let (small, elongated) = (count(Rejection::TooSmall), count(Rejection::TooElongated));
+        if small > 0 {
+            let min = self.min_resolution.unwrap();
+            info!("Skipping {} images smaller than {}", small, min);
+        }
+        if elongated > 0 {
+            let max = self.max_aspect_ratio.unwrap();
+            info!(
+                "Skipping {} images with an aspect ratio over {}",
+                elongated, max
+            );
+        }
+        checked
+            .into_iter()
+            .filter_map(|(item, rejection)| rejection.is_none().then_some(item))
+            .collect()
+    }
+}
+
+/// Width and height of the picture at `path`, from the header of its file, or as read by
+/// [`formats::dimensions`] for camera RAW files and HEIF pictures. Crops of a picture have the
+/// dimensions of the whole picture.
+pub(super) fn picture_dimensions(path: &Path) -> Option<(u32, u32)> {
+    let path = split_crop(path).0;
+    if formats::is_raw(path) || formats::is_heif(path) {
+        let bytes = std::fs::read(path).ok()?;
+        formats::dimensions(path, &bytes)
+    } else {
+        let reader = ImageReader::open(path).ok()?.with_guessed_format().ok()?;
+        reader.into_dimensions().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_parse_resolution() {
+        let resolution: Resolution = "640x480".parse().unwrap();
+        assert_eq!(
+            resolution,
+            Resolution {
+                width: 640,
+                height: 480
+            }
+        );
+        assert_eq!(resolution.to_string(), "640x480");
+        assert!("640".parse::<Resolution>().is_err());
+        assert!("640xabc".parse::<Resolution>().is_err());
+    }
+
+    #[test]
+    fn test_tile_filter() {
+        let filter = TileFilter {
+            min_resolution: Some(Resolution {
+                width: 200,
+                height: 100,
+            }),
+            max_aspect_ratio: Some(3.0),
+            min_sharpness: None,
+        };
+        assert_eq!(filter.check(150, 80), Some(Rejection::TooSmall));
+        // Either orientation is enough
+        assert_eq!(filter.check(100, 200), None);
+        assert_eq!(filter.check(1200, 300), Some(Rejection::TooElongated));
+        assert_eq!(filter.check(300, 900), None);
+        assert_eq!(TileFilter::default().check(1, 1000), None);
+
+        let filter = TileFilter {
+            min_sharpness: Some(50.0),
+            ..TileFilter::default()
+        };
+        assert!(filter.is_blurry(Some(49.0)));
+        assert!(!filter.is_blurry(Some(50.0)));
+        assert!(!filter.is_blurry(None));
+        assert!(!TileFilter::default().is_blurry(Some(0.0)));
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let mut paths = Vec::new();
+        for (name, (width, height)) in [("a.png", (40, 40)), ("b.png", (8, 8)), ("c.png", (90, 10))]
+        {
+            let path = dir.join(name);
+            RgbImage::from_pixel(width, height, Rgb([1, 2, 3]))
+                .save(&path)
+                .unwrap();
+            paths.push(path);
+        }
+        paths.push(dir.join("missing.png"));
+        let filter = TileFilter {
+            min_resolution: Some(Resolution {
+                width: 16,
+                height: 16,
+            }),
+            max_aspect_ratio: Some(2.0),
+            min_sharpness: None,
+        };
+        let kept = filter.retain(paths, |path| path);
+        assert_eq!(kept, [dir.join("a.png"), dir.join("missing.png")]);
+    }
+}
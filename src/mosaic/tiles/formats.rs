@@ -0,0 +1,619 @@
+use std::convert::{TryFrom, TryInto};
+use std::ffi::OsStr;
+use std::io::Cursor;
+use std::path::Path;
+
+#[cfg(any(feature = "heif", feature = "raw"))]
+use image::error::{DecodingError, LimitError, LimitErrorKind};
+use image::error::{ImageFormatHint, UnsupportedError, UnsupportedErrorKind};
+#[cfg(any(feature = "heif", feature = "raw"))]
+use image::RgbImage;
+use image::{DynamicImage, ImageError, ImageFormat, ImageReader};
+
+#[cfg(any(feature = "heif", feature = "raw"))]
+use super::utils::MAX_TILE_PIXELS;
+
+/// Extensions of the camera RAW formats, whose pictures are read from the JPEG preview the
+/// cameras embed in them, or from their sensor data with the `raw` feature.
+pub const RAW_EXTENSIONS: &[&str] = &[
+    "arw", "cr2", "cr3", "dng", "nef", "nrw", "orf", "pef", "raf", "rw2", "srw",
+];
+
+/// Extensions of the HEIF pictures, e.g. those of iPhones. Their metadata is always read, but
+/// their pixels are coded with HEVC, which only the `heif` feature decodes.
+pub const HEIF_EXTENSIONS: &[&str] = &["heic", "heif", "hif"];
+
+/// Most IFDs of a TIFF file looked for previews in, so that offsets looping back to an IFD
+/// already read do not loop forever.
+const MAX_IFDS: usize = 64;
+
+/// The `uuid` of the box of CR3 files holding the preview of the picture.
+const CR3_PREVIEW_UUID: [u8; 16] = [
+    0xEA, 0xF4, 0x2B, 0x5E, 0x1C, 0x98, 0x4B, 0x88, 0xB9, 0xFB, 0xB7, 0xDC, 0x40, 0x6E, 0x4D, 0x16,
+];
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .is_some_and(|extension| {
+            extensions
+                .iter()
+                .any(|known| extension.eq_ignore_ascii_case(known))
+        })
+}
+
+/// Whether the file at `path` is a camera RAW picture, from its extension.
+pub fn is_raw(path: &Path) -> bool {
+    has_extension(path, RAW_EXTENSIONS)
+}
+
+/// Whether the file at `path` is a HEIF picture, from its extension.
+pub fn is_heif(path: &Path) -> bool {
+    has_extension(path, HEIF_EXTENSIONS)
+}
+
+/// The largest JPEG picture embedded in `bytes`, the contents of a camera RAW file, which is
+/// the preview of the picture the camera wrote next to the sensor data, at full or nearly full
+/// size for most cameras. The previews are found where the container says they are: the
+/// IFDs of the TIFF based formats, the header of Fujifilm's RAF files and the preview box of
+/// Canon's CR3 files.
+pub fn embedded_preview(bytes: &[u8]) -> Option<&[u8]> {
+    let previews = if bytes.starts_with(b"FUJIFILMCCD-RAW") {
+        raf_preview(bytes).into_iter().collect()
+    } else if bytes.get(4..12) == Some(b"ftypcrx ") {
+        cr3_preview(bytes).into_iter().collect()
+    } else {
+        tiff_previews(bytes)
+    };
+    previews
+        .into_iter()
+        .filter_map(|jpeg| {
+            let reader = ImageReader::with_format(Cursor::new(jpeg), ImageFormat::Jpeg);
+            let (width, height) = reader.into_dimensions().ok()?;
+            Some((u64::from(width) * u64::from(height), jpeg))
+        })
+        .max_by_key(|(pixels, _)| *pixels)
+        .map(|(_, jpeg)| jpeg)
+}
+
+/// The `length` bytes of `bytes` at `offset`, if they are those of a JPEG picture.
+fn jpeg_at(bytes: &[u8], offset: u32, length: u32) -> Option<&[u8]> {
+    let start = usize::try_from(offset).ok()?;
+    let end = start.checked_add(usize::try_from(length).ok()?)?;
+    bytes
+        .get(start..end)
+        .filter(|jpeg| jpeg.starts_with(&[0xFF, 0xD8, 0xFF]))
+}
+
+/// Reads the values of a TIFF file in its byte order.
+struct Tiff<'a> {
+    bytes: &'a [u8],
+    big_endian: bool,
+}
+
+impl Tiff<'_> {
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self
+            .bytes
+            .get(offset..offset.checked_add(2)?)?
+            .try_into()
+            .ok()?;
+        Some(match self.big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self
+            .bytes
+            .get(offset..offset.checked_add(4)?)?
+            .try_into()
+            .ok()?;
+        Some(match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    }
+
+    /// The `n`th value of the IFD entry at `entry`, a short or a long, stored in the entry
+    /// itself when they all fit in it, or at the offset it gives otherwise.
+    fn value(&self, entry: usize, n: u32) -> Option<u32> {
+        let (kind, count) = (self.u16(entry + 2)?, self.u32(entry + 4)?);
+        let size = match kind {
+            3 => 2,
+            4 | 13 => 4,
+            _ => return None,
+        };
+        if n >= count {
+            return None;
+        }
+        let values = match u64::from(count) * size <= 4 {
+            true => entry + 8,
+            false => usize::try_from(self.u32(entry + 8)?).ok()?,
+        };
+        let offset = values.checked_add(usize::try_from(u64::from(n) * size).ok()?)?;
+        match size {
+            2 => self.u16(offset).map(u32::from),
+            _ => self.u32(offset),
+        }
+    }
+}
+
+/// The JPEG pictures the IFDs of a TIFF based RAW file point to: the thumbnails and previews
+/// of the `JPEGInterchangeFormat` tags, the strips of the IFDs compressed as JPEG previews,
+/// e.g. the full size preview of CR2 files and the previews of DNG files, and the
+/// `JpgFromRaw` of Panasonic's RW2 files.
+fn tiff_previews(bytes: &[u8]) -> Vec<&[u8]> {
+    let big_endian = match bytes.get(..2) {
+        Some(b"II") => false,
+        Some(b"MM") => true,
+        _ => return Vec::new(),
+    };
+    let tiff = Tiff { bytes, big_endian };
+    // The magic number of TIFF, or of its ORF and RW2 variants
+    if !matches!(tiff.u16(2), Some(42 | 0x4F52 | 0x5352 | 0x55)) {
+        return Vec::new();
+    }
+    let mut previews = Vec::new();
+    let mut ifds: Vec<u32> = tiff.u32(4).into_iter().collect();
+    let mut visited = Vec::new();
+    while let Some(ifd) = ifds.pop() {
+        if ifd == 0 || visited.contains(&ifd) || visited.len() >= MAX_IFDS {
+            continue;
+        }
+        visited.push(ifd);
+        let Some(start) = usize::try_from(ifd).ok() else {
+            continue;
+        };
+        let Some(entries) = tiff.u16(start) else {
+            continue;
+        };
+        let (mut subfile_type, mut compression) = (0, 1);
+        let (mut strips, mut strip_lengths) = (None, None);
+        let (mut jpeg, mut jpeg_length) = (None, None);
+        for entry in (0..usize::from(entries)).map(|i| start + 2 + 12 * i) {
+            let Some(tag) = tiff.u16(entry) else {
+                break;
+            };
+            let single = tiff.u32(entry + 4) == Some(1);
+            match tag {
+                0x00FE => subfile_type = tiff.value(entry, 0).unwrap_or(0),
+                0x0103 => compression = tiff.value(entry, 0).unwrap_or(1),
+                0x0111 if single => strips = tiff.value(entry, 0),
+                0x0117 if single => strip_lengths = tiff.value(entry, 0),
+                0x0201 => jpeg = tiff.value(entry, 0),
+                0x0202 => jpeg_length = tiff.value(entry, 0),
+                0x002E => {
+                    let raw_preview = (tiff.u32(entry + 8), tiff.u32(entry + 4));
+                    if let (Some(offset), Some(length)) = raw_preview {
+                        previews.extend(jpeg_at(bytes, offset, length));
+                    }
+                }
+                0x014A => {
+                    let count = tiff.u32(entry + 4).unwrap_or(0);
+                    let sub_ifds = (0..count).map_while(|n| tiff.value(entry, n));
+                    ifds.extend(sub_ifds.take(MAX_IFDS));
+                }
+                _ => {}
+            }
+        }
+        if let (Some(offset), Some(length)) = (jpeg, jpeg_length) {
+            previews.extend(jpeg_at(bytes, offset, length));
+        }
+        // Old style JPEG, or JPEG in an IFD of reduced resolution, as the sensor data of
+        // some formats is compressed as lossless JPEG
+        let preview = compression == 6 || (compression == 7 && subfile_type & 1 == 1);
+        if let (true, Some(offset), Some(length)) = (preview, strips, strip_lengths) {
+            previews.extend(jpeg_at(bytes, offset, length));
+        }
+        let next = tiff.u32(start + 2 + 12 * usize::from(entries));
+        ifds.extend(next);
+    }
+    previews
+}
+
+/// The preview of a RAF file, whose header gives its offset and length.
+fn raf_preview(bytes: &[u8]) -> Option<&[u8]> {
+    let tiff = Tiff {
+        bytes,
+        big_endian: true,
+    };
+    jpeg_at(bytes, tiff.u32(84)?, tiff.u32(88)?)
+}
+
+/// The preview of a CR3 file, in the `PRVW` box of its preview `uuid` box.
+fn cr3_preview(bytes: &[u8]) -> Option<&[u8]> {
+    let tiff = Tiff {
+        bytes,
+        big_endian: true,
+    };
+    let mut offset = 0;
+    while offset + 8 <= bytes.len() {
+        let size = match tiff.u32(offset)? {
+            0 => bytes.len() - offset,
+            1 => {
+                let (high, low) = (tiff.u32(offset + 8)?, tiff.u32(offset + 12)?);
+                usize::try_from((u64::from(high) << 32) | u64::from(low)).ok()?
+            }
+            size => usize::try_from(size).ok()?,
+        };
+        if size < 8 {
+            return None;
+        }
+        let uuid = bytes.get(offset + 8..offset + 24);
+        if &bytes[offset + 4..offset + 8] == b"uuid" && uuid == Some(&CR3_PREVIEW_UUID[..]) {
+            // The PRVW box follows 8 bytes, and its JPEG picture its dimensions and length
+            let prvw = offset + 32;
+            if bytes.get(prvw + 4..prvw + 8) != Some(b"PRVW") {
+                return None;
+            }
+            let start = u32::try_from(prvw + 24).ok()?;
+            return jpeg_at(bytes, start, tiff.u32(prvw + 20)?);
+        }
+        offset = offset.checked_add(size)?;
+    }
+    None
+}
+
+/// Width and height of the picture at `path` whose contents are `bytes`: of the preview of
+/// camera RAW files, or of their sensor data with the `raw` feature, and of HEIF pictures with
+/// the `heif` feature, without decoding their pixels.
+pub fn dimensions(path: &Path, bytes: &[u8]) -> Option<(u32, u32)> {
+    if is_heif(path) {
+        heif_dimensions(bytes)
+    } else if is_raw(path) {
+        match embedded_preview(bytes) {
+            Some(preview) => {
+                let reader = ImageReader::with_format(Cursor::new(preview), ImageFormat::Jpeg);
+                reader.into_dimensions().ok()
+            }
+            None => raw_dimensions(bytes),
+        }
+    } else {
+        let reader = ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()
+            .ok()?;
+        reader.into_dimensions().ok()
+    }
+}
+
+/// Decode the picture at `path` from its contents `bytes`: camera RAW files from their
+/// embedded preview, or from their sensor data with the `raw` feature, HEIF pictures with the
+/// `heif` feature, and the formats the image crate reads with `decode_image`.
+pub fn decode(
+    path: &Path,
+    bytes: &[u8],
+    decode_image: impl FnOnce(&[u8]) -> Result<DynamicImage, ImageError>,
+) -> Result<DynamicImage, ImageError> {
+    if is_heif(path) {
+        decode_heif(bytes)
+    } else if is_raw(path) {
+        match embedded_preview(bytes) {
+            Some(preview) => decode_image(preview),
+            None => decode_raw(bytes),
+        }
+    } else {
+        decode_image(bytes)
+    }
+}
+
+/// Fail for pictures larger than the tiles decoded, before their pixels are allocated.
+#[cfg(any(feature = "heif", feature = "raw"))]
+fn check_size(width: u64, height: u64) -> Result<(), ImageError> {
+    if width * height > MAX_TILE_PIXELS {
+        return Err(ImageError::Limits(LimitError::from_kind(
+            LimitErrorKind::InsufficientMemory,
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(any(feature = "heif", feature = "raw"))]
+fn decoding(
+    format: &str,
+    error: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+) -> ImageError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Name(format.to_string()),
+        error,
+    ))
+}
+
+#[cfg(feature = "heif")]
+fn heif_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let context = libheif_rs::HeifContext::read_from_bytes(bytes).ok()?;
+    let handle = context.primary_image_handle().ok()?;
+    Some((handle.width(), handle.height()))
+}
+
+#[cfg(not(feature = "heif"))]
+fn heif_dimensions(_bytes: &[u8]) -> Option<(u32, u32)> {
+    None
+}
+
+/// Decode a HEIF picture with libheif, which applies its rotation and mirroring, so that the
+/// orientation of its EXIF data must not be applied again.
+#[cfg(feature = "heif")]
+fn decode_heif(bytes: &[u8]) -> Result<DynamicImage, ImageError> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let context = HeifContext::read_from_bytes(bytes).map_err(|e| decoding("HEIF", e))?;
+    let handle = context
+        .primary_image_handle()
+        .map_err(|e| decoding("HEIF", e))?;
+    check_size(u64::from(handle.width()), u64::from(handle.height()))?;
+    let image = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)
+        .map_err(|e| decoding("HEIF", e))?;
+    let plane = image
+        .planes()
+        .interleaved
+        .ok_or_else(|| unsupported("HEIF without RGB"))?;
+    // Rows may be padded past the pixels
+    let row = plane.width as usize * 3;
+    let pixels = plane
+        .data
+        .chunks(plane.stride)
+        .take(plane.height as usize)
+        .flat_map(|line| &line[..row])
+        .copied()
+        .collect();
+    let image = RgbImage::from_raw(plane.width, plane.height, pixels)
+        .ok_or_else(|| decoding("HEIF", "truncated image"))?;
+    Ok(DynamicImage::ImageRgb8(image))
+}
+
+#[cfg(not(feature = "heif"))]
+fn decode_heif(_bytes: &[u8]) -> Result<DynamicImage, ImageError> {
+    Err(unsupported("HEIF, without the heif feature"))
+}
+
+#[cfg(feature = "raw")]
+fn raw_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    // The dummy decoding reads the metadata but not the sensor data
+    let raw = rawloader::decode_dummy(&mut Cursor::new(bytes)).ok()?;
+    let [top, right, bottom, left] = raw.crops;
+    let width = raw.width.saturating_sub(left + right);
+    let height = raw.height.saturating_sub(top + bottom);
+    Some((u32::try_from(width).ok()?, u32::try_from(height).ok()?))
+}
+
+#[cfg(not(feature = "raw"))]
+fn raw_dimensions(_bytes: &[u8]) -> Option<(u32, u32)> {
+    None
+}
+
+/// Develop the sensor data of a camera RAW file with the default settings of imagepipe. The
+/// picture is not turned upright, as the orientation of its EXIF data is applied to it like
+/// to the previews.
+#[cfg(feature = "raw")]
+fn decode_raw(bytes: &[u8]) -> Result<DynamicImage, ImageError> {
+    use imagepipe::transform::OpTransform;
+    use imagepipe::{ImageSource, Pipeline, Rotation};
+
+    let raw = rawloader::decode(&mut Cursor::new(bytes)).map_err(|e| decoding("camera RAW", e))?;
+    check_size(raw.width as u64, raw.height as u64)?;
+    let mut pipeline =
+        Pipeline::new_from_source(ImageSource::Raw(raw)).map_err(|e| decoding("camera RAW", e))?;
+    pipeline.ops.transform = OpTransform {
+        rotation: Rotation::Normal,
+        fliph: false,
+        flipv: false,
+    };
+    let image = pipeline
+        .output_8bit(None)
+        .map_err(|e| decoding("camera RAW", e))?;
+    let (width, height) = (image.width as u32, image.height as u32);
+    let image = RgbImage::from_raw(width, height, image.data)
+        .ok_or_else(|| decoding("camera RAW", "truncated image"))?;
+    Ok(DynamicImage::ImageRgb8(image))
+}
+
+#[cfg(not(feature = "raw"))]
+fn decode_raw(_bytes: &[u8]) -> Result<DynamicImage, ImageError> {
+    Err(unsupported(
+        "camera RAW without a JPEG preview, without the raw feature",
+    ))
+}
+
+fn unsupported(format: &str) -> ImageError {
+    let format = ImageFormatHint::Name(format.to_string());
+    ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+        format.clone(),
+        UnsupportedErrorKind::Format(format),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        RgbImage::from_pixel(width, height, Rgb([200, 10, 10]))
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Jpeg)
+            .unwrap();
+        bytes
+    }
+
+    /// The entries of an IFD, of tag, type, count and value, and the index of the IFD after it.
+    type Ifd = (Vec<(u16, u16, u32, u32)>, Option<usize>);
+
+    /// A TIFF file with `data` after its header, followed by the `ifds`. The values of the
+    /// `JPEGInterchangeFormat` and `StripOffsets` tags are offsets in `data`, and those of the
+    /// `SubIFDs` tags indices of `ifds`.
+    fn tiff(big_endian: bool, data: &[u8], ifds: &[Ifd]) -> Vec<u8> {
+        let u16 = |value: u16| match big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        };
+        let u32 = |value: u32| match big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        };
+        let mut offsets = vec![8 + data.len() as u32];
+        for (entries, _) in ifds {
+            offsets.push(offsets.last().unwrap() + 6 + 12 * entries.len() as u32);
+        }
+        let mut bytes = match big_endian {
+            true => b"MM".to_vec(),
+            false => b"II".to_vec(),
+        };
+        bytes.extend(u16(42));
+        bytes.extend(u32(offsets[0]));
+        bytes.extend(data);
+        for (entries, next) in ifds {
+            bytes.extend(u16(entries.len() as u16));
+            for &(tag, kind, count, value) in entries {
+                let value = match tag {
+                    0x0111 | 0x0201 => value + 8,
+                    0x014A => offsets[value as usize],
+                    _ => value,
+                };
+                bytes.extend(u16(tag));
+                bytes.extend(u16(kind));
+                bytes.extend(u32(count));
+                match kind {
+                    3 => bytes.extend([u16(value as u16), [0, 0]].concat()),
+                    _ => bytes.extend(u32(value)),
+                }
+            }
+            bytes.extend(u32(next.map_or(0, |next| offsets[next])));
+        }
+        bytes
+    }
+
+    fn preview_dimensions(raw: &[u8]) -> Option<(u32, u32)> {
+        let image = image::load_from_memory(embedded_preview(raw)?).unwrap();
+        Some((image.width(), image.height()))
+    }
+
+    #[test]
+    fn test_embedded_preview() {
+        // A thumbnail and a preview amid sensor data, as in CR2 files, and a larger JPEG
+        // picture no IFD points to
+        let (thumbnail, preview, other) = (jpeg(8, 6), jpeg(64, 48), jpeg(128, 96));
+        let data = [&thumbnail[..], &[0x5A; 100], &preview, &other].concat();
+        let preview_at = (thumbnail.len() + 100) as u32;
+        let ifds = [
+            (
+                vec![
+                    (0x0103, 3, 1, 6),
+                    (0x0111, 4, 1, preview_at),
+                    (0x0117, 4, 1, preview.len() as u32),
+                ],
+                Some(1),
+            ),
+            (
+                vec![(0x0201, 4, 1, 0), (0x0202, 4, 1, thumbnail.len() as u32)],
+                None,
+            ),
+        ];
+        assert_eq!(
+            preview_dimensions(&tiff(false, &data, &ifds)),
+            Some((64, 48))
+        );
+
+        // Previews in sub IFDs, as in NEF and DNG files, the sensor data compressed as
+        // lossless JPEG left out
+        let ifds = [
+            (vec![(0x014A, 4, 1, 1)], Some(2)),
+            (
+                vec![
+                    (0x00FE, 4, 1, 1),
+                    (0x0103, 3, 1, 7),
+                    (0x0111, 4, 1, preview_at),
+                    (0x0117, 4, 1, preview.len() as u32),
+                ],
+                None,
+            ),
+            (
+                vec![
+                    (0x00FE, 4, 1, 0),
+                    (0x0103, 3, 1, 7),
+                    (0x0111, 4, 1, preview_at + preview.len() as u32),
+                    (0x0117, 4, 1, other.len() as u32),
+                ],
+                None,
+            ),
+        ];
+        assert_eq!(
+            preview_dimensions(&tiff(true, &data, &ifds)),
+            Some((64, 48))
+        );
+
+        // IFDs pointing back to themselves
+        let ifds = [(vec![(0x014A, 4, 1, 0)], Some(0))];
+        assert_eq!(preview_dimensions(&tiff(false, &data, &ifds)), None);
+
+        // Pictures are not looked for outside of the containers
+        assert_eq!(
+            embedded_preview(&[b"sensor".to_vec(), jpeg(16, 16)].concat()),
+            None
+        );
+        assert_eq!(embedded_preview(&[0x5A; 100]), None);
+    }
+
+    #[test]
+    fn test_raf_and_cr3_previews() {
+        let preview = jpeg(32, 24);
+        let mut raf = b"FUJIFILMCCD-RAW 0201FF383501".to_vec();
+        raf.resize(84, 0);
+        raf.extend(100u32.to_be_bytes());
+        raf.extend((preview.len() as u32).to_be_bytes());
+        raf.resize(100, 0);
+        raf.extend(&preview);
+        assert_eq!(preview_dimensions(&raf), Some((32, 24)));
+
+        let mut cr3 = [&24u32.to_be_bytes()[..], b"ftypcrx ", &[0; 12]].concat();
+        let size = 8 + 16 + 8 + 24 + preview.len() as u32;
+        cr3.extend(size.to_be_bytes());
+        cr3.extend(b"uuid");
+        cr3.extend(CR3_PREVIEW_UUID);
+        cr3.extend([0; 8]);
+        cr3.extend((24 + preview.len() as u32).to_be_bytes());
+        cr3.extend(b"PRVW");
+        cr3.extend([0, 0, 0, 0, 0, 1, 0, 32, 0, 24, 0, 1]);
+        cr3.extend((preview.len() as u32).to_be_bytes());
+        cr3.extend(&preview);
+        assert_eq!(preview_dimensions(&cr3), Some((32, 24)));
+    }
+
+    #[test]
+    fn test_decode() {
+        let preview = jpeg(16, 16);
+        let raw = tiff(
+            false,
+            &preview,
+            &[(
+                vec![(0x0201, 4, 1, 0), (0x0202, 4, 1, preview.len() as u32)],
+                None,
+            )],
+        );
+        let decoded = |path, bytes| decode(Path::new(path), bytes, image::load_from_memory);
+        assert_eq!(decoded("IMG_0001.CR2", &raw).unwrap().width(), 16);
+        assert_eq!(dimensions(Path::new("IMG_0001.CR2"), &raw), Some((16, 16)));
+        assert_eq!(decoded("a.jpg", &preview).unwrap().width(), 16);
+        assert_eq!(dimensions(Path::new("a.jpg"), &preview), Some((16, 16)));
+        assert!(decoded("IMG_0002.nef", b"sensor").is_err());
+        assert!(decoded("IMG_0003.HEIC", &raw).is_err());
+        assert!(is_heif(Path::new("IMG_0003.HEIC")));
+    }
+
+    #[cfg(not(feature = "heif"))]
+    #[test]
+    fn test_decode_heif_unsupported() {
+        let error = decode(
+            Path::new("IMG_0003.heic"),
+            b"ftypheic",
+            image::load_from_memory,
+        );
+        assert!(
+            matches!(error, Err(ImageError::Unsupported(_))),
+            "{:?}",
+            error
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashSet;
+
+use instant_distance::{Builder, HnswMap, Point, Search};
+use kiddo::NearestNeighbour;
+
+use super::tileset::TileIndex;
+use super::SIZE;
+
+/// Tile coordinates stored compactly as the color components they were built from.
+#[derive(Clone, Debug)]
+struct TilePoint(Box<[u8]>);
+
+impl TilePoint {
+    fn new<const K: usize>(coords: &[SIZE; K]) -> TilePoint {
+        TilePoint(coords.iter().map(|c| c.to_num::<u8>()).collect())
+    }
+
+    fn manhattan(&self, other: &TilePoint) -> u32 {
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .map(|(a, b)| u32::from(a.abs_diff(*b)))
+            .sum()
+    }
+}
+
+impl Point for TilePoint {
+    fn distance(&self, other: &Self) -> f32 {
+        self.manhattan(other) as f32
+    }
+}
+
+/// Candidates kept while searching the graph, and so the most items a search yields.
+const EF_SEARCH: usize = 100;
+
+/// Approximate nearest neighbour index backed by a Hierarchical Navigable Small World graph.
+///
+/// Much faster than the kd-tree for large, high dimensional tile sets, at the cost of
+/// occasionally missing the true nearest tile. The graph does not support deletion, so
+/// removed items are filtered out of search results instead.
+///
+/// A search of the graph yields at most [`EF_SEARCH`] items, fewer once some of them are
+/// removed: queries for more items than it yields, e.g. those of the greedy placement, are
+/// answered exactly by scanning every remaining item, as slow as a brute force index.
+pub struct HnswIndex<const K: usize> {
+    map: HnswMap<TilePoint, i32>,
+    live: HashSet<i32>,
+}
+
+impl<const K: usize> HnswIndex<K> {
+    /// Build the index from tile coordinates and their signed tile indices.
+    pub fn build(items: impl Iterator<Item = ([SIZE; K], i32)>) -> HnswIndex<K> {
+        let (points, values): (Vec<TilePoint>, Vec<i32>) = items
+            .map(|(coords, item)| (TilePoint::new(&coords), item))
+            .unzip();
+        let live = values.iter().copied().collect();
+        HnswIndex {
+            map: Builder::default()
+                .ef_search(EF_SEARCH)
+                .build(points, values),
+            live,
+        }
+    }
+
+    fn neighbour(query: &TilePoint, point: &TilePoint, item: i32) -> NearestNeighbour<SIZE, i32> {
+        NearestNeighbour {
+            distance: SIZE::from_num(query.manhattan(point)),
+            item,
+        }
+    }
+
+    /// Exhaustive search over the remaining items.
+    fn scan(&self, query: &TilePoint, n: usize) -> Vec<NearestNeighbour<SIZE, i32>> {
+        let mut nearest: Vec<_> = self
+            .map
+            .iter()
+            .zip(self.map.values.iter())
+            .filter(|(_, item)| self.live.contains(item))
+            .map(|((_, point), item)| Self::neighbour(query, point, *item))
+            .collect();
+        nearest.sort_by_key(|x| x.distance);
+        nearest.truncate(n);
+        nearest
+    }
+}
+
+impl<const K: usize> TileIndex<K> for HnswIndex<K> {
+    fn nearest_one(&self, query: &[SIZE; K]) -> NearestNeighbour<SIZE, i32> {
+        self.nearest_n(query, 1).pop().unwrap_or(NearestNeighbour {
+            distance: SIZE::MAX,
+            item: 0,
+        })
+    }
+
+    fn nearest_n(&self, query: &[SIZE; K], n: usize) -> Vec<NearestNeighbour<SIZE, i32>> {
+        let query = TilePoint::new(query);
+        let mut search = Search::default();
+        let mut nearest: Vec<_> = self
+            .map
+            .search(&query, &mut search)
+            .filter(|item| self.live.contains(item.value))
+            .take(n)
+            .map(|item| Self::neighbour(&query, item.point, *item.value))
+            .collect();
+        if nearest.len() < n.min(self.live.len()) {
+            // More items are wanted than the search yields, or its neighbourhood of the query
+            // has been removed
+            nearest = self.scan(&query, n);
+        }
+        nearest.sort_by_key(|x| x.distance);
+        nearest
+    }
+
+    fn remove(&mut self, _coords: &[SIZE; K], item: i32) -> usize {
+        usize::from(self.live.remove(&item))
+    }
+
+    fn size(&self) -> usize {
+        self.live.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mosaic::algorithms::BruteForceIndex;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn entries(count: i32) -> Vec<([SIZE; 12], i32)> {
+        let mut rng = StdRng::seed_from_u64(7);
+        (1..=count)
+            .map(|i| {
+                let mut coords = [SIZE::from_num(0); 12];
+                for c in coords.iter_mut() {
+                    *c = SIZE::from_num(rng.gen_range(0, 256));
+                }
+                (coords, i)
+            })
+            .collect()
+    }
+
+    fn distances(xs: &[NearestNeighbour<SIZE, i32>]) -> Vec<SIZE> {
+        xs.iter().map(|x| x.distance).collect()
+    }
+
+    #[test]
+    fn test_hnsw_matches_brute_force() {
+        // A set smaller than the candidates of a search is searched exhaustively
+        let entries = entries(60);
+        let mut hnsw = HnswIndex::build(entries.iter().copied());
+        let mut brute = BruteForceIndex::build(entries.iter().copied());
+        assert_eq!(hnsw.size(), 60);
+        for (query, _) in entries.iter().rev().take(20) {
+            let expected = brute.nearest_n(query, 5);
+            assert_eq!(distances(&hnsw.nearest_n(query, 5)), distances(&expected));
+            assert_eq!(hnsw.nearest_one(query).distance, expected[0].distance);
+        }
+
+        let (coords, item) = entries[0];
+        assert_eq!(hnsw.remove(&coords, item), 1);
+        assert_eq!(hnsw.remove(&coords, item), 0);
+        brute.remove(&coords, item);
+        assert_eq!(hnsw.size(), 59);
+        assert_ne!(hnsw.nearest_one(&coords).item, item);
+        assert_eq!(
+            distances(&hnsw.nearest_n(&coords, 5)),
+            distances(&brute.nearest_n(&coords, 5))
+        );
+    }
+
+    #[test]
+    fn test_hnsw_beyond_search() {
+        let entries = entries(300);
+        let mut hnsw = HnswIndex::build(entries.iter().copied());
+        let mut brute = BruteForceIndex::build(entries.iter().copied());
+        let query = entries[0].0;
+        // More items than a search yields
+        let nearest = hnsw.nearest_n(&query, 250);
+        assert_eq!(nearest.len(), 250);
+        assert_eq!(
+            distances(&nearest),
+            distances(&brute.nearest_n(&query, 250))
+        );
+
+        // Every item near the query removed
+        for neighbour in brute.nearest_n(&query, 200) {
+            let coords = entries[neighbour.item as usize - 1].0;
+            assert_eq!(hnsw.remove(&coords, neighbour.item), 1);
+            brute.remove(&coords, neighbour.item);
+        }
+        assert_eq!(hnsw.size(), 100);
+        assert_eq!(
+            distances(&hnsw.nearest_n(&query, 3)),
+            distances(&brute.nearest_n(&query, 3))
+        );
+
+        for (coords, item) in &entries {
+            hnsw.remove(coords, *item);
+        }
+        assert_eq!(hnsw.size(), 0);
+        assert!(hnsw.nearest_n(&query, 3).is_empty());
+        assert_eq!(hnsw.nearest_one(&query).item, 0);
+    }
+}
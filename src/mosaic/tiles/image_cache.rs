@@ -0,0 +1,152 @@
+use std::sync::{Arc, Mutex};
+
+use ::image::RgbImage;
+use lru::LruCache;
+
+use crate::mosaic::memory;
+
+/// Default memory budget for decoded tile images kept during rendering.
+pub const DEFAULT_IMAGE_CACHE_BYTES: usize = 512 * 1024 * 1024;
+
+/// Share of the memory budget of the run the decoded tile images may take at most.
+const MEMORY_BUDGET_SHARE: u64 = 4;
+
+/// Key of a decoded tile image: tile index, tile size and whether it is flipped.
+type ImageKey = (u32, u32, bool);
+
+/// An LRU cache of decoded tile images bounded by the memory used by their pixels.
+///
+/// Clones share the same cache, so it can be used from the parallel render loops.
+#[derive(Clone, Debug)]
+pub struct ImageCache(Arc<Mutex<Inner>>);
+
+#[derive(Debug)]
+struct Inner {
+    images: LruCache<ImageKey, RgbImage>,
+    bytes: usize,
+    budget: usize,
+    /// Number of lookups that found their image, and that did not
+    hits: u64,
+    misses: u64,
+}
+
+impl ImageCache {
+    /// Create an empty cache holding at most `budget` bytes of pixels, and no more than a
+    /// quarter of the `memory_budget` of the run, if any.
+    pub fn new(budget: usize, memory_budget: Option<u64>) -> ImageCache {
+        let budget = match memory_budget {
+            Some(memory) => budget.min((memory / MEMORY_BUDGET_SHARE) as usize),
+            None => budget,
+        };
+        ImageCache(Arc::new(Mutex::new(Inner {
+            images: LruCache::unbounded(),
+            bytes: 0,
+            budget,
+            hits: 0,
+            misses: 0,
+        })))
+    }
+
+    /// Get a copy of the cached image for `key`, marking it as recently used.
+    pub fn get(&self, key: ImageKey) -> Option<RgbImage> {
+        let mut inner = self.0.lock().unwrap();
+        let image = inner.images.get(&key).cloned();
+        match image {
+            Some(_) => inner.hits += 1,
+            None => inner.misses += 1,
+        }
+        image
+    }
+
+    /// Insert an image, evicting the least recently used ones to stay within budget. While the
+    /// memory used approaches the budget of the run, the cache shrinks instead.
+    pub fn insert(&self, key: ImageKey, image: RgbImage) {
+        let size = image.as_raw().len();
+        let mut inner = self.0.lock().unwrap();
+        if memory::under_pressure() {
+            if let Some((_, evicted)) = inner.images.pop_lru() {
+                inner.bytes -= evicted.as_raw().len();
+            }
+            return;
+        }
+        if size > inner.budget {
+            return;
+        }
+        if let Some(old) = inner.images.put(key, image) {
+            inner.bytes -= old.as_raw().len();
+        }
+        inner.bytes += size;
+        while inner.bytes > inner.budget {
+            match inner.images.pop_lru() {
+                Some((_, evicted)) => inner.bytes -= evicted.as_raw().len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Maximum number of bytes of pixels kept in the cache.
+    pub fn budget(&self) -> usize {
+        self.0.lock().unwrap().budget
+    }
+
+    /// Share of the lookups that found their image, if there was any.
+    pub fn hit_rate(&self) -> Option<f64> {
+        let inner = self.0.lock().unwrap();
+        let lookups = inner.hits + inner.misses;
+        (lookups > 0).then(|| inner.hits as f64 / lookups as f64)
+    }
+
+    /// Number of bytes of pixels currently cached.
+    #[cfg(test)]
+    pub fn bytes(&self) -> usize {
+        self.0.lock().unwrap().bytes
+    }
+}
+
+impl Default for ImageCache {
+    fn default() -> Self {
+        ImageCache::new(DEFAULT_IMAGE_CACHE_BYTES, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_cache_hit() {
+        let cache = ImageCache::new(1024, None);
+        cache.insert((1, 4, false), RgbImage::new(4, 4));
+        assert!(cache.get((1, 4, false)).is_some());
+        assert!(cache.get((1, 4, true)).is_none());
+        assert!(cache.get((1, 8, false)).is_none());
+        assert_eq!(cache.bytes(), 4 * 4 * 3);
+        assert_eq!(cache.hit_rate(), Some(1.0 / 3.0));
+        assert_eq!(ImageCache::new(1024, None).hit_rate(), None);
+    }
+
+    #[test]
+    fn test_image_cache_evicts_least_recently_used() {
+        // Room for two 4x4 images
+        let cache = ImageCache::new(2 * 4 * 4 * 3, None);
+        cache.insert((1, 4, false), RgbImage::new(4, 4));
+        cache.insert((2, 4, false), RgbImage::new(4, 4));
+        assert!(cache.get((1, 4, false)).is_some());
+        cache.insert((3, 4, false), RgbImage::new(4, 4));
+        assert!(cache.get((1, 4, false)).is_some());
+        assert!(cache.get((2, 4, false)).is_none());
+        assert!(cache.get((3, 4, false)).is_some());
+        assert_eq!(cache.bytes(), 2 * 4 * 4 * 3);
+    }
+
+    #[test]
+    fn test_image_cache_skips_oversized_images() {
+        let cache = ImageCache::new(10, None);
+        cache.insert((1, 4, false), RgbImage::new(4, 4));
+        assert!(cache.get((1, 4, false)).is_none());
+        assert_eq!(cache.bytes(), 0);
+        // At most a quarter of the memory budget of the run
+        assert_eq!(ImageCache::new(1024, Some(2048)).budget(), 512);
+        assert_eq!(ImageCache::new(1024, Some(1 << 20)).budget(), 1024);
+    }
+}
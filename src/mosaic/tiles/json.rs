@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use ::image::Rgb;
+use serde::{Deserialize, Serialize};
+
+use super::tile::Tile;
+use super::tileset::TileSet;
+use crate::mosaic::analysis::downsample;
+
+/// A tile of a JSON analysis file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AnalysedTile {
+    /// Path of the tile image, relative paths are resolved from the directory of the file
+    pub path: PathBuf,
+    /// Average `[r, g, b]` colors of a square grid over the tile, row by row
+    pub colors: Vec<[u8; 3]>,
+    /// When the picture was taken, as `YYYY:MM:DD` optionally followed by ` HH:MM:SS` and an
+    /// offset from UTC such as `+02:00`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    /// Caption of the picture
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+    /// Camera the picture was taken with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera: Option<String>,
+    /// Sharpness of the picture, the variance of its Laplacian
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sharpness: Option<f32>,
+}
+
+/// Tile analyses in a JSON format documented for external tools, which can inspect them or
+/// synthesize their own tiles and feed them back in instead of analysing a tiles directory.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TileAnalysis {
+    pub tiles: Vec<AnalysedTile>,
+}
+
+impl TileAnalysis {
+    /// The analyses of the tiles of `tile_set`.
+    pub fn from_tile_set<const N: usize>(tile_set: &TileSet<[Rgb<u8>; N]>) -> TileAnalysis {
+        let tiles = tile_set
+            .tiles
+            .iter()
+            .map(|tile| AnalysedTile {
+                path: tile_set.get_path(tile).to_path_buf(),
+                colors: tile.colors.iter().map(|rgb| rgb.0).collect(),
+                date: tile.date_taken.clone(),
+                caption: tile.caption.clone(),
+                camera: tile.camera.clone(),
+                sharpness: tile.sharpness,
+            })
+            .collect();
+        TileAnalysis { tiles }
+    }
+
+    /// A tile set for mode N, downsampling finer analyses and resolving relative paths
+    /// from `base_dir`.
+    ///
+    /// Fails when the colors of a tile are not a square grid at least as fine as N.
+    pub fn to_tile_set<const N: usize>(
+        &self,
+        base_dir: &Path,
+    ) -> Result<TileSet<[Rgb<u8>; N]>, String> {
+        let (tiles, paths) = self
+            .tiles
+            .iter()
+            .enumerate()
+            .map(|(i, tile)| {
+                let len = tile.colors.len();
+                let dim = (len as f64).sqrt() as usize;
+                if dim * dim != len || len < N {
+                    return Err(format!(
+                        "{}: {} colors, expected a square grid of at least {}",
+                        tile.path.display(),
+                        len,
+                        N
+                    ));
+                }
+                let colors: Vec<_> = tile.colors.iter().map(|&rgb| Rgb(rgb)).collect();
+                let idx = (i + 1) as u32;
+                let analysed =
+                    Tile::new_with_date(idx, downsample::<N>(&colors), tile.date.clone())
+                        .with_details(tile.caption.clone(), tile.camera.clone());
+                let analysed = Tile {
+                    sharpness: tile.sharpness,
+                    ..analysed
+                };
+                Ok((analysed, base_dir.join(&tile.path)))
+            })
+            .collect::<Result<(Vec<_>, Vec<_>), _>>()?;
+        Ok(TileSet::from_tiles(tiles, paths))
+    }
+
+    /// Read the analyses in the JSON file at `path`.
+    pub fn load(path: &Path) -> Result<TileAnalysis, std::io::Error> {
+        serde_json::from_reader(BufReader::new(File::open(path)?)).map_err(io::Error::from)
+    }
+
+    /// Write the analyses as compact JSON, as fine grids have many colors, to the file at
+    /// `path`.
+    pub fn save(&self, path: &Path) -> Result<(), std::io::Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tile_analysis_round_trip() {
+        let mut tile_set: TileSet<[Rgb<u8>; 4]> = TileSet::new();
+        tile_set.push_tile(PathBuf::from("/photos/a.jpg"), [Rgb([1, 2, 3]); 4]);
+        tile_set.tiles[0].date_taken = Some("2024:06:01".to_string());
+        tile_set.tiles[0].camera = Some("Canon EOS 5D".to_string());
+
+        let analysis = TileAnalysis::from_tile_set(&tile_set);
+        let json = serde_json::to_string(&analysis).unwrap();
+        assert_eq!(
+            json,
+            r#"{"tiles":[{"path":"/photos/a.jpg","colors":[[1,2,3],[1,2,3],[1,2,3],[1,2,3]],"date":"2024:06:01","camera":"Canon EOS 5D"}]}"#
+        );
+        let decoded: TileAnalysis = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, analysis);
+
+        let tile_set: TileSet<[Rgb<u8>; 4]> = decoded.to_tile_set(Path::new("/")).unwrap();
+        assert_eq!(tile_set.tiles[0].colors, [Rgb([1, 2, 3]); 4]);
+        assert_eq!(tile_set.tiles[0].date_taken.as_deref(), Some("2024:06:01"));
+        assert_eq!(tile_set.tiles[0].camera.as_deref(), Some("Canon EOS 5D"));
+        assert_eq!(
+            tile_set.get_path(&tile_set.tiles[0]),
+            Path::new("/photos/a.jpg")
+        );
+    }
+
+    #[test]
+    fn test_tile_analysis_synthesized() {
+        let json = r#"{"tiles": [
+            {"path": "red.png", "colors": [[255, 0, 0]]},
+            {"path": "split.png", "colors": [[0, 0, 0], [200, 0, 0], [0, 0, 0], [200, 0, 0]]}
+        ]}"#;
+        let analysis: TileAnalysis = serde_json::from_str(json).unwrap();
+
+        let tile_set = analysis.to_tile_set::<1>(Path::new("generated")).unwrap();
+        assert_eq!(tile_set.tiles[1].colors, [Rgb([100, 0, 0])]);
+        assert_eq!(
+            tile_set.get_path(&tile_set.tiles[0]),
+            Path::new("generated/red.png")
+        );
+        let err = analysis
+            .to_tile_set::<4>(Path::new("generated"))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "red.png: 1 colors, expected a square grid of at least 4"
+        );
+    }
+}
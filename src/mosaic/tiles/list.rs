@@ -0,0 +1,170 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::mosaic::error::EmosaicError;
+
+/// A tile image of a [`TileList`], with the weight and label given to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListedTile {
+    pub path: PathBuf,
+    /// Prior favoring the tile: its distances to a cell are divided by it
+    pub weight: f64,
+    /// Shown for the tile instead of the caption of its picture
+    pub label: Option<String>,
+}
+
+/// Tile images picked one by one from a text file instead of found in a tiles directory, e.g.
+/// a curated subset of a large library.
+///
+/// Every line is the path of an image, relative to the directory of the file unless absolute,
+/// optionally followed by a tab and a weight, and by another tab and a label. The weight may be
+/// left empty to give a label alone. Blank lines and lines starting with `#` are skipped.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TileList {
+    pub tiles: Vec<ListedTile>,
+}
+
+impl TileList {
+    /// Read the list at `path`.
+    pub fn load(path: &Path) -> Result<TileList, EmosaicError> {
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            EmosaicError::io(format!("Failed to read tiles list {}", path.display()), e)
+        })?;
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        TileList::parse(&contents, base_dir).map_err(|e| {
+            EmosaicError::Validation(format!("❌ Invalid tiles list {}: {}", path.display(), e))
+        })
+    }
+
+    /// Parse the lines of `contents`, with relative paths relative to `base_dir`.
+    fn parse(contents: &str, base_dir: &Path) -> Result<TileList, String> {
+        let mut tiles = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut columns = line.split('\t');
+            let path = columns.next().unwrap_or_default().trim();
+            let weight = match columns.next().map(str::trim) {
+                None | Some("") => 1.0,
+                Some(weight) => match weight.parse::<f64>() {
+                    Ok(weight) if weight.is_finite() && weight > 0.0 => weight,
+                    _ => {
+                        return Err(format!(
+                            "Invalid weight {:?} on line {}, expected a positive number",
+                            weight,
+                            number + 1
+                        ))
+                    }
+                },
+            };
+            let label = columns
+                .next()
+                .map(str::trim)
+                .filter(|label| !label.is_empty())
+                .map(String::from);
+            tiles.push(ListedTile {
+                path: base_dir.join(path),
+                weight,
+                label,
+            });
+        }
+        Ok(TileList { tiles })
+    }
+
+    /// Turn the paths of the tiles into absolute paths without links, dropping the tiles whose
+    /// image is missing and those listed before under another path.
+    ///
+    /// Returns the number of tiles whose image is missing.
+    pub fn resolve(&mut self) -> usize {
+        let mut seen = HashSet::new();
+        let mut missing = 0;
+        self.tiles
+            .retain_mut(|tile| match tile.path.canonicalize() {
+                Ok(path) => {
+                    tile.path = path;
+                    seen.insert(tile.path.clone())
+                }
+                Err(_) => {
+                    missing += 1;
+                    false
+                }
+            });
+        missing
+    }
+
+    /// Whether some tiles are given a weight other than the default.
+    pub fn is_weighted(&self) -> bool {
+        self.tiles.iter().any(|tile| tile.weight != 1.0)
+    }
+
+    /// The weight of every tile, by path.
+    pub fn weights(&self) -> HashMap<&Path, f64> {
+        let tiles = self.tiles.iter();
+        tiles
+            .map(|tile| (tile.path.as_path(), tile.weight))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tile_list() {
+        let contents = "# Best of 2024\n\
+                        2024/us.jpg\t2\tUs at the beach\n\
+                        \n\
+                        /photos/dog.jpg\r\n\
+                        2024/cake.jpg\t\tBirthday\n";
+        let list = TileList::parse(contents, Path::new("lists")).unwrap();
+        let listed = |path: &str, weight, label: Option<&str>| ListedTile {
+            path: PathBuf::from(path),
+            weight,
+            label: label.map(String::from),
+        };
+        assert_eq!(
+            list.tiles,
+            [
+                listed("lists/2024/us.jpg", 2.0, Some("Us at the beach")),
+                listed("/photos/dog.jpg", 1.0, None),
+                listed("lists/2024/cake.jpg", 1.0, Some("Birthday")),
+            ]
+        );
+        assert!(list.is_weighted());
+
+        let weights = list.weights();
+        assert_eq!(weights[Path::new("lists/2024/us.jpg")], 2.0);
+        assert_eq!(weights[Path::new("/photos/dog.jpg")], 1.0);
+
+        let error = TileList::parse("a.jpg\t0\n", Path::new("")).unwrap_err();
+        assert!(error.contains("line 1"), "{}", error);
+        assert!(TileList::parse("a.jpg\tmany\n", Path::new("")).is_err());
+    }
+
+    #[test]
+    fn test_resolve_tile_list() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::create_dir_all(dir.join("2024")).unwrap();
+        std::fs::write(dir.join("2024/us.jpg"), b"").unwrap();
+        let contents = "2024/us.jpg\t2\nmissing.jpg\n2024/../2024/us.jpg\t3\n";
+        let mut list = TileList::parse(contents, dir).unwrap();
+        assert_eq!(list.resolve(), 1);
+        // The first of the paths of the same image is kept
+        let path = dir.join("2024/us.jpg").canonicalize().unwrap();
+        assert_eq!(list.tiles.len(), 1);
+        assert_eq!((&list.tiles[0].path, list.tiles[0].weight), (&path, 2.0));
+
+        let list_path = dir.join("list.txt");
+        std::fs::write(&list_path, contents).unwrap();
+        assert_eq!(TileList::load(&list_path).unwrap().tiles.len(), 3);
+        std::fs::write(&list_path, "2024/us.jpg\tmany\n").unwrap();
+        let error = TileList::load(&list_path).unwrap_err();
+        assert!(matches!(error, EmosaicError::Validation(_)), "{}", error);
+        let error = TileList::load(&dir.join("missing.txt")).unwrap_err();
+        assert!(matches!(error, EmosaicError::Io { .. }), "{}", error);
+    }
+}
@@ -0,0 +1,25 @@
+use std::path::PathBuf;
+
+use super::filter::TileFilter;
+use crate::mosaic::ignore::Ignore;
+
+/// How the pictures of the tiles directories are found, filtered and analysed, and where their
+/// analyses are cached. The default finds every picture, without following the symbolic links
+/// to directories, and caches the analyses in the tiles directories themselves.
+#[derive(Clone, Debug, Default)]
+pub struct LibraryOptions {
+    /// Directory holding the analysis caches of every tiles directory, instead of the tiles
+    /// directories themselves, e.g. on read-only network shares
+    pub cache_root: Option<PathBuf>,
+    /// Pictures left out of every tiles directory, after the rules of its ignore file
+    pub excludes: Ignore,
+    /// Whether the symbolic links to directories are followed when looking for images, e.g.
+    /// into albums linked from elsewhere. The links to files are always followed
+    pub follow_symlinks: bool,
+    /// Pictures left out of the tiles for their dimensions or their sharpness
+    pub filter: TileFilter,
+    /// Number of crops taken from every picture analysed besides the whole picture
+    pub augment_crops: usize,
+    /// Resident memory the run is kept within, in bytes, if any
+    pub memory_budget: Option<u64>,
+}
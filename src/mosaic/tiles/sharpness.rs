@@ -0,0 +1,69 @@
+use image::{DynamicImage, GrayImage};
+
+/// Longest side the pictures are scaled down to before measuring their sharpness, so that the
+/// scores of pictures of any resolution compare.
+pub const SHARPNESS_SIZE: u32 = 512;
+
+/// Sharpness of `image`: the variance of the Laplacian of its luminance, once scaled down to
+/// fit [`SHARPNESS_SIZE`]. Blurry pictures have few edges and score low, typically below 100.
+pub fn sharpness(image: &DynamicImage) -> f32 {
+    let gray = if image.width().max(image.height()) > SHARPNESS_SIZE {
+        image.thumbnail(SHARPNESS_SIZE, SHARPNESS_SIZE).to_luma8()
+    } else {
+        image.to_luma8()
+    };
+    laplacian_variance(&gray)
+}
+
+/// Variance of the 4-neighbour Laplacian over the pixels of `gray` away from its borders.
+fn laplacian_variance(gray: &GrayImage) -> f32 {
+    let (width, height) = gray.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+    let luma = |x: u32, y: u32| f64::from(gray.get_pixel(x, y).0[0]);
+    let (mut sum, mut sum_squares) = (0.0, 0.0);
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let laplacian = luma(x - 1, y) + luma(x + 1, y) + luma(x, y - 1) + luma(x, y + 1)
+                - 4.0 * luma(x, y);
+            sum += laplacian;
+            sum_squares += laplacian * laplacian;
+        }
+    }
+    let count = f64::from((width - 2) * (height - 2));
+    let mean = sum / count;
+    (sum_squares / count - mean * mean) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::imageops::{self, FilterType};
+    use image::{Luma, Rgb, RgbImage};
+
+    #[test]
+    fn test_sharpness() {
+        let checkerboard = RgbImage::from_fn(64, 64, |x, y| {
+            if (x / 4 + y / 4) % 2 == 0 {
+                Rgb([0, 0, 0])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        });
+        let sharp = sharpness(&DynamicImage::ImageRgb8(checkerboard.clone()));
+        let small = imageops::resize(&checkerboard, 16, 16, FilterType::Triangle);
+        let blurred = imageops::resize(&small, 64, 64, FilterType::Triangle);
+        let blurry = sharpness(&DynamicImage::ImageRgb8(blurred));
+        assert!(
+            sharp > 1000.0 && blurry < sharp / 4.0,
+            "{} {}",
+            sharp,
+            blurry
+        );
+
+        let flat = GrayImage::from_pixel(2000, 1000, Luma([128]));
+        assert_eq!(sharpness(&DynamicImage::ImageLuma8(flat)), 0.0);
+        assert_eq!(sharpness(&DynamicImage::new_rgb8(2, 2)), 0.0);
+    }
+}
@@ -0,0 +1,97 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use super::tileset::TileSet;
+
+/// A directory of tile images, with the weight given to its tiles when matching.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TileSource {
+    pub path: PathBuf,
+    /// Prior favoring the tiles of this directory: their distances to a cell are divided by it
+    pub weight: f64,
+}
+
+impl TileSource {
+    /// A directory whose tiles are matched on their colors alone.
+    pub fn unweighted(path: PathBuf) -> TileSource {
+        TileSource { path, weight: 1.0 }
+    }
+}
+
+impl FromStr for TileSource {
+    type Err = String;
+
+    /// Parse a directory written as `path` or `path:weight`. A suffix that is not a number is
+    /// part of the path.
+    fn from_str(s: &str) -> Result<TileSource, String> {
+        let (path, weight) = match s.rsplit_once(':') {
+            Some((path, weight)) if !path.is_empty() => match weight.parse::<f64>() {
+                Ok(weight) if weight.is_finite() && weight > 0.0 => (path, weight),
+                Ok(_) => return Err(format!("Weight must be positive, got {}", weight)),
+                Err(_) => (s, 1.0),
+            },
+            _ => (s, 1.0),
+        };
+        Ok(TileSource {
+            path: PathBuf::from(path),
+            weight,
+        })
+    }
+}
+
+/// Index of the source of every tile, in tile order: the first source whose directory
+/// contains its path, or `None` for tiles of no source such as fillers.
+pub fn tile_sources<T>(tile_set: &TileSet<T>, sources: &[TileSource]) -> Vec<Option<usize>> {
+    tile_set
+        .paths()
+        .iter()
+        .map(|path| {
+            sources
+                .iter()
+                .position(|source| path.starts_with(&source.path))
+        })
+        .collect()
+}
+
+/// Weight of every tile, in tile order, by the source containing it. Tiles of no source
+/// weigh 1.
+pub fn tile_weights<T>(tile_set: &TileSet<T>, sources: &[TileSource]) -> Vec<f64> {
+    tile_sources(tile_set, sources)
+        .into_iter()
+        .map(|source| source.map_or(1.0, |source| sources[source].weight))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tile_source() {
+        let source: TileSource = "family:2".parse().unwrap();
+        assert_eq!(
+            source,
+            TileSource {
+                path: PathBuf::from("family"),
+                weight: 2.0
+            }
+        );
+        let source: TileSource = "photos/2024".parse().unwrap();
+        assert_eq!(source, TileSource::unweighted(PathBuf::from("photos/2024")));
+        let source: TileSource = "C:/photos".parse().unwrap();
+        assert_eq!(source, TileSource::unweighted(PathBuf::from("C:/photos")));
+        assert!("family:0".parse::<TileSource>().is_err());
+        assert!("family:-1".parse::<TileSource>().is_err());
+    }
+
+    #[test]
+    fn test_tile_weights() {
+        let mut tile_set = TileSet::<()>::new();
+        tile_set.push_tile(PathBuf::from("family/a.jpg"), ());
+        tile_set.push_tile(PathBuf::from("landscapes/b.jpg"), ());
+        tile_set.push_filler((), ::image::Rgb([0, 0, 0]));
+        let sources = ["family:2", "landscapes:0.5"].map(|s| s.parse().unwrap());
+        assert_eq!(tile_sources(&tile_set, &sources), [Some(0), Some(1), None]);
+        assert_eq!(tile_weights(&tile_set, &sources), [2.0, 0.5, 1.0]);
+    }
+}
@@ -5,14 +5,21 @@ use serde::ser::SerializeTuple;
 use serde::{Deserialize, Serialize};
 use super::utils::flipped_coords;
 use super::SIZE;
+use crate::mosaic::timestamp::Timestamp;
 
 /// Represents a single tile in a mosaic with its color data and metadata.
-#[derive(Clone, Debug, Eq)]
+#[derive(Clone, Debug)]
 pub struct Tile<T> {
     pub colors: T,
-    pub idx: u16,
+    pub idx: u32,
     pub flipped: bool,
     pub date_taken: Option<String>,
+    /// Caption of the picture, from its IPTC or EXIF data
+    pub caption: Option<String>,
+    /// Camera the picture was taken with
+    pub camera: Option<String>,
+    /// Sharpness of the picture, the variance of its Laplacian, when it was measured
+    pub sharpness: Option<f32>,
 }
 
 impl<T> PartialEq for Tile<T> {
@@ -21,6 +28,8 @@ impl<T> PartialEq for Tile<T> {
     }
 }
 
+impl<T: Eq> Eq for Tile<T> {}
+
 impl<T> Hash for Tile<T> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.idx.hash(state);
@@ -43,10 +52,13 @@ where
     where
         S: serde::Serializer,
     {
-        let mut st = serializer.serialize_tuple(3)?;
+        let mut st = serializer.serialize_tuple(6)?;
         st.serialize_element(&self.colors)?;
         st.serialize_element(&self.idx)?;
         st.serialize_element(&self.date_taken)?;
+        st.serialize_element(&self.caption)?;
+        st.serialize_element(&self.camera)?;
+        st.serialize_element(&self.sharpness)?;
         st.end()
     }
 }
@@ -59,8 +71,16 @@ where
     where
         D: serde::Deserializer<'de>,
     {
-        let (colors, idx, date_taken): (T, u16, Option<String>) = Deserialize::deserialize(deserializer)?;
-        Ok(Tile::new_with_date(idx, colors, date_taken))
+        let (colors, idx, date_taken, caption, camera, sharpness): (
+            T,
+            u32,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+            Option<f32>,
+        ) = Deserialize::deserialize(deserializer)?;
+        let tile = Tile::new_with_date(idx, colors, date_taken).with_details(caption, camera);
+        Ok(Tile { sharpness, ..tile })
     }
 }
 
@@ -71,25 +91,50 @@ impl<T> Tile<T> {
     }
     
     /// Create a new tile with the given index and colors.
-    pub(crate) fn new(idx: u16, colors: T) -> Tile<T> {
+    pub fn new(idx: u32, colors: T) -> Tile<T> {
         Tile {
             idx,
             colors,
             flipped: false,
             date_taken: None,
+            caption: None,
+            camera: None,
+            sharpness: None,
         }
     }
     
     /// Create a new tile with the given index, colors, and date.
-    pub(crate) fn new_with_date(idx: u16, colors: T, date_taken: Option<String>) -> Tile<T> {
+    pub fn new_with_date(idx: u32, colors: T, date_taken: Option<String>) -> Tile<T> {
         Tile {
             idx,
             colors,
             flipped: false,
             date_taken,
+            caption: None,
+            camera: None,
+            sharpness: None,
+        }
+    }
+
+    /// The tile with the caption and camera of its picture.
+    pub fn with_details(self, caption: Option<String>, camera: Option<String>) -> Tile<T> {
+        Tile {
+            caption,
+            camera,
+            ..self
         }
     }
 
+    /// When the picture was taken, parsed from its EXIF timestamp.
+    pub fn taken(&self) -> Option<Timestamp> {
+        Timestamp::parse(self.date_taken.as_deref()?)
+    }
+
+    /// Day the picture was taken on the wall clock of the camera, counted from 1970-01-01.
+    pub fn day_taken(&self) -> Option<i64> {
+        self.taken().map(|taken| taken.day())
+    }
+
     /// Transform the tile's colors using the provided function.
     pub fn map<T1>(self, f: impl FnOnce(T) -> T1) -> Tile<T1> {
         Tile {
@@ -97,6 +142,9 @@ impl<T> Tile<T> {
             idx: self.idx,
             flipped: self.flipped,
             date_taken: self.date_taken,
+            caption: self.caption,
+            camera: self.camera,
+            sharpness: self.sharpness,
         }
     }
 }
@@ -122,6 +170,12 @@ impl<const N: usize> Tile<[Rgb<u8>; N]> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mosaic::tiles::TileSet;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use proptest::test_runner::TestCaseError;
+    use std::convert::TryInto;
+    use std::path::PathBuf;
 
     #[test]
     fn test_tile_coords() {
@@ -138,4 +192,81 @@ mod tests {
         let coords = tile.coords();
         assert_eq!(coords, [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_day_taken() {
+        let day = |date: &str| Tile::new_with_date(1, (), Some(date.to_string())).day_taken();
+        assert_eq!(day("1970:01:01"), Some(0));
+        assert_eq!(day("2000:03:01"), Some(11017));
+        assert_eq!(day("2024:02:29"), Some(19782));
+        assert_eq!(day("2024:03:01"), Some(19783));
+        assert_eq!(day("2024:03:01 23:59:59+02:00"), Some(19783));
+        assert_eq!(day("2024:13:01"), None);
+        assert_eq!(day("0000:00:00"), None);
+        assert_eq!(day("unknown"), None);
+        assert_eq!(Tile::new(1, ()).day_taken(), None);
+    }
+
+    /// Check that `coords` flattens the colors (mirrored when flipped) and that
+    /// the coordinates survive a round-trip through the analysis cache format.
+    fn check_coords_round_trip<const N: usize>(
+        bytes: Vec<u8>,
+        flipped: bool,
+    ) -> Result<(), TestCaseError>
+    where
+        [(); N * 3]:,
+    {
+        let colors: [Rgb<u8>; N] = bytes
+            .chunks(3)
+            .map(|c| Rgb([c[0], c[1], c[2]]))
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap();
+        let tile = Tile {
+            flipped,
+            ..Tile::new(1, colors)
+        };
+
+        let mut expected = [SIZE::from(0u8); N * 3];
+        for (coord, byte) in expected.iter_mut().zip(bytes.iter()) {
+            *coord = (*byte).into();
+        }
+        if flipped {
+            flipped_coords(&mut expected);
+        }
+        prop_assert_eq!(tile.coords(), expected);
+
+        let mut tile_set: TileSet<[Rgb<u8>; N]> = TileSet::new();
+        tile_set.push_tile(PathBuf::from("tile.jpg"), colors);
+        let encoded = bincode::serialize(&tile_set).unwrap();
+        let decoded: TileSet<[Rgb<u8>; N]> = bincode::deserialize(&encoded).unwrap();
+        let decoded_tile = decoded.get_tile(if flipped { -1 } else { 1 }).unwrap();
+        prop_assert_eq!(decoded_tile.coords(), tile.coords());
+        Ok(())
+    }
+
+    macro_rules! coords_props {
+        ($($dim:literal => $n:literal),*) => {
+            paste::paste! {
+                proptest! {
+                    $(
+                        #[test]
+                        fn [<prop_coords_round_trip_ $dim x $dim>](
+                            bytes in vec(any::<u8>(), $n * 3),
+                            flipped in any::<bool>()
+                        ) {
+                            check_coords_round_trip::<$n>(bytes, flipped)?;
+                        }
+                    )*
+                }
+            }
+        };
+    }
+
+    coords_props!(
+        1 => 1, 2 => 4, 3 => 9, 4 => 16,
+        5 => 25, 6 => 36, 7 => 49, 8 => 64,
+        9 => 81, 10 => 100, 11 => 121, 12 => 144,
+        13 => 169, 14 => 196, 15 => 225, 16 => 256
+    );
+}
@@ -1,28 +1,122 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::iter::FromIterator;
 use std::path::{Path, PathBuf};
 
 use ::image::Rgb;
+use bincode::Options;
 use itertools::MultiUnzip;
 use rand::prelude::*;
 use rayon::iter::FromParallelIterator;
+use rayon::iter::IndexedParallelIterator;
 use rayon::iter::IntoParallelIterator;
+use rayon::iter::IntoParallelRefIterator;
 use rayon::iter::ParallelIterator;
+use serde::de::Error as _;
 use serde::ser::SerializeTuple;
 use serde::{Deserialize, Serialize};
+use tracing::debug_span;
 
+use kiddo::fixed::distance::Manhattan;
+use kiddo::fixed::kdtree::KdTree;
+use kiddo::NearestNeighbour;
+
+use super::hnsw::HnswIndex;
+use super::image_cache::ImageCache;
 use super::tile::Tile;
 use super::utils::{flipped_coords, prepare_tile};
 use super::SIZE;
+use crate::mosaic::algorithms::{BruteForceIndex, CompactKdTree};
+use crate::mosaic::analysis::downsample;
 use crate::mosaic::error::ImageError;
+use crate::mosaic::progress;
+
+/// Largest number of tiles in a set: tiles are numbered from 1, and the nearest neighbour
+/// indices store them as signed 32 bit items, negative for the flipped tiles.
+pub const MAX_TILES: usize = i32::MAX as usize;
+
+/// Nearest neighbour search backend used to match the source image against the tiles.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum IndexKind {
+    /// Brute force for small or high dimensional tile sets, kd-tree otherwise
+    #[default]
+    Auto,
+    /// Exact search using a fixed-point kd-tree
+    Kdtree,
+    /// Kd-tree with 16-bit coordinates, half the memory of `Kdtree` for huge tile sets
+    KdtreeCompact,
+    /// Exact search scanning every tile, fastest for small tile sets
+    Brute,
+    /// Approximate search using an HNSW graph, much faster for huge tile sets
+    Hnsw,
+}
+
+/// Indexes with fewer entries than this are always searched by brute force.
+const BRUTE_FORCE_MAX_ENTRIES: usize = 10_000;
+
+impl IndexKind {
+    /// Pick a concrete index kind for `entries` points of dimension `dims`.
+    ///
+    /// A kd-tree only prunes the search well when it holds many more points than 2^dims,
+    /// below that (or for small sets) a linear scan is faster.
+    pub fn resolve(self, entries: usize, dims: usize) -> IndexKind {
+        match self {
+            IndexKind::Auto => {
+                let kdtree_pays_off = dims < usize::BITS as usize && entries > 1 << dims;
+                if entries < BRUTE_FORCE_MAX_ENTRIES || !kdtree_pays_off {
+                    IndexKind::Brute
+                } else {
+                    IndexKind::Kdtree
+                }
+            }
+            kind => kind,
+        }
+    }
+}
+
+/// A nearest neighbour index over tile coordinates.
+///
+/// Items are signed tile indices (negative for flipped tiles) and distances are Manhattan.
+pub trait TileIndex<const K: usize>: Send + Sync {
+    /// Find the item nearest to `query`.
+    fn nearest_one(&self, query: &[SIZE; K]) -> NearestNeighbour<SIZE, i32>;
+    /// Find up to `n` items nearest to `query`, sorted by increasing distance.
+    fn nearest_n(&self, query: &[SIZE; K], n: usize) -> Vec<NearestNeighbour<SIZE, i32>>;
+    /// Remove `item` stored at `coords`, returning the number of entries removed.
+    fn remove(&mut self, coords: &[SIZE; K], item: i32) -> usize;
+    /// Number of items in the index.
+    fn size(&self) -> usize;
+}
+
+impl<const K: usize> TileIndex<K> for KdTree<SIZE, i32, K, 640, u32> {
+    fn nearest_one(&self, query: &[SIZE; K]) -> NearestNeighbour<SIZE, i32> {
+        KdTree::nearest_one::<Manhattan>(self, query)
+    }
+
+    fn nearest_n(&self, query: &[SIZE; K], n: usize) -> Vec<NearestNeighbour<SIZE, i32>> {
+        KdTree::nearest_n::<Manhattan>(self, query, n)
+    }
+
+    fn remove(&mut self, coords: &[SIZE; K], item: i32) -> usize {
+        KdTree::remove(self, coords, item)
+    }
+
+    fn size(&self) -> usize {
+        KdTree::size(self) as usize
+    }
+}
 
 /// A collection of tiles used for mosaic generation.
 #[derive(Clone, Debug)]
 pub struct TileSet<T> {
     pub tiles: Vec<Tile<T>>,
     paths: Vec<PathBuf>,
-    images: HashMap<u16, ::image::ImageBuffer<Rgb<u8>, Vec<u8>>>,
+    images: HashMap<u32, ::image::ImageBuffer<Rgb<u8>, Vec<u8>>>,
+    /// Colors of the synthetic filler tiles, which have no image file
+    fillers: HashMap<u32, Rgb<u8>>,
+    write_cache: bool,
+    image_cache: ImageCache,
+    memory_budget: Option<u64>,
 }
 
 impl<const N: usize> Serialize for TileSet<[Rgb<u8>; N]> {
@@ -30,19 +124,8 @@ impl<const N: usize> Serialize for TileSet<[Rgb<u8>; N]> {
     where
         S: serde::Serializer,
     {
-        let colors: Vec<Tile<Vec<u8>>> = self
-            .tiles
-            .iter()
-            .map(|tile| {
-                tile.clone().map(|rgbs| {
-                    rgbs.iter()
-                        .flat_map(|rgb| [rgb[0], rgb[1], rgb[2]])
-                        .collect()
-                })
-            })
-            .collect::<Vec<_>>();
         let mut st = serializer.serialize_tuple(2)?;
-        st.serialize_element(&colors)?;
+        st.serialize_element(&self.raw_colors())?;
         st.serialize_element(&self.paths)?;
         st.end()
     }
@@ -55,21 +138,84 @@ impl<'de, const N: usize> Deserialize<'de> for TileSet<[Rgb<u8>; N]> {
     {
         let (colors, paths): (Vec<Tile<Vec<u8>>>, Vec<PathBuf>) =
             Deserialize::deserialize(deserializer)?;
-        let tiles: Vec<Tile<[Rgb<u8>; N]>> = colors
+        TileSet::from_raw(colors, paths).map_err(D::Error::custom)
+    }
+}
+
+impl<const N: usize> TileSet<[Rgb<u8>; N]> {
+    /// Decode a tile set from the bytes of an analysis cache file.
+    ///
+    /// Fails instead of panicking on corrupt input or on a cache produced for a different N,
+    /// and never allocates more than the size of the input.
+    pub fn from_cache_bytes(bytes: &[u8]) -> bincode::Result<Self> {
+        super::cache::cache_options(bytes.len()).deserialize(bytes)
+    }
+
+    /// Stand in for the image of every tile with its analysis, a sqrt(N)xsqrt(N) image of
+    /// its colors, so a mosaic can be matched and previewed without loading any tile image.
+    pub fn use_analysis_images(&mut self) {
+        let dim = (N as f64).sqrt() as u32;
+        for tile in &self.tiles {
+            let image =
+                image::ImageBuffer::from_fn(dim, dim, |x, y| tile.colors[(y * dim + x) as usize]);
+            self.images.insert(tile.idx, image);
+        }
+    }
+
+    /// The tiles with their colors flattened to RGB components, as stored in caches.
+    pub(crate) fn raw_colors(&self) -> Vec<Tile<Vec<u8>>> {
+        self.tiles
+            .iter()
+            .map(|tile| {
+                tile.clone().map(|rgbs| {
+                    rgbs.iter()
+                        .flat_map(|rgb| [rgb[0], rgb[1], rgb[2]])
+                        .collect()
+                })
+            })
+            .collect()
+    }
+
+    /// Build a tile set from tiles whose colors are flattened RGB components.
+    pub(crate) fn from_raw(
+        colors: Vec<Tile<Vec<u8>>>,
+        paths: Vec<PathBuf>,
+    ) -> Result<Self, String> {
+        if colors.len() != paths.len() {
+            return Err(format!(
+                "tile set has {} tiles but {} paths",
+                colors.len(),
+                paths.len()
+            ));
+        }
+        let tiles = colors
             .into_iter()
             .map(|tile| {
-                let colors: Vec<Rgb<u8>> = tile
-                    .colors
-                    .chunks(3)
-                    .map(|chunk| Rgb([chunk[0], chunk[1], chunk[2]]))
-                    .collect();
-                let colors_array: [Rgb<u8>; N] = colors.try_into().unwrap();
-                Tile {
+                if tile.colors.len() != N * 3 {
+                    return Err(format!(
+                        "invalid length {}, expected {} color components (analysis for N={})",
+                        tile.colors.len(),
+                        N * 3,
+                        N
+                    ));
+                }
+                if tile.idx == 0 || tile.idx as usize > paths.len() {
+                    return Err(format!(
+                        "tile index {} out of range 1..={}",
+                        tile.idx,
+                        paths.len()
+                    ));
+                }
+                let mut colors_array = [Rgb([0, 0, 0]); N];
+                for (color, chunk) in colors_array.iter_mut().zip(tile.colors.chunks_exact(3)) {
+                    *color = Rgb([chunk[0], chunk[1], chunk[2]]);
+                }
+                Ok(Tile {
                     colors: colors_array,
                     ..tile
-                }
+                })
             })
-            .collect();
+            .collect::<Result<Vec<Tile<[Rgb<u8>; N]>>, String>>()?;
         Ok(TileSet::from_tiles(tiles, paths))
     }
 }
@@ -85,7 +231,11 @@ impl<T> TileSet<T> {
         TileSet::<T> {
             tiles,
             paths,
-            images: HashMap::new().into(),
+            images: HashMap::new(),
+            fillers: HashMap::new(),
+            write_cache: true,
+            image_cache: ImageCache::default(),
+            memory_budget: None,
         }
     }
 
@@ -101,6 +251,11 @@ impl<T> TileSet<T> {
         self.tiles.len()
     }
 
+    /// Check whether the set contains no tiles.
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
     #[allow(dead_code)]
     pub fn map<T1>(self, f: fn(T) -> T1) -> TileSet<T1> {
         let tiles = self.tiles.into_iter().map(|t| t.map(f)).collect();
@@ -109,7 +264,7 @@ impl<T> TileSet<T> {
 
     /// Add a new tile to the set.
     pub fn push_tile(&mut self, path: PathBuf, colors: T) {
-        let idx = self.tiles.len() as u16 + 1;
+        let idx = self.tiles.len() as u32 + 1;
         self.tiles.push(Tile::new(idx, colors));
         self.paths.push(path);
     }
@@ -121,43 +276,111 @@ impl<T> TileSet<T> {
         colors: T,
         image: ::image::ImageBuffer<Rgb<u8>, Vec<u8>>,
     ) {
-        let idx = self.tiles.len() as u16 + 1;
+        let idx = self.tiles.len() as u32 + 1;
         self.tiles.push(Tile::new(idx, colors));
         self.paths.push(path_buf);
-        self.images.insert(idx, image.into());
+        self.images.insert(idx, image);
+    }
+
+    /// Add a synthetic tile of a flat `color`, rendered on the fly rather than loaded from a
+    /// file, for cells that no tile matches well. Returns its index.
+    pub fn push_filler(&mut self, colors: T, color: Rgb<u8>) -> u32 {
+        let [r, g, b] = color.0;
+        let path = format!("filler #{:02x}{:02x}{:02x}", r, g, b);
+        let idx = self.tiles.len() as u32 + 1;
+        self.tiles.push(Tile::new(idx, colors));
+        self.paths.push(PathBuf::from(path));
+        self.fillers.insert(idx, color);
+        idx
+    }
+
+    /// The color of `tile` if it is a synthetic filler.
+    pub fn filler_color<A>(&self, tile: &Tile<A>) -> Option<Rgb<u8>> {
+        self.fillers.get(&tile.idx).copied()
     }
 
     /// Get a tile by its index (positive for normal, negative for flipped).
-    pub fn get_tile(&self, idx: i16) -> Option<Tile<T>>
+    pub fn get_tile(&self, idx: i32) -> Option<Tile<T>>
     where
         T: Copy,
     {
-        let tile = self.tiles.get(idx.abs() as usize - 1).map(|tile| Tile {
-            colors: tile.colors,
-            idx: tile.idx,
-            flipped: idx < 0,
-            date_taken: tile.date_taken.clone(),
-        });
-        assert!(tile.as_ref().map_or(true, |t| t.idx == idx.abs() as u16));
+        let tile = self
+            .tiles
+            .get(idx.unsigned_abs() as usize - 1)
+            .map(|tile| Tile {
+                colors: tile.colors,
+                idx: tile.idx,
+                flipped: idx < 0,
+                date_taken: tile.date_taken.clone(),
+                caption: tile.caption.clone(),
+                camera: tile.camera.clone(),
+                sharpness: tile.sharpness,
+            });
+        assert!(tile.as_ref().is_none_or(|t| t.idx == idx.unsigned_abs()));
         tile
     }
 
     /// Get the image for a tile, loading it if necessary.
+    ///
+    /// Images loaded from disk are kept in an in-memory LRU cache, so tiles placed many
+    /// times are only decoded once.
     pub fn get_image(
         &self,
         tile: &Tile<T>,
         tile_size: u32,
     ) -> Result<image::ImageBuffer<Rgb<u8>, Vec<u8>>, ImageError> {
-        let path = self.get_path(tile);
-        let image = self
-            .images
-            .get(&tile.idx)
-            .map_or_else(|| prepare_tile(path, tile_size, true), |x| Ok(x.clone()))?;
-        Ok(if tile.flipped {
+        if let Some(&color) = self.fillers.get(&tile.idx) {
+            return Ok(image::ImageBuffer::from_pixel(tile_size, tile_size, color));
+        }
+        if let Some(image) = self.images.get(&tile.idx) {
+            return Ok(if tile.flipped {
+                image::imageops::flip_horizontal(image)
+            } else {
+                image.clone()
+            });
+        }
+        let key = (tile.idx, tile_size, tile.flipped);
+        if let Some(image) = self.image_cache.get(key) {
+            return Ok(image);
+        }
+        progress::set_current_file(self.get_path(tile));
+        let image = prepare_tile(self.get_path(tile), tile_size, true, self.write_cache)?;
+        let image = if tile.flipped {
             image::imageops::flip_horizontal(&image)
         } else {
             image
-        })
+        };
+        self.image_cache.insert(key, image.clone());
+        Ok(image)
+    }
+
+    /// Limit the memory used by decoded tile images kept during rendering.
+    pub fn set_image_cache_budget(&mut self, bytes: usize) {
+        self.image_cache = ImageCache::new(bytes, self.memory_budget);
+    }
+
+    /// Keep the mosaics rendered with the tile set within `bytes` of resident memory, if given:
+    /// the decoded tile images kept are capped, the rows of the mosaic are composited as soon
+    /// as they are rendered, and fewer matches are scored at once.
+    pub fn set_memory_budget(&mut self, bytes: Option<u64>) {
+        self.memory_budget = bytes;
+        self.set_image_cache_budget(self.image_cache.budget());
+    }
+
+    /// The memory budget given to [`TileSet::set_memory_budget`], in bytes.
+    pub fn memory_budget(&self) -> Option<u64> {
+        self.memory_budget
+    }
+
+    /// Share of the tile images loaded so far that were found in the cache of decoded images,
+    /// if any was loaded.
+    pub fn image_cache_hit_rate(&self) -> Option<f64> {
+        self.image_cache.hit_rate()
+    }
+
+    /// Paths of all the tiles, in index order.
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
     }
 
     /// Get the file path for a tile.
@@ -165,6 +388,74 @@ impl<T> TileSet<T> {
         self.paths[tile.idx as usize - 1].as_path()
     }
 
+    /// Control whether tiles prepared on demand are saved to the tile cache.
+    pub fn set_write_cache(&mut self, write_cache: bool) {
+        self.write_cache = write_cache;
+    }
+
+    /// Keep a random sample of at most `count` tiles, renumbering them sequentially.
+    pub fn sample(self, count: usize) -> TileSet<T> {
+        if self.tiles.len() <= count {
+            return self;
+        }
+        let mut indices: Vec<usize> = (0..self.tiles.len()).collect();
+        indices.shuffle(&mut thread_rng());
+        indices.truncate(count);
+        self.select(indices)
+    }
+
+    /// Keep the tiles whose path satisfies `keep`, renumbering them sequentially.
+    ///
+    /// Returns the filtered tile set and the number of tiles discarded.
+    pub fn retain_paths(self, keep: impl Fn(&Path) -> bool) -> (TileSet<T>, usize) {
+        let indices: Vec<usize> = (0..self.tiles.len())
+            .filter(|&i| keep(&self.paths[i]))
+            .collect();
+        let discarded = self.tiles.len() - indices.len();
+        (self.select(indices), discarded)
+    }
+
+    /// Add all the tiles of `other` after the tiles of this set, renumbering them
+    /// sequentially.
+    pub fn append(&mut self, other: TileSet<T>) {
+        let offset = self.tiles.len() as u32;
+        for tile in other.tiles {
+            let idx = tile.idx + offset;
+            self.tiles.push(Tile { idx, ..tile });
+        }
+        self.paths.extend(other.paths);
+        let images = other.images.into_iter();
+        self.images
+            .extend(images.map(|(idx, image)| (idx + offset, image)));
+        let fillers = other.fillers.into_iter();
+        self.fillers
+            .extend(fillers.map(|(idx, color)| (idx + offset, color)));
+    }
+
+    /// Keep the tiles at `indices`, renumbering them sequentially in index order.
+    fn select(self, mut indices: Vec<usize>) -> TileSet<T> {
+        indices.sort_unstable();
+        let mut tiles: Vec<Option<Tile<T>>> = self.tiles.into_iter().map(Some).collect();
+        let mut images = self.images;
+        let mut sampled = TileSet::from_tiles(vec![], vec![]);
+        sampled.write_cache = self.write_cache;
+        sampled.memory_budget = self.memory_budget;
+        sampled.set_image_cache_budget(self.image_cache.budget());
+        for index in indices {
+            let tile = tiles[index].take().unwrap();
+            let idx = sampled.tiles.len() as u32 + 1;
+            if let Some(image) = images.remove(&tile.idx) {
+                sampled.images.insert(idx, image);
+            }
+            if let Some(color) = self.fillers.get(&tile.idx) {
+                sampled.fillers.insert(idx, *color);
+            }
+            sampled.paths.push(self.paths[index].clone());
+            sampled.tiles.push(Tile { idx, ..tile });
+        }
+        sampled
+    }
+
     #[allow(dead_code)]
     pub fn set_image(&mut self, tile: &Tile<T>, image: ::image::ImageBuffer<Rgb<u8>, Vec<u8>>) {
         self.images.insert(tile.idx, image);
@@ -175,19 +466,124 @@ impl<const N: usize> TileSet<[Rgb<u8>; N]>
 //   where T: Copy, T: Default
 {
     /// Build a kd-tree for fast nearest neighbor searches.
-    pub fn build_kiddo(&self) -> kiddo::fixed::kdtree::KdTree<SIZE, i16, { N * 3 }, 640, u16> {
-        let mut kd = kiddo::fixed::kdtree::KdTree::new();
-        for tile in self.tiles.iter() {
-            let mut coords = tile.coords();
-            let idx: i16 = tile.idx.try_into().unwrap();
-            assert!(idx != 0);
+    pub fn build_kiddo(&self) -> KdTree<SIZE, i32, { N * 3 }, 640, u32>
+    where
+        [(); N * 3]:,
+    {
+        let mut kd = KdTree::new();
+        for (coords, idx) in self.index_entries() {
             kd.add(&coords, idx);
-            flipped_coords(&mut coords);
-            assert!(-idx != 0);
-            kd.add(&coords, -idx);
         }
         kd
     }
+
+    /// Discard the tiles whose average color is farther than `max_distance` (summed over the
+    /// RGB channels) from the average color of every block in `blocks`, as they can only
+    /// be poor matches. The `min_tiles` closest tiles, and the tiles whose path satisfies
+    /// `keep`, are always kept.
+    ///
+    /// Returns the pruned tile set and the number of tiles discarded.
+    pub fn prune_far_from(
+        self,
+        blocks: &[Rgb<u8>],
+        max_distance: u32,
+        min_tiles: usize,
+        keep: impl Fn(&Path) -> bool + Sync,
+    ) -> (TileSet<[Rgb<u8>; N]>, usize) {
+        let mut index: KdTree<SIZE, u32, 3, 640, u32> = KdTree::new();
+        let unique: HashSet<[u8; 3]> = blocks.iter().map(|block| block.0).collect();
+        for (i, color) in unique.iter().enumerate() {
+            index.add(&color.map(SIZE::from_num), i as u32);
+        }
+        if index.size() == 0 {
+            return (self, 0);
+        }
+
+        let mut distances: Vec<(u32, usize)> = self
+            .tiles
+            .par_iter()
+            .enumerate()
+            .map(|(i, tile)| {
+                if keep(&self.paths[i]) {
+                    return (0, i);
+                }
+                let color = downsample::<1>(&tile.colors)[0].0.map(SIZE::from_num);
+                let nearest = index.nearest_one::<Manhattan>(&color);
+                (nearest.distance.to_num(), i)
+            })
+            .collect();
+        distances.sort_unstable();
+        let near = distances.partition_point(|&(distance, _)| distance <= max_distance);
+        let keep = near.max(min_tiles).min(distances.len());
+        let pruned = distances.len() - keep;
+        let indices = distances[..keep].iter().map(|&(_, i)| i).collect();
+        (self.select(indices), pruned)
+    }
+
+    /// Build a nearest neighbor index of the requested kind.
+    pub fn build_index(&self, kind: IndexKind) -> Box<dyn TileIndex<{ N * 3 }>>
+    where
+        [(); N * 3]:,
+    {
+        let _span = debug_span!("index", tiles = self.len()).entered();
+        // Every tile is indexed twice, as is and flipped
+        match kind.resolve(self.len() * 2, N * 3) {
+            IndexKind::Auto | IndexKind::Kdtree => Box::new(self.build_kiddo()),
+            IndexKind::KdtreeCompact => Box::new(CompactKdTree::build(self.index_entries())),
+            IndexKind::Brute => Box::new(BruteForceIndex::build(self.index_entries())),
+            IndexKind::Hnsw => Box::new(HnswIndex::build(self.index_entries())),
+        }
+    }
+
+    /// Build a nearest neighbor index of the requested kind over the tiles at `positions` in
+    /// `tiles` only, matching the same signed indices as the full index.
+    pub fn build_index_of(
+        &self,
+        kind: IndexKind,
+        positions: &[usize],
+    ) -> Box<dyn TileIndex<{ N * 3 }>>
+    where
+        [(); N * 3]:,
+    {
+        let entries = || tile_entries(positions.iter().map(|&i| &self.tiles[i]));
+        match kind.resolve(positions.len() * 2, N * 3) {
+            IndexKind::Auto | IndexKind::Kdtree => {
+                let mut kd: KdTree<SIZE, i32, { N * 3 }, 640, u32> = KdTree::new();
+                for (coords, idx) in entries() {
+                    kd.add(&coords, idx);
+                }
+                Box::new(kd)
+            }
+            IndexKind::KdtreeCompact => Box::new(CompactKdTree::build(entries())),
+            IndexKind::Brute => Box::new(BruteForceIndex::build(entries())),
+            IndexKind::Hnsw => Box::new(HnswIndex::build(entries())),
+        }
+    }
+
+    /// Coordinates of every tile, both as is and flipped, with their signed indices.
+    fn index_entries(&self) -> impl Iterator<Item = ([SIZE; N * 3], i32)> + '_
+    where
+        [(); N * 3]:,
+    {
+        tile_entries(self.tiles.iter())
+    }
+}
+
+/// Coordinates of the given tiles, both as is and flipped, with their signed indices.
+fn tile_entries<'a, const N: usize>(
+    tiles: impl Iterator<Item = &'a Tile<[Rgb<u8>; N]>> + 'a,
+) -> impl Iterator<Item = ([SIZE; N * 3], i32)> + 'a
+where
+    [(); N * 3]:,
+{
+    tiles.flat_map(|tile| {
+        let coords = tile.coords();
+        let idx: i32 = tile.idx.try_into().unwrap();
+        assert!(idx != 0);
+        let mut flipped = coords;
+        flipped_coords(&mut flipped);
+        [(coords, idx), (flipped, -idx)]
+    })
 }
 
 impl<T> Default for TileSet<T> {
@@ -220,7 +616,7 @@ impl<T> FromIterator<(PathBuf, ::image::ImageBuffer<Rgb<u8>, Vec<u8>>, T)> for T
                 (
                     path,
                     Tile::new((idx + 1).try_into().unwrap(), color),
-                    ((idx + 1) as u16, img),
+                    ((idx + 1) as u32, img),
                 )
             })
             .multiunzip();
@@ -228,6 +624,10 @@ impl<T> FromIterator<(PathBuf, ::image::ImageBuffer<Rgb<u8>, Vec<u8>>, T)> for T
             tiles,
             images,
             paths,
+            fillers: HashMap::new(),
+            write_cache: true,
+            image_cache: ImageCache::default(),
+            memory_budget: None,
         }
     }
 }
@@ -1,6 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Seek};
 use std::ops::Div;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
 
 use ::image::imageops;
 use ::image::Rgb;
@@ -8,11 +12,18 @@ use exif::In;
 use exif::Tag;
 use image::error::LimitError;
 use image::imageops::FilterType;
-use image::DynamicImage;
+use image::{DynamicImage, ImageFormat, ImageReader, Limits};
 use num_integer::Roots;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use std::ops::Deref;
+use tracing::warn;
 
+use super::atomic::write_atomic;
+use super::crops::{split_crop, CropRegion};
+use super::formats;
+use super::sharpness::sharpness;
 use crate::mosaic::error::ImageError;
+use crate::mosaic::timestamp::Timestamp;
 
 /// Flip coordinates horizontally for tile flipping operations.
 pub fn flipped_coords<A, const N: usize>(coords: &mut [A; N]) {
@@ -42,56 +53,276 @@ pub fn flipped_coords<A, const N: usize>(coords: &mut [A; N]) {
     }
 }
 
-/// Prepare a tile image by resizing, cropping, and caching it, and extract date information.
-pub fn prepare_tile_with_date(
+/// Metadata of a picture read from its EXIF data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExifMetadata {
+    /// EXIF orientation (1-8), 1 when absent
+    pub orientation: u32,
+    /// When the picture was taken, as an EXIF timestamp (`YYYY:MM:DD HH:MM:SS`) followed by
+    /// its offset from UTC when recorded
+    pub date_taken: Option<String>,
+    /// Latitude and longitude in degrees, positive to the north and east
+    pub gps: Option<(f64, f64)>,
+    /// Manufacturer of the camera
+    pub camera_make: Option<String>,
+    /// Model of the camera
+    pub camera_model: Option<String>,
+    /// Caption of the picture, from its IPTC data or else its EXIF description
+    pub caption: Option<String>,
+}
+
+impl ExifMetadata {
+    /// The camera the picture was taken with, its make followed by its model unless the
+    /// model already names it, as in `Canon` and `Canon EOS 5D`.
+    pub fn camera(&self) -> Option<String> {
+        match (self.camera_make.as_deref(), self.camera_model.as_deref()) {
+            (Some(make), Some(model)) => {
+                let brand = make
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or(make)
+                    .to_lowercase();
+                if model.to_lowercase().starts_with(&brand) {
+                    Some(model.to_string())
+                } else {
+                    Some(format!("{} {}", make, model))
+                }
+            }
+            (make, model) => make.or(model).map(str::to_string),
+        }
+    }
+}
+
+impl Default for ExifMetadata {
+    fn default() -> Self {
+        ExifMetadata {
+            orientation: 1,
+            date_taken: None,
+            gps: None,
+            camera_make: None,
+            camera_model: None,
+            caption: None,
+        }
+    }
+}
+
+/// Prepare a tile image by resizing, cropping, and caching it, and read its EXIF metadata,
+/// with the sharpness of the picture when it is decoded, i.e. unless its tile was cached.
+///
+/// The file is read and its EXIF data parsed only once for both. The tile of a crop of a
+/// picture, whose path names its region, is prepared from that region alone.
+pub fn prepare_tile_with_metadata(
     path: &Path,
     tile_size: u32,
     crop: bool,
-) -> Result<
-    (
-        ::image::ImageBuffer<::image::Rgb<u8>, Vec<u8>>,
-        Option<String>,
-    ),
-    ImageError,
-> {
-    let date_taken = get_exif_date(path);
-    let image = prepare_tile(path, tile_size, crop)?;
-    Ok((image, date_taken))
+    write_cache: bool,
+) -> Result<(::image::RgbImage, ExifMetadata, Option<f32>), ImageError> {
+    let (file, region) = split_crop(path);
+    let bytes = read_bytes(file)?;
+    let metadata = metadata_from_bytes(file, &bytes);
+    let orientation = metadata.orientation;
+    let (image, sharpness) = prepare_tile_from_bytes(
+        file,
+        &bytes,
+        orientation,
+        region,
+        tile_size,
+        crop,
+        write_cache,
+    )?;
+    Ok((image, metadata, sharpness))
 }
 
 /// Prepare a tile image by resizing, cropping, and caching it.
+///
+/// When `write_cache` is false, previously cached tiles are still reused but
+/// newly prepared ones are not saved to the cache directory.
 pub fn prepare_tile(
     path: &Path,
     tile_size: u32,
     crop: bool,
+    write_cache: bool,
 ) -> Result<::image::ImageBuffer<::image::Rgb<u8>, Vec<u8>>, ImageError> {
-    // We cache resized images in the home cache path using their content hash
-    let content_hash = md5::compute(std::fs::read(path).map_err(|e| ImageError {
+    let (file, region) = split_crop(path);
+    let bytes = read_bytes(file)?;
+    let orientation = read_picture_exif(file, &bytes).map_or(1, |metadata| metadata.orientation);
+    prepare_tile_from_bytes(
+        file,
+        &bytes,
+        orientation,
+        region,
+        tile_size,
+        crop,
+        write_cache,
+    )
+    .map(|(image, _)| image)
+}
+
+/// Measure the sharpness of the picture at `path`, or of the region of it a crop is of.
+pub fn read_sharpness(path: &Path) -> Result<f32, ImageError> {
+    let (file, region) = split_crop(path);
+    let bytes = read_bytes(file)?;
+    let image = formats::decode(file, &bytes, decode_limited).map_err(|e| ImageError {
+        path: file.to_owned(),
+        error: e,
+    })?;
+    let image = match region {
+        Some(region) => {
+            let orientation =
+                read_picture_exif(file, &bytes).map_or(1, |metadata| metadata.orientation);
+            region.apply(&rotate(image, orientation))
+        }
+        None => image,
+    };
+    Ok(sharpness(&image))
+}
+
+/// Read the metadata of the picture at `path`, or that a crop is of, with the caption of its
+/// IPTC data.
+pub fn read_picture_metadata(path: &Path) -> io::Result<ExifMetadata> {
+    let file = split_crop(path).0;
+    Ok(metadata_from_bytes(file, &std::fs::read(file)?))
+}
+
+/// The metadata of the picture at `path` whose contents are `bytes`, the IPTC caption taking
+/// precedence over the EXIF description as photo managers edit the former.
+fn metadata_from_bytes(path: &Path, bytes: &[u8]) -> ExifMetadata {
+    let mut metadata = read_picture_exif(path, bytes).unwrap_or_default();
+    metadata.caption = read_iptc_caption(bytes).or(metadata.caption);
+    metadata
+}
+
+/// The EXIF metadata of the picture at `path` whose contents are `bytes`, read from the JPEG
+/// preview of the camera RAW files whose own EXIF data cannot be read, e.g. Fujifilm's. HEIF
+/// pictures are upright once decoded, so the orientation of their EXIF data is left out.
+fn read_picture_exif(path: &Path, bytes: &[u8]) -> Result<ExifMetadata, exif::Error> {
+    let mut metadata =
+        read_exif_metadata(&mut std::io::Cursor::new(bytes)).or_else(
+            |error| match formats::is_raw(path).then(|| formats::embedded_preview(bytes)) {
+                Some(Some(preview)) => read_exif_metadata(&mut std::io::Cursor::new(preview)),
+                _ => Err(error),
+            },
+        )?;
+    if formats::is_heif(path) {
+        metadata.orientation = 1;
+    }
+    Ok(metadata)
+}
+
+/// The caption (dataset 2:120) of the IPTC data of a JPEG picture, which photo managers write
+/// in the image resources of its Photoshop segment.
+fn read_iptc_caption(bytes: &[u8]) -> Option<String> {
+    let mut segments = bytes.strip_prefix(&[0xFF, 0xD8])?;
+    // The segments of metadata all come before the image data
+    while let [0xFF, marker, high, low, ..] = *segments {
+        if marker == 0xDA {
+            break;
+        }
+        let length = usize::from(u16::from_be_bytes([high, low]));
+        let segment = segments.get(4..2 + length)?;
+        if marker == 0xED {
+            if let Some(caption) = segment
+                .strip_prefix(b"Photoshop 3.0\0")
+                .and_then(photoshop_iptc_caption)
+            {
+                return Some(caption);
+            }
+        }
+        segments = &segments[2 + length..];
+    }
+    None
+}
+
+/// The caption of the IPTC resource among the Photoshop image resources `resources`.
+fn photoshop_iptc_caption(mut resources: &[u8]) -> Option<String> {
+    while let Some(resource) = resources.strip_prefix(b"8BIM") {
+        let id = u16::from_be_bytes([*resource.first()?, *resource.get(1)?]);
+        // The name is a Pascal string, padded to an even length like the data
+        let name_length = usize::from(*resource.get(2)?);
+        let start = 2 + (name_length + 2) / 2 * 2;
+        let size = resource.get(start..start + 4)?;
+        let size = u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize;
+        let data = resource.get(start + 4..start + 4 + size)?;
+        if id == 0x0404 {
+            return iptc_caption(data);
+        }
+        resources = resource.get(start + 4 + size.div_ceil(2) * 2..)?;
+    }
+    None
+}
+
+/// The caption among the IPTC datasets `datasets`.
+fn iptc_caption(mut datasets: &[u8]) -> Option<String> {
+    while let [0x1C, record, dataset, high, low, ref rest @ ..] = *datasets {
+        // Datasets of more than 32767 bytes have an extended size, and are no captions
+        if high & 0x80 != 0 {
+            return None;
+        }
+        let size = usize::from(u16::from_be_bytes([high, low]));
+        let data = rest.get(..size)?;
+        if (record, dataset) == (2, 120) {
+            let caption = String::from_utf8_lossy(data).trim().to_string();
+            return Some(caption).filter(|caption| !caption.is_empty());
+        }
+        datasets = &rest[size..];
+    }
+    None
+}
+
+/// Version of the way tiles are prepared, bumped whenever the trimming, cropping or resizing
+/// changes so that the tiles prepared before are prepared again.
+const PREPARATION_VERSION: u32 = 1;
+
+/// Channel value above which the pixels along the edges of an image are trimmed as white.
+const TRIM_WHITE_THRESHOLD: u8 = 240;
+
+/// Filter tiles are resized with.
+const RESIZE_FILTER: FilterType = FilterType::Lanczos3;
+
+fn read_bytes(path: &Path) -> Result<Vec<u8>, ImageError> {
+    std::fs::read(path).map_err(|e| ImageError {
         path: path.to_owned(),
         error: e.into(),
-    })?);
-    let cache_path = dirs::cache_dir().unwrap().join("mosaic").join(format!(
-        "{:x}{}.{}.jpg",
-        content_hash,
-        if crop { "_cropped" } else { "" },
-        tile_size
-    ));
+    })
+}
+
+/// Prepare the tile of the image file at `path`, whose contents are `bytes`, or of its `region`
+/// if any, with the sharpness of the picture unless its tile was cached.
+fn prepare_tile_from_bytes(
+    path: &Path,
+    bytes: &[u8],
+    orientation: u32,
+    region: Option<CropRegion>,
+    tile_size: u32,
+    crop: bool,
+    write_cache: bool,
+) -> Result<(::image::RgbImage, Option<f32>), ImageError> {
+    // We cache resized images in the home cache path using their content hash
+    // and orientation, since the rotation is baked into the cached tile
+    let content_hash = md5::compute(bytes);
+    let cache_name = tile_cache_name(content_hash, crop, orientation, region, tile_size);
+    let cache_path = tile_cache_dir().join(cache_name);
     // check if the cache path exists and load it, otherwise resize and save it
-    let cached_img: Result<::image::ImageBuffer<_, _>, _> = ::image::open(&cache_path)
+    let cached_img: Result<(::image::RgbImage, _), _> = ::image::open(&cache_path)
         .map_err(|e| ImageError {
             path: path.to_owned(),
             error: e,
         })
-        .map(|img| img.to_rgb8());
+        .map(|img| (img.to_rgb8(), None));
     cached_img.or_else(|_| {
-        let mut tile_img = ::image::open(path)
-            .map_err(|e| ImageError {
-                path: path.to_owned(),
-                error: e,
-            })?
-            .to_rgb8();
+        let decoded = formats::decode(path, bytes, decode_limited).map_err(|e| ImageError {
+            path: path.to_owned(),
+            error: e,
+        })?;
+        // Regions are of the upright picture, so crops are rotated before being cut out
+        let (decoded, orientation) = match region {
+            Some(region) => (region.apply(&rotate(decoded, orientation)), 1),
+            None => (decoded, orientation),
+        };
+        let sharpness = sharpness(&decoded);
+        let mut tile_img = decoded.to_rgb8();
         // Crop all the white pixels from the edges
-        let is_white_pixel = |pixel: &Rgb<u8>| pixel[0] > 240 && pixel[1] > 240 && pixel[2] > 240;
+        let is_white_pixel = |pixel: &Rgb<u8>| pixel.0.iter().all(|&c| c > TRIM_WHITE_THRESHOLD);
 
         let w = tile_img.width();
         let h = tile_img.height();
@@ -160,8 +391,9 @@ pub fn prepare_tile(
         let first_non_white_row = most_common_value(from_top.into_iter().filter(|x| *x != h));
         let last_non_white_row = most_common_value(from_bottom.into_iter().filter(|x| *x != 0));
 
-        assert!(first_non_white_col < last_non_white_col);
-        assert!(first_non_white_row < last_non_white_row);
+        if first_non_white_col >= last_non_white_col || first_non_white_row >= last_non_white_row {
+            return Err(ImageError::all_white(path.to_owned()));
+        }
 
         let w = last_non_white_col - first_non_white_col;
         let h = last_non_white_row - first_non_white_row;
@@ -186,63 +418,289 @@ pub fn prepare_tile(
             );
         }
 
-        let tile_img =
-            imageops::resize(tile_img.deref(), tile_size, tile_size, FilterType::Lanczos3);
-        let orientation = get_jpeg_orientation(path).unwrap_or(1);
+        let tile_img = imageops::resize(tile_img.deref(), tile_size, tile_size, RESIZE_FILTER);
         let tile_img = rotate(tile_img.into(), orientation);
-        tile_img.save(cache_path).unwrap();
-        Ok(tile_img.into())
+        if write_cache {
+            if let Some(cache_dir) = cache_path.parent() {
+                std::fs::create_dir_all(cache_dir).map_err(|e| ImageError {
+                    path: path.to_owned(),
+                    error: e.into(),
+                })?;
+            }
+            // Another run may be preparing the same tile, or reading it from the cache
+            let saved = write_atomic(&cache_path, |writer| {
+                tile_img
+                    .write_to(writer, ImageFormat::Jpeg)
+                    .map_err(io::Error::other)
+            });
+            match saved {
+                Ok(()) if region.is_none() => {
+                    // The tile cached before every setting was part of its name is stale now
+                    let legacy = legacy_tile_cache_name(content_hash, crop, orientation, tile_size);
+                    let _ = std::fs::remove_file(cache_path.with_file_name(legacy));
+                }
+                Ok(()) => (),
+                Err(e) => warn!(
+                    "⚠️  Failed to cache the tile of {} to {}: {}",
+                    path.display(),
+                    cache_path.display(),
+                    e
+                ),
+            }
+        }
+        Ok((tile_img.into(), Some(sharpness)))
     })
 }
 
-fn get_jpeg_orientation(file_path: &Path) -> Result<u32, exif::Error> {
-    let file = std::fs::File::open(file_path).expect("problem opening the file");
-    let mut bufreader = std::io::BufReader::new(&file);
-    let exifreader = exif::Reader::new();
-    let exif = exifreader.read_from_container(&mut bufreader)?;
-    let orientation: u32 = match exif.get_field(Tag::Orientation, In::PRIMARY) {
-        Some(orientation) => match orientation.value.get_uint(0) {
-            Some(v @ 1..=8) => v,
-            _ => 1,
-        },
-        None => 1,
+/// Largest image, in pixels, decoded as a tile. Larger images, such as huge panoramas or
+/// decompression bombs, are refused before their pixels are allocated.
+pub const MAX_TILE_PIXELS: u64 = 100_000_000;
+
+/// Decode an image, refusing those larger than `MAX_TILE_PIXELS`.
+fn decode_limited(bytes: &[u8]) -> Result<DynamicImage, ::image::ImageError> {
+    let too_large = || {
+        ::image::ImageError::Limits(LimitError::from_kind(
+            image::error::LimitErrorKind::InsufficientMemory,
+        ))
     };
+    let reader = ImageReader::new(io::Cursor::new(bytes)).with_guessed_format()?;
+    let (width, height) = reader.into_dimensions()?;
+    if u64::from(width) * u64::from(height) > MAX_TILE_PIXELS {
+        return Err(too_large());
+    }
+    // The header may lie, so decoders are also kept from allocating much more than a large
+    // image with 16 bit RGBA pixels
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(MAX_TILE_PIXELS * 8);
+    let mut reader = ImageReader::new(io::Cursor::new(bytes)).with_guessed_format()?;
+    reader.limits(limits);
+    reader.decode()
+}
+
+/// Prepare a tile like [`prepare_tile_with_metadata`] on a thread of its own, so that an
+/// image crashing the decoder fails on its own instead of taking down the analysis, and
+/// giving up on it after `timeout` if any.
+///
+/// The thread of an image that timed out is left to finish in the background, and no
+/// longer counts towards the threads of the rayon pool, so that images hanging the decoder
+/// cannot stall the analysis of the others.
+pub fn prepare_tile_isolated(
+    path: &Path,
+    tile_size: u32,
+    crop: bool,
+    write_cache: bool,
+    timeout: Option<Duration>,
+) -> Result<(::image::RgbImage, ExifMetadata, Option<f32>), ImageError> {
+    let owned_path = path.to_owned();
+    run_isolated(path, timeout, move || {
+        prepare_tile_with_metadata(&owned_path, tile_size, crop, write_cache)
+    })
+}
 
-    Ok(orientation)
+/// Run `prepare`, preparing the tile of the image at `path`, on a thread of its own as
+/// [`prepare_tile_isolated`] does.
+fn run_isolated<T: Send + 'static>(
+    path: &Path,
+    timeout: Option<Duration>,
+    prepare: impl FnOnce() -> Result<T, ImageError> + Send + 'static,
+) -> Result<T, ImageError> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        // The receiver is gone if the tile timed out
+        let _ = sender.send(prepare());
+    });
+    match timeout {
+        Some(timeout) => receiver.recv_timeout(timeout).map_err(|e| match e {
+            RecvTimeoutError::Timeout => ImageError::timed_out(path.to_owned()),
+            RecvTimeoutError::Disconnected => ImageError::crashed(path.to_owned()),
+        })?,
+        None => receiver
+            .recv()
+            .map_err(|_| ImageError::crashed(path.to_owned()))?,
+    }
 }
 
-/// Extract EXIF date information from an image file.
-fn get_exif_date(file_path: &Path) -> Option<String> {
-    let file = std::fs::File::open(file_path).ok()?;
-    let mut bufreader = std::io::BufReader::new(&file);
-    let exifreader = exif::Reader::new();
-    let exif = exifreader.read_from_container(&mut bufreader).ok()?;
-
-    // Try different date tags in order of preference
-    let date_tags = [Tag::DateTimeOriginal, Tag::DateTime, Tag::DateTimeDigitized];
-
-    for tag in date_tags.iter() {
-        if let Some(field) = exif.get_field(*tag, In::PRIMARY) {
-            if let exif::Value::Ascii(values) = &field.value {
-                if let Some(first_value) = values.first() {
-                    // Convert bytes to string, handling potential encoding issues
-                    return String::from_utf8(first_value.to_vec())
-                        .ok()
-                        .map(|s| s.trim_end_matches('\0').to_string())
-                        .map(|s| {
-                            // Extract only the date part, remove time if present
-                            if let Some(space_pos) = s.find(' ') {
-                                s[..space_pos].to_string()
-                            } else {
-                                s
-                            }
-                        });
-                }
-            }
+/// Directory where prepared tiles are cached.
+fn tile_cache_dir() -> PathBuf {
+    dirs::cache_dir().unwrap().join("mosaic")
+}
+
+/// File name of a prepared tile in the cache: the hash of the contents of its image followed by
+/// a hash of every setting it was prepared with, the fixed ones included, so that a change to
+/// any of them never serves a stale tile.
+fn tile_cache_name(
+    content_hash: md5::Digest,
+    crop: bool,
+    orientation: u32,
+    region: Option<CropRegion>,
+    tile_size: u32,
+) -> String {
+    let mut settings = format!(
+        "v{} size={} crop={} orientation={} filter={:?} white={}",
+        PREPARATION_VERSION, tile_size, crop, orientation, RESIZE_FILTER, TRIM_WHITE_THRESHOLD
+    );
+    // Whole pictures keep the names they had before crops were taken
+    if let Some(region) = region {
+        settings.push_str(&format!(" region={}", region));
+    }
+    format!("{:x}_{:x}.jpg", content_hash, md5::compute(settings))
+}
+
+/// File name of a prepared tile in the cache of the versions of emosaic naming them after the
+/// crop, orientation and size only, `{hash}[_cropped][_oN].{size}.jpg`.
+fn legacy_tile_cache_name(
+    content_hash: md5::Digest,
+    crop: bool,
+    orientation: u32,
+    tile_size: u32,
+) -> String {
+    format!(
+        "{:x}{}{}.{}.jpg",
+        content_hash,
+        if crop { "_cropped" } else { "" },
+        if orientation == 1 {
+            String::new()
+        } else {
+            format!("_o{}", orientation)
+        },
+        tile_size
+    )
+}
+
+/// Delete every cached prepared tile of the rotated images (EXIF orientation other
+/// than 1) among `paths`, so they are prepared again with their current orientation.
+///
+/// Returns the number of cached tiles deleted.
+pub fn refresh_rotated_tiles(paths: &[PathBuf]) -> io::Result<usize> {
+    discard_prepared_tiles(paths, |bytes| {
+        read_exif_metadata(&mut std::io::Cursor::new(bytes))
+            .is_ok_and(|metadata| metadata.orientation != 1)
+    })
+}
+
+/// Delete every cached prepared tile of the images at `paths`, so they are prepared again.
+///
+/// Returns the number of cached tiles deleted.
+pub fn refresh_tiles(paths: &[PathBuf]) -> io::Result<usize> {
+    discard_prepared_tiles(paths, |_| true)
+}
+
+/// Delete every cached prepared tile of the images at `paths` whose contents are `selected`.
+fn discard_prepared_tiles(
+    paths: &[PathBuf],
+    selected: impl Fn(&[u8]) -> bool + Sync,
+) -> io::Result<usize> {
+    discard_prepared_tiles_in(&tile_cache_dir(), paths, selected)
+}
+
+/// Delete every prepared tile cached in `cache_dir` of the images at `paths` whose contents are
+/// `selected`, those named by [`legacy_tile_cache_name`] included, as all start with the hash
+/// of the contents.
+fn discard_prepared_tiles_in(
+    cache_dir: &Path,
+    paths: &[PathBuf],
+    selected: impl Fn(&[u8]) -> bool + Sync,
+) -> io::Result<usize> {
+    if !cache_dir.exists() {
+        return Ok(0);
+    }
+    let prefixes: HashSet<String> = paths
+        .par_iter()
+        .filter_map(|path| {
+            let bytes = std::fs::read(path).ok()?;
+            selected(&bytes).then(|| format!("{:x}", md5::compute(&bytes)))
+        })
+        .collect();
+    let mut deleted = 0;
+    for entry in std::fs::read_dir(cache_dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let hash = name.to_str().and_then(|name| name.get(..32));
+        if hash.is_some_and(|hash| prefixes.contains(hash)) {
+            std::fs::remove_file(entry.path())?;
+            deleted += 1;
         }
     }
+    Ok(deleted)
+}
 
-    None
+/// Read the orientation, dates, GPS position, camera and description of a picture from an
+/// image container in a single pass over its EXIF data.
+pub fn read_exif_metadata<R: BufRead + Seek>(reader: &mut R) -> Result<ExifMetadata, exif::Error> {
+    let exifreader = exif::Reader::new();
+    let exif = exifreader.read_from_container(reader)?;
+    let field = |tag| exif.get_field(tag, In::PRIMARY).map(|field| &field.value);
+    let ascii = |tag| match field(tag)? {
+        exif::Value::Ascii(values) => {
+            // Convert bytes to string, handling potential encoding issues
+            let value = String::from_utf8(values.first()?.to_vec()).ok()?;
+            Some(value.trim_end_matches('\0').trim().to_string()).filter(|v| !v.is_empty())
+        }
+        _ => None,
+    };
+
+    let orientation = field(Tag::Orientation)
+        .and_then(|orientation| match orientation.get_uint(0) {
+            Some(v @ 1..=8) => Some(v),
+            _ => None,
+        })
+        .unwrap_or(1);
+
+    // Try different date tags in order of preference, each with its offset tag
+    let date_tags = [
+        (Tag::DateTimeOriginal, Tag::OffsetTimeOriginal),
+        (Tag::DateTime, Tag::OffsetTime),
+        (Tag::DateTimeDigitized, Tag::OffsetTimeDigitized),
+    ];
+    let date_taken = date_tags.iter().find_map(|&(date_tag, offset_tag)| {
+        let date_time = ascii(date_tag)?;
+        let offset = ascii(offset_tag);
+        Timestamp::from_exif(&date_time, offset.as_deref()).map(|taken| taken.to_string())
+    });
+
+    // Degrees, minutes and seconds, negated to the south and west
+    let coordinate = |tag, ref_tag, negative: &str| {
+        let degrees = match field(tag)? {
+            exif::Value::Rational(dms) if dms.len() == 3 => {
+                dms[0].to_f64() + dms[1].to_f64() / 60.0 + dms[2].to_f64() / 3600.0
+            }
+            _ => return None,
+        };
+        let degrees = if ascii(ref_tag).as_deref() == Some(negative) {
+            -degrees
+        } else {
+            degrees
+        };
+        degrees.is_finite().then_some(degrees)
+    };
+    let latitude = coordinate(Tag::GPSLatitude, Tag::GPSLatitudeRef, "S");
+    let longitude = coordinate(Tag::GPSLongitude, Tag::GPSLongitudeRef, "W");
+
+    Ok(ExifMetadata {
+        orientation,
+        date_taken,
+        gps: latitude.zip(longitude),
+        camera_make: ascii(Tag::Make),
+        camera_model: ascii(Tag::Model),
+        caption: ascii(Tag::ImageDescription),
+    })
+}
+
+/// Open an image with its EXIF orientation applied, or the region of it a crop is of.
+pub fn open_oriented(path: &Path) -> Result<DynamicImage, ImageError> {
+    let (file, region) = split_crop(path);
+    let error = |error| ImageError {
+        path: file.to_owned(),
+        error,
+    };
+    let bytes = std::fs::read(file).map_err(|e| error(e.into()))?;
+    let orientation = read_picture_exif(file, &bytes).map_or(1, |metadata| metadata.orientation);
+    let img = formats::decode(file, &bytes, ::image::load_from_memory).map_err(error)?;
+    let img = rotate(img, orientation);
+    Ok(match region {
+        Some(region) => region.apply(&img),
+        None => img,
+    })
 }
 
 fn rotate(mut img: DynamicImage, orientation: u32) -> DynamicImage {
@@ -276,9 +734,31 @@ fn most_common_value(values: impl Iterator<Item = u32>) -> u32 {
     most_common
 }
 
+/// A JPEG container holding only the given EXIF fields.
+#[cfg(test)]
+pub(crate) fn jpeg_with_exif(fields: &[exif::Field]) -> Vec<u8> {
+    let mut writer = exif::experimental::Writer::new();
+    for field in fields {
+        writer.push_field(field);
+    }
+    let mut tiff = std::io::Cursor::new(Vec::new());
+    writer.write(&mut tiff, false).unwrap();
+    let tiff = tiff.into_inner();
+    let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe1];
+    jpeg.extend_from_slice(&(tiff.len() as u16 + 8).to_be_bytes());
+    jpeg.extend_from_slice(b"Exif\0\0");
+    jpeg.extend_from_slice(&tiff);
+    jpeg.extend_from_slice(&[0xff, 0xd9]);
+    jpeg
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use proptest::test_runner::TestCaseError;
+    use std::convert::TryInto;
 
     #[test]
     fn test_most_common_value() {
@@ -291,13 +771,265 @@ mod tests {
     fn test_prepare_tile() {
         let path = Path::new("example/warhol.png");
         let tile_size = 32;
-        let result = prepare_tile(path, tile_size, true);
+        let result = prepare_tile(path, tile_size, true, true);
         assert!(result.is_ok());
         let tile_img = result.unwrap();
         assert_eq!(tile_img.width(), tile_size);
         assert_eq!(tile_img.height(), tile_size);
     }
 
+    #[test]
+    fn test_prepare_crop_tile() {
+        let path = Path::new("example/warhol.png#xywh=percent:0,0,50,50");
+        let tile_img = prepare_tile(path, 32, true, false).unwrap();
+        assert_eq!(tile_img.dimensions(), (32, 32));
+        let whole = open_oriented(Path::new("example/warhol.png")).unwrap();
+        let crop = open_oriented(path).unwrap();
+        assert_eq!(crop.width(), whole.width() / 2);
+        assert!(read_sharpness(path).is_ok());
+    }
+
+    #[test]
+    fn test_prepare_all_white_tile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("all-white.png");
+        ::image::RgbImage::from_pixel(40, 40, Rgb([255, 255, 255]))
+            .save(&path)
+            .unwrap();
+        let error = prepare_tile(&path, 32, true, false).unwrap_err();
+        assert_eq!(error.kind(), crate::mosaic::error::FailureKind::AllWhite);
+    }
+
+    #[test]
+    fn test_decode_limited() {
+        // A farbfeld header claiming 400 megapixels is refused before decoding any pixel
+        let mut bytes = b"farbfeld".to_vec();
+        bytes.extend(20_000u32.to_be_bytes());
+        bytes.extend(20_000u32.to_be_bytes());
+        let error = decode_limited(&bytes).unwrap_err();
+        assert!(
+            matches!(error, ::image::ImageError::Limits(_)),
+            "{:?}",
+            error
+        );
+
+        let image = ::image::RgbImage::from_pixel(3, 2, Rgb([1, 2, 3]));
+        let mut bytes = Vec::new();
+        image
+            .write_to(&mut io::Cursor::new(&mut bytes), ::image::ImageFormat::Png)
+            .unwrap();
+        assert_eq!(decode_limited(&bytes).unwrap().to_rgb8(), image);
+    }
+
+    #[test]
+    fn test_prepare_tile_isolated() {
+        let path = Path::new("example/warhol.png");
+        let (tile, _, _) = prepare_tile_isolated(path, 32, true, false, None).unwrap();
+        assert_eq!(tile.dimensions(), (32, 32));
+        let error = prepare_tile_isolated(Path::new("missing.jpg"), 32, true, false, None);
+        assert!(error.is_err());
+    }
+
+    #[test]
+    fn test_run_isolated_hanging_decoder() {
+        use crate::mosaic::error::FailureKind;
+        let path = Path::new("hanging.jpg");
+        let timeout = Some(Duration::from_millis(50));
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        pool.install(|| {
+            // A decoder that never returns is given up on after the timeout
+            let hanging = run_isolated(path, timeout, || -> Result<(), ImageError> {
+                loop {
+                    thread::park();
+                }
+            });
+            assert_eq!(hanging.unwrap_err().kind(), FailureKind::Timeout);
+            // and the next tiles are prepared even with a single thread
+            for _ in 0..3 {
+                assert_eq!(run_isolated(path, timeout, || Ok(42)).unwrap(), 42);
+            }
+        });
+        let crashed = run_isolated(path, None, || -> Result<(), ImageError> { panic!() });
+        assert_eq!(crashed.unwrap_err().kind(), FailureKind::Crash);
+    }
+
+    #[test]
+    fn test_read_exif_metadata() {
+        let field = |tag, value| exif::Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value,
+        };
+        let ascii = |s: &str| exif::Value::Ascii(vec![s.as_bytes().to_vec()]);
+        let dms =
+            |d, m, s| exif::Value::Rational(vec![(d, 1).into(), (m, 1).into(), (s, 1).into()]);
+        let jpeg = jpeg_with_exif(&[
+            field(Tag::Orientation, exif::Value::Short(vec![6])),
+            field(Tag::DateTimeOriginal, ascii("2003:03:19 11:44:30")),
+            field(Tag::OffsetTimeOriginal, ascii("-05:00")),
+            field(Tag::Make, ascii("Canon")),
+            field(Tag::ImageDescription, ascii("Sunset over the bay ")),
+            field(Tag::GPSLatitude, dms(40, 30, 0)),
+            field(Tag::GPSLatitudeRef, ascii("S")),
+            field(Tag::GPSLongitude, dms(3, 45, 0)),
+            field(Tag::GPSLongitudeRef, ascii("E")),
+        ]);
+        let metadata = read_exif_metadata(&mut std::io::Cursor::new(jpeg)).unwrap();
+        assert_eq!(metadata.orientation, 6);
+        assert_eq!(
+            metadata.date_taken.as_deref(),
+            Some("2003:03:19 11:44:30-05:00")
+        );
+        assert_eq!(metadata.gps, Some((-40.5, 3.75)));
+        assert_eq!(metadata.camera_make.as_deref(), Some("Canon"));
+        assert_eq!(metadata.camera_model, None);
+        assert_eq!(metadata.camera().as_deref(), Some("Canon"));
+        assert_eq!(metadata.caption.as_deref(), Some("Sunset over the bay"));
+
+        let jpeg = jpeg_with_exif(&[field(Tag::DateTime, ascii("2003:03:19"))]);
+        let metadata = read_exif_metadata(&mut std::io::Cursor::new(jpeg)).unwrap();
+        assert_eq!(metadata.orientation, 1);
+        assert_eq!(metadata.date_taken.as_deref(), Some("2003:03:19"));
+    }
+
+    #[test]
+    fn test_camera() {
+        let camera = |make: Option<&str>, model: Option<&str>| {
+            ExifMetadata {
+                camera_make: make.map(str::to_string),
+                camera_model: model.map(str::to_string),
+                ..ExifMetadata::default()
+            }
+            .camera()
+        };
+        assert_eq!(
+            camera(Some("Canon"), Some("Canon EOS 5D")).as_deref(),
+            Some("Canon EOS 5D")
+        );
+        assert_eq!(
+            camera(Some("NIKON CORPORATION"), Some("NIKON D750")).as_deref(),
+            Some("NIKON D750")
+        );
+        assert_eq!(
+            camera(Some("Apple"), Some("iPhone 12")).as_deref(),
+            Some("Apple iPhone 12")
+        );
+        assert_eq!(
+            camera(None, Some("iPhone 12")).as_deref(),
+            Some("iPhone 12")
+        );
+        assert_eq!(camera(None, None), None);
+    }
+
+    #[test]
+    fn test_read_iptc_caption() {
+        let mut datasets = vec![0x1C, 2, 5, 0, 5];
+        datasets.extend_from_slice(b"Title");
+        let caption = "Café at nine".as_bytes();
+        datasets.extend_from_slice(&[0x1C, 2, 120, 0, caption.len() as u8]);
+        datasets.extend_from_slice(caption);
+        let mut resources = Vec::new();
+        // A resource with an odd size and a name before the IPTC one
+        resources.extend_from_slice(b"8BIM\x03\xED\x01a\x00\x00\x00\x03xyz\x00");
+        resources.extend_from_slice(b"8BIM\x04\x04\x00\x00");
+        resources.extend_from_slice(&(datasets.len() as u32).to_be_bytes());
+        resources.extend_from_slice(&datasets);
+        let mut segment = b"Photoshop 3.0\0".to_vec();
+        segment.extend_from_slice(&resources);
+
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, 0xFF, 0xED];
+        jpeg.extend_from_slice(&(segment.len() as u16 + 2).to_be_bytes());
+        jpeg.extend_from_slice(&segment);
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x02]);
+        assert_eq!(read_iptc_caption(&jpeg).as_deref(), Some("Café at nine"));
+
+        // The IPTC caption takes precedence over the EXIF description
+        let description = exif::Field {
+            tag: Tag::ImageDescription,
+            ifd_num: In::PRIMARY,
+            value: exif::Value::Ascii(vec![b"OLYMPUS DIGITAL CAMERA".to_vec()]),
+        };
+        let exif = jpeg_with_exif(&[description]);
+        assert_eq!(
+            metadata_from_bytes(Path::new("a.jpg"), &exif)
+                .caption
+                .as_deref(),
+            Some("OLYMPUS DIGITAL CAMERA")
+        );
+        let mut both = exif[..exif.len() - 2].to_vec();
+        both.extend_from_slice(&jpeg[2..]);
+        assert_eq!(
+            metadata_from_bytes(Path::new("a.jpg"), &both)
+                .caption
+                .as_deref(),
+            Some("Café at nine")
+        );
+
+        assert_eq!(read_iptc_caption(&jpeg[..jpeg.len() - 20]), None);
+        assert_eq!(read_iptc_caption(b"\x89PNG"), None);
+    }
+
+    #[test]
+    fn test_tile_cache_name() {
+        let hash = md5::compute(b"tile");
+        let name = tile_cache_name(hash, false, 1, None, 16);
+        assert!(name.starts_with(&format!("{:x}_", hash)) && name.ends_with(".jpg"));
+        assert_eq!(name, tile_cache_name(hash, false, 1, None, 16));
+        // Every setting is part of the name
+        let region = crate::mosaic::tiles::CROP_REGIONS[0];
+        for other in [
+            tile_cache_name(hash, true, 1, None, 16),
+            tile_cache_name(hash, false, 6, None, 16),
+            tile_cache_name(hash, false, 1, Some(region), 16),
+            tile_cache_name(hash, false, 1, None, 32),
+            tile_cache_name(md5::compute(b"other"), false, 1, None, 16),
+        ] {
+            assert_ne!(name, other);
+        }
+    }
+
+    #[test]
+    fn test_discard_prepared_tiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let (picture, other) = (dir.path().join("a.jpg"), dir.path().join("b.jpg"));
+        std::fs::write(&picture, b"picture").unwrap();
+        std::fs::write(&other, b"other").unwrap();
+        let cache_dir = dir.path().join("cache");
+        std::fs::create_dir(&cache_dir).unwrap();
+        let hash = md5::compute(b"picture");
+        let names = [
+            tile_cache_name(hash, false, 1, None, 16),
+            tile_cache_name(hash, true, 6, None, 32),
+            legacy_tile_cache_name(hash, false, 1, 16),
+            legacy_tile_cache_name(hash, true, 6, 32),
+        ];
+        assert_eq!(names[3], format!("{:x}_cropped_o6.32.jpg", hash));
+        let kept = tile_cache_name(md5::compute(b"other"), false, 1, None, 16);
+        for name in names.iter().chain([&kept]) {
+            std::fs::write(cache_dir.join(name), b"tile").unwrap();
+        }
+
+        let discarded = |selected: bool| {
+            let paths = [picture.clone(), dir.path().join("missing.jpg")];
+            discard_prepared_tiles_in(&cache_dir, &paths, |_| selected).unwrap()
+        };
+        assert_eq!(discarded(false), 0);
+        assert_eq!(discarded(true), names.len());
+        let left: Vec<_> = std::fs::read_dir(&cache_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(left, [kept.as_str()]);
+        let missing_dir = dir.path().join("none");
+        assert_eq!(
+            discard_prepared_tiles_in(&missing_dir, &[other], |_| true).unwrap(),
+            0
+        );
+    }
+
     #[test]
     fn test_flipped_coords() {
         let mut coords = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
@@ -307,25 +1039,47 @@ mod tests {
         assert_eq!(coords, [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
     }
 
-    #[test]
-    fn test_exif_date_extraction() {
-        // Test the date extraction logic (simulating what happens in get_exif_date)
-        let full_datetime = "2003:03:19 11:44:30\0";
-        let trimmed = full_datetime.trim_end_matches('\0').to_string();
-        let date_only = if let Some(space_pos) = trimmed.find(' ') {
-            trimmed[..space_pos].to_string()
-        } else {
-            trimmed
-        };
-        assert_eq!(date_only, "2003:03:19");
-        
-        // Test date-only input (no time part)
-        let date_only_input = "2003:03:19";
-        let result = if let Some(space_pos) = date_only_input.find(' ') {
-            date_only_input[..space_pos].to_string()
-        } else {
-            date_only_input.to_string()
+    /// Check `flipped_coords` on the raw pixels of an N-pixel square image:
+    /// it must agree with `imageops::flip_horizontal` and be an involution.
+    fn check_flipped_coords<const N: usize>(pixels: Vec<u8>) -> Result<(), TestCaseError>
+    where
+        [(); N * 3]:,
+    {
+        let dim = N.sqrt() as u32;
+        let img = ::image::RgbImage::from_raw(dim, dim, pixels).unwrap();
+        let original: [u8; N * 3] = img.as_raw().as_slice().try_into().unwrap();
+
+        let mut coords = original;
+        flipped_coords(&mut coords);
+        let flipped_img = imageops::flip_horizontal(&img);
+        prop_assert_eq!(&coords[..], flipped_img.as_raw().as_slice());
+
+        flipped_coords(&mut coords);
+        prop_assert_eq!(coords, original);
+        Ok(())
+    }
+
+    macro_rules! flipped_coords_props {
+        ($($dim:literal => $n:literal),*) => {
+            paste::paste! {
+                proptest! {
+                    $(
+                        #[test]
+                        fn [<prop_flipped_coords_ $dim x $dim>](
+                            pixels in vec(any::<u8>(), $n * 3)
+                        ) {
+                            check_flipped_coords::<$n>(pixels)?;
+                        }
+                    )*
+                }
+            }
         };
-        assert_eq!(result, "2003:03:19");
     }
+
+    flipped_coords_props!(
+        1 => 1, 2 => 4, 3 => 9, 4 => 16,
+        5 => 25, 6 => 36, 7 => 49, 8 => 64,
+        9 => 81, 10 => 100, 11 => 121, 12 => 144,
+        13 => 169, 14 => 196, 15 => 225, 16 => 256
+    );
 }
@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+
+use super::tiles::TileSet;
+
+/// Direction in which the pictures of a time-gradient mosaic get newer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimeGradient {
+    /// Oldest pictures on the left, newest on the right
+    LeftRight,
+    /// Oldest pictures at the top, newest at the bottom
+    TopBottom,
+    /// Oldest pictures at the top left, newest at the bottom right, row by row
+    ReadingOrder,
+}
+
+impl TimeGradient {
+    /// Position of the block at (`x`, `y`) along the gradient, from 0 at the oldest end to 1
+    /// at the newest, given the block size and the dimensions of the source image.
+    pub fn position(self, (x, y): (u32, u32), step: u32, (width, height): (u32, u32)) -> f64 {
+        let (offset, last) = match self {
+            TimeGradient::LeftRight => (x, width.saturating_sub(step)),
+            TimeGradient::TopBottom => (y, height.saturating_sub(step)),
+            TimeGradient::ReadingOrder => {
+                let cols = width / step;
+                let cells = cols * (height / step);
+                ((y / step) * cols + x / step, cells.saturating_sub(1))
+            }
+        };
+        if last == 0 {
+            0.5
+        } else {
+            offset as f64 / last as f64
+        }
+    }
+}
+
+/// Rank of the date of every tile among the dated tiles, in tile order, from 0 for the
+/// oldest to 1 for the newest. Undated tiles have no position.
+pub fn date_positions<T>(tile_set: &TileSet<T>) -> Vec<Option<f64>> {
+    let mut days: Vec<i64> = tile_set
+        .tiles
+        .iter()
+        .filter_map(|tile| tile.day_taken())
+        .collect();
+    days.sort_unstable();
+    let last = days.len().saturating_sub(1);
+    tile_set
+        .tiles
+        .iter()
+        .map(|tile| {
+            let taken = tile.day_taken()?;
+            let rank = days.partition_point(|&day| day < taken);
+            Some(if last == 0 {
+                0.5
+            } else {
+                rank as f64 / last as f64
+            })
+        })
+        .collect()
+}
+
+/// How far a tile with the given date position is from the position of its cell along the
+/// gradient, from 0 to 1. Undated tiles are treated as if taken halfway through.
+pub fn deviation(date_position: Option<f64>, cell_position: f64) -> f64 {
+    (date_position.unwrap_or(0.5) - cell_position).abs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_position() {
+        let gradient = TimeGradient::LeftRight;
+        assert_eq!(gradient.position((0, 6), 2, (10, 8)), 0.0);
+        assert_eq!(gradient.position((4, 6), 2, (10, 8)), 0.5);
+        assert_eq!(gradient.position((8, 6), 2, (10, 8)), 1.0);
+        assert_eq!(TimeGradient::TopBottom.position((8, 3), 1, (10, 4)), 1.0);
+        assert_eq!(TimeGradient::TopBottom.position((8, 0), 1, (10, 1)), 0.5);
+        let reading = TimeGradient::ReadingOrder;
+        assert_eq!(reading.position((0, 0), 2, (4, 6)), 0.0);
+        assert_eq!(reading.position((2, 0), 2, (4, 6)), 0.2);
+        assert_eq!(reading.position((0, 2), 2, (4, 6)), 0.4);
+        assert_eq!(reading.position((2, 4), 2, (4, 6)), 1.0);
+    }
+
+    #[test]
+    fn test_date_positions() {
+        let mut tile_set: TileSet<()> = TileSet::new();
+        let dates = [
+            Some("2024:06:01"),
+            None,
+            Some("2020:01:01"),
+            Some("2022:01:01"),
+            Some("2020:01:01"),
+        ];
+        for (i, date) in dates.iter().enumerate() {
+            tile_set.push_tile(PathBuf::from(format!("{}.jpg", i)), ());
+            tile_set.tiles[i].date_taken = date.map(str::to_string);
+        }
+        assert_eq!(
+            date_positions(&tile_set),
+            [Some(1.0), None, Some(0.0), Some(2.0 / 3.0), Some(0.0)]
+        );
+        assert_eq!(deviation(None, 1.0), 0.5);
+        assert_eq!(deviation(Some(0.25), 1.0), 0.75);
+    }
+}
@@ -0,0 +1,305 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::tiles::TileSet;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// When a picture was taken: the wall clock time of the camera, with its offset from UTC when
+/// one was recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timestamp {
+    /// Wall clock time in seconds from 1970-01-01 00:00:00
+    pub local: i64,
+    /// Offset of the wall clock from UTC, if known
+    pub offset: Option<TimeZone>,
+    /// Whether the time of day is known, or only the date
+    pub has_time: bool,
+}
+
+/// A fixed offset from UTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeZone {
+    /// Minutes east of UTC
+    pub minutes: i32,
+}
+
+/// How dates are shown in the tooltips of the HTML pages.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum DateFormat {
+    /// 2024-06-01
+    #[default]
+    Date,
+    /// 2024-06-01 12:30
+    DateTime,
+    /// 2024-06-01 12:30:45 +02:00, with the offset from UTC when known
+    Full,
+    /// 2024
+    Year,
+}
+
+impl Timestamp {
+    /// Parse a timestamp written as in EXIF data, `YYYY:MM:DD HH:MM:SS`, followed by an
+    /// optional offset from UTC such as `+02:00` or `Z`. The time may be left out.
+    pub fn parse(s: &str) -> Option<Timestamp> {
+        let s = s.trim();
+        let date = s.get(..10)?;
+        let mut parts = date.split([':', '-']).map(|part| part.parse::<i64>().ok());
+        let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return None;
+        }
+        let days = days_from_civil(year, month, day);
+        let rest = s[10..].trim_start();
+        if rest.is_empty() {
+            return Some(Timestamp {
+                local: days * SECONDS_PER_DAY,
+                offset: None,
+                has_time: false,
+            });
+        }
+        let time = rest.get(..8)?;
+        let mut parts = time.split(':').map(|part| part.parse::<i64>().ok());
+        let (hours, minutes, seconds) = (parts.next()??, parts.next()??, parts.next()??);
+        if hours > 23 || minutes > 59 || seconds > 60 {
+            return None;
+        }
+        let offset = match rest[8..].trim() {
+            "" => None,
+            offset => Some(offset.parse().ok()?),
+        };
+        Some(Timestamp {
+            local: days * SECONDS_PER_DAY + hours * 3600 + minutes * 60 + seconds,
+            offset,
+            has_time: true,
+        })
+    }
+
+    /// Combine the `DateTime` and `OffsetTime` values of EXIF data.
+    pub fn from_exif(date_time: &str, offset: Option<&str>) -> Option<Timestamp> {
+        let timestamp = Timestamp::parse(date_time)?;
+        match offset.and_then(|offset| offset.trim().parse().ok()) {
+            Some(offset) if timestamp.has_time => Some(Timestamp {
+                offset: Some(offset),
+                ..timestamp
+            }),
+            _ => Some(timestamp),
+        }
+    }
+
+    /// Day of the wall clock, counted from 1970-01-01.
+    pub fn day(&self) -> i64 {
+        self.local.div_euclid(SECONDS_PER_DAY)
+    }
+
+    /// Month of the wall clock, counted from January 1970.
+    pub fn month(&self) -> i64 {
+        let (year, month, _) = civil_from_days(self.day());
+        (year - 1970) * 12 + month - 1
+    }
+
+    /// Seconds from 1970-01-01 00:00:00 UTC, taking the wall clock as UTC when its offset is
+    /// unknown.
+    pub fn utc(&self) -> i64 {
+        self.local - self.offset.map_or(0, |zone| i64::from(zone.minutes) * 60)
+    }
+
+    /// The same instant on the wall clock of `zone`. Timestamps without an offset are taken to
+    /// be in `zone` already.
+    pub fn in_zone(self, zone: TimeZone) -> Timestamp {
+        match self.offset {
+            Some(_) => Timestamp {
+                local: self.utc() + i64::from(zone.minutes) * 60,
+                offset: Some(zone),
+                has_time: self.has_time,
+            },
+            None => Timestamp {
+                offset: self.has_time.then_some(zone),
+                ..self
+            },
+        }
+    }
+
+    /// The timestamp as shown in the tooltips.
+    pub fn format(&self, format: DateFormat) -> String {
+        let (year, month, day) = civil_from_days(self.day());
+        let seconds = self.local.rem_euclid(SECONDS_PER_DAY);
+        let (hours, minutes) = (seconds / 3600, seconds / 60 % 60);
+        match format {
+            DateFormat::Year => format!("{}", year),
+            DateFormat::Date => format!("{:04}-{:02}-{:02}", year, month, day),
+            DateFormat::DateTime | DateFormat::Full if !self.has_time => {
+                format!("{:04}-{:02}-{:02}", year, month, day)
+            }
+            DateFormat::DateTime => format!(
+                "{:04}-{:02}-{:02} {:02}:{:02}",
+                year, month, day, hours, minutes
+            ),
+            DateFormat::Full => {
+                let offset = self
+                    .offset
+                    .map_or(String::new(), |zone| format!(" {}", zone));
+                format!(
+                    "{:04}-{:02}-{:02} {:02}:{:02}:{:02}{}",
+                    year,
+                    month,
+                    day,
+                    hours,
+                    minutes,
+                    seconds % 60,
+                    offset
+                )
+            }
+        }
+    }
+}
+
+impl fmt::Display for Timestamp {
+    /// Write the timestamp as in EXIF data, followed by its offset from UTC when known.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = civil_from_days(self.day());
+        write!(f, "{:04}:{:02}:{:02}", year, month, day)?;
+        if self.has_time {
+            let seconds = self.local.rem_euclid(SECONDS_PER_DAY);
+            let (hours, minutes) = (seconds / 3600, seconds / 60 % 60);
+            write!(f, " {:02}:{:02}:{:02}", hours, minutes, seconds % 60)?;
+        }
+        if let Some(zone) = self.offset {
+            write!(f, "{}", zone)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for TimeZone {
+    type Err = String;
+
+    /// Parse an offset written as `UTC`, `Z`, `+HH:MM`, `-HH:MM` or `+HHMM`.
+    fn from_str(s: &str) -> Result<TimeZone, String> {
+        if s.eq_ignore_ascii_case("utc") || s == "Z" {
+            return Ok(TimeZone { minutes: 0 });
+        }
+        let invalid = || format!("Invalid time zone {:?}, expected UTC or +HH:MM", s);
+        let (sign, digits) = match s.split_at_checked(1) {
+            Some(("+", digits)) => (1, digits),
+            Some(("-", digits)) => (-1, digits),
+            _ => return Err(invalid()),
+        };
+        let digits = digits.replace(':', "");
+        if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let hours: i32 = digits[..2].parse().unwrap();
+        let minutes: i32 = digits[2..].parse().unwrap();
+        if hours > 14 || minutes > 59 {
+            return Err(invalid());
+        }
+        Ok(TimeZone {
+            minutes: sign * (hours * 60 + minutes),
+        })
+    }
+}
+
+impl fmt::Display for TimeZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.minutes < 0 { '-' } else { '+' };
+        let minutes = self.minutes.abs();
+        write!(f, "{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+    }
+}
+
+/// Rewrite the dates of all the tiles on the wall clock of `zone`.
+pub fn normalize_dates<T>(tile_set: &mut TileSet<T>, zone: TimeZone) {
+    for tile in &mut tile_set.tiles {
+        if let Some(taken) = tile.taken() {
+            tile.date_taken = Some(taken.in_zone(zone).to_string());
+        }
+    }
+}
+
+/// Days from 1970-01-01 to a date of the proleptic Gregorian calendar.
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Shift the year to start in March so leap days come last
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Year, month and day of the date `days` after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() {
+        let taken = Timestamp::parse("2024:06:01 12:30:45+02:00").unwrap();
+        assert_eq!(taken.day(), 19875);
+        assert_eq!(taken.month(), 54 * 12 + 5);
+        assert_eq!(
+            taken.utc(),
+            19875 * SECONDS_PER_DAY + 10 * 3600 + 30 * 60 + 45
+        );
+        assert_eq!(taken.to_string(), "2024:06:01 12:30:45+02:00");
+        let date = Timestamp::parse("2024:06:01").unwrap();
+        assert!(!date.has_time);
+        assert_eq!(date.to_string(), "2024:06:01");
+        assert_eq!(Timestamp::parse("2024:06:01 25:00:00"), None);
+        assert_eq!(Timestamp::parse("    :  :     :  :  "), None);
+    }
+
+    #[test]
+    fn test_from_exif() {
+        let taken = Timestamp::from_exif("2003:03:19 11:44:30", Some("-05:00")).unwrap();
+        assert_eq!(taken.to_string(), "2003:03:19 11:44:30-05:00");
+        let taken = Timestamp::from_exif("2003:03:19 11:44:30", None).unwrap();
+        assert_eq!(taken.to_string(), "2003:03:19 11:44:30");
+    }
+
+    #[test]
+    fn test_in_zone() {
+        let utc: TimeZone = "UTC".parse().unwrap();
+        let taken = Timestamp::parse("2024:06:01 01:30:00+02:00").unwrap();
+        assert_eq!(taken.in_zone(utc).to_string(), "2024:05:31 23:30:00+00:00");
+        let local = Timestamp::parse("2024:06:01 01:30:00").unwrap();
+        let zone = "-0330".parse().unwrap();
+        assert_eq!(local.in_zone(zone).to_string(), "2024:06:01 01:30:00-03:30");
+        assert!("+25:00".parse::<TimeZone>().is_err());
+        assert!("Europe/Paris".parse::<TimeZone>().is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        let taken = Timestamp::parse("2024:06:01 12:30:45+02:00").unwrap();
+        assert_eq!(taken.format(DateFormat::Date), "2024-06-01");
+        assert_eq!(taken.format(DateFormat::DateTime), "2024-06-01 12:30");
+        assert_eq!(taken.format(DateFormat::Full), "2024-06-01 12:30:45 +02:00");
+        assert_eq!(taken.format(DateFormat::Year), "2024");
+        let date = Timestamp::parse("1969:12:31").unwrap();
+        assert_eq!(date.format(DateFormat::Full), "1969-12-31");
+        assert_eq!(date.month(), -1);
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use super::stats::{RenderStats, StatsSummary};
+use super::tiles::TileSet;
+
+/// Number of the worst matches listed in the statistics.
+pub const WORST_MATCHES_LISTED: usize = 10;
+
+/// Percentiles of the distances of the placements shown with the statistics.
+pub const DISTANCE_PERCENTILES: [f64; 3] = [50.0, 90.0, 99.0];
+
+/// How often an image was placed in the mosaic.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TileUsage {
+    pub path: PathBuf,
+    /// Number of cells it was placed in
+    pub count: usize,
+    /// Average distance from those cells to the image
+    pub average_distance: f64,
+}
+
+/// The distance from a cell of the mosaic grid to the tile placed in it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CellDistance {
+    /// Column of the cell in the mosaic grid
+    pub x: usize,
+    /// Row of the cell in the mosaic grid
+    pub y: usize,
+    pub path: PathBuf,
+    pub distance: f64,
+    /// Whether a synthetic filler was placed instead of an image
+    pub filler: bool,
+}
+
+/// The statistics of a rendered mosaic in full, for spreadsheets and scripts.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UsageReport {
+    pub summary: StatsSummary,
+    /// Every image placed, the most used first
+    pub usage: Vec<TileUsage>,
+    /// Every placement, in row major order
+    pub placements: Vec<CellDistance>,
+    /// The placements farthest from their cells, the farthest first
+    pub worst_matches: Vec<CellDistance>,
+}
+
+/// Format of the statistics written with `--stats-out`, from the extension of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageFormat {
+    Csv,
+    Json,
+}
+
+impl UsageFormat {
+    /// The format of a file at `path`, if its extension is `csv` or `json`.
+    pub fn from_path(path: &Path) -> Option<UsageFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "csv" => Some(UsageFormat::Csv),
+            "json" => Some(UsageFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+impl UsageReport {
+    /// Write the report as JSON.
+    pub fn write_json<W: Write>(&self, writer: W) -> Result<(), io::Error> {
+        serde_json::to_writer_pretty(writer, self).map_err(io::Error::from)
+    }
+
+    /// Write the report as a single CSV table, whose `record` column tells the usage of an
+    /// image, a placement and a worst match apart. Placements and worst matches leave `count`
+    /// empty, and usages leave `x` and `y` empty and give their average distance.
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        writeln!(writer, "record,x,y,path,count,distance,filler")?;
+        for usage in &self.usage {
+            writeln!(
+                writer,
+                "usage,,,{},{},{},false",
+                csv_field(&usage.path),
+                usage.count,
+                usage.average_distance
+            )?;
+        }
+        let cells = self.placements.iter().map(|cell| ("placement", cell));
+        let worst = self.worst_matches.iter().map(|cell| ("worst", cell));
+        for (record, cell) in cells.chain(worst) {
+            writeln!(
+                writer,
+                "{},{},{},{},,{},{}",
+                record,
+                cell.x,
+                cell.y,
+                csv_field(&cell.path),
+                cell.distance,
+                cell.filler
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The distance of the placement below which `percentile` percent of them are, by nearest
+    /// rank, if any image was placed.
+    pub fn distance_percentile(&self, percentile: f64) -> Option<f64> {
+        let mut distances: Vec<f64> = self.placements.iter().map(|cell| cell.distance).collect();
+        distances.sort_by(f64::total_cmp);
+        let rank = (percentile / 100.0 * distances.len() as f64).ceil() as usize;
+        distances
+            .get(rank.clamp(1, distances.len().max(1)) - 1)
+            .copied()
+    }
+
+    /// Number of placements in each of `bins` ranges of distance of the same width, from 0 to
+    /// the farthest placement.
+    pub fn distance_histogram(&self, bins: usize) -> Vec<usize> {
+        let mut histogram = vec![0; bins];
+        let farthest = self.worst_matches.first().map_or(0.0, |cell| cell.distance);
+        for cell in &self.placements {
+            let bin = if farthest > 0.0 {
+                (cell.distance / farthest * bins as f64) as usize
+            } else {
+                0
+            };
+            if let Some(count) = histogram.get_mut(bin.min(bins.saturating_sub(1))) {
+                *count += 1;
+            }
+        }
+        histogram
+    }
+
+    /// Write the report to the file at `path`, in `format`.
+    pub fn save(&self, path: &Path, format: UsageFormat) -> Result<(), io::Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        match format {
+            UsageFormat::Csv => self.write_csv(&mut writer)?,
+            UsageFormat::Json => self.write_json(&mut writer)?,
+        }
+        writer.flush()
+    }
+}
+
+/// `path` as a CSV field, quoted when it holds a separator, a quote or a line break.
+fn csv_field(path: &Path) -> String {
+    let field = path.display().to_string();
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+impl<D> RenderStats<D>
+where
+    f64: From<D>,
+    D: std::cmp::Ord,
+    D: std::convert::From<u8>,
+    D: std::ops::AddAssign,
+    D: Copy,
+    D: std::fmt::Display,
+{
+    /// Build the full statistics of the placements, with the usage of every image.
+    ///
+    /// # Arguments
+    /// * `tile_set` - The tile set used for generating the mosaic
+    pub fn usage_report<T>(&self, tile_set: &TileSet<T>) -> UsageReport {
+        let placements: Vec<CellDistance> = self
+            .grid_cells()
+            .into_iter()
+            .map(|(col, row)| {
+                let tile = &self.tiles()[&(col, row)];
+                CellDistance {
+                    x: col as usize,
+                    y: row as usize,
+                    path: tile_set.get_path(tile).to_path_buf(),
+                    distance: tile.colors.into(),
+                    filler: tile_set.filler_color(tile).is_some(),
+                }
+            })
+            .collect();
+
+        // The number of placements and sum of their distances of every image
+        let mut counts: HashMap<&Path, (usize, f64)> = HashMap::new();
+        for cell in placements.iter().filter(|cell| !cell.filler) {
+            let (count, distances) = counts.entry(&cell.path).or_default();
+            *count += 1;
+            *distances += cell.distance;
+        }
+        let mut usage: Vec<TileUsage> = counts
+            .into_iter()
+            .map(|(path, (count, distances))| TileUsage {
+                path: path.to_path_buf(),
+                count,
+                average_distance: distances / count as f64,
+            })
+            .collect();
+        usage.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.path.cmp(&b.path)));
+
+        let mut worst_matches = placements.clone();
+        worst_matches.sort_by(|a, b| {
+            b.distance
+                .total_cmp(&a.distance)
+                .then((a.y, a.x).cmp(&(b.y, b.x)))
+        });
+        worst_matches.truncate(WORST_MATCHES_LISTED);
+
+        UsageReport {
+            summary: self.summary(tile_set),
+            usage,
+            placements,
+            worst_matches,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn test_usage_report() {
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        tile_set.push_tile(PathBuf::from("a.jpg"), [Rgb([255, 0, 0])]);
+        tile_set.push_tile(PathBuf::from("b, c.jpg"), [Rgb([0, 0, 255])]);
+        let (a, b) = (tile_set.tiles[0].clone(), tile_set.tiles[1].clone());
+        let mut stats: RenderStats<u32> = RenderStats::new();
+        stats.push_tile(0, 0, &a, 10);
+        stats.push_tile(1, 0, &b, 40);
+        stats.push_tile(0, 1, &a, 30);
+
+        let report = stats.usage_report(&tile_set);
+        assert_eq!(report.summary.cells, 3);
+        assert_eq!(
+            report.usage,
+            [
+                TileUsage {
+                    path: PathBuf::from("a.jpg"),
+                    count: 2,
+                    average_distance: 20.0
+                },
+                TileUsage {
+                    path: PathBuf::from("b, c.jpg"),
+                    count: 1,
+                    average_distance: 40.0
+                },
+            ]
+        );
+        let cells: Vec<_> = report.placements.iter().map(|c| (c.x, c.y)).collect();
+        assert_eq!(cells, [(0, 0), (1, 0), (0, 1)]);
+        let worst: Vec<_> = report.worst_matches.iter().map(|c| c.distance).collect();
+        assert_eq!(worst, [40.0, 30.0, 10.0]);
+
+        let mut csv = Vec::new();
+        report.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "record,x,y,path,count,distance,filler");
+        assert_eq!(lines[1], "usage,,,a.jpg,2,20,false");
+        assert_eq!(lines[2], "usage,,,\"b, c.jpg\",1,40,false");
+        assert_eq!(lines[4], "placement,1,0,\"b, c.jpg\",,40,false");
+        assert_eq!(lines[6], "worst,1,0,\"b, c.jpg\",,40,false");
+        assert_eq!(lines.len(), 9);
+
+        let mut json = Vec::new();
+        report.write_json(&mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["usage"][0]["count"], 2);
+        assert_eq!(json["worst_matches"][0]["path"], "b, c.jpg");
+        assert_eq!(json["summary"]["unique_tiles"], 2);
+
+        assert_eq!(
+            UsageFormat::from_path(Path::new("stats.CSV")),
+            Some(UsageFormat::Csv)
+        );
+        assert_eq!(
+            UsageFormat::from_path(Path::new("stats.json")),
+            Some(UsageFormat::Json)
+        );
+        assert_eq!(UsageFormat::from_path(Path::new("stats.txt")), None);
+
+        assert_eq!(report.distance_percentile(50.0), Some(30.0));
+        assert_eq!(report.distance_percentile(90.0), Some(40.0));
+        assert_eq!(report.distance_percentile(0.0), Some(10.0));
+        assert_eq!(report.distance_histogram(4), [0, 1, 0, 2]);
+    }
+}
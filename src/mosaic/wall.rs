@@ -0,0 +1,155 @@
+use ::image::{Rgb, RgbImage};
+use rand::seq::SliceRandom;
+
+use super::rendering::{render, LoadFailures, RowCallback};
+use super::tiles::{Tile, TileSet};
+
+/// Saturation below which a tile reads as gray and is placed after the colorful ones.
+const GRAY_SATURATION: f64 = 0.15;
+
+/// Color of the cells of the last row left without a tile.
+const BACKGROUND: Rgb<u8> = Rgb([0, 0, 0]);
+
+/// How the tiles of a photo wall are ordered, row by row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum WallOrder {
+    /// Around the color wheel from red to purple, followed by the grays from dark to light
+    Hue,
+    /// From the darkest tiles to the lightest
+    Luminance,
+    /// From the oldest pictures to the newest, followed by the undated ones
+    Date,
+    /// In a random order, different on every run
+    Shuffle,
+}
+
+/// Hue of `color` in degrees, or `None` for the grays, too unsaturated for a hue to show.
+fn hue(Rgb([r, g, b]): Rgb<u8>) -> Option<f64> {
+    let (r, g, b) = (f64::from(r), f64::from(g), f64::from(b));
+    let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+    let delta = max - min;
+    if max == 0.0 || delta / max < GRAY_SATURATION {
+        return None;
+    }
+    let sector = if max == r {
+        (g - b) / delta
+    } else if max == g {
+        2.0 + (b - r) / delta
+    } else {
+        4.0 + (r - g) / delta
+    };
+    Some((sector * 60.0).rem_euclid(360.0))
+}
+
+/// Perceived brightness of `color`, from 0 to 255.
+fn luminance(Rgb([r, g, b]): Rgb<u8>) -> f64 {
+    0.299 * f64::from(r) + 0.587 * f64::from(g) + 0.114 * f64::from(b)
+}
+
+/// The indices of the tiles of `tile_set`, in the `order` they are laid out on the wall.
+pub fn arrange(tile_set: &TileSet<[Rgb<u8>; 1]>, order: WallOrder) -> Vec<u32> {
+    let mut tiles: Vec<&Tile<[Rgb<u8>; 1]>> = tile_set.tiles.iter().collect();
+    match order {
+        WallOrder::Hue => tiles.sort_by(|a, b| {
+            let key = |tile: &Tile<[Rgb<u8>; 1]>| match hue(tile.colors[0]) {
+                Some(hue) => (false, hue),
+                None => (true, luminance(tile.colors[0])),
+            };
+            let ((a_gray, a), (b_gray, b)) = (key(a), key(b));
+            a_gray.cmp(&b_gray).then(a.total_cmp(&b))
+        }),
+        WallOrder::Luminance => {
+            tiles.sort_by(|a, b| luminance(a.colors[0]).total_cmp(&luminance(b.colors[0])))
+        }
+        WallOrder::Date => tiles.sort_by_cached_key(|tile| match tile.taken() {
+            Some(taken) => (false, taken.utc()),
+            None => (true, 0),
+        }),
+        WallOrder::Shuffle => tiles.shuffle(&mut rand::thread_rng()),
+    }
+    tiles.into_iter().map(|tile| tile.idx).collect()
+}
+
+/// Number of columns of a grid of `count` tiles about as wide as it is tall.
+pub fn square_columns(count: usize) -> u32 {
+    let mut columns = (count as f64).sqrt() as u32;
+    while (columns as usize) * (columns as usize) < count {
+        columns += 1;
+    }
+    columns.max(1)
+}
+
+/// Renders the tiles `arranged` row by row on a grid of `columns` columns, leaving the cells
+/// after the last tile black.
+///
+/// # Returns
+/// The rendered wall, with a solid block of the average color of the tile wherever its image
+/// failed to load, and the cells and errors of the failures in no particular order
+pub fn render_wall(
+    tile_set: &TileSet<[Rgb<u8>; 1]>,
+    arranged: &[u32],
+    columns: u32,
+    tile_size: u32,
+    on_row: Option<RowCallback>,
+) -> (RgbImage, LoadFailures) {
+    let rows = (arranged.len() as u32).div_ceil(columns).max(1);
+    let tile_at = |x: u32, y: u32| {
+        let idx = *arranged.get((y * columns + x) as usize)?;
+        tile_set.get_tile(idx as i32)
+    };
+    // The average colors stand in for the tiles that fail to load
+    let grid_img = RgbImage::from_fn(columns, rows, |x, y| match tile_at(x, y) {
+        Some(tile) => tile.colors[0],
+        None => BACKGROUND,
+    });
+    let get_tile = |x: u32, y: u32| match tile_at(x, y) {
+        Some(tile) => tile_set.get_image(&tile, tile_size),
+        None => Ok(RgbImage::from_pixel(tile_size, tile_size, BACKGROUND)),
+    };
+    let in_place = tile_set.memory_budget().is_some();
+    render(&grid_img, tile_size, 1, in_place, get_tile, on_row)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn tile_set(tiles: &[(Rgb<u8>, Option<&str>)]) -> TileSet<[Rgb<u8>; 1]> {
+        let (tiles, paths) = tiles
+            .iter()
+            .enumerate()
+            .map(|(i, &(color, date))| {
+                let tile = Tile::new_with_date(i as u32 + 1, [color], date.map(str::to_string));
+                (tile, PathBuf::from(format!("{}.jpg", i + 1)))
+            })
+            .unzip();
+        TileSet::from_tiles(tiles, paths)
+    }
+
+    #[test]
+    fn test_arrange() {
+        let tile_set = tile_set(&[
+            (Rgb([200, 200, 200]), Some("2024:05:03 10:00:00")),
+            (Rgb([0, 0, 250]), None),
+            (Rgb([250, 0, 0]), Some("2024:05:01 10:00:00")),
+            (Rgb([40, 40, 40]), None),
+            (Rgb([0, 250, 0]), Some("2024:05:02 10:00:00")),
+        ]);
+        // Red, green and blue around the color wheel, then the grays from dark to light
+        assert_eq!(arrange(&tile_set, WallOrder::Hue), [3, 5, 2, 4, 1]);
+        assert_eq!(arrange(&tile_set, WallOrder::Luminance), [2, 4, 3, 5, 1]);
+        assert_eq!(arrange(&tile_set, WallOrder::Date), [3, 5, 1, 2, 4]);
+        let mut shuffled = arrange(&tile_set, WallOrder::Shuffle);
+        shuffled.sort();
+        assert_eq!(shuffled, [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_square_columns() {
+        assert_eq!(square_columns(0), 1);
+        assert_eq!(square_columns(1), 1);
+        assert_eq!(square_columns(9), 3);
+        assert_eq!(square_columns(10), 4);
+    }
+}
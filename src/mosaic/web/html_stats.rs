@@ -1,8 +1,17 @@
-use std::collections::HashMap;
 use std::path::Path;
 
-use super::super::stats::{MosaicConfig, RenderStats};
+use clap::ValueEnum;
+
+use super::super::stats::{MosaicConfig, RenderStats, BAD_MATCHES_LISTED};
 use super::super::tiles::TileSet;
+use super::super::usage::{UsageReport, DISTANCE_PERCENTILES};
+use super::escape_html;
+
+/// Number of bars of the histogram of the distances.
+const HISTOGRAM_BINS: usize = 20;
+/// Size of the drawing area of the histogram, in SVG units.
+const HISTOGRAM_WIDTH: usize = 300;
+const HISTOGRAM_HEIGHT: usize = 100;
 
 impl<D> RenderStats<D>
 where
@@ -13,32 +22,23 @@ where
     D: Copy,
     D: std::fmt::Display,
 {
-    /// Helper function to append statistics section to HTML
+    /// Helper function to append statistics section to HTML, with a link to the full
+    /// statistics written as CSV to `csv_path`
     pub(crate) fn append_stats_html<T>(
         &self,
         html: &mut String,
         tile_set: &TileSet<T>,
         config: &MosaicConfig,
+        csv_path: &Path,
     ) {
-        // Calculate basic statistics
-        let mut total_distance: D = 0_u8.into();
-        let mut tile_usage_count: HashMap<&Path, u16> = HashMap::new();
-
-        for tile in self.tiles().values() {
-            total_distance += tile.colors;
-            let path = tile_set.get_path(tile);
-            *tile_usage_count.entry(path).or_insert(0) += 1;
-        }
-
-        let unique_tiles = tile_usage_count.len();
-        let total_distance_f64: f64 = total_distance.into();
-        let tile_count = self.tiles().len() as f64;
-        let avg_distance = total_distance_f64 / tile_count;
+        let report = self.usage_report(tile_set);
+        let summary = report.summary;
 
         html.push_str(&format!(
             r#"
         <div class="stats">
             <h2>Mosaic Statistics</h2>
+            <a class="distance-toggle stats-download" href="{}" download>Download CSV</a>
             <div class="stats-grid">
                 <div class="stats-section">
                     <h3>Overview</h3>
@@ -50,6 +50,10 @@ where
                         <span>Unique images used:</span>
                         <span>{}</span>
                     </div>
+                    <div class="tile-info">
+                        <span>Synthetic fillers placed:</span>
+                        <span>{}</span>
+                    </div>
                     <div class="tile-info">
                         <span>Average distance:</span>
                         <span>{:.3}</span>
@@ -89,15 +93,29 @@ where
                         <span>Randomization:</span>
                         <span>{}</span>
                     </div>
+                    <div class="tile-info">
+                        <span>Story layout:</span>
+                        <span>{}</span>
+                    </div>
+                    <div class="tile-info">
+                        <span>Time gradient:</span>
+                        <span>{}</span>
+                    </div>
+                    <div class="tile-info">
+                        <span>Fillers beyond distance:</span>
+                        <span>{}</span>
+                    </div>
                     <div class="tile-info">
                         <span>Tiles directory:</span>
                         <span>{}</span>
                     </div>
                 </div>
 "#,
-            self.tiles().len(),
-            unique_tiles,
-            avg_distance,
+            escape_html(&csv_path.file_name().unwrap_or_default().to_string_lossy()),
+            summary.cells,
+            summary.unique_tiles,
+            summary.fillers,
+            summary.average_distance,
             config.mode,
             config.tile_size,
             if config.no_repeat { "Yes" } else { "No" },
@@ -105,72 +123,219 @@ where
             if config.crop { "Yes" } else { "No" },
             config.tint_opacity * 100.0,
             config.downsample,
+            match (config.randomize, config.randomize_max_variance) {
+                (None, _) => "None".to_string(),
+                (Some(r), None) => format!("{:.1}%", r),
+                (Some(r), Some(v)) => format!("{:.1}% where the variance is at most {}", r, v),
+            },
+            if config.story { "Yes" } else { "No" },
+            config
+                .time_gradient
+                .and_then(|gradient| gradient.to_possible_value())
+                .map_or("None", |value| value.get_name()),
             config
-                .randomize
-                .map_or("None".to_string(), |r| format!("{:.1}%", r)),
+                .filler
+                .map_or_else(|| String::from("None"), |d| d.to_string()),
             config.tiles_dir
         ));
 
-        // Most used tiles
-        let mut usage_by_count: Vec<_> = tile_usage_count.into_iter().collect();
-        usage_by_count.sort_by(|(_, a), (_, b)| b.cmp(a));
+        append_distances_html(html, &report);
 
+        // Every image placed, the most used first
         html.push_str(
             r#"
                 <div class="stats-section">
                     <h3>Most Used Tiles</h3>
-"#,
+                    <div class="stats-table-wrapper">
+                        <table class="stats-table">
+                            <thead>
+                                <tr>
+                                    <th onclick="sortStatsTable(this, 'text')">Image</th>
+                                    <th class="sorted-desc" onclick="sortStatsTable(this, 'number')">Times</th>
+                                    <th onclick="sortStatsTable(this, 'number')">Average distance</th>
+                                </tr>
+                            </thead>
+                            <tbody>"#,
         );
-
-        for (i, (path, count)) in usage_by_count.iter().take(10).enumerate() {
+        for usage in &report.usage {
             html.push_str(&format!(
                 r#"
-                    <div class="tile-info">
-                        <span>{}. {}</span>
-                        <span>{} times</span>
-                    </div>
-"#,
-                i + 1,
-                path.file_name().unwrap_or_default().to_string_lossy(),
-                count
+                                <tr title="{}"><td>{}</td><td>{}</td><td>{:.3}</td></tr>"#,
+                escape_html(&usage.path.display().to_string()),
+                escape_html(&usage.path.file_name().unwrap_or_default().to_string_lossy()),
+                usage.count,
+                usage.average_distance
             ));
         }
+        html.push_str(
+            r#"
+                            </tbody>
+                        </table>
+                    </div>
+                </div>
+"#,
+        );
 
-        html.push_str("                </div>\n");
-
-        // Worst matches
-        let mut worst_matches: Vec<_> = self.tiles().values().collect();
-        worst_matches.sort_by(|a, b| b.colors.cmp(&a.colors));
-
+        // Every placement, the worst match first
+        let mut worst_matches = report.placements;
+        worst_matches.sort_by(|a, b| {
+            b.distance
+                .total_cmp(&a.distance)
+                .then((a.y, a.x).cmp(&(b.y, b.x)))
+        });
         html.push_str(
             r#"
                 <div class="stats-section">
                     <h3>Worst Matches</h3>
+                    <div class="stats-table-wrapper">
+                        <table class="stats-table">
+                            <thead>
+                                <tr>
+                                    <th onclick="sortStatsTable(this, 'number')">Column</th>
+                                    <th onclick="sortStatsTable(this, 'number')">Row</th>
+                                    <th onclick="sortStatsTable(this, 'text')">Image</th>
+                                    <th class="sorted-desc" onclick="sortStatsTable(this, 'number')">Distance</th>
+                                </tr>
+                            </thead>
+                            <tbody>"#,
+        );
+        for cell in &worst_matches {
+            let name = match cell.filler {
+                true => String::from("Synthetic filler"),
+                false => cell
+                    .path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into(),
+            };
+            html.push_str(&format!(
+                r#"
+                                <tr title="{}"><td>{}</td><td>{}</td><td>{}</td><td>{:.3}</td></tr>"#,
+                escape_html(&cell.path.display().to_string()),
+                cell.x,
+                cell.y,
+                escape_html(&name),
+                cell.distance
+            ));
+        }
+        html.push_str(
+            r#"
+                            </tbody>
+                        </table>
+                    </div>
+                </div>
 "#,
         );
 
-        for (i, tile) in worst_matches.iter().take(10).enumerate() {
-            let path = tile_set.get_path(tile);
-            let distance: f64 = tile.colors.into();
+        // Matches farther than the maximum distance
+        if self.max_distance().is_some() {
+            let bad_matches = self.bad_matches();
             html.push_str(&format!(
                 r#"
+                <div class="stats-section">
+                    <h3>Bad Matches ({})</h3>
+"#,
+                bad_matches.len()
+            ));
+            for ((col, row), tile) in bad_matches.iter().take(BAD_MATCHES_LISTED) {
+                let path = tile_set.get_path(tile);
+                let distance: f64 = tile.colors.into();
+                html.push_str(&format!(
+                    r#"
                     <div class="tile-info">
-                        <span>{}. {}</span>
+                        <span>({}, {}) {}</span>
                         <span class="distance-bad">{:.3}</span>
                     </div>
 "#,
-                i + 1,
-                path.file_name().unwrap_or_default().to_string_lossy(),
-                distance
-            ));
+                    col,
+                    row,
+                    path.file_name().unwrap_or_default().to_string_lossy(),
+                    distance
+                ));
+            }
+            if bad_matches.len() > BAD_MATCHES_LISTED {
+                html.push_str(&format!(
+                    r#"
+                    <div class="tile-info">
+                        <span>... and {} more</span>
+                    </div>
+"#,
+                    bad_matches.len() - BAD_MATCHES_LISTED
+                ));
+            }
+            html.push_str("                </div>\n");
         }
 
         html.push_str(
             r#"
-                </div>
             </div>
         </div>
 "#,
         );
     }
-}
\ No newline at end of file
+}
+
+/// Append a histogram of the distances of the placements, as inline SVG, and their percentiles.
+fn append_distances_html(html: &mut String, report: &UsageReport) {
+    let histogram = report.distance_histogram(HISTOGRAM_BINS);
+    let tallest = histogram.iter().copied().max().unwrap_or(0).max(1);
+    let farthest = report
+        .worst_matches
+        .first()
+        .map_or(0.0, |cell| cell.distance);
+    let bin_width = farthest / HISTOGRAM_BINS as f64;
+    let bar_width = HISTOGRAM_WIDTH / HISTOGRAM_BINS;
+    html.push_str(&format!(
+        r#"
+                <div class="stats-section">
+                    <h3>Match Distances</h3>
+                    <svg class="stats-histogram" viewBox="0 0 {} {}" role="img" aria-label="Histogram of the distances of the placements">"#,
+        HISTOGRAM_WIDTH,
+        HISTOGRAM_HEIGHT + 14
+    ));
+    for (bin, &count) in histogram.iter().enumerate() {
+        let height = count * HISTOGRAM_HEIGHT / tallest;
+        html.push_str(&format!(
+            r#"
+                        <rect x="{}" y="{}" width="{}" height="{}"><title>{:.1} to {:.1}: {} placements</title></rect>"#,
+            bin * bar_width,
+            HISTOGRAM_HEIGHT - height,
+            bar_width - 1,
+            height,
+            bin as f64 * bin_width,
+            (bin + 1) as f64 * bin_width,
+            count
+        ));
+    }
+    html.push_str(&format!(
+        r#"
+                        <text x="0" y="{y}">0</text>
+                        <text x="{}" y="{y}" text-anchor="end">{:.1}</text>
+                    </svg>
+                    <table class="stats-table">
+                        <thead>
+                            <tr><th>Percentile</th><th>Distance</th></tr>
+                        </thead>
+                        <tbody>"#,
+        HISTOGRAM_WIDTH,
+        farthest,
+        y = HISTOGRAM_HEIGHT + 12
+    ));
+    for percentile in DISTANCE_PERCENTILES {
+        if let Some(distance) = report.distance_percentile(percentile) {
+            html.push_str(&format!(
+                r#"
+                            <tr><td>p{}</td><td>{:.3}</td></tr>"#,
+                percentile, distance
+            ));
+        }
+    }
+    html.push_str(
+        r#"
+                        </tbody>
+                    </table>
+                </div>
+"#,
+    );
+}
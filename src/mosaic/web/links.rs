@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::super::pins::identity;
+use super::super::tiles::split_crop;
+
+/// Pages opened by clicking the tiles of the widget instead of their images, e.g. the page
+/// of each picture in a photo library.
+///
+/// Written in TOML as a table from tile paths to URLs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TileLinks(BTreeMap<PathBuf, String>);
+
+impl TileLinks {
+    /// Parse links written in TOML.
+    pub fn parse(text: &str) -> Result<TileLinks, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    /// Read the links in the TOML file at `path`, resolving relative tile paths from
+    /// `tiles_dir`.
+    pub fn load(path: &Path, tiles_dir: &Path) -> Result<TileLinks, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let links = TileLinks::parse(&text)?;
+        Ok(TileLinks(
+            links
+                .0
+                .into_iter()
+                .map(|(path, url)| (identity(&tiles_dir.join(path)), url))
+                .collect(),
+        ))
+    }
+
+    /// The page linked to the tile at `path`, or to the picture it is a crop of, if any.
+    pub fn get(&self, path: &Path) -> Option<&str> {
+        self.0
+            .get(&identity(split_crop(path).0))
+            .map(String::as_str)
+    }
+
+    /// Whether no tile is linked to a page.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tile_links() {
+        let links = TileLinks::parse(
+            r#"
+"2024/beach.jpg" = "https://photos.example.com/photo/1234"
+"/elsewhere/cat.png" = "https://photos.example.com/photo/5678"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            links.get(Path::new("2024/beach.jpg")),
+            Some("https://photos.example.com/photo/1234")
+        );
+        assert_eq!(links.get(Path::new("2024/dog.jpg")), None);
+        assert!(TileLinks::parse("\"cat.png\" = 3").is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("links.toml");
+        fs::write(&path, "\"2024/beach.jpg\" = \"https://example.com/1\"").unwrap();
+        let links = TileLinks::load(&path, Path::new("/photos")).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            links.get(Path::new("/photos/2024/beach.jpg")),
+            Some("https://example.com/1")
+        );
+        assert!(TileLinks::load(&path, Path::new("/photos")).is_err());
+    }
+}
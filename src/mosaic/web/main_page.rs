@@ -1,8 +1,15 @@
 use std::io::Write;
 use std::path::Path;
 
-use super::super::stats::{MosaicConfig, RenderStats};
+use image::RgbImage;
+use tracing::info;
+
+use super::super::seams::{HARSH_SEAM, WORST_SEAM};
+use super::super::stats::{DistanceScale, MosaicConfig, RenderStats};
 use super::super::tiles::TileSet;
+use super::super::usage::UsageFormat;
+use super::palette::OVERLAY_CLASSES;
+use super::{escape_html, metadata_tags};
 
 impl<D> RenderStats<D>
 where
@@ -21,17 +28,19 @@ where
     /// * `tile_set` - The tile set used for generating the mosaic
     /// * `config` - Configuration settings used to generate the mosaic
     /// * `web_compatible` - If true, generates relative URLs suitable for web hosting
+    /// * `pyramid` - The mosaic image, to embed it as a zoomable tile pyramid instead of a single image
     ///
     /// # Returns
     /// * `Ok(())` - If HTML file was successfully generated
     /// * `Err(std::io::Error)` - If file writing failed
-    pub fn generate_html_with_options<T>(
+    pub fn generate_html_with_options<T: Sync>(
         &self,
         mosaic_image_path: &Path,
         output_path: &Path,
         tile_set: &TileSet<T>,
         config: &MosaicConfig,
         web_compatible: bool,
+        pyramid: Option<&RgbImage>,
     ) -> Result<(), std::io::Error> {
         if self.tiles().is_empty() {
             return Err(std::io::Error::new(
@@ -40,6 +49,13 @@ where
             ));
         }
 
+        if web_compatible {
+            let exported = self.export_thumbnails(output_path, tile_set, config)?;
+            if exported > 0 {
+                info!("🖼️  Exported {} tile thumbnails", exported);
+            }
+        }
+
         // First, generate the standalone mosaic widget
         let widget_path = output_path.with_file_name(format!(
             "{}_widget.html",
@@ -49,21 +65,42 @@ where
                 .to_string_lossy()
         ));
 
-        self.generate_mosaic_widget_with_options(
-            mosaic_image_path,
-            &widget_path,
-            tile_set,
-            config,
-            web_compatible,
-        )?;
+        match pyramid {
+            Some(mosaic_image) => self.generate_pyramid_widget(
+                mosaic_image_path,
+                mosaic_image,
+                &widget_path,
+                tile_set,
+                config,
+                web_compatible,
+            )?,
+            None => self.generate_mosaic_widget_with_options(
+                mosaic_image_path,
+                &widget_path,
+                tile_set,
+                config,
+                web_compatible,
+            )?,
+        }
+
+        // Then the full statistics, for the download link of the statistics section
+        let csv_path = output_path.with_file_name(format!(
+            "{}_stats.csv",
+            output_path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+        ));
+        self.usage_report(tile_set)
+            .save(&csv_path, UsageFormat::Csv)?;
 
         let mut html = String::new();
 
         // Generate HTML header and structure
-        self.append_main_page_header(&mut html, mosaic_image_path, &widget_path);
+        self.append_main_page_header(&mut html, &widget_path, config);
 
         // Generate statistics section
-        self.append_stats_html(&mut html, tile_set, config);
+        self.append_stats_html(&mut html, tile_set, config, &csv_path);
 
         // Close HTML document
         html.push_str(
@@ -81,13 +118,32 @@ where
     }
 
     /// Generate the main page HTML header with CSS and JavaScript
-    fn append_main_page_header(&self, html: &mut String, mosaic_image_path: &Path, widget_path: &Path) {
+    fn append_main_page_header(
+        &self,
+        html: &mut String,
+        widget_path: &Path,
+        config: &MosaicConfig,
+    ) {
+        let mut byline = String::new();
+        if let Some(description) = &config.description {
+            byline.push_str(&format!(
+                "\n        <p class=\"description\">{}</p>",
+                escape_html(description)
+            ));
+        }
+        if let Some(author) = &config.author {
+            byline.push_str(&format!(
+                "\n        <p class=\"author\">By {}</p>",
+                escape_html(author)
+            ));
+        }
+
         html.push_str(&format!(r#"<!DOCTYPE html>
 <html lang="en">
 <head>
     <meta charset="UTF-8">
     <meta name="viewport" content="width=device-width, initial-scale=1.0">
-    <title>Mosaic Visualization - {}</title>
+{}    <title>{} - Mosaic Visualization</title>
     <style>
         body {{
             font-family: Arial, sans-serif;
@@ -103,6 +159,13 @@ where
             border-radius: 8px;
             box-shadow: 0 2px 4px rgba(0,0,0,0.1);
         }}
+        .description {{
+            color: #333;
+        }}
+        .author {{
+            color: #666;
+            font-style: italic;
+        }}
         .mosaic-frame {{
             margin: 20px 0;
             border: 1px solid #ddd;
@@ -142,6 +205,52 @@ where
             margin-top: 0;
             color: #555;
         }}
+        .stats-download {{
+            display: inline-block;
+            text-decoration: none;
+        }}
+        .stats-histogram {{
+            width: 100%;
+            margin-bottom: 10px;
+        }}
+        .stats-histogram rect {{
+            fill: #007bff;
+        }}
+        .stats-histogram rect:hover {{
+            fill: #0056b3;
+        }}
+        .stats-histogram text {{
+            font-size: 10px;
+            fill: #555;
+        }}
+        .stats-table-wrapper {{
+            max-height: 320px;
+            overflow-y: auto;
+        }}
+        .stats-table {{
+            width: 100%;
+            border-collapse: collapse;
+            font-size: 13px;
+        }}
+        .stats-table th {{
+            position: sticky;
+            top: 0;
+            background: white;
+            text-align: left;
+            cursor: pointer;
+            user-select: none;
+            border-bottom: 1px solid #ddd;
+        }}
+        .stats-table th.sorted-asc::after {{
+            content: " ▲";
+        }}
+        .stats-table th.sorted-desc::after {{
+            content: " ▼";
+        }}
+        .stats-table td {{
+            padding: 4px 8px 4px 0;
+            border-bottom: 1px solid #eee;
+        }}
         .tile-info {{
             display: flex;
             justify-content: space-between;
@@ -151,9 +260,6 @@ where
         .tile-info:last-child {{
             border-bottom: none;
         }}
-        .distance-good {{ color: #28a745; }}
-        .distance-medium {{ color: #ffc107; }}
-        .distance-bad {{ color: #dc3545; }}
 
         /* Distance overlay controls */
         .distance-toggle {{
@@ -184,6 +290,10 @@ where
             display: inline-block;
             margin: 5px 10px 5px 0;
         }}
+        .legend-calibration {{
+            margin: 5px 0 0;
+            color: #555;
+        }}
         .legend-color {{
             display: inline-block;
             width: 20px;
@@ -192,13 +302,8 @@ where
             vertical-align: middle;
             border: 1px solid #ccc;
         }}
-        /* Distance color coding for overlay */
-        .overlay-distance-excellent {{ background: rgba(0, 255, 0, 0.8); }}
-        .overlay-distance-good {{ background: rgba(40, 167, 69, 0.8); }}
-        .overlay-distance-medium {{ background: rgba(255, 193, 7, 0.8); }}
-        .overlay-distance-poor {{ background: rgba(255, 152, 0, 0.8); }}
-        .overlay-distance-bad {{ background: rgba(220, 53, 69, 0.8); }}
-    </style>
+        /* Distance colors of the palette */
+{}    </style>
     <script>
         function toggleDistanceOverlay() {{
             const iframe = document.getElementById('mosaic-iframe');
@@ -234,42 +339,153 @@ where
             }}
         }});
 
-        // Make function globally accessible
+        function toggleSeamOverlay() {{
+            const iframe = document.getElementById('mosaic-iframe');
+            if (iframe) {{
+                iframe.contentWindow.postMessage({{
+                    type: 'toggleSeamOverlay'
+                }}, '*');
+            }}
+        }}
+
+        // Listen for the seam overlay being toggled in the iframe
+        window.addEventListener('message', function(event) {{
+            if (event.data.type === 'seamOverlayToggled') {{
+                const legend = document.getElementById('seam-legend');
+                const button = document.getElementById('seam-toggle-btn');
+
+                if (legend && button) {{
+                    legend.classList.toggle('visible', event.data.visible);
+                    button.textContent = event.data.visible ? 'Hide Seam Overlay' : 'Show Seam Overlay';
+                }}
+            }}
+        }});
+
+        // Sort the rows of a statistics table by the column of its header `th`, of numbers or
+        // text, flipping the order when sorted by that column already
+        function sortStatsTable(th, kind) {{
+            const table = th.closest('table');
+            const column = Array.from(th.parentNode.children).indexOf(th);
+            const ascending = !th.classList.contains('sorted-asc');
+            const value = row => row.children[column].textContent;
+            const compare = kind === 'number'
+                ? (a, b) => parseFloat(value(a)) - parseFloat(value(b))
+                : (a, b) => value(a).localeCompare(value(b));
+            const body = table.tBodies[0];
+            const rows = Array.from(body.rows);
+            rows.sort((a, b) => ascending ? compare(a, b) : compare(b, a));
+            rows.forEach(row => body.appendChild(row));
+            th.parentNode.querySelectorAll('th').forEach(other => {{
+                other.classList.remove('sorted-asc', 'sorted-desc');
+            }});
+            th.classList.add(ascending ? 'sorted-asc' : 'sorted-desc');
+        }}
+
+        // Make functions globally accessible
         window.toggleDistanceOverlay = toggleDistanceOverlay;
+        window.sortStatsTable = sortStatsTable;
+        window.toggleSeamOverlay = toggleSeamOverlay;
     </script>
 </head>
 <body>
     <div class="container">
-        <h1>Mosaic Visualization</h1>
+        <h1>{}</h1>{}
         <p>Hover over any tile to see detailed information including distance score and source file. <strong>Click on any tile to open the original image in a new tab.</strong></p>
 
         <button id="distance-toggle-btn" class="distance-toggle" onclick="toggleDistanceOverlay()">Show Distance Overlay</button>
 
         <div id="distance-legend" class="distance-legend">
-            <strong>Distance Legend:</strong>
-            <div class="legend-item">
-                <span class="legend-color overlay-distance-excellent"></span>Excellent (0-20%)
-            </div>
-            <div class="legend-item">
-                <span class="legend-color overlay-distance-good"></span>Good (20-40%)
-            </div>
-            <div class="legend-item">
-                <span class="legend-color overlay-distance-medium"></span>Medium (40-60%)
-            </div>
-            <div class="legend-item">
-                <span class="legend-color overlay-distance-poor"></span>Poor (60-80%)
-            </div>
-            <div class="legend-item">
-                <span class="legend-color overlay-distance-bad"></span>Bad (80-100%)
-            </div>
+            <strong>Distance Legend:</strong>{}
         </div>
-
+{}
         <div class="mosaic-frame">
             <iframe id="mosaic-iframe" class="mosaic-iframe" src="{}" title="Interactive Mosaic Visualization"></iframe>
         </div>
 "#,
-            mosaic_image_path.file_name().unwrap_or_default().to_string_lossy(),
+            metadata_tags(config, "    "),
+            escape_html(&config.title),
+            config.palette.css("        "),
+            escape_html(&config.title),
+            byline,
+            self.distance_legend(),
+            self.seam_controls(),
             widget_path.file_name().unwrap_or_default().to_string_lossy()
         ));
     }
-}
\ No newline at end of file
+    /// Button and legend of the seam overlay, if the junctions were measured.
+    fn seam_controls(&self) -> String {
+        let seams = match self.seams() {
+            Some(seams) => seams,
+            None => return String::new(),
+        };
+        let labels = ["Seamless", "Soft", "Visible", "Harsh", "Jarring"];
+        let mut legend = String::new();
+        let bound = |i: usize| i as f64 / labels.len() as f64 * WORST_SEAM;
+        for (i, (label, class)) in labels.iter().zip(OVERLAY_CLASSES).enumerate() {
+            let range = if i + 1 == labels.len() {
+                format!("{:.0}+", bound(i))
+            } else {
+                format!("{:.0}-{:.0}", bound(i), bound(i + 1))
+            };
+            legend.push_str(&format!(
+                r#"
+            <div class="legend-item">
+                <span class="legend-color {}"></span>{} ({})
+            </div>"#,
+                class, label, range
+            ));
+        }
+        format!(
+            r#"
+        <button id="seam-toggle-btn" class="distance-toggle" onclick="toggleSeamOverlay()">Show Seam Overlay</button>
+
+        <div id="seam-legend" class="distance-legend">
+            <strong>Seam Legend:</strong>{}
+            <p class="legend-calibration">Delta-E across the junctions between the tiles: {:.1} on average, {:.1}% above {}. Harsh junctions show the grid; --continuity-weight or --tint-opacity smooth them.</p>
+        </div>
+"#,
+            legend,
+            seams.mean(),
+            100.0 * seams.share_above(HARSH_SEAM),
+            HARSH_SEAM
+        )
+    }
+
+    /// Items of the legend of the distance overlay, with the calibration of an absolute scale.
+    fn distance_legend(&self) -> String {
+        let labels = ["Excellent", "Good", "Medium", "Poor", "Bad"];
+        let mut legend = String::new();
+        let bounds = |i: usize| i as f64 / labels.len() as f64;
+        for (i, (label, class)) in labels.iter().zip(OVERLAY_CLASSES).enumerate() {
+            let range = match self.distance_scale() {
+                DistanceScale::Relative => {
+                    format!("{:.0}-{:.0}%", bounds(i) * 100.0, bounds(i + 1) * 100.0)
+                }
+                DistanceScale::Absolute { .. } if i + 1 == labels.len() => {
+                    format!("{:.0}+", bounds(i) * DistanceScale::WORST_PER_PIXEL)
+                }
+                DistanceScale::Absolute { .. } => format!(
+                    "{:.0}-{:.0}",
+                    bounds(i) * DistanceScale::WORST_PER_PIXEL,
+                    bounds(i + 1) * DistanceScale::WORST_PER_PIXEL
+                ),
+            };
+            legend.push_str(&format!(
+                r#"
+            <div class="legend-item">
+                <span class="legend-color {}"></span>{} ({})
+            </div>"#,
+                class, label, range
+            ));
+        }
+        if let DistanceScale::Absolute { pixels } = self.distance_scale() {
+            legend.push_str(&format!(
+                r#"
+            <p class="legend-calibration">Absolute scale: color distance per source pixel, summed over the RGB channels. Cells cover {} pixels, so a cell is bad from a total distance of {:.0}.</p>"#,
+                pixels,
+                bounds(labels.len() - 1) * DistanceScale::WORST_PER_PIXEL * pixels as f64
+            ));
+        }
+        legend
+    }
+}
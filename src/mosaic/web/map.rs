@@ -0,0 +1,122 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use rayon::prelude::*;
+use serde::Serialize;
+
+use super::super::tiles::{read_exif_metadata, split_crop};
+use super::tile_path_hash;
+
+/// Where the stylesheet and script of Leaflet are loaded from, the first time the map is shown.
+const LEAFLET_URL: &str = "https://unpkg.com/leaflet@1.9.4/dist";
+
+/// Where a picture placed in the mosaic was taken, from its EXIF GPS position.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PhotoLocation {
+    /// Hash of the path of the picture, as in the `data-tile-hash` of its tile regions
+    pub hash: String,
+    /// File name of the picture
+    pub name: String,
+    /// Degrees, positive to the north
+    pub latitude: f64,
+    /// Degrees, positive to the east
+    pub longitude: f64,
+}
+
+/// Read where the pictures at `paths` were taken, leaving out those without a GPS position.
+pub fn locate_photos(paths: &[&Path]) -> Vec<PhotoLocation> {
+    paths
+        .par_iter()
+        .filter_map(|path| {
+            let mut reader = BufReader::new(File::open(split_crop(path).0).ok()?);
+            let (latitude, longitude) = read_exif_metadata(&mut reader).ok()?.gps?;
+            Some(PhotoLocation {
+                hash: tile_path_hash(path),
+                name: path.file_name()?.to_string_lossy().into_owned(),
+                latitude,
+                longitude,
+            })
+        })
+        .collect()
+}
+
+/// Append the button and pane of the map of `locations` to the widget. The map itself is drawn
+/// by the script of the widget, with Leaflet loaded when it is first shown.
+pub(crate) fn append_map_pane(html: &mut String, locations: &[PhotoLocation]) {
+    // Closing tags in the names must not end the script early
+    let locations = serde_json::to_string(locations)
+        .unwrap_or_else(|_| String::from("[]"))
+        .replace("</", "<\\/");
+    html.push_str(&format!(
+        r#"
+    <!-- Map of where the pictures were taken -->
+    <button id="map-toggle-btn" class="map-toggle" onclick="toggleMapPane()">🗺️ Map</button>
+    <div id="map-pane" class="map-pane">
+        <div id="photo-map" class="photo-map"></div>
+    </div>
+    <script>
+        var leafletUrl = '{}';
+        var photoLocations = {};
+    </script>
+"#,
+        LEAFLET_URL, locations
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_map_pane() {
+        let locations = [PhotoLocation {
+            hash: tile_path_hash(Path::new("/photos/a.jpg")),
+            name: String::from("</script>.jpg"),
+            latitude: -40.5,
+            longitude: 3.75,
+        }];
+        let mut html = String::new();
+        append_map_pane(&mut html, &locations);
+        assert!(html.contains(r#"<div id="photo-map" class="photo-map"></div>"#));
+        assert!(html.contains(r#""latitude":-40.5,"longitude":3.75"#));
+        assert!(html.contains(&format!(r#""hash":"{}""#, locations[0].hash)));
+        // Names cannot close the script
+        assert_eq!(html.matches("</script>").count(), 1);
+    }
+
+    #[test]
+    fn test_locate_photos() {
+        use super::super::super::tiles::jpeg_with_exif;
+        use exif::{Field, In, Tag, Value};
+
+        let field = |tag, value| Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value,
+        };
+        let dms = |d, m, s| Value::Rational(vec![(d, 1).into(), (m, 1).into(), (s, 1).into()]);
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let located = dir.join("located.jpg");
+        let jpeg = jpeg_with_exif(&[
+            field(Tag::GPSLatitude, dms(48, 51, 0)),
+            field(Tag::GPSLatitudeRef, Value::Ascii(vec![b"N".to_vec()])),
+            field(Tag::GPSLongitude, dms(2, 21, 0)),
+            field(Tag::GPSLongitudeRef, Value::Ascii(vec![b"E".to_vec()])),
+        ]);
+        std::fs::write(&located, jpeg).unwrap();
+        let unlocated = dir.join("unlocated.jpg");
+        let make = field(Tag::Make, Value::Ascii(vec![b"Canon".to_vec()]));
+        std::fs::write(&unlocated, jpeg_with_exif(&[make])).unwrap();
+
+        // Pictures that cannot be read or have no GPS position are left out
+        let missing = dir.join("missing.jpg");
+        let locations = locate_photos(&[&located, &unlocated, &missing]);
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].hash, tile_path_hash(&located));
+        assert_eq!(locations[0].name, "located.jpg");
+        assert!((locations[0].latitude - 48.85).abs() < 1e-9);
+        assert!((locations[0].longitude - 2.35).abs() < 1e-9);
+    }
+}
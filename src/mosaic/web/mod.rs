@@ -1,3 +1,235 @@
 pub mod widget;
 pub mod html_stats;
-pub mod main_page;
\ No newline at end of file
+pub mod links;
+pub mod main_page;
+pub mod map;
+pub mod palette;
+pub mod pyramid;
+pub mod serve;
+pub mod thumbnails;
+
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use super::stats::MosaicConfig;
+use super::tiles::split_crop;
+
+/// Escape text for inclusion in HTML content and attribute values.
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// `<meta>` tags describing the mosaic, one per line with the given indentation.
+pub(crate) fn metadata_tags(config: &MosaicConfig, indent: &str) -> String {
+    let mut tags = format!(
+        "{}<meta property=\"og:title\" content=\"{}\">\n",
+        indent,
+        escape_html(&config.title)
+    );
+    if let Some(description) = &config.description {
+        let description = escape_html(description);
+        tags.push_str(&format!(
+            "{indent}<meta name=\"description\" content=\"{d}\">\n{indent}<meta property=\"og:description\" content=\"{d}\">\n",
+            indent = indent,
+            d = description
+        ));
+    }
+    if let Some(author) = &config.author {
+        tags.push_str(&format!(
+            "{}<meta name=\"author\" content=\"{}\">\n",
+            indent,
+            escape_html(author)
+        ));
+    }
+    tags
+}
+
+/// Path of a tile relative to the `tiles/` directory deployed next to the pages: its path
+/// relative to the tiles directory, or just its file name when it is outside of it.
+/// Thumbnails are always JPEG images. Crops share the file of their picture.
+pub(crate) fn web_tile_path(tile_path: &Path, config: &MosaicConfig) -> PathBuf {
+    let tile_path = split_crop(tile_path).0;
+    let relative = match tile_path.strip_prefix(&config.tiles_dir) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => PathBuf::from(tile_path.file_name().unwrap_or_default()),
+    };
+    if config.thumbnail_size.is_some() {
+        relative.with_extension("jpg")
+    } else {
+        relative
+    }
+}
+
+/// URL of a tile image as seen from the generated pages: relative to a `tiles/` directory
+/// next to the page when hosting on the web, or a `file://` URL to the original otherwise.
+/// The URL of a crop is that of its picture followed by the fragment naming its region.
+pub(crate) fn tile_image_url(
+    tile_path: &Path,
+    config: &MosaicConfig,
+    web_compatible: bool,
+) -> String {
+    let (file, region) = split_crop(tile_path);
+    let url = if web_compatible {
+        format!("tiles/{}", web_tile_path(file, config).display())
+    } else if file.is_absolute() {
+        format!("file://{}", file.display())
+    } else {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        format!("file://{}", cwd.join(file).display())
+    };
+    match region {
+        Some(region) => url + &region.fragment(),
+        None => url,
+    }
+}
+
+/// Short hash of the path of a tile, identifying its regions in the widget, e.g. for flagging.
+pub(crate) fn tile_path_hash(tile_path: &Path) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(tile_path.to_string_lossy().as_bytes());
+    format!("{:x}", hasher.finalize())[..16].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> MosaicConfig {
+        MosaicConfig {
+            tile_size: 16,
+            mode: "1x1 (N=1)".to_string(),
+            no_repeat: false,
+            greedy: false,
+            story: false,
+            time_gradient: None,
+            filler: None,
+            crop: false,
+            tint_opacity: 0.0,
+            downsample: 1,
+            randomize: None,
+            randomize_max_variance: None,
+            tiles_dir: "tiles".to_string(),
+            title: "Tom & Jerry".to_string(),
+            description: None,
+            author: None,
+            thumbnail_size: None,
+            export_quality: None,
+            export_strip_exif: false,
+            date_format: Default::default(),
+            palette: Default::default(),
+            tile_links: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html(r#"<a href="x">'&'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn test_metadata_tags() {
+        let tags = metadata_tags(&config(), "");
+        assert_eq!(
+            tags,
+            "<meta property=\"og:title\" content=\"Tom &amp; Jerry\">\n"
+        );
+
+        let config = MosaicConfig {
+            description: Some("A \"cat\" and mouse".to_string()),
+            author: Some("Hanna".to_string()),
+            ..config()
+        };
+        let tags = metadata_tags(&config, "  ");
+        assert!(tags
+            .contains("  <meta name=\"description\" content=\"A &quot;cat&quot; and mouse\">\n"));
+        assert!(tags.contains("  <meta property=\"og:description\""));
+        assert!(tags.contains("  <meta name=\"author\" content=\"Hanna\">\n"));
+    }
+
+    #[test]
+    fn test_tile_image_url() {
+        let path = Path::new("/photos/2024/cat.png");
+        let config = MosaicConfig {
+            tiles_dir: "/photos".to_string(),
+            ..config()
+        };
+        assert_eq!(tile_image_url(path, &config, true), "tiles/2024/cat.png");
+        assert_eq!(
+            tile_image_url(path, &config, false),
+            "file:///photos/2024/cat.png"
+        );
+
+        let elsewhere = MosaicConfig {
+            tiles_dir: "/elsewhere".to_string(),
+            ..config.clone()
+        };
+        assert_eq!(tile_image_url(path, &elsewhere, true), "tiles/cat.png");
+
+        let thumbnails = MosaicConfig {
+            thumbnail_size: Some(512),
+            export_quality: None,
+            export_strip_exif: false,
+            ..config
+        };
+        assert_eq!(
+            tile_image_url(path, &thumbnails, true),
+            "tiles/2024/cat.jpg"
+        );
+
+        let crop = Path::new("/photos/2024/cat.png#xywh=percent:0,0,70,70");
+        assert_eq!(
+            tile_image_url(crop, &thumbnails, true),
+            "tiles/2024/cat.jpg#xywh=percent:0,0,70,70"
+        );
+        assert_eq!(
+            tile_image_url(crop, &thumbnails, false),
+            "file:///photos/2024/cat.png#xywh=percent:0,0,70,70"
+        );
+    }
+
+    #[test]
+    fn test_stats_tables() {
+        use super::super::stats::RenderStats;
+        use super::super::tiles::TileSet;
+        use super::super::time_gradient::TimeGradient;
+        use image::Rgb;
+
+        let mut tile_set: TileSet<[Rgb<u8>; 1]> = TileSet::new();
+        tile_set.push_tile(PathBuf::from("/photos/a.jpg"), [Rgb([255, 0, 0])]);
+        tile_set.push_tile(PathBuf::from("/photos/<b>.jpg"), [Rgb([0, 0, 255])]);
+        let (a, b) = (tile_set.tiles[0].clone(), tile_set.tiles[1].clone());
+        let mut stats: RenderStats<u32> = RenderStats::new();
+        stats.push_tile(0, 0, &a, 10);
+        stats.push_tile(1, 0, &b, 40);
+        stats.push_tile(0, 1, &a, 30);
+
+        let config = MosaicConfig {
+            time_gradient: Some(TimeGradient::LeftRight),
+            filler: Some(40),
+            ..config()
+        };
+        let mut html = String::new();
+        stats.append_stats_html(&mut html, &tile_set, &config, Path::new("/out/m_stats.csv"));
+        assert!(html.contains(r#"href="m_stats.csv" download"#));
+        // The settings of the run are listed
+        assert!(html.contains("<span>left-right</span>"));
+        assert!(html.contains("<span>40</span>"));
+        // Every image and every placement is listed, the worst match first
+        assert_eq!(html.matches("<tr title=").count(), 5);
+        assert!(html.contains("<td>&lt;b&gt;.jpg</td>"));
+        let worst = html.find("<td>1</td><td>0</td>").unwrap();
+        assert!(worst < html.find("<td>0</td><td>1</td>").unwrap());
+        // The distances are drawn as a histogram, with their percentiles
+        assert_eq!(html.matches("<rect ").count(), 20);
+        assert!(html.contains("<tr><td>p50</td><td>30.000</td></tr>"));
+        assert!(html.contains("<tr><td>p99</td><td>40.000</td></tr>"));
+    }
+}
@@ -0,0 +1,175 @@
+use serde::{Deserialize, Serialize};
+
+/// Classes of the distance overlay, from the best matches to the worst.
+pub(crate) const OVERLAY_CLASSES: [&str; 5] = [
+    "overlay-distance-excellent",
+    "overlay-distance-good",
+    "overlay-distance-medium",
+    "overlay-distance-poor",
+    "overlay-distance-bad",
+];
+
+/// Classes of the distances written in the tooltips and statistics, from the best matches to
+/// the worst, with where their colors are taken in the scheme.
+pub(crate) const TEXT_CLASSES: [(&str, f64); 3] = [
+    ("distance-good", 0.25),
+    ("distance-medium", 0.5),
+    ("distance-bad", 1.0),
+];
+
+/// Color scheme of the distance overlay and of the distances in the HTML pages.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Palette {
+    /// Green for good matches through yellow to red for bad ones
+    #[default]
+    Classic,
+    /// Perceptually uniform, from dark purple for good matches to yellow for bad ones
+    Viridis,
+    /// Like viridis, from dark blue to yellow, and readable with any color vision deficiency
+    Cividis,
+    /// Blue for good matches through yellow to vermillion, from the Okabe-Ito colors that
+    /// stay distinct without green-sensitive cones
+    DeuteranopiaSafe,
+    /// Rainbow from dark blue for good matches through green to dark red, telling more
+    /// distances apart than viridis at the cost of perceptual uniformity
+    Turbo,
+}
+
+impl Palette {
+    /// Colors the scheme is interpolated between, evenly spread from the best matches to the
+    /// worst.
+    fn stops(self) -> &'static [[u8; 3]] {
+        match self {
+            Palette::Classic => &[
+                [0, 255, 0],
+                [40, 167, 69],
+                [255, 193, 7],
+                [255, 152, 0],
+                [220, 53, 69],
+            ],
+            Palette::Viridis => &[
+                [68, 1, 84],
+                [59, 82, 139],
+                [33, 145, 140],
+                [94, 201, 98],
+                [253, 231, 37],
+            ],
+            Palette::Cividis => &[
+                [0, 34, 78],
+                [65, 77, 107],
+                [124, 123, 120],
+                [188, 175, 111],
+                [254, 232, 56],
+            ],
+            Palette::DeuteranopiaSafe => &[
+                [0, 114, 178],
+                [86, 180, 233],
+                [240, 228, 66],
+                [230, 159, 0],
+                [213, 94, 0],
+            ],
+            Palette::Turbo => &[
+                [48, 18, 59],
+                [70, 134, 251],
+                [27, 229, 181],
+                [164, 252, 60],
+                [251, 185, 56],
+                [228, 81, 23],
+                [122, 4, 3],
+            ],
+        }
+    }
+
+    /// Color of a match at `t`, from 0 for the best matches to 1 for the worst.
+    pub fn color(self, t: f64) -> [u8; 3] {
+        let stops = self.stops();
+        let scaled = t.clamp(0.0, 1.0) * (stops.len() - 1) as f64;
+        let i = (scaled as usize).min(stops.len() - 2);
+        let frac = scaled - i as f64;
+        let mut color = [0; 3];
+        for (c, (a, b)) in color.iter_mut().zip(stops[i].iter().zip(&stops[i + 1])) {
+            *c = (f64::from(*a) + (f64::from(*b) - f64::from(*a)) * frac).round() as u8;
+        }
+        color
+    }
+
+    /// CSS rules coloring the overlay and distance classes, each line with the given
+    /// indentation.
+    pub fn css(self, indent: &str) -> String {
+        let mut css = String::new();
+        for (class, t) in TEXT_CLASSES {
+            let [r, g, b] = self.color(t);
+            css.push_str(&format!(
+                "{}.{} {{ color: rgb({}, {}, {}); }}\n",
+                indent, class, r, g, b
+            ));
+        }
+        for (i, class) in OVERLAY_CLASSES.iter().enumerate() {
+            let [r, g, b] = self.color(i as f64 / (OVERLAY_CLASSES.len() - 1) as f64);
+            css.push_str(&format!(
+                "{}.{} {{ background: rgba({}, {}, {}, 0.8); }}\n",
+                indent, class, r, g, b
+            ));
+        }
+        css
+    }
+}
+
+/// Class of the overlay tile of a match at `t`, from 0 for the best matches to 1 for the
+/// worst.
+pub(crate) fn overlay_class(t: f64) -> &'static str {
+    let i = (t * OVERLAY_CLASSES.len() as f64) as usize;
+    OVERLAY_CLASSES[i.min(OVERLAY_CLASSES.len() - 1)]
+}
+
+/// Class of the distance of a match at `t` in the tooltips, from 0 for the best matches to 1
+/// for the worst.
+pub(crate) fn text_class(t: f64) -> &'static str {
+    let i = if t < 0.40 {
+        0
+    } else if t < 0.60 {
+        1
+    } else {
+        2
+    };
+    TEXT_CLASSES[i].0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_palette_color() {
+        assert_eq!(Palette::Viridis.color(0.0), [68, 1, 84]);
+        assert_eq!(Palette::Viridis.color(1.0), [253, 231, 37]);
+        assert_eq!(Palette::Viridis.color(2.0), [253, 231, 37]);
+        assert_eq!(Palette::DeuteranopiaSafe.color(0.125), [43, 147, 206]);
+        assert_eq!(Palette::Turbo.color(0.5), [164, 252, 60]);
+    }
+
+    #[test]
+    fn test_palette_css() {
+        // The classic scheme keeps the colors the pages always had
+        let css = Palette::Classic.css("");
+        assert!(css.contains(".overlay-distance-excellent { background: rgba(0, 255, 0, 0.8); }"));
+        assert!(css.contains(".overlay-distance-bad { background: rgba(220, 53, 69, 0.8); }"));
+        assert!(css.contains(".distance-good { color: rgb(40, 167, 69); }"));
+        assert!(css.contains(".distance-bad { color: rgb(220, 53, 69); }"));
+        let css = Palette::Cividis.css("    ");
+        assert_eq!(
+            css.lines().count(),
+            TEXT_CLASSES.len() + OVERLAY_CLASSES.len()
+        );
+        assert!(css.contains("    .overlay-distance-good { background: rgba(65, 77, 107, 0.8); }"));
+    }
+
+    #[test]
+    fn test_overlay_class() {
+        assert_eq!(overlay_class(0.0), "overlay-distance-excellent");
+        assert_eq!(overlay_class(0.5), "overlay-distance-medium");
+        assert_eq!(overlay_class(1.0), "overlay-distance-bad");
+        assert_eq!(text_class(0.5), "distance-medium");
+    }
+}
@@ -0,0 +1,335 @@
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{self, FilterType};
+use image::RgbImage;
+use rayon::prelude::*;
+use serde_json::json;
+
+use super::super::stats::{MosaicConfig, RenderStats};
+use super::super::tiles::TileSet;
+use super::{escape_html, metadata_tags, tile_image_url};
+
+/// Size of the square tiles of the pyramid, without their overlap.
+pub const TILE_SIZE: u32 = 254;
+/// Pixels shared with each neighbouring tile, so that zoomed tiles join without seams.
+pub const OVERLAP: u32 = 1;
+const JPEG_QUALITY: u8 = 90;
+const OPENSEADRAGON_URL: &str =
+    "https://cdn.jsdelivr.net/npm/openseadragon@4.1/build/openseadragon";
+
+/// The full resolution level of a Deep Zoom pyramid: level 0 is a single pixel and each
+/// level doubles the size of the previous one.
+pub fn max_level(width: u32, height: u32) -> u32 {
+    let size = width.max(height).max(1);
+    32 - (size - 1).leading_zeros()
+}
+
+/// Dimensions of the image at `level` of the pyramid for a `width` x `height` image.
+pub fn level_dimensions(width: u32, height: u32, level: u32) -> (u32, u32) {
+    let scale = 1u64 << (max_level(width, height) - level);
+    let scaled = |size: u32| u64::from(size).div_ceil(scale) as u32;
+    (scaled(width), scaled(height))
+}
+
+/// Number of columns and rows of tiles of a level.
+fn tile_counts((width, height): (u32, u32)) -> (u32, u32) {
+    (width.div_ceil(TILE_SIZE), height.div_ceil(TILE_SIZE))
+}
+
+/// Region `(x, y, width, height)` of a level covered by the tile at `col`, `row`, including
+/// its overlap with the neighbouring tiles.
+fn tile_rect(col: u32, row: u32, (width, height): (u32, u32)) -> (u32, u32, u32, u32) {
+    let span = |index: u32, size: u32| {
+        let start = (index * TILE_SIZE).saturating_sub(OVERLAP);
+        let end = ((index + 1) * TILE_SIZE + OVERLAP).min(size);
+        (start, end - start)
+    };
+    let (x, w) = span(col, width);
+    let (y, h) = span(row, height);
+    (x, y, w, h)
+}
+
+/// Directory holding the tiles of the pyramid described by `dzi_path`.
+fn files_dir(dzi_path: &Path) -> PathBuf {
+    dzi_path.with_file_name(format!(
+        "{}_files",
+        dzi_path.file_stem().unwrap_or_default().to_string_lossy()
+    ))
+}
+
+fn write_tile(image: &RgbImage, rect: (u32, u32, u32, u32), path: &Path) -> io::Result<()> {
+    let (x, y, width, height) = rect;
+    let tile = imageops::crop_imm(image, x, y, width, height).to_image();
+    let writer = BufWriter::new(fs::File::create(path)?);
+    JpegEncoder::new_with_quality(writer, JPEG_QUALITY)
+        .encode_image(&tile)
+        .map_err(io::Error::other)
+}
+
+/// Slice `image` into a Deep Zoom (DZI) tile pyramid: the `dzi_path` descriptor and
+/// the JPEG tiles in `{stem}_files/{level}/{col}_{row}.jpg` next to it.
+pub fn write_deep_zoom(image: &RgbImage, dzi_path: &Path) -> io::Result<()> {
+    let (width, height) = image.dimensions();
+    fs::write(
+        dzi_path,
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<Image xmlns="http://schemas.microsoft.com/deepzoom/2008" TileSize="{}" Overlap="{}" Format="jpg">
+    <Size Width="{}" Height="{}"/>
+</Image>
+"#,
+            TILE_SIZE, OVERLAP, width, height
+        ),
+    )?;
+
+    let files_dir = files_dir(dzi_path);
+    // Each level is downscaled from the one above it rather than from the full image
+    let mut downscaled: Option<RgbImage> = None;
+    for level in (0..=max_level(width, height)).rev() {
+        let level_image = downscaled.as_ref().unwrap_or(image);
+        let dimensions = level_image.dimensions();
+        let level_dir = files_dir.join(level.to_string());
+        fs::create_dir_all(&level_dir)?;
+
+        let (cols, rows) = tile_counts(dimensions);
+        (0..cols * rows).into_par_iter().try_for_each(|i| {
+            let (col, row) = (i % cols, i / cols);
+            let path = level_dir.join(format!("{}_{}.jpg", col, row));
+            write_tile(level_image, tile_rect(col, row, dimensions), &path)
+        })?;
+
+        if level > 0 {
+            let (w, h) = level_dimensions(width, height, level - 1);
+            downscaled = Some(imageops::resize(level_image, w, h, FilterType::Triangle));
+        }
+    }
+    Ok(())
+}
+
+impl<D> RenderStats<D>
+where
+    f64: From<D>,
+    D: std::cmp::Ord,
+    D: std::convert::From<u8>,
+    D: std::ops::AddAssign,
+    D: Copy,
+    D: std::fmt::Display,
+{
+    /// Generate a zoomable widget backed by a Deep Zoom tile pyramid of the mosaic, for
+    /// mosaics too large to be shown as a single image.
+    ///
+    /// # Arguments
+    /// * `mosaic_image_path` - Path to the generated mosaic image, naming the pyramid
+    /// * `mosaic_image` - The generated mosaic, sliced into the pyramid
+    /// * `output_path` - Path where the widget HTML file should be written
+    /// * `tile_set` - The tile set used for generating the mosaic
+    /// * `config` - Configuration settings used to generate the mosaic
+    /// * `web_compatible` - If true, generates relative URLs suitable for web hosting
+    ///
+    /// # Returns
+    /// * `Ok(())` - If the pyramid and widget HTML file were successfully generated
+    /// * `Err(std::io::Error)` - If file writing failed
+    pub fn generate_pyramid_widget<T>(
+        &self,
+        mosaic_image_path: &Path,
+        mosaic_image: &RgbImage,
+        output_path: &Path,
+        tile_set: &TileSet<T>,
+        config: &MosaicConfig,
+        web_compatible: bool,
+    ) -> Result<(), std::io::Error> {
+        if self.tiles().is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "No tiles recorded in statistics",
+            ));
+        }
+
+        let dzi_name = format!(
+            "{}.dzi",
+            mosaic_image_path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+        );
+        write_deep_zoom(mosaic_image, &output_path.with_file_name(&dzi_name))?;
+
+        // Tile details looked up by the widget under the pointer
+        let tiles: Vec<_> = self
+            .grid_cells()
+            .into_iter()
+            .map(|cell| {
+                let (col, row) = cell;
+                let tile = &self.tiles()[&cell];
+                // Synthetic fillers have no image to show, only a label
+                let (url, date) = match tile_set.filler_color(tile) {
+                    Some(_) => (None, Some("Synthetic filler".to_string())),
+                    None => {
+                        let url = tile_image_url(tile_set.get_path(tile), config, web_compatible);
+                        let date = tile.taken().map(|taken| taken.format(config.date_format));
+                        (Some(url), date)
+                    }
+                };
+                // Distances are only meaningful locally, as in the regular widget
+                let distance = (!web_compatible).then(|| f64::from(tile.colors));
+                let link = config.tile_links.get(tile_set.get_path(tile));
+                json!([col, row, url, distance, date, link])
+            })
+            .collect();
+        let data = json!({ "tileSize": config.tile_size, "tiles": tiles });
+        // Keep tile paths from closing the script element
+        let data = data.to_string().replace("</", "<\\/");
+
+        let html = format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+{metadata}    <title>{title}</title>
+    <script src="{osd}/openseadragon.min.js"></script>
+    <style>
+        html, body {{ margin: 0; height: 100%; background: #111; }}
+        #viewer {{ position: relative; width: 100%; height: 100%; }}
+        .tooltip {{
+            display: none; position: absolute; z-index: 10; pointer-events: none;
+            padding: 6px; border-radius: 4px; background: rgba(0, 0, 0, 0.85);
+            color: #fff; font: 12px sans-serif;
+        }}
+        .tooltip img {{ display: block; max-width: 160px; max-height: 160px; margin-bottom: 4px; }}
+    </style>
+</head>
+<body>
+    <div id="viewer">
+        <div id="tooltip" class="tooltip"><img id="tooltip-image" alt="Tile Preview"/><div id="tooltip-info"></div></div>
+    </div>
+    <script>
+        var mosaic = {data};
+        var tilesByCell = {{}};
+        mosaic.tiles.forEach(function (tile) {{
+            tilesByCell[tile[0] + "," + tile[1]] = tile;
+        }});
+
+        var viewer = OpenSeadragon({{
+            id: "viewer",
+            prefixUrl: "{osd}/images/",
+            tileSources: "{dzi}",
+            showNavigator: true,
+            maxZoomPixelRatio: 4
+        }});
+        var tooltip = document.getElementById("tooltip");
+        var tooltipImage = document.getElementById("tooltip-image");
+        var tooltipInfo = document.getElementById("tooltip-info");
+
+        function tileAt(position) {{
+            var point = viewer.viewport.viewerElementToImageCoordinates(position);
+            var col = Math.floor(point.x / mosaic.tileSize);
+            var row = Math.floor(point.y / mosaic.tileSize);
+            return tilesByCell[col + "," + row];
+        }}
+
+        new OpenSeadragon.MouseTracker({{
+            element: viewer.element,
+            moveHandler: function (event) {{
+                var tile = tileAt(event.position);
+                if (!tile) {{
+                    tooltip.style.display = "none";
+                    return;
+                }}
+                if (tile[2] !== null && tooltipImage.getAttribute("src") !== tile[2]) {{
+                    tooltipImage.setAttribute("src", tile[2]);
+                }}
+                tooltipImage.style.display = tile[2] === null ? "none" : "block";
+                var info = [];
+                if (tile[3] !== null) info.push("Distance: " + tile[3].toFixed(3));
+                if (tile[4] !== null) info.push(tile[4]);
+                tooltipInfo.textContent = info.join(" · ");
+                tooltip.style.left = (event.position.x + 16) + "px";
+                tooltip.style.top = (event.position.y + 16) + "px";
+                tooltip.style.display = "block";
+            }},
+            leaveHandler: function () {{
+                tooltip.style.display = "none";
+            }}
+        }});
+
+        viewer.addHandler("canvas-click", function (event) {{
+            if (!event.quick) return;
+            var tile = tileAt(event.position);
+            var target = tile && (tile[5] || tile[2]);
+            if (target) window.open(target, "_blank");
+        }});
+    </script>
+</body>
+</html>"#,
+            metadata = metadata_tags(config, "    "),
+            title = escape_html(&config.title),
+            osd = OPENSEADRAGON_URL,
+            dzi = escape_html(&dzi_name),
+            data = data,
+        );
+
+        let mut file = std::fs::File::create(output_path)?;
+        file.write_all(html.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn test_max_level() {
+        assert_eq!(max_level(1, 1), 0);
+        assert_eq!(max_level(2, 1), 1);
+        assert_eq!(max_level(3, 2), 2);
+        assert_eq!(max_level(256, 100), 8);
+        assert_eq!(max_level(257, 100), 9);
+    }
+
+    #[test]
+    fn test_level_dimensions() {
+        assert_eq!(level_dimensions(600, 300, 10), (600, 300));
+        assert_eq!(level_dimensions(600, 300, 9), (300, 150));
+        assert_eq!(level_dimensions(600, 300, 8), (150, 75));
+        assert_eq!(level_dimensions(600, 300, 7), (75, 38));
+        assert_eq!(level_dimensions(600, 300, 0), (1, 1));
+    }
+
+    #[test]
+    fn test_tile_rect() {
+        let level = (600, 300);
+        assert_eq!(tile_counts(level), (3, 2));
+        assert_eq!(tile_rect(0, 0, level), (0, 0, 255, 255));
+        assert_eq!(tile_rect(1, 1, level), (253, 253, 256, 47));
+        assert_eq!(tile_rect(2, 0, level), (507, 0, 93, 255));
+    }
+
+    #[test]
+    fn test_write_deep_zoom() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let dzi_path = dir.join("mosaic.dzi");
+        let image = RgbImage::from_pixel(600, 300, Rgb([10, 200, 30]));
+
+        write_deep_zoom(&image, &dzi_path).unwrap();
+
+        let dzi = fs::read_to_string(&dzi_path).unwrap();
+        assert!(dzi.contains(r#"TileSize="254" Overlap="1" Format="jpg""#));
+        assert!(dzi.contains(r#"<Size Width="600" Height="300"/>"#));
+        let files = dir.join("mosaic_files");
+        for level in 0..=10 {
+            let (cols, rows) = tile_counts(level_dimensions(600, 300, level));
+            let count = fs::read_dir(files.join(level.to_string())).unwrap().count();
+            assert_eq!(count as u32, cols * rows, "level {}", level);
+        }
+        let corner = image::open(files.join("10/2_1.jpg")).unwrap();
+        assert_eq!((corner.width(), corner.height()), (93, 47));
+    }
+}
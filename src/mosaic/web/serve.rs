@@ -0,0 +1,749 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde::Serialize;
+use tracing::error;
+
+use super::escape_html;
+
+/// Modes offered in the form of the page, from the fastest to the most detailed.
+pub const MODES: [u32; 7] = [1, 2, 3, 4, 5, 6, 8];
+
+/// Most header lines read from a request, which the server never needs.
+const MAX_HEADERS: usize = 100;
+
+/// The settings of a mosaic chosen in the form of the page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderParams {
+    /// Side of the grid of colors matched in every tile, one of [`MODES`]
+    pub mode: u32,
+    /// Side of every tile of the full mosaic, in pixels
+    pub tile_size: u32,
+    /// Downsampling factor of the source image, one tile per remaining pixel
+    pub downsample: u16,
+    /// Whether every picture is placed at most once
+    pub no_repeat: bool,
+    /// Opacity of the source image over the mosaic, from 0 to 1
+    pub tint_opacity: f64,
+    /// Whether the pictures are cropped to square tiles instead of resized
+    pub crop: bool,
+}
+
+impl RenderParams {
+    /// The settings in the query string parameters of a request, `defaults` for those left out
+    /// except the checkboxes, which the form only sends when they are ticked.
+    pub fn from_query(
+        query: &HashMap<String, String>,
+        defaults: &RenderParams,
+    ) -> Result<RenderParams, String> {
+        fn parse<T: std::str::FromStr>(
+            query: &HashMap<String, String>,
+            name: &str,
+            default: T,
+        ) -> Result<T, String> {
+            match query.get(name) {
+                Some(value) => value
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid {}: {}", name, value)),
+                None => Ok(default),
+            }
+        }
+        let ticked = |name: &str| query.get(name).is_some_and(|value| value != "false");
+        let params = RenderParams {
+            mode: parse(query, "mode", defaults.mode)?,
+            tile_size: parse(query, "tile_size", defaults.tile_size)?,
+            downsample: parse(query, "downsample", defaults.downsample)?,
+            no_repeat: ticked("no_repeat"),
+            tint_opacity: parse(query, "tint_opacity", defaults.tint_opacity)?,
+            crop: ticked("crop"),
+        };
+        if !MODES.contains(&params.mode) {
+            return Err(format!("Invalid mode: {}", params.mode));
+        }
+        if params.tile_size == 0 || params.downsample == 0 {
+            return Err(String::from(
+                "The tile size and downsampling must be at least 1",
+            ));
+        }
+        if !(0.0..=1.0).contains(&params.tint_opacity) {
+            return Err(String::from("The tint opacity must be between 0 and 1"));
+        }
+        Ok(params)
+    }
+
+    /// The arguments of emosaic rendering the mosaic of `source` to `output`, from the
+    /// pictures of `tiles_dir` chosen by the options of the mosaic subcommand `tiles_args`,
+    /// after the options of the command serving the page that choose the pictures and
+    /// resources used, `global_args`.
+    pub fn args(
+        &self,
+        global_args: &[OsString],
+        source: &Path,
+        tiles_dir: &Path,
+        tiles_args: &[String],
+        output: &Path,
+    ) -> Vec<OsString> {
+        let mut args = global_args.to_vec();
+        args.extend([
+            "-s".into(),
+            self.tile_size.to_string().into(),
+            "-o".into(),
+            output.into(),
+        ]);
+        if self.crop {
+            args.push("--crop".into());
+        }
+        args.extend([
+            source.into(),
+            "mosaic".into(),
+            tiles_dir.into(),
+            "-m".into(),
+            self.mode.to_string().into(),
+            "--downsample".into(),
+            self.downsample.to_string().into(),
+            "-t".into(),
+            self.tint_opacity.to_string().into(),
+        ]);
+        if self.no_repeat {
+            args.push("--no-repeat".into());
+        }
+        args.extend(tiles_args.iter().map(OsString::from));
+        args
+    }
+}
+
+/// A request read from the browser: its method, path, query string parameters and headers.
+/// Bodies are never needed and left unread.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    /// Values of the headers, by lowercase name
+    pub headers: HashMap<String, String>,
+}
+
+impl Request {
+    /// Read the request line and headers of a request from `reader`.
+    pub fn read<R: BufRead>(reader: &mut R) -> io::Result<Request> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let (method, target) = match (parts.next(), parts.next()) {
+            (Some(method), Some(target)) => (method, target),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed request line {:?}", line.trim_end()),
+                ))
+            }
+        };
+        let mut headers = HashMap::new();
+        for _ in 0..MAX_HEADERS {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+            }
+        }
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let query = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (percent_decode(name), percent_decode(value))
+            })
+            .collect();
+        Ok(Request {
+            method: method.to_string(),
+            path: percent_decode(path),
+            query,
+            headers,
+        })
+    }
+}
+
+/// `text` with its `%XX` escapes decoded and `+` as spaces, as browsers encode forms.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = text
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (_, Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', None) => decoded.push(b' '),
+            (byte, None) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// A response to the browser.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    /// Status code and reason, e.g. `200 OK`
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn ok(content_type: &'static str, body: Vec<u8>) -> Response {
+        Response {
+            status: "200 OK",
+            content_type,
+            body,
+        }
+    }
+
+    fn text(status: &'static str, text: &str) -> Response {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: text.as_bytes().to_vec(),
+        }
+    }
+
+    fn json(status: &'static str, value: &impl Serialize) -> Response {
+        Response {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_vec(value).unwrap_or_default(),
+        }
+    }
+
+    /// The contents of the image at `path`, or not found when it cannot be read.
+    fn image(path: &Path) -> Response {
+        match std::fs::read(path) {
+            Ok(bytes) => Response::ok(image_content_type(path), bytes),
+            Err(_) => Response::text("404 Not Found", "Not rendered yet"),
+        }
+    }
+
+    /// Write the response to `writer`, closing the connection after it.
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(
+            writer,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\n\
+             Connection: close\r\n\r\n",
+            self.status,
+            self.content_type,
+            self.body.len()
+        )?;
+        writer.write_all(&self.body)?;
+        writer.flush()
+    }
+}
+
+/// Content type of the image at `path`, from its extension.
+fn image_content_type(path: &Path) -> &'static str {
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    match extension.to_ascii_lowercase().as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "gif" => "image/gif",
+        _ => "application/octet-stream",
+    }
+}
+
+/// The render of the full mosaic, started from the page.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum Job {
+    Idle,
+    Running,
+    Done,
+    Failed { error: String },
+}
+
+/// The server of the page choosing the settings of the mosaic of a source image, rendering
+/// previews with `render`, which runs emosaic with the given arguments.
+pub struct Server<R> {
+    /// Port of the server, which the `Host` and `Origin` of requests must name
+    port: u16,
+    /// Options of the command serving the page given to every render, see [`RenderParams::args`]
+    global_args: Vec<OsString>,
+    source: PathBuf,
+    tiles_dir: PathBuf,
+    /// Options of the mosaic subcommand choosing the pictures of `tiles_dir`, e.g.
+    /// `--extensions=jpg`
+    tiles_args: Vec<String>,
+    /// Number of pictures in the tiles directory
+    pictures: usize,
+    /// Where the full mosaic is written
+    output: PathBuf,
+    /// Where the previews are written, as PNG
+    preview: PathBuf,
+    defaults: RenderParams,
+    render: R,
+    job: Mutex<Job>,
+    /// Previews are rendered one at a time, and those superseded by a later request skipped
+    previews: Mutex<()>,
+    latest_preview: AtomicU64,
+}
+
+impl<R> Server<R>
+where
+    R: Fn(&[OsString]) -> Result<(), String> + Send + Sync + 'static,
+{
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        port: u16,
+        global_args: Vec<OsString>,
+        source: PathBuf,
+        tiles_dir: PathBuf,
+        tiles_args: Vec<String>,
+        pictures: usize,
+        output: PathBuf,
+        preview: PathBuf,
+        defaults: RenderParams,
+        render: R,
+    ) -> Server<R> {
+        Server {
+            port,
+            global_args,
+            source,
+            tiles_dir,
+            tiles_args,
+            pictures,
+            output,
+            preview,
+            defaults,
+            render,
+            job: Mutex::new(Job::Idle),
+            previews: Mutex::new(()),
+            latest_preview: AtomicU64::new(0),
+        }
+    }
+
+    /// Answer the requests of `listener`, each on a thread of its own, until the process ends.
+    pub fn run(self: Arc<Self>, listener: TcpListener) {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    error!("❌ Failed to accept a connection: {}", e);
+                    continue;
+                }
+            };
+            let server = Arc::clone(&self);
+            thread::spawn(move || {
+                if let Err(e) = server.handle(stream) {
+                    error!("❌ Failed to answer a request: {}", e);
+                }
+            });
+        }
+    }
+
+    fn handle(self: &Arc<Self>, mut stream: TcpStream) -> io::Result<()> {
+        let request = Request::read(&mut BufReader::new(&stream))?;
+        self.respond(&request).write(&mut stream)
+    }
+
+    /// Whether `request` comes from the page of this server: its `Host`, and its `Origin` if
+    /// any, name the server, so that other sites cannot read the page through a hostname of
+    /// theirs resolving to this computer nor start renders from the browser.
+    fn is_same_origin(&self, request: &Request) -> bool {
+        let hosts = [
+            format!("127.0.0.1:{}", self.port),
+            format!("localhost:{}", self.port),
+        ];
+        let host = request.headers.get("host");
+        let origin = request.headers.get("origin");
+        host.is_some_and(|host| hosts.contains(host))
+            && origin.is_none_or(|origin| {
+                origin
+                    .strip_prefix("http://")
+                    .is_some_and(|host| hosts.iter().any(|h| h == host))
+            })
+    }
+
+    /// The response to `request`.
+    pub fn respond(self: &Arc<Self>, request: &Request) -> Response {
+        if !self.is_same_origin(request) {
+            return Response::text("403 Forbidden", "Only the page of the server is served");
+        }
+        // Rendering the full mosaic writes to disk, which only the button of the page does
+        let method = if request.path == "/render" {
+            "POST"
+        } else {
+            "GET"
+        };
+        if request.method != method {
+            return Response::text("405 Method Not Allowed", &format!("Use {}", method));
+        }
+        let params = || RenderParams::from_query(&request.query, &self.defaults);
+        match request.path.as_str() {
+            "/" => Response::ok("text/html; charset=utf-8", self.page().into_bytes()),
+            "/source" => Response::image(&self.source),
+            "/output" => Response::image(&self.output),
+            "/status" => Response::json("200 OK", &*self.job.lock().unwrap()),
+            "/preview" => match params() {
+                Ok(params) => self.render_preview(&params),
+                Err(e) => Response::text("400 Bad Request", &e),
+            },
+            "/render" => match params() {
+                Ok(params) => self.start_render(params),
+                Err(e) => Response::text("400 Bad Request", &e),
+            },
+            _ => Response::text("404 Not Found", "Not found"),
+        }
+    }
+
+    /// Render a quick preview with `params`, unless a later preview was asked for while
+    /// waiting for the previous ones.
+    fn render_preview(&self, params: &RenderParams) -> Response {
+        let request = self.latest_preview.fetch_add(1, Ordering::SeqCst) + 1;
+        let _rendering = self.previews.lock().unwrap();
+        if self.latest_preview.load(Ordering::SeqCst) != request {
+            return Response::text("409 Conflict", "Superseded by a later preview");
+        }
+        let mut args = params.args(
+            &self.global_args,
+            &self.source,
+            &self.tiles_dir,
+            &self.tiles_args,
+            &self.preview,
+        );
+        args.push("--preview".into());
+        match (self.render)(&args) {
+            Ok(()) => Response::image(&self.preview),
+            Err(e) => Response::text("500 Internal Server Error", &e),
+        }
+    }
+
+    /// Start rendering the full mosaic with `params` in the background, unless it already is.
+    fn start_render(self: &Arc<Self>, params: RenderParams) -> Response {
+        let mut job = self.job.lock().unwrap();
+        if *job == Job::Running {
+            return Response::json("409 Conflict", &*job);
+        }
+        *job = Job::Running;
+        let server = Arc::clone(self);
+        thread::spawn(move || {
+            let args = params.args(
+                &server.global_args,
+                &server.source,
+                &server.tiles_dir,
+                &server.tiles_args,
+                &server.output,
+            );
+            let done = match (server.render)(&args) {
+                Ok(()) => Job::Done,
+                Err(error) => Job::Failed { error },
+            };
+            *server.job.lock().unwrap() = done;
+        });
+        Response::json("202 Accepted", &*job)
+    }
+
+    /// The page: the form of the settings next to the source image and the preview.
+    fn page(&self) -> String {
+        let defaults = &self.defaults;
+        let modes: String = MODES
+            .iter()
+            .map(|&mode| {
+                let selected = if mode == defaults.mode {
+                    " selected"
+                } else {
+                    ""
+                };
+                format!(r#"<option value="{0}"{1}>{0}x{0}</option>"#, mode, selected)
+            })
+            .collect();
+        let checked = |ticked: bool| if ticked { " checked" } else { "" };
+        format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>emosaic - {source}</title>
+    <style>
+        body {{ font-family: Arial, sans-serif; margin: 0; display: flex; min-height: 100vh; }}
+        form {{ width: 260px; padding: 20px; background: #f5f5f5; box-sizing: border-box; }}
+        label {{ display: block; margin: 12px 0 4px; font-size: 14px; }}
+        input[type=number], select {{ width: 100%; box-sizing: border-box; }}
+        button {{ margin-top: 20px; width: 100%; padding: 10px; font-size: 15px; background: #007bff;
+                  color: white; border: none; border-radius: 4px; cursor: pointer; }}
+        button:disabled {{ background: #999; cursor: default; }}
+        .library {{ font-size: 13px; color: #555; word-break: break-all; }}
+        .status {{ margin-top: 12px; font-size: 13px; color: #333; }}
+        main {{ flex: 1; display: flex; gap: 20px; padding: 20px; align-items: flex-start; }}
+        figure {{ margin: 0; flex: 1; }}
+        figure img {{ max-width: 100%; image-rendering: pixelated; border: 1px solid #ddd; }}
+        figcaption {{ font-size: 13px; color: #555; }}
+    </style>
+</head>
+<body>
+    <form id="params">
+        <div class="library">Source: {source}<br>Tiles: {tiles_dir}, {pictures} pictures</div>
+        <label for="mode">Mode</label>
+        <select id="mode" name="mode">{modes}</select>
+        <label for="tile_size">Tile size (pixels)</label>
+        <input type="number" id="tile_size" name="tile_size" min="1" value="{tile_size}">
+        <label for="downsample">Downsample</label>
+        <input type="number" id="downsample" name="downsample" min="1" value="{downsample}">
+        <label for="tint_opacity">Tint opacity</label>
+        <input type="range" id="tint_opacity" name="tint_opacity" min="0" max="1" step="0.05" value="{tint_opacity}">
+        <label><input type="checkbox" name="no_repeat"{no_repeat}> No repeats</label>
+        <label><input type="checkbox" name="crop"{crop}> Crop the pictures</label>
+        <button type="button" id="render-full" data-output="{output}">Render full</button>
+        <div id="status" class="status"></div>
+    </form>
+    <main>
+        <figure><img src="/source" alt="Source image"><figcaption>Source</figcaption></figure>
+        <figure><img id="preview" alt="Preview"><figcaption id="preview-caption">Preview</figcaption></figure>
+    </main>
+    <script>
+        const form = document.getElementById('params');
+        const renderButton = document.getElementById('render-full');
+        let controller = null;
+        let timer = null;
+
+        function setStatus(text) {{
+            document.getElementById('status').textContent = text;
+        }}
+
+        function query() {{
+            return new URLSearchParams(new FormData(form)).toString();
+        }}
+
+        // Render a quick preview, abandoning the one on its way
+        function refreshPreview() {{
+            if (controller) controller.abort();
+            controller = new AbortController();
+            setStatus('Rendering a preview…');
+            fetch('/preview?' + query(), {{ signal: controller.signal }})
+                .then(response => {{
+                    if (response.status === 409) return null;
+                    if (!response.ok) return response.text().then(text => Promise.reject(new Error(text)));
+                    return response.blob();
+                }})
+                .then(blob => {{
+                    if (!blob) return;
+                    const preview = document.getElementById('preview');
+                    if (preview.src) URL.revokeObjectURL(preview.src);
+                    preview.src = URL.createObjectURL(blob);
+                    setStatus('Preview up to date');
+                }})
+                .catch(error => {{
+                    if (error.name !== 'AbortError') setStatus('❌ ' + error.message);
+                }});
+        }}
+
+        function showJob(job) {{
+            renderButton.disabled = job.state === 'running';
+            if (job.state === 'running') {{
+                setStatus('Rendering the full mosaic…');
+            }} else if (job.state === 'done') {{
+                setStatus('✅ Full mosaic rendered to ' + renderButton.dataset.output);
+                window.open('/output?' + Date.now(), '_blank');
+            }} else if (job.state === 'failed') {{
+                setStatus('❌ ' + job.error);
+            }}
+        }}
+
+        function pollJob() {{
+            fetch('/status').then(response => response.json()).then(job => {{
+                showJob(job);
+                if (job.state === 'running') setTimeout(pollJob, 1000);
+            }});
+        }}
+
+        form.addEventListener('input', () => {{
+            clearTimeout(timer);
+            timer = setTimeout(refreshPreview, 400);
+        }});
+        form.addEventListener('submit', event => event.preventDefault());
+        renderButton.addEventListener('click', () => {{
+            fetch('/render?' + query(), {{ method: 'POST' }}).then(response => response.json()).then(job => {{
+                showJob(job);
+                if (job.state === 'running') setTimeout(pollJob, 1000);
+            }});
+        }});
+        refreshPreview();
+    </script>
+</body>
+</html>
+"#,
+            source = escape_html(&self.source.display().to_string()),
+            tiles_dir = escape_html(&self.tiles_dir.display().to_string()),
+            pictures = self.pictures,
+            modes = modes,
+            tile_size = defaults.tile_size,
+            downsample = defaults.downsample,
+            tint_opacity = defaults.tint_opacity,
+            no_repeat = checked(defaults.no_repeat),
+            crop = checked(defaults.crop),
+            output = escape_html(&self.output.display().to_string()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn defaults() -> RenderParams {
+        RenderParams {
+            mode: 1,
+            tile_size: 16,
+            downsample: 4,
+            no_repeat: false,
+            tint_opacity: 0.0,
+            crop: false,
+        }
+    }
+
+    #[test]
+    fn test_read_request() {
+        let mut bytes = "GET /preview?mode=2&tile_size=%2032&no_repeat=on&name=a+b HTTP/1.1\r\n\
+                         Host: localhost\r\n\r\n"
+            .as_bytes();
+        let request = Request::read(&mut bytes).unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.headers["host"], "localhost");
+        assert_eq!(request.path, "/preview");
+        assert_eq!(request.query["tile_size"], " 32");
+        assert_eq!(request.query["name"], "a b");
+        assert!(Request::read(&mut "\r\n".as_bytes()).is_err());
+
+        let params = RenderParams::from_query(&request.query, &defaults()).unwrap();
+        assert_eq!(
+            (params.mode, params.tile_size, params.downsample),
+            (2, 32, 4)
+        );
+        assert!(params.no_repeat && !params.crop);
+        let mut query = request.query.clone();
+        query.insert(String::from("mode"), String::from("7"));
+        assert!(RenderParams::from_query(&query, &defaults()).is_err());
+        query.insert(String::from("mode"), String::from("abc"));
+        assert!(RenderParams::from_query(&query, &defaults()).is_err());
+
+        let global_args = ["--cache-dir=cache", "--exclude", "*.png"].map(OsString::from);
+        let args = params.args(
+            &global_args,
+            Path::new("in.png"),
+            Path::new("tiles"),
+            &[String::from("--extensions=jpg")],
+            Path::new("out.png"),
+        );
+        let args: Vec<_> = args.iter().map(|arg| arg.to_string_lossy()).collect();
+        assert_eq!(
+            args.join(" "),
+            "--cache-dir=cache --exclude *.png -s 32 -o out.png in.png mosaic tiles -m 2 --downsample 4 -t 0 --no-repeat \
+             --extensions=jpg"
+        );
+    }
+
+    #[test]
+    fn test_server() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let source = dir.join("source.png");
+        std::fs::write(&source, b"source").unwrap();
+        // Renders write their arguments to their output
+        let render = |args: &[OsString]| {
+            let output = args.iter().skip_while(|arg| *arg != "-o").nth(1).unwrap();
+            let args: Vec<_> = args.iter().map(|arg| arg.to_string_lossy()).collect();
+            if args.contains(&"-t".into()) && args.contains(&"0.5".into()) {
+                return Err(String::from("tint failed"));
+            }
+            std::fs::write(output, args.join(" ")).map_err(|e| e.to_string())
+        };
+        let server = Arc::new(Server::new(
+            8080,
+            vec![OsString::from("--follow-symlinks")],
+            source,
+            dir.join("tiles"),
+            vec![String::from("--extensions=jpg")],
+            3,
+            dir.join("full.jpg"),
+            dir.join("preview.png"),
+            defaults(),
+            render,
+        ));
+        let send = |method: &str, target: &str, headers: &str| {
+            let request = format!("{} {} HTTP/1.1\r\n{}\r\n", method, target, headers);
+            server.respond(&Request::read(&mut request.as_bytes()).unwrap())
+        };
+        let get = |target: &str| send("GET", target, "Host: localhost:8080\r\n");
+
+        let page = get("/");
+        assert_eq!(page.status, "200 OK");
+        let page = String::from_utf8(page.body).unwrap();
+        assert!(page.contains(r#"<option value="1" selected>1x1</option>"#));
+        assert!(page.contains("3 pictures"));
+        assert_eq!(get("/source").body, b"source");
+        assert_eq!(get("/output").status, "404 Not Found");
+        assert_eq!(get("/missing").status, "404 Not Found");
+
+        let preview = get("/preview?mode=4&downsample=2");
+        assert_eq!(preview.content_type, "image/png");
+        let preview = String::from_utf8(preview.body).unwrap();
+        assert!(preview.contains("-m 4 --downsample 2"), "{}", preview);
+        assert!(preview.ends_with("--preview"), "{}", preview);
+        assert!(
+            preview.starts_with("--follow-symlinks -s 16"),
+            "{}",
+            preview
+        );
+        assert_eq!(get("/preview?mode=0").status, "400 Bad Request");
+        let failed = get("/preview?tint_opacity=0.5");
+        assert_eq!(failed.status, "500 Internal Server Error");
+        assert_eq!(failed.body, b"tint failed");
+
+        assert_eq!(get("/status").body, br#"{"state":"idle"}"#);
+        // Renders are only started by the page of the server, with a POST
+        assert_eq!(get("/render?crop=on").status, "405 Method Not Allowed");
+        assert_eq!(send("GET", "/", "").status, "403 Forbidden");
+        let host = "Host: 127.0.0.1:8080\r\n";
+        assert_eq!(
+            send("GET", "/", "Host: evil.com:8080\r\n").status,
+            "403 Forbidden"
+        );
+        let cross_site = format!("{}Origin: http://evil.com\r\n", host);
+        assert_eq!(send("POST", "/render", &cross_site).status, "403 Forbidden");
+        let other_port = format!("{}Origin: http://127.0.0.1:9090\r\n", host);
+        assert_eq!(send("POST", "/render", &other_port).status, "403 Forbidden");
+        let same_site = format!("{}Origin: http://127.0.0.1:8080\r\n", host);
+        assert_eq!(
+            send("POST", "/render?crop=on", &same_site).status,
+            "202 Accepted"
+        );
+        let start = Instant::now();
+        while get("/status").body != br#"{"state":"done"}"# {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "the render never finished"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        let output = get("/output");
+        assert_eq!(output.content_type, "image/jpeg");
+        assert!(String::from_utf8(output.body).unwrap().contains("--crop"));
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::HashSet;
+use std::convert::{TryFrom, TryInto};
+use std::fs;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use image::codecs::jpeg::JpegEncoder;
+use rayon::prelude::*;
+
+use super::super::stats::{MosaicConfig, RenderStats};
+use super::super::tiles::{open_oriented, split_crop, TileSet};
+use super::web_tile_path;
+
+const JPEG_QUALITY: u8 = 85;
+
+/// Whether the thumbnail at `thumbnail` is missing or older than its original.
+fn is_stale(original: &Path, thumbnail: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    match (modified(original), modified(thumbnail)) {
+        (Some(original), Some(thumbnail)) => thumbnail < original,
+        _ => true,
+    }
+}
+
+/// Tag of the orientation of the image in the first IFD of EXIF data.
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// Reset the orientation recorded in the TIFF data of EXIF `tiff` to upright, if any, since
+/// thumbnails are written with the orientation already applied.
+fn reset_orientation(tiff: &mut [u8]) -> Option<()> {
+    let big_endian = match tiff.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let u16_at = |tiff: &[u8], i: usize| {
+        let bytes = tiff.get(i..i + 2)?.try_into().ok()?;
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let bytes = tiff.get(4..8)?.try_into().ok()?;
+    let ifd = if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    };
+    let ifd = ifd as usize;
+    let upright = if big_endian {
+        1u16.to_be_bytes()
+    } else {
+        1u16.to_le_bytes()
+    };
+    for entry in (0..usize::from(u16_at(tiff, ifd)?)).map(|i| ifd + 2 + 12 * i) {
+        if u16_at(tiff, entry)? == ORIENTATION_TAG {
+            // A single SHORT value is stored in the first bytes of the value field
+            tiff.get_mut(entry + 8..entry + 10)?
+                .copy_from_slice(&upright);
+        }
+    }
+    Some(())
+}
+
+/// The EXIF data of `original` as the APP1 segment of a JPEG image, or `None` when it has
+/// none or more than fits in a segment.
+fn exif_segment(original: &Path) -> Option<Vec<u8>> {
+    let mut reader = BufReader::new(fs::File::open(original).ok()?);
+    let exif = exif::Reader::new().read_from_container(&mut reader).ok()?;
+    let mut tiff = exif.buf().to_vec();
+    reset_orientation(&mut tiff);
+    let length = u16::try_from(2 + 6 + tiff.len()).ok()?;
+    let mut segment = vec![0xFF, 0xE1];
+    segment.extend(length.to_be_bytes());
+    segment.extend(b"Exif\0\0");
+    segment.extend(tiff);
+    Some(segment)
+}
+
+/// Write a JPEG thumbnail of `original` at the given `quality`, at most `size` pixels on its
+/// longest side, with the EXIF data of the original unless `strip_exif`.
+fn write_thumbnail(
+    original: &Path,
+    thumbnail: &Path,
+    size: u32,
+    quality: u8,
+    strip_exif: bool,
+) -> io::Result<()> {
+    let image = open_oriented(original).map_err(|e| io::Error::other(e.error))?;
+    let image = if image.width().max(image.height()) > size {
+        image.thumbnail(size, size)
+    } else {
+        image
+    };
+    if let Some(parent) = thumbnail.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, quality)
+        .encode_image(&image.to_rgb8())
+        .map_err(io::Error::other)?;
+    // The segment goes right after the start of image marker
+    if let Some(segment) = exif_segment(original).filter(|_| !strip_exif) {
+        jpeg.splice(2..2, segment);
+    }
+    fs::write(thumbnail, jpeg)
+}
+
+impl<D> RenderStats<D>
+where
+    f64: From<D>,
+    D: std::cmp::Ord,
+    D: std::convert::From<u8>,
+    D: std::ops::AddAssign,
+    D: Copy,
+    D: std::fmt::Display,
+{
+    /// Export thumbnails of the tiles placed in the mosaic to the `tiles/` directory next to
+    /// `output_path`, where the web-compatible pages link them.
+    ///
+    /// Thumbnails newer than their original are kept, so exporting again after rendering
+    /// another mosaic from the same tiles only resizes the new ones. The thumbnails keep the
+    /// EXIF data of their original, orientation aside, unless the config strips it. Crops
+    /// link the thumbnail of their whole picture.
+    ///
+    /// # Arguments
+    /// * `output_path` - Path of the HTML page linking the thumbnails
+    /// * `tile_set` - The tile set used for generating the mosaic
+    /// * `config` - Configuration settings used to generate the mosaic, with the thumbnail
+    ///   size and quality
+    ///
+    /// # Returns
+    /// * `Ok(count)` - The number of thumbnails written
+    /// * `Err(std::io::Error)` - If a tile could not be read or its thumbnail written
+    pub fn export_thumbnails<T: Sync>(
+        &self,
+        output_path: &Path,
+        tile_set: &TileSet<T>,
+        config: &MosaicConfig,
+    ) -> Result<usize, std::io::Error> {
+        let Some(size) = config.thumbnail_size else {
+            return Ok(0);
+        };
+        let tiles_dir = output_path
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join("tiles");
+        let originals: HashSet<&Path> = self
+            .tiles()
+            .values()
+            .filter(|tile| tile_set.filler_color(tile).is_none())
+            .map(|tile| split_crop(tile_set.get_path(tile)).0)
+            .collect();
+
+        originals
+            .into_par_iter()
+            .map(|original| {
+                let thumbnail = tiles_dir.join(web_tile_path(original, config));
+                if !is_stale(original, &thumbnail) {
+                    return Ok(0);
+                }
+                let quality = config.export_quality.unwrap_or(JPEG_QUALITY);
+                let strip_exif = config.export_strip_exif;
+                write_thumbnail(original, &thumbnail, size, quality, strip_exif).map_err(|e| {
+                    io::Error::new(e.kind(), format!("{}: {}", original.display(), e))
+                })?;
+                Ok(1)
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_export_thumbnails() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let photos = dir.join("photos");
+        fs::create_dir_all(photos.join("2024")).unwrap();
+        let original = photos.join("2024/cat.png");
+        RgbImage::from_pixel(1000, 500, Rgb([200, 100, 0]))
+            .save(&original)
+            .unwrap();
+
+        let mut tile_set: TileSet<()> = TileSet::new();
+        tile_set.push_tile(original, ());
+        let mut stats: RenderStats<u32> = RenderStats::new();
+        stats.push_tile(0, 0, &tile_set.tiles[0], 1);
+        stats.push_tile(1, 0, &tile_set.tiles[0], 2);
+        let config = MosaicConfig {
+            tile_size: 16,
+            mode: "test".to_string(),
+            no_repeat: false,
+            greedy: false,
+            story: false,
+            time_gradient: None,
+            filler: None,
+            crop: false,
+            tint_opacity: 0.0,
+            downsample: 1,
+            randomize: None,
+            randomize_max_variance: None,
+            tiles_dir: photos.display().to_string(),
+            title: "Test Mosaic".to_string(),
+            description: None,
+            author: None,
+            thumbnail_size: Some(100),
+            export_quality: None,
+            export_strip_exif: false,
+            date_format: Default::default(),
+            palette: Default::default(),
+            tile_links: Default::default(),
+        };
+        let output_path: PathBuf = dir.join("site/mosaic.html");
+
+        let export = || {
+            stats
+                .export_thumbnails(&output_path, &tile_set, &config)
+                .unwrap()
+        };
+        assert_eq!(export(), 1);
+        let thumbnail = image::open(dir.join("site/tiles/2024/cat.jpg")).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (100, 50));
+        assert_eq!(export(), 0, "up to date thumbnails are kept");
+    }
+
+    #[test]
+    fn test_thumbnail_exif() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        // Little endian TIFF data with an orientation turning the picture clockwise and a
+        // description, which fits in the value field
+        let mut tiff = b"II*\0\x08\0\0\0\x02\0".to_vec();
+        tiff.extend([0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0]);
+        tiff.extend([0x0E, 0x01, 2, 0, 4, 0, 0, 0, b'c', b'a', b't', 0]);
+        tiff.extend([0, 0, 0, 0]);
+        let mut segment = vec![0xFF, 0xE1];
+        segment.extend((8 + tiff.len() as u16).to_be_bytes());
+        segment.extend(b"Exif\0\0");
+        segment.extend(&tiff);
+        let mut jpeg = Vec::new();
+        JpegEncoder::new(&mut jpeg)
+            .encode_image(&RgbImage::new(1000, 500))
+            .unwrap();
+        jpeg.splice(2..2, segment);
+        let original = dir.join("cat.jpg");
+        fs::write(&original, jpeg).unwrap();
+
+        let read_exif = |path: &Path| {
+            let mut reader = BufReader::new(fs::File::open(path).unwrap());
+            exif::Reader::new().read_from_container(&mut reader)
+        };
+        let thumbnail = dir.join("thumbnail.jpg");
+        write_thumbnail(&original, &thumbnail, 100, 80, false).unwrap();
+        let image = image::open(&thumbnail).unwrap();
+        assert_eq!((image.width(), image.height()), (50, 100));
+        let exif = read_exif(&thumbnail).unwrap();
+        let field = |tag| &exif.get_field(tag, exif::In::PRIMARY).unwrap().value;
+        assert_eq!(field(exif::Tag::Orientation).get_uint(0), Some(1));
+        assert_eq!(
+            field(exif::Tag::ImageDescription)
+                .display_as(exif::Tag::ImageDescription)
+                .to_string(),
+            "\"cat\""
+        );
+
+        let stripped = dir.join("stripped.jpg");
+        write_thumbnail(&original, &stripped, 100, 80, true).unwrap();
+        assert!(read_exif(&stripped).is_err());
+    }
+}
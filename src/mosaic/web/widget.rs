@@ -2,9 +2,18 @@ use std::fs;
 use std::io::Write;
 use std::path::Path;
 
-use sha2::{Sha256, Digest};
+use super::super::seams::WORST_SEAM;
 use super::super::stats::{MosaicConfig, RenderStats};
 use super::super::tiles::TileSet;
+use super::super::timestamp::DateFormat;
+use super::map::append_map_pane;
+use super::palette::{overlay_class, text_class};
+use super::{escape_html, metadata_tags, tile_image_url, tile_path_hash};
+
+/// Stylesheet of the widget, embedded so that installed binaries need no source tree.
+const WIDGET_CSS: &str = include_str!("../../assets/mosaic-widget.css");
+/// Script of the widget, embedded so that installed binaries need no source tree.
+const WIDGET_JS: &str = include_str!("../../assets/mosaic-widget.js");
 
 impl<D> RenderStats<D>
 where
@@ -45,12 +54,10 @@ where
         // Extract years from tiles for year filter
         let mut years: Vec<i32> = Vec::new();
         for tile in self.tiles().values() {
-            if let Some(ref date_taken) = tile.date_taken {
-                if let Some(year_str) = date_taken.split(':').next() {
-                    if let Ok(year) = year_str.parse::<i32>() {
-                        if !years.contains(&year) {
-                            years.push(year);
-                        }
+            if let Some(taken) = tile.taken() {
+                if let Ok(year) = taken.format(DateFormat::Year).parse::<i32>() {
+                    if !years.contains(&year) {
+                        years.push(year);
                     }
                 }
             }
@@ -61,19 +68,21 @@ where
 
         let mut html = String::new();
 
-        // Copy JavaScript file to output directory and generate HTML header
-        self.copy_assets_to_output_dir(output_path)?;
-        self.append_widget_header(&mut html, mosaic_image_path, min_year, max_year, &config.title);
+        // Write the CSS and JavaScript files to the output directory and generate HTML header
+        self.write_assets_to_output_dir(output_path)?;
+        self.append_widget_header(&mut html, mosaic_image_path, min_year, max_year, config);
 
         // Calculate image dimensions and tile positions
-        let max_x = self.tiles().keys().map(|(x, _)| *x).max().unwrap_or(0);
-        let max_y = self.tiles().keys().map(|(_, y)| *y).max().unwrap_or(0);
-        let image_width = max_x + config.tile_size;
-        let image_height = max_y + config.tile_size;
+        let (cols, rows) = self.grid();
+        let image_width = cols * config.tile_size;
+        let image_height = rows * config.tile_size;
 
         // Generate distance overlay
         self.append_distance_overlay(&mut html, config, image_width, image_height);
 
+        // Generate seam overlay, if the junctions were measured
+        self.append_seam_overlay(&mut html, config, image_width, image_height);
+
         // Generate interactive tile regions
         self.append_tile_regions(
             &mut html,
@@ -89,6 +98,25 @@ where
         // Generate year filter and mobile modal
         self.append_widget_controls(&mut html, min_year, max_year);
 
+        // Generate the search of the tiles by file name or folder
+        self.append_tile_search(&mut html);
+
+        // Generate the timeline scrubber, if the pictures were taken over several months
+        let months = self
+            .tiles()
+            .values()
+            .filter_map(|tile| Some(tile.taken()?.month()));
+        if let Some((first, last)) = months.clone().min().zip(months.max()) {
+            if last > first {
+                self.append_timeline(&mut html, first, last);
+            }
+        }
+
+        // Generate the map of where the pictures were taken, if located
+        if let Some(locations) = self.locations().filter(|locations| !locations.is_empty()) {
+            append_map_pane(&mut html, locations);
+        }
+
         // Close HTML document
         html.push_str(
             r#"
@@ -103,23 +131,11 @@ where
         Ok(())
     }
 
-    /// Copy CSS and JavaScript assets to output directory
-    fn copy_assets_to_output_dir(&self, output_path: &Path) -> Result<(), std::io::Error> {
+    /// Write the CSS and JavaScript assets, embedded in the binary, to the output directory
+    fn write_assets_to_output_dir(&self, output_path: &Path) -> Result<(), std::io::Error> {
         let output_dir = output_path.parent().unwrap_or_else(|| Path::new("."));
-        let assets_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/assets");
-
-        // Copy CSS file
-        let css_template_path = assets_dir.join("mosaic-widget.css");
-        let css_content = fs::read_to_string(&css_template_path)?;
-        let css_output_path = output_dir.join("mosaic-widget.css");
-        fs::write(&css_output_path, css_content)?;
-
-        // Copy JavaScript file
-        let js_template_path = assets_dir.join("mosaic-widget.js");
-        let js_content = fs::read_to_string(&js_template_path)?;
-        let js_output_path = output_dir.join("mosaic-widget.js");
-        fs::write(&js_output_path, js_content)?;
-
+        fs::write(output_dir.join("mosaic-widget.css"), WIDGET_CSS)?;
+        fs::write(output_dir.join("mosaic-widget.js"), WIDGET_JS)?;
         Ok(())
     }
 
@@ -130,7 +146,7 @@ where
         mosaic_image_path: &Path,
         min_year: i32,
         max_year: i32,
-        title: &str,
+        config: &MosaicConfig,
     ) {
         // Generate cache-busting timestamp
         let timestamp = std::time::SystemTime::now()
@@ -149,8 +165,10 @@ where
     <meta name="apple-mobile-web-app-status-bar-style" content="black-translucent">
     <meta name="apple-mobile-web-app-title" content="{title}">
     <meta name="mobile-web-app-capable" content="yes">
-    <title>{title}</title>
+{metadata}    <title>{title}</title>
     <link rel="stylesheet" href="mosaic-widget.css?v={timestamp}">
+    <style>
+{palette}    </style>
     <script>
         // Initialize template variables for the JavaScript
         var yearFilterMinYear = {min_year};
@@ -171,10 +189,24 @@ where
             min_year = min_year,
             max_year = max_year,
             timestamp = timestamp,
-            title = title
+            palette = config.palette.css("        "),
+            title = escape_html(&config.title),
+            metadata = metadata_tags(config, "    ")
         ));
     }
 
+    /// Lowest distance and width of the range of distances the overlay and tooltips are
+    /// colored over: those of this mosaic, or the absolute scale.
+    fn distance_range(&self) -> (f64, f64) {
+        if let Some(ceiling) = self.distance_scale().ceiling() {
+            return (0.0, ceiling);
+        }
+        let distances: Vec<f64> = self.tiles().values().map(|t| t.colors.into()).collect();
+        let min_distance = distances.iter().fold(f64::INFINITY, |a, &b| a.min(b));
+        let max_distance = distances.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+        (min_distance, max_distance - min_distance)
+    }
+
     /// Generate distance overlay tiles
     fn append_distance_overlay(
         &self,
@@ -184,37 +216,24 @@ where
         image_height: u32,
     ) {
         // Find distance range for color coding
-        let distances: Vec<f64> = self.tiles().values().map(|t| t.colors.into()).collect();
-        let min_distance = distances.iter().fold(f64::INFINITY, |a, &b| a.min(b));
-        let max_distance = distances.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b));
-        let distance_range = max_distance - min_distance;
+        let (min_distance, distance_range) = self.distance_range();
 
         // Generate distance overlay tiles
-        for ((x, y), tile) in self.tiles() {
+        for ((col, row), tile) in self.tiles() {
             let distance: f64 = tile.colors.into();
 
             // Calculate relative position as percentage of image size
-            let left_percent = (*x as f64 / image_width as f64) * 100.0;
-            let top_percent = (*y as f64 / image_height as f64) * 100.0;
+            let (x, y) = (col * config.tile_size, row * config.tile_size);
+            let left_percent = (x as f64 / image_width as f64) * 100.0;
+            let top_percent = (y as f64 / image_height as f64) * 100.0;
             let width_percent = (config.tile_size as f64 / image_width as f64) * 100.0;
             let height_percent = (config.tile_size as f64 / image_height as f64) * 100.0;
 
             // Determine overlay color class
             let overlay_class = if distance_range > 0.0 {
-                let normalized = (distance - min_distance) / distance_range;
-                if normalized < 0.20 {
-                    "overlay-distance-excellent"
-                } else if normalized < 0.40 {
-                    "overlay-distance-good"
-                } else if normalized < 0.60 {
-                    "overlay-distance-medium"
-                } else if normalized < 0.80 {
-                    "overlay-distance-poor"
-                } else {
-                    "overlay-distance-bad"
-                }
+                overlay_class((distance - min_distance) / distance_range)
             } else {
-                "overlay-distance-excellent"
+                overlay_class(0.0)
             };
 
             // Add distance overlay tile
@@ -228,7 +247,55 @@ where
         html.push_str("        </div>\n");
     }
 
+    /// Generate seam overlay lines along the junctions between the tiles
+    fn append_seam_overlay(
+        &self,
+        html: &mut String,
+        config: &MosaicConfig,
+        image_width: u32,
+        image_height: u32,
+    ) {
+        let seams = match self.seams() {
+            Some(seams) => seams,
+            None => return,
+        };
+        let width_percent = (config.tile_size as f64 / image_width as f64) * 100.0;
+        let height_percent = (config.tile_size as f64 / image_height as f64) * 100.0;
+
+        html.push_str(r#"            <div id="seam-overlay" class="distance-overlay">"#);
+        for seam in seams.seams() {
+            let (col, row) = seam.cell;
+            let overlay_class = overlay_class(seam.contrast / WORST_SEAM);
+            // Lines run along the right or bottom side of the tile of the seam
+            let (direction, left, top, width, height) = if seam.vertical {
+                let left = (col + 1) as f64 * width_percent;
+                (
+                    "vertical",
+                    left,
+                    row as f64 * height_percent,
+                    0.0,
+                    height_percent,
+                )
+            } else {
+                let top = (row + 1) as f64 * height_percent;
+                (
+                    "horizontal",
+                    col as f64 * width_percent,
+                    top,
+                    width_percent,
+                    0.0,
+                )
+            };
+            html.push_str(&format!(r#"
+            <div class="seam-overlay-line seam-overlay-{} {}" style="left: {:.2}%; top: {:.2}%; width: {:.2}%; height: {:.2}%;"></div>"#,
+                direction, overlay_class, left, top, width, height
+            ));
+        }
+        html.push_str("\n            </div>\n");
+    }
+
     /// Generate interactive tile regions with tooltips
+    #[allow(clippy::too_many_arguments)]
     fn append_tile_regions<T>(
         &self,
         html: &mut String,
@@ -241,97 +308,79 @@ where
         max_year: i32,
     ) {
         // Find distance range for color coding
-        let distances: Vec<f64> = self.tiles().values().map(|t| t.colors.into()).collect();
-        let min_distance = distances.iter().fold(f64::INFINITY, |a, &b| a.min(b));
-        let max_distance = distances.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b));
-        let distance_range = max_distance - min_distance;
+        let (min_distance, distance_range) = self.distance_range();
 
-        for ((x, y), tile) in self.tiles() {
+        for ((col, row), tile) in self.tiles() {
             let distance: f64 = tile.colors.into();
             let tile_path = tile_set.get_path(tile);
 
             // Calculate relative position as percentage of image size
-            let left_percent = (*x as f64 / image_width as f64) * 100.0;
-            let top_percent = (*y as f64 / image_height as f64) * 100.0;
+            let (x, y) = (col * config.tile_size, row * config.tile_size);
+            let left_percent = (x as f64 / image_width as f64) * 100.0;
+            let top_percent = (y as f64 / image_height as f64) * 100.0;
             let width_percent = (config.tile_size as f64 / image_width as f64) * 100.0;
             let height_percent = (config.tile_size as f64 / image_height as f64) * 100.0;
 
             // Determine distance color class for tooltip text
             let distance_class = if distance_range > 0.0 {
-                let normalized = (distance - min_distance) / distance_range;
-                if normalized < 0.20 {
-                    "distance-good"
-                } else if normalized < 0.40 {
-                    "distance-good"
-                } else if normalized < 0.60 {
-                    "distance-medium"
-                } else {
-                    "distance-bad"
-                }
+                text_class((distance - min_distance) / distance_range)
             } else {
-                "distance-good"
+                text_class(0.0)
             };
 
             // Generate URLs based on web compatibility mode
-            let (click_url, tooltip_image_url, web_compat_flag) = if web_compatible {
-                // For web hosting, preserve directory structure relative to tiles_dir
-                let tiles_dir_path = std::path::Path::new(&config.tiles_dir);
-                let relative_to_tiles_dir =
-                    if let Ok(rel_path) = tile_path.strip_prefix(tiles_dir_path) {
-                        // Successfully stripped the tiles_dir prefix
-                        rel_path.display().to_string()
-                    } else {
-                        // Fallback: if we can't strip prefix, just use filename
-                        tile_path
-                            .file_name()
-                            .unwrap_or_default()
-                            .to_string_lossy()
-                            .to_string()
-                    };
-
-                let web_path = format!("tiles/{}", relative_to_tiles_dir);
-                (web_path.clone(), web_path, "true")
+            let tooltip_image_url = tile_image_url(tile_path, config, web_compatible);
+            let link = config.tile_links.get(tile_path);
+            let (click_url, web_compat_flag) = if let Some(link) = link {
+                // Linked pages are opened as given, quoted for the script and the attribute
+                (
+                    escape_html(&link.replace('\\', "\\\\").replace('\'', "\\'")),
+                    "true",
+                )
+            } else if web_compatible {
+                (tooltip_image_url.clone(), "true")
             } else {
-                // For local files, use file:// URLs
+                // Local files are opened from their escaped path
                 let escaped_path = tile_path
                     .display()
                     .to_string()
                     .replace("\\", "\\\\")
                     .replace("'", "\\'")
                     .replace("\"", "\\\"");
-
-                let cwd = std::env::current_dir().unwrap();
-                let _escaped_cwd = cwd
-                    .display()
-                    .to_string()
-                    .replace("\\", "\\\\")
-                    .replace("'", "\\'")
-                    .replace("\"", "\\\"");
-
-                // Create absolute path for the image source
-                let absolute_tile_path = if tile_path.is_absolute() {
-                    tile_path.to_path_buf()
-                } else {
-                    cwd.join(tile_path)
-                };
-
-                // Convert to file URL for browser
-                let file_url = format!("file://{}", absolute_tile_path.display());
-                (escaped_path, file_url, "false")
+                (escaped_path, "false")
             };
 
             // Format date information and extract year
-            let (date_info, tile_year) = if let Some(ref date_taken) = tile.date_taken {
-                let year = date_taken
-                    .split(':')
-                    .next()
-                    .and_then(|y| y.parse::<i32>().ok())
-                    .unwrap_or(0);
-                (date_taken.clone(), year.to_string())
+            let (date_info, tile_year, tile_month) = if let Some(taken) = tile.taken() {
+                (
+                    taken.format(config.date_format),
+                    taken.format(DateFormat::Year),
+                    taken.month().to_string(),
+                )
             } else {
-                (String::new(), "unknown".to_string())
+                (String::new(), "unknown".to_string(), "unknown".to_string())
             };
 
+            // Caption and camera of the picture, under its date
+            let details_info: String = [
+                tile.caption.as_deref().map(|caption| {
+                    format!(
+                        r#"<span class="tile-caption">{}</span>"#,
+                        escape_html(caption)
+                    )
+                }),
+                tile.camera.as_deref().map(|camera| {
+                    format!(
+                        r#"<span class="tile-camera">📷 {}</span>"#,
+                        escape_html(camera)
+                    )
+                }),
+            ]
+            .iter()
+            .flatten()
+            .map(|detail| format!("<br/>{}", detail))
+            .collect();
+
             let distance_info = if web_compatible {
                 String::new()
             } else {
@@ -340,13 +389,13 @@ where
                     distance_class, distance
                 )
             };
+            let distance_info = match tile_set.filler_color(tile) {
+                Some(_) => format!("<span>Synthetic filler</span><br/>{}", distance_info),
+                None => distance_info,
+            };
 
             // Generate tile path hash for flagging system
-            let tile_path_hash = {
-                let mut hasher = Sha256::new();
-                hasher.update(tile_path.to_string_lossy().as_bytes());
-                format!("{:x}", hasher.finalize())[..16].to_string()
-            };
+            let tile_path_hash = tile_path_hash(tile_path);
 
             html.push_str(&format!(r#"
         <div class="tile-region" style="left: {:.2}%; top: {:.2}%; width: {:.2}%; height: {:.2}%;"
@@ -355,13 +404,15 @@ where
              data-tile-image="{}"
              data-distance-info="{}"
              data-date-info="{}"
+             data-details-info="{}"
              data-year="{}"
+             data-month="{}"
              data-tile-hash="{}"
-             data-tile-path="{}">
+             data-tile-path="{}"{}>
             <div class="tooltip">
                 <img data-src="{}" alt="Tile Preview" class="tooltip-image" onerror="this.style.display='none'" style="display:none"/><br/>
                 {}
-                {}
+                {}{}
                 
                 <!-- Flag UI -->
                 <div class="flag-status" id="flag-status-{}"></div>
@@ -378,12 +429,16 @@ where
                 tooltip_image_url,
                 distance_info.replace("\"", "&quot;").replace("'", "&#39;"),
                 date_info.replace("\"", "&quot;").replace("'", "&#39;"),
+                escape_html(&details_info),
                 tile_year,
+                tile_month,
                 tile_path_hash,
                 tile_path.display().to_string().replace("\"", "&quot;").replace("'", "&#39;"),
+                link.map(|link| format!(r#" data-tile-link="{}""#, escape_html(link))).unwrap_or_default(),
                 tooltip_image_url,
                 distance_info,
                 date_info,
+                details_info,
                 tile_path_hash,
                 tile_path_hash,
                 tile_path_hash,
@@ -411,6 +466,48 @@ where
         ));
     }
 
+    /// Generate the search box highlighting the tiles whose path contains the text searched,
+    /// matched against the `data-tile-path` of the tile regions, and the switch making clicks
+    /// highlight every placement of the picture clicked, by the `data-tile-hash` of its regions
+    fn append_tile_search(&self, html: &mut String) {
+        html.push_str(
+            r#"
+    <!-- Search of the tiles by file name or folder -->
+    <div id="tile-search" class="tile-search">
+        <input type="search" id="tile-search-input" class="tile-search-input"
+               placeholder="Search file or folder" aria-label="Search the tiles by file name or folder" />
+        <label class="tile-search-dim"><input type="checkbox" id="tile-search-dim" checked /> Dim others</label>
+        <label class="tile-search-dim" title="Click a tile to highlight every placement of its picture"><input type="checkbox" id="tile-locate" /> Locate on click</label>
+        <span id="tile-search-count" class="tile-search-count"></span>
+    </div>
+"#,
+        );
+    }
+
+    /// Generate the timeline scrubber revealing the tiles month by month, from `first_month` to
+    /// `last_month` counted from January 1970, the undated tiles last
+    fn append_timeline(&self, html: &mut String, first_month: i64, last_month: i64) {
+        let steps = last_month - first_month + 1;
+        html.push_str(&format!(
+            r#"
+    <!-- Timeline scrubber -->
+    <div id="timeline" class="timeline">
+        <button id="timeline-play" class="timeline-play" onclick="toggleTimelinePlay()" aria-label="Play the timeline">▶</button>
+        <input type="range" id="timeline-slider" class="timeline-slider"
+               min="0" max="{steps}" value="{steps}" step="1" aria-label="Timeline" />
+        <div id="timeline-display" class="timeline-display">All</div>
+    </div>
+    <script>
+        var timelineFirstMonth = {first_month};
+        var timelineLastMonth = {last_month};
+    </script>
+"#,
+            steps = steps,
+            first_month = first_month,
+            last_month = last_month
+        ));
+    }
+
     /// Generate mobile modal controls
     fn append_widget_controls(&self, html: &mut String, _min_year: i32, _max_year: i32) {
         // Add mobile modal HTML
@@ -0,0 +1,399 @@
+use std::collections::BTreeSet;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use super::image::find_images;
+use super::tiles::LibraryOptions;
+
+/// Extensions of the pictures looked for in the tiles directory, in any case.
+const PICTURE_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+
+/// Most tiles across the mosaic: more are hard to tell apart and slow to match.
+pub const MAX_TILES_ACROSS: u32 = 100;
+
+/// Smallest side of a tile, below which the pictures cannot be made out.
+pub const MIN_TILE_SIZE: u32 = 8;
+
+/// Largest side of a tile accepted with -s.
+pub const MAX_TILE_SIZE: u32 = 1024;
+
+/// Width of the mosaic suggested when asking, in pixels.
+const SUGGESTED_WIDTH: u32 = 4000;
+
+/// Output path suggested when asking.
+const SUGGESTED_OUTPUT: &str = "mosaic.jpg";
+
+/// The settings chosen with `emosaic init`, saved as a config file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InitConfig {
+    /// The picture the mosaic reproduces
+    pub source: PathBuf,
+    /// The directory of the pictures it is made of
+    pub tiles_dir: PathBuf,
+    /// Extensions of the pictures found in the tiles directory
+    pub extensions: Vec<String>,
+    pub output: PathBuf,
+    /// Width of the mosaic asked for, in pixels
+    pub width: u32,
+    /// Whether a picture may be placed more than once
+    pub repeats: bool,
+    /// Side of every tile, in pixels
+    pub tile_size: u32,
+    /// Downsampling factor of the source image, one tile per remaining pixel
+    pub downsample: u16,
+}
+
+/// The downsampling factor of a source image of `source` pixels and the tile size giving a
+/// mosaic about `width` pixels wide, with at most [`MAX_TILES_ACROSS`] tiles across and, when
+/// pictures may not repeat, no more tiles than the `pictures` available.
+pub fn plan(source: (u32, u32), width: u32, pictures: usize, repeats: bool) -> (u16, u32) {
+    let (source_width, source_height) = (source.0.max(1), source.1.max(1));
+    let tiles = |downsample: u32| {
+        let (cols, rows) = (source_width / downsample, source_height / downsample);
+        cols.max(1) as usize * rows.max(1) as usize
+    };
+    let mut downsample = source_width.div_ceil(MAX_TILES_ACROSS).max(1);
+    if !repeats {
+        while tiles(downsample) > pictures && downsample < source_width.max(source_height) {
+            downsample += 1;
+        }
+    }
+    let downsample = downsample.min(u32::from(u16::MAX));
+    let cols = (source_width / downsample).max(1);
+    let tile_size = (width / cols).clamp(MIN_TILE_SIZE, MAX_TILE_SIZE);
+    (downsample as u16, tile_size)
+}
+
+impl InitConfig {
+    /// The arguments of emosaic rendering the mosaic to `output`.
+    fn args_to(&self, output: &Path) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec![
+            "-s".into(),
+            self.tile_size.to_string().into(),
+            "-o".into(),
+            output.into(),
+            self.source.clone().into(),
+            "mosaic".into(),
+            self.tiles_dir.clone().into(),
+            "--downsample".into(),
+            self.downsample.to_string().into(),
+        ];
+        if !self.repeats {
+            args.push("--no-repeat".into());
+        }
+        for extension in &self.extensions {
+            args.push(format!("--extensions={}", extension).into());
+        }
+        args
+    }
+
+    /// The arguments of emosaic rendering the mosaic.
+    pub fn args(&self) -> Vec<OsString> {
+        self.args_to(&self.output)
+    }
+
+    /// Where the preview of the mosaic is written: next to it, with `.preview` before its
+    /// extension.
+    pub fn preview_path(&self) -> PathBuf {
+        let extension = self
+            .output
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy();
+        self.output.with_extension(format!("preview.{}", extension))
+    }
+
+    /// The arguments of emosaic rendering a quick preview of the mosaic and opening it.
+    pub fn preview_args(&self) -> Vec<OsString> {
+        let mut args = self.args_to(&self.preview_path());
+        args.push("--preview".into());
+        args.push("--open".into());
+        args
+    }
+
+    /// The command rendering the mosaic, quoted for a POSIX shell.
+    pub fn command(&self) -> String {
+        let args = self.args();
+        let args = args.iter().map(|arg| shell_quote(&arg.to_string_lossy()));
+        std::iter::once(String::from("emosaic"))
+            .chain(args)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The config file: the settings as TOML, after a comment with the command rendering the
+    /// mosaic.
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        Ok(format!(
+            "# Settings chosen with `emosaic init`. Make the mosaic with:\n#   {}\n\n{}",
+            self.command(),
+            toml::to_string(self)?
+        ))
+    }
+}
+
+/// `arg` quoted for a POSIX shell, unless it only holds characters that need no quoting.
+fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Ask `question` on `output` until `parse` accepts the answer read from `input`, or the
+/// `default` answer shown in brackets when the answer is empty. Fails when the input ends.
+pub fn ask<T, R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    question: &str,
+    default: Option<&str>,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> io::Result<T> {
+    loop {
+        match default {
+            Some(default) => write!(output, "{} [{}] ", question, default)?,
+            None => write!(output, "{} ", question)?,
+        }
+        output.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the answers ended before every question was asked",
+            ));
+        }
+        let answer = match (line.trim(), default) {
+            ("", Some(default)) => default,
+            ("", None) => continue,
+            (answer, _) => answer,
+        };
+        match parse(answer) {
+            Ok(value) => return Ok(value),
+            Err(e) => writeln!(output, "   {}", e)?,
+        }
+    }
+}
+
+/// `answer` to a yes or no question.
+fn yes_or_no(answer: &str) -> Result<bool, String> {
+    match answer.to_ascii_lowercase().as_str() {
+        "y" | "yes" => Ok(true),
+        "n" | "no" => Ok(false),
+        _ => Err(String::from("Please answer yes or no")),
+    }
+}
+
+/// The pictures of `dir` with a known extension, in any case, found as `library` says, and
+/// the extensions found.
+fn find_pictures(dir: &Path, library: &LibraryOptions) -> io::Result<(usize, Vec<String>)> {
+    let known = |extension: &OsStr| {
+        let extension = extension.to_string_lossy().to_ascii_lowercase();
+        PICTURE_EXTENSIONS.contains(&extension.as_str())
+    };
+    let pictures = find_images(dir, known, library)?;
+    let extensions: BTreeSet<String> = pictures
+        .iter()
+        .filter_map(|path| Some(path.extension()?.to_string_lossy().into_owned()))
+        .collect();
+    Ok((pictures.len(), extensions.into_iter().collect()))
+}
+
+/// Ask for the source image, suggesting `source`, the tiles directory, the size of the mosaic,
+/// whether pictures may repeat and where to save it, and plan the settings from the answers.
+/// The pictures of the tiles directory are found as `library` says.
+pub fn interview<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    source: Option<&Path>,
+    library: &LibraryOptions,
+) -> io::Result<InitConfig> {
+    writeln!(
+        output,
+        "Let's set up a mosaic. Press Enter to keep the suggestion in brackets."
+    )?;
+    let suggested = source.map(|path| path.to_string_lossy().into_owned());
+    let (source, dimensions) = ask(
+        input,
+        output,
+        "Which picture should the mosaic reproduce?",
+        suggested.as_deref(),
+        |answer| {
+            let path = PathBuf::from(answer);
+            match ::image::image_dimensions(&path) {
+                Ok(dimensions) => Ok((path, dimensions)),
+                Err(e) => Err(format!("Cannot open {} as a picture: {}", answer, e)),
+            }
+        },
+    )?;
+    let (tiles_dir, pictures, extensions) = ask(
+        input,
+        output,
+        "Which folder holds the pictures to make it from?",
+        None,
+        |answer| {
+            let path = PathBuf::from(answer);
+            match find_pictures(&path, library) {
+                Ok((0, _)) => Err(format!(
+                    "There are no {} pictures in {}",
+                    PICTURE_EXTENSIONS.join("/"),
+                    answer
+                )),
+                Ok((count, extensions)) => Ok((path, count, extensions)),
+                Err(e) => Err(format!("Cannot read the folder {}: {}", answer, e)),
+            }
+        },
+    )?;
+    writeln!(output, "   Found {} pictures", pictures)?;
+    let width = ask(
+        input,
+        output,
+        "How wide should the mosaic be, in pixels? (about 120 per cm for a print)",
+        Some(&SUGGESTED_WIDTH.to_string()),
+        |answer| match answer.parse::<u32>() {
+            Ok(width) if width >= MIN_TILE_SIZE => Ok(width),
+            _ => Err(format!(
+                "Please give a number of pixels from {}",
+                MIN_TILE_SIZE
+            )),
+        },
+    )?;
+    let repeats = ask(
+        input,
+        output,
+        "May the same picture appear more than once? (yes gives a more detailed mosaic)",
+        Some("yes"),
+        yes_or_no,
+    )?;
+    let output_path = ask(
+        input,
+        output,
+        "Where should the mosaic be saved?",
+        Some(SUGGESTED_OUTPUT),
+        |answer| {
+            let path = PathBuf::from(answer);
+            match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() && !parent.is_dir() => {
+                    Err(format!("The folder {} does not exist", parent.display()))
+                }
+                _ => Ok(path),
+            }
+        },
+    )?;
+
+    let (downsample, tile_size) = plan(dimensions, width, pictures, repeats);
+    let (cols, rows) = (
+        dimensions.0 / u32::from(downsample),
+        dimensions.1 / u32::from(downsample),
+    );
+    writeln!(
+        output,
+        "   The mosaic will be {}x{} pictures of {} pixels, {}x{} pixels in all",
+        cols,
+        rows,
+        tile_size,
+        cols * tile_size,
+        rows * tile_size
+    )?;
+    if !repeats && (cols as usize) * (rows as usize) > pictures {
+        writeln!(
+            output,
+            "   There are too few pictures for every tile to be different, some will repeat"
+        )?;
+    }
+    Ok(InitConfig {
+        source,
+        tiles_dir,
+        extensions,
+        output: output_path,
+        width,
+        repeats,
+        tile_size,
+        downsample,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_plan() {
+        // At most 100 tiles across, as wide as asked
+        assert_eq!(plan((1000, 800), 4000, 10, true), (10, 40));
+        // A small source keeps one tile per pixel
+        assert_eq!(plan((40, 30), 4000, 10, true), (1, 100));
+        // Without repeats, no more tiles than pictures
+        assert_eq!(plan((1000, 800), 4000, 200, false), (62, 250));
+        // Tiles are never too small or too large to render
+        assert_eq!(plan((1000, 800), 100, 10, true), (10, MIN_TILE_SIZE));
+        assert_eq!(plan((10, 10), 100_000, 10, true), (1, MAX_TILE_SIZE));
+    }
+
+    #[test]
+    fn test_interview() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let tiles = dir.join("tiles");
+        std::fs::create_dir_all(&tiles).unwrap();
+        let source = dir.join("source.png");
+        RgbImage::from_pixel(300, 200, Rgb([10, 20, 30]))
+            .save(&source)
+            .unwrap();
+        for name in ["a.JPG", "b.png", "notes.txt"] {
+            std::fs::write(tiles.join(name), b"").unwrap();
+        }
+
+        let answers = format!(
+            "\n{}\n{}\nabc\n6000\nmaybe\nn\n{}\n",
+            dir.join("missing").display(),
+            tiles.display(),
+            dir.join("gift.jpg").display()
+        );
+        let (mut output, library) = (Vec::new(), LibraryOptions::default());
+        let config = interview(
+            &mut answers.as_bytes(),
+            &mut output,
+            Some(&source),
+            &library,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Cannot read the folder"), "{}", output);
+        assert!(output.contains("Found 2 pictures"), "{}", output);
+        assert!(output.contains("Please answer yes or no"), "{}", output);
+
+        assert_eq!(config.source, source);
+        assert_eq!(config.extensions, ["JPG", "png"]);
+        assert_eq!((config.width, config.repeats), (6000, false));
+        assert_eq!(config.output, dir.join("gift.jpg"));
+        assert_eq!(config.preview_path(), dir.join("gift.preview.jpg"));
+        // Two pictures cannot fill more than a tile without repeating
+        assert_eq!(config.downsample, 101);
+
+        let args = config.args();
+        assert!(args.contains(&OsString::from("--no-repeat")));
+        assert!(args.contains(&OsString::from("--extensions=JPG")));
+        assert!(config.preview_args().contains(&OsString::from("--preview")));
+        let toml = config.to_toml().unwrap();
+        assert!(toml.starts_with("# Settings chosen with `emosaic init`"));
+        assert!(toml.contains("repeats = false"), "{}", toml);
+
+        // Running out of answers is an error rather than a loop
+        let mut output = Vec::new();
+        let error = interview(&mut "\n".as_bytes(), &mut output, None, &library).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("--extensions=jpg"), "--extensions=jpg");
+        assert_eq!(shell_quote("my photos"), "'my photos'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+}